@@ -0,0 +1,134 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use ipmes_rust::input_event::InputEvent;
+use ipmes_rust::pattern::{decompose, Pattern, PatternEvent};
+use ipmes_rust::process_layers::composition_layer::CompositionLayer;
+use regex::{RegexSet, SetMatches};
+use std::hint::black_box;
+use std::rc::Rc;
+
+/// A live stream of `num_events` single-event batches, where one in `match_every` events matches
+/// the first pattern event and the others are background noise of the signature `noise`.
+fn gen_stream(
+    pattern: &Pattern,
+    num_events: u64,
+    match_every: u64,
+    noise: &str,
+) -> Vec<Rc<InputEvent>> {
+    let first = &pattern.events[0];
+    (0..num_events)
+        .map(|i| {
+            let event = if i % match_every == 0 {
+                InputEvent::new(
+                    i,
+                    i,
+                    &first.signature,
+                    1,
+                    &first.subject.signature,
+                    2,
+                    &first.object.signature,
+                )
+            } else {
+                let object = format!("FILE_OBJECT_FILE::/tmp/noise{}", i % 97);
+                InputEvent::new(i, i, noise, 1, "SUBJECT_PROCESS::bash", 2, &object)
+            };
+            Rc::new(event)
+        })
+        .collect()
+}
+
+/// The regex set of the Composition layer, where the signatures are taken literally.
+fn event_regexes(pattern: &Pattern) -> RegexSet {
+    let patterns = pattern.events.iter().map(|event: &PatternEvent| {
+        let signatures = format!(
+            "{}\0{}\0{}",
+            event.signature, event.subject.signature, event.object.signature
+        );
+        format!("^{}$", regex::escape(&signatures))
+    });
+    RegexSet::new(patterns).unwrap()
+}
+
+/// The signature matching of a batch before the single-event fast path: the matches of the regex
+/// set are collected for every event.
+fn match_batch(
+    regexes: &RegexSet,
+    batch: &[Rc<InputEvent>],
+    cur_batch: &mut Vec<(Rc<InputEvent>, SetMatches)>,
+) {
+    cur_batch.clear();
+    for event in batch {
+        let result = regexes.matches(event.get_signatures());
+        if result.matched_any() {
+            cur_batch.push((Rc::clone(event), result));
+        }
+    }
+}
+
+fn single_event_benchmark(c: &mut Criterion) {
+    let pattern = Pattern::parse("data/universal_patterns/DP1.json").unwrap();
+    let decomposition = decompose(&pattern);
+    let regexes = event_regexes(&pattern);
+
+    let mut group = c.benchmark_group("single_event_batch");
+    for match_every in [1, 10, 1000] {
+        let stream = gen_stream(&pattern, 10000, match_every, "EVENT_READ");
+        group.bench_with_input(
+            BenchmarkId::new("set_matches", match_every),
+            &stream,
+            |b, stream| {
+                let mut cur_batch = vec![];
+                b.iter(|| {
+                    for event in stream {
+                        match_batch(&regexes, std::slice::from_ref(event), &mut cur_batch);
+                    }
+                    black_box(cur_batch.len())
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("composition_layer", match_every),
+            &stream,
+            |b, stream| {
+                let mut layer = CompositionLayer::new((), &decomposition, 1000, false).unwrap();
+                b.iter(|| {
+                    for event in stream {
+                        layer.add_batch(std::slice::from_ref(event));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// A sparse stream of batches of 8 events, where the noise is in a category no pattern event is
+/// in, with and without skipping the irrelevant batches.
+fn sparse_stream_benchmark(c: &mut Criterion) {
+    let pattern = Pattern::parse("data/universal_patterns/DP1.json").unwrap();
+    let decomposition = decompose(&pattern);
+
+    let mut group = c.benchmark_group("sparse_stream");
+    for match_every in [100, 1000] {
+        let batches: Vec<Box<[Rc<InputEvent>]>> =
+            gen_stream(&pattern, 10000, match_every, "EVENT_MMAP")
+                .chunks(8)
+                .map(Box::from)
+                .collect();
+        for skip in [false, true] {
+            let id = if skip { "skip" } else { "no_skip" };
+            group.bench_with_input(BenchmarkId::new(id, match_every), &batches, |b, batches| {
+                b.iter(|| {
+                    let mut layer =
+                        CompositionLayer::new(batches.iter().cloned(), &decomposition, 1000, false)
+                            .unwrap();
+                    layer.set_skip_irrelevant_batches(skip);
+                    black_box(layer.count())
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, single_event_benchmark, sparse_stream_benchmark);
+criterion_main!(benches);
@@ -1,9 +1,9 @@
 use std::{rc::Rc, vec};
 use ipmes_rust::match_event::{MatchEvent, RawEvents};
 
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use ipmes_rust::{
-    input_event::InputEvent, pattern::{decompose, parser::parse_json, SubPattern}, process_layers::{
+    input_event::InputEvent, pattern::{decompose, parser::parse_json, Pattern, SubPattern}, process_layers::{
         composition_layer::MatchInstance,
         JoinLayer,
     }
@@ -70,9 +70,11 @@ fn gen_match_instance_from_subpattern<'p>(
     MatchInstance {
         start_time: set_time,
         match_events: match_events.into_boxed_slice(),
-        match_entities: match_entities.into_boxed_slice(),
-        event_ids: event_ids.into_boxed_slice(),
+        match_entities: match_entities.into(),
+        event_ids: event_ids.into(),
         state_id: 0,
+        entity_attributes: vec![],
+        wildcard_entities: vec![],
     }
 }
 
@@ -101,7 +103,7 @@ fn run_join_layer() {
     let json_obj: Value = serde_json::from_str(raw_pattern).expect("error reading json");
     let pattern = parse_json(&json_obj).expect("Failed to parse pattern");
 
-    let windows_size = 1 * 1000;
+    let windows_size = 1000;
     let sub_patterns = decompose(&pattern);
 
     debug!("sub_patterns: {:#?}", sub_patterns);
@@ -148,8 +150,116 @@ fn run_join_layer() {
     join_layer.run_isolated_join_layer(&mut match_instances);
 }
 
+/// Join the matches of a path pattern with `num_entities` entities, 10 full matches in a row,
+/// to measure the entity uniqueness checks on patterns far wider than a machine word.
+fn run_wide_join_layer(pattern: &Pattern) {
+    let windows_size = 1000;
+    let sub_patterns = decompose(pattern);
+    let mut join_layer = JoinLayer::new((), pattern, &sub_patterns, windows_size);
+
+    let all_ids = (0..sub_patterns.len()).collect_vec();
+    let mut match_instances = vec![];
+    for i in 0..10 {
+        match_instances.append(&mut gen_match_instances(&sub_patterns, &all_ids, 2 * i * windows_size));
+    }
+    join_layer.run_isolated_join_layer(&mut match_instances);
+}
+
+/// A path `v0 -> v1 -> ... -> v{num_entities - 1}`.
+fn path_pattern(num_entities: usize) -> Pattern {
+    let names = (0..num_entities).map(|i| format!("v{}", i)).collect_vec();
+    let vertices = names.iter().map(String::as_str).collect_vec();
+    let signatures = (1..num_entities).map(|i| format!("e{}", i)).collect_vec();
+    let edges = signatures
+        .iter()
+        .enumerate()
+        .map(|(i, sig)| (i, i + 1, sig.as_str()))
+        .collect_vec();
+    Pattern::from_graph(&vertices, &edges, false)
+}
+
+/// A match of the sub-pattern of `pattern_event` alone, binding its subject and object to the
+/// given input entities.
+fn gen_single_event_match(
+    pattern: &Pattern,
+    sub_patterns: &[SubPattern],
+    pattern_event: usize,
+    set_time: u64,
+    event_id: u64,
+    subject: u64,
+    object: u64,
+) -> (u32, MatchInstance) {
+    let sub_pattern = sub_patterns
+        .iter()
+        .find(|sub_pattern| sub_pattern.events.iter().any(|e| e.id == pattern_event))
+        .unwrap();
+    let event = &pattern.events[pattern_event];
+    let input_event = InputEvent::new(set_time, event_id, "", subject, "", object, "");
+    let match_event = MatchEvent {
+        match_id: event.id as u32,
+        input_subject_id: subject,
+        input_object_id: object,
+        pattern_subject_id: event.subject.id as u64,
+        pattern_object_id: event.object.id as u64,
+        raw_events: RawEvents::Single(Rc::new(input_event)),
+    };
+    let match_entities = [
+        (subject, event.subject.id as u64),
+        (object, event.object.id as u64),
+    ];
+    let match_instance = MatchInstance {
+        start_time: set_time,
+        match_events: Box::new([match_event]),
+        match_entities: match_entities.into_iter().sorted().collect(),
+        event_ids: vec![event_id].into(),
+        state_id: 0,
+        entity_attributes: vec![],
+        wildcard_entities: vec![],
+    };
+    (sub_pattern.id as u32, match_instance)
+}
+
+/// `a -> b -> c`: 1000 matches of `a -> b`, and then a burst of 1000 matches of `b -> c` in one
+/// batch with the same entities, none of which joins, e.g. a process reading a file many times.
+fn run_bursty_join_layer(join_memo: bool) {
+    let edges = [(0, 1, "e0"), (1, 2, "e1")];
+    let pattern = Pattern::from_graph(&["a", "b", "c"], &edges, false);
+    let sub_patterns = decompose(&pattern);
+    let mut join_layer = JoinLayer::new((), &pattern, &sub_patterns, 1000);
+    join_layer.set_join_memo(join_memo);
+
+    let mut match_instances = vec![];
+    for i in 0..1000 {
+        match_instances.push(gen_single_event_match(
+            &pattern, &sub_patterns, 0, 10, i, 10000 + i, 200,
+        ));
+    }
+    for i in 0..1000 {
+        match_instances.push(gen_single_event_match(
+            &pattern, &sub_patterns, 1, 20, 1000 + i, 201, 300,
+        ));
+    }
+    join_layer.run_isolated_join_layer(&mut match_instances);
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("run join layer", |b| b.iter(|| run_join_layer()));
+    c.bench_function("run join layer", |b| b.iter(run_join_layer));
+
+    let mut group = c.benchmark_group("wide join layer");
+    for num_entities in [16, 256, 1024] {
+        let pattern = path_pattern(num_entities);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_entities),
+            &pattern,
+            |b, pattern| b.iter(|| run_wide_join_layer(pattern)),
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("bursty join layer");
+    group.bench_function("memo", |b| b.iter(|| run_bursty_join_layer(true)));
+    group.bench_function("no memo", |b| b.iter(|| run_bursty_join_layer(false)));
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,63 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use ipmes_rust::pattern_match::PatternMatch;
+use ipmes_rust::process_layers::UniquenessLayer;
+use std::collections::HashSet;
+use std::hint::black_box;
+
+/// `num_matches` matches of a pattern with `num_events` events, where every other match
+/// duplicates the previous one, as the Join layer outputs a match once per join order.
+fn gen_matches(num_matches: u64, num_events: u64) -> Vec<PatternMatch> {
+    (0..num_matches)
+        .map(|i| {
+            let first_id = i / 2 * num_events;
+            PatternMatch {
+                latest_time: i,
+                earliest_time: i,
+                event_ids: (0..num_events).map(|j| (first_id + j, j as u32)).collect(),
+                match_event_map: Box::new([]),
+                annotations: serde_json::Map::new(),
+                sequence: None,
+            }
+        })
+        .collect()
+}
+
+/// The deduplication before matches were keyed by their rolling hashes: the whole match is hashed
+/// and cloned into a `HashSet`.
+fn dedup_by_hash_set(matches: Vec<PatternMatch>) -> usize {
+    let mut pool = HashSet::new();
+    let mut unique = vec![];
+    for pattern_match in matches {
+        if !pool.contains(&pattern_match) {
+            pool.insert(pattern_match.clone());
+            unique.push(pattern_match);
+        }
+    }
+    unique.len()
+}
+
+fn uniqueness_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("uniqueness");
+    for num_events in [10, 100, 500] {
+        let matches = gen_matches(2000, num_events);
+        group.bench_with_input(
+            BenchmarkId::new("hash_set", num_events),
+            &matches,
+            |b, matches| b.iter(|| black_box(dedup_by_hash_set(matches.clone()))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("uniqueness_layer", num_events),
+            &matches,
+            |b, matches| {
+                b.iter(|| {
+                    let layer = UniquenessLayer::new(matches.clone().into_iter(), 1000);
+                    black_box(layer.count())
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, uniqueness_benchmark);
+criterion_main!(benches);
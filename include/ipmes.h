@@ -0,0 +1,56 @@
+/* The C API of IPMES+, built with `cargo build --release --features capi`. See `src/capi.rs` for
+ * the details of each function. */
+#ifndef IPMES_H
+#define IPMES_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct IpmesEngine IpmesEngine;
+
+typedef struct IpmesMatch {
+    /* The timestamp of the earliest event in milliseconds. */
+    uint64_t earliest_time;
+    /* The timestamp of the latest event in milliseconds. */
+    uint64_t latest_time;
+    /* The number of matched input events. */
+    size_t num_events;
+    /* The ids of the matched input events, valid until the next call on the same engine. */
+    const uint64_t *event_ids;
+} IpmesMatch;
+
+/* Returns null on errors, see `ipmes_last_error`. The window size is in seconds. A panic inside
+ * any of the functions is caught and reported as an error. */
+IpmesEngine *ipmes_engine_new(const char *pattern_json, uint64_t window_size);
+
+/* Push records in the data graph format, one or more lines. Returns 0 on success, -1 on errors. */
+int ipmes_engine_push_event(IpmesEngine *engine, const char *csv);
+
+/* Mark the end of the input. Returns 0 on success, -1 on errors. */
+int ipmes_engine_finish(IpmesEngine *engine);
+
+/* Returns 1 if a match is written to `out`, 0 if there is no match yet, -1 on errors. */
+int ipmes_engine_poll_match(IpmesEngine *engine, IpmesMatch *out);
+
+/* Returns the next match as a JSON object tagged by its schema "version", or null if there is no
+ * match yet or on errors. The string must be freed by `ipmes_string_free`. */
+char *ipmes_engine_poll_match_json(IpmesEngine *engine);
+
+uint64_t ipmes_engine_num_events(IpmesEngine *engine);
+
+void ipmes_engine_free(IpmesEngine *engine);
+
+void ipmes_string_free(char *s);
+
+/* Returns the message of the last error on this thread, or null. */
+const char *ipmes_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* IPMES_H */
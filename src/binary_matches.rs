@@ -0,0 +1,283 @@
+//! A compact binary format of pattern matches for high-volume offline runs, holding the same
+//! fields as [`PatternMatch::to_json`]. The `decode` subcommand converts it back to JSON lines.
+//!
+//! The file starts with the header: the magic bytes `IPMB` and a version byte. Each match follows
+//! as unsigned LEB128 varints: `earliest_time`, `latest_time - earliest_time`, the sequence number
+//! and the window id of the match (each plus one, or 0 if it is not numbered), the number of input
+//! events, and then the input event ids, each but the first as the zigzag-encoded difference from
+//! the previous one, since the events of a match are usually close in the input. Version 1 has no
+//! sequence numbers and window ids.
+use crate::match_schema::{MatchRecord, MatchRecordV3};
+use crate::pattern_match::{MatchSequence, PatternMatch};
+use std::io::{self, BufRead, Write};
+use thiserror::Error;
+
+pub const MAGIC: &[u8; 4] = b"IPMB";
+pub const VERSION: u8 = 2;
+
+#[derive(Error, Debug)]
+pub enum BinaryMatchError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a binary match file")]
+    BadMagic,
+    #[error("unsupported format version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("the file ends in the middle of a match")]
+    Truncated,
+    #[error("a varint exceeds 64 bits")]
+    VarintOverflow,
+}
+
+/// Writes pattern matches in the binary format.
+pub struct BinaryMatchWriter<W: Write> {
+    writer: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> BinaryMatchWriter<W> {
+    /// Write the header into `writer`. Wrap a file in a `BufWriter`, since each match is written
+    /// by a separate call.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Self {
+            writer,
+            buf: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, pattern_match: &PatternMatch) -> io::Result<()> {
+        let event_ids = pattern_match.event_ids.iter().map(|(id, _)| *id);
+        self.write_fields(
+            pattern_match.earliest_time,
+            pattern_match.latest_time,
+            pattern_match.sequence,
+            event_ids,
+        )
+    }
+
+    fn write_fields(
+        &mut self,
+        earliest_time: u64,
+        latest_time: u64,
+        sequence: Option<MatchSequence>,
+        event_ids: impl ExactSizeIterator<Item = u64>,
+    ) -> io::Result<()> {
+        self.buf.clear();
+        write_varint(&mut self.buf, earliest_time);
+        write_varint(&mut self.buf, latest_time.saturating_sub(earliest_time));
+        let (number, window_id) = sequence.map_or((0, 0), |sequence| {
+            (sequence.number + 1, sequence.window_id + 1)
+        });
+        write_varint(&mut self.buf, number);
+        write_varint(&mut self.buf, window_id);
+        write_varint(&mut self.buf, event_ids.len() as u64);
+        let mut prev = 0u64;
+        for id in event_ids {
+            write_varint(&mut self.buf, zigzag(id.wrapping_sub(prev) as i64));
+            prev = id;
+        }
+        self.writer.write_all(&self.buf)
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// A match decoded from the binary format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedMatch {
+    pub earliest_time: u64,
+    pub latest_time: u64,
+    pub sequence: Option<MatchSequence>,
+    /// The ids of the matched input events, in the order of the matched pattern events.
+    pub event_ids: Vec<u64>,
+}
+
+impl DecodedMatch {
+    /// The same JSON object as [`PatternMatch::to_json`], without the flows and the annotations,
+    /// which are not in the binary format.
+    pub fn to_json(&self) -> serde_json::Value {
+        MatchRecord::V3(MatchRecordV3 {
+            earliest_time: self.earliest_time,
+            latest_time: self.latest_time,
+            event_ids: self.event_ids.clone(),
+            sequence: self.sequence.map(|sequence| sequence.number),
+            window_id: self.sequence.map(|sequence| sequence.window_id),
+            flows: vec![],
+            annotations: serde_json::Map::new(),
+            events: None,
+        })
+        .to_json()
+    }
+}
+
+/// Reads the matches written by [`BinaryMatchWriter`].
+pub struct BinaryMatchReader<R: BufRead> {
+    reader: R,
+    version: u8,
+}
+
+impl<R: BufRead> BinaryMatchReader<R> {
+    /// Read and check the header.
+    pub fn new(mut reader: R) -> Result<Self, BinaryMatchError> {
+        let mut header = [0u8; 5];
+        reader
+            .read_exact(&mut header)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => BinaryMatchError::BadMagic,
+                _ => err.into(),
+            })?;
+        if &header[..4] != MAGIC {
+            return Err(BinaryMatchError::BadMagic);
+        }
+        let version = header[4];
+        if !(1..=VERSION).contains(&version) {
+            return Err(BinaryMatchError::UnsupportedVersion(version));
+        }
+        Ok(Self { reader, version })
+    }
+
+    fn read_match(&mut self) -> Result<DecodedMatch, BinaryMatchError> {
+        let earliest_time = self.read_varint()?;
+        let latest_time = earliest_time.saturating_add(self.read_varint()?);
+        let mut sequence = None;
+        if self.version >= 2 {
+            let (number, window_id) = (self.read_varint()?, self.read_varint()?);
+            if number > 0 && window_id > 0 {
+                sequence = Some(MatchSequence {
+                    number: number - 1,
+                    window_id: window_id - 1,
+                });
+            }
+        }
+        let num_events = self.read_varint()?;
+        let mut event_ids = Vec::with_capacity(num_events.min(1024) as usize);
+        let mut prev = 0u64;
+        for _ in 0..num_events {
+            prev = prev.wrapping_add(unzigzag(self.read_varint()?) as u64);
+            event_ids.push(prev);
+        }
+        Ok(DecodedMatch {
+            earliest_time,
+            latest_time,
+            sequence,
+            event_ids,
+        })
+    }
+
+    fn read_varint(&mut self) -> Result<u64, BinaryMatchError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0u8];
+            self.reader
+                .read_exact(&mut byte)
+                .map_err(|err| match err.kind() {
+                    io::ErrorKind::UnexpectedEof => BinaryMatchError::Truncated,
+                    _ => err.into(),
+                })?;
+            let bits = (byte[0] & 0x7f) as u64;
+            if shift == 63 && bits > 1 {
+                return Err(BinaryMatchError::VarintOverflow);
+            }
+            value |= bits << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(BinaryMatchError::VarintOverflow)
+    }
+}
+
+impl<R: BufRead> Iterator for BinaryMatchReader<R> {
+    type Item = Result<DecodedMatch, BinaryMatchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.fill_buf() {
+            Ok([]) => None,
+            Ok(_) => Some(self.read_match()),
+            Err(err) => Some(Err(err.into())),
+        }
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let sequence = Some(MatchSequence {
+            number: 5,
+            window_id: 1,
+        });
+        let matches = [
+            (1000, 1500, sequence, vec![3, 1, 2]),
+            (0, u64::MAX, None, vec![u64::MAX, 0, 1 << 40]),
+            (7, 7, sequence, vec![]),
+        ];
+        let mut writer = BinaryMatchWriter::new(Vec::new()).unwrap();
+        for (earliest_time, latest_time, sequence, event_ids) in &matches {
+            let event_ids = event_ids.iter().copied();
+            writer
+                .write_fields(*earliest_time, *latest_time, *sequence, event_ids)
+                .unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        // header, then 2 + 2 + 1 + 1 + 1 + 3 bytes for the first match
+        assert_eq!(bytes[5..15], [0xe8, 0x07, 0xf4, 0x03, 6, 2, 3, 6, 3, 2]);
+
+        let decoded: Vec<_> = BinaryMatchReader::new(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|m| (m.earliest_time, m.latest_time, m.sequence, m.event_ids))
+            .collect();
+        assert_eq!(decoded, matches);
+
+        let truncated = BinaryMatchReader::new(&bytes[..bytes.len() - 1])
+            .unwrap()
+            .last()
+            .unwrap();
+        assert!(matches!(truncated, Err(BinaryMatchError::Truncated)));
+    }
+
+    #[test]
+    fn test_header() {
+        assert!(matches!(
+            BinaryMatchReader::new(&b"{\"earliest_time\""[..]),
+            Err(BinaryMatchError::BadMagic)
+        ));
+        assert!(matches!(
+            BinaryMatchReader::new(&b"IPMB\x09"[..]),
+            Err(BinaryMatchError::UnsupportedVersion(9))
+        ));
+        // version 1, without the sequence numbers
+        let decoded = BinaryMatchReader::new(&b"IPMB\x01\xe8\x07\x00\x01\x06"[..])
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.sequence, None);
+        assert_eq!(decoded.event_ids, [3]);
+    }
+}
@@ -0,0 +1,85 @@
+//! Cancellation of the ingestion, e.g. on SIGINT or SIGTERM. A cancelled Parse layer stops reading
+//! as if the input ended, so the later layers still flush the matches completed by then, see
+//! [`ParseLayer::set_cancellation`](crate::process_layers::ParseLayer::set_cancellation).
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag shared between the Parse layer and whoever cancels it, possibly from another thread or
+/// a signal handler.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(unix)]
+pub use self::termination::{cancel_on_termination, termination_signal};
+
+#[cfg(unix)]
+mod termination {
+    use super::CancellationToken;
+    use nix::libc::{self, c_int};
+    use nix::sys::signal::{self, SigHandler, Signal};
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::OnceLock;
+
+    static TERMINATION: OnceLock<CancellationToken> = OnceLock::new();
+    /// The first termination signal received, or 0.
+    static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+    /// Returns a token cancelled on the first SIGINT or SIGTERM. On the second one, the process
+    /// exits at once with status 128 plus the signal number, in case the flush takes too long.
+    ///
+    /// A blocking read is resumed after the signal, so reading a live input (e.g. a named pipe)
+    /// stops only at the next record, unless it is read through an
+    /// [`IdleReader`](crate::process_layers::parse_layer::IdleReader).
+    pub fn cancel_on_termination() -> nix::Result<CancellationToken> {
+        let token = TERMINATION.get_or_init(CancellationToken::default).clone();
+        for sig in [Signal::SIGINT, Signal::SIGTERM] {
+            // SAFETY: the handler only touches atomics, and `_exit` is async-signal-safe
+            unsafe { signal::signal(sig, SigHandler::Handler(handle_termination)) }?;
+        }
+        Ok(token)
+    }
+
+    /// The signal that cancelled the token of [`cancel_on_termination`], if any.
+    pub fn termination_signal() -> Option<Signal> {
+        Signal::try_from(SIGNAL.load(Ordering::SeqCst)).ok()
+    }
+
+    extern "C" fn handle_termination(sig: c_int) {
+        if SIGNAL.swap(sig, Ordering::SeqCst) != 0 {
+            // SAFETY: `_exit` does not run any handler or destructor
+            unsafe { libc::_exit(128 + sig) };
+        }
+        if let Some(token) = TERMINATION.get() {
+            token.cancel();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use nix::sys::signal::{raise, Signal};
+
+    #[test]
+    fn test_cancel_on_termination() {
+        let token = cancel_on_termination().unwrap();
+        assert!(!token.is_cancelled());
+        assert_eq!(termination_signal(), None);
+
+        raise(Signal::SIGTERM).unwrap();
+        assert!(token.is_cancelled());
+        assert_eq!(termination_signal(), Some(Signal::SIGTERM));
+    }
+}
@@ -0,0 +1,370 @@
+//! The C API, enabled by the `capi` feature. See `include/ipmes.h` for the declarations.
+//!
+//! ```c
+//! IpmesEngine *engine = ipmes_engine_new(pattern_json, 1800);
+//! ipmes_engine_push_event(engine, "5,5,1,recvmsg,1,Socket::ip::8.8.8.8,2,Process::name::bash");
+//! ipmes_engine_finish(engine);
+//! IpmesMatch m;
+//! while (ipmes_engine_poll_match(engine, &m) == 1) { ... }
+//! ipmes_engine_free(engine);
+//! ```
+use crate::engine::{Engine, Session};
+use crate::pattern::Pattern;
+use crate::pattern_match::PatternMatch;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(err: impl Into<Box<dyn Error>>) {
+    let message = err.into().to_string().replace('\0', " ");
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = CString::new(message).ok());
+}
+
+/// Run `f`, catching a panic so that it does not unwind into the caller. On a panic, the panic
+/// message is set as the last error and `on_panic` is returned.
+fn catch_panic<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        set_last_error(format!("panicked: {message}"));
+        on_panic
+    })
+}
+
+/// An incremental matching session on a single pattern, see [`Session`].
+pub struct IpmesEngine {
+    /// Borrows `*engine`, so it is dropped first in `ipmes_engine_free`.
+    session: Option<Session<'static>>,
+    engine: *mut Engine,
+    /// The matches polled from `session` but not yet returned.
+    matches: VecDeque<PatternMatch>,
+    /// The event ids of the match last returned by `ipmes_engine_poll_match`.
+    event_ids: Vec<u64>,
+}
+
+impl IpmesEngine {
+    fn session(&mut self) -> &mut Session<'static> {
+        self.session
+            .as_mut()
+            .expect("the session lives until the engine is freed")
+    }
+
+    fn next_match(&mut self) -> Option<PatternMatch> {
+        if self.matches.is_empty() {
+            let matches = self.session().poll_matches();
+            self.matches.extend(matches);
+        }
+        self.matches.pop_front()
+    }
+}
+
+impl Drop for IpmesEngine {
+    fn drop(&mut self) {
+        self.session = None;
+        // SAFETY: `engine` is created by `Box::into_raw` in `ipmes_engine_new`, and nothing
+        // borrows it after the session is dropped.
+        drop(unsafe { Box::from_raw(self.engine) });
+    }
+}
+
+/// A match returned by `ipmes_engine_poll_match`.
+#[repr(C)]
+pub struct IpmesMatch {
+    /// The timestamp of the earliest event in milliseconds.
+    pub earliest_time: u64,
+    /// The timestamp of the latest event in milliseconds.
+    pub latest_time: u64,
+    /// The number of matched input events.
+    pub num_events: usize,
+    /// The ids of the matched input events, valid until the next call on the same engine.
+    pub event_ids: *const u64,
+}
+
+/// Create an engine matching the pattern in `pattern_json` (the content of a pattern file), with
+/// the window size in seconds. Returns null on errors, see `ipmes_last_error`, e.g. if the window
+/// size overflows in milliseconds.
+///
+/// # Safety
+///
+/// `pattern_json` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ipmes_engine_new(
+    pattern_json: *const c_char,
+    window_size: u64,
+) -> *mut IpmesEngine {
+    catch_panic(ptr::null_mut(), || engine_new(pattern_json, window_size))
+}
+
+unsafe fn engine_new(pattern_json: *const c_char, window_size: u64) -> *mut IpmesEngine {
+    if pattern_json.is_null() {
+        set_last_error("pattern_json is null");
+        return ptr::null_mut();
+    }
+    let pattern = CStr::from_ptr(pattern_json)
+        .to_str()
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|pattern_json| Ok(Pattern::parse_str(pattern_json)?));
+    let pattern = match pattern {
+        Ok(pattern) => pattern,
+        Err(err) => {
+            set_last_error(err);
+            return ptr::null_mut();
+        }
+    };
+
+    let Some(window_size) = window_size.checked_mul(1000) else {
+        set_last_error("window_size overflows in milliseconds");
+        return ptr::null_mut();
+    };
+    let engine = Box::into_raw(Box::new(Engine::new(pattern, window_size)));
+    // SAFETY: `engine` is only freed after the session, see `IpmesEngine::drop`.
+    match (*engine).session() {
+        Ok(session) => Box::into_raw(Box::new(IpmesEngine {
+            session: Some(session),
+            engine,
+            matches: VecDeque::new(),
+            event_ids: Vec::new(),
+        })),
+        Err(err) => {
+            drop(Box::from_raw(engine));
+            set_last_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Push input events in the csv format of the data graph, one or more lines. Returns 0 on
+/// success, or -1 on errors, e.g. if `csv` is not valid UTF-8.
+///
+/// # Safety
+///
+/// `engine` must be created by `ipmes_engine_new`, and `csv` must be a valid NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn ipmes_engine_push_event(
+    engine: *mut IpmesEngine,
+    csv: *const c_char,
+) -> c_int {
+    catch_panic(-1, || engine_push_event(engine, csv))
+}
+
+unsafe fn engine_push_event(engine: *mut IpmesEngine, csv: *const c_char) -> c_int {
+    let Some(engine) = engine.as_mut() else {
+        set_last_error("engine is null");
+        return -1;
+    };
+    if csv.is_null() {
+        set_last_error("csv is null");
+        return -1;
+    }
+    match CStr::from_ptr(csv).to_str() {
+        Ok(csv) => {
+            engine.session().push_events(csv);
+            0
+        }
+        Err(err) => {
+            set_last_error(err);
+            -1
+        }
+    }
+}
+
+/// Mark the end of the input, so that all the remaining matches can be polled. Returns 0 on
+/// success, or -1 on errors.
+///
+/// # Safety
+///
+/// `engine` must be created by `ipmes_engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ipmes_engine_finish(engine: *mut IpmesEngine) -> c_int {
+    catch_panic(-1, || {
+        let Some(engine) = engine.as_mut() else {
+            set_last_error("engine is null");
+            return -1;
+        };
+        engine.session().finish();
+        0
+    })
+}
+
+/// Poll the next match found so far into `*out`. Returns 1 if a match is returned, 0 if there is
+/// no match yet, or -1 on errors.
+///
+/// # Safety
+///
+/// `engine` must be created by `ipmes_engine_new`, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ipmes_engine_poll_match(
+    engine: *mut IpmesEngine,
+    out: *mut IpmesMatch,
+) -> c_int {
+    catch_panic(-1, || engine_poll_match(engine, out))
+}
+
+unsafe fn engine_poll_match(engine: *mut IpmesEngine, out: *mut IpmesMatch) -> c_int {
+    let (Some(engine), false) = (engine.as_mut(), out.is_null()) else {
+        set_last_error("engine or out is null");
+        return -1;
+    };
+    let Some(pattern_match) = engine.next_match() else {
+        return 0;
+    };
+
+    engine.event_ids.clear();
+    engine.event_ids.extend(
+        pattern_match
+            .event_ids
+            .iter()
+            .map(|(input_event_id, _)| input_event_id),
+    );
+    out.write(IpmesMatch {
+        earliest_time: pattern_match.earliest_time,
+        latest_time: pattern_match.latest_time,
+        num_events: engine.event_ids.len(),
+        event_ids: engine.event_ids.as_ptr(),
+    });
+    1
+}
+
+/// Poll the next match found so far as a JSON object, with its `earliest_time` and `latest_time`
+/// in milliseconds, and the ids of the matched input events in `event_ids`, tagged by the schema
+/// `version`, see [`MatchRecord`](crate::match_schema::MatchRecord). Returns null if there is no
+/// match yet or on errors. The string must be freed by `ipmes_string_free`.
+///
+/// # Safety
+///
+/// `engine` must be created by `ipmes_engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ipmes_engine_poll_match_json(engine: *mut IpmesEngine) -> *mut c_char {
+    catch_panic(ptr::null_mut(), || {
+        let Some(pattern_match) = engine.as_mut().and_then(IpmesEngine::next_match) else {
+            return ptr::null_mut();
+        };
+        // a JSON string never contains NUL
+        CString::new(pattern_match.to_json().to_string())
+            .map_or(ptr::null_mut(), CString::into_raw)
+    })
+}
+
+/// Returns the total number of input events pushed so far, or 0 on errors.
+///
+/// # Safety
+///
+/// `engine` must be created by `ipmes_engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ipmes_engine_num_events(engine: *mut IpmesEngine) -> u64 {
+    catch_panic(0, || {
+        engine
+            .as_mut()
+            .map_or(0, |engine| engine.session().num_events())
+    })
+}
+
+/// Free an engine created by `ipmes_engine_new`. Does nothing if `engine` is null.
+///
+/// # Safety
+///
+/// `engine` must be created by `ipmes_engine_new`, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ipmes_engine_free(engine: *mut IpmesEngine) {
+    if !engine.is_null() {
+        catch_panic((), || drop(Box::from_raw(engine)));
+    }
+}
+
+/// Free a string returned by `ipmes_engine_poll_match_json`. Does nothing if `s` is null.
+///
+/// # Safety
+///
+/// `s` must be returned by this library, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ipmes_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        catch_panic((), || drop(CString::from_raw(s)));
+    }
+}
+
+/// Returns the message of the last error on this thread, or null if there is none. The string
+/// is valid until the next failed call on this thread.
+#[no_mangle]
+pub extern "C" fn ipmes_last_error() -> *const c_char {
+    catch_panic(ptr::null(), || {
+        LAST_ERROR.with(|last_error| {
+            last_error
+                .borrow()
+                .as_ref()
+                .map_or(ptr::null(), |message| message.as_ptr())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capi() {
+        let pattern = CString::new(std::fs::read("testcases/paper/pattern.json").unwrap()).unwrap();
+        let data = CString::new(std::fs::read("testcases/paper/data.csv").unwrap()).unwrap();
+        let run = || unsafe {
+            let engine = ipmes_engine_new(pattern.as_ptr(), 1000);
+            assert!(!engine.is_null());
+            assert_eq!(ipmes_engine_push_event(engine, data.as_ptr()), 0);
+            assert_eq!(ipmes_engine_finish(engine), 0);
+            assert_eq!(ipmes_engine_num_events(engine), 11);
+            engine
+        };
+
+        unsafe {
+            let engine = run();
+            let mut pattern_match = std::mem::MaybeUninit::<IpmesMatch>::uninit();
+            assert_eq!(
+                ipmes_engine_poll_match(engine, pattern_match.as_mut_ptr()),
+                1
+            );
+            let pattern_match = pattern_match.assume_init();
+            let event_ids =
+                std::slice::from_raw_parts(pattern_match.event_ids, pattern_match.num_events)
+                    .to_vec();
+            assert!(event_ids.contains(&3));
+            let mut next_match = std::mem::MaybeUninit::<IpmesMatch>::uninit();
+            assert_eq!(ipmes_engine_poll_match(engine, next_match.as_mut_ptr()), 0);
+            ipmes_engine_free(engine);
+
+            let engine = run();
+            let json = ipmes_engine_poll_match_json(engine);
+            assert!(!json.is_null());
+            let value: serde_json::Value =
+                serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            assert_eq!(
+                value["event_ids"].as_array().unwrap().len(),
+                event_ids.len()
+            );
+            ipmes_string_free(json);
+            assert!(ipmes_engine_poll_match_json(engine).is_null());
+            ipmes_engine_free(engine);
+
+            let invalid = CString::new("{}").unwrap();
+            assert!(ipmes_engine_new(invalid.as_ptr(), 1000).is_null());
+            assert!(!ipmes_last_error().is_null());
+            assert!(ipmes_engine_new(pattern.as_ptr(), u64::MAX).is_null());
+            assert_eq!(ipmes_engine_finish(ptr::null_mut()), -1);
+        }
+    }
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(-1, || panic!("broken invariant")), -1);
+        let message = unsafe { CStr::from_ptr(ipmes_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "panicked: broken invariant");
+    }
+}
@@ -0,0 +1,257 @@
+use clap::{Args, ValueEnum};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Run a suite of (pattern, data graph) pairs and report the CPU time, peak memory usage and
+/// number of matches of each pair
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// The path to the benchmark manifest in toml format
+    manifest: PathBuf,
+
+    /// The path to the output report
+    #[arg(short, long, default_value = "bench_report.csv")]
+    output: PathBuf,
+
+    /// The format of the output report
+    #[arg(short, long, value_enum, default_value_t = ReportFormat::Csv)]
+    format: ReportFormat,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum ReportFormat {
+    Csv,
+    Json,
+}
+
+/// The benchmark manifest, e.g.
+///
+/// ```toml
+/// window_size = 1800
+///
+/// [[run]]
+/// name = "SP6"
+/// pattern = "data/universal_patterns/SP6_regex.json"
+/// data_graph = "../data/preprocessed/attack.csv"
+/// repeat = 3
+/// ```
+///
+/// Relative paths are resolved against the directory containing the manifest.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    /// The default window size (sec) of the runs.
+    #[serde(default = "default_window_size")]
+    window_size: u64,
+    #[serde(rename = "run")]
+    runs: Vec<BenchRun>,
+}
+
+fn default_window_size() -> u64 {
+    1800
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct BenchRun {
+    /// Name of this run in the report, defaults to the file stem of the pattern.
+    name: Option<String>,
+    pattern: PathBuf,
+    data_graph: PathBuf,
+    /// Window size (sec), overrides the one in the manifest.
+    window_size: Option<u64>,
+    /// Number of times to run; the CPU time is averaged.
+    #[serde(default = "default_repeat")]
+    repeat: u32,
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+/// A row of the report.
+#[derive(Serialize, Debug, PartialEq)]
+struct BenchResult {
+    name: String,
+    pattern: String,
+    data_graph: String,
+    window_size: u64,
+    num_matches: u64,
+    /// Average CPU time in seconds.
+    cpu_time: f64,
+    /// Peak memory usage in kB.
+    peak_memory: Option<u64>,
+}
+
+pub fn run(args: &BenchArgs) -> Result<(), Box<dyn Error>> {
+    let manifest: Manifest = toml::from_str(&std::fs::read_to_string(&args.manifest)?)?;
+    let base_dir = args.manifest.parent().unwrap_or(Path::new(""));
+    let exe = std::env::current_exe()?;
+
+    let mut results = vec![];
+    for bench_run in &manifest.runs {
+        let result = run_one(&exe, base_dir, bench_run, manifest.window_size)?;
+        println!(
+            "{}: {} matches, {:.3} secs, {} kB",
+            result.name,
+            result.num_matches,
+            result.cpu_time,
+            result
+                .peak_memory
+                .map_or("-".to_string(), |m| m.to_string())
+        );
+        results.push(result);
+    }
+
+    write_report(&results, &args.output, args.format)?;
+    println!("Report written to {}", args.output.display());
+    Ok(())
+}
+
+fn run_one(
+    exe: &Path,
+    base_dir: &Path,
+    bench_run: &BenchRun,
+    default_window_size: u64,
+) -> Result<BenchResult, Box<dyn Error>> {
+    let pattern = base_dir.join(&bench_run.pattern);
+    let data_graph = base_dir.join(&bench_run.data_graph);
+    let window_size = bench_run.window_size.unwrap_or(default_window_size);
+    let name = bench_run.name.clone().unwrap_or_else(|| {
+        pattern
+            .file_stem()
+            .map_or(String::new(), |s| s.to_string_lossy().into_owned())
+    });
+
+    let mut outputs = vec![];
+    for i in 0..bench_run.repeat.max(1) {
+        info!("Running {} ({} / {})", name, i + 1, bench_run.repeat);
+        let output = Command::new(exe)
+            .arg(&pattern)
+            .arg(&data_graph)
+            .args(["-w", &window_size.to_string(), "--silent"])
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "run {} failed: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into());
+        }
+        let stdout = String::from_utf8(output.stdout)?;
+        outputs.push(
+            RunOutput::parse(&stdout).ok_or_else(|| format!("unexpected output of run {name}"))?,
+        );
+    }
+
+    let cpu_time = outputs.iter().map(|o| o.cpu_time).sum::<f64>() / outputs.len() as f64;
+    let last = outputs.last().unwrap();
+    Ok(BenchResult {
+        name,
+        pattern: pattern.display().to_string(),
+        data_graph: data_graph.display().to_string(),
+        window_size,
+        num_matches: last.num_matches,
+        cpu_time,
+        peak_memory: outputs.iter().filter_map(|o| o.peak_memory).max(),
+    })
+}
+
+/// The statistics printed by a single run of ipmes.
+#[derive(Debug, PartialEq)]
+struct RunOutput {
+    num_matches: u64,
+    cpu_time: f64,
+    peak_memory: Option<u64>,
+}
+
+impl RunOutput {
+    fn parse(stdout: &str) -> Option<Self> {
+        let field = |prefix: &str| {
+            stdout
+                .lines()
+                .find_map(|line| line.strip_prefix(prefix))
+                .and_then(|rest| rest.split_whitespace().next())
+        };
+
+        Some(Self {
+            num_matches: field("Total number of matches:")?.parse().ok()?,
+            cpu_time: field("CPU time elapsed:")?.parse().ok()?,
+            peak_memory: field("Peak memory usage:").and_then(|m| m.parse().ok()),
+        })
+    }
+}
+
+fn write_report(
+    results: &[BenchResult],
+    path: &Path,
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Csv => {
+            let mut writer = csv::Writer::from_path(path)?;
+            for result in results {
+                writer.serialize(result)?;
+            }
+            writer.flush()?;
+        }
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(File::create(path)?, results)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            window_size = 100
+
+            [[run]]
+            pattern = "SP6.json"
+            data_graph = "attack.csv"
+
+            [[run]]
+            name = "paper"
+            pattern = "pattern.json"
+            data_graph = "data.csv"
+            window_size = 10
+            repeat = 3
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.window_size, 100);
+        assert_eq!(manifest.runs.len(), 2);
+        assert_eq!(manifest.runs[0].name, None);
+        assert_eq!(manifest.runs[0].repeat, 1);
+        assert_eq!(manifest.runs[1].window_size, Some(10));
+        assert_eq!(manifest.runs[1].repeat, 3);
+    }
+
+    #[test]
+    fn test_parse_run_output() {
+        let stdout = "Total number of matches: 12\n\
+                      CPU time elapsed: 0.25 secs\n\
+                      Peak memory usage: 7780 kB\n";
+        assert_eq!(
+            RunOutput::parse(stdout),
+            Some(RunOutput {
+                num_matches: 12,
+                cpu_time: 0.25,
+                peak_memory: Some(7780),
+            })
+        );
+        assert_eq!(RunOutput::parse("Total number of matches: 12\n"), None);
+    }
+}
@@ -0,0 +1,63 @@
+use ipmes_rust::process_layers::parse_layer::parse_timestamp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Replay the historical data graph at `path` before `live` as a single stream, so that the
+/// partial matches and the other states of the layers carry over to the live events.
+pub fn chain(path: &str, live: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+    let history = File::open(path)?;
+    // in case the history does not end with a newline, the csv reader skips the empty line
+    Ok(Box::new(history.chain(&b"\n"[..]).chain(live)))
+}
+
+/// The timestamp of the last record of the data graph at `path`, read from the end of the file.
+pub fn last_timestamp(path: &str) -> io::Result<Option<u64>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut tail_len = 4096;
+    loop {
+        let start = len.saturating_sub(tail_len);
+        file.seek(SeekFrom::Start(start))?;
+        let mut tail = vec![];
+        (&mut file).take(len - start).read_to_end(&mut tail)?;
+        let tail = String::from_utf8_lossy(&tail);
+        // the first line is cut unless the tail is the whole file
+        let lines: Vec<&str> = tail.lines().skip(usize::from(start > 0)).collect();
+        let timestamp = lines
+            .iter()
+            .rev()
+            .find_map(|line| parse_timestamp(line.split(',').next()?));
+        if timestamp.is_some() || start == 0 {
+            return Ok(timestamp);
+        }
+        tail_len *= 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bootstrap() {
+        assert_eq!(
+            last_timestamp("testcases/paper/data.csv").unwrap(),
+            Some(12000)
+        );
+
+        let mut stream = String::new();
+        chain(
+            "testcases/paper/data.csv",
+            Box::new(&b"13,13,10,read,1,a,2,b"[..]),
+        )
+        .unwrap()
+        .read_to_string(&mut stream)
+        .unwrap();
+        let records = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(stream.as_bytes())
+            .records()
+            .count();
+        assert_eq!(records, 10);
+    }
+}
@@ -0,0 +1,156 @@
+use clap::Args;
+use ipmes_rust::pattern::Pattern;
+use ipmes_rust::process_layers::join_layer::{PartialAlert, PartialAlertTrigger, PartialAlerts};
+use ipmes_rust::process_layers::{
+    ParseLayer, Pipeline, PipelineConfig, WindowBoundary, WindowKind,
+};
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+
+/// Match a pattern over a data graph with a large window, report the distribution of the time
+/// spans of the matches (and near-matches), and suggest the smallest window size (sec) keeping the
+/// given fraction of the matches
+#[derive(Args, Debug)]
+pub struct CalibrateWindowArgs {
+    /// The path to the pattern file in json format
+    pattern_file: String,
+
+    /// The path to the data graph in csv format
+    data_graph: String,
+
+    /// The window size (sec) of the calibration run, which bounds the spans found
+    #[arg(long, default_value_t = 86400)]
+    max_window: u64,
+
+    /// The fraction of the matches kept by the suggested window size
+    #[arg(long, default_value_t = 0.99, value_parser = crate::parse_fraction_arg)]
+    recall: f64,
+
+    /// Also report the spans of the partial matches covering at least this fraction of the
+    /// pattern events, which a larger window may complete
+    #[arg(long, default_value_t = 0.5, value_parser = crate::parse_fraction_arg)]
+    near_fraction: f64,
+}
+
+/// The percentiles reported for the spans.
+const PERCENTILES: [f64; 5] = [0.5, 0.9, 0.95, 0.99, 1.0];
+
+pub fn run(args: &CalibrateWindowArgs) -> Result<(), Box<dyn Error>> {
+    let mut pattern = Pattern::parse(&args.pattern_file)?;
+    pattern.optimize();
+    if pattern.window != WindowKind::Sliding {
+        println!("Note: the spans are measured as in a sliding window, unlike the pattern");
+    }
+
+    let csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_path(&args.data_graph)?;
+    let config = PipelineConfig::new(args.max_window * 1000);
+    let mut pipeline = Pipeline::new(ParseLayer::new(csv_reader), &pattern, config)?;
+    let near_spans = Rc::new(RefCell::new(vec![]));
+    pipeline.join_layer().set_partial_alerts(PartialAlerts::new(
+        PartialAlertTrigger::Fraction(args.near_fraction),
+        {
+            let near_spans = near_spans.clone();
+            move |alert: &PartialAlert| {
+                let pattern_match = &alert.pattern_match;
+                near_spans
+                    .borrow_mut()
+                    .push(pattern_match.latest_time - pattern_match.earliest_time);
+            }
+        },
+    ));
+    let mut spans: Vec<u64> = pipeline
+        .map(|pattern_match| pattern_match.latest_time - pattern_match.earliest_time)
+        .collect();
+    spans.sort_unstable();
+    let mut near_spans = near_spans.take();
+    near_spans.sort_unstable();
+
+    println!(
+        "Matches within a window of {} secs: {}",
+        args.max_window,
+        spans.len()
+    );
+    print_percentiles(&spans);
+    println!(
+        "Near-matches (at least {:.0}% of the pattern events): {}",
+        args.near_fraction * 100.0,
+        near_spans.len()
+    );
+    print_percentiles(&near_spans);
+
+    match suggest_window_size(&spans, args.recall, pattern.window_boundary) {
+        Some(window_size) => println!(
+            "Suggested window size: {} secs ({:.0}% of the matches)",
+            window_size,
+            args.recall * 100.0
+        ),
+        None => println!("No matches found, try a larger --max-window"),
+    }
+    Ok(())
+}
+
+fn print_percentiles(sorted_spans: &[u64]) {
+    if sorted_spans.is_empty() {
+        return;
+    }
+    let percentiles: Vec<String> = PERCENTILES
+        .iter()
+        .map(|&p| {
+            let span = percentile(sorted_spans, p) as f64 / 1000.0;
+            format!("p{}: {:.3}", (p * 100.0) as u32, span)
+        })
+        .collect();
+    println!("    span (sec) {}", percentiles.join(", "));
+}
+
+/// The nearest-rank percentile `p` of the sorted non-empty `sorted_spans`.
+fn percentile(sorted_spans: &[u64], p: f64) -> u64 {
+    let rank = (p * sorted_spans.len() as f64).ceil() as usize;
+    sorted_spans[rank.clamp(1, sorted_spans.len()) - 1]
+}
+
+/// The smallest window size (sec) containing the fraction `recall` of the spans (ms), or `None` if
+/// there are no spans.
+fn suggest_window_size(sorted_spans: &[u64], recall: f64, boundary: WindowBoundary) -> Option<u64> {
+    if sorted_spans.is_empty() {
+        return None;
+    }
+    let span = percentile(sorted_spans, recall);
+    let window_size = match boundary {
+        WindowBoundary::Inclusive => span,
+        // the events exactly a window size apart are not in the same window
+        WindowBoundary::Exclusive => span + 1,
+    };
+    Some(window_size.div_ceil(1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggest_window_size() {
+        let spans: Vec<u64> = (1..=100).map(|i| i * 1000).collect();
+        assert_eq!(percentile(&spans, 0.5), 50_000);
+        assert_eq!(percentile(&spans, 1.0), 100_000);
+        assert_eq!(
+            suggest_window_size(&spans, 0.99, WindowBoundary::Inclusive),
+            Some(99)
+        );
+        assert_eq!(
+            suggest_window_size(&spans, 0.99, WindowBoundary::Exclusive),
+            Some(100)
+        );
+        assert_eq!(
+            suggest_window_size(&[1500], 0.5, WindowBoundary::Inclusive),
+            Some(2)
+        );
+        assert_eq!(
+            suggest_window_size(&[], 0.99, WindowBoundary::Inclusive),
+            None
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use cpu_time::ThreadTime;
+use ipmes_rust::pattern::{CompositePattern, Pattern};
+use ipmes_rust::pattern_match::{MatchSequencer, PatternMatch};
+use ipmes_rust::process_layers::composition_layer::DeactivationPolicy;
+use ipmes_rust::process_layers::parse_layer::Prefilter;
+use ipmes_rust::process_layers::{
+    CorrelationLayer, ParseLayer, Pipeline, PipelineConfig, SignatureSet,
+};
+use log::{info, warn};
+use std::cell::Cell;
+use std::error::Error;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The cost of a component pattern, accounted by [`Profiled`].
+#[derive(Default)]
+struct PatternCost {
+    /// CPU time of the current thread spent in the pipeline of the pattern.
+    cpu_time: Cell<Duration>,
+    num_matches: Cell<u64>,
+}
+
+/// Accounts the CPU time spent in pulling the matches from `inner`, which includes parsing its
+/// own copy of the data graph.
+struct Profiled<'a, I> {
+    inner: I,
+    cost: &'a PatternCost,
+}
+
+impl<'a, I: Iterator<Item = PatternMatch>> Iterator for Profiled<'a, I> {
+    type Item = PatternMatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start_time = ThreadTime::now();
+        let next = self.inner.next();
+        let cost = self.cost;
+        cost.cpu_time
+            .set(cost.cpu_time.get() + start_time.elapsed());
+        cost.num_matches
+            .set(cost.num_matches.get() + next.is_some() as u64);
+        next
+    }
+}
+
+/// Match a composite pattern on the data graph, running one pipeline for each component pattern.
+/// Returns the number of composite matches. If `profile`, the CPU time, the matches and the
+/// match instances of each component pattern are printed at the end, to find the costly ones.
+/// The component patterns deactivated by `deactivation_policy` are reported at the end.
+///
+/// The signatures of all the component patterns are compiled into one [`SignatureSet`], by which
+/// each pipeline drops the events irrelevant to its pattern at ingestion. A component pattern
+/// failing to load is skipped and reported at the end, and never matches.
+pub fn run(
+    composite: &CompositePattern,
+    data_graph: &str,
+    window_size: u64,
+    zip_events: bool,
+    silent: bool,
+    profile: bool,
+    deactivation_policy: Option<DeactivationPolicy>,
+) -> Result<u32, Box<dyn Error>> {
+    info!("Composite expression: {:?}", composite.expression);
+
+    let mut patterns = vec![];
+    let mut skipped = vec![None; composite.pattern_files.len()];
+    for (i, pattern_file) in composite.pattern_files.iter().enumerate() {
+        let pattern_file = pattern_file.to_string_lossy();
+        match Pattern::parse(&pattern_file) {
+            Ok(mut pattern) => {
+                pattern.optimize();
+                patterns.push(Some(pattern));
+            }
+            Err(err) => {
+                skipped[i] = Some(format!("failed to parse {pattern_file}: {err}"));
+                patterns.push(None);
+            }
+        }
+    }
+
+    // the component patterns in the signature set, by their indices in the set
+    let loaded: Vec<usize> = (0..patterns.len())
+        .filter(|i| patterns[*i].is_some())
+        .collect();
+    let signatures = match SignatureSet::new(loaded.iter().flat_map(|i| &patterns[*i])) {
+        Ok(signatures) => Some(Rc::new(signatures)),
+        Err(err) => {
+            warn!("No prefilter is applied: {err}");
+            None
+        }
+    };
+    if let Some(signatures) = &signatures {
+        for (set_idx, err) in signatures.rejected() {
+            skipped[loaded[*set_idx]] = Some(format!("rejected signature: {err}"));
+        }
+    }
+
+    let mut pipelines = vec![];
+    for (i, pattern) in patterns.iter().enumerate() {
+        let Some(pattern) = pattern.as_ref().filter(|_| skipped[i].is_none()) else {
+            pipelines.push(None);
+            continue;
+        };
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(data_graph)?;
+        let mut parse_layer = ParseLayer::new(csv_reader);
+        let set_idx = loaded.binary_search(&i).ok();
+        if let (Some(signatures), Some(set_idx)) = (&signatures, set_idx) {
+            if let Some(prefilter) =
+                Prefilter::from_shared_signatures(signatures.clone(), set_idx, pattern)
+            {
+                parse_layer.set_prefilter(prefilter);
+            }
+        }
+        let mut config = PipelineConfig::new(window_size)
+            .with_zip_events(zip_events)
+            .with_ordered_output(true);
+        if let Some(policy) = deactivation_policy {
+            config = config.with_deactivation_policy(policy);
+        }
+        match Pipeline::new(parse_layer, pattern, config) {
+            Ok(pipeline) => pipelines.push(Some(pipeline)),
+            Err(err) => {
+                skipped[i] = Some(err.to_string());
+                pipelines.push(None);
+            }
+        }
+    }
+
+    let costs: Vec<PatternCost> = patterns.iter().map(|_| PatternCost::default()).collect();
+    // a skipped pattern has no pipeline, and its stream is empty
+    let streams = (pipelines.iter_mut().zip(&costs))
+        .map(|(pipeline, cost)| Profiled {
+            inner: pipeline.iter_mut().flatten(),
+            cost,
+        })
+        .collect();
+    let mut num_result = 0u32;
+    let mut sequencer = MatchSequencer::new(window_size);
+    for composite_match in CorrelationLayer::new(streams, &composite.expression, window_size) {
+        let sequence = sequencer.next(composite_match.earliest_time);
+        if !silent {
+            let matches = composite_match
+                .matches
+                .iter()
+                .map(|(pattern_id, m)| format!("{}: {}", composite.names[*pattern_id], m))
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!(
+                "Composite Match: #{} (window {}) {{{}}}",
+                sequence.number, sequence.window_id, matches
+            );
+        }
+        num_result += 1;
+    }
+
+    for (i, pipeline) in pipelines.iter_mut().enumerate() {
+        if let Some(reason) = &skipped[i] {
+            println!("{}: skipped, {}", composite.names[i], reason);
+        }
+        let (Some(pipeline), Some(pattern)) = (pipeline, &patterns[i]) else {
+            continue;
+        };
+        if let Some(deactivation) = pipeline.composition_layer().deactivation() {
+            let description = super::match_pattern::describe_deactivation(pattern, deactivation);
+            println!("{}: {}", composite.names[i], description);
+        }
+    }
+
+    if profile {
+        let mut order: Vec<usize> = (0..patterns.len()).collect();
+        order.sort_by_key(|i| std::cmp::Reverse(costs[*i].cpu_time.get()));
+        println!("Pattern costs:");
+        for i in order {
+            let Some(pipeline) = &mut pipelines[i] else {
+                continue;
+            };
+            println!(
+                "    {}: {:.3} secs CPU time, {} matches",
+                composite.names[i],
+                costs[i].cpu_time.get().as_secs_f64(),
+                costs[i].num_matches.get()
+            );
+            for sub_pattern in pipeline.composition_layer().sub_pattern_instances() {
+                println!(
+                    "        sub-pattern {}: {} partial instances, {} sub-pattern matches",
+                    sub_pattern.sub_pattern_id, sub_pattern.num_partial, sub_pattern.num_complete
+                );
+            }
+        }
+    }
+    Ok(num_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipmes_rust::pattern::CompositeExpr;
+
+    #[test]
+    fn test_skip_unloadable_pattern() {
+        let composite = CompositePattern {
+            names: vec!["paper".into(), "missing".into(), "sp6".into()],
+            pattern_files: vec![
+                "testcases/paper/pattern.json".into(),
+                "testcases/paper/missing.json".into(),
+                "data/universal_patterns/SP6.json".into(),
+            ],
+            expression: CompositeExpr::Or(vec![
+                CompositeExpr::Pattern(0),
+                CompositeExpr::Pattern(1),
+                CompositeExpr::Pattern(2),
+            ]),
+        };
+        let num_result = run(
+            &composite,
+            "testcases/paper/data.csv",
+            1000 * 1000,
+            false,
+            true,
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(num_result, 1);
+    }
+}
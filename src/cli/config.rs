@@ -0,0 +1,208 @@
+use clap::parser::ValueSource;
+use clap::{ArgAction, Command};
+use std::error::Error;
+use std::ffi::OsString;
+use toml::{Table, Value};
+
+/// The positional arguments of the main command, which can also be given in the config file.
+const POSITIONALS: [&str; 2] = ["pattern_file", "data_graph"];
+
+/// Returns the command line `args` (starting with the program name) along with the arguments in
+/// the config file of `--config`, if any, in toml format, e.g.
+///
+/// ```toml
+/// pattern_file = "data/universal_patterns/SP6.json"
+/// window_size = 600
+/// host_column = true
+/// trace_events = [123, 456]
+/// ```
+///
+/// The keys are the long names of the arguments, in snake case or kebab case. The arguments given
+/// on the command line override those in the config file, and `--no-<flag>` turns off a flag set
+/// in the config file. The config file only applies to the main command, not to the subcommands.
+pub fn merge_args(command: Command, args: Vec<OsString>) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let has_config = args
+        .iter()
+        .filter_map(|arg| arg.to_str())
+        .any(|arg| arg == "--config" || arg.starts_with("--config="));
+    if !has_config {
+        return Ok(take_negated_flags(&command, args).0);
+    }
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)?;
+    if matches.subcommand().is_some() {
+        return Ok(args);
+    }
+    let Some(config_file) = matches.get_one::<String>("config") else {
+        return Ok(args);
+    };
+    let config: Table = toml::from_str(&std::fs::read_to_string(config_file)?)?;
+    merge_config(command, &config, args)
+}
+
+fn merge_config(
+    command: Command,
+    config: &Table,
+    args: Vec<OsString>,
+) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let (args, negated_flags) = take_negated_flags(&command, args);
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)?;
+    let on_command_line = |id: &str| {
+        matches.value_source(id) == Some(ValueSource::CommandLine)
+            || negated_flags.iter().any(|flag| flag == id)
+    };
+
+    let mut options = vec![];
+    for (key, value) in config {
+        let id = key.replace('-', "_");
+        if id == "config" || POSITIONALS.contains(&id.as_str()) {
+            continue;
+        }
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str())
+            .ok_or_else(|| format!("unknown argument: {key}"))?;
+        if on_command_line(&id) {
+            continue;
+        }
+        let long = arg
+            .get_long()
+            .ok_or_else(|| format!("unknown argument: {key}"))?;
+        match (arg.get_action(), value) {
+            (ArgAction::SetTrue, Value::Boolean(true)) => options.push(format!("--{long}")),
+            (ArgAction::SetTrue, Value::Boolean(false)) => {}
+            (ArgAction::SetTrue, _) => return Err(format!("expected a boolean: {key}").into()),
+            (_, Value::Array(values)) => {
+                for value in values {
+                    options.push(format!("--{long}={}", scalar(key, value)?));
+                }
+            }
+            (_, value) => options.push(format!("--{long}={}", scalar(key, value)?)),
+        }
+    }
+
+    // The positional arguments on the command line override the ones in the config file in
+    // order, except that a single one is the data graph if the config file has the pattern file.
+    let [pattern_file, data_graph] =
+        POSITIONALS.map(|id| config.get(id).map(|value| scalar(id, value)).transpose());
+    let (pattern_file, data_graph) = (pattern_file?, data_graph?);
+    let num_positionals = POSITIONALS.iter().filter(|id| on_command_line(id)).count();
+    let (prepended, appended) = match (num_positionals, pattern_file) {
+        (0, pattern_file) => (pattern_file.into_iter().chain(data_graph).collect(), None),
+        (1, Some(pattern_file)) => (vec![pattern_file], None),
+        (1, None) => (vec![], data_graph),
+        _ => (vec![], None),
+    };
+
+    let mut args = args.into_iter();
+    let mut merged: Vec<OsString> = args.next().into_iter().collect();
+    merged.extend(prepended.into_iter().map(OsString::from));
+    merged.extend(options.into_iter().map(OsString::from));
+    merged.extend(args);
+    merged.extend(appended.map(OsString::from));
+    Ok(merged)
+}
+
+/// Remove the `--no-<flag>` arguments of the flags of `command` from `args`, which are only
+/// meaningful along with a config file. Returns the rest of `args` and the ids of the flags.
+fn take_negated_flags(command: &Command, args: Vec<OsString>) -> (Vec<OsString>, Vec<String>) {
+    let mut negated_flags = vec![];
+    let mut rest = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    rest.extend(args.next());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            rest.push(arg);
+            rest.extend(args.by_ref());
+            break;
+        }
+        let flag = (arg.to_str())
+            .and_then(|arg| arg.strip_prefix("--no-"))
+            .and_then(|long| {
+                command.get_arguments().find(|arg| {
+                    arg.get_long() == Some(long) && matches!(arg.get_action(), ArgAction::SetTrue)
+                })
+            });
+        match flag {
+            Some(flag) => negated_flags.push(flag.get_id().to_string()),
+            None => rest.push(arg),
+        }
+    }
+    (rest, negated_flags)
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, Box<dyn Error>> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Float(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(format!("expected a value or an array of values: {key}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, Parser};
+
+    #[derive(Parser, Debug)]
+    struct TestArgs {
+        pattern_file: Option<String>,
+        data_graph: Option<String>,
+        #[arg(long)]
+        config: Option<String>,
+        #[arg(short, long, default_value_t = 1800)]
+        window_size: u64,
+        #[arg(short, long, default_value_t = false)]
+        silent: bool,
+        #[arg(long, value_delimiter = ',')]
+        rules: Vec<String>,
+    }
+
+    fn parse(config: &str, args: &[&str]) -> Result<TestArgs, Box<dyn Error>> {
+        let config: Table = toml::from_str(config)?;
+        let args = args.iter().map(OsString::from).collect();
+        let args = merge_config(TestArgs::command(), &config, args)?;
+        Ok(TestArgs::try_parse_from(args)?)
+    }
+
+    #[test]
+    fn test_merge_config() {
+        let config = r#"
+            pattern_file = "p.json"
+            window-size = 10
+            silent = true
+            rules = ["SP6", "SP8"]
+        "#;
+        let args = parse(
+            config,
+            &["ipmes", "--config", "c.toml", "-w", "20", "d.csv"],
+        )
+        .unwrap();
+        assert_eq!(args.pattern_file.as_deref(), Some("p.json"));
+        assert_eq!(args.data_graph.as_deref(), Some("d.csv"));
+        assert_eq!(args.window_size, 20);
+        assert!(args.silent);
+        assert_eq!(args.rules, ["SP6", "SP8"]);
+
+        let args = parse(config, &["ipmes", "--rules", "SP1", "q.json", "d.csv"]).unwrap();
+        assert_eq!(args.pattern_file.as_deref(), Some("q.json"));
+        assert_eq!(args.window_size, 10);
+        assert_eq!(args.rules, ["SP1"]);
+
+        // a flag set in the config file is turned off by `--no-<flag>`
+        let args = parse(config, &["ipmes", "--no-silent", "d.csv"]).unwrap();
+        assert!(!args.silent);
+        assert_eq!(args.data_graph.as_deref(), Some("d.csv"));
+        assert!(parse(config, &["ipmes", "--no-window-size", "d.csv"]).is_err());
+
+        assert!(parse("window = 10", &["ipmes"]).is_err());
+        assert!(parse("silent = 1", &["ipmes"]).is_err());
+    }
+}
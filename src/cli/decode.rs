@@ -0,0 +1,25 @@
+use clap::Args;
+use ipmes_rust::binary_matches::BinaryMatchReader;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+
+/// Convert the matches written with `--binary-output` into JSON lines, as printed with `--jsonl`
+#[derive(Args, Debug)]
+pub struct DecodeArgs {
+    /// The binary match file
+    input: String,
+}
+
+/// Returns the number of matches decoded.
+pub fn run(args: &DecodeArgs) -> Result<u64, Box<dyn Error>> {
+    let reader = BinaryMatchReader::new(BufReader::new(File::open(&args.input)?))?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut num_matches = 0;
+    for pattern_match in reader {
+        writeln!(out, "{}", pattern_match?.to_json())?;
+        num_matches += 1;
+    }
+    out.flush()?;
+    Ok(num_matches)
+}
@@ -0,0 +1,144 @@
+use clap::Args;
+use ipmes_rust::match_schema::MatchRecord;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Compare the matches of two runs printed with `--jsonl`, e.g. by different versions of the
+/// engine, and report the matches found by only one of them. Matches are keyed by their sets of
+/// input event ids
+#[derive(Args, Debug)]
+pub struct DiffResultsArgs {
+    /// The output of the first run
+    first: String,
+
+    /// The output of the second run
+    second: String,
+}
+
+/// The matches of a run with the same input event ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MatchEntry {
+    count: usize,
+    /// The time range of the first of the matches, kept for the report.
+    earliest_time: u64,
+    latest_time: u64,
+}
+
+impl MatchEntry {
+    fn new(earliest_time: u64, latest_time: u64) -> Self {
+        Self {
+            count: 0,
+            earliest_time,
+            latest_time,
+        }
+    }
+}
+
+/// The matches of a run, keyed by their sorted input event ids.
+type MatchSet = BTreeMap<Vec<u64>, MatchEntry>;
+
+/// Returns `Ok(true)` if both runs have the same matches.
+pub fn run(args: &DiffResultsArgs) -> Result<bool, Box<dyn Error>> {
+    let first = read_matches(&args.first)?;
+    let second = read_matches(&args.second)?;
+    let only_first = difference(&first, &second);
+    let only_second = difference(&second, &first);
+
+    for (name, only) in [(&args.first, &only_first), (&args.second, &only_second)] {
+        println!("Only in {}: {} matches", name, only.len());
+        for (event_ids, entry) in only {
+            println!(
+                "    <{:.3}, {:.3}>{:?}",
+                entry.earliest_time as f64 / 1000.0,
+                entry.latest_time as f64 / 1000.0,
+                event_ids
+            );
+        }
+    }
+    let num_first: usize = first.values().map(|entry| entry.count).sum();
+    println!("Common: {} matches", num_first - only_first.len());
+    Ok(only_first.is_empty() && only_second.is_empty())
+}
+
+/// Read the JSON lines of matches in the output at `path`. The other lines, such as the summary
+/// of the run, are skipped.
+fn read_matches(path: &str) -> Result<MatchSet, Box<dyn Error>> {
+    let mut matches = MatchSet::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let Some((event_ids, earliest_time, latest_time)) = parse_match(&line) else {
+            continue;
+        };
+        matches
+            .entry(event_ids)
+            .or_insert(MatchEntry::new(earliest_time, latest_time))
+            .count += 1;
+    }
+    Ok(matches)
+}
+
+/// Parse a match printed by `PatternMatch::to_json()` of any version, returning its sorted event
+/// ids and time range.
+fn parse_match(line: &str) -> Option<(Vec<u64>, u64, u64)> {
+    let value: Value = serde_json::from_str(line.trim()).ok()?;
+    let record = MatchRecord::from_json(value).ok()?.into_latest();
+    let mut event_ids = record.event_ids;
+    event_ids.sort_unstable();
+    Some((event_ids, record.earliest_time, record.latest_time))
+}
+
+/// The matches in `a` but not in `b`, one entry for each missing copy of a match.
+fn difference<'a>(a: &'a MatchSet, b: &MatchSet) -> Vec<(&'a Vec<u64>, MatchEntry)> {
+    let mut only = vec![];
+    for (event_ids, entry) in a {
+        let count_b = b.get(event_ids).map_or(0, |entry| entry.count);
+        for _ in count_b..entry.count {
+            only.push((event_ids, *entry));
+        }
+    }
+    only
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_difference() {
+        let parse = |lines: &[&str]| {
+            let mut matches = MatchSet::new();
+            for line in lines {
+                if let Some((event_ids, t1, t2)) = parse_match(line) {
+                    matches
+                        .entry(event_ids)
+                        .or_insert(MatchEntry::new(t1, t2))
+                        .count += 1;
+                }
+            }
+            matches
+        };
+        let first = parse(&[
+            r#"{"earliest_time":7000,"latest_time":12000,"event_ids":[3,4,6]}"#,
+            r#"{"earliest_time":7000,"latest_time":12000,"event_ids":[6,3,4]}"#,
+            r#"{"earliest_time":1000,"latest_time":2000,"event_ids":[1,2]}"#,
+            "Total number of matches: 3",
+        ]);
+        // the output of a version printing the schema version
+        let second = parse(&[
+            r#"{"version":"1","earliest_time":7000,"latest_time":12000,"event_ids":[4,3,6]}"#,
+            r#"{"earliest_time":5000,"latest_time":9000,"event_ids":[5]}"#,
+        ]);
+
+        let only_first = difference(&first, &second);
+        let keys: Vec<&[u64]> = only_first.iter().map(|(ids, _)| ids.as_slice()).collect();
+        assert_eq!(keys, [&[1, 2][..], &[3, 4, 6][..]]);
+        let only_second = difference(&second, &first);
+        assert_eq!(only_second.len(), 1);
+        assert_eq!(only_second[0].0, &[5]);
+        let entry = only_second[0].1;
+        assert_eq!((entry.earliest_time, entry.latest_time), (5000, 9000));
+    }
+}
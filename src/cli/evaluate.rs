@@ -0,0 +1,159 @@
+use ipmes_rust::pattern_match::PatternMatch;
+use serde_json::Value;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+/// An expected match in the ground-truth file of `--evaluate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Label {
+    /// Hit by a match containing all these input events, e.g. `{"EventIds": [3, 4, 6]}`.
+    EventIds(Vec<u64>),
+    /// Hit by a match lying within this time range (ms), e.g. `{"From": 7.0, "To": 12.5}` in
+    /// seconds.
+    TimeRange(u64, u64),
+}
+
+impl Label {
+    fn parse(value: &Value) -> Option<Self> {
+        if let Some(event_ids) = value["EventIds"].as_array() {
+            let event_ids = event_ids.iter().map(Value::as_u64).collect::<Option<_>>()?;
+            return Some(Self::EventIds(event_ids));
+        }
+        let to_millis = |secs: &Value| secs.as_f64().map(|secs| (secs * 1000.0).round() as u64);
+        Some(Self::TimeRange(
+            to_millis(&value["From"])?,
+            to_millis(&value["To"])?,
+        ))
+    }
+
+    fn is_hit_by(&self, pattern_match: &PatternMatch) -> bool {
+        match self {
+            Label::EventIds(event_ids) => event_ids.iter().all(|id| {
+                pattern_match
+                    .event_ids
+                    .iter()
+                    .any(|(input_event_id, _)| input_event_id == id)
+            }),
+            Label::TimeRange(from, to) => {
+                *from <= pattern_match.earliest_time && pattern_match.latest_time <= *to
+            }
+        }
+    }
+}
+
+/// Scores the output matches against the expected ones, for `--evaluate`.
+///
+/// A match is a true positive if it hits any label, and a label is recalled if any match hits it.
+#[derive(Debug)]
+pub struct Evaluation {
+    labels: Vec<Label>,
+    recalled: Vec<bool>,
+    num_matches: usize,
+    num_true_matches: usize,
+}
+
+impl Evaluation {
+    /// Load the ground-truth file at `path`, a JSON array of labels.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let value: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        Self::from_json(&value)
+    }
+
+    fn from_json(value: &Value) -> Result<Self, Box<dyn Error>> {
+        let labels = value
+            .as_array()
+            .ok_or("the ground truth must be an array of labels")?
+            .iter()
+            .map(|label| Label::parse(label).ok_or(format!("invalid label: {label}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            recalled: vec![false; labels.len()],
+            labels,
+            num_matches: 0,
+            num_true_matches: 0,
+        })
+    }
+
+    pub fn add(&mut self, pattern_match: &PatternMatch) {
+        self.num_matches += 1;
+        let mut is_true = false;
+        for (label, recalled) in self.labels.iter().zip(&mut self.recalled) {
+            if label.is_hit_by(pattern_match) {
+                *recalled = true;
+                is_true = true;
+            }
+        }
+        if is_true {
+            self.num_true_matches += 1;
+        }
+    }
+
+    /// Returns the precision, recall and F1 score, where the precision (recall) is 1 if there is
+    /// no match (label).
+    pub fn scores(&self) -> (f64, f64, f64) {
+        let ratio = |a: usize, b: usize| if b == 0 { 1.0 } else { a as f64 / b as f64 };
+        let precision = ratio(self.num_true_matches, self.num_matches);
+        let num_recalled = self.recalled.iter().filter(|r| **r).count();
+        let recall = ratio(num_recalled, self.labels.len());
+        let f1 = if precision + recall == 0.0 {
+            0.0
+        } else {
+            2.0 * precision * recall / (precision + recall)
+        };
+        (precision, recall, f1)
+    }
+
+    pub fn print_summary(&self) {
+        let (precision, recall, f1) = self.scores();
+        let num_recalled = self.recalled.iter().filter(|r| **r).count();
+        println!(
+            "Precision: {precision:.3} ({} of {} matches)",
+            self.num_true_matches, self.num_matches
+        );
+        println!(
+            "Recall: {recall:.3} ({num_recalled} of {} labels)",
+            self.labels.len()
+        );
+        println!("F1: {f1:.3}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pattern_match(event_ids: &[u64], earliest_time: u64, latest_time: u64) -> PatternMatch {
+        PatternMatch {
+            earliest_time,
+            latest_time,
+            event_ids: event_ids.iter().map(|id| (*id, 0)).collect(),
+            match_event_map: Box::new([]),
+            annotations: serde_json::Map::new(),
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let mut evaluation = Evaluation::from_json(&json!([
+            { "EventIds": [3, 6] },
+            { "From": 20, "To": 30.5 },
+            { "EventIds": [100] },
+        ]))
+        .unwrap();
+        evaluation.add(&pattern_match(&[3, 4, 6], 7000, 12000));
+        evaluation.add(&pattern_match(&[20, 21], 20000, 30500));
+        evaluation.add(&pattern_match(&[20, 40], 20000, 40000));
+        evaluation.add(&pattern_match(&[3, 5], 7000, 9000));
+
+        let (precision, recall, f1) = evaluation.scores();
+        assert_eq!(precision, 0.5);
+        assert_eq!(recall, 2.0 / 3.0);
+        assert!((f1 - 4.0 / 7.0).abs() < 1e-9);
+
+        assert!(Evaluation::from_json(&json!([{ "From": 1 }])).is_err());
+        assert!(Evaluation::from_json(&json!({ "EventIds": [1] })).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use clap::{Args, ValueEnum};
+use ipmes_rust::pattern::{decompose, Pattern, SubPattern};
+use ipmes_rust::process_layers::composition_layer::explain_state_table;
+use ipmes_rust::process_layers::join_layer::{join_plan, JoinPlan};
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Print how a pattern is executed: its decomposition into sub-patterns, the state table of the
+/// Composition layer, and the join tree of the Join layer
+#[derive(Args, Debug)]
+pub struct ExplainPatternArgs {
+    /// The path to the pattern file in json format
+    pattern_file: String,
+
+    /// The output format
+    #[arg(short, long, value_enum, default_value_t = ExplainFormat::Text)]
+    format: ExplainFormat,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum ExplainFormat {
+    Text,
+    Json,
+}
+
+pub fn run(args: &ExplainPatternArgs) -> Result<(), Box<dyn Error>> {
+    let mut pattern = Pattern::parse(&args.pattern_file)?;
+    pattern.optimize();
+    let decomposition = decompose(&pattern);
+    let plan = match &pattern.join_plan {
+        Some(plan) => plan.clone(),
+        None => join_plan::build(&decomposition),
+    };
+    let explanation = explain(&decomposition, &plan);
+    match args.format {
+        ExplainFormat::Json => println!("{}", serde_json::to_string_pretty(&explanation)?),
+        ExplainFormat::Text => print!("{}", to_text(&explanation, &plan)),
+    }
+    Ok(())
+}
+
+fn explain(decomposition: &[SubPattern], plan: &JoinPlan) -> Value {
+    let sub_patterns: Vec<Value> = decomposition
+        .iter()
+        .map(|sub_pattern| {
+            let events: Vec<Value> = sub_pattern
+                .events
+                .iter()
+                .map(|event| {
+                    json!({
+                        "id": event.id,
+                        "type": format!("{:?}", event.event_type),
+                        "signature": if event.alternatives.is_empty() {
+                            json!(event.signature)
+                        } else {
+                            json!(event.signatures().collect::<Vec<_>>())
+                        },
+                        "subject": event.subject.id,
+                        "object": event.object.id,
+                    })
+                })
+                .collect();
+            json!({
+                "id": sub_pattern.id,
+                "optional": sub_pattern.is_optional(),
+                "events": events,
+            })
+        })
+        .collect();
+
+    json!({
+        "decomposition": sub_patterns,
+        "state_table": explain_state_table(decomposition),
+        "join_plan": {
+            "height": plan.height(),
+            "merges": plan.merges,
+        },
+    })
+}
+
+fn to_text(explanation: &Value, plan: &JoinPlan) -> String {
+    let mut text = String::from("Decomposition:\n");
+    for sub_pattern in explanation["decomposition"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        let optional = if sub_pattern["optional"] == true {
+            " (optional)"
+        } else {
+            ""
+        };
+        text += &format!("  Sub-pattern {}{optional}:\n", sub_pattern["id"]);
+        for event in sub_pattern["events"].as_array().into_iter().flatten() {
+            text += &format!(
+                "    event {}: {} -> {}, {}",
+                event["id"],
+                event["subject"],
+                event["object"],
+                event["type"].as_str().unwrap_or_default()
+            );
+            match &event["signature"] {
+                Value::String(signature) if !signature.is_empty() => {
+                    text += &format!(", signature {signature:?}\n")
+                }
+                Value::Array(alternatives) => {
+                    let alternatives = alternatives.iter().map(|s| s.as_str().unwrap_or_default());
+                    text += &format!(", signature {:?}\n", alternatives.collect::<Vec<_>>())
+                }
+                _ => text += "\n",
+            }
+        }
+    }
+
+    text += "State table:\n";
+    for state in explanation["state_table"].as_array().into_iter().flatten() {
+        text += &format!(
+            "  state {}: {}; filter {}",
+            state["state_id"],
+            describe(state, &["state_id", "filter", "shared_node_info"]),
+            describe(&state["filter"], &[])
+        );
+        if let Some(shared_node_info) = state["shared_node_info"].as_str() {
+            text += &format!("; shared node {shared_node_info}");
+        }
+        text += "\n";
+    }
+
+    text += &format!("Join tree (height {}):\n", plan.height());
+    if plan.num_sub_patterns > 0 {
+        let root = plan.num_sub_patterns + plan.merges.len() - 1;
+        write_join_tree(&mut text, root, plan, 1);
+    }
+    text
+}
+
+/// Describe the fields of a json object in a line, with its `type` first.
+fn describe(object: &Value, skipped_keys: &[&str]) -> String {
+    let Some(object) = object.as_object() else {
+        return object.to_string();
+    };
+    let mut fields = vec![];
+    if let Some(object_type) = object.get("type").and_then(Value::as_str) {
+        fields.push(object_type.to_string());
+    }
+    for (key, value) in object {
+        if key == "type" || skipped_keys.contains(&key.as_str()) {
+            continue;
+        }
+        let value = value
+            .as_str()
+            .map_or_else(|| value.to_string(), str::to_string);
+        fields.push(format!("{key} {value}"));
+    }
+    fields.join(", ")
+}
+
+fn write_join_tree(text: &mut String, buffer_id: usize, plan: &JoinPlan, depth: usize) {
+    let indent = "  ".repeat(depth);
+    if buffer_id < plan.num_sub_patterns {
+        *text += &format!("{indent}buffer {buffer_id}: sub-pattern {buffer_id}\n");
+    } else {
+        let (left, right) = plan.merges[buffer_id - plan.num_sub_patterns];
+        *text += &format!("{indent}buffer {buffer_id}: join of buffers {left} and {right}\n");
+        write_join_tree(text, left, plan, depth + 1);
+        write_join_tree(text, right, plan, depth + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_paper_pattern() {
+        let mut pattern = Pattern::parse("data/paper/behavioral_pattern.json").unwrap();
+        pattern.optimize();
+        let decomposition = decompose(&pattern);
+        let plan = join_plan::build(&decomposition);
+        let explanation = explain(&decomposition, &plan);
+
+        assert_eq!(explanation["state_table"].as_array().unwrap().len(), 7);
+        let text = to_text(&explanation, &plan);
+        assert!(text.contains("    event 2: 1 -> 2, Frequency(2), signature \"write\"\n"));
+        assert!(text.contains("  state 1: InitFreq, next_state 2; filter Subject, match_idx 1, subject object_of(0); shared node Subject\n"));
+        assert!(text.ends_with("  buffer 2: join of buffers 0 and 1\n    buffer 0: sub-pattern 0\n    buffer 1: sub-pattern 1\n"));
+    }
+}
@@ -0,0 +1,97 @@
+use clap::{Args, ValueEnum};
+use ipmes_rust::pattern::Pattern;
+use ipmes_rust::synthetic_data::{generate, SyntheticConfig, TimeDistribution};
+use serde_json::json;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Synthesize a data graph in csv format with instances of the given patterns embedded in random
+/// background events, e.g. for benchmarking or for checking that all the instances are matched
+#[derive(Args, Debug)]
+pub struct GenDataArgs {
+    /// The path to a pattern file in json format, can be given multiple times
+    #[arg(short, long = "pattern", required = true)]
+    patterns: Vec<String>,
+
+    /// The number of instances embedded for each pattern
+    #[arg(long, default_value_t = 10)]
+    instances: usize,
+
+    /// The number of background events
+    #[arg(long, default_value_t = 10_000)]
+    noise_events: usize,
+
+    /// The number of entities among which the background events occur
+    #[arg(long, default_value_t = 1000)]
+    entities: u64,
+
+    /// The time span (sec) of the data graph
+    #[arg(long, default_value_t = 3600)]
+    duration: u64,
+
+    /// The maximum time span (sec) of an embedded instance, unless its time gaps require more
+    #[arg(long, default_value_t = 10)]
+    instance_span: u64,
+
+    /// How the background events are spread over time
+    #[arg(long, value_enum, default_value_t = Distribution::Uniform)]
+    time_distribution: Distribution,
+
+    /// The seed of the random generator, the same seed gives the same data graph
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// The path to the output data graph, stdout if not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Also write the input event ids of the embedded instances to this file, in the format of
+    /// `--evaluate`
+    #[arg(long)]
+    truth: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum Distribution {
+    Uniform,
+    Poisson,
+}
+
+pub fn run(args: &GenDataArgs) -> Result<(), Box<dyn Error>> {
+    let patterns = args
+        .patterns
+        .iter()
+        .map(|path| Pattern::parse(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let config = SyntheticConfig {
+        num_instances: args.instances,
+        num_noise_events: args.noise_events,
+        num_entities: args.entities,
+        duration: args.duration * 1000,
+        instance_span: args.instance_span * 1000,
+        time_distribution: match args.time_distribution {
+            Distribution::Uniform => TimeDistribution::Uniform,
+            Distribution::Poisson => TimeDistribution::Poisson,
+        },
+        seed: args.seed,
+        ..Default::default()
+    };
+    let data = generate(&patterns, &config)?;
+
+    match &args.output {
+        Some(path) => data.write_csv(BufWriter::new(File::create(path)?))?,
+        None => data.write_csv(io::stdout().lock())?,
+    }
+
+    if let Some(path) = &args.truth {
+        let labels: Vec<_> = (data.instances.iter())
+            .map(|(_, event_ids)| json!({ "EventIds": event_ids }))
+            .collect();
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut out, &labels)?;
+        out.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use clap::Args;
+use ipmes_rust::pattern::PatternLibrary;
+use std::error::Error;
+
+/// List the patterns in the pattern library with their categories and ATT&CK techniques, which
+/// can be selected by `--rules`, `--category` or `--technique`
+#[derive(Args, Debug)]
+pub struct ListRulesArgs {
+    /// A directory of patterns instead of the bundled ones, see `--library` of the main command
+    #[arg(long, value_name = "DIR")]
+    library: Option<String>,
+}
+
+pub fn run(args: &ListRulesArgs) -> Result<(), Box<dyn Error>> {
+    let library = match &args.library {
+        Some(library_dir) => PatternLibrary::load(library_dir)?,
+        None => PatternLibrary::bundled()?,
+    };
+    for entry in &library.entries {
+        println!(
+            "{:<8} {:<32} {:<24} {}",
+            entry.name,
+            entry.categories.join(","),
+            entry.techniques.join(","),
+            entry.description.as_deref().unwrap_or("")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use clap::Args;
+use ipmes_rust::pattern::RulePack;
+use ipmes_rust::process_layers::SignatureSet;
+use std::error::Error;
+
+/// Load a rule pack, a json or tar file bundling many patterns, and report the loading status of
+/// each rule
+#[derive(Args, Debug)]
+pub struct LoadPackArgs {
+    /// The path to the rule pack, a tar file if it ends with `.tar`
+    pack_file: String,
+}
+
+/// Returns `Ok(true)` if all the rules are loaded.
+pub fn run(args: &LoadPackArgs) -> Result<bool, Box<dyn Error>> {
+    let pack = RulePack::load(&args.pack_file)?;
+    let pack_version = pack
+        .pack_version
+        .as_ref()
+        .map_or_else(String::new, |version| format!(" {version}"));
+    println!(
+        "Rule pack {}{} (pattern version {}): {} rules",
+        pack.name,
+        pack_version,
+        pack.version,
+        pack.rules.len()
+    );
+    // the signatures of all the rules in one regex set, where a rule with an invalid signature
+    // fails by itself
+    let loaded: Vec<_> = pack
+        .rules
+        .iter()
+        .enumerate()
+        .filter_map(|(rule_idx, rule)| Some((rule_idx, rule.pattern.as_ref().ok()?)))
+        .collect();
+    let signatures = SignatureSet::new(loaded.iter().map(|(_, pattern)| *pattern))?;
+    let mut regex_errors = vec![None; pack.rules.len()];
+    for (pattern_idx, err) in signatures.rejected() {
+        regex_errors[loaded[*pattern_idx].0] = Some(err);
+    }
+
+    let mut num_failed = 0;
+    for (rule, regex_error) in pack.rules.iter().zip(&regex_errors) {
+        match (&rule.pattern, regex_error) {
+            (Ok(pattern), None) => println!("OK   {} ({} events)", rule.name, pattern.events.len()),
+            (Err(err), _) => println!("FAIL {}: {}", rule.name, err),
+            (Ok(_), Some(err)) => println!("FAIL {}: {}", rule.name, err),
+        }
+        num_failed += usize::from(rule.pattern.is_err() || regex_error.is_some());
+    }
+    println!(
+        "{} loaded, {} failed",
+        pack.rules.len() - num_failed,
+        num_failed
+    );
+
+    let shared: Vec<_> = signatures.shared_signatures().collect();
+    if !shared.is_empty() {
+        println!(
+            "{} of {} signatures are shared across rules:",
+            shared.len(),
+            signatures.len()
+        );
+    }
+    for (index, owners) in shared {
+        let owners = owners
+            .iter()
+            .map(|(pattern_idx, event_id)| {
+                format!(
+                    "{} event {}",
+                    pack.rules[loaded[*pattern_idx].0].name, event_id
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("    signature {index}: {owners}");
+    }
+    Ok(num_failed == 0)
+}
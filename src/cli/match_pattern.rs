@@ -0,0 +1,893 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Read};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use cpu_time::ProcessTime;
+
+use ipmes_rust::binary_matches::BinaryMatchWriter;
+#[cfg(unix)]
+use ipmes_rust::cancellation::{cancel_on_termination, termination_signal};
+use ipmes_rust::invariant;
+use ipmes_rust::metrics::Metrics;
+use ipmes_rust::pattern::library::{self, RuleSelection};
+use ipmes_rust::pattern::{decompose, CompositePattern, Pattern, PatternLibrary, SubPattern};
+use ipmes_rust::pattern_match::PatternMatch;
+use ipmes_rust::process_layers::composition_layer::{
+    Deactivation, DeactivationPolicy, EntitySignatureMode, RateThresholds,
+};
+use ipmes_rust::process_layers::join_layer::{
+    join_plan, PartialAlert, PartialAlertTrigger, PartialAlerts, SubPatternMatch,
+};
+use ipmes_rust::process_layers::matching_layer::FuzzyTolerance;
+use ipmes_rust::process_layers::parse_layer::{
+    ClockCorrections, IdleReader, IdleState, MmapReader, Prefilter, PrefilterError,
+    RenameTracker, Retractions, ZeekReader,
+};
+use ipmes_rust::process_layers::{
+    DebugDump, EventTrace, FlushPolicy, HistoricalCutoff, IncidentLayer, NeighborhoodExport,
+    NeighborhoodFormat, ParseLayer, Pipeline, PipelineConfig, RecentGraph, StateSeriesWriter,
+    StatsReport, StatsReporter, WindowSize,
+};
+#[cfg(unix)]
+use ipmes_rust::reload::reload_on_hangup;
+use ipmes_rust::reload::ReloadRequest;
+use ipmes_rust::watchdog::{Watchdog, Watched};
+
+use crate::{cli, Args, EntitySignatures, InputFormat, InputReader, NeighborhoodOutput};
+
+/// The exit status when `--time-limit` is exceeded.
+const EXIT_TRUNCATED: i32 = 3;
+
+/// The maximum number of recent input events kept for `--neighborhood`.
+const NEIGHBORHOOD_MAX_EVENTS: usize = 1 << 20;
+
+/// Match the pattern file, or the patterns selected from the library, on the data graph, and
+/// report the matches. Exits with status 1 on bad arguments or inputs, and with
+/// [`EXIT_TRUNCATED`] if `--time-limit` is exceeded.
+pub fn run(args: &Args) {
+    let window_size = match &args.window_size_file {
+        Some(path) => read_window_size(path).unwrap_or_else(|err| {
+            eprintln!("Failed to read the window size from {path}: {err}");
+            std::process::exit(1);
+        }),
+        None => args.window_size * 1000,
+    };
+
+    let (pattern_file, data_graph, composite) = select_patterns(args);
+
+    if composite.is_some() && args.bootstrap.is_some() {
+        eprintln!("--bootstrap does not work with --composite or several library patterns");
+        std::process::exit(1);
+    }
+    let deactivation_policy = match (args.deactivate_after, args.deactivate_after_events) {
+        (None, None) => None,
+        (after_secs, after_events) => Some(DeactivationPolicy {
+            after_time: after_secs.map(|secs| secs * 1000),
+            after_events,
+        }),
+    };
+    if let Some(composite) = &composite {
+        let start_time = ProcessTime::now();
+        match cli::composite::run(
+            composite,
+            &data_graph,
+            window_size,
+            args.zip_events,
+            args.silent,
+            args.profile_patterns,
+            deactivation_policy,
+        ) {
+            Ok(num_result) => {
+                println!("Total number of matches: {num_result}");
+                print_violations();
+            }
+            Err(err) => {
+                eprintln!("Failed to match the composite pattern: {err}");
+                std::process::exit(1);
+            }
+        }
+        print_resource_usage(start_time);
+        return;
+    }
+
+    let mut pattern = Pattern::parse(&pattern_file).expect("Failed to parse pattern");
+    pattern.optimize();
+    info!("Pattern Edges: {:#?}", pattern.events);
+
+    let decomposition = decompose(&pattern);
+    info!("Decomposition results: {:#?}", decomposition);
+
+    if args.print_join_plan {
+        print_join_plan(&pattern, &decomposition);
+        return;
+    }
+
+    let idle_state = IdleState::default();
+    let mut parse_layer = open_input(args, &data_graph, &idle_state);
+    let deadline = args
+        .time_limit
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    parse_layer.set_capture_payload(args.capture_payload);
+    parse_layer.set_record_positions(args.record_positions);
+    parse_layer.set_host_column(args.host_column);
+    if let Some(input_manifest) = &args.input_manifest {
+        let manifest = match std::fs::read_to_string(input_manifest) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                eprintln!("Failed to read input manifest {input_manifest}: {err}");
+                std::process::exit(1);
+            }
+        };
+        match ClockCorrections::parse(&manifest) {
+            Ok(clock_corrections) => parse_layer.set_clock_corrections(clock_corrections),
+            Err(err) => {
+                eprintln!("Invalid input manifest: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+    let truncated = Rc::new(Cell::new(false));
+    if let Some(deadline) = deadline {
+        parse_layer.set_deadline(deadline, truncated.clone());
+    }
+    // on SIGINT or SIGTERM, stop reading and still flush the matches completed so far
+    #[cfg(unix)]
+    match cancel_on_termination() {
+        Ok(token) => parse_layer.set_cancellation(token),
+        Err(err) => warn!("Failed to handle the termination signals: {err}"),
+    }
+    let retractions = Retractions::default();
+    parse_layer.set_retractions(retractions.clone());
+    if let Some(rename_signature) = &args.rename_signature {
+        parse_layer.set_entity_resolver(RenameTracker::new(rename_signature));
+    }
+    let entity_signature_mode = match args.entity_signatures {
+        EntitySignatures::EveryEvent => EntitySignatureMode::EveryEvent,
+        EntitySignatures::FirstBinding => EntitySignatureMode::FirstBinding,
+        EntitySignatures::AnyInWindow => EntitySignatureMode::AnyInWindow,
+    };
+    let fuzzy_tolerance = match (args.fuzzy_edit_distance, args.fuzzy_token_overlap) {
+        (Some(max_distance), _) => Some(FuzzyTolerance::EditDistance(max_distance)),
+        (_, Some(min_ratio)) => Some(FuzzyTolerance::TokenOverlap(min_ratio)),
+        (None, None) => None,
+    };
+    if let Some(prefilter) = &args.prefilter {
+        let is_auto = prefilter == "auto";
+        let prefilter = if is_auto {
+            Prefilter::from_patterns([&pattern]).map_err(PrefilterError::from)
+        } else {
+            Prefilter::parse(prefilter).map(Some)
+        };
+        match prefilter {
+            // the relaxed entity signatures may match the events the pattern signatures do not
+            Ok(Some(_)) if is_auto && entity_signature_mode != EntitySignatureMode::EveryEvent => {
+                warn!("No prefilter is applied, since the entity signatures are relaxed")
+            }
+            Ok(Some(_)) if is_auto && fuzzy_tolerance.is_some() => {
+                warn!("No prefilter is applied, since the signatures are matched fuzzily")
+            }
+            Ok(Some(prefilter)) => parse_layer.set_prefilter(prefilter),
+            Ok(None) => warn!("No prefilter is applied, since flow events may match any event"),
+            Err(err) => {
+                eprintln!("Invalid prefilter: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+    // the later layers flush the matches expired at the stream time while the input is idle, or
+    // the Join layer publishes a watermark
+    let flush_idle_output = args.flush_interval.is_some() || args.watermark_interval.is_some();
+    let mut config = PipelineConfig::new(window_size)
+        .with_zip_events(args.zip_events)
+        .with_skip_irrelevant_batches(!args.no_batch_skipping)
+        .with_entity_signature_mode(entity_signature_mode)
+        .with_ordered_output(args.ordered_output)
+        .with_maximal_only(args.maximal_only)
+        .with_multi_host(args.host_column)
+        .with_rate_thresholds(RateThresholds {
+            max_event_rate: args.max_event_rate,
+            max_instance_rate: args.max_instance_rate,
+        })
+        .with_retractions(retractions)
+        .with_flush_policy(FlushPolicy {
+            idle_state: flush_idle_output.then(|| idle_state.clone()),
+            watermark_interval: args.watermark_interval,
+        });
+    if let Some(tolerance) = fuzzy_tolerance {
+        config = config.with_fuzzy_tolerance(tolerance);
+    }
+    let resizable_window = args
+        .window_size_file
+        .as_ref()
+        .map(|_| WindowSize::new(window_size));
+    if let Some(resizable_window) = &resizable_window {
+        config = config.with_resizable_window(resizable_window.clone());
+    }
+    if let Some(policy) = deactivation_policy {
+        config = config.with_deactivation_policy(policy);
+    }
+    if let (Some(anchor), Some(max_matches)) = (args.anchor_entity, args.max_matches_per_anchor) {
+        let anchor = pattern
+            .entity_id(anchor)
+            .expect("The anchor entity is not in the pattern");
+        config = config.with_anchor_limit(anchor as u64, max_matches);
+    }
+    if let Some(event_ids) = &args.trace_events {
+        let event_trace = EventTrace::new(event_ids.iter().copied(), |record| {
+            eprintln!("{record}");
+        });
+        config = config.with_event_trace(event_trace);
+    }
+    let recent_graph = args
+        .neighborhood
+        .map(|_| RecentGraph::new(window_size, NEIGHBORHOOD_MAX_EVENTS));
+    if let Some(recent_graph) = &recent_graph {
+        config = config.with_recent_graph(recent_graph.clone());
+    }
+    let metrics = args.metrics_addr.as_ref().map(|addr| {
+        let metrics = Metrics::default();
+        if let Err(err) = metrics.serve(addr) {
+            eprintln!("Failed to serve the metrics at {addr}: {err}");
+            std::process::exit(1);
+        }
+        metrics
+    });
+    let watchdog = args.watchdog.map(|secs| {
+        let watchdog = Watchdog::default();
+        watchdog.spawn(Duration::from_secs(secs));
+        watchdog
+    });
+    // on SIGHUP, read the window size again from --window-size-file
+    let window_reload = args
+        .window_size_file
+        .clone()
+        .zip(resizable_window.clone())
+        .map(|(path, window_size)| {
+            #[cfg(unix)]
+            let request = reload_on_hangup().unwrap_or_else(|err| {
+                warn!("Failed to handle SIGHUP: {err}");
+                ReloadRequest::default()
+            });
+            #[cfg(not(unix))]
+            let request = ReloadRequest::default();
+            (path, window_size, request)
+        });
+    let input_metrics = metrics.clone();
+    let source = parse_layer.inspect(move |batch| {
+        if let Some((path, window_size, request)) = &window_reload {
+            reload_window_size(path, window_size, request);
+        }
+        if let Some(metrics) = &input_metrics {
+            metrics.add_input_events(batch.len());
+        }
+    });
+    let source = Watched::new(source, watchdog.clone());
+    let mut pipeline = match Pipeline::new(source, &pattern, config) {
+        Ok(pipeline) => pipeline,
+        Err(err) => {
+            eprintln!("Invalid pattern: {err}");
+            std::process::exit(1);
+        }
+    };
+    let historical_cutoff = args.bootstrap.as_ref().map(|history| {
+        let cutoff = match args.bootstrap_cutoff {
+            Some(cutoff) => cutoff,
+            None => cli::bootstrap::last_timestamp(history)
+                .expect("Failed to read bootstrap graph")
+                .map_or(0, |time| time + 1),
+        };
+        HistoricalCutoff::new(cutoff, args.emit_historical)
+    });
+    let neighborhood_export = recent_graph.zip(args.neighborhood).map(|(graph, hops)| {
+        let format = match args.neighborhood_format {
+            NeighborhoodOutput::Json => NeighborhoodFormat::Json,
+            NeighborhoodOutput::Dot => NeighborhoodFormat::Dot,
+        };
+        NeighborhoodExport::new(graph, hops, format)
+    });
+    pipeline.set_post_filter((historical_cutoff, neighborhood_export));
+    if let Some(context_graph) = &args.context_graph {
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(context_graph)
+            .expect("Failed to open context graph");
+        let events: Vec<_> = ParseLayer::new(csv_reader).flat_map(Vec::from).collect();
+        pipeline
+            .composition_layer()
+            .set_context_graph(&events)
+            .unwrap();
+    }
+    let join_layer = pipeline.join_layer();
+    if let Some(time) = args.debug_dump_at {
+        join_layer.set_debug_dump(DebugDump::new(time, &args.debug_dump_file));
+    }
+    if args.stats_interval.is_some() || metrics.is_some() || watchdog.is_some() {
+        // the metrics and the watchdog are updated every second of stream time, unless
+        // --stats-interval is given
+        let interval = args.stats_interval.unwrap_or(1) * 1000;
+        let print_stats = args.stats_interval.is_some();
+        let stats_metrics = metrics.clone();
+        let mut stats_reporter = StatsReporter::new(interval, move |report: &StatsReport| {
+            if print_stats {
+                print_stats_report(report);
+            }
+            if let Some(metrics) = &stats_metrics {
+                metrics.set_stats(report);
+            }
+            if let Some(watchdog) = &watchdog {
+                watchdog.set_stats(report);
+            }
+        });
+        stats_reporter.set_top_states(args.top_states.unwrap_or(0));
+        join_layer.set_stats_reporter(stats_reporter);
+    }
+    if let Some(path) = &args.state_series {
+        let file = File::create(path).expect("Failed to create the state series file");
+        let mut series = StateSeriesWriter::new(BufWriter::new(file));
+        let mut stats_reporter = StatsReporter::new(
+            args.state_series_interval * 1000,
+            move |report: &StatsReport| {
+                if let Err(err) = series.write(report) {
+                    warn!("Failed to write the state series: {err}");
+                }
+            },
+        );
+        stats_reporter.set_live_instances(true);
+        join_layer.add_stats_reporter(stats_reporter);
+    }
+    let num_partial_alerts = Rc::new(Cell::new(0u32));
+    let partial_alert_trigger = match (args.partial_alerts, &args.partial_alert_events) {
+        (Some(fraction), _) => Some(PartialAlertTrigger::Fraction(fraction)),
+        (None, Some(event_ids)) => Some(PartialAlertTrigger::Events(
+            event_ids
+                .iter()
+                .map(|id| {
+                    pattern
+                        .event_id(*id)
+                        .expect("The partial alert event is not in the pattern")
+                })
+                .collect(),
+        )),
+        (None, None) => None,
+    };
+    if let Some(trigger) = partial_alert_trigger {
+        let num_partial_alerts = num_partial_alerts.clone();
+        let silent = args.silent;
+        join_layer.set_partial_alerts(PartialAlerts::new(trigger, move |alert: &PartialAlert| {
+            if !silent {
+                println!(
+                    "Partial Match (confidence {:.2}): {}",
+                    alert.confidence, alert.pattern_match
+                );
+            }
+            num_partial_alerts.set(num_partial_alerts.get() + 1);
+        }));
+    }
+    if args.print_instances {
+        join_layer.set_sub_match_callback(|sub_match: &SubPatternMatch| {
+            println!("Sub-pattern {} Match: {}", sub_match.id, sub_match);
+        });
+    }
+
+    let start_time = ProcessTime::now();
+
+    let print_match = |pattern_match: &PatternMatch, indent: &str| {
+        if args.jsonl {
+            if args.verbose_matches {
+                println!("{}", pattern_match.to_verbose_json());
+            } else {
+                println!("{}", pattern_match.to_json());
+            }
+            return;
+        }
+        if pattern_match.annotations.contains_key("historical") {
+            println!("{indent}Historical Pattern Match: {}", pattern_match);
+        } else {
+            println!("{indent}Pattern Match: {}", pattern_match);
+        }
+        if args.verbose_matches {
+            print_event_details(pattern_match, indent);
+        }
+        match pattern_match.annotations.get("neighborhood") {
+            Some(serde_json::Value::String(dot)) => {
+                dot.lines().for_each(|line| println!("{indent}    {line}"))
+            }
+            Some(json) => println!("{indent}    Neighborhood: {json}"),
+            None => {}
+        }
+        if args.capture_payload {
+            print_payloads(pattern_match, indent);
+        }
+    };
+    #[cfg(feature = "sqlite")]
+    let mut store_match = result_store_sink(args.sqlite.as_deref(), &pattern_file, &pattern);
+    #[cfg(feature = "parquet")]
+    let mut parquet_sink = args.parquet.as_deref().map(|path| {
+        let sink = ipmes_rust::parquet_sink::ParquetSink::create(path)
+            .expect("Failed to create the Parquet file");
+        (sink, pattern_name(&pattern_file))
+    });
+    let mut binary_writer = args.binary_output.as_deref().map(|path| {
+        File::create(path)
+            .and_then(|file| BinaryMatchWriter::new(BufWriter::new(file)))
+            .expect("Failed to create the binary output file")
+    });
+    let mut evaluation = args.evaluate.as_deref().map(|path| {
+        cli::evaluate::Evaluation::load(path).unwrap_or_else(|err| {
+            eprintln!("Failed to load the ground truth: {err}");
+            std::process::exit(1);
+        })
+    });
+    let mut num_result = 0u32;
+    if args.incidents {
+        let mut incident_layer = IncidentLayer::new(pipeline.by_ref(), window_size);
+        if flush_idle_output {
+            incident_layer.set_idle_state(idle_state.clone());
+        }
+        let mut num_incidents = 0u32;
+        drain_while_idle(&mut incident_layer, &idle_state, |incident| {
+            if !args.silent {
+                println!("Incident {}", incident);
+                for pattern_match in &incident.matches {
+                    print_match(pattern_match, "    ");
+                }
+            }
+            #[cfg(feature = "sqlite")]
+            incident.matches.iter().for_each(&mut store_match);
+            #[cfg(feature = "parquet")]
+            for pattern_match in &incident.matches {
+                write_parquet(&mut parquet_sink, &pattern, pattern_match);
+            }
+            for pattern_match in &incident.matches {
+                write_binary(&mut binary_writer, pattern_match);
+            }
+            if let Some(metrics) = &metrics {
+                metrics.add_matches(incident.matches.len());
+            }
+            if let Some(evaluation) = &mut evaluation {
+                incident.matches.iter().for_each(|m| evaluation.add(m));
+            }
+            num_incidents += 1;
+            num_result += incident.matches.len() as u32;
+        });
+        println!("Total number of incidents: {num_incidents}");
+    } else {
+        drain_while_idle(&mut pipeline, &idle_state, |pattern_match| {
+            if !args.silent {
+                print_match(&pattern_match, "");
+            }
+            #[cfg(feature = "sqlite")]
+            store_match(&pattern_match);
+            #[cfg(feature = "parquet")]
+            write_parquet(&mut parquet_sink, &pattern, &pattern_match);
+            write_binary(&mut binary_writer, &pattern_match);
+            if let Some(metrics) = &metrics {
+                metrics.add_matches(1);
+            }
+            if let Some(evaluation) = &mut evaluation {
+                evaluation.add(&pattern_match);
+            }
+            num_result += 1;
+        });
+    }
+    #[cfg(feature = "parquet")]
+    if let Some((sink, _)) = parquet_sink {
+        if let Err(err) = sink.finish() {
+            eprintln!("Failed to write the Parquet file: {err}");
+            std::process::exit(1);
+        }
+    }
+    if let Some(writer) = binary_writer {
+        if let Err(err) = writer.finish() {
+            eprintln!("Failed to write the binary output file: {err}");
+            std::process::exit(1);
+        }
+    }
+    println!("Total number of matches: {num_result}");
+    print_violations();
+    if truncated.get() {
+        println!("Truncated: the time limit is exceeded before the end of the data graph");
+    }
+    #[cfg(unix)]
+    let interrupted = termination_signal();
+    #[cfg(unix)]
+    if let Some(signal) = interrupted {
+        println!("Interrupted: {signal} is received before the end of the data graph");
+    }
+    if args.partial_alerts.is_some() || args.partial_alert_events.is_some() {
+        println!(
+            "Total number of partial alerts: {}",
+            num_partial_alerts.get()
+        );
+    }
+    if let Some(evaluation) = &evaluation {
+        evaluation.print_summary();
+    }
+
+    if args.max_event_rate.is_some() || args.max_instance_rate.is_some() {
+        let summary = pipeline.composition_layer().rate_summary();
+        println!(
+            "Peak input event rate: {} events/s ({} alarms)",
+            summary.peak_event_rate, summary.event_rate_alarms
+        );
+        println!(
+            "Peak partial match creation rate: {} instances/s ({} alarms)",
+            summary.peak_instance_rate, summary.instance_rate_alarms
+        );
+    }
+
+    if let Some(deactivation) = pipeline.composition_layer().deactivation() {
+        println!("{}", describe_deactivation(&pattern, deactivation));
+    }
+
+    if args.signature_stats {
+        println!("Signature statistics:");
+        for ((event_id, alternative), hits) in pipeline.composition_layer().signature_hits() {
+            let event = &pattern.events[event_id];
+            println!(
+                "  event {} alternative {} {:?}: {} events",
+                pattern.original_event_ids[event_id],
+                alternative,
+                event.signatures().nth(alternative).unwrap_or_default(),
+                hits
+            );
+        }
+    }
+
+    print_resource_usage(start_time);
+
+    #[cfg(unix)]
+    if let Some(signal) = interrupted {
+        std::process::exit(128 + signal as i32);
+    }
+    if truncated.get() {
+        std::process::exit(EXIT_TRUNCATED);
+    }
+}
+
+/// Returns the pattern file, the data graph and the composite pattern to match, if any, which
+/// is given by `--composite` or built from several patterns selected from the library.
+fn select_patterns(args: &Args) -> (String, String, Option<CompositePattern>) {
+    let selection = RuleSelection {
+        names: args.rules.clone(),
+        categories: args.category.clone(),
+        techniques: args.technique.clone(),
+    };
+    if selection.is_empty() {
+        let pattern_file = args.pattern_file.clone().unwrap();
+        let composite = args.composite.then(|| {
+            CompositePattern::parse(&pattern_file).unwrap_or_else(|err| {
+                eprintln!("Failed to parse the composite pattern: {err}");
+                std::process::exit(1);
+            })
+        });
+        (pattern_file, args.data_graph.clone().unwrap(), composite)
+    } else {
+        // the only positional argument is the data graph
+        let data_graph = match (&args.pattern_file, &args.data_graph) {
+            (Some(data_graph), None) => data_graph.clone(),
+            _ => {
+                eprintln!("Give only the data graph when selecting patterns from the library");
+                std::process::exit(1);
+            }
+        };
+        let library = match &args.library {
+            Some(library_dir) => PatternLibrary::load(library_dir),
+            None => PatternLibrary::bundled(),
+        };
+        let selected = library.and_then(|library| {
+            let selected = library.select(&selection)?;
+            Ok(selected.into_iter().cloned().collect::<Vec<_>>())
+        });
+        let selected = selected.unwrap_or_else(|err| {
+            eprintln!("Failed to select patterns from the library: {err}");
+            std::process::exit(1);
+        });
+        info!("Selected patterns: {:?}", selected);
+        match &selected[..] {
+            [entry] => (
+                entry.pattern_file.to_string_lossy().into_owned(),
+                data_graph,
+                None,
+            ),
+            _ => {
+                let entries: Vec<_> = selected.iter().collect();
+                let composite = library::any_of(&entries);
+                (String::new(), data_graph, Some(composite))
+            }
+        }
+    }
+}
+
+/// Print the sub-patterns of `decomposition` and the order to join them.
+fn print_join_plan(pattern: &Pattern, decomposition: &[SubPattern]) {
+    for sub_pattern in decomposition {
+        let event_ids: Vec<_> = sub_pattern.events.iter().map(|e| e.id).collect();
+        println!("Sub-pattern {}: events {:?}", sub_pattern.id, event_ids);
+    }
+    let plan = match &pattern.join_plan {
+        Some(plan) => plan.clone(),
+        None => join_plan::build(decomposition),
+    };
+    println!("Join plan (height {}):", plan.height());
+    println!(
+        "\"JoinOrder\": {}",
+        serde_json::to_string(&plan.merges).unwrap()
+    );
+}
+
+/// Open the data graph with the reader and the input format of `args`.
+fn open_input(
+    args: &Args,
+    data_graph: &str,
+    idle_state: &IdleState,
+) -> ParseLayer<Box<dyn Read>> {
+    if args.input_format == InputFormat::Zeek
+        && (args.reader == InputReader::Mmap || args.host_column)
+    {
+        eprintln!("--input-format zeek does not work with --reader mmap or --host-column");
+        std::process::exit(1);
+    }
+    if args.bootstrap.is_some()
+        && (args.reader == InputReader::Mmap || args.input_format == InputFormat::Zeek)
+    {
+        eprintln!("--bootstrap does not work with --reader mmap or --input-format zeek");
+        std::process::exit(1);
+    }
+    match args.reader {
+        InputReader::Mmap => {
+            if args.flush_interval.is_some() {
+                eprintln!("--flush-interval does not work with --reader mmap");
+                std::process::exit(1);
+            }
+            let reader = MmapReader::open(data_graph).expect("Failed to open input graph");
+            ParseLayer::from_mmap(reader)
+        }
+        InputReader::Csv => {
+            let data_graph = File::open(data_graph).expect("Failed to open input graph");
+            let (reader, idle_probe): (Box<dyn Read>, _) = match args.flush_interval {
+                Some(secs) => {
+                    let (reader, probe) = IdleReader::new(data_graph, Duration::from_secs(secs));
+                    (Box::new(reader), Some(probe))
+                }
+                None => (Box::new(data_graph), None),
+            };
+            let reader = match &args.bootstrap {
+                Some(history) => {
+                    cli::bootstrap::chain(history, reader).expect("Failed to open bootstrap graph")
+                }
+                None => reader,
+            };
+            let mut parse_layer = match args.input_format {
+                InputFormat::Csv => {
+                    let csv_reader = csv::ReaderBuilder::new()
+                        .has_headers(false)
+                        .flexible(args.capture_payload)
+                        .from_reader(reader);
+                    ParseLayer::new(csv_reader)
+                }
+                InputFormat::Zeek => ParseLayer::from_zeek(ZeekReader::new(reader)),
+            };
+            if let Some(probe) = idle_probe {
+                parse_layer.set_idle_probe(probe, idle_state.clone());
+            }
+            parse_layer
+        }
+    }
+}
+
+/// Returns a callback writing each match into the SQLite database at `path`, under the name of
+/// the pattern file. The callback does nothing if `path` is `None`.
+#[cfg(feature = "sqlite")]
+fn result_store_sink<'p>(
+    path: Option<&str>,
+    pattern_file: &str,
+    pattern: &'p Pattern,
+) -> impl FnMut(&PatternMatch) + 'p {
+    let mut store = path.map(|path| {
+        ipmes_rust::result_store::ResultStore::open(path)
+            .expect("Failed to open the SQLite database")
+    });
+    let pattern_name = pattern_name(pattern_file);
+    move |pattern_match| {
+        if let Some(store) = &mut store {
+            if let Err(err) = store.insert(&pattern_name, pattern, pattern_match) {
+                eprintln!("Failed to store the match: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Writes `pattern_match` into the Parquet file of `sink`, along with the pattern name, if any.
+#[cfg(feature = "parquet")]
+fn write_parquet(
+    sink: &mut Option<(ipmes_rust::parquet_sink::ParquetSink<std::fs::File>, String)>,
+    pattern: &Pattern,
+    pattern_match: &PatternMatch,
+) {
+    if let Some((sink, pattern_name)) = sink {
+        if let Err(err) = sink.insert(pattern_name, pattern, pattern_match) {
+            eprintln!("Failed to write the match: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Writes `pattern_match` into the binary output file, if any.
+fn write_binary(
+    writer: &mut Option<BinaryMatchWriter<BufWriter<File>>>,
+    pattern_match: &PatternMatch,
+) {
+    if let Some(writer) = writer {
+        if let Err(err) = writer.write(pattern_match) {
+            eprintln!("Failed to write the match: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The name of the pattern in the result sinks, i.e. the pattern file name without the extension.
+#[cfg(any(feature = "sqlite", feature = "parquet"))]
+fn pattern_name(pattern_file: &str) -> String {
+    std::path::Path::new(pattern_file)
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+}
+
+/// Read the window size (sec) in the file at `path`, in milliseconds.
+fn read_window_size(path: &str) -> Result<u64, Box<dyn Error>> {
+    let secs: u64 = std::fs::read_to_string(path)?.trim().parse()?;
+    Ok(secs * 1000)
+}
+
+/// Resize the window from the file at `path` if a reload is requested. A bad file keeps the
+/// current window size.
+fn reload_window_size(path: &str, window_size: &WindowSize, request: &ReloadRequest) {
+    if !request.take() {
+        return;
+    }
+    match read_window_size(path) {
+        Ok(size) => {
+            info!("Window size reloaded: {} secs", size / 1000);
+            window_size.set(size);
+        }
+        Err(err) => warn!("Failed to reload the window size from {path}: {err}"),
+    }
+}
+
+/// Consume the output of `layer`, and keep waiting for more while the input is only idle.
+fn drain_while_idle<T>(
+    layer: &mut impl Iterator<Item = T>,
+    idle_state: &IdleState,
+    mut handle: impl FnMut(T),
+) {
+    loop {
+        for item in layer.by_ref() {
+            handle(item);
+        }
+        if !idle_state.is_idle() {
+            break;
+        }
+    }
+}
+
+/// Describe the deactivation of `pattern`, with the pattern event IDs in the pattern file.
+pub fn describe_deactivation(pattern: &Pattern, deactivation: &Deactivation) -> String {
+    let event_ids: Vec<_> = (deactivation.pattern_event_ids.iter())
+        .map(|id| pattern.original_event_ids[*id])
+        .collect();
+    format!(
+        "Pattern deactivated at {:.3} after {} input events: events {:?} never matched",
+        deactivation.time as f64 / 1000.0,
+        deactivation.num_events,
+        event_ids
+    )
+}
+
+/// Print each input event of the match as `id at time: signature (subject -> object)`.
+fn print_event_details(pattern_match: &PatternMatch, indent: &str) {
+    for input_event in pattern_match.input_events() {
+        let position = match input_event.position {
+            Some(position) => format!(" [line {}, byte {}]", position.line, position.byte),
+            None => String::new(),
+        };
+        println!(
+            "{indent}    {} at {:.3}: {} ({} -> {}){position}",
+            input_event.event_id,
+            input_event.timestamp as f64 / 1000.0,
+            input_event.get_event_signature(),
+            input_event.get_subject_signature(),
+            input_event.get_object_signature()
+        );
+    }
+    for (flow, start_time, end_time) in pattern_match.flows() {
+        println!(
+            "{indent}    flow ({} -> {}) from {:.3} to {:.3}",
+            flow.input_subject_id,
+            flow.input_object_id,
+            start_time as f64 / 1000.0,
+            end_time as f64 / 1000.0
+        );
+    }
+}
+
+fn print_payloads(pattern_match: &PatternMatch, indent: &str) {
+    for input_event in pattern_match.input_events() {
+        if let Some(payload) = &input_event.payload {
+            println!(
+                "{indent}    {}: {}",
+                input_event.event_id,
+                payload.join(",")
+            );
+        }
+    }
+}
+
+fn print_stats_report(report: &StatsReport) {
+    let total: usize = report.buffers.iter().map(|s| s.approx_bytes).sum();
+    eprintln!(
+        "Buffer stats at {:.3}, watermark {:.3} (~{} bytes in total):",
+        report.time as f64 / 1000.0,
+        report.watermark as f64 / 1000.0,
+        total
+    );
+    for buffer in &report.buffers {
+        eprintln!("    {buffer}");
+    }
+    if !report.top_states.is_empty() {
+        eprintln!("Top {} states by live instances:", report.top_states.len());
+        for state in &report.top_states {
+            eprintln!("    {state}");
+        }
+    }
+}
+
+fn print_violations() {
+    let num_violations = invariant::num_violations();
+    if num_violations > 0 {
+        println!("Skipped {num_violations} violations of internal invariants, see the warnings");
+    }
+}
+
+fn print_resource_usage(start_time: ProcessTime) {
+    println!(
+        "CPU time elapsed: {:?} secs",
+        start_time.elapsed().as_secs_f64()
+    );
+
+    if let Err(err) = print_peak_memory() {
+        warn!(
+            "Encounter an error when tring to get peak memory usage: {}",
+            err
+        )
+    }
+
+    info!("Finished");
+}
+
+fn print_peak_memory() -> Result<(), Box<dyn Error>> {
+    #[cfg(target_family = "windows")]
+    {
+        use windows::System::Diagnostics::ProcessDiagnosticInfo;
+        let info = ProcessDiagnosticInfo::GetForCurrentProcess()?;
+        let mem_usage = info.MemoryUsage()?;
+        let mem_report = mem_usage.GetReport()?;
+        let max_rss = mem_report.PeakWorkingSetSizeInBytes()?;
+        println!("Peak memory usage: {} kB", max_rss / 1024u64);
+    }
+
+    #[cfg(target_family = "unix")]
+    {
+        use nix::sys::resource::{getrusage, UsageWho};
+        let usage = getrusage(UsageWho::RUSAGE_SELF)?;
+        println!("Peak memory usage: {} kB", usage.max_rss());
+    }
+
+    Ok(())
+}
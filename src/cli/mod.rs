@@ -0,0 +1,18 @@
+pub mod bench;
+pub mod bootstrap;
+pub mod calibrate_window;
+pub mod composite;
+pub mod config;
+pub mod decode;
+pub mod diff_results;
+pub mod evaluate;
+pub mod explain_pattern;
+pub mod gen_data;
+pub mod list_rules;
+pub mod load_pack;
+pub mod match_pattern;
+pub mod mutate_pattern;
+#[cfg(feature = "sqlite")]
+pub mod query;
+pub mod sweep_window;
+pub mod test_pattern;
@@ -0,0 +1,226 @@
+use clap::Args;
+use ipmes_rust::engine::Engine;
+use ipmes_rust::pattern::parser::parse_json;
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Generate variants of a pattern, each dropping an event, relaxing an order edge or
+/// generalizing a signature, match them over a data graph, and report how the number of matches
+/// changes. A robust pattern keeps matching the attack under small variations
+#[derive(Args, Debug)]
+pub struct MutatePatternArgs {
+    /// The path to the pattern file in json format
+    pattern_file: String,
+
+    /// The path to the data graph in csv format
+    data_graph: String,
+
+    /// Window size (sec)
+    #[arg(short, long, default_value_t = 1800)]
+    window_size: u64,
+}
+
+/// A variant of the pattern file.
+struct Mutation {
+    description: String,
+    pattern_json: Value,
+}
+
+pub fn run(args: &MutatePatternArgs) -> Result<(), Box<dyn Error>> {
+    let pattern_json: Value = serde_json::from_str(&std::fs::read_to_string(&args.pattern_file)?)?;
+    let window_size = args.window_size * 1000;
+    let baseline = count_matches(&pattern_json, &args.data_graph, window_size)?;
+    println!("original: {} matches", baseline);
+
+    for mutation in mutations(&pattern_json) {
+        match count_matches(&mutation.pattern_json, &args.data_graph, window_size) {
+            Ok(num_matches) => println!(
+                "{}: {} matches ({:+})",
+                mutation.description,
+                num_matches,
+                num_matches as i64 - baseline as i64
+            ),
+            Err(err) => println!("{}: invalid pattern ({})", mutation.description, err),
+        }
+    }
+    Ok(())
+}
+
+fn count_matches(
+    pattern_json: &Value,
+    data_graph: &str,
+    window_size: u64,
+) -> Result<usize, Box<dyn Error>> {
+    let mut pattern = parse_json(pattern_json)?;
+    pattern.optimize();
+    let engine = Engine::new(pattern, window_size);
+    Ok(engine.run_file(data_graph, |_| {})?.pattern_matches.len())
+}
+
+/// All the single-step variants of the pattern file `pattern_json`, in the order of the events.
+fn mutations(pattern_json: &Value) -> Vec<Mutation> {
+    let events = pattern_json["Events"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let mut mutations = vec![];
+    if events.len() > 1 {
+        for event in &events {
+            let id = &event["ID"];
+            mutations.push(Mutation {
+                description: format!("drop event {id}"),
+                pattern_json: drop_event(pattern_json, id),
+            });
+        }
+    }
+    for (idx, event) in events.iter().enumerate() {
+        let parents = event["Parents"].as_array().cloned().unwrap_or_default();
+        for (parent_idx, parent) in parents.iter().enumerate() {
+            let mut variant = pattern_json.clone();
+            remove_parent(&mut variant["Events"][idx], parent_idx);
+            mutations.push(Mutation {
+                description: format!("relax order {parent} -> {}", event["ID"]),
+                pattern_json: variant,
+            });
+        }
+    }
+    for (key, kind) in [("Events", "event"), ("Entities", "entity")] {
+        let items = pattern_json[key].as_array().cloned().unwrap_or_default();
+        for (idx, item) in items.iter().enumerate() {
+            let Some(signature) = item["Signature"].as_str() else {
+                continue;
+            };
+            if signature.is_empty() || signature == ".*" {
+                continue;
+            }
+            let mut variant = use_regex(pattern_json);
+            variant[key][idx]["Signature"] = json!(".*");
+            mutations.push(Mutation {
+                description: format!("generalize {kind} {} signature {signature:?}", item["ID"]),
+                pattern_json: variant,
+            });
+        }
+    }
+    mutations
+}
+
+/// Remove the event `id`, along with its order edges and the constraints on it. The join order
+/// is removed as the sub-patterns may change.
+fn drop_event(pattern_json: &Value, id: &Value) -> Value {
+    let mut variant = pattern_json.clone();
+    if let Some(object) = variant.as_object_mut() {
+        object.remove("JoinOrder");
+    }
+    if let Some(events) = variant["Events"].as_array_mut() {
+        events.retain(|event| event["ID"] != *id);
+        for event in events {
+            let parents = event["Parents"].as_array().cloned().unwrap_or_default();
+            // remove from the back, so that the indices of the rest are kept
+            for (parent_idx, _) in parents.iter().enumerate().rev().filter(|(_, p)| *p == id) {
+                remove_parent(event, parent_idx);
+            }
+        }
+    }
+    if let Some(constraints) = variant["Constraints"].as_array_mut() {
+        constraints.retain(|constraint| {
+            let entities = constraint["Entities"].as_array();
+            !entities
+                .into_iter()
+                .flatten()
+                .any(|end| end["Event"] == *id)
+        });
+    }
+    variant
+}
+
+/// Remove the `parent_idx`-th parent of `event`, along with its time gaps if they are given per
+/// parent.
+fn remove_parent(event: &mut Value, parent_idx: usize) {
+    for key in ["Parents", "ParentsMinGapMs", "ParentsMaxGapMs"] {
+        if let Some(values) = event[key].as_array_mut() {
+            if parent_idx < values.len() {
+                values.remove(parent_idx);
+            }
+        }
+    }
+}
+
+/// Returns the pattern file with `UseRegex` enabled, escaping the signatures if it is disabled.
+fn use_regex(pattern_json: &Value) -> Value {
+    let mut variant = pattern_json.clone();
+    if variant["UseRegex"].as_bool().unwrap_or(true) {
+        return variant;
+    }
+    variant["UseRegex"] = json!(true);
+    for key in ["Events", "Entities"] {
+        for item in variant[key].as_array_mut().into_iter().flatten() {
+            match &mut item["Signature"] {
+                Value::String(signature) => *signature = regex::escape(signature),
+                Value::Array(alternatives) => {
+                    for alternative in alternatives {
+                        if let Value::String(signature) = alternative {
+                            *signature = regex::escape(signature);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    variant
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mutations() {
+        let pattern_json = json!({
+            "Version": "0.2.0",
+            "UseRegex": false,
+            "Entities": [
+                { "ID": 0, "Signature": "a.b" },
+                { "ID": 1, "Signature": "" },
+                { "ID": 2, "Signature": "" }
+            ],
+            "Events": [
+                { "ID": 0, "Signature": "x", "SubjectID": 0, "ObjectID": 1 },
+                {
+                    "ID": 1, "Signature": "y", "SubjectID": 1, "ObjectID": 2,
+                    "Parents": [0], "ParentsMaxGapMs": [1000]
+                }
+            ],
+            "Constraints": [{
+                "Type": "Distinct",
+                "Entities": [{ "Event": 1, "Role": "Object" }, { "Entity": 0 }]
+            }]
+        });
+
+        let mutations = mutations(&pattern_json);
+        let descriptions: Vec<&str> = mutations.iter().map(|m| m.description.as_str()).collect();
+        assert_eq!(
+            descriptions,
+            [
+                "drop event 0",
+                "drop event 1",
+                "relax order 0 -> 1",
+                "generalize event 0 signature \"x\"",
+                "generalize event 1 signature \"y\"",
+                "generalize entity 0 signature \"a.b\"",
+            ]
+        );
+        for mutation in &mutations {
+            assert!(parse_json(&mutation.pattern_json).is_ok());
+        }
+
+        let dropped = &mutations[0].pattern_json;
+        assert_eq!(dropped["Events"][0]["Parents"], json!([]));
+        assert_eq!(dropped["Events"][0]["ParentsMaxGapMs"], json!([]));
+        assert_eq!(mutations[1].pattern_json["Constraints"], json!([]));
+        let generalized = &mutations[5].pattern_json;
+        assert_eq!(generalized["Entities"][0]["Signature"], ".*");
+        assert_eq!(generalized["Events"][0]["Signature"], "x");
+        assert_eq!(generalized["UseRegex"], true);
+    }
+}
@@ -0,0 +1,58 @@
+use clap::Args;
+use ipmes_rust::result_store::{ResultStore, StoredMatch};
+use std::error::Error;
+
+/// Query the matches stored by `--sqlite`, either by an input entity or by a pattern and a time
+/// range
+#[derive(Args, Debug)]
+pub struct QueryArgs {
+    /// The path to the SQLite database
+    database: String,
+
+    /// List the matches binding this input entity
+    #[arg(long, value_name = "ENTITY_ID", conflicts_with = "pattern")]
+    entity: Option<u64>,
+
+    /// List the matches of this pattern, named by its file name without the extension
+    #[arg(long, value_name = "NAME", required_unless_present = "entity")]
+    pattern: Option<String>,
+
+    /// With `--pattern`, only list the matches starting at or after this timestamp (sec)
+    #[arg(long, value_name = "TIMESTAMP", value_parser = crate::parse_timestamp_arg)]
+    from: Option<u64>,
+
+    /// With `--pattern`, only list the matches ending at or before this timestamp (sec)
+    #[arg(long, value_name = "TIMESTAMP", value_parser = crate::parse_timestamp_arg)]
+    to: Option<u64>,
+}
+
+pub fn run(args: &QueryArgs) -> Result<(), Box<dyn Error>> {
+    let store = ResultStore::open(&args.database)?;
+    let matches = match (&args.entity, &args.pattern) {
+        (Some(entity), _) => store.matches_with_entity(*entity)?,
+        (None, Some(pattern)) => store.matches_of_pattern(
+            pattern,
+            args.from.unwrap_or(0),
+            args.to.unwrap_or(i64::MAX as u64),
+        )?,
+        (None, None) => unreachable!("either --entity or --pattern is required"),
+    };
+    for stored in &matches {
+        let event_ids = store.event_ids(stored.id)?;
+        println!("{}", format_match(stored, &event_ids));
+    }
+    println!("Total number of matches: {}", matches.len());
+    Ok(())
+}
+
+fn format_match(stored: &StoredMatch, event_ids: &[u64]) -> String {
+    let event_ids: Vec<String> = event_ids.iter().map(u64::to_string).collect();
+    format!(
+        "Match #{} of {}: <{:.3}, {:.3}>[{}]",
+        stored.id,
+        stored.pattern,
+        stored.earliest_time as f64 / 1000.0,
+        stored.latest_time as f64 / 1000.0,
+        event_ids.join(", ")
+    )
+}
@@ -0,0 +1,42 @@
+use clap::Args;
+use ipmes_rust::engine::Engine;
+use ipmes_rust::pattern::Pattern;
+use std::error::Error;
+
+/// Match a pattern over a data graph at several window sizes in a single pass, and report the
+/// number of matches at each window size. The data graph is parsed, and the signatures are
+/// matched, only once for all the window sizes
+#[derive(Args, Debug)]
+pub struct SweepWindowArgs {
+    /// The path to the pattern file in json format
+    pattern_file: String,
+
+    /// The path to the data graph in csv format
+    data_graph: String,
+
+    /// The window sizes (sec) to match at, e.g. 600,1800,3600
+    #[arg(long, value_name = "SECS", value_delimiter = ',', required = true)]
+    window_sizes: Vec<u64>,
+}
+
+pub fn run(args: &SweepWindowArgs) -> Result<(), Box<dyn Error>> {
+    let mut pattern = Pattern::parse(&args.pattern_file)?;
+    pattern.optimize();
+
+    let window_sizes: Vec<u64> = args.window_sizes.iter().map(|size| size * 1000).collect();
+    let engine = Engine::new(pattern, 0);
+    let summary = engine.sweep_file(&args.data_graph, &window_sizes)?;
+    for (window_size, num_matches) in &summary.num_matches {
+        println!(
+            "Window size {} secs: {} matches",
+            window_size / 1000,
+            num_matches
+        );
+    }
+    println!(
+        "Processed {} events in {:.3} CPU secs",
+        summary.num_events,
+        summary.cpu_time.as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use clap::Args;
+use ipmes_rust::engine::Engine;
+use ipmes_rust::pattern::Pattern;
+use std::error::Error;
+
+/// Run the examples embedded in a pattern file and check the number of matches
+#[derive(Args, Debug)]
+pub struct TestPatternArgs {
+    /// The path to the pattern file in json format
+    pattern_file: String,
+
+    /// Window size (sec) of the examples that do not specify one
+    #[arg(short, long, default_value_t = 1800)]
+    window_size: u64,
+}
+
+/// Returns `Ok(true)` if all the examples pass.
+pub fn run(args: &TestPatternArgs) -> Result<bool, Box<dyn Error>> {
+    let (mut pattern, examples) = Pattern::parse_with_examples(&args.pattern_file)?;
+    pattern.optimize();
+    if examples.is_empty() {
+        println!("No examples in {}", args.pattern_file);
+        return Ok(true);
+    }
+
+    let mut engine = Engine::new(pattern, args.window_size * 1000);
+    let mut num_failed = 0;
+    for example in &examples {
+        engine.set_window_size(example.window_size.unwrap_or(args.window_size) * 1000);
+        let summary = engine.run_reader(example.events.as_bytes(), |_| {})?;
+        let num_matches = summary.pattern_matches.len();
+        if num_matches == example.expected_matches {
+            println!("PASS {} ({} matches)", example.name, num_matches);
+        } else {
+            num_failed += 1;
+            println!(
+                "FAIL {}: expected {} matches, got {}",
+                example.name, example.expected_matches, num_matches
+            );
+            for pattern_match in &summary.pattern_matches {
+                println!("    Pattern Match: {}", pattern_match);
+            }
+        }
+    }
+
+    println!(
+        "{} passed, {} failed",
+        examples.len() - num_failed,
+        num_failed
+    );
+    Ok(num_failed == 0)
+}
@@ -0,0 +1,1001 @@
+use crate::input_event::InputEvent;
+use crate::pattern::Pattern;
+use crate::pattern_match::PatternMatch;
+use crate::process_layers::composition_layer::{EntityInstance, SignatureCache};
+use crate::process_layers::parse_layer::{
+    parse_timestamp, EntityVersions, IdleState, Prefilter, Retractions,
+};
+use crate::process_layers::{
+    FlushPolicy, MatchPostFilter, ParseLayer, Pipeline, PipelineConfig, WindowPolicy, WindowSize,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use cpu_time::ProcessTime;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+use thiserror::Error;
+
+#[cfg(feature = "async")]
+mod async_engine;
+#[cfg(feature = "kafka")]
+pub use async_engine::kafka_events;
+#[cfg(feature = "nats")]
+pub use async_engine::nats_events;
+#[cfg(feature = "async")]
+pub use async_engine::{parse_records, AsyncEngine, MatchStream, OwnedMatch};
+
+/// There is no process clock on wasm32, so the CPU time is always reported as zero there.
+#[cfg(target_arch = "wasm32")]
+struct ProcessTime;
+
+#[cfg(target_arch = "wasm32")]
+impl ProcessTime {
+    fn now() -> Self {
+        ProcessTime
+    }
+
+    fn elapsed(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// The default number of input events between two progress reports.
+pub const DEFAULT_PROGRESS_INTERVAL: u64 = 100_000;
+
+#[derive(Error, Debug)]
+pub enum EngineError {
+    #[error("failed to open the data graph: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid regex in the pattern: {0}")]
+    Regex(#[from] regex::Error),
+}
+
+/// A snapshot of the progress of [`Engine::run_file`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Number of input events sent into the pipeline so far.
+    pub events_processed: u64,
+    /// Number of bytes read from the data graph so far.
+    pub bytes_read: u64,
+    /// Size of the data graph in bytes, or 0 if unknown.
+    pub total_bytes: u64,
+    /// Number of pattern matches found so far.
+    pub num_matches: u64,
+    /// The earliest time (ms) a match can still start at, i.e. the timestamp of the latest input
+    /// event minus the window size for sliding windows, or `None` before the first event. The
+    /// matches starting before it are final.
+    pub watermark: Option<u64>,
+}
+
+impl Progress {
+    /// The percentage (0 ~ 100) of the data graph that has been read. Returns 100 if the size of
+    /// the data graph is unknown.
+    pub fn percentage(&self) -> f64 {
+        if self.total_bytes == 0 {
+            100.0
+        } else {
+            self.bytes_read as f64 * 100.0 / self.total_bytes as f64
+        }
+    }
+}
+
+/// The result of [`Engine::run_file`].
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+    /// All the pattern matches found in the data graph.
+    pub pattern_matches: Vec<PatternMatch>,
+    /// Total number of input events processed.
+    pub num_events: u64,
+    /// CPU time spent on matching.
+    pub cpu_time: Duration,
+}
+
+/// The result of [`Engine::sweep_file`].
+#[derive(Debug, Clone)]
+pub struct SweepSummary {
+    /// The number of matches at each window size (in milliseconds), in the order given.
+    pub num_matches: Vec<(u64, u64)>,
+    /// Total number of input events processed.
+    pub num_events: u64,
+    /// CPU time spent on matching at all the window sizes.
+    pub cpu_time: Duration,
+}
+
+/// Runs the whole IPMES pipeline on offline data graphs.
+pub struct Engine {
+    pattern: Pattern,
+    window_size: u64,
+    progress_interval: u64,
+}
+
+impl Engine {
+    /// Create an engine matching `pattern` with the given window size (in milliseconds).
+    pub fn new(pattern: Pattern, window_size: u64) -> Self {
+        Self {
+            pattern,
+            window_size,
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+        }
+    }
+
+    /// Set the window size (in milliseconds).
+    pub fn set_window_size(&mut self, window_size: u64) {
+        self.window_size = window_size;
+    }
+
+    /// Set the number of input events between two progress reports.
+    pub fn set_progress_interval(&mut self, interval: u64) {
+        self.progress_interval = interval.max(1);
+    }
+
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    fn window_policy(&self) -> Rc<dyn WindowPolicy> {
+        let pattern = &self.pattern;
+        pattern
+            .window
+            .build(self.window_size, pattern.window_boundary)
+    }
+
+    /// Start an incremental matching session, where the input events are pushed into the
+    /// pipeline in memory instead of being read from a data graph file.
+    pub fn session(&self) -> Result<Session<'_>, EngineError> {
+        Session::new(&self.pattern, self.window_size)
+    }
+
+    /// Process the whole data graph (in csv format) at `path`.
+    ///
+    /// `progress` is called every `progress_interval` input events, and once more after the input
+    /// is exhausted.
+    pub fn run_file(
+        &self,
+        path: impl AsRef<Path>,
+        progress: impl Fn(Progress),
+    ) -> Result<RunSummary, EngineError> {
+        let file = File::open(path)?;
+        let total_bytes = file.metadata()?.len();
+        self.run(file, total_bytes, progress)
+    }
+
+    /// Process the data graph (in csv format) read from `reader`. The size of the input is
+    /// unknown, so `Progress::total_bytes` is always 0.
+    pub fn run_reader(
+        &self,
+        reader: impl Read,
+        progress: impl Fn(Progress),
+    ) -> Result<RunSummary, EngineError> {
+        self.run(reader, 0, progress)
+    }
+
+    /// Process the whole data graph (in csv format) at `path` at each of `window_sizes` (in
+    /// milliseconds) in a single pass, ignoring the window size of the engine.
+    ///
+    /// The data graph is parsed, and the signatures of each batch are matched, only once. Each
+    /// window size then runs its own Composition, Join and Uniqueness layers on the signature
+    /// matches, which hold all the window-sensitive state. The pipelines advance together, one
+    /// batch at a time.
+    pub fn sweep_file(
+        &self,
+        path: impl AsRef<Path>,
+        window_sizes: &[u64],
+    ) -> Result<SweepSummary, EngineError> {
+        self.sweep(File::open(path)?, window_sizes)
+    }
+
+    /// Process the data graph (in csv format) read from `reader`, see [`Engine::sweep_file`].
+    pub fn sweep_reader(
+        &self,
+        reader: impl Read,
+        window_sizes: &[u64],
+    ) -> Result<SweepSummary, EngineError> {
+        self.sweep(reader, window_sizes)
+    }
+
+    fn sweep(&self, reader: impl Read, window_sizes: &[u64]) -> Result<SweepSummary, EngineError> {
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(reader);
+        let retractions = Retractions::default();
+        let mut parse_layer = ParseLayer::new(csv_reader);
+        parse_layer.set_retractions(retractions.clone());
+        // the events matching none of the signatures cannot change the matches at any window size
+        if let Some(prefilter) = Prefilter::from_patterns([&self.pattern])? {
+            parse_layer.set_prefilter(prefilter);
+        }
+
+        // Each pipeline takes the batches from its own queue, which holds at most the current
+        // batch. A pipeline out of batches is idle rather than at the end of the input, until the
+        // source is exhausted.
+        let idle_state = IdleState::default();
+        idle_state.set(Some(0));
+        let queues: Vec<EventQueue> = (window_sizes.iter())
+            .map(|_| EventQueue::default())
+            .collect();
+        let signature_cache = SignatureCache::default();
+        let mut pipelines = Vec::with_capacity(window_sizes.len());
+        for (queue, &window_size) in queues.iter().zip(window_sizes) {
+            let config = PipelineConfig::new(window_size)
+                .with_signature_cache(signature_cache.clone())
+                .with_retractions(retractions.clone())
+                .with_flush_policy(FlushPolicy {
+                    idle_state: Some(idle_state.clone()),
+                    watermark_interval: None,
+                });
+            pipelines.push(Pipeline::new(queue.clone(), &self.pattern, config)?);
+        }
+
+        let start_time = ProcessTime::now();
+        let mut num_matches = vec![0; window_sizes.len()];
+        let mut num_events = 0;
+        loop {
+            let batch = parse_layer.next();
+            match &batch {
+                Some(batch) => {
+                    num_events += batch.len() as u64;
+                    if let Some(event) = batch.first() {
+                        idle_state.set(Some(event.timestamp));
+                    }
+                    for queue in &queues {
+                        queue.0.borrow_mut().push_back(batch.clone());
+                    }
+                }
+                None => idle_state.set(None),
+            }
+            // run every pipeline through the batch, or to the end of the input
+            for (pipeline, num_matches) in pipelines.iter_mut().zip(&mut num_matches) {
+                *num_matches += pipeline.by_ref().count() as u64;
+            }
+            if batch.is_none() {
+                break;
+            }
+        }
+        let cpu_time = start_time.elapsed();
+
+        Ok(SweepSummary {
+            num_matches: window_sizes.iter().copied().zip(num_matches).collect(),
+            num_events,
+            cpu_time,
+        })
+    }
+
+    fn run(
+        &self,
+        reader: impl Read,
+        total_bytes: u64,
+        progress: impl Fn(Progress),
+    ) -> Result<RunSummary, EngineError> {
+        let bytes_read = Rc::new(Cell::new(0u64));
+        let reader = CountingReader {
+            inner: reader,
+            bytes_read: bytes_read.clone(),
+        };
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(reader);
+
+        let events_processed = Cell::new(0u64);
+        let num_matches = Cell::new(0u64);
+        let latest_time = Cell::new(None);
+        let window = self.window_policy();
+        let report = || {
+            progress(Progress {
+                events_processed: events_processed.get(),
+                bytes_read: bytes_read.get(),
+                total_bytes,
+                num_matches: num_matches.get(),
+                watermark: latest_time.get().map(|time| window.window_bound(time)),
+            })
+        };
+
+        let retractions = Retractions::default();
+        let mut parse_layer = ParseLayer::new(csv_reader);
+        parse_layer.set_retractions(retractions.clone());
+        let parse_layer = ProgressTap {
+            prev_layer: parse_layer,
+            events_processed: &events_processed,
+            latest_time: &latest_time,
+            next_report: self.progress_interval,
+            interval: self.progress_interval,
+            report: &report,
+        };
+        let config = PipelineConfig::new(self.window_size).with_retractions(retractions);
+        let pipeline = Pipeline::new(parse_layer, &self.pattern, config)?;
+
+        let start_time = ProcessTime::now();
+        let mut pattern_matches = Vec::new();
+        for pattern_match in pipeline {
+            pattern_matches.push(pattern_match);
+            num_matches.set(num_matches.get() + 1);
+        }
+        let cpu_time = start_time.elapsed();
+        report();
+
+        Ok(RunSummary {
+            pattern_matches,
+            num_events: events_processed.get(),
+            cpu_time,
+        })
+    }
+}
+
+/// An incremental matching session created by [`Engine::session`].
+///
+/// Input events are pushed with [`Session::push_events`], and the matches found so far are
+/// collected with [`Session::poll_matches`]. Records must be pushed in the ascending order of
+/// their (first) timestamps, as in a data graph file.
+pub struct Session<'p> {
+    queue: EventQueue,
+    pipeline: Pipeline<'p, EventQueue>,
+    /// Events held until a record with a later timestamp is pushed, sorted by timestamp.
+    pending: Vec<Rc<InputEvent>>,
+    num_events: u64,
+    /// The events retracted by tombstone records, along with the number of them already removed
+    /// from `pending`.
+    retractions: (Retractions, usize),
+    /// The incarnations of the entities terminated by terminate records.
+    entity_versions: EntityVersions,
+    window_size: WindowSize,
+    window: Rc<dyn WindowPolicy>,
+    /// The timestamp of the latest event released to the pipeline.
+    latest_time: Option<u64>,
+    /// Tells the pipeline that it is out of the input released so far, rather than at the end of
+    /// the input, until [`Session::finish`].
+    idle_state: IdleState,
+}
+
+impl<'p> Session<'p> {
+    fn new(pattern: &'p Pattern, window_size: u64) -> Result<Self, EngineError> {
+        let queue = EventQueue::default();
+        let retractions = Retractions::default();
+        let resizable_window = WindowSize::new(window_size);
+        let idle_state = IdleState::default();
+        idle_state.set(Some(0));
+        let config = PipelineConfig::new(window_size)
+            .with_retractions(retractions.clone())
+            .with_resizable_window(resizable_window.clone())
+            .with_flush_policy(FlushPolicy {
+                idle_state: Some(idle_state.clone()),
+                watermark_interval: None,
+            });
+        let pipeline = Pipeline::new(queue.clone(), pattern, config)?;
+        let window =
+            (pattern.window).build_resizable(resizable_window.clone(), pattern.window_boundary);
+
+        Ok(Self {
+            queue,
+            pipeline,
+            pending: Vec::new(),
+            num_events: 0,
+            retractions: (retractions, 0),
+            entity_versions: EntityVersions::default(),
+            window_size: resizable_window,
+            window,
+            latest_time: None,
+            idle_state,
+        })
+    }
+
+    /// Push records in the same csv format as the data graph, one record per line. Malformed
+    /// lines are skipped.
+    ///
+    /// Events at or after the timestamp of the last record are held back, since later records
+    /// may still have the same timestamp. Call [`Session::finish`] at the end of the input.
+    pub fn push_events(&mut self, csv: &str) {
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(csv.as_bytes());
+        let mut parse_layer = ParseLayer::new(csv_reader);
+        parse_layer.set_retractions(self.retractions.0.clone());
+        parse_layer.set_entity_versions(self.entity_versions.clone());
+        for batch in parse_layer {
+            self.num_events += batch.len() as u64;
+            self.pending.extend(batch.iter().cloned());
+        }
+        let (retractions, cursor) = &mut self.retractions;
+        let retracted_ids = retractions.take_new(cursor);
+        if !retracted_ids.is_empty() {
+            self.pending
+                .retain(|event| retracted_ids.binary_search(&event.event_id).is_err());
+        }
+
+        let boundary = csv
+            .lines()
+            .rev()
+            .find_map(|line| parse_timestamp(line.split(',').next()?))
+            .unwrap_or(0);
+        self.release_before(boundary);
+    }
+
+    /// Push parsed input events, which are held back in the same way as [`Session::push_events`].
+    pub fn push_input_events(&mut self, events: impl IntoIterator<Item = InputEvent>) {
+        let num_pending = self.pending.len();
+        self.pending.extend(events.into_iter().map(Rc::new));
+        self.num_events += (self.pending.len() - num_pending) as u64;
+        if let Some(last_event) = self.pending[num_pending..].last() {
+            self.release_before(last_event.timestamp);
+        }
+    }
+
+    /// Set the filter suppressing or annotating the matches before they are polled, see
+    /// [`Pipeline::set_post_filter`].
+    pub fn set_post_filter(&mut self, post_filter: impl MatchPostFilter + 'static) {
+        self.pipeline.set_post_filter(post_filter);
+    }
+
+    /// Mark the end of the input, releasing all the held events. The next
+    /// [`Session::poll_matches`] flushes all the remaining matches.
+    pub fn finish(&mut self) {
+        self.release(self.pending.len());
+        self.idle_state.set(None);
+    }
+
+    /// Send the pending events before `boundary` to the pipeline.
+    fn release_before(&mut self, boundary: u64) {
+        // stable, so that events with the same timestamp stay in the order they are pushed
+        self.pending.sort_by_key(|event| event.timestamp);
+        let num_ready = self
+            .pending
+            .partition_point(|event| event.timestamp < boundary);
+        self.release(num_ready);
+    }
+
+    /// Send the first `num_events` pending events to the pipeline, one batch per timestamp.
+    fn release(&mut self, num_events: usize) {
+        if let Some(last_event) = self.pending[..num_events].last() {
+            self.latest_time = Some(last_event.timestamp);
+            if self.idle_state.is_idle() {
+                self.idle_state.set(Some(last_event.timestamp));
+            }
+        }
+        let mut queue = self.queue.0.borrow_mut();
+        for batch in self.pending[..num_events].chunk_by(|a, b| a.timestamp == b.timestamp) {
+            queue.push_back(batch.into());
+        }
+        self.pending.drain(..num_events);
+    }
+
+    /// Process all the released events and return the matches found.
+    ///
+    /// A match is returned once its window has expired at the latest released event, so that it
+    /// is never reported twice even if later events complete it again. The rest are returned
+    /// after [`Session::finish`].
+    pub fn poll_matches(&mut self) -> Vec<PatternMatch> {
+        self.pipeline.by_ref().collect()
+    }
+
+    /// The partial matches bound to the input entity `entity_id` as of the last
+    /// [`Session::poll_matches`], for investigating an entity while the stream is running. See
+    /// [`CompositionLayer::instances_of_entity`](crate::process_layers::CompositionLayer::instances_of_entity).
+    pub fn instances_of_entity(&mut self, entity_id: u64) -> Vec<EntityInstance> {
+        let composition_layer = self.pipeline.composition_layer();
+        composition_layer.instances_of_entity(entity_id)
+    }
+
+    /// Resize the window (in milliseconds) without restarting the session. The partial matches
+    /// still alive are kept, and expire against the new size from the next released batch, see
+    /// [`WindowKind::build_resizable`](crate::process_layers::WindowKind::build_resizable).
+    pub fn set_window_size(&mut self, window_size: u64) {
+        self.window_size.set(window_size);
+    }
+
+    /// Total number of input events pushed so far.
+    pub fn num_events(&self) -> u64 {
+        self.num_events
+    }
+
+    /// The earliest time (ms) a match can still start at, i.e. the timestamp of the latest event
+    /// released to the pipeline minus the window size for sliding windows, or `None` before any
+    /// event is released. The matches starting before it are final, so orchestrating systems can
+    /// tell how far behind real time the detector is.
+    pub fn watermark(&self) -> Option<u64> {
+        self.latest_time.map(|time| self.window.window_bound(time))
+    }
+}
+
+/// The input of a [`Session`], yields the batches pushed so far.
+#[derive(Clone, Default)]
+struct EventQueue(Rc<RefCell<VecDeque<EventBatch>>>);
+
+type EventBatch = Box<[Rc<InputEvent>]>;
+
+impl Iterator for EventQueue {
+    type Item = EventBatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.borrow_mut().pop_front()
+    }
+}
+
+/// A reader that records the number of bytes read from the underlying reader.
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.bytes_read.set(self.bytes_read.get() + len as u64);
+        Ok(len)
+    }
+}
+
+/// Sits between the parse layer and the composition layer, counting the input events and
+/// reporting the progress periodically.
+struct ProgressTap<'a, P> {
+    prev_layer: P,
+    events_processed: &'a Cell<u64>,
+    /// The timestamp of the latest batch.
+    latest_time: &'a Cell<Option<u64>>,
+    next_report: u64,
+    interval: u64,
+    report: &'a dyn Fn(),
+}
+
+impl<'a, P> Iterator for ProgressTap<'a, P>
+where
+    P: Iterator<Item = Box<[Rc<InputEvent>]>>,
+{
+    type Item = Box<[Rc<InputEvent>]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.prev_layer.next()?;
+        if let Some(event) = batch.first() {
+            self.latest_time.set(Some(event.timestamp));
+        }
+        let events_processed = self.events_processed.get() + batch.len() as u64;
+        self.events_processed.set(events_processed);
+        if events_processed >= self.next_report {
+            (self.report)();
+            self.next_report = events_processed + self.interval;
+        }
+        Some(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    #[test]
+    fn test_run_file() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let mut engine = Engine::new(pattern, 1000 * 1000);
+        engine.set_progress_interval(1);
+
+        let reports = RefCell::new(Vec::new());
+        let summary = engine
+            .run_file("testcases/paper/data.csv", |p| reports.borrow_mut().push(p))
+            .unwrap();
+        let reports = reports.into_inner();
+
+        let last = reports.last().unwrap();
+        assert_eq!(last.events_processed, summary.num_events);
+        assert_eq!(last.num_matches, summary.pattern_matches.len() as u64);
+        assert_eq!(last.bytes_read, last.total_bytes);
+        assert_eq!(last.percentage(), 100.0);
+        // the window covers the whole data graph
+        assert_eq!(last.watermark, Some(0));
+        assert!(reports.len() > 1);
+        assert!(reports
+            .windows(2)
+            .all(|w| w[0].events_processed <= w[1].events_processed));
+    }
+
+    #[test]
+    fn test_sweep_file() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let mut engine = Engine::new(pattern, 0);
+        let window_sizes = [1000, 5 * 1000, 10 * 1000, 1000 * 1000];
+        let summary = engine
+            .sweep_file("testcases/paper/data.csv", &window_sizes)
+            .unwrap();
+
+        for (window_size, num_matches) in summary.num_matches {
+            engine.set_window_size(window_size);
+            let expected = engine.run_file("testcases/paper/data.csv", |_| {}).unwrap();
+            assert_eq!(
+                num_matches,
+                expected.pattern_matches.len() as u64,
+                "window size {window_size}"
+            );
+        }
+        assert!(summary.num_events > 0);
+    }
+
+    #[test]
+    fn test_session() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let engine = Engine::new(pattern, 1000 * 1000);
+        let expected = engine.run_file("testcases/paper/data.csv", |_| {}).unwrap();
+
+        let data = std::fs::read_to_string("testcases/paper/data.csv").unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+        let (first, second) = lines.split_at(lines.len() / 2);
+
+        let mut session = engine.session().unwrap();
+        session.push_events(&first.join("\n"));
+        let mut pattern_matches = session.poll_matches();
+        session.push_events(&second.join("\n"));
+        pattern_matches.extend(session.poll_matches());
+        session.finish();
+        pattern_matches.extend(session.poll_matches());
+        assert!(session.poll_matches().is_empty());
+
+        assert_eq!(session.num_events(), expected.num_events);
+        assert!(!expected.pattern_matches.is_empty());
+        assert_eq!(pattern_matches.len(), expected.pattern_matches.len());
+    }
+
+    #[test]
+    fn test_session_no_duplicates() {
+        // the flow b ~> c is completed again by the second arc, after the first poll
+        let pattern = serde_json::json!({
+            "Version": "0.2.0",
+            "Entities": [
+                { "ID": 0, "Signature": "a" },
+                { "ID": 1, "Signature": "b" },
+                { "ID": 2, "Signature": "c" }
+            ],
+            "Events": [
+                { "ID": 0, "Signature": "y", "SubjectID": 0, "ObjectID": 1 },
+                { "ID": 1, "Type": "Flow", "SubjectID": 1, "ObjectID": 2, "Parents": [0] }
+            ]
+        });
+        let pattern = Pattern::parse_str(&pattern.to_string()).unwrap();
+        let engine = Engine::new(pattern, 10 * 1000);
+        let first = "0,0,1,y,1,a,2,b\n1,1,2,x,2,b,3,c\n2,2,3,x,4,n,4,n";
+        let second = "3,3,4,x,2,b,3,c\n4,4,5,x,4,n,4,n";
+
+        let mut session = engine.session().unwrap();
+        session.push_events(first);
+        // the match may still be completed again within its window
+        assert!(session.poll_matches().is_empty());
+        session.push_events(second);
+        let mut pattern_matches = session.poll_matches();
+        session.finish();
+        pattern_matches.extend(session.poll_matches());
+
+        let data = format!("{first}\n{second}");
+        let expected = engine.run_reader(data.as_bytes(), |_| {}).unwrap();
+        assert_eq!(expected.pattern_matches.len(), 1);
+        assert_eq!(pattern_matches.len(), 1);
+        // the pipelines of a sweep are idle between the batches in the same way
+        let summary = engine.sweep_reader(data.as_bytes(), &[10 * 1000]).unwrap();
+        assert_eq!(summary.num_matches, [(10 * 1000, 1)]);
+    }
+
+    #[test]
+    fn test_optional_event() {
+        // event 1 is optional
+        let pattern = serde_json::json!({
+            "Version": "0.2.0",
+            "UseRegex": false,
+            "Entities": [
+                { "ID": 0, "Signature": "p" },
+                { "ID": 1, "Signature": "f" },
+                { "ID": 2, "Signature": "g" }
+            ],
+            "Events": [
+                { "ID": 0, "Signature": "exec", "SubjectID": 0, "ObjectID": 1 },
+                { "ID": 1, "Signature": "read", "SubjectID": 0, "ObjectID": 2,
+                  "Parents": [0], "Optional": true },
+                { "ID": 2, "Signature": "write", "SubjectID": 0, "ObjectID": 1,
+                  "Parents": [0, 1] }
+            ]
+        });
+        let pattern = Pattern::parse_str(&pattern.to_string()).unwrap();
+        let engine = Engine::new(pattern, 10 * 1000);
+        let data = "1,1,1,exec,1,p,2,f\n2,2,2,read,1,p,3,g\n3,3,3,write,1,p,2,f\n";
+
+        // only the match including the optional event is output
+        let summary = engine.run_reader(data.as_bytes(), |_| {}).unwrap();
+        let event_ids: Vec<Vec<u64>> = summary
+            .pattern_matches
+            .iter()
+            .map(|m| m.event_ids.iter().map(|(id, _)| *id).sorted().collect())
+            .collect();
+        assert_eq!(event_ids, [vec![1, 2, 3]]);
+
+        let mut session = engine.session().unwrap();
+        session.push_events(data);
+        session.finish();
+        assert_eq!(session.poll_matches().len(), 1);
+        let summary = engine.sweep_reader(data.as_bytes(), &[10 * 1000]).unwrap();
+        assert_eq!(summary.num_matches, [(10 * 1000, 1)]);
+    }
+
+    #[test]
+    fn test_session_instances_of_entity() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let engine = Engine::new(pattern, 1000 * 1000);
+        let data = std::fs::read_to_string("testcases/paper/data.csv").unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+
+        let mut session = engine.session().unwrap();
+        session.push_events(&lines[..5].join("\n"));
+        assert!(session.poll_matches().is_empty());
+        // the fork of vim (entity 4) by virus (entity 3) at 7 s waits for the rest of its
+        // sub-pattern
+        let instances = session.instances_of_entity(4);
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].state_id, 1);
+        assert_eq!(instances[0].event_ids, [3]);
+        assert_eq!(instances[0].start_time, 7000);
+        // the same instance, with virus bound to another pattern entity
+        let virus_instances = session.instances_of_entity(3);
+        assert_eq!(virus_instances.len(), 1);
+        assert_eq!(virus_instances[0].event_ids, [3]);
+        assert_ne!(
+            virus_instances[0].pattern_entity_ids,
+            instances[0].pattern_entity_ids
+        );
+        // the crontab (entity 5) is read at 8 s, which is not released yet
+        assert!(session.instances_of_entity(5).is_empty());
+    }
+
+    #[test]
+    fn test_session_watermark() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let engine = Engine::new(pattern, 5 * 1000);
+        let data = std::fs::read_to_string("testcases/paper/data.csv").unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+
+        let mut session = engine.session().unwrap();
+        assert_eq!(session.watermark(), None);
+        // the events before the last one at 8 s are released
+        session.push_events(&lines[..4].join("\n"));
+        assert_eq!(session.watermark(), Some(2000));
+        session.push_events(&lines[4..].join("\n"));
+        session.finish();
+        // the latest event is the write event ending at 15 s
+        assert_eq!(session.watermark(), Some(10000));
+    }
+
+    #[test]
+    fn test_session_set_window_size() {
+        let data = std::fs::read_to_string("testcases/paper/data.csv").unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+        let num_matches = |initial: u64, resized: u64| {
+            let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+            let engine = Engine::new(pattern, initial);
+            let mut session = engine.session().unwrap();
+            session.push_events(&lines[..4].join("\n"));
+            session.set_window_size(resized);
+            session.push_events(&lines[4..].join("\n"));
+            session.finish();
+            (session.watermark(), session.poll_matches().len())
+        };
+
+        assert_eq!(num_matches(1000, 1000 * 1000), (Some(0), 1));
+        // the partial matches in flight expire against the shrunk window
+        assert_eq!(num_matches(1000 * 1000, 1000), (Some(14000), 0));
+    }
+
+    #[test]
+    fn test_window_boundary() {
+        // events `a` and `b` are exactly a window size (10 s) apart, and `b` is after `a` when
+        // `chained`, otherwise they are in different sub-patterns and joined by the Join layer
+        let num_matches = |boundary: &str, chained: bool| {
+            let mut pattern = serde_json::json!({
+                "Version": "0.2.0",
+                "Entities": [
+                    { "ID": 0, "Signature": "p" },
+                    { "ID": 1, "Signature": "p" },
+                    { "ID": 2, "Signature": "p" }
+                ],
+                "Events": [
+                    { "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 1, "Signature": "b", "SubjectID": 1, "ObjectID": 2 }
+                ],
+                "Window": { "Type": "Sliding", "Boundary": boundary }
+            });
+            if chained {
+                pattern["Events"][1]["Parents"] = serde_json::json!([0]);
+            }
+            let pattern = Pattern::parse_str(&pattern.to_string()).unwrap();
+            let data = "0,0,1,a,1,p,2,p\n10,10,2,b,2,p,3,p\n";
+            let engine = Engine::new(pattern, 10 * 1000);
+            let summary = engine.run_reader(data.as_bytes(), |_| {}).unwrap();
+            summary.pattern_matches.len()
+        };
+
+        assert_eq!(num_matches("Inclusive", true), 1);
+        assert_eq!(num_matches("Exclusive", true), 0);
+        assert_eq!(num_matches("Inclusive", false), 1);
+        assert_eq!(num_matches("Exclusive", false), 0);
+    }
+
+    #[test]
+    fn test_tumbling_and_session_windows() {
+        // `a` and then `b` on a path of entities, in one sub-pattern when `chained`, otherwise
+        // joined by the Join layer
+        let num_matches = |window: serde_json::Value, chained: bool, data: &str| {
+            let mut pattern = serde_json::json!({
+                "Version": "0.2.0",
+                "Entities": [
+                    { "ID": 0, "Signature": "p" },
+                    { "ID": 1, "Signature": "p" },
+                    { "ID": 2, "Signature": "p" }
+                ],
+                "Events": [
+                    { "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 1, "Signature": "b", "SubjectID": 1, "ObjectID": 2 }
+                ],
+                "Window": window
+            });
+            if chained {
+                pattern["Events"][1]["Parents"] = serde_json::json!([0]);
+            }
+            let pattern = Pattern::parse_str(&pattern.to_string()).unwrap();
+            let engine = Engine::new(pattern, 10 * 1000);
+            let summary = engine.run_reader(data.as_bytes(), |_| {}).unwrap();
+            summary.pattern_matches.len()
+        };
+
+        // windows of 10 s: [0, 10), [10, 20)
+        let tumbling = serde_json::json!({ "Type": "Tumbling" });
+        for chained in [true, false] {
+            let data = "2,2,1,a,1,p,2,p\n8,8,2,b,2,p,3,p\n";
+            assert_eq!(num_matches(tumbling.clone(), chained, data), 1);
+            let data = "8,8,1,a,1,p,2,p\n12,12,2,b,2,p,3,p\n";
+            assert_eq!(num_matches(tumbling.clone(), chained, data), 0);
+        }
+
+        // sessions with a gap of 5 s
+        let session = serde_json::json!({ "Type": "Session", "GapMs": 5000 });
+        for chained in [true, false] {
+            let data = "0,0,1,a,1,p,2,p\n4,4,2,b,2,p,3,p\n";
+            assert_eq!(num_matches(session.clone(), chained, data), 1);
+            let data = "0,0,1,a,1,p,2,p\n8,8,2,b,2,p,3,p\n";
+            assert_eq!(num_matches(session.clone(), chained, data), 0);
+            // the session is not extended by an unrelated event
+            let data = "0,0,1,a,1,p,2,p\n4,4,2,x,4,p,2,p\n8,8,3,b,2,p,3,p\n";
+            assert_eq!(num_matches(session.clone(), chained, data), 0);
+            // a new session starts at 10
+            let data = "0,0,1,a,1,p,2,p\n10,10,2,a,4,p,2,p\n12,12,3,b,2,p,3,p\n";
+            assert_eq!(num_matches(session.clone(), chained, data), 1);
+        }
+        // the session is extended by the sub-pattern match of another `a` in between
+        let data = "0,0,1,a,1,p,2,p\n4,4,2,a,4,p,2,p\n8,8,3,b,2,p,3,p\n";
+        assert_eq!(num_matches(session, false, data), 2);
+    }
+
+    #[test]
+    fn test_flow_window_boundary() {
+        // a flow from `a` through `m` to `b`, after an unrelated event at 0
+        let flows = |boundary: &str, start: u64| {
+            let pattern = serde_json::json!({
+                "Version": "0.2.0",
+                "Entities": [{ "ID": 0, "Signature": "a" }, { "ID": 1, "Signature": "b" }],
+                "Events": [{ "ID": 0, "Type": "Flow", "SubjectID": 0, "ObjectID": 1 }],
+                "Window": { "Type": "Sliding", "Boundary": boundary }
+            });
+            let pattern = Pattern::parse_str(&pattern.to_string()).unwrap();
+            let data = format!("0,0,1,x,5,n,6,n\n{start},{start},2,x,1,a,7,m\n20,20,3,x,7,m,2,b\n");
+            let engine = Engine::new(pattern, 10 * 1000);
+            let summary = engine.run_reader(data.as_bytes(), |_| {}).unwrap();
+            (summary.pattern_matches.iter())
+                .map(|pattern_match| pattern_match.to_json()["flows"].clone())
+                .collect::<Vec<_>>()
+        };
+
+        let flow = |start_time: u64| {
+            serde_json::json!([{
+                "pattern_event_id": 0,
+                "subject_id": 1,
+                "object_id": 2,
+                "start_time": start_time,
+                "end_time": 20000
+            }])
+        };
+        // the previous batch is out of the window when the flow starts
+        assert_eq!(flows("Inclusive", 15), [flow(15000)]);
+        assert_eq!(flows("Exclusive", 15), [flow(15000)]);
+        // the first arc is exactly a window size before the last one
+        assert_eq!(flows("Inclusive", 10), [flow(10000)]);
+        assert!(flows("Exclusive", 10).is_empty());
+    }
+
+    #[test]
+    fn test_flow_only() {
+        // flows a ~> b ~> c without any default event
+        let pattern = serde_json::json!({
+            "Version": "0.2.0",
+            "Entities": [
+                { "ID": 0, "Signature": "a" },
+                { "ID": 1, "Signature": "b" },
+                { "ID": 2, "Signature": "c" }
+            ],
+            "Events": [
+                { "ID": 0, "Type": "Flow", "SubjectID": 0, "ObjectID": 1 },
+                { "ID": 1, "Type": "Flow", "SubjectID": 1, "ObjectID": 2, "Parents": [0] }
+            ]
+        });
+        let pattern = Pattern::parse_str(&pattern.to_string()).unwrap();
+        let data = "1,1,0,x,1,a,7,m\n2,2,1,x,7,m,2,b\n3,3,2,x,2,b,3,c\n4,4,3,x,5,n,3,c\n";
+        let engine = Engine::new(pattern, 10 * 1000);
+        let summary = engine.run_reader(data.as_bytes(), |_| {}).unwrap();
+
+        assert_eq!(summary.pattern_matches.len(), 1);
+        let json = summary.pattern_matches[0].to_json();
+        assert_eq!(json["event_ids"], serde_json::json!([]));
+        assert_eq!(json["earliest_time"], 1000);
+        assert_eq!(json["latest_time"], 3000);
+        let endpoints: Vec<_> = (json["flows"].as_array().unwrap().iter())
+            .map(|flow| (flow["subject_id"].clone(), flow["object_id"].clone()))
+            .collect();
+        assert_eq!(endpoints, [(1.into(), 2.into()), (2.into(), 3.into())]);
+    }
+
+    #[test]
+    fn test_event_uniqueness() {
+        // the two events are in different sub-patterns, and both match the single input event
+        let num_matches = |event_uniqueness: bool| {
+            let pattern = serde_json::json!({
+                "Version": "0.2.0",
+                "EventUniqueness": event_uniqueness,
+                "Entities": [
+                    { "ID": 0, "Signature": "p" },
+                    { "ID": 1, "Signature": "p" }
+                ],
+                "Events": [
+                    { "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 1, "Signature": "a", "SubjectID": 0, "ObjectID": 1 }
+                ]
+            });
+            let pattern = Pattern::parse_str(&pattern.to_string()).unwrap();
+            let engine = Engine::new(pattern, 10 * 1000);
+            let summary = engine
+                .run_reader("0,0,1,a,1,p,2,p\n".as_bytes(), |_| {})
+                .unwrap();
+            summary.pattern_matches.len()
+        };
+
+        assert_eq!(num_matches(true), 0);
+        assert_eq!(num_matches(false), 1);
+    }
+
+    #[test]
+    fn test_session_tombstone() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let engine = Engine::new(pattern, 1000 * 1000);
+        let data = std::fs::read_to_string("testcases/paper/data.csv").unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+        let (first, second) = lines.split_at(4);
+
+        // the fork event 3 is part of every match, and is retracted after entering the pipeline
+        let mut session = engine.session().unwrap();
+        session.push_events(&first.join("\n"));
+        assert!(session.poll_matches().is_empty());
+        session.push_events("8,8,3,!tombstone,,,,");
+        session.push_events(&second.join("\n"));
+        session.finish();
+        assert!(session.poll_matches().is_empty());
+    }
+
+    #[test]
+    fn test_session_terminate() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let engine = Engine::new(pattern, 1000 * 1000);
+        let data = std::fs::read_to_string("testcases/paper/data.csv").unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+        let (first, second) = lines.split_at(3);
+
+        // vim (entity 4) exits right after it is forked, so the later events on entity 4 belong
+        // to another process reusing its pid
+        let mut session = engine.session().unwrap();
+        session.push_events(&first.join("\n"));
+        session.push_events("7,7,0,!terminate,4,,,");
+        session.push_events(&second.join("\n"));
+        session.finish();
+        assert!(session.poll_matches().is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+//! The Tokio-based front-end of [`Engine`], enabled by the `async` feature.
+//!
+//! ```ignore
+//! let engine = AsyncEngine::new(Engine::new(pattern, window_size))?;
+//! let mut matches = engine.run(nats_events(client.subscribe("audit").await?));
+//! while let Some(pattern_match) = matches.next().await { ... }
+//! ```
+use super::{Engine, EngineError, Session};
+use crate::input_event::InputEvent;
+use crate::invariant::OrViolated;
+use crate::pattern_match::{MatchSequence, PatternMatch};
+use crate::process_layers::composition_layer::match_instance::{InputEventId, PatternEventId};
+use crate::process_layers::ParseLayer;
+use futures_core::Stream;
+use futures_util::StreamExt;
+use std::pin::{pin, Pin};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+/// The capacity of the channels to and from the matching thread.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Runs the pipeline of an [`Engine`] on an async stream of input events.
+///
+/// The layers are not `Send`, so each [`AsyncEngine::run`] spawns a dedicated thread running a
+/// [`Session`], which receives the input events and sends back the matches through channels.
+pub struct AsyncEngine {
+    engine: Arc<Engine>,
+}
+
+impl AsyncEngine {
+    /// Returns an error if the pattern of `engine` has an invalid regex.
+    pub fn new(engine: Engine) -> Result<Self, EngineError> {
+        engine.session()?;
+        Ok(Self {
+            engine: Arc::new(engine),
+        })
+    }
+
+    /// Match the input events from `events`, which must be in the ascending order of their
+    /// timestamps. The returned stream ends after `events` ends and the remaining matches are
+    /// flushed.
+    ///
+    /// Must be called within a Tokio runtime, which forwards `events` to the matching thread.
+    pub fn run<S>(&self, events: S) -> MatchStream
+    where
+        S: Stream<Item = InputEvent> + Send + 'static,
+    {
+        let (event_sender, event_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        let (match_sender, match_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            let mut events = pin!(events);
+            while let Some(event) = events.next().await {
+                if event_sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let engine = self.engine.clone();
+        thread::spawn(move || run_session(&engine, event_receiver, match_sender));
+        MatchStream {
+            receiver: match_receiver,
+        }
+    }
+}
+
+/// The body of the matching thread. The events received at once are pushed as a batch. Polling
+/// returns only the matches whose windows have expired, and the rest are flushed once the input
+/// channel is closed.
+fn run_session(engine: &Engine, mut events: Receiver<InputEvent>, matches: Sender<OwnedMatch>) {
+    // the pattern is checked by AsyncEngine::new, otherwise the match stream ends at once
+    let Ok(mut session) = engine
+        .session()
+        .or_violated("failed to start a session of a checked pattern")
+    else {
+        return;
+    };
+    while let Some(event) = events.blocking_recv() {
+        let mut batch = vec![event];
+        while let Ok(event) = events.try_recv() {
+            batch.push(event);
+        }
+        session.push_input_events(batch);
+        if !send_matches(&mut session, &matches) {
+            return;
+        }
+    }
+    session.finish();
+    send_matches(&mut session, &matches);
+}
+
+/// Returns `false` if the [`MatchStream`] is dropped.
+fn send_matches(session: &mut Session, matches: &Sender<OwnedMatch>) -> bool {
+    session
+        .poll_matches()
+        .iter()
+        .all(|pattern_match| matches.blocking_send(pattern_match.into()).is_ok())
+}
+
+/// The matches found by [`AsyncEngine::run`].
+pub struct MatchStream {
+    receiver: Receiver<OwnedMatch>,
+}
+
+impl Stream for MatchStream {
+    type Item = OwnedMatch;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// A [`PatternMatch`] owning a copy of its input events, so that it can be sent across threads.
+#[derive(Debug, Clone)]
+pub struct OwnedMatch {
+    pub earliest_time: u64,
+    pub latest_time: u64,
+    pub event_ids: Box<[(InputEventId, PatternEventId)]>,
+    pub sequence: Option<MatchSequence>,
+    /// The input events in this match, in the order of the matched pattern events.
+    pub input_events: Vec<InputEvent>,
+}
+
+impl From<&PatternMatch> for OwnedMatch {
+    fn from(pattern_match: &PatternMatch) -> Self {
+        Self {
+            earliest_time: pattern_match.earliest_time,
+            latest_time: pattern_match.latest_time,
+            event_ids: pattern_match.event_ids.clone(),
+            sequence: pattern_match.sequence,
+            input_events: pattern_match
+                .input_events()
+                .map(|event| InputEvent::clone(event))
+                .collect(),
+        }
+    }
+}
+
+/// Parse records in the same csv format as the data graph, e.g. the payload of a message. Malformed
+/// records are skipped.
+pub fn parse_records(csv: &[u8]) -> Vec<InputEvent> {
+    let csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(csv);
+    ParseLayer::new(csv_reader)
+        .flatten()
+        .map(|event| Rc::try_unwrap(event).unwrap_or_else(|event| InputEvent::clone(&event)))
+        .collect()
+}
+
+/// The input events in the messages received by `consumer`, each message holding records in the
+/// csv format of [`parse_records`]. Errors of the consumer are logged and skipped.
+#[cfg(feature = "kafka")]
+pub fn kafka_events(
+    consumer: rdkafka::consumer::StreamConsumer,
+) -> impl Stream<Item = InputEvent> + Send {
+    use rdkafka::Message;
+
+    futures_util::stream::unfold(consumer, |consumer| async move {
+        let events = match consumer.recv().await {
+            Ok(message) => message.payload().map(parse_records).unwrap_or_default(),
+            Err(err) => {
+                log::warn!("failed to receive from kafka: {err}");
+                Vec::new()
+            }
+        };
+        Some((futures_util::stream::iter(events), consumer))
+    })
+    .flatten()
+}
+
+/// The input events in the messages received by `subscriber`, each message holding records in
+/// the csv format of [`parse_records`].
+#[cfg(feature = "nats")]
+pub fn nats_events(subscriber: async_nats::Subscriber) -> impl Stream<Item = InputEvent> + Send {
+    subscriber.flat_map(|message| futures_util::stream::iter(parse_records(&message.payload)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+
+    #[test]
+    fn test_async_engine() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let engine = Engine::new(pattern, 1000 * 1000);
+        let expected = engine.run_file("testcases/paper/data.csv", |_| {}).unwrap();
+        let events = parse_records(&std::fs::read("testcases/paper/data.csv").unwrap());
+        assert_eq!(events.len() as u64, expected.num_events);
+
+        let engine = AsyncEngine::new(engine).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let matches: Vec<OwnedMatch> = runtime.block_on(async {
+            engine
+                .run(futures_util::stream::iter(events))
+                .collect()
+                .await
+        });
+
+        assert!(!expected.pattern_matches.is_empty());
+        assert_eq!(matches.len(), expected.pattern_matches.len());
+        for (record, pattern_match) in matches.iter().zip(&expected.pattern_matches) {
+            assert_eq!(record.event_ids, pattern_match.event_ids);
+            assert_eq!(
+                record.input_events.len(),
+                pattern_match.input_events().count()
+            );
+        }
+    }
+}
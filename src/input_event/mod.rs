@@ -1,8 +1,44 @@
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 use std::cmp::Ordering;
 
 mod id_sensitive_input_event;
 pub use id_sensitive_input_event::IdSensitiveInputEvent;
 
+/// In multi-host data graphs, the highest 16 bits of an entity id hold the index of its host, so
+/// that entities are identified by `(host, id)`. See `ParseLayer::set_host_column()`.
+const HOST_SHIFT: u32 = 48;
+
+/// Combine the index of a host and an entity id in the data graph into the entity id used by the
+/// layers. Returns `None` if `raw_id` does not fit in the lower 48 bits.
+///
+/// Host 0 stands for single-host data graphs, whose entity ids are kept as they are, so the hosts
+/// of multi-host data graphs are indexed from 1.
+pub fn entity_key(host: u16, raw_id: u64) -> Option<u64> {
+    if host == 0 {
+        Some(raw_id)
+    } else if raw_id >> HOST_SHIFT == 0 {
+        Some((host as u64) << HOST_SHIFT | raw_id)
+    } else {
+        None
+    }
+}
+
+/// The index of the host of an entity id made by [`entity_key`].
+pub fn entity_host(entity_key: u64) -> u16 {
+    (entity_key >> HOST_SHIFT) as u16
+}
+
+/// Where the record of an input event is in the data graph, see
+/// `ParseLayer::set_record_positions()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RecordPosition {
+    /// The line number of the record, starting from 1.
+    pub line: u64,
+    /// The byte offset of the start of the record.
+    pub byte: u64,
+}
+
 /// Input event, which is an arc of the provenance graph.
 #[derive(Eq, Debug, Clone)]
 pub struct InputEvent {
@@ -14,9 +50,17 @@ pub struct InputEvent {
     pub subject_id: u64,
     /// The node (entity) where this arc goes to.
     pub object_id: u64,
+    /// The index of the host reporting this event, 0 for single-host data graphs.
+    pub host: u16,
     signatures: String,
     subject_sig_start: usize,
     object_sig_start: usize,
+    /// The extra columns of this event in the data graph, if captured. See
+    /// `ParseLayer::set_capture_payload()`.
+    pub payload: Option<Box<[String]>>,
+    /// The position of the record of this event in the data graph, if recorded. See
+    /// `ParseLayer::set_record_positions()`.
+    pub position: Option<RecordPosition>,
 }
 
 impl InputEvent {
@@ -46,12 +90,30 @@ impl InputEvent {
             event_id,
             subject_id,
             object_id,
+            host: 0,
             signatures,
             subject_sig_start,
             object_sig_start,
+            payload: None,
+            position: None,
         }
     }
 
+    pub fn with_host(mut self, host: u16) -> Self {
+        self.host = host;
+        self
+    }
+
+    pub fn with_payload(mut self, payload: Option<Box<[String]>>) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    pub fn with_position(mut self, position: Option<RecordPosition>) -> Self {
+        self.position = position;
+        self
+    }
+
     /// Returns the signature of this event, of the subject entity and of the object entity concatenated
     /// into a single string, seperated by the `'\0'` character.
     pub fn get_signatures(&self) -> &str {
@@ -79,7 +141,34 @@ impl Ord for InputEvent {
 
 impl PartialOrd for InputEvent {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.timestamp.cmp(&other.timestamp))
+        Some(self.cmp(other))
+    }
+}
+
+impl Serialize for InputEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("InputEvent", 10)?;
+        state.serialize_field("timestamp", &self.timestamp)?;
+        state.serialize_field("event_id", &self.event_id)?;
+        state.serialize_field("event_signature", self.get_event_signature())?;
+        state.serialize_field("subject_id", &self.subject_id)?;
+        state.serialize_field("subject_signature", self.get_subject_signature())?;
+        state.serialize_field("object_id", &self.object_id)?;
+        state.serialize_field("object_signature", self.get_object_signature())?;
+        if self.host != 0 {
+            state.serialize_field("host", &self.host)?;
+        } else {
+            state.skip_field("host")?;
+        }
+        match &self.payload {
+            Some(payload) => state.serialize_field("payload", payload)?,
+            None => state.skip_field("payload")?,
+        }
+        match &self.position {
+            Some(position) => state.serialize_field("position", position)?,
+            None => state.skip_field("position")?,
+        }
+        state.end()
     }
 }
 
@@ -0,0 +1,95 @@
+//! Violations of the internal invariants on the streaming paths, e.g. a flow without the update
+//! time of its source. Such a violation is a bug, but a long-running detector should not die of
+//! it: by default the offending item (an event, a flow or a match) is skipped, a warning is logged
+//! and the violation is counted. In strict mode, e.g. `--strict` of the CLI, a violation panics
+//! instead, so that it can be debugged where it happens.
+use log::warn;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+static NUM_VIOLATIONS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(test)]
+thread_local! {
+    /// Strict mode of the current thread only, so that a test does not make the tests running in
+    /// parallel panic.
+    static STRICT_IN_THREAD: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Panic on violations rather than skipping the offending items, for all the engines in this
+/// process.
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+pub fn is_strict() -> bool {
+    #[cfg(test)]
+    if STRICT_IN_THREAD.get() {
+        return true;
+    }
+    STRICT.load(Ordering::Relaxed)
+}
+
+/// The number of violations skipped so far by all the engines in this process.
+pub fn num_violations() -> u64 {
+    NUM_VIOLATIONS.load(Ordering::Relaxed)
+}
+
+/// Report a violated invariant, e.g. `"a flow without the update time of its source"`. Panics in
+/// strict mode, otherwise the caller skips the offending item.
+#[track_caller]
+pub fn violated(invariant: &str) {
+    if is_strict() {
+        panic!("invariant violated: {invariant}");
+    }
+    NUM_VIOLATIONS.fetch_add(1, Ordering::Relaxed);
+    warn!(
+        "invariant violated at {}, skipped: {invariant}",
+        std::panic::Location::caller()
+    );
+}
+
+/// Reporting a missing value as a violation, in place of `Option::expect`.
+pub trait OrViolated {
+    /// Returns `self`, reporting `invariant` as violated if it is `None`.
+    fn or_violated(self, invariant: &str) -> Self;
+}
+
+impl<T> OrViolated for Option<T> {
+    #[track_caller]
+    fn or_violated(self, invariant: &str) -> Self {
+        if self.is_none() {
+            violated(invariant);
+        }
+        self
+    }
+}
+
+impl<T, E: std::fmt::Display> OrViolated for Result<T, E> {
+    #[track_caller]
+    fn or_violated(self, invariant: &str) -> Self {
+        if let Err(err) = &self {
+            violated(&format!("{invariant}: {err}"));
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_or_violated() {
+        // the other tests may count violations concurrently
+        let before = num_violations();
+        assert_eq!(Some(1).or_violated("never"), Some(1));
+        assert_eq!(None::<u32>.or_violated("a missing value"), None);
+        assert!(num_violations() > before);
+
+        STRICT_IN_THREAD.set(true);
+        let strict = std::panic::catch_unwind(|| None::<u32>.or_violated("a missing value"));
+        STRICT_IN_THREAD.set(false);
+        assert!(strict.is_err());
+    }
+}
@@ -1,6 +1,24 @@
+pub mod binary_matches;
+pub mod cancellation;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod engine;
 pub mod input_event;
+pub mod invariant;
 pub mod match_event;
+pub mod match_schema;
+pub mod metrics;
+#[cfg(feature = "parquet")]
+pub mod parquet_sink;
 pub mod pattern;
 pub mod pattern_match;
 pub mod process_layers;
+pub mod reload;
+#[cfg(feature = "sqlite")]
+pub mod result_store;
+pub mod synthetic_data;
+mod trace;
 pub mod universal_match_event;
+pub mod watchdog;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,100 +1,495 @@
-use std::error::Error;
+mod cli;
 
-use clap::{arg, Parser};
-use log::{info, warn};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use log::info;
 
-use cpu_time::ProcessTime;
-
-use ipmes_rust::pattern::{decompose, Pattern};
-use ipmes_rust::process_layers::{
-    CompositionLayer, JoinLayer, ParseLayer, UniquenessLayer,
-};
+use ipmes_rust::invariant;
+use ipmes_rust::process_layers::parse_layer::parse_timestamp;
 
 /// IPMES implemented in rust
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// The path to the pattern file in json format, e.g. data/universal_patterns/SP12.json
-    pattern_file: String,
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// The path to the pattern file in json format, e.g. data/universal_patterns/SP12.json. Omitted
+    /// when the patterns are selected from the pattern library by `--rules`, `--category` or
+    /// `--technique`
+    #[arg(required_unless_present_any = ["rules", "category", "technique"])]
+    pattern_file: Option<String>,
+
+    /// The path to the preprocessed data graph (provenance graph) in csv format. With `--rules`,
+    /// `--category` or `--technique`, it is the only positional argument
+    #[arg(required_unless_present_any = ["rules", "category", "technique"])]
+    data_graph: Option<String>,
+
+    /// Read the arguments from this config file in toml format, e.g. `window_size = 600`, where
+    /// the keys are the long names of the arguments. The arguments on the command line override
+    /// the ones in the file, and `--no-<flag>` turns off a flag set in the file
+    #[arg(long, value_name = "FILE")]
+    config: Option<String>,
+
+    /// Run these patterns of the pattern library by name, e.g. `SP6,SP8`. Several selected
+    /// patterns run together as a composite pattern matching any of them
+    #[arg(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        conflicts_with = "composite"
+    )]
+    rules: Vec<String>,
+
+    /// Run the patterns of the pattern library in these categories, e.g. `exfiltration`
+    #[arg(long, value_delimiter = ',', conflicts_with = "composite")]
+    category: Vec<String>,
+
+    /// Run the patterns of the pattern library of these ATT&CK techniques, including their
+    /// sub-techniques, e.g. `T1048`
+    #[arg(long, value_delimiter = ',', conflicts_with = "composite")]
+    technique: Vec<String>,
+
+    /// A directory of patterns to select from instead of the bundled data/universal_patterns,
+    /// see `list-rules`
+    #[arg(long, value_name = "DIR")]
+    library: Option<String>,
+
+    /// Treat the pattern file as a composite pattern, which combines several patterns with
+    /// AND/OR. Only `--window-size`, `--silent`, `--zip-events`, `--profile-patterns` and the
+    /// deactivation options apply to the component patterns
+    #[arg(long, default_value_t = false)]
+    composite: bool,
+
+    /// With --composite, print the CPU time, the number of matches and the number of match
+    /// instances in each sub-pattern of each component pattern at the end, to find costly rules
+    #[arg(long, default_value_t = false, requires = "composite")]
+    profile_patterns: bool,
+
+    /// How to read the data graph. `mmap` memory-maps the whole file, which parses large offline
+    /// data graphs faster, but does not work with pipes
+    #[arg(long, value_enum, default_value_t = InputReader::Csv)]
+    reader: InputReader,
+
+    /// The format of the data graph. `zeek` reads a Zeek conn.log (TSV or JSON), where each
+    /// connection is an event from the originator host to the responder host
+    #[arg(long, value_enum, default_value_t = InputFormat::Csv)]
+    input_format: InputFormat,
 
-    /// The path to the preprocessed data graph (provenance graph) in csv format
-    data_graph: String,
+    /// A static context graph in the csv format of the data graph, e.g. asset inventory edges,
+    /// matched by the pattern events marked `"Static": true` regardless of time
+    #[arg(long, value_name = "FILE")]
+    context_graph: Option<String>,
+
+    /// Replay this historical data graph (in csv format) before the data graph, so that the
+    /// partial matches are warmed up when the live events arrive. The matches completed before
+    /// `--bootstrap-cutoff` are not output
+    #[arg(long, value_name = "FILE")]
+    bootstrap: Option<String>,
+
+    /// The timestamp (sec) before which a completed match is historical, e.g. 11.5. By default,
+    /// the matches completed within the bootstrap data graph are historical
+    #[arg(
+        long,
+        value_name = "TIMESTAMP",
+        value_parser = parse_timestamp_arg,
+        requires = "bootstrap"
+    )]
+    bootstrap_cutoff: Option<u64>,
+
+    /// Also output the historical matches of `--bootstrap`, flagged as historical
+    #[arg(long, default_value_t = false, requires = "bootstrap")]
+    emit_historical: bool,
+
+    /// Export the input events within this number of hops from the entities of each match,
+    /// around the time of the match, as its "neighborhood" for triage
+    #[arg(long, value_name = "HOPS")]
+    neighborhood: Option<usize>,
+
+    /// The format of `--neighborhood`, a JSON subgraph or a graph in the DOT language of Graphviz
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = NeighborhoodOutput::Json,
+        requires = "neighborhood"
+    )]
+    neighborhood_format: NeighborhoodOutput,
 
     /// Window size (sec)
     #[arg(short, long, default_value_t = 1800)]
     window_size: u64,
 
+    /// Read the window size (sec) from this file instead of `--window-size`, and read it again on
+    /// SIGHUP to resize the window without a restart
+    #[arg(long, value_name = "FILE", conflicts_with = "composite")]
+    window_size_file: Option<String>,
+
     /// Enable silent mode will not print individual pattern matches.
     #[arg(short, long, default_value_t = false)]
     silent: bool,
+
+    /// Print each pattern match as a JSON object on its own line, which can be compared with the
+    /// `diff-results` subcommand
+    #[arg(long, default_value_t = false)]
+    jsonl: bool,
+
+    /// Also print the timestamp and the signatures (of the event, the subject and the object) of
+    /// each matched input event, or include them under "events" with --jsonl
+    #[arg(long, default_value_t = false)]
+    verbose_matches: bool,
+
+    /// With --verbose-matches, also print the line number and the byte offset of the record of
+    /// each matched input event in the data graph, or include them as "line" and "byte"
+    #[arg(long, default_value_t = false, requires = "verbose_matches")]
+    record_positions: bool,
+
+    /// Match duplicated events (same subject, object and signatures) with the same timestamp as a
+    /// single event, reducing the number of partial matches on bursty duplicates
+    #[arg(long, default_value_t = false)]
+    zip_events: bool,
+
+    /// Match every batch of events, rather than skipping the batches without events in the
+    /// categories of the pattern events
+    #[arg(long, default_value_t = false)]
+    no_batch_skipping: bool,
+
+    /// Output pattern matches in the chronological order of their latest events. Matches are
+    /// delayed by about one window
+    #[arg(long, default_value_t = false)]
+    ordered_output: bool,
+
+    /// Group the pattern matches sharing input entities into incidents, and print the incidents.
+    /// An incident is printed once no match extends it for a window
+    #[arg(long, default_value_t = false)]
+    incidents: bool,
+
+    /// Only output maximal matches, suppressing the matches whose input events are a strict
+    /// subset of those of another match. Matches are delayed by about one more window. Always on
+    /// for patterns with optional events
+    #[arg(long, default_value_t = false)]
+    maximal_only: bool,
+
+    /// Keep the extra columns (after the 8th one) of the data graph as the payloads of the input
+    /// events, and print them along with the pattern matches
+    #[arg(long, default_value_t = false)]
+    capture_payload: bool,
+
+    /// The 9th column of the data graph is the host of each event, for data graphs merged from
+    /// multiple hosts. Entities are identified by (host, id), and a match only contains events of
+    /// one host unless the pattern sets `SpanHosts`
+    #[arg(long, default_value_t = false)]
+    host_column: bool,
+
+    /// An input manifest in toml format with the clock corrections of the hosts in the host
+    /// column, i.e. timestamp offsets (sec) and skew factors, so that clock drift across hosts
+    /// does not violate the order relations
+    #[arg(long, value_name = "FILE", requires = "host_column")]
+    input_manifest: Option<String>,
+
+    /// Treat events with this signature as renames from their subjects to their objects, so that
+    /// a renamed entity keeps the id of the original one
+    #[arg(long, value_name = "EVENT_SIGNATURE")]
+    rename_signature: Option<String>,
+
+    /// Where the entity signatures of the pattern must match, since they may change over time:
+    /// at every event of the entity, only at the event binding it first in a sub-pattern, or at
+    /// any event of the entity within the window
+    #[arg(long, value_enum, default_value_t = EntitySignatures::EveryEvent)]
+    entity_signatures: EntitySignatures,
+
+    /// Match the signatures of the input events within this Levenshtein distance (in characters)
+    /// of the pattern signatures, which are treated as plain strings, e.g. to tolerate small
+    /// variations in command lines
+    #[arg(long, value_name = "N", conflicts_with = "fuzzy_token_overlap")]
+    fuzzy_edit_distance: Option<usize>,
+
+    /// Match the signatures of the input events sharing at least this fraction of their tokens
+    /// (alphanumeric segments) with the pattern signatures, which are treated as plain strings
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction_arg)]
+    fuzzy_token_overlap: Option<f64>,
+
+    /// Drop the input events not satisfying this expression at ingestion, e.g.
+    /// 'subject_sig =~ "bash|powershell" && event_sig != "read"'. `auto` keeps only the events
+    /// matching the signatures of some pattern event
+    #[arg(long, value_name = "EXPR")]
+    prefilter: Option<String>,
+
+    /// The ID (in the pattern file) of the anchor pattern entity for `--max-matches-per-anchor`
+    #[arg(long, value_name = "ENTITY_ID", requires = "max_matches_per_anchor")]
+    anchor_entity: Option<usize>,
+
+    /// Output at most this number of matches for each input entity bound to the anchor entity
+    /// within a window
+    #[arg(long, value_name = "N", requires = "anchor_entity")]
+    max_matches_per_anchor: Option<usize>,
+
+    /// Raise an alarm when the number of input events in a second exceeds this threshold
+    #[arg(long, value_name = "EVENTS_PER_SEC")]
+    max_event_rate: Option<u64>,
+
+    /// Raise an alarm when the number of partial matches created in a second exceeds this
+    /// threshold
+    #[arg(long, value_name = "INSTANCES_PER_SEC")]
+    max_instance_rate: Option<u64>,
+
+    /// Print the number of input events matching each alternative signature of the pattern
+    /// events at the end
+    #[arg(long, default_value_t = false)]
+    signature_stats: bool,
+
+    /// Deactivate the pattern if, after this many seconds of stream time, some of its events have
+    /// matched no input event, since it can hardly complete. Its partial matches are dropped and
+    /// the rest of the input is skipped
+    #[arg(long, value_name = "SECS")]
+    deactivate_after: Option<u64>,
+
+    /// Deactivate the pattern if, after this many input events, some of its events have matched
+    /// none of them, as `--deactivate-after`
+    #[arg(long, value_name = "EVENTS")]
+    deactivate_after_events: Option<u64>,
+
+    /// Also print the partial matches of at least this fraction of the pattern events, e.g. 0.5,
+    /// as early warnings tagged with their confidence (the fraction of pattern events matched).
+    /// Partial matches are joined from whole sub-patterns
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction_arg)]
+    partial_alerts: Option<f64>,
+
+    /// Like `--partial-alerts`, but print the partial matches containing all these pattern events
+    /// (IDs in the pattern file), e.g. 0,1,2
+    #[arg(
+        long,
+        value_name = "EVENT_IDS",
+        value_delimiter = ',',
+        conflicts_with = "partial_alerts"
+    )]
+    partial_alert_events: Option<Vec<usize>>,
+
+    /// Print each match of a sub-pattern as it enters the Join layer, with its time range, input
+    /// events and entity bindings (pattern entity: input entity)
+    #[arg(long, default_value_t = false)]
+    print_instances: bool,
+
+    /// Print a JSON line to stderr whenever one of these input events (IDs in the data graph)
+    /// passes a decision of the layers: signature matching, instance extension, join and
+    /// deduplication, e.g. 123,456
+    #[arg(long, value_name = "EVENT_IDS", value_delimiter = ',')]
+    trace_events: Option<Vec<u64>>,
+
+    /// Print the sub-patterns and the join plan of the pattern in the format of `JoinOrder`, then
+    /// exit
+    #[arg(long, default_value_t = false)]
+    print_join_plan: bool,
+
+    /// For live data graphs (e.g. a named pipe), if no event arrives for this many seconds,
+    /// advance the stream time by the idle wall-clock time and output the matches whose window
+    /// has closed
+    #[arg(long, value_name = "SECS")]
+    flush_interval: Option<u64>,
+
+    /// Output the completed matches whose window has closed every this many milliseconds of
+    /// stream time, instead of holding them until another match completes
+    #[arg(long, value_name = "MS")]
+    watermark_interval: Option<u64>,
+
+    /// Print the number of entries, the oldest timestamp and the approximate memory usage of each
+    /// buffer of partial matches to stderr, every this many seconds of stream time
+    #[arg(long, value_name = "SECS")]
+    stats_interval: Option<u64>,
+
+    /// Serve the engine metrics (input events, live instances, buffer sizes, matches emitted and
+    /// watermark lag) in the Prometheus text format over HTTP at this address, e.g. 127.0.0.1:9464
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<String>,
+
+    /// With --stats-interval, also print the N states of the Composition layer holding the most
+    /// live partial matches, i.e. the pattern prefixes that grow the state the most
+    #[arg(long, value_name = "N", requires = "stats_interval")]
+    top_states: Option<usize>,
+
+    /// Write a CSV line with the live partial matches of each state of the Composition layer and
+    /// the sizes of each buffer (including the reach sets of flow events) every
+    /// --state-series-interval, to plot the state size over the course of the data graph
+    #[arg(long, value_name = "FILE")]
+    state_series: Option<String>,
+
+    /// The interval of --state-series in seconds of stream time
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 60,
+        requires = "state_series"
+    )]
+    state_series_interval: u64,
+
+    /// If no time batch completes for this many seconds while the input is pending, log the
+    /// latest buffer statistics and the largest buffers at the warn level (e.g. RUST_LOG=warn)
+    #[arg(long, value_name = "SECS")]
+    watchdog: Option<u64>,
+
+    /// Stop reading the data graph after this many seconds of wall-clock time, output the matches
+    /// completed so far, and exit with status 3
+    #[arg(long, value_name = "SECS")]
+    time_limit: Option<u64>,
+
+    /// Dump the internal states of the Composition and Join layers to a JSON file once the stream
+    /// reaches the given timestamp (sec), e.g. 11.5
+    #[arg(long, value_name = "TIMESTAMP", value_parser = parse_timestamp_arg)]
+    debug_dump_at: Option<u64>,
+
+    /// The output file of `--debug-dump-at`
+    #[arg(long, default_value = "debug_dump.json")]
+    debug_dump_file: String,
+
+    /// Panic on a violated internal invariant, e.g. a flow without the update time of its source,
+    /// for debugging. By default, the offending event, flow or match is skipped, a warning is
+    /// logged, and the number of violations is printed at the end
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// Score the matches against a ground-truth file of expected matches, and print the
+    /// precision, recall and F1 score after the run
+    #[arg(long, value_name = "LABELS")]
+    evaluate: Option<String>,
+
+    /// Also write the matches in a compact binary format, which can be converted back to JSON
+    /// lines with the `decode` subcommand
+    #[arg(long, value_name = "FILE")]
+    binary_output: Option<String>,
+
+    /// Also write the matches into a SQLite database, which can be queried with the `query`
+    /// subcommand
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "FILE")]
+    sqlite: Option<String>,
+
+    /// Also write the matches into a Parquet file, with a row per match
+    #[cfg(feature = "parquet")]
+    #[arg(long, value_name = "FILE")]
+    parquet: Option<String>,
 }
 
-fn main() {
-    env_logger::init();
-    let args = Args::parse();
-    info!("Command line arguments: {:?}", args);
-    let window_size = args.window_size * 1000;
-
-    let mut pattern = Pattern::parse(&args.pattern_file).expect("Failed to parse pattern");
-    pattern.optimize();
-    info!("Pattern Edges: {:#?}", pattern.events);
-
-    let decomposition = decompose(&pattern);
-    info!("Decomposition results: {:#?}", decomposition);
-
-    let csv_reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_path(args.data_graph)
-        .expect("Failed to open input graph");
-    let parse_layer = ParseLayer::new(csv_reader);
-    let composition_layer =
-        CompositionLayer::new(parse_layer, &decomposition, window_size, pattern.use_regex).unwrap();
-    let join_layer = JoinLayer::new(composition_layer, &pattern, &decomposition, window_size);
-    let uniqueness_layer = UniquenessLayer::new(join_layer, window_size);
-
-    let start_time = ProcessTime::now();
-
-    let mut num_result = 0u32;
-    for pattern_match in uniqueness_layer {
-        if !args.silent {
-            println!("Pattern Match: {}", pattern_match);
-        }
-        num_result += 1;
-    }
-    println!("Total number of matches: {num_result}");
-
-    println!(
-        "CPU time elapsed: {:?} secs",
-        start_time.elapsed().as_secs_f64()
-    );
-
-    if let Err(err) = print_peak_memory() {
-        warn!(
-            "Encounter an error when tring to get peak memory usage: {}",
-            err
-        )
-    }
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum InputReader {
+    Csv,
+    Mmap,
+}
 
-    info!("Finished");
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    Csv,
+    Zeek,
 }
 
-fn print_peak_memory() -> Result<(), Box<dyn Error>> {
-    #[cfg(target_family = "windows")]
-    {
-        use windows::System::Diagnostics::ProcessDiagnosticInfo;
-        let info = ProcessDiagnosticInfo::GetForCurrentProcess()?;
-        let mem_usage = info.MemoryUsage()?;
-        let mem_report = mem_usage.GetReport()?;
-        let max_rss = mem_report.PeakWorkingSetSizeInBytes()?;
-        println!("Peak memory usage: {} kB", max_rss / 1024u64);
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum EntitySignatures {
+    EveryEvent,
+    FirstBinding,
+    AnyInWindow,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum NeighborhoodOutput {
+    Json,
+    Dot,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    Bench(cli::bench::BenchArgs),
+    TestPattern(cli::test_pattern::TestPatternArgs),
+    LoadPack(cli::load_pack::LoadPackArgs),
+    ExplainPattern(cli::explain_pattern::ExplainPatternArgs),
+    MutatePattern(cli::mutate_pattern::MutatePatternArgs),
+    DiffResults(cli::diff_results::DiffResultsArgs),
+    Decode(cli::decode::DecodeArgs),
+    CalibrateWindow(cli::calibrate_window::CalibrateWindowArgs),
+    GenData(cli::gen_data::GenDataArgs),
+    ListRules(cli::list_rules::ListRulesArgs),
+    SweepWindow(cli::sweep_window::SweepWindowArgs),
+    #[cfg(feature = "sqlite")]
+    Query(cli::query::QueryArgs),
+}
+
+fn parse_timestamp_arg(arg: &str) -> Result<u64, String> {
+    parse_timestamp(arg).ok_or_else(|| format!("invalid timestamp: {arg}"))
+}
+
+fn parse_fraction_arg(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!("expected a fraction in (0, 1]: {arg}")),
     }
+}
+
+fn main() {
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+    #[cfg(not(feature = "tracing"))]
+    env_logger::init();
+    let args = match cli::config::merge_args(Args::command(), std::env::args_os().collect()) {
+        Ok(args) => Args::parse_from(args),
+        Err(err) => {
+            eprintln!("Invalid config file: {err}");
+            std::process::exit(1);
+        }
+    };
+    info!("Command line arguments: {:?}", args);
+    invariant::set_strict(args.strict);
 
-    #[cfg(target_family = "unix")]
-    {
-        use nix::sys::resource::{getrusage, UsageWho};
-        let usage = getrusage(UsageWho::RUSAGE_SELF)?;
-        println!("Peak memory usage: {} kB", usage.max_rss());
+    match &args.command {
+        Some(command) => run_command(command),
+        None => cli::match_pattern::run(&args),
     }
+}
 
-    Ok(())
+/// Run the subcommand, and exit with status 1 if it fails.
+fn run_command(command: &Commands) {
+    let (result, context) = match command {
+        Commands::Bench(args) => (cli::bench::run(args).map(|_| true), "Benchmark failed"),
+        Commands::TestPattern(args) => (cli::test_pattern::run(args), "Failed to test the pattern"),
+        Commands::LoadPack(args) => (cli::load_pack::run(args), "Failed to load the rule pack"),
+        Commands::ExplainPattern(args) => (
+            cli::explain_pattern::run(args).map(|_| true),
+            "Failed to explain the pattern",
+        ),
+        Commands::MutatePattern(args) => (
+            cli::mutate_pattern::run(args).map(|_| true),
+            "Failed to mutate the pattern",
+        ),
+        Commands::DiffResults(args) => {
+            (cli::diff_results::run(args), "Failed to compare the results")
+        }
+        Commands::Decode(args) => {
+            (cli::decode::run(args).map(|_| true), "Failed to decode the matches")
+        }
+        Commands::CalibrateWindow(args) => (
+            cli::calibrate_window::run(args).map(|_| true),
+            "Failed to calibrate the window size",
+        ),
+        Commands::GenData(args) => {
+            (cli::gen_data::run(args).map(|_| true), "Failed to generate the data")
+        }
+        Commands::ListRules(args) => {
+            (cli::list_rules::run(args).map(|_| true), "Failed to list the patterns")
+        }
+        Commands::SweepWindow(args) => (
+            cli::sweep_window::run(args).map(|_| true),
+            "Failed to sweep the window sizes",
+        ),
+        #[cfg(feature = "sqlite")]
+        Commands::Query(args) => {
+            (cli::query::run(args).map(|_| true), "Failed to query the matches")
+        }
+    };
+    match result {
+        Ok(true) => {}
+        // the subcommand has reported the failure, e.g. a failing pattern test
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("{context}: {err}");
+            std::process::exit(1);
+        }
+    }
 }
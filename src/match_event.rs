@@ -1,12 +1,13 @@
 use crate::input_event::InputEvent;
-use crate::pattern::PatternEvent;
-use std::fmt;
-use std::fmt::{Debug, Formatter};
+use crate::invariant;
+use itertools::Itertools;
+use serde::Serialize;
+use std::fmt::{self, Debug, Display};
 use std::rc::Rc;
 
 use crate::match_event::RawEvents::{Flow, Multiple, Single};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct MatchEvent {
     /// The pattern event id that `self.raw_events` are matched to.
     pub match_id: u32,
@@ -22,7 +23,7 @@ pub struct MatchEvent {
     pub raw_events: RawEvents,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum RawEvents {
     Single(Rc<InputEvent>),
     /// Correspond to `Frequency` match type
@@ -31,15 +32,22 @@ pub enum RawEvents {
 }
 
 impl RawEvents {
-    pub fn get_ids<'p>(
-        &'p self,
-    ) -> Box<dyn Iterator<Item = u64> + 'p> {
+    pub fn get_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.as_slice().iter().map(|e| e.event_id)
+    }
+
+    pub fn get_events(&self) -> impl Iterator<Item = &Rc<InputEvent>> + '_ {
+        self.as_slice().iter()
+    }
+
+    /// The input events as a slice, which is empty for flows.
+    fn as_slice(&self) -> &[Rc<InputEvent>] {
         match self {
-            Single(event) => Box::new(Some(event.event_id).into_iter()),
+            Single(event) => std::slice::from_ref(event),
 
-            Multiple(events) => Box::new(events.iter().map(|e| e.event_id)),
+            Multiple(events) => events,
 
-            Flow(_, _) => Box::new(None.into_iter()),
+            Flow(_, _) => &[],
         }
     }
 
@@ -47,13 +55,33 @@ impl RawEvents {
         match self {
             Single(event) => (event.timestamp, event.timestamp),
 
-            Multiple(events) => {
-                let first = events.first().unwrap();
-                let last = events.last().unwrap();
-                (first.timestamp, last.timestamp)
-            }
+            Multiple(events) => match (events.first(), events.last()) {
+                (Some(first), Some(last)) => (first.timestamp, last.timestamp),
+                _ => {
+                    invariant::violated("a frequency event without input events");
+                    (0, 0)
+                }
+            },
 
             Flow(start_time, end_time) => (*start_time, *end_time),
         }
     }
 }
+
+/// Writes the input event ids of the match, e.g. `3`, `(3, 4, 5)` for a frequency event, or
+/// `(17 -> 20)` for the endpoints of a flow.
+impl Display for MatchEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.raw_events {
+            Single(input_event) => write!(f, "{}", input_event.event_id),
+            Multiple(input_events) => {
+                write!(
+                    f,
+                    "({})",
+                    input_events.iter().map(|e| e.event_id).join(", ")
+                )
+            }
+            Flow(_, _) => write!(f, "({} -> {})", self.input_subject_id, self.input_object_id),
+        }
+    }
+}
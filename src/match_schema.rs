@@ -0,0 +1,297 @@
+use crate::pattern_match::PatternMatch;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A pattern match as output in JSON, e.g. by `--jsonl`, the C API and the WebAssembly bindings,
+/// tagged by its schema `"version"`:
+///
+/// ```json
+/// {"version": "3", "earliest_time": 7000, "latest_time": 12000, "event_ids": [3, 4, 5, 6, 7],
+///  "sequence": 0, "window_id": 3}
+/// ```
+///
+/// The fields of a released version never change, so that downstream consumers can rely on them
+/// across crate versions. A change of the schema adds a new version instead, and the matches are
+/// converted from [`PatternMatch`] to the latest version here only.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "version")]
+pub enum MatchRecord {
+    #[serde(rename = "1")]
+    V1(MatchRecordV1),
+    #[serde(rename = "2")]
+    V2(MatchRecordV2),
+    #[serde(rename = "3")]
+    V3(MatchRecordV3),
+}
+
+/// Version 1 of [`MatchRecord`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchRecordV1 {
+    /// The timestamp (in milliseconds) of the earliest matched input event.
+    pub earliest_time: u64,
+    /// The timestamp (in milliseconds) of the latest matched input event.
+    pub latest_time: u64,
+    /// The ids of the matched input events, in the order of the matched pattern events.
+    pub event_ids: Vec<u64>,
+    /// The matched flows, listed if there are any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flows: Vec<FlowRecordV1>,
+    /// The annotations of the post-filter, listed if there are any.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub annotations: Map<String, Value>,
+    /// The matched input events, listed in the verbose output only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<EventRecordV1>>,
+}
+
+/// Version 2 of [`MatchRecord`], which adds the sequence number and the window id of the match,
+/// see [`MatchSequence`](crate::pattern_match::MatchSequence).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchRecordV2 {
+    pub earliest_time: u64,
+    pub latest_time: u64,
+    pub event_ids: Vec<u64>,
+    /// The number of the match in the order of the output, listed if it is numbered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// The window of the match, `earliest_time / window size`, listed if it is numbered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flows: Vec<FlowRecordV1>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub annotations: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<EventRecordV1>>,
+}
+
+/// Version 3 of [`MatchRecord`], whose matched input events may list the positions of their
+/// records in the data graph, see [`EventRecordV2`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchRecordV3 {
+    pub earliest_time: u64,
+    pub latest_time: u64,
+    pub event_ids: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flows: Vec<FlowRecordV1>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub annotations: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<EventRecordV2>>,
+}
+
+impl From<MatchRecordV1> for MatchRecordV2 {
+    fn from(record: MatchRecordV1) -> Self {
+        Self {
+            earliest_time: record.earliest_time,
+            latest_time: record.latest_time,
+            event_ids: record.event_ids,
+            sequence: None,
+            window_id: None,
+            flows: record.flows,
+            annotations: record.annotations,
+            events: record.events,
+        }
+    }
+}
+
+impl From<MatchRecordV2> for MatchRecordV3 {
+    fn from(record: MatchRecordV2) -> Self {
+        Self {
+            earliest_time: record.earliest_time,
+            latest_time: record.latest_time,
+            event_ids: record.event_ids,
+            sequence: record.sequence,
+            window_id: record.window_id,
+            flows: record.flows,
+            annotations: record.annotations,
+            events: record
+                .events
+                .map(|events| events.into_iter().map(EventRecordV2::from).collect()),
+        }
+    }
+}
+
+/// A flow of [`MatchRecordV1`], which starts at its first arc and ends at its last arc.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlowRecordV1 {
+    pub pattern_event_id: u32,
+    pub subject_id: u64,
+    pub object_id: u64,
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+/// A matched input event of [`MatchRecordV1`], with its raw signatures.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecordV1 {
+    pub event_id: u64,
+    pub timestamp: u64,
+    pub signature: String,
+    pub subject_signature: String,
+    pub object_signature: String,
+}
+
+/// A matched input event of [`MatchRecordV3`], which adds the position of its record in the data
+/// graph to [`EventRecordV1`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecordV2 {
+    pub event_id: u64,
+    pub timestamp: u64,
+    pub signature: String,
+    pub subject_signature: String,
+    pub object_signature: String,
+    /// The line number of the record of the event in the data graph, listed if recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+    /// The byte offset of the record of the event in the data graph, listed if recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub byte: Option<u64>,
+}
+
+impl From<EventRecordV1> for EventRecordV2 {
+    fn from(record: EventRecordV1) -> Self {
+        Self {
+            event_id: record.event_id,
+            timestamp: record.timestamp,
+            signature: record.signature,
+            subject_signature: record.subject_signature,
+            object_signature: record.object_signature,
+            line: None,
+            byte: None,
+        }
+    }
+}
+
+impl MatchRecord {
+    /// Convert `pattern_match` to the latest version, listing its input events if `verbose`.
+    pub fn new(pattern_match: &PatternMatch, verbose: bool) -> Self {
+        let flows = pattern_match
+            .flows()
+            .map(|(flow, start_time, end_time)| FlowRecordV1 {
+                pattern_event_id: flow.match_id,
+                subject_id: flow.input_subject_id,
+                object_id: flow.input_object_id,
+                start_time,
+                end_time,
+            })
+            .collect();
+        let events = verbose.then(|| {
+            pattern_match
+                .input_events()
+                .map(|input_event| EventRecordV2 {
+                    event_id: input_event.event_id,
+                    timestamp: input_event.timestamp,
+                    signature: input_event.get_event_signature().to_string(),
+                    subject_signature: input_event.get_subject_signature().to_string(),
+                    object_signature: input_event.get_object_signature().to_string(),
+                    line: input_event.position.map(|position| position.line),
+                    byte: input_event.position.map(|position| position.byte),
+                })
+                .collect()
+        });
+        let sequence = pattern_match.sequence;
+        Self::V3(MatchRecordV3 {
+            earliest_time: pattern_match.earliest_time,
+            latest_time: pattern_match.latest_time,
+            event_ids: pattern_match.event_ids.iter().map(|(id, _)| *id).collect(),
+            sequence: sequence.map(|sequence| sequence.number),
+            window_id: sequence.map(|sequence| sequence.window_id),
+            flows,
+            annotations: pattern_match.annotations.clone(),
+            events,
+        })
+    }
+
+    /// Parse a record, where a record without `"version"`, output before the schema was
+    /// versioned, is taken as version 1.
+    pub fn from_json(mut value: Value) -> serde_json::Result<Self> {
+        if let Some(object) = value.as_object_mut() {
+            object.entry("version").or_insert_with(|| Value::from("1"));
+        }
+        serde_json::from_value(value)
+    }
+
+    /// Convert to the latest version, where the fields added since are missing.
+    pub fn into_latest(self) -> MatchRecordV3 {
+        match self {
+            Self::V1(record) => MatchRecordV2::from(record).into(),
+            Self::V2(record) => record.into(),
+            Self::V3(record) => record,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).expect("a match record is always valid json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern_match::MatchSequence;
+    use serde_json::json;
+
+    #[test]
+    fn test_match_record() {
+        let pattern_match = PatternMatch {
+            latest_time: 2000,
+            earliest_time: 1000,
+            event_ids: Box::new([(5, 0), (3, 1)]),
+            match_event_map: Box::new([]),
+            annotations: json!({ "score": 0.5 }).as_object().unwrap().clone(),
+            sequence: Some(MatchSequence {
+                number: 4,
+                window_id: 1,
+            }),
+        };
+        let record = MatchRecord::new(&pattern_match, false);
+        let value = record.to_json();
+        assert_eq!(
+            value,
+            json!({
+                "version": "3",
+                "earliest_time": 1000,
+                "latest_time": 2000,
+                "event_ids": [5, 3],
+                "sequence": 4,
+                "window_id": 1,
+                "annotations": { "score": 0.5 }
+            })
+        );
+        assert_eq!(MatchRecord::from_json(value).unwrap(), record);
+
+        // before versioning
+        let legacy = json!({ "earliest_time": 1000, "latest_time": 2000, "event_ids": [5, 3] });
+        let legacy = MatchRecord::from_json(legacy).unwrap();
+        assert!(matches!(legacy, MatchRecord::V1(_)));
+        let legacy = legacy.into_latest();
+        assert_eq!(legacy.event_ids, [5, 3]);
+        assert_eq!(legacy.sequence, None);
+        assert!(legacy.annotations.is_empty());
+
+        assert!(MatchRecord::from_json(json!({ "version": "0", "event_ids": [] })).is_err());
+
+        // the events of version 2 have no positions
+        let v2 = json!({
+            "version": "2",
+            "earliest_time": 1000,
+            "latest_time": 2000,
+            "event_ids": [5],
+            "events": [{
+                "event_id": 5,
+                "timestamp": 1000,
+                "signature": "read",
+                "subject_signature": "p",
+                "object_signature": "f"
+            }]
+        });
+        let v2 = MatchRecord::from_json(v2).unwrap().into_latest();
+        let events = v2.events.unwrap();
+        assert_eq!((events[0].event_id, events[0].line, events[0].byte), (5, None, None));
+    }
+}
@@ -0,0 +1,233 @@
+//! Engine metrics in the Prometheus text format, for dashboarding and alerting on a long-running
+//! detector. The endpoint can be scraped by Prometheus, or by the Prometheus receiver of the
+//! OpenTelemetry Collector.
+use crate::invariant;
+use crate::process_layers::{BufferStats, StatsReport};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    input_events: u64,
+    matches: u64,
+    /// The stream time (ms) and the buffers of the latest [`StatsReport`].
+    stats: Option<StatsReport>,
+}
+
+/// A handle to the engine metrics, shared between the thread running the engine and the one
+/// serving the metrics.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    state: Arc<Mutex<MetricsState>>,
+}
+
+impl Metrics {
+    pub fn add_input_events(&self, n: usize) {
+        self.state.lock().unwrap().input_events += n as u64;
+    }
+
+    pub fn add_matches(&self, n: usize) {
+        self.state.lock().unwrap().matches += n as u64;
+    }
+
+    /// Update the buffer sizes and the stream time, see
+    /// [`StatsReporter`](crate::process_layers::StatsReporter).
+    pub fn set_stats(&self, report: &StatsReport) {
+        self.state.lock().unwrap().stats = Some(report.clone());
+    }
+
+    /// Render the metrics in the Prometheus text format. The watermark lag is the wall-clock time
+    /// minus the stream time, which is meaningful if the timestamps are Unix times.
+    pub fn render(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        self.render_at(now)
+    }
+
+    fn render_at(&self, now: u64) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+        write_metric(
+            &mut out,
+            ("ipmes_input_events_total", "counter"),
+            "Number of input events read.",
+            &[(String::new(), state.input_events as f64)],
+        );
+        write_metric(
+            &mut out,
+            ("ipmes_matches_total", "counter"),
+            "Number of pattern matches emitted.",
+            &[(String::new(), state.matches as f64)],
+        );
+        write_metric(
+            &mut out,
+            ("ipmes_invariant_violations_total", "counter"),
+            "Number of violations of internal invariants, whose offending items are skipped.",
+            &[(String::new(), invariant::num_violations() as f64)],
+        );
+
+        let Some(stats) = &state.stats else {
+            return out;
+        };
+        let live_instances: usize = stats
+            .buffers
+            .iter()
+            .filter(|buffer| buffer.name.starts_with("composition."))
+            .map(|buffer| buffer.len)
+            .sum();
+        write_metric(
+            &mut out,
+            ("ipmes_live_instances", "gauge"),
+            "Number of match instances held by the Composition layer.",
+            &[(String::new(), live_instances as f64)],
+        );
+        let per_buffer = |value: fn(&BufferStats) -> usize| -> Vec<(String, f64)> {
+            let buffers = stats.buffers.iter();
+            buffers
+                .map(|buffer| {
+                    (
+                        format!("{{buffer=\"{}\"}}", buffer.name),
+                        value(buffer) as f64,
+                    )
+                })
+                .collect()
+        };
+        write_metric(
+            &mut out,
+            ("ipmes_buffer_entries", "gauge"),
+            "Number of entries in each buffer of the Composition and Join layers.",
+            &per_buffer(|buffer| buffer.len),
+        );
+        write_metric(
+            &mut out,
+            ("ipmes_buffer_bytes", "gauge"),
+            "Approximate size in bytes of each buffer.",
+            &per_buffer(|buffer| buffer.approx_bytes),
+        );
+        write_metric(
+            &mut out,
+            ("ipmes_stream_time_seconds", "gauge"),
+            "The stream time of the latest buffer statistics.",
+            &[(String::new(), stats.time as f64 / 1000.0)],
+        );
+        write_metric(
+            &mut out,
+            ("ipmes_watermark_seconds", "gauge"),
+            "The earliest time a match can still start at, i.e. the stream time minus the window.",
+            &[(String::new(), stats.watermark as f64 / 1000.0)],
+        );
+        write_metric(
+            &mut out,
+            ("ipmes_watermark_lag_seconds", "gauge"),
+            "The wall-clock time minus the stream time.",
+            &[(
+                String::new(),
+                now.saturating_sub(stats.time) as f64 / 1000.0,
+            )],
+        );
+        out
+    }
+
+    /// Serve the metrics over HTTP at `addr` in a background thread, answering every request with
+    /// the rendered metrics.
+    pub fn serve(&self, addr: impl ToSocketAddrs) -> io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr)?;
+        let metrics = self.clone();
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                // skip the request headers
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 0) {
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                    line.clear();
+                }
+                let body = metrics.render();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        }))
+    }
+}
+
+/// Write a metric of the given name and type, with a sample per (labels, value) pair.
+fn write_metric(
+    out: &mut String,
+    (name, kind): (&str, &str),
+    help: &str,
+    samples: &[(String, f64)],
+) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::default();
+        metrics.add_input_events(10);
+        metrics.add_matches(2);
+        let out = metrics.render_at(5000);
+        assert!(out.contains("ipmes_input_events_total 10\n"));
+        assert!(out.contains("ipmes_matches_total 2\n"));
+        assert!(!out.contains("ipmes_live_instances"));
+
+        let mut buffer = BufferStats::new("composition.subject_instances");
+        buffer.add(1000, 64);
+        buffer.add(2000, 64);
+        metrics.set_stats(&StatsReport {
+            time: 3000,
+            watermark: 1000,
+            buffers: vec![buffer, BufferStats::new("join.buffer 3")],
+            top_states: vec![],
+            live_instances: vec![],
+        });
+        let out = metrics.render_at(5000);
+        assert!(out.contains("ipmes_live_instances 2\n"));
+        assert!(out.contains("ipmes_buffer_entries{buffer=\"composition.subject_instances\"} 2\n"));
+        assert!(out.contains("ipmes_buffer_bytes{buffer=\"join.buffer 3\"} 0\n"));
+        assert!(out.contains("ipmes_stream_time_seconds 3\n"));
+        assert!(out.contains("ipmes_watermark_seconds 1\n"));
+        assert!(out.contains("ipmes_watermark_lag_seconds 2\n"));
+    }
+
+    #[test]
+    fn test_serve_metrics() {
+        let metrics = Metrics::default();
+        metrics.add_matches(1);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        metrics.serve(addr).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(&metrics.render_at(0)));
+    }
+}
@@ -0,0 +1,249 @@
+//! A Parquet sink of pattern matches, enabled by the `parquet` feature.
+//!
+//! Each match is a row of the columns
+//!
+//! - `pattern: Utf8`
+//! - `earliest_time: UInt64`, `latest_time: UInt64`
+//! - `sequence: UInt64`, `window_id: UInt64`, null unless the match is numbered, see
+//!   [`MatchSequence`](crate::pattern_match::MatchSequence)
+//! - `events: List<Struct<pattern_event_id: UInt64, input_event_id: UInt64>>`
+//! - `entity_bindings: List<Struct<pattern_entity_id: UInt64, input_entity_id: UInt64>>`
+//!
+//! where the times are in milliseconds, and the pattern event and entity ids are those in the
+//! pattern file, as in the SQLite result store. The file can be read directly by Spark or DuckDB,
+//! e.g. `SELECT pattern, unnest(events) FROM 'matches.parquet'`.
+use crate::pattern::Pattern;
+use crate::pattern_match::PatternMatch;
+use arrow_array::builder::{ListBuilder, StringBuilder, StructBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Fields, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::Result;
+use parquet::file::properties::WriterProperties;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The number of matches buffered before they are written as a record batch.
+const BATCH_SIZE: usize = 8192;
+
+/// Writes pattern matches into a Parquet file in batches. Call [`ParquetSink::finish`] to write
+/// the remaining matches and the file footer; dropping the sink does the same but ignores the
+/// errors.
+pub struct ParquetSink<W: Write + Send> {
+    writer: Option<ArrowWriter<W>>,
+    schema: SchemaRef,
+    pattern: StringBuilder,
+    earliest_time: UInt64Builder,
+    latest_time: UInt64Builder,
+    sequence: UInt64Builder,
+    window_id: UInt64Builder,
+    events: ListBuilder<StructBuilder>,
+    entity_bindings: ListBuilder<StructBuilder>,
+    num_buffered: usize,
+}
+
+impl ParquetSink<File> {
+    /// Create or truncate the Parquet file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(File::create(path)?)
+    }
+}
+
+impl<W: Write + Send> ParquetSink<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        let events = pair_list_field("events", ["pattern_event_id", "input_event_id"]);
+        let entity_bindings =
+            pair_list_field("entity_bindings", ["pattern_entity_id", "input_entity_id"]);
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("pattern", DataType::Utf8, false),
+            Field::new("earliest_time", DataType::UInt64, false),
+            Field::new("latest_time", DataType::UInt64, false),
+            Field::new("sequence", DataType::UInt64, true),
+            Field::new("window_id", DataType::UInt64, true),
+            events.clone(),
+            entity_bindings.clone(),
+        ]));
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+
+        Ok(Self {
+            writer: Some(writer),
+            schema,
+            pattern: StringBuilder::new(),
+            earliest_time: UInt64Builder::new(),
+            latest_time: UInt64Builder::new(),
+            sequence: UInt64Builder::new(),
+            window_id: UInt64Builder::new(),
+            events: pair_list_builder(&events),
+            entity_bindings: pair_list_builder(&entity_bindings),
+            num_buffered: 0,
+        })
+    }
+
+    /// Append `pattern_match` of `pattern`, which is stored under the name `pattern_name`.
+    pub fn insert(
+        &mut self,
+        pattern_name: &str,
+        pattern: &Pattern,
+        pattern_match: &PatternMatch,
+    ) -> Result<()> {
+        self.pattern.append_value(pattern_name);
+        self.earliest_time.append_value(pattern_match.earliest_time);
+        self.latest_time.append_value(pattern_match.latest_time);
+        let sequence = pattern_match.sequence;
+        self.sequence.append_option(sequence.map(|sequence| sequence.number));
+        self.window_id.append_option(sequence.map(|sequence| sequence.window_id));
+
+        let mut events = vec![];
+        let mut bindings = BTreeMap::new();
+        for (event_id, match_event) in pattern_match.match_event_map.iter().enumerate() {
+            let Some(match_event) = match_event else {
+                continue;
+            };
+            let pattern_event_id = pattern.original_event_ids[event_id] as u64;
+            for input_event_id in match_event.raw_events.get_ids() {
+                events.push((pattern_event_id, input_event_id));
+            }
+            bindings.insert(match_event.pattern_subject_id, match_event.input_subject_id);
+            bindings.insert(match_event.pattern_object_id, match_event.input_object_id);
+        }
+        append_pairs(&mut self.events, events);
+        append_pairs(
+            &mut self.entity_bindings,
+            bindings
+                .into_iter()
+                .map(|(pattern_entity_id, input_entity_id)| {
+                    let pattern_entity_id = pattern.original_entity_ids[pattern_entity_id as usize];
+                    (pattern_entity_id as u64, input_entity_id)
+                }),
+        );
+
+        self.num_buffered += 1;
+        if self.num_buffered >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the buffered matches and the file footer, and return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush()?;
+        self.writer.take().unwrap().into_inner()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.num_buffered == 0 {
+            return Ok(());
+        }
+        self.num_buffered = 0;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.pattern.finish()),
+            Arc::new(self.earliest_time.finish()),
+            Arc::new(self.latest_time.finish()),
+            Arc::new(self.sequence.finish()),
+            Arc::new(self.window_id.finish()),
+            Arc::new(self.events.finish()),
+            Arc::new(self.entity_bindings.finish()),
+        ];
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.as_mut().unwrap().write(&batch)
+    }
+}
+
+impl<W: Write + Send> Drop for ParquetSink<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.flush();
+            let _ = self.writer.take().unwrap().close();
+        }
+    }
+}
+
+/// A `List<Struct<keys[0]: UInt64, keys[1]: UInt64>>` field.
+fn pair_list_field(name: &str, keys: [&str; 2]) -> Field {
+    let fields: Fields = keys
+        .iter()
+        .map(|key| Field::new(*key, DataType::UInt64, false))
+        .collect();
+    let item = Field::new("item", DataType::Struct(fields), false);
+    Field::new(name, DataType::List(Arc::new(item)), false)
+}
+
+/// The builder of a field created by `pair_list_field()`.
+fn pair_list_builder(field: &Field) -> ListBuilder<StructBuilder> {
+    let DataType::List(item) = field.data_type() else {
+        unreachable!("not a list field");
+    };
+    let DataType::Struct(fields) = item.data_type() else {
+        unreachable!("not a list of structs");
+    };
+    let values = StructBuilder::from_fields(fields.clone(), 0);
+    ListBuilder::new(values).with_field(item.clone())
+}
+
+fn append_pairs(
+    builder: &mut ListBuilder<StructBuilder>,
+    pairs: impl IntoIterator<Item = (u64, u64)>,
+) {
+    let values = builder.values();
+    for (first, second) in pairs {
+        let first_builder = values.field_builder::<UInt64Builder>(0).unwrap();
+        first_builder.append_value(first);
+        let second_builder = values.field_builder::<UInt64Builder>(1).unwrap();
+        second_builder.append_value(second);
+        values.append(true);
+    }
+    builder.append(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::UInt64Type;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_parquet_sink() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let engine = Engine::new(pattern, 1000 * 1000);
+        let summary = engine.run_file("testcases/paper/data.csv", |_| {}).unwrap();
+        let path = std::env::temp_dir().join(format!("ipmes-{}.parquet", std::process::id()));
+        let mut sink = ParquetSink::create(&path).unwrap();
+        for pattern_match in &summary.pattern_matches {
+            sink.insert("paper", engine.pattern(), pattern_match)
+                .unwrap();
+        }
+        sink.finish().unwrap();
+
+        let file = File::open(&path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), summary.pattern_matches.len());
+
+        let pattern_match = &summary.pattern_matches[0];
+        let earliest_time = batch["earliest_time"].as_primitive::<UInt64Type>();
+        assert_eq!(earliest_time.value(0), pattern_match.earliest_time);
+        let sequence = batch["sequence"].as_primitive::<UInt64Type>();
+        assert_eq!(sequence.value(0), 0);
+        let events = batch["events"].as_list::<i32>().value(0);
+        let input_event_ids = events.as_struct()["input_event_id"].as_primitive::<UInt64Type>();
+        let mut event_ids: Vec<u64> = input_event_ids.values().to_vec();
+        event_ids.sort_unstable();
+        let expected: Vec<u64> = pattern_match.event_ids.iter().map(|(id, _)| *id).collect();
+        assert_eq!(event_ids, expected);
+    }
+}
@@ -0,0 +1,144 @@
+use super::PatternParsingError;
+use serde_json::Value;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// A boolean composition of the matches of several patterns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompositeExpr {
+    /// A match of the pattern at the given index of [`CompositePattern::pattern_files`].
+    Pattern(usize),
+    /// A match of every operand, all within `within` (ms) from the earliest to the latest event.
+    /// `None` means the window size of the whole run.
+    And {
+        operands: Vec<CompositeExpr>,
+        within: Option<u64>,
+    },
+    /// A match of any operand.
+    Or(Vec<CompositeExpr>),
+}
+
+/// A pattern combinator file, which declares a boolean composition of existing patterns.
+///
+/// ```json
+/// {
+///     "Patterns": {
+///         "download": "SP6_regex.json",
+///         "exec": "SP7_regex.json",
+///         "persist": "SP8_regex.json"
+///     },
+///     "Expression": {
+///         "And": ["download", { "Or": ["exec", "persist"] }],
+///         "Within": 600
+///     }
+/// }
+/// ```
+///
+/// Paths are relative to the directory containing the file, and `"Within"` is in seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompositePattern {
+    /// Names of the patterns, indexed by the pattern indices in [`CompositeExpr::Pattern`].
+    pub names: Vec<String>,
+    pub pattern_files: Vec<PathBuf>,
+    pub expression: CompositeExpr,
+}
+
+impl CompositePattern {
+    pub fn parse(composite_file: &str) -> Result<Self, PatternParsingError> {
+        let json_obj: Value = serde_json::from_reader(File::open(composite_file)?)?;
+        let base_dir = Path::new(composite_file).parent().unwrap_or(Path::new(""));
+        Self::parse_json(&json_obj, base_dir)
+    }
+
+    fn parse_json(json_obj: &Value, base_dir: &Path) -> Result<Self, PatternParsingError> {
+        let patterns = json_obj["Patterns"]
+            .as_object()
+            .ok_or(PatternParsingError::KeyError("Patterns"))?;
+
+        let mut names = vec![];
+        let mut pattern_files = vec![];
+        for (name, path) in patterns {
+            let path = path
+                .as_str()
+                .ok_or(PatternParsingError::TypeError("Patterns"))?;
+            names.push(name.clone());
+            pattern_files.push(base_dir.join(path));
+        }
+
+        let expression = parse_expr(&json_obj["Expression"], &names)?;
+        Ok(Self {
+            names,
+            pattern_files,
+            expression,
+        })
+    }
+}
+
+fn parse_expr(json_obj: &Value, names: &[String]) -> Result<CompositeExpr, PatternParsingError> {
+    if let Some(name) = json_obj.as_str() {
+        return names
+            .iter()
+            .position(|n| n == name)
+            .map(CompositeExpr::Pattern)
+            .ok_or_else(|| PatternParsingError::UndefinedPattern(name.to_string()));
+    }
+
+    let parse_operands = |key| -> Result<Vec<CompositeExpr>, PatternParsingError> {
+        json_obj[key]
+            .as_array()
+            .filter(|operands| !operands.is_empty())
+            .ok_or(PatternParsingError::TypeError(key))?
+            .iter()
+            .map(|operand| parse_expr(operand, names))
+            .collect()
+    };
+
+    if !json_obj["And"].is_null() {
+        Ok(CompositeExpr::And {
+            operands: parse_operands("And")?,
+            within: json_obj["Within"].as_u64().map(|sec| sec * 1000),
+        })
+    } else if !json_obj["Or"].is_null() {
+        Ok(CompositeExpr::Or(parse_operands("Or")?))
+    } else {
+        Err(PatternParsingError::KeyError("Expression"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_composite() {
+        let json_obj = json!({
+            "Patterns": { "a": "a.json", "b": "b.json", "c": "c.json" },
+            "Expression": {
+                "And": ["a", { "Or": ["b", "c"] }],
+                "Within": 600
+            }
+        });
+
+        let composite = CompositePattern::parse_json(&json_obj, Path::new("data")).unwrap();
+        assert_eq!(composite.names, ["a", "b", "c"]);
+        assert_eq!(composite.pattern_files[1], Path::new("data/b.json"));
+        assert_eq!(
+            composite.expression,
+            CompositeExpr::And {
+                operands: vec![
+                    CompositeExpr::Pattern(0),
+                    CompositeExpr::Or(vec![CompositeExpr::Pattern(1), CompositeExpr::Pattern(2)]),
+                ],
+                within: Some(600_000),
+            }
+        );
+
+        let undefined =
+            json!({ "Patterns": { "a": "a.json" }, "Expression": { "Or": ["a", "x"] } });
+        assert!(matches!(
+            CompositePattern::parse_json(&undefined, Path::new("")),
+            Err(PatternParsingError::UndefinedPattern(name)) if name == "x"
+        ));
+    }
+}
@@ -1,6 +0,0 @@
-use super::Pattern;
-
-struct DecomposedPattern {
-    pattern: Pattern,
-    subpattern_heads: Vec<usize>,
-}
@@ -0,0 +1,119 @@
+use super::PatternParsingError;
+use serde_json::Value;
+
+/// A miniature data graph shipped along with a pattern, together with the expected number of
+/// matches of the pattern on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternExample {
+    pub name: String,
+    /// Input events in the same csv format as the data graph, one event per line.
+    pub events: String,
+    pub expected_matches: usize,
+    /// Window size (sec).
+    pub window_size: Option<u64>,
+}
+
+/// Parse the optional `"Examples"` section of a pattern file.
+///
+/// ```json
+/// "Examples": [
+///     {
+///         "Name": "match",
+///         "WindowSize": 1800,
+///         "Events": [
+///             "5,5,1,recvmsg,1,Socket::ip::8.8.8.8,2,Process::name::bash",
+///             "7,7,2,fork,2,Process::name::bash,3,Process::name::vim"
+///         ],
+///         "ExpectedMatches": 1
+///     }
+/// ]
+/// ```
+///
+/// `"Name"` and `"WindowSize"` are optional.
+pub fn parse_examples(json_obj: &Value) -> Result<Vec<PatternExample>, PatternParsingError> {
+    let examples_json = match &json_obj["Examples"] {
+        Value::Null => return Ok(vec![]),
+        Value::Array(examples) => examples,
+        _ => return Err(PatternParsingError::TypeError("Examples")),
+    };
+
+    let mut examples = vec![];
+    for (index, example) in examples_json.iter().enumerate() {
+        let name = example["Name"]
+            .as_str()
+            .map_or_else(|| format!("example {index}"), str::to_string);
+
+        let mut events = String::new();
+        for line in example["Events"]
+            .as_array()
+            .ok_or(PatternParsingError::KeyError("Events"))?
+        {
+            events.push_str(
+                line.as_str()
+                    .ok_or(PatternParsingError::TypeError("Events"))?,
+            );
+            events.push('\n');
+        }
+
+        let expected_matches = example["ExpectedMatches"]
+            .as_u64()
+            .ok_or(PatternParsingError::KeyError("ExpectedMatches"))?
+            as usize;
+        let window_size = example["WindowSize"].as_u64();
+
+        examples.push(PatternExample {
+            name,
+            events,
+            expected_matches,
+            window_size,
+        });
+    }
+
+    Ok(examples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_examples() {
+        let json_obj = json!({
+            "Examples": [
+                {
+                    "Name": "match",
+                    "WindowSize": 10,
+                    "Events": ["0,0,1,a,1,x,2,y", "1,1,2,b,2,y,3,z"],
+                    "ExpectedMatches": 1
+                },
+                {
+                    "Events": [],
+                    "ExpectedMatches": 0
+                }
+            ]
+        });
+
+        let examples = parse_examples(&json_obj).unwrap();
+        assert_eq!(
+            examples,
+            [
+                PatternExample {
+                    name: "match".to_string(),
+                    events: "0,0,1,a,1,x,2,y\n1,1,2,b,2,y,3,z\n".to_string(),
+                    expected_matches: 1,
+                    window_size: Some(10),
+                },
+                PatternExample {
+                    name: "example 1".to_string(),
+                    events: String::new(),
+                    expected_matches: 0,
+                    window_size: None,
+                },
+            ]
+        );
+
+        assert!(parse_examples(&json!({})).unwrap().is_empty());
+        assert!(parse_examples(&json!({ "Examples": [{ "Events": [] }] })).is_err());
+    }
+}
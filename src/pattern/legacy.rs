@@ -0,0 +1,218 @@
+//! The legacy (version 0.1) pattern format of three files sharing a prefix, see
+//! [`get_input_files`]:
+//!
+//! - `<prefix>_node.json`: a node per line, `{"node": {"id": ..., "properties": {...}}}`
+//! - `<prefix>_edge.json`: an edge per line, `{"edge": {"properties": {...}, "start": {"id": ...},
+//!   "end": {"id": ...}}}`
+//! - `<prefix>_oRels.json`: `{"<edge index>": {"parents": [...], "children": [...]}, "root": ...}`
+//!
+//! The signatures are extracted from the raw SPADE or DARPA properties in the same way as the
+//! preprocessing scripts, and the patterns with the prefix ending in `_regex` use regex.
+use super::parser::{get_input_files, parse_json};
+use super::{Pattern, PatternParsingError};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Parse the legacy pattern files with the prefix `input_prefix`, e.g. `data/patterns/TTP11`.
+pub fn parse_legacy(input_prefix: &str) -> Result<Pattern, PatternParsingError> {
+    let (node_file, edge_file, orels_file) = get_input_files(input_prefix);
+    let use_regex = input_prefix.ends_with("_regex");
+    let nodes = read_lines(&node_file)?;
+    let edges = read_lines(&edge_file)?;
+    let orels: Value = serde_json::from_reader(File::open(orels_file)?)?;
+    parse_json(&convert(&nodes, &edges, &orels, use_regex)?)
+}
+
+fn read_lines(file: &str) -> Result<Vec<Value>, PatternParsingError> {
+    let mut values = vec![];
+    for line in BufReader::new(File::open(file)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            values.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(values)
+}
+
+/// Convert the legacy pattern into a pattern file of version 0.2.0. The entities and events are
+/// numbered by their lines.
+fn convert(
+    nodes: &[Value],
+    edges: &[Value],
+    orels: &Value,
+    use_regex: bool,
+) -> Result<Value, PatternParsingError> {
+    let mut entities = vec![];
+    let mut node_ids = HashMap::new();
+    for (id, node) in nodes.iter().enumerate() {
+        let node = &node["node"];
+        node_ids.insert(raw_id(&node["id"])?, id);
+        entities.push(json!({ "ID": id, "Signature": node_signature(&node["properties"])? }));
+    }
+
+    let entity_id = |endpoint: &Value| {
+        let raw_id = raw_id(&endpoint["id"])?;
+        node_ids
+            .get(&raw_id)
+            .copied()
+            .ok_or(PatternParsingError::KeyError("node id"))
+    };
+    let mut events = vec![];
+    for (id, edge) in edges.iter().enumerate() {
+        let edge = &edge["edge"];
+        let parents: Vec<&Value> = orels[id.to_string()]["parents"]
+            .as_array()
+            .map_or(vec![], |parents| {
+                parents.iter().filter(|parent| parent.is_u64()).collect()
+            });
+        events.push(json!({
+            "ID": id,
+            "Signature": edge_signature(&edge["properties"])?,
+            "SubjectID": entity_id(&edge["start"])?,
+            "ObjectID": entity_id(&edge["end"])?,
+            "Parents": parents,
+        }));
+    }
+
+    Ok(json!({
+        "Version": "0.2.0",
+        "UseRegex": use_regex,
+        "Entities": entities,
+        "Events": events,
+    }))
+}
+
+/// The node ids are either strings or integers.
+fn raw_id(id: &Value) -> Result<String, PatternParsingError> {
+    match id {
+        Value::String(id) => Ok(id.clone()),
+        Value::Number(id) => Ok(id.to_string()),
+        _ => Err(PatternParsingError::KeyError("id")),
+    }
+}
+
+/// The `operation` of SPADE edges, or the `type` of DARPA edges.
+fn edge_signature(properties: &Value) -> Result<String, PatternParsingError> {
+    let properties = as_object(properties)?;
+    if properties.contains_key("operation") {
+        property(properties, "operation")
+    } else {
+        property(properties, "type")
+    }
+}
+
+/// Only DARPA nodes have `CDMVersion`.
+fn node_signature(properties: &Value) -> Result<String, PatternParsingError> {
+    let properties = as_object(properties)?;
+    if properties.contains_key("CDMVersion") {
+        darpa_node_signature(properties)
+    } else {
+        spade_node_signature(properties)
+    }
+}
+
+fn spade_node_signature(properties: &Map<String, Value>) -> Result<String, PatternParsingError> {
+    let node_type = property(properties, "type")?;
+    let mut signature = format!("{node_type}::");
+    if node_type == "Process" {
+        signature += &property(properties, "name")?;
+    } else if node_type == "Artifact" {
+        let subtype = property(properties, "subtype")?;
+        signature += &format!("{subtype}::");
+        if subtype == "file" || subtype == "directory" {
+            signature += &property(properties, "path")?;
+        } else if subtype == "network socket" {
+            signature += &format!(
+                "{}:{}",
+                property(properties, "remote address")?,
+                property(properties, "remote port")?
+            );
+        }
+    }
+    Ok(signature)
+}
+
+fn darpa_node_signature(properties: &Map<String, Value>) -> Result<String, PatternParsingError> {
+    let node_type = if properties.contains_key("NetFlowObject_baseObject_epoch") {
+        "OBJECT_SOCKET".to_string()
+    } else if properties.contains_key("UnnamedPipeObject_baseObject_epoch") {
+        "OBJECT_UNNAMEPIPE".to_string()
+    } else if properties.contains_key("type") {
+        property(properties, "type")?
+    } else {
+        return Ok("OTHER".to_string());
+    };
+
+    let mut signature = format!("{node_type}::");
+    match node_type.as_str() {
+        "SUBJECT_PROCESS" | "SUBJECT_UNIT" => {
+            signature += &property(properties, "Subject_properties_map_name")?;
+        }
+        "FILE_OBJECT_CHAR" | "FILE_OBJECT_FILE" | "FILE_OBJECT_DIR" => {
+            signature += &property(properties, "path")?;
+        }
+        "OBJECT_SOCKET" => {
+            signature += &format!(
+                "{}:{}",
+                property(properties, "NetFlowObject_remoteAddress")?,
+                property(properties, "NetFlowObject_remotePort")?
+            );
+        }
+        "SRCSINK_UNKNOWN" => signature += &property(properties, "SrcSinkObject_pid")?,
+        _ => {}
+    }
+    Ok(signature)
+}
+
+fn as_object(properties: &Value) -> Result<&Map<String, Value>, PatternParsingError> {
+    properties
+        .as_object()
+        .ok_or(PatternParsingError::KeyError("properties"))
+}
+
+/// The property `key` as a string, where numbers are converted.
+fn property(
+    properties: &Map<String, Value>,
+    key: &'static str,
+) -> Result<String, PatternParsingError> {
+    match properties.get(key) {
+        Some(Value::String(value)) => Ok(value.clone()),
+        Some(Value::Number(value)) => Ok(value.to_string()),
+        _ => Err(PatternParsingError::KeyError(key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    /// The universal patterns are converted from the legacy ones by
+    /// `scripts/to_universal_patterns.py`.
+    #[test]
+    fn test_parse_legacy() {
+        for (legacy, universal) in [
+            ("data/patterns/TTP11", "data/universal_patterns/SP12.json"),
+            (
+                "data/patterns/TTP9_regex",
+                "data/universal_patterns/SP9_regex.json",
+            ),
+            (
+                "data/darpa_patterns/TTP2",
+                "data/universal_patterns/DP3.json",
+            ),
+        ] {
+            let pattern = parse_legacy(legacy).unwrap();
+            let expected = Pattern::parse(universal).unwrap();
+            assert_eq!(pattern.use_regex, expected.use_regex, "{legacy}");
+            assert_eq!(pattern.entities, expected.entities, "{legacy}");
+            assert_eq!(pattern.events, expected.events, "{legacy}");
+            let dependencies: Vec<_> = pattern.order.get_dependencies().sorted().collect();
+            let expected_dependencies: Vec<_> =
+                expected.order.get_dependencies().sorted().collect();
+            assert_eq!(dependencies, expected_dependencies, "{legacy}");
+        }
+    }
+}
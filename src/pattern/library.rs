@@ -0,0 +1,258 @@
+use super::{CompositeExpr, CompositePattern, PatternParsingError};
+use serde_json::Value;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// The directory of the patterns shipped with this crate.
+pub const BUNDLED_LIBRARY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/universal_patterns");
+
+/// The file in a library directory describing its patterns.
+const CATALOG_FILE: &str = "library.json";
+
+/// A pattern in a [`PatternLibrary`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibraryEntry {
+    pub name: String,
+    pub pattern_file: PathBuf,
+    /// The tactics of the pattern in lower case, e.g. `exfiltration`.
+    pub categories: Vec<String>,
+    /// The ATT&CK techniques of the pattern, e.g. `T1048` or `T1059.004`.
+    pub techniques: Vec<String>,
+    pub description: Option<String>,
+}
+
+impl LibraryEntry {
+    fn in_category(&self, category: &str) -> bool {
+        self.categories
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(category))
+    }
+
+    /// A technique also selects its sub-techniques, e.g. `T1048` selects `T1048.003`.
+    fn has_technique(&self, technique: &str) -> bool {
+        self.techniques.iter().any(|t| {
+            t.eq_ignore_ascii_case(technique)
+                || t.get(..technique.len())
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(technique))
+                    && t[technique.len()..].starts_with('.')
+        })
+    }
+}
+
+/// The patterns selected by name, category or technique. A pattern is selected if any of them
+/// selects it.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSelection {
+    pub names: Vec<String>,
+    pub categories: Vec<String>,
+    pub techniques: Vec<String>,
+}
+
+impl RuleSelection {
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.categories.is_empty() && self.techniques.is_empty()
+    }
+}
+
+/// A directory of patterns, named and categorized by its `library.json`:
+///
+/// ```json
+/// {
+///     "Patterns": {
+///         "SP6": {
+///             "File": "SP6_regex.json",
+///             "Categories": ["discovery"],
+///             "Techniques": ["T1046", "T1016"],
+///             "Description": "Network scanning with nmap"
+///         }
+///     }
+/// }
+/// ```
+///
+/// Without `library.json`, every `.json` file in the directory is a pattern named after its file
+/// stem, without categories or techniques.
+#[derive(Debug, Clone)]
+pub struct PatternLibrary {
+    /// Sorted by name.
+    pub entries: Vec<LibraryEntry>,
+}
+
+impl PatternLibrary {
+    /// The patterns shipped with this crate, see [`BUNDLED_LIBRARY`].
+    pub fn bundled() -> Result<Self, PatternParsingError> {
+        Self::load(BUNDLED_LIBRARY)
+    }
+
+    pub fn load(library_dir: impl AsRef<Path>) -> Result<Self, PatternParsingError> {
+        let library_dir = library_dir.as_ref();
+        let catalog_file = library_dir.join(CATALOG_FILE);
+        if catalog_file.exists() {
+            let json_obj: Value = serde_json::from_reader(File::open(catalog_file)?)?;
+            return Self::parse_json(&json_obj, library_dir);
+        }
+
+        let mut entries = vec![];
+        for dir_entry in fs::read_dir(library_dir)? {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let Some(stem) = path.file_stem() else {
+                    continue;
+                };
+                entries.push(LibraryEntry {
+                    name: stem.to_string_lossy().into_owned(),
+                    pattern_file: path.clone(),
+                    categories: vec![],
+                    techniques: vec![],
+                    description: None,
+                });
+            }
+        }
+        entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self { entries })
+    }
+
+    fn parse_json(json_obj: &Value, base_dir: &Path) -> Result<Self, PatternParsingError> {
+        let patterns = json_obj["Patterns"]
+            .as_object()
+            .ok_or(PatternParsingError::KeyError("Patterns"))?;
+
+        let strings = |entry: &Value, key| -> Result<Vec<String>, PatternParsingError> {
+            match &entry[key] {
+                Value::Null => Ok(vec![]),
+                value => value
+                    .as_array()
+                    .ok_or(PatternParsingError::TypeError(key))?
+                    .iter()
+                    .map(|s| {
+                        s.as_str()
+                            .map(str::to_string)
+                            .ok_or(PatternParsingError::TypeError(key))
+                    })
+                    .collect(),
+            }
+        };
+
+        let mut entries = vec![];
+        for (name, entry) in patterns {
+            let file = entry["File"]
+                .as_str()
+                .ok_or(PatternParsingError::KeyError("File"))?;
+            entries.push(LibraryEntry {
+                name: name.clone(),
+                pattern_file: base_dir.join(file),
+                categories: strings(entry, "Categories")?,
+                techniques: strings(entry, "Techniques")?,
+                description: entry["Description"].as_str().map(str::to_string),
+            });
+        }
+        entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self { entries })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LibraryEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// The selected patterns in the order of the library. Returns an error if a name is not in the
+    /// library, or nothing is selected.
+    pub fn select(
+        &self,
+        selection: &RuleSelection,
+    ) -> Result<Vec<&LibraryEntry>, PatternParsingError> {
+        if let Some(name) = selection.names.iter().find(|name| self.get(name).is_none()) {
+            return Err(PatternParsingError::UndefinedPattern(name.clone()));
+        }
+        let selected: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                selection.names.contains(&entry.name)
+                    || selection.categories.iter().any(|c| entry.in_category(c))
+                    || selection.techniques.iter().any(|t| entry.has_technique(t))
+            })
+            .collect();
+        if selected.is_empty() {
+            return Err(PatternParsingError::NoPatternSelected);
+        }
+        Ok(selected)
+    }
+}
+
+/// A composite pattern matching any of `entries`, so that several patterns of a library run
+/// together.
+pub fn any_of(entries: &[&LibraryEntry]) -> CompositePattern {
+    CompositePattern {
+        names: entries.iter().map(|entry| entry.name.clone()).collect(),
+        pattern_files: entries
+            .iter()
+            .map(|entry| entry.pattern_file.clone())
+            .collect(),
+        expression: CompositeExpr::Or((0..entries.len()).map(CompositeExpr::Pattern).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+    use serde_json::json;
+
+    #[test]
+    fn test_select() {
+        let json_obj = json!({
+            "Patterns": {
+                "SP4": { "File": "SP4.json", "Categories": ["exfiltration"], "Techniques": ["T1048"] },
+                "SP6": { "File": "SP6.json", "Categories": ["Discovery"], "Techniques": ["T1046"] },
+                "SP8": { "File": "SP8.json", "Techniques": ["T1074.001"] }
+            }
+        });
+        let library = PatternLibrary::parse_json(&json_obj, Path::new("lib")).unwrap();
+        assert_eq!(
+            library.get("SP6").unwrap().pattern_file,
+            Path::new("lib/SP6.json")
+        );
+
+        let names = |selection: RuleSelection| -> Vec<String> {
+            let selected = library.select(&selection).unwrap();
+            selected.iter().map(|entry| entry.name.clone()).collect()
+        };
+        let selection = RuleSelection {
+            names: vec!["SP8".into()],
+            categories: vec!["discovery".into()],
+            ..Default::default()
+        };
+        assert_eq!(names(selection), ["SP6", "SP8"]);
+        let selection = RuleSelection {
+            techniques: vec!["T1074".into()],
+            ..Default::default()
+        };
+        assert_eq!(names(selection), ["SP8"]);
+        // not a sub-technique
+        let selection = RuleSelection {
+            techniques: vec!["T107".into()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            library.select(&selection),
+            Err(PatternParsingError::NoPatternSelected)
+        ));
+        let selection = RuleSelection {
+            names: vec!["SP9".into()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            library.select(&selection),
+            Err(PatternParsingError::UndefinedPattern(name)) if name == "SP9"
+        ));
+    }
+
+    #[test]
+    fn test_bundled_library() {
+        let library = PatternLibrary::bundled().unwrap();
+        assert!(!library.entries.is_empty());
+        for entry in &library.entries {
+            let pattern_file = entry.pattern_file.to_string_lossy();
+            assert!(Pattern::parse(&pattern_file).is_ok(), "{pattern_file}");
+        }
+    }
+}
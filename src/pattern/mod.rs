@@ -1,19 +1,53 @@
-mod decomposed_pattern;
+pub mod composite;
+pub mod example;
+pub mod legacy;
+pub mod library;
 pub mod order_relation;
 pub mod parser;
+pub mod rule_pack;
 pub mod sub_pattern;
 
-use self::parser::parse_json;
+pub use self::composite::{CompositeExpr, CompositePattern};
+use self::example::parse_examples;
+pub use self::example::PatternExample;
+pub use self::library::{LibraryEntry, PatternLibrary, RuleSelection};
+pub use self::order_relation::OrderRelation;
 pub use self::parser::PatternParsingError;
-use order_relation::OrderRelation;
+use self::parser::{get_input_files, parse_json};
+pub use self::rule_pack::{Rule, RulePack};
+use crate::process_layers::composition_layer::construct_regex_patterns;
+use crate::process_layers::join_layer::JoinPlan;
+use crate::process_layers::regex_limits::build_regex;
+use crate::process_layers::{WindowBoundary, WindowKind};
 use serde_json::Value;
+use std::path::Path;
 use std::{fs::File, io::Read};
-pub use sub_pattern::{decompose, SubPattern};
+pub use sub_pattern::{
+    decompose, reassign_ids, validate_decomposition, DecompositionError, SubPattern,
+};
 
 #[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub struct PatternEntity {
     pub id: usize,
     pub signature: String,
+    /// Given by `Wildcard`, this entity binds to any input entity regardless of `signature`, and
+    /// is exempt from the node uniqueness: it may bind the same input entity as other pattern
+    /// entities, e.g. two "some file" placeholders.
+    pub is_wildcard: bool,
+}
+
+impl PatternEntity {
+    /// The regex (without anchors) matching the signatures of the input entities this entity
+    /// binds to, where `signature` is escaped if `escape_regex`.
+    pub fn signature_regex(&self, escape_regex: bool) -> String {
+        if self.is_wildcard {
+            "[^\0]*".to_string()
+        } else if escape_regex {
+            regex::escape(&self.signature)
+        } else {
+            self.signature.clone()
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -21,6 +55,31 @@ pub enum PatternEventType {
     Default,
     Frequency(u32),
     Flow,
+    /// Matches when a subject performs at least `count` matching events toward at least
+    /// `distinct_objects` different objects within `within` milliseconds (the window if `None`),
+    /// e.g. a scan. The object of the event is bound to that of the latest matching event.
+    Aggregate {
+        count: u32,
+        distinct_objects: u32,
+        within: Option<u64>,
+    },
+}
+
+/// The allowed time gap (ms) from the end of a parent event to the start of its child event.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct TimeGap {
+    pub min: u64,
+    pub max: Option<u64>,
+}
+
+impl TimeGap {
+    /// Whether a child event starting at `child_start` is allowed after its parent event ending
+    /// at `parent_end`.
+    pub fn allows(&self, parent_end: u64, child_start: u64) -> bool {
+        child_start
+            .checked_sub(parent_end)
+            .is_some_and(|gap| gap >= self.min && self.max.is_none_or(|max| gap <= max))
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -28,8 +87,66 @@ pub struct PatternEvent {
     pub id: usize,
     pub event_type: PatternEventType,
     pub signature: String,
+    /// The other signatures an input event may match instead of `signature`, given by an array
+    /// of alternatives as `Signature`, e.g. `["read", "pread64"]`.
+    pub alternatives: Vec<String>,
     pub subject: PatternEntity,
     pub object: PatternEntity,
+    /// The time gaps from the parent events (by id) given by `ParentsMinGapMs` and
+    /// `ParentsMaxGapMs`, and from the earlier events given by `Gap` constraints. Parents not
+    /// listed here only need to end before this event starts.
+    pub parent_gaps: Vec<(usize, TimeGap)>,
+    /// The pairs of pattern entities (by id) that must match different input entities, given by
+    /// `Constraints`, among those involving the subject or the object of this event.
+    pub distinct_entities: Vec<(usize, usize)>,
+    /// The `(pattern entity id, payload column)` pairs given by `Constraints`, among those of the
+    /// subject or the object of this event: the input events binding the entity must agree on
+    /// the column of their payloads, see `ParseLayer::set_capture_payload()`.
+    pub same_attributes: Vec<(usize, usize)>,
+    /// The events (by id) that must be matched by input events with the same timestamp as this
+    /// event, i.e. in the same batch, given by `Simultaneous` constraints.
+    pub simultaneous_events: Vec<usize>,
+    /// Given by `Static`, this event matches the static context graph rather than the input
+    /// stream, see `CompositionLayer::set_context_graph`.
+    pub is_static: bool,
+    /// Given by `Optional`, a match is still output if this event never occurs, but includes it
+    /// if it occurs within the window and satisfies the constraints. See [`decompose`].
+    pub is_optional: bool,
+}
+
+impl PatternEvent {
+    /// Whether the subject and the object are wildcard entities, see `PatternEntity::is_wildcard`.
+    pub fn wildcards(&self) -> (bool, bool) {
+        (self.subject.is_wildcard, self.object.is_wildcard)
+    }
+
+    /// `signature` followed by its `alternatives`.
+    pub fn signatures(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.signature.as_str()).chain(self.alternatives.iter().map(String::as_str))
+    }
+
+    /// Whether any signature of this (non-flow) event matches an input event with arbitrary
+    /// signatures, e.g. `.*` for the event, the subject and the object, or a signature matching
+    /// the empty string such as `|read`, which leaves the rest of the regex unanchored. Such an
+    /// event matches every input event and explodes the number of instances.
+    pub fn is_broad(&self, use_regex: bool) -> bool {
+        const PROBE: &str = "\u{1}\u{2}\u{3}\0\u{1}\u{2}\u{3}\0\u{1}\u{2}\u{3}";
+        if self.event_type == PatternEventType::Flow {
+            return false;
+        }
+        construct_regex_patterns(self, !use_regex)
+            .iter()
+            .filter_map(|pattern| build_regex(pattern).ok())
+            .any(|regex| regex.is_match(PROBE))
+    }
+
+    /// The time gap from the parent event `parent_id` to this event.
+    pub fn gap_from(&self, parent_id: usize) -> TimeGap {
+        self.parent_gaps
+            .iter()
+            .find(|(id, _)| *id == parent_id)
+            .map_or_else(TimeGap::default, |(_, gap)| *gap)
+    }
 }
 
 #[derive(Debug)]
@@ -38,17 +155,53 @@ pub struct Pattern {
     pub entities: Vec<PatternEntity>,
     pub events: Vec<PatternEvent>,
     pub order: OrderRelation,
+    /// The entity ids in the pattern file, indexed by the (reassigned) entity ids.
+    pub original_entity_ids: Vec<usize>,
+    /// The event ids in the pattern file, indexed by the (reassigned) event ids.
+    pub original_event_ids: Vec<usize>,
+    /// The explicit join order in the pattern file, which overrides the heuristic of the Join
+    /// layer.
+    pub join_plan: Option<JoinPlan>,
+    /// The windowing semantics of the pattern, sliding window by default.
+    pub window: WindowKind,
+    /// Whether events exactly a window size apart are in the same window.
+    pub window_boundary: WindowBoundary,
+    /// Whether a match may contain events of different hosts in a multi-host data graph.
+    pub span_hosts: bool,
+    /// Whether an input event may match at most one pattern event in a match.
+    pub event_uniqueness: bool,
 }
 
 impl Pattern {
+    /// Parse the pattern file. If there is no such file, but the legacy pattern files with the
+    /// prefix `pattern_file` exist, e.g. `data/patterns/TTP11`, parse them instead, see [`legacy`].
     pub fn parse(pattern_file: &str) -> Result<Self, PatternParsingError> {
+        let legacy_edge_file = get_input_files(pattern_file).1;
+        if !Path::new(pattern_file).exists() && Path::new(&legacy_edge_file).exists() {
+            return legacy::parse_legacy(pattern_file);
+        }
+        parse_json(&Self::read_json(pattern_file)?)
+    }
+
+    /// Parse the pattern from the content of a pattern file.
+    pub fn parse_str(content: &str) -> Result<Self, PatternParsingError> {
+        parse_json(&serde_json::from_str(content)?)
+    }
+
+    /// Parse the pattern along with the examples in its `"Examples"` section.
+    pub fn parse_with_examples(
+        pattern_file: &str,
+    ) -> Result<(Self, Vec<PatternExample>), PatternParsingError> {
+        let json_obj = Self::read_json(pattern_file)?;
+        Ok((parse_json(&json_obj)?, parse_examples(&json_obj)?))
+    }
+
+    fn read_json(pattern_file: &str) -> Result<Value, PatternParsingError> {
         let mut file = File::open(pattern_file)?;
         let mut content = Vec::new();
         file.read_to_end(&mut content)?;
 
-        let json_obj: Value = serde_json::from_slice(&content)?;
-
-        parse_json(&json_obj)
+        Ok(serde_json::from_slice(&content)?)
     }
 
     /// Create pattern from graph (V, E). Each vertex is associated with a signature.
@@ -63,6 +216,7 @@ impl Pattern {
             entities.push(PatternEntity {
                 id,
                 signature: signature.to_string(),
+                is_wildcard: false,
             });
         }
 
@@ -74,8 +228,15 @@ impl Pattern {
                 id,
                 event_type: PatternEventType::Default,
                 signature: edge.2.to_string(),
+                alternatives: vec![],
                 subject: entities[edge.0].clone(),
                 object: entities[edge.1].clone(),
+                parent_gaps: vec![],
+                distinct_entities: vec![],
+                same_attributes: vec![],
+                simultaneous_events: vec![],
+                is_static: false,
+                is_optional: false,
             });
         }
 
@@ -83,12 +244,33 @@ impl Pattern {
 
         Self {
             use_regex,
+            original_entity_ids: (0..entities.len()).collect(),
+            original_event_ids: (0..events.len()).collect(),
+            join_plan: None,
+            window: WindowKind::Sliding,
+            window_boundary: WindowBoundary::Inclusive,
+            span_hosts: false,
+            event_uniqueness: true,
             entities,
             events,
             order,
         }
     }
 
+    /// Returns the (reassigned) id of the entity whose id in the pattern file is `original_id`.
+    pub fn entity_id(&self, original_id: usize) -> Option<usize> {
+        self.original_entity_ids
+            .iter()
+            .position(|id| *id == original_id)
+    }
+
+    /// Returns the (reassigned) id of the event whose id in the pattern file is `original_id`.
+    pub fn event_id(&self, original_id: usize) -> Option<usize> {
+        self.original_event_ids
+            .iter()
+            .position(|id| *id == original_id)
+    }
+
     /// Optimize the pattern. Currently implemented optimizations:
     /// - Turn frequency 1 event to default event
     pub fn optimize(&mut self) {
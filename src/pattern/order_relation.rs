@@ -1,76 +1,154 @@
 use crate::pattern::parser::PatternParsingError;
-use petgraph::algo::floyd_warshall;
-use petgraph::graph::NodeIndex;
-use petgraph::graph::{DefaultIx, Graph};
+use petgraph::graph::{self, DefaultIx, Graph, NodeIndex};
 use petgraph::visit::EdgeRef;
 use petgraph::Direction;
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::fs::File;
 use std::io::Read;
 
+/// The partial order of the pattern edges (events) of a pattern, given by their `Parents`: a
+/// pattern edge starts only after its parents end. The pattern edges are referred to by their ids
+/// in [`Pattern`](super::Pattern), i.e. their indices in `Pattern::events`.
+///
+/// The relation is immutable once built, and all the queries borrow it. Internally, it is a
+/// dependency graph where node 0 is a virtual root pointing to the events without parents, and
+/// node `i + 1` is event `i`.
 #[derive(Debug)]
 pub struct OrderRelation {
-    pub graph: Graph<usize, ()>,
+    graph: Graph<usize, ()>,
+    /// `successors[i]` is the bitset of the pattern edges that should appear after pattern edge
+    /// `i`, i.e. the transitive closure of the dependencies. See `must_precede()`.
+    successors: Vec<Box<[u64]>>,
+    /// `predecessors[i]` is the bitset of the pattern edges that should appear before pattern
+    /// edge `i`, the transpose of `successors`.
+    predecessors: Vec<Box<[u64]>>,
 }
 
 impl From<Graph<usize, ()>> for OrderRelation {
     fn from(value: Graph<usize, ()>) -> Self {
+        let successors = transitive_closure(&value);
         Self {
+            predecessors: transpose(&successors),
+            successors,
             graph: value,
         }
     }
 }
 
+/// Returns the bitsets of the pattern edges reachable from each pattern edge in `graph`.
+fn transitive_closure(graph: &Graph<usize, ()>) -> Vec<Box<[u64]>> {
+    let num_edges = graph.node_count().saturating_sub(1);
+    (0..num_edges)
+        .map(|eid| {
+            let mut successors = vec![0u64; num_edges.div_ceil(64)].into_boxed_slice();
+            let mut stack = vec![NodeIndex::<DefaultIx>::new(eid + 1)];
+            while let Some(idx) = stack.pop() {
+                for next in graph.neighbors_directed(idx, Direction::Outgoing) {
+                    // the root has no incoming dependency
+                    let next_id = next.index() - 1;
+                    if successors[next_id / 64] & (1 << (next_id % 64)) == 0 {
+                        successors[next_id / 64] |= 1 << (next_id % 64);
+                        stack.push(next);
+                    }
+                }
+            }
+            successors
+        })
+        .collect()
+}
+
+/// Returns the bitsets where `b` is in the `a`-th one iff `a` is in the `b`-th one of `bitsets`.
+fn transpose(bitsets: &[Box<[u64]>]) -> Vec<Box<[u64]>> {
+    let mut transposed =
+        vec![vec![0u64; bitsets.len().div_ceil(64)].into_boxed_slice(); bitsets.len()];
+    for (a, bitset) in bitsets.iter().enumerate() {
+        for b in Bits::of(Some(bitset)) {
+            transposed[b][a / 64] |= 1 << (a % 64);
+        }
+    }
+    transposed
+}
+
 impl OrderRelation {
+    /// The number of pattern edges in the relation.
+    pub fn num_events(&self) -> usize {
+        self.graph.node_count().saturating_sub(1)
+    }
+
     /// Return an iterator over the pair (source id, target id) of dependency edges, where the ids
     /// are those of pattern edges'.
-    pub fn get_dependencies(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
-        self.graph
-            .edge_references()
-            .filter_map(|edge| {
-                let src = edge.source().index();
-                let tgt = edge.target().index();
-                
-                if src == 0 || tgt == 0 {
-                    None
-                } else {
-                    Some((src - 1, tgt - 1))
-                }
-            })
+    pub fn get_dependencies(&self) -> Dependencies<'_> {
+        Dependencies {
+            edges: self.graph.edge_references(),
+        }
+    }
+
+    /// Returns `true` if pattern edge `a` should appear before pattern edge `b`, either directly or
+    /// through other pattern edges. The transitive closure is precomputed, so this takes O(1).
+    pub fn must_precede(&self, a: usize, b: usize) -> bool {
+        self.successors
+            .get(a)
+            .and_then(|successors| successors.get(b / 64))
+            .is_some_and(|word| word & (1 << (b % 64)) != 0)
     }
 
     /// Returns an iterator over the id of pattern edges that should appear **before** the given pattern
     /// edge.
-    pub fn get_previous(&self, eid: usize) -> impl Iterator<Item = usize> + '_ {
+    pub fn get_previous(&self, eid: usize) -> Neighbors<'_> {
         // Indices in "graph" is incremented by 1, since "0" is reserved for "root".
-        let idx = NodeIndex::<DefaultIx>::new(eid + 1);
-        self.graph
-            .neighbors_directed(idx, Direction::Incoming)
-            .filter_map(|idx| {
-                if idx.index() > 0 {
-                    Some(idx.index() - 1)
-                } else {
-                    None
-                }
-            })
+        self.neighbors(eid + 1, Direction::Incoming)
     }
 
     /// Returns an iterator over the id of pattern edges that should appear **after** the given pattern
     /// edge.
-    pub fn get_next(&self, eid: usize) -> impl Iterator<Item = usize> + '_ {
-        let idx = NodeIndex::<DefaultIx>::new(eid + 1);
-        self.graph
-            .neighbors_directed(idx, Direction::Outgoing)
-            .map(|idx| idx.index() - 1)
+    pub fn get_next(&self, eid: usize) -> Neighbors<'_> {
+        self.neighbors(eid + 1, Direction::Outgoing)
     }
 
     /// Returns an iterator over the id of pattern edges that are roots
-    pub fn get_roots(&self) -> impl Iterator<Item = usize> + '_ {
-        let idx = NodeIndex::<DefaultIx>::new(0);
-        self.graph
-            .neighbors_directed(idx, Direction::Outgoing)
-            .map(|idx| idx.index() - 1)
+    pub fn get_roots(&self) -> Neighbors<'_> {
+        self.neighbors(0, Direction::Outgoing)
+    }
+
+    fn neighbors(&self, index: usize, direction: Direction) -> Neighbors<'_> {
+        let neighbors = (index < self.graph.node_count()).then(|| {
+            self.graph
+                .neighbors_directed(NodeIndex::new(index), direction)
+        });
+        Neighbors { neighbors }
+    }
+
+    /// Returns an iterator over the ids of the pattern edges that should appear before the given
+    /// pattern edge, directly or through other pattern edges, in ascending order.
+    pub fn ancestors(&self, eid: usize) -> Bits<'_> {
+        Bits::of(self.predecessors.get(eid).map(|bitset| &bitset[..]))
+    }
+
+    /// Returns an iterator over the ids of the pattern edges that should appear after the given
+    /// pattern edge, directly or through other pattern edges, in ascending order.
+    pub fn descendants(&self, eid: usize) -> Bits<'_> {
+        Bits::of(self.successors.get(eid).map(|bitset| &bitset[..]))
+    }
+
+    /// Returns an iterator over the ids of all the pattern edges in a topological order, where
+    /// each appears after all those that should appear before it. Among the pattern edges ready at
+    /// the same time, the one with the smallest id comes first.
+    pub fn topological_order(&self) -> TopologicalOrder<'_> {
+        let mut in_degrees = vec![0; self.num_events()];
+        for (_, tgt) in self.get_dependencies() {
+            in_degrees[tgt] += 1;
+        }
+        let ready = (in_degrees.iter().enumerate())
+            .filter(|(_, in_degree)| **in_degree == 0)
+            .map(|(eid, _)| Reverse(eid))
+            .collect();
+        TopologicalOrder {
+            order: self,
+            in_degrees,
+            ready,
+        }
     }
 
     /// Construct OrderRelation from order rules for easier unit testing.
@@ -136,43 +214,114 @@ impl OrderRelation {
     /// a cycle in the dependency graph. If a cycle is found, return
     /// `false`, otherwise return `true`.
     pub fn is_valid(&self) -> bool {
-        for root in self.get_roots() {
-            let mut ancestors = HashSet::new();
-            let mut visited = HashSet::new();
-            let root = NodeIndex::<DefaultIx>::new(root);
-            if self.contains_cycle(root, &mut ancestors, &mut visited) {
-                return false;
-            }
+        (0..self.successors.len()).all(|eid| !self.must_precede(eid, eid))
+    }
+}
+
+/// The pattern edges directly before or after a pattern edge, or the roots, see
+/// [`OrderRelation::get_previous`], [`OrderRelation::get_next`] and [`OrderRelation::get_roots`].
+#[derive(Clone)]
+pub struct Neighbors<'a> {
+    /// `None` for a pattern edge out of range.
+    neighbors: Option<graph::Neighbors<'a, (), DefaultIx>>,
+}
+
+impl Iterator for Neighbors<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // skip the root
+        self.neighbors
+            .as_mut()?
+            .find(|idx| idx.index() > 0)
+            .map(|idx| idx.index() - 1)
+    }
+}
+
+/// The (source id, target id) pairs of the dependencies, see [`OrderRelation::get_dependencies`].
+#[derive(Clone)]
+pub struct Dependencies<'a> {
+    edges: graph::EdgeReferences<'a, ()>,
+}
+
+impl Iterator for Dependencies<'_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.edges.find_map(|edge| {
+            let src = edge.source().index();
+            let tgt = edge.target().index();
+            (src > 0 && tgt > 0).then(|| (src - 1, tgt - 1))
+        })
+    }
+}
+
+/// The pattern edges in a bitset of the transitive closure, in ascending order, see
+/// [`OrderRelation::ancestors`] and [`OrderRelation::descendants`].
+#[derive(Clone)]
+pub struct Bits<'a> {
+    words: &'a [u64],
+    /// The next bit to check.
+    next: usize,
+}
+
+impl<'a> Bits<'a> {
+    /// The bits of `bitset`, which is `None` for a pattern edge out of range.
+    fn of(bitset: Option<&'a [u64]>) -> Self {
+        Self {
+            words: bitset.unwrap_or_default(),
+            next: 0,
         }
-        true
     }
+}
 
-    fn contains_cycle(
-        &self,
-        root: NodeIndex,
-        ancestors: &mut HashSet<NodeIndex>,
-        visited: &mut HashSet<NodeIndex>,
-    ) -> bool {
-        if visited.contains(&root) {
-            return false;
+impl Iterator for Bits<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(word) = self.words.get(self.next / 64) {
+            // the bits not yet checked in the current word
+            let remaining = word & (u64::MAX << (self.next % 64));
+            if remaining != 0 {
+                let eid = self.next / 64 * 64 + remaining.trailing_zeros() as usize;
+                self.next = eid + 1;
+                return Some(eid);
+            }
+            self.next = (self.next / 64 + 1) * 64;
         }
+        None
+    }
+}
 
-        ancestors.insert(root);
-        visited.insert(root);
-        let neighbors = self.graph.neighbors_directed(root, Direction::Outgoing);
-        for nxt in neighbors {
-            if ancestors.contains(&nxt) || self.contains_cycle(nxt, ancestors, visited) {
-                return true;
+/// The pattern edges in a topological order, see [`OrderRelation::topological_order`]. It ends
+/// early if the relation has a cycle, leaving out the pattern edges on or after the cycle.
+#[derive(Clone)]
+pub struct TopologicalOrder<'a> {
+    order: &'a OrderRelation,
+    /// The number of dependencies from the pattern edges not yet visited.
+    in_degrees: Vec<usize>,
+    ready: BinaryHeap<Reverse<usize>>,
+}
+
+impl Iterator for TopologicalOrder<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse(eid) = self.ready.pop()?;
+        for next in self.order.get_next(eid) {
+            self.in_degrees[next] -= 1;
+            if self.in_degrees[next] == 0 {
+                self.ready.push(Reverse(next));
             }
         }
-        ancestors.remove(&root);
-        false
+        Some(eid)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use itertools::Itertools;
 
     #[test]
     fn test_parsing() {
@@ -193,4 +342,54 @@ mod tests {
         let order = OrderRelation::from_order_rules(&cycle_rules, &[0]);
         assert!(!order.is_valid());
     }
+
+    #[test]
+    fn test_must_precede() {
+        let rules = [(0, 1), (1, 2), (0, 3)];
+        let order = OrderRelation::from_order_rules(&rules, &[0, 4]);
+        assert!(order.must_precede(0, 1));
+        assert!(order.must_precede(0, 2));
+        assert!(order.must_precede(0, 3));
+        assert!(!order.must_precede(2, 0));
+        assert!(!order.must_precede(1, 3));
+        assert!(!order.must_precede(0, 4));
+        assert!(!order.must_precede(0, 0));
+        // out of range
+        assert!(!order.must_precede(0, 100));
+    }
+
+    #[test]
+    fn test_queries() {
+        // 3 -> 1 -> 2, 0 -> 2, and 4 alone
+        let rules = [(3, 1), (1, 2), (0, 2)];
+        let order = OrderRelation::from_order_rules(&rules, &[0, 3, 4]);
+        assert_eq!(order.num_events(), 5);
+        assert_eq!(order.get_roots().sorted().collect_vec(), [0, 3, 4]);
+        assert_eq!(order.get_previous(2).sorted().collect_vec(), [0, 1]);
+        assert_eq!(order.get_previous(0).count(), 0);
+        assert_eq!(order.get_next(3).collect_vec(), [1]);
+        assert_eq!(
+            order.get_dependencies().sorted().collect_vec(),
+            [(0, 2), (1, 2), (3, 1)]
+        );
+
+        assert_eq!(order.ancestors(2).collect_vec(), [0, 1, 3]);
+        assert_eq!(order.descendants(3).collect_vec(), [1, 2]);
+        assert_eq!(order.descendants(4).count(), 0);
+        assert_eq!(order.topological_order().collect_vec(), [0, 3, 1, 2, 4]);
+
+        // out of range
+        assert_eq!(order.get_next(100).count(), 0);
+        assert_eq!(order.ancestors(100).count(), 0);
+
+        // across the words of the bitsets
+        let chain: Vec<(u32, u32)> = (0..99).map(|i| (i, i + 1)).collect();
+        let order = OrderRelation::from_order_rules(&chain, &[0]);
+        assert_eq!(order.ancestors(99).collect_vec(), (0..99).collect_vec());
+        assert_eq!(order.descendants(63).collect_vec(), (64..100).collect_vec());
+        assert_eq!(
+            order.topological_order().collect_vec(),
+            (0..100).collect_vec()
+        );
+    }
 }
@@ -1,6 +1,10 @@
 use super::{
-    order_relation::OrderRelation, Pattern, PatternEntity, PatternEvent, PatternEventType,
+    decompose, order_relation::OrderRelation, Pattern, PatternEntity, PatternEvent,
+    PatternEventType, TimeGap,
 };
+use crate::process_layers::join_layer::join_plan::JoinPlanError;
+use crate::process_layers::join_layer::JoinPlan;
+use crate::process_layers::{WindowBoundary, WindowKind};
 use log::warn;
 use petgraph::Graph;
 use serde_json::Value;
@@ -39,10 +43,55 @@ pub enum PatternParsingError {
     #[error("frequency must be an integer that > 1, but {0} is provided")]
     InvalidFrequency(u32),
 
+    #[error("aggregate event requires Count >= DistinctObjects >= 1, but {0} and {1} given")]
+    InvalidAggregate(u32, u32),
+
+    #[error("the object of aggregate event {0} cannot be shared with other events")]
+    SharedAggregateObject(usize),
+
     #[error("cycle detected in the dependency graph")]
     DependencyCycle,
+
+    #[error("the minimum time gap to a parent exceeds the maximum one in event {0}")]
+    InvalidTimeGap(usize),
+
+    #[error("invalid join order: {0}")]
+    InvalidJoinOrder(#[from] JoinPlanError),
+
+    #[error("undefined pattern: {0}")]
+    UndefinedPattern(String),
+
+    #[error("no pattern is selected")]
+    NoPatternSelected,
+
+    #[error("constraint {0} requires an entity to differ from itself")]
+    UnsatisfiableConstraint(usize),
+
+    #[error("the first event of gap constraint {0} does not precede the second one by Parents")]
+    UnorderedGapEvents(usize),
+
+    #[error("only default-typed events can be static, but event {0} is not")]
+    InvalidStaticEvent(usize),
+
+    #[error("only default-typed, non-static events can be simultaneous, but event {0} is not")]
+    InvalidSimultaneousEvent(usize),
+
+    #[error("static event {0} cannot be optional")]
+    InvalidOptionalEvent(usize),
+
+    #[error("there must be at least one pattern event that is not optional")]
+    NoRequiredEvent,
+
+    #[error("JoinOrder cannot be given for patterns with optional events")]
+    JoinOrderWithOptionalEvents,
+
+    #[error("event {0} matches every input event, which requires AllowBroadSignature")]
+    BroadSignature(usize),
 }
 
+/// The pattern versions (`"Version"`) that can be parsed.
+pub const SUPPORTED_VERSIONS: &[&str] = &["0.2.0"];
+
 pub fn get_input_files(input_prefix: &str) -> (String, String, String) {
     let node_file = format!("{}_node.json", input_prefix);
     let edge_file = format!("{}_edge.json", input_prefix);
@@ -59,35 +108,87 @@ pub fn parse_json(json_obj: &Value) -> Result<Pattern, PatternParsingError> {
     let version = json_obj["Version"]
         .as_str()
         .ok_or(PatternParsingError::KeyError("Version"))?;
-    if version != "0.2.0" {
+    if !SUPPORTED_VERSIONS.contains(&version) {
         return Err(PatternParsingError::UnsupportedVersion(version.to_string()));
     }
 
     let use_regex = json_obj["UseRegex"].as_bool().unwrap_or(true);
+    let span_hosts = json_obj["SpanHosts"].as_bool().unwrap_or(false);
+    let event_uniqueness = json_obj["EventUniqueness"].as_bool().unwrap_or(true);
+    let allow_broad_signature = json_obj["AllowBroadSignature"].as_bool().unwrap_or(false);
 
     let entities_json = json_obj["Entities"]
         .as_array()
         .ok_or(PatternParsingError::KeyError("Entities"))?;
     let mut entities = parse_entities(entities_json)?;
+    let original_entity_ids = entities.iter().map(|e| e.id).collect();
     let entity_id2index = reassign_entity_id(&mut entities);
 
     let events_json = json_obj["Events"]
         .as_array()
         .ok_or(PatternParsingError::KeyError("Events"))?;
     let mut events = parse_events(events_json, &entity_id2index, &entities)?;
+    let has_optional_events = events.iter().any(|event| event.is_optional);
+    if events.iter().all(|event| event.is_optional) {
+        return Err(PatternParsingError::NoRequiredEvent);
+    }
+    check_aggregate_objects(&events)?;
+    check_broad_signatures(&events, use_regex, allow_broad_signature)?;
+    let original_event_ids = events.iter().map(|e| e.id).collect();
     let event_id2index = reassign_event_id(&mut events);
 
     let order = parse_order_relation(events_json, &event_id2index)?;
     if !order.is_valid() {
         return Err(PatternParsingError::DependencyCycle);
     }
+    parse_parent_gaps(events_json, &event_id2index, &mut events)?;
+    if let Some(constraints) = json_obj["Constraints"].as_array() {
+        parse_constraints(
+            constraints,
+            &entity_id2index,
+            &event_id2index,
+            &order,
+            &mut events,
+        )?;
+    }
 
-    Ok(Pattern {
+    let mut pattern = Pattern {
         use_regex,
         entities,
         events,
         order,
-    })
+        original_entity_ids,
+        original_event_ids,
+        join_plan: None,
+        window: WindowKind::Sliding,
+        window_boundary: WindowBoundary::Inclusive,
+        span_hosts,
+        event_uniqueness,
+    };
+
+    if !json_obj["JoinOrder"].is_null() {
+        // optional sub-patterns are always joined last, see `join_plan::build`
+        if has_optional_events {
+            return Err(PatternParsingError::JoinOrderWithOptionalEvents);
+        }
+        let merges = serde_json::from_value(json_obj["JoinOrder"].clone())
+            .map_err(|_| PatternParsingError::TypeError("JoinOrder"))?;
+        let sub_patterns = decompose(&pattern);
+        let plan = JoinPlan::from_merges(sub_patterns.len(), merges)?;
+        plan.check(&sub_patterns)?;
+        pattern.join_plan = Some(plan);
+    }
+
+    if !json_obj["Window"].is_null() {
+        pattern.window = serde_json::from_value(json_obj["Window"].clone())
+            .map_err(|_| PatternParsingError::TypeError("Window"))?;
+    }
+    if !json_obj["Window"]["Boundary"].is_null() {
+        pattern.window_boundary = serde_json::from_value(json_obj["Window"]["Boundary"].clone())
+            .map_err(|_| PatternParsingError::TypeError("Boundary"))?;
+    }
+
+    Ok(pattern)
 }
 
 fn parse_entities(entities_json: &[Value]) -> Result<Vec<PatternEntity>, PatternParsingError> {
@@ -96,11 +197,18 @@ fn parse_entities(entities_json: &[Value]) -> Result<Vec<PatternEntity>, Pattern
         let id = entity["ID"]
             .as_u64()
             .ok_or(PatternParsingError::KeyError("ID"))? as usize;
-        let signature = entity["Signature"]
-            .as_str()
-            .ok_or(PatternParsingError::KeyError("Signature"))?
-            .to_string();
-        entities.push(PatternEntity { id, signature });
+        let is_wildcard = entity["Wildcard"].as_bool().unwrap_or(false);
+        // the signature of a wildcard entity is ignored, and thus optional
+        let signature = match entity["Signature"].as_str() {
+            Some(signature) => signature.to_string(),
+            None if is_wildcard => String::new(),
+            None => return Err(PatternParsingError::KeyError("Signature")),
+        };
+        entities.push(PatternEntity {
+            id,
+            signature,
+            is_wildcard,
+        });
     }
 
     Ok(entities)
@@ -148,13 +256,22 @@ fn parse_events(
 
         let event_type = parse_event_type(event)?;
 
-        let signature = event["Signature"].as_str().unwrap_or_default().to_string();
+        let (signature, alternatives) = parse_event_signature(&event["Signature"])?;
         if event_type == PatternEventType::Flow && !signature.is_empty() {
             warn!("Signature on pattern event of type Flow will be ignored");
         } else if event_type != PatternEventType::Flow && signature.is_empty() {
             warn!("Empty signature detected, the matching behavior is undefined");
         }
 
+        let is_static = event["Static"].as_bool().unwrap_or(false);
+        if is_static && event_type != PatternEventType::Default {
+            return Err(PatternParsingError::InvalidStaticEvent(id));
+        }
+        let is_optional = event["Optional"].as_bool().unwrap_or(false);
+        if is_optional && is_static {
+            return Err(PatternParsingError::InvalidOptionalEvent(id));
+        }
+
         let subject_id = event["SubjectID"]
             .as_u64()
             .ok_or(PatternParsingError::KeyError("SubjectID"))? as usize;
@@ -173,14 +290,73 @@ fn parse_events(
             id,
             event_type,
             signature,
+            alternatives,
             subject: entities[*subject_idx].clone(),
             object: entities[*object_idx].clone(),
+            parent_gaps: vec![],
+            distinct_entities: vec![],
+            same_attributes: vec![],
+            simultaneous_events: vec![],
+            is_static,
+            is_optional,
         });
     }
 
     Ok(events)
 }
 
+/// Parse the `Signature` of an event, either a string or a non-empty array of alternative
+/// signatures, into the first signature and the other alternatives.
+fn parse_event_signature(signature: &Value) -> Result<(String, Vec<String>), PatternParsingError> {
+    let Some(alternatives) = signature.as_array() else {
+        let signature = signature.as_str().unwrap_or_default().to_string();
+        return Ok((signature, vec![]));
+    };
+    let mut alternatives = alternatives
+        .iter()
+        .map(|alternative| alternative.as_str().map(str::to_string))
+        .collect::<Option<Vec<_>>>()
+        .filter(|alternatives| !alternatives.is_empty())
+        .ok_or(PatternParsingError::TypeError("Signature"))?;
+    let signature = alternatives.remove(0);
+    Ok((signature, alternatives))
+}
+
+/// The object of an aggregate event stands for any of the objects it counts, so it must not be
+/// shared with other events.
+fn check_aggregate_objects(events: &[PatternEvent]) -> Result<(), PatternParsingError> {
+    for event in events {
+        if !matches!(event.event_type, PatternEventType::Aggregate { .. }) {
+            continue;
+        }
+        let object = &event.object;
+        let is_shared = event.subject == *object
+            || events.iter().any(|other| {
+                other.id != event.id && (other.subject == *object || other.object == *object)
+            });
+        if is_shared {
+            return Err(PatternParsingError::SharedAggregateObject(event.id));
+        }
+    }
+    Ok(())
+}
+
+/// Reject the events matching every input event (see [`PatternEvent::is_broad`]) unless `allow`
+/// is given by `AllowBroadSignature`, in which case they are only warned about.
+fn check_broad_signatures(
+    events: &[PatternEvent],
+    use_regex: bool,
+    allow: bool,
+) -> Result<(), PatternParsingError> {
+    for event in events.iter().filter(|event| event.is_broad(use_regex)) {
+        if !allow {
+            return Err(PatternParsingError::BroadSignature(event.id));
+        }
+        warn!("Event {} matches every input event", event.id);
+    }
+    Ok(())
+}
+
 fn parse_event_type(event_json: &Value) -> Result<PatternEventType, PatternParsingError> {
     let event_type = event_json["Type"].as_str();
     let event_type = match event_type {
@@ -210,6 +386,29 @@ fn parse_event_type(event_json: &Value) -> Result<PatternEventType, PatternParsi
             }
             PatternEventType::Flow
         }
+        Some("Aggregate") => {
+            let count = event_json["Count"]
+                .as_u64()
+                .ok_or(PatternParsingError::KeyError("Count"))? as u32;
+            let distinct_objects = event_json["DistinctObjects"].as_u64().unwrap_or(1) as u32;
+            if distinct_objects == 0 || count < distinct_objects {
+                let error = PatternParsingError::InvalidAggregate(count, distinct_objects);
+                return Err(error);
+            }
+            let within = match &event_json["WithinMs"] {
+                Value::Null => None,
+                within => Some(
+                    within
+                        .as_u64()
+                        .ok_or(PatternParsingError::KeyError("WithinMs"))?,
+                ),
+            };
+            PatternEventType::Aggregate {
+                count,
+                distinct_objects,
+                within,
+            }
+        }
         Some(unknow_type) => {
             return Err(PatternParsingError::UnknownEventType(
                 unknow_type.to_string(),
@@ -219,7 +418,10 @@ fn parse_event_type(event_json: &Value) -> Result<PatternEventType, PatternParsi
     Ok(event_type)
 }
 
-fn parse_order_relation(events: &[Value], event_id2index: &HashMap<usize, usize>) -> Result<OrderRelation, PatternParsingError> {
+fn parse_order_relation(
+    events: &[Value],
+    event_id2index: &HashMap<usize, usize>,
+) -> Result<OrderRelation, PatternParsingError> {
     let mut orel_edges = Vec::new();
 
     for event in events {
@@ -251,6 +453,279 @@ fn parse_order_relation(events: &[Value], event_id2index: &HashMap<usize, usize>
     Ok(graph.into())
 }
 
+/// Parse `ParentsMinGapMs` and `ParentsMaxGapMs` of the events, each of which is either a number
+/// for all the parents, or an array in the same order as `Parents` (`null` for no limit).
+fn parse_parent_gaps(
+    events_json: &[Value],
+    event_id2index: &HashMap<usize, usize>,
+    events: &mut [PatternEvent],
+) -> Result<(), PatternParsingError> {
+    for (event_json, event) in events_json.iter().zip(events.iter_mut()) {
+        let Some(parents) = event_json["Parents"].as_array() else {
+            continue;
+        };
+        let min_gaps = parse_gaps(event_json, "ParentsMinGapMs", parents.len())?;
+        let max_gaps = parse_gaps(event_json, "ParentsMaxGapMs", parents.len())?;
+
+        for ((parent, min), max) in parents.iter().zip(min_gaps).zip(max_gaps) {
+            if min.is_none() && max.is_none() {
+                continue;
+            }
+            let gap = TimeGap {
+                min: min.unwrap_or(0),
+                max,
+            };
+            if gap.max.is_some_and(|max| max < gap.min) {
+                let event_id = event_json["ID"].as_u64().unwrap_or_default() as usize;
+                return Err(PatternParsingError::InvalidTimeGap(event_id));
+            }
+
+            let parent_id = parent
+                .as_u64()
+                .ok_or(PatternParsingError::KeyError("Parents"))?
+                as usize;
+            let parent_idx = event_id2index
+                .get(&parent_id)
+                .ok_or(PatternParsingError::UndefinedEventId(parent_id))?;
+            event.parent_gaps.push((*parent_idx, gap));
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse the `Constraints` section, an array of
+///
+/// - `{"Type": "Distinct", "Entities": [a, b]}`, requiring `a` and `b` to match different input
+///   entities,
+/// - `{"Type": "SameAttribute", "Entity": a, "Column": c}`, requiring the input events binding
+///   `a` to have the same value at the payload column `c`, and
+/// - `{"Type": "Gap", "Events": [x, y], "MinGapMs": min, "MaxGapMs": max}`, requiring event `y`
+///   to start within `min` to `max` ms (both optional) after event `x` ends, where `x` must
+///   precede `y` through `Parents`, and
+/// - `{"Type": "Simultaneous", "Events": [x, y]}`, requiring events `x` and `y` to be matched by
+///   input events with the same timestamp, e.g. the records of a single system call,
+///
+/// where the entities are either `{"Entity": id}` or `{"Event": id, "Role": "Subject" | "Object"}`.
+/// Each entity constraint is stored in the events involving any of its entities, each gap
+/// constraint in `parent_gaps` of its later event, and each simultaneous constraint in both of
+/// its events.
+fn parse_constraints(
+    constraints: &[Value],
+    entity_id2index: &HashMap<usize, usize>,
+    event_id2index: &HashMap<usize, usize>,
+    order: &OrderRelation,
+    events: &mut [PatternEvent],
+) -> Result<(), PatternParsingError> {
+    for (i, constraint) in constraints.iter().enumerate() {
+        match constraint["Type"].as_str() {
+            Some("Distinct") => {
+                parse_distinct(i, constraint, entity_id2index, event_id2index, events)?
+            }
+            Some("SameAttribute") => {
+                parse_same_attribute(constraint, entity_id2index, event_id2index, events)?
+            }
+            Some("Gap") => parse_gap(i, constraint, event_id2index, order, events)?,
+            Some("Simultaneous") => parse_simultaneous(constraint, event_id2index, events)?,
+            _ => return Err(PatternParsingError::TypeError("Constraints")),
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_distinct(
+    i: usize,
+    constraint: &Value,
+    entity_id2index: &HashMap<usize, usize>,
+    event_id2index: &HashMap<usize, usize>,
+    events: &mut [PatternEvent],
+) -> Result<(), PatternParsingError> {
+    let entities = match constraint["Entities"].as_array() {
+        Some(entities) if entities.len() == 2 => entities
+            .iter()
+            .map(|entity| parse_constraint_entity(entity, entity_id2index, event_id2index, events))
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(PatternParsingError::KeyError("Entities")),
+    };
+    let pair = (entities[0], entities[1]);
+    if pair.0 == pair.1 {
+        return Err(PatternParsingError::UnsatisfiableConstraint(i));
+    }
+
+    for event in events.iter_mut() {
+        let involved = [event.subject.id, event.object.id];
+        if involved.contains(&pair.0) || involved.contains(&pair.1) {
+            event.distinct_entities.push(pair);
+        }
+    }
+    Ok(())
+}
+
+fn parse_same_attribute(
+    constraint: &Value,
+    entity_id2index: &HashMap<usize, usize>,
+    event_id2index: &HashMap<usize, usize>,
+    events: &mut [PatternEvent],
+) -> Result<(), PatternParsingError> {
+    let entity = parse_constraint_entity(
+        &constraint["Entity"],
+        entity_id2index,
+        event_id2index,
+        events,
+    )?;
+    let column = constraint["Column"]
+        .as_u64()
+        .ok_or(PatternParsingError::KeyError("Column"))? as usize;
+    for event in events.iter_mut() {
+        if event.subject.id == entity || event.object.id == entity {
+            event.same_attributes.push((entity, column));
+        }
+    }
+    Ok(())
+}
+
+/// The gap is narrowed to fit the one given by `Parents`, if the first event is a parent.
+fn parse_gap(
+    i: usize,
+    constraint: &Value,
+    event_id2index: &HashMap<usize, usize>,
+    order: &OrderRelation,
+    events: &mut [PatternEvent],
+) -> Result<(), PatternParsingError> {
+    let (first, second) = parse_event_pair(constraint, event_id2index)?;
+    if !order.must_precede(first, second) {
+        return Err(PatternParsingError::UnorderedGapEvents(i));
+    }
+
+    let parse_bound = |key| match &constraint[key] {
+        Value::Null => Ok(None),
+        bound => bound
+            .as_u64()
+            .map(Some)
+            .ok_or(PatternParsingError::TypeError(key)),
+    };
+    let min = parse_bound("MinGapMs")?.unwrap_or(0);
+    let max = parse_bound("MaxGapMs")?;
+    let event = &mut events[second];
+    let gap = event.gap_from(first);
+    let gap = TimeGap {
+        min: gap.min.max(min),
+        max: match (gap.max, max) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        },
+    };
+    if gap.max.is_some_and(|max| max < gap.min) {
+        let event_id = constraint["Events"][1].as_u64().unwrap_or_default() as usize;
+        return Err(PatternParsingError::InvalidTimeGap(event_id));
+    }
+    event
+        .parent_gaps
+        .retain(|(parent_id, _)| *parent_id != first);
+    event.parent_gaps.push((first, gap));
+    Ok(())
+}
+
+/// Both events must be default-typed and not static, so that each of them has a single timestamp
+/// from the input stream.
+fn parse_simultaneous(
+    constraint: &Value,
+    event_id2index: &HashMap<usize, usize>,
+    events: &mut [PatternEvent],
+) -> Result<(), PatternParsingError> {
+    let (first, second) = parse_event_pair(constraint, event_id2index)?;
+    for (i, idx) in [first, second].into_iter().enumerate() {
+        let event = &events[idx];
+        if event.event_type != PatternEventType::Default || event.is_static {
+            let event_id = constraint["Events"][i].as_u64().unwrap_or_default() as usize;
+            return Err(PatternParsingError::InvalidSimultaneousEvent(event_id));
+        }
+    }
+    if first != second {
+        events[first].simultaneous_events.push(second);
+        events[second].simultaneous_events.push(first);
+    }
+    Ok(())
+}
+
+/// Returns the (reassigned) ids of the two events in `Events` of a constraint.
+fn parse_event_pair(
+    constraint: &Value,
+    event_id2index: &HashMap<usize, usize>,
+) -> Result<(usize, usize), PatternParsingError> {
+    let pair = match constraint["Events"].as_array() {
+        Some(pair) if pair.len() == 2 => pair
+            .iter()
+            .map(|event_id| {
+                let event_id = event_id
+                    .as_u64()
+                    .ok_or(PatternParsingError::KeyError("Events"))?
+                    as usize;
+                event_id2index
+                    .get(&event_id)
+                    .copied()
+                    .ok_or(PatternParsingError::UndefinedEventId(event_id))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(PatternParsingError::KeyError("Events")),
+    };
+    Ok((pair[0], pair[1]))
+}
+
+/// Returns the (reassigned) id of the pattern entity referred to in a constraint.
+fn parse_constraint_entity(
+    entity_json: &Value,
+    entity_id2index: &HashMap<usize, usize>,
+    event_id2index: &HashMap<usize, usize>,
+    events: &[PatternEvent],
+) -> Result<usize, PatternParsingError> {
+    if let Some(entity_id) = entity_json["Entity"].as_u64() {
+        let entity_id = entity_id as usize;
+        return entity_id2index
+            .get(&entity_id)
+            .copied()
+            .ok_or(PatternParsingError::UndefinedEntityId(entity_id));
+    }
+
+    let event_id = entity_json["Event"]
+        .as_u64()
+        .ok_or(PatternParsingError::KeyError("Event"))? as usize;
+    let event = event_id2index
+        .get(&event_id)
+        .map(|idx| &events[*idx])
+        .ok_or(PatternParsingError::UndefinedEventId(event_id))?;
+    match entity_json["Role"].as_str() {
+        Some("Subject") => Ok(event.subject.id),
+        Some("Object") => Ok(event.object.id),
+        _ => Err(PatternParsingError::KeyError("Role")),
+    }
+}
+
+fn parse_gaps(
+    event_json: &Value,
+    key: &'static str,
+    num_parents: usize,
+) -> Result<Vec<Option<u64>>, PatternParsingError> {
+    match &event_json[key] {
+        Value::Null => Ok(vec![None; num_parents]),
+        Value::Array(gaps) if gaps.len() == num_parents => gaps
+            .iter()
+            .map(|gap| match gap {
+                Value::Null => Ok(None),
+                _ => gap
+                    .as_u64()
+                    .map(Some)
+                    .ok_or(PatternParsingError::TypeError(key)),
+            })
+            .collect(),
+        gap => gap
+            .as_u64()
+            .map(|gap| vec![Some(gap); num_parents])
+            .ok_or(PatternParsingError::TypeError(key)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::pattern::PatternEventType;
@@ -319,6 +794,8 @@ mod tests {
         let pattern = parse_json(&json_obj).unwrap();
 
         assert!(!pattern.use_regex);
+        assert!(!pattern.span_hosts);
+        assert!(pattern.event_uniqueness);
         assert_eq!(pattern.entities.len(), 3);
 
         let correct_events = [
@@ -326,27 +803,45 @@ mod tests {
                 id: 0,
                 event_type: PatternEventType::Default,
                 signature: "aaa".to_string(),
+                alternatives: vec![],
                 subject: PatternEntity {
                     id: 0,
                     signature: "".to_string(),
+                    is_wildcard: false,
                 },
                 object: PatternEntity {
                     id: 1,
                     signature: "".to_string(),
+                    is_wildcard: false,
                 },
+                parent_gaps: vec![],
+                distinct_entities: vec![],
+                same_attributes: vec![],
+                simultaneous_events: vec![],
+                is_static: false,
+                is_optional: false,
             },
             PatternEvent {
                 id: 1,
                 event_type: PatternEventType::Default,
                 signature: "bbb".to_string(),
+                alternatives: vec![],
                 subject: PatternEntity {
                     id: 1,
                     signature: "".to_string(),
+                    is_wildcard: false,
                 },
                 object: PatternEntity {
                     id: 2,
                     signature: "".to_string(),
+                    is_wildcard: false,
                 },
+                parent_gaps: vec![],
+                distinct_entities: vec![],
+                same_attributes: vec![],
+                simultaneous_events: vec![],
+                is_static: false,
+                is_optional: false,
             },
         ];
         assert_eq!(pattern.events, correct_events);
@@ -356,6 +851,290 @@ mod tests {
         assert!(pattern.order.get_next(1).next().is_none());
     }
 
+    #[test]
+    fn test_parse_parent_gaps() {
+        let events = |min_gaps: Value, max_gaps: Value| {
+            json!({
+                "Version": "0.2.0",
+                "Entities": [{ "ID": 0, "Signature": "" }, { "ID": 1, "Signature": "" }],
+                "Events": [
+                    { "ID": 5, "Signature": "a", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 6, "Signature": "b", "SubjectID": 0, "ObjectID": 1 },
+                    {
+                        "ID": 7,
+                        "Signature": "c",
+                        "SubjectID": 0,
+                        "ObjectID": 1,
+                        "Parents": [5, 6],
+                        "ParentsMinGapMs": min_gaps,
+                        "ParentsMaxGapMs": max_gaps
+                    }
+                ]
+            })
+        };
+
+        let pattern = parse_json(&events(json!(100), json!([2000, null]))).unwrap();
+        assert_eq!(
+            pattern.events[2].parent_gaps,
+            [
+                (
+                    0,
+                    TimeGap {
+                        min: 100,
+                        max: Some(2000)
+                    }
+                ),
+                (
+                    1,
+                    TimeGap {
+                        min: 100,
+                        max: None
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            pattern.events[2].gap_from(1),
+            TimeGap {
+                min: 100,
+                max: None
+            }
+        );
+        assert!(pattern.events[0].parent_gaps.is_empty());
+
+        let pattern = parse_json(&events(Value::Null, Value::Null)).unwrap();
+        assert!(pattern.events[2].parent_gaps.is_empty());
+
+        assert!(matches!(
+            parse_json(&events(json!(3000), json!(2000))),
+            Err(PatternParsingError::InvalidTimeGap(7))
+        ));
+        assert!(matches!(
+            parse_json(&events(json!([1]), Value::Null)),
+            Err(PatternParsingError::TypeError("ParentsMinGapMs"))
+        ));
+    }
+
+    #[test]
+    fn test_parse_constraints() {
+        let with_constraints = |constraints: Value| {
+            json!({
+                "Version": "0.2.0",
+                "Entities": [
+                    { "ID": 5, "Signature": "" },
+                    { "ID": 6, "Signature": "" },
+                    { "ID": 7, "Signature": "" }
+                ],
+                "Events": [
+                    { "ID": 0, "Signature": "a", "SubjectID": 5, "ObjectID": 6 },
+                    { "ID": 1, "Signature": "b", "SubjectID": 6, "ObjectID": 7 }
+                ],
+                "Constraints": constraints
+            })
+        };
+
+        let pattern = parse_json(&with_constraints(json!([{
+            "Type": "Distinct",
+            "Entities": [{ "Event": 0, "Role": "Subject" }, { "Entity": 7 }]
+        }])))
+        .unwrap();
+        assert_eq!(pattern.events[0].distinct_entities, [(0, 2)]);
+        assert_eq!(pattern.events[1].distinct_entities, [(0, 2)]);
+
+        let pattern = parse_json(&with_constraints(json!([{
+            "Type": "Distinct",
+            "Entities": [{ "Event": 0, "Role": "Subject" }, { "Event": 0, "Role": "Object" }]
+        }])))
+        .unwrap();
+        assert_eq!(pattern.events[0].distinct_entities, [(0, 1)]);
+        assert_eq!(pattern.events[1].distinct_entities, [(0, 1)]);
+
+        assert!(matches!(
+            parse_json(&with_constraints(json!([{
+                "Type": "Distinct",
+                "Entities": [{ "Event": 0, "Role": "Object" }, { "Event": 1, "Role": "Subject" }]
+            }]))),
+            Err(PatternParsingError::UnsatisfiableConstraint(0))
+        ));
+        assert!(matches!(
+            parse_json(&with_constraints(json!([{
+                "Type": "Distinct",
+                "Entities": [{ "Event": 3, "Role": "Object" }, { "Entity": 5 }]
+            }]))),
+            Err(PatternParsingError::UndefinedEventId(3))
+        ));
+
+        let pattern = parse_json(&with_constraints(json!([{
+            "Type": "SameAttribute",
+            "Entity": { "Entity": 6 },
+            "Column": 2
+        }])))
+        .unwrap();
+        assert_eq!(pattern.events[0].same_attributes, [(1, 2)]);
+        assert_eq!(pattern.events[1].same_attributes, [(1, 2)]);
+        assert!(pattern.events[0].distinct_entities.is_empty());
+        assert!(matches!(
+            parse_json(&with_constraints(json!([{
+                "Type": "SameAttribute",
+                "Entity": { "Entity": 5 }
+            }]))),
+            Err(PatternParsingError::KeyError("Column"))
+        ));
+    }
+
+    #[test]
+    fn test_parse_gap_constraints() {
+        let with_gap = |gap: Value| {
+            json!({
+                "Version": "0.2.0",
+                "Entities": [
+                    { "ID": 0, "Signature": "" },
+                    { "ID": 1, "Signature": "" },
+                    { "ID": 2, "Signature": "" }
+                ],
+                "Events": [
+                    { "ID": 3, "Signature": "a", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 4, "Signature": "b", "SubjectID": 1, "ObjectID": 2, "Parents": [3] },
+                    {
+                        "ID": 5, "Signature": "c", "SubjectID": 2, "ObjectID": 0, "Parents": [4],
+                        "ParentsMaxGapMs": 500
+                    }
+                ],
+                "Constraints": [gap]
+            })
+        };
+
+        let pattern = parse_json(&with_gap(json!({
+            "Type": "Gap", "Events": [3, 5], "MinGapMs": 100, "MaxGapMs": 2000
+        })))
+        .unwrap();
+        assert_eq!(
+            pattern.events[2].gap_from(0),
+            TimeGap {
+                min: 100,
+                max: Some(2000)
+            }
+        );
+        // narrowed to fit the gap given by `Parents`
+        let pattern = parse_json(&with_gap(json!({
+            "Type": "Gap", "Events": [4, 5], "MaxGapMs": 1000
+        })))
+        .unwrap();
+        assert_eq!(pattern.events[2].parent_gaps.len(), 1);
+        assert_eq!(pattern.events[2].gap_from(1).max, Some(500));
+
+        assert!(matches!(
+            parse_json(&with_gap(json!({ "Type": "Gap", "Events": [5, 3] }))),
+            Err(PatternParsingError::UnorderedGapEvents(0))
+        ));
+        assert!(matches!(
+            parse_json(&with_gap(json!({
+                "Type": "Gap", "Events": [4, 5], "MinGapMs": 600
+            }))),
+            Err(PatternParsingError::InvalidTimeGap(5))
+        ));
+    }
+
+    #[test]
+    fn test_parse_simultaneous_constraints() {
+        let with_constraint = |constraint: Value| {
+            json!({
+                "Version": "0.2.0",
+                "Entities": [
+                    { "ID": 0, "Signature": "" },
+                    { "ID": 1, "Signature": "" },
+                    { "ID": 2, "Signature": "" }
+                ],
+                "Events": [
+                    { "ID": 3, "Signature": "a", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 4, "Signature": "b", "SubjectID": 1, "ObjectID": 2 },
+                    { "ID": 5, "Type": "Flow", "SubjectID": 2, "ObjectID": 0 }
+                ],
+                "Constraints": [constraint]
+            })
+        };
+
+        let pattern = parse_json(&with_constraint(
+            json!({ "Type": "Simultaneous", "Events": [3, 4] }),
+        ))
+        .unwrap();
+        assert_eq!(pattern.events[0].simultaneous_events, [1]);
+        assert_eq!(pattern.events[1].simultaneous_events, [0]);
+        assert!(pattern.events[2].simultaneous_events.is_empty());
+
+        assert!(matches!(
+            parse_json(&with_constraint(
+                json!({ "Type": "Simultaneous", "Events": [3, 5] })
+            )),
+            Err(PatternParsingError::InvalidSimultaneousEvent(5))
+        ));
+        assert!(matches!(
+            parse_json(&with_constraint(
+                json!({ "Type": "Simultaneous", "Events": [3] })
+            )),
+            Err(PatternParsingError::KeyError("Events"))
+        ));
+    }
+
+    #[test]
+    fn test_parse_window() {
+        let with_window = |window: Value| {
+            json!({
+                "Version": "0.2.0",
+                "Entities": [{ "ID": 0, "Signature": "" }, { "ID": 1, "Signature": "" }],
+                "Events": [{ "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1 }],
+                "Window": window
+            })
+        };
+
+        let pattern = parse_json(&with_window(Value::Null)).unwrap();
+        assert_eq!(pattern.window, WindowKind::Sliding);
+        let pattern = parse_json(&with_window(json!({ "Type": "Tumbling" }))).unwrap();
+        assert_eq!(pattern.window, WindowKind::Tumbling);
+        let pattern =
+            parse_json(&with_window(json!({ "Type": "Session", "GapMs": 60000 }))).unwrap();
+        assert_eq!(pattern.window, WindowKind::Session { gap: Some(60000) });
+        assert_eq!(pattern.window_boundary, WindowBoundary::Inclusive);
+        let pattern = parse_json(&with_window(
+            json!({ "Type": "Sliding", "Boundary": "Exclusive" }),
+        ))
+        .unwrap();
+        assert_eq!(pattern.window, WindowKind::Sliding);
+        assert_eq!(pattern.window_boundary, WindowBoundary::Exclusive);
+
+        assert!(matches!(
+            parse_json(&with_window(json!({ "Type": "Hopping" }))),
+            Err(PatternParsingError::TypeError("Window"))
+        ));
+        assert!(matches!(
+            parse_json(&with_window(
+                json!({ "Type": "Sliding", "Boundary": "Open" })
+            )),
+            Err(PatternParsingError::TypeError("Boundary"))
+        ));
+    }
+
+    #[test]
+    fn test_parse_event_signature() {
+        assert_eq!(
+            parse_event_signature(&json!("read")).unwrap(),
+            ("read".to_string(), vec![])
+        );
+        assert_eq!(
+            parse_event_signature(&json!(["read", "pread64", "readv"])).unwrap(),
+            (
+                "read".to_string(),
+                vec!["pread64".to_string(), "readv".to_string()]
+            )
+        );
+        for invalid in [json!([]), json!(["read", 0])] {
+            assert!(matches!(
+                parse_event_signature(&invalid),
+                Err(PatternParsingError::TypeError("Signature"))
+            ));
+        }
+    }
+
     #[test]
     fn test_parse_event_type() {
         assert_eq!(
@@ -378,4 +1157,172 @@ mod tests {
         assert!(parse_event_type(&json!({"Type": "Frequency"})).is_err());
         assert!(parse_event_type(&json!({"Frequency": 0})).is_err());
     }
+
+    #[test]
+    fn test_parse_aggregate() {
+        assert_eq!(
+            parse_event_type(&json!({"Type": "Aggregate", "Count": 10, "WithinMs": 5000})).unwrap(),
+            PatternEventType::Aggregate {
+                count: 10,
+                distinct_objects: 1,
+                within: Some(5000)
+            }
+        );
+        assert!(matches!(
+            parse_event_type(&json!({"Type": "Aggregate", "Count": 2, "DistinctObjects": 3})),
+            Err(PatternParsingError::InvalidAggregate(2, 3))
+        ));
+        assert!(parse_event_type(&json!({"Type": "Aggregate"})).is_err());
+
+        let with_events = |events: Value| {
+            json!({
+                "Version": "0.2.0",
+                "Entities": [
+                    { "ID": 0, "Signature": "" },
+                    { "ID": 1, "Signature": "" },
+                    { "ID": 2, "Signature": "" }
+                ],
+                "Events": events
+            })
+        };
+        let scan = json!({
+            "ID": 1, "Signature": "connect", "SubjectID": 0, "ObjectID": 2,
+            "Type": "Aggregate", "Count": 10, "DistinctObjects": 5, "Parents": [0]
+        });
+        let pattern = parse_json(&with_events(json!([
+            { "ID": 0, "Signature": "exec", "SubjectID": 1, "ObjectID": 0 },
+            scan
+        ])))
+        .unwrap();
+        assert_eq!(decompose(&pattern).len(), 2);
+
+        assert!(matches!(
+            parse_json(&with_events(json!([
+                { "ID": 0, "Signature": "exec", "SubjectID": 1, "ObjectID": 2 },
+                scan
+            ]))),
+            Err(PatternParsingError::SharedAggregateObject(1))
+        ));
+    }
+
+    #[test]
+    fn test_broad_signature() {
+        let with_signatures = |event: Value, entity: &str, allow: bool| {
+            json!({
+                "Version": "0.2.0",
+                "AllowBroadSignature": allow,
+                "Entities": [{ "ID": 0, "Signature": entity }, { "ID": 1, "Signature": entity }],
+                "Events": [
+                    { "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 1, "Signature": event, "SubjectID": 1, "ObjectID": 0 }
+                ]
+            })
+        };
+
+        for (event, entity) in [
+            (json!(".*"), ".*"),
+            (json!(".+"), "[^:]*"),
+            (json!(["read", "|x"]), "proc"),
+        ] {
+            assert!(matches!(
+                parse_json(&with_signatures(event.clone(), entity, false)),
+                Err(PatternParsingError::BroadSignature(1))
+            ));
+            assert!(parse_json(&with_signatures(event, entity, true)).is_ok());
+        }
+        for (event, entity) in [(json!(".*"), "proc"), (json!("x*"), ".*"), (json!(""), "")] {
+            assert!(parse_json(&with_signatures(event, entity, false)).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_parse_static() {
+        let with_static = |event: Value| {
+            json!({
+                "Version": "0.2.0",
+                "Entities": [{ "ID": 0, "Signature": "" }, { "ID": 1, "Signature": "" }],
+                "Events": [{ "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1 }, event]
+            })
+        };
+
+        let pattern = parse_json(&with_static(json!({
+            "ID": 1, "Signature": "b", "SubjectID": 1, "ObjectID": 0, "Static": true
+        })))
+        .unwrap();
+        assert!(!pattern.events[0].is_static);
+        assert!(pattern.events[1].is_static);
+
+        assert!(matches!(
+            parse_json(&with_static(json!({
+                "ID": 1, "Type": "Flow", "SubjectID": 1, "ObjectID": 0, "Static": true
+            }))),
+            Err(PatternParsingError::InvalidStaticEvent(1))
+        ));
+    }
+
+    #[test]
+    fn test_parse_optional() {
+        let with_events = |events: Value| {
+            json!({
+                "Version": "0.2.0",
+                "Entities": [{ "ID": 0, "Signature": "" }, { "ID": 1, "Signature": "" }],
+                "Events": events
+            })
+        };
+        let event = |id: u64, optional: bool| {
+            json!({
+                "ID": id, "Signature": "a", "SubjectID": 0, "ObjectID": 1, "Optional": optional
+            })
+        };
+
+        let pattern = parse_json(&with_events(json!([event(0, false), event(1, true)]))).unwrap();
+        assert!(!pattern.events[0].is_optional);
+        assert!(pattern.events[1].is_optional);
+
+        assert!(matches!(
+            parse_json(&with_events(json!([event(0, true), event(1, true)]))),
+            Err(PatternParsingError::NoRequiredEvent)
+        ));
+        let mut static_event = event(1, true);
+        static_event["Static"] = json!(true);
+        assert!(matches!(
+            parse_json(&with_events(json!([event(0, false), static_event]))),
+            Err(PatternParsingError::InvalidOptionalEvent(1))
+        ));
+        let mut json_obj = with_events(json!([event(0, false), event(1, true)]));
+        json_obj["JoinOrder"] = json!([[0, 1]]);
+        assert!(matches!(
+            parse_json(&json_obj),
+            Err(PatternParsingError::JoinOrderWithOptionalEvents)
+        ));
+    }
+
+    #[test]
+    fn test_parse_wildcard() {
+        let with_entities = |entities: Value| {
+            json!({
+                "Version": "0.2.0",
+                "Entities": entities,
+                "Events": [{ "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1 }]
+            })
+        };
+
+        // the signature of a wildcard is optional
+        let pattern = parse_json(&with_entities(json!([
+            { "ID": 0, "Signature": "bash" },
+            { "ID": 1, "Wildcard": true }
+        ])))
+        .unwrap();
+        assert!(!pattern.entities[0].is_wildcard);
+        assert!(pattern.entities[1].is_wildcard);
+        assert_eq!(pattern.events[0].wildcards(), (false, true));
+
+        assert!(matches!(
+            parse_json(&with_entities(json!([
+                { "ID": 0, "Signature": "bash" },
+                { "ID": 1, "Wildcard": false }
+            ]))),
+            Err(PatternParsingError::KeyError("Signature"))
+        ));
+    }
 }
@@ -0,0 +1,218 @@
+use super::parser::{parse_json, SUPPORTED_VERSIONS};
+use super::{Pattern, PatternParsingError};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// The file in a tar rule pack holding the pack metadata.
+const METADATA_FILE: &str = "pack.json";
+
+/// A rule of a [`RulePack`], along with its loading result.
+#[derive(Debug)]
+pub struct Rule {
+    pub name: String,
+    pub pattern: Result<Pattern, PatternParsingError>,
+}
+
+/// A bundle of many patterns (rules) with the pack metadata, in a single json file:
+///
+/// ```json
+/// {
+///     "Name": "baseline",
+///     "PackVersion": "2024.06",
+///     "Version": "0.2.0",
+///     "Rules": {
+///         "SP6": { "Entities": [...], "Events": [...] },
+///         "SP7": { "Entities": [...], "Events": [...] }
+///     }
+/// }
+/// ```
+///
+/// or in a tar file, where `pack.json` holds the metadata and every other `.json` file is a rule
+/// named after its file stem.
+///
+/// `"Version"` is the pattern version of the rules, which must be supported, otherwise the whole
+/// pack is rejected. A rule may still give its own `"Version"`. The other errors only fail the
+/// rules they are in, see [`Rule::pattern`].
+#[derive(Debug)]
+pub struct RulePack {
+    /// The `"Name"` of the pack, or the pack file name without the extension.
+    pub name: String,
+    /// The `"PackVersion"`, the version of the rules in the pack, if any.
+    pub pack_version: Option<String>,
+    /// The pattern version of the rules.
+    pub version: String,
+    pub rules: Vec<Rule>,
+}
+
+impl RulePack {
+    /// Load the rule pack at `pack_file`, which is a tar file if it ends with `.tar`.
+    pub fn load(pack_file: &str) -> Result<Self, PatternParsingError> {
+        let path = Path::new(pack_file);
+        let default_name = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let file = File::open(path)?;
+        if path.extension().is_some_and(|ext| ext == "tar") {
+            Self::parse_tar(file, &default_name)
+        } else {
+            Self::parse_json(&serde_json::from_reader(file)?, &default_name)
+        }
+    }
+
+    fn parse_json(json_obj: &Value, default_name: &str) -> Result<Self, PatternParsingError> {
+        let mut pack = Self::parse_metadata(json_obj, default_name)?;
+        let rules = json_obj["Rules"]
+            .as_object()
+            .ok_or(PatternParsingError::KeyError("Rules"))?;
+        for (name, rule) in rules {
+            pack.add_rule(name.clone(), Ok(rule.clone()));
+        }
+        Ok(pack)
+    }
+
+    fn parse_tar(reader: impl Read, default_name: &str) -> Result<Self, PatternParsingError> {
+        let mut metadata = None;
+        let mut rules = vec![];
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?.into_owned();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let content = serde_json::from_reader::<_, Value>(entry);
+            if path.file_name().is_some_and(|name| name == METADATA_FILE) {
+                metadata = Some(content?);
+            } else {
+                let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+                rules.push((name, content.map_err(PatternParsingError::from)));
+            }
+        }
+
+        let metadata = metadata.ok_or(PatternParsingError::KeyError(METADATA_FILE))?;
+        let mut pack = Self::parse_metadata(&metadata, default_name)?;
+        for (name, rule) in rules {
+            pack.add_rule(name, rule);
+        }
+        Ok(pack)
+    }
+
+    fn parse_metadata(json_obj: &Value, default_name: &str) -> Result<Self, PatternParsingError> {
+        let version = json_obj["Version"]
+            .as_str()
+            .ok_or(PatternParsingError::KeyError("Version"))?;
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(PatternParsingError::UnsupportedVersion(version.to_string()));
+        }
+        Ok(Self {
+            name: json_obj["Name"]
+                .as_str()
+                .unwrap_or(default_name)
+                .to_string(),
+            pack_version: json_obj["PackVersion"].as_str().map(str::to_string),
+            version: version.to_string(),
+            rules: vec![],
+        })
+    }
+
+    /// Parse the rule `name`, whose `"Version"` defaults to that of the pack.
+    fn add_rule(&mut self, name: String, rule: Result<Value, PatternParsingError>) {
+        let pattern = rule.and_then(|mut rule| {
+            if let Some(rule) = rule.as_object_mut() {
+                let version = Value::String(self.version.clone());
+                rule.entry("Version").or_insert(version);
+            }
+            parse_json(&rule)
+        });
+        self.rules.push(Rule { name, pattern });
+    }
+
+    /// The rules loaded successfully, along with their names.
+    pub fn patterns(&self) -> impl Iterator<Item = (&str, &Pattern)> {
+        self.rules
+            .iter()
+            .filter_map(|rule| Some((rule.name.as_str(), rule.pattern.as_ref().ok()?)))
+    }
+
+    /// The number of rules failed to load.
+    pub fn num_failed(&self) -> usize {
+        self.rules
+            .iter()
+            .filter(|rule| rule.pattern.is_err())
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(signature: &str) -> Value {
+        json!({
+            "Entities": [{ "ID": 0, "Signature": "a" }, { "ID": 1, "Signature": "b" }],
+            "Events": [{ "ID": 0, "Signature": signature, "SubjectID": 0, "ObjectID": 1 }]
+        })
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let mut invalid = rule("x");
+        invalid["Events"][0]["ObjectID"] = json!(2);
+        let json_obj = json!({
+            "Name": "baseline",
+            "PackVersion": "2024.06",
+            "Version": "0.2.0",
+            "Rules": { "read": rule("read"), "undefined": invalid }
+        });
+
+        let pack = RulePack::parse_json(&json_obj, "pack").unwrap();
+        assert_eq!(pack.name, "baseline");
+        assert_eq!(pack.pack_version.as_deref(), Some("2024.06"));
+        assert_eq!(pack.num_failed(), 1);
+        assert!(matches!(
+            pack.rules[1].pattern,
+            Err(PatternParsingError::UndefinedEntityId(2))
+        ));
+        let patterns: Vec<_> = pack.patterns().collect();
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].0, "read");
+        assert_eq!(patterns[0].1.events[0].signature, "read");
+
+        let unsupported = json!({ "Version": "0.1.0", "Rules": {} });
+        assert!(matches!(
+            RulePack::parse_json(&unsupported, "pack"),
+            Err(PatternParsingError::UnsupportedVersion(version)) if version == "0.1.0"
+        ));
+    }
+
+    #[test]
+    fn test_parse_tar() {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut append = |path: &str, content: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content).unwrap();
+        };
+        append("rules/write.json", rule("write").to_string().as_bytes());
+        append("rules/broken.json", b"{");
+        append("README.md", b"not a rule");
+        append("pack.json", br#"{ "Version": "0.2.0" }"#);
+        let archive = builder.into_inner().unwrap();
+
+        let pack = RulePack::parse_tar(archive.as_slice(), "pack").unwrap();
+        assert_eq!(pack.name, "pack");
+        assert_eq!(pack.pack_version, None);
+        let names: Vec<_> = pack.rules.iter().map(|rule| rule.name.as_str()).collect();
+        assert_eq!(names, ["write", "broken"]);
+        assert!(pack.rules[0].pattern.is_ok());
+        assert!(matches!(
+            pack.rules[1].pattern,
+            Err(PatternParsingError::SerdeError(_))
+        ));
+    }
+}
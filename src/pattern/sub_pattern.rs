@@ -1,18 +1,184 @@
-use crate::pattern::{Pattern, PatternEvent};
+use crate::pattern::{Pattern, PatternEvent, PatternEventType};
+use thiserror::Error;
+
+/// Why a (custom) decomposition cannot be fed to the Composition and Join layers, see
+/// [`validate_decomposition`].
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum DecompositionError {
+    #[error("undefined event id: {0}")]
+    UndefinedEventId(usize),
+    #[error("sub-pattern {0} is empty")]
+    EmptySubPattern(usize),
+    #[error("event {1} does not have to precede event {2} in sub-pattern {0}")]
+    UnorderedEvents(usize, usize, usize),
+    #[error("optional or aggregate event {1} must form sub-pattern {0} by itself")]
+    StandaloneEvent(usize, usize),
+    #[error("event {0} is in more than one sub-pattern")]
+    DuplicateEvent(usize),
+    #[error("event {0} is not in any sub-pattern")]
+    MissingEvent(usize),
+    #[error("sub-pattern {1} is at index {0}, reassign the ids")]
+    MismatchedId(usize, usize),
+    #[error("optional sub-pattern {0} is followed by a non-optional one, reassign the ids")]
+    MisplacedOptional(usize),
+}
 
-/// Decomposed sub-pattern of a behavioral pattern
-#[derive(Debug)]
+/// Decomposed sub-pattern of a behavioral pattern.
+///
+/// The events of a sub-pattern are matched one after another by the Composition layer, so each
+/// of them must precede the next one in the order relation of the pattern. Besides
+/// [`decompose`], a decomposition can be built by hand, or by merging and splitting the
+/// sub-patterns of another one, e.g. to experiment with the decomposition strategy. Check it by
+/// [`validate_decomposition`] before feeding it to the `CompositionLayer` and `JoinLayer`.
+#[derive(Debug, Clone)]
 pub struct SubPattern<'a> {
-    /// sub-pattern id, given after decomposition
+    /// sub-pattern id, given after decomposition, see [`reassign_ids`]
     pub id: usize,
     pub events: Vec<&'a PatternEvent>,
 }
 
-impl<'a> SubPattern<'a> {}
+impl<'a> SubPattern<'a> {
+    pub fn new(id: usize, events: Vec<&'a PatternEvent>) -> Self {
+        Self { id, events }
+    }
+
+    /// Build the sub-pattern of the events of `pattern` with the given ids, in that order.
+    pub fn from_event_ids(
+        pattern: &'a Pattern,
+        id: usize,
+        event_ids: &[usize],
+    ) -> Result<Self, DecompositionError> {
+        let events = event_ids
+            .iter()
+            .map(|&event_id| {
+                pattern
+                    .events
+                    .get(event_id)
+                    .ok_or(DecompositionError::UndefinedEventId(event_id))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(id, events))
+    }
+
+    /// Whether this sub-pattern consists of an optional event, see `PatternEvent::is_optional`.
+    pub fn is_optional(&self) -> bool {
+        self.events.iter().all(|event| event.is_optional)
+    }
+
+    /// Check that this sub-pattern is not empty, each event must precede the next one in the
+    /// order relation of `pattern`, and that an optional or aggregate event is by itself.
+    pub fn validate(&self, pattern: &Pattern) -> Result<(), DecompositionError> {
+        if self.events.is_empty() {
+            return Err(DecompositionError::EmptySubPattern(self.id));
+        }
+        if self.events.len() > 1 {
+            let standalone = self.events.iter().find(|event| {
+                event.is_optional || matches!(event.event_type, PatternEventType::Aggregate { .. })
+            });
+            if let Some(event) = standalone {
+                return Err(DecompositionError::StandaloneEvent(self.id, event.id));
+            }
+        }
+        for pair in self.events.windows(2) {
+            if !pattern.order.must_precede(pair[0].id, pair[1].id) {
+                return Err(DecompositionError::UnorderedEvents(
+                    self.id, pair[0].id, pair[1].id,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the events of `other` into this sub-pattern, ordering them by the order relation of
+    /// `pattern`. The merged sub-pattern keeps the id of this one.
+    ///
+    /// Fails if the merged events are not totally ordered, see [`SubPattern::validate`].
+    pub fn merge(
+        mut self,
+        other: SubPattern<'a>,
+        pattern: &Pattern,
+    ) -> Result<Self, DecompositionError> {
+        self.events.extend(other.events);
+        // in a chain, an event is preceded by exactly the events before it
+        let num_preceding: Vec<usize> = self
+            .events
+            .iter()
+            .map(|event| {
+                self.events
+                    .iter()
+                    .filter(|other| pattern.order.must_precede(other.id, event.id))
+                    .count()
+            })
+            .collect();
+        let mut indexed: Vec<_> = num_preceding.into_iter().zip(self.events).collect();
+        indexed.sort_by_key(|(num_preceding, _)| *num_preceding);
+        self.events = indexed.into_iter().map(|(_, event)| event).collect();
+        self.validate(pattern)?;
+        Ok(self)
+    }
+
+    /// Split this sub-pattern into the events before `mid` and those from `mid` on. Both halves
+    /// keep the id of this one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid > len`.
+    pub fn split_at(mut self, mid: usize) -> (Self, Self) {
+        let tail = self.events.split_off(mid);
+        let id = self.id;
+        (self, Self::new(id, tail))
+    }
+}
+
+/// Move the optional sub-patterns after all the other ones, keeping the relative order
+/// otherwise, and number the sub-patterns by their positions.
+pub fn reassign_ids(decomposition: &mut [SubPattern]) {
+    decomposition.sort_by_key(SubPattern::is_optional);
+    for (id, x) in decomposition.iter_mut().enumerate() {
+        x.id = id;
+    }
+}
+
+/// Check that `decomposition` can be fed to the Composition and Join layers: each sub-pattern is
+/// valid (see [`SubPattern::validate`]), each event of `pattern` is in exactly one of them, the
+/// ids are the positions, and the optional sub-patterns come last (see [`reassign_ids`]).
+pub fn validate_decomposition(
+    pattern: &Pattern,
+    decomposition: &[SubPattern],
+) -> Result<(), DecompositionError> {
+    let mut is_covered = vec![false; pattern.events.len()];
+    for (idx, sub_pattern) in decomposition.iter().enumerate() {
+        if sub_pattern.id != idx {
+            return Err(DecompositionError::MismatchedId(idx, sub_pattern.id));
+        }
+        sub_pattern.validate(pattern)?;
+        for event in &sub_pattern.events {
+            match is_covered.get_mut(event.id) {
+                Some(true) => return Err(DecompositionError::DuplicateEvent(event.id)),
+                Some(covered) => *covered = true,
+                None => return Err(DecompositionError::UndefinedEventId(event.id)),
+            }
+        }
+    }
+    if let Some(event_id) = is_covered.iter().position(|covered| !covered) {
+        return Err(DecompositionError::MissingEvent(event_id));
+    }
+    let misplaced = decomposition
+        .windows(2)
+        .find(|pair| pair[0].is_optional() && !pair[1].is_optional());
+    if let Some(pair) = misplaced {
+        return Err(DecompositionError::MisplacedOptional(pair[0].id));
+    }
+    Ok(())
+}
 
 // maybe we can set a maximum sub-pattern size
 /// Decompose the input behavioral pattern into disjoint sub-patterns.
-pub fn decompose(pattern: &Pattern) -> Vec<SubPattern> {
+///
+/// Each optional event forms a sub-pattern by itself, so that a match can be joined without it.
+/// The optional sub-patterns come after all the other ones. Each aggregate event also forms a
+/// sub-pattern by itself, since its object is not bound to a single input entity.
+pub fn decompose(pattern: &Pattern) -> Vec<SubPattern<'_>> {
     let mut sub_patterns: Vec<SubPattern> = Vec::new();
     let mut parents: Vec<&PatternEvent> = Vec::new();
     for edge in &pattern.events {
@@ -20,9 +186,7 @@ pub fn decompose(pattern: &Pattern) -> Vec<SubPattern> {
     }
 
     let mut selected: Vec<SubPattern> = select_sub_patterns(pattern.events.len(), sub_patterns);
-    for (id, x) in selected.iter_mut().enumerate() {
-        x.id = id;
-    }
+    reassign_ids(&mut selected);
     // this.TCQRelation = genRelations(selected); [reserved to "join_layer"]
     selected
 }
@@ -38,6 +202,15 @@ fn generate_sub_patterns<'a>(
     if !has_shared_node(edge, parents) {
         return;
     }
+    if edge.is_optional || matches!(edge.event_type, PatternEventType::Aggregate { .. }) {
+        if parents.is_empty() {
+            results.push(SubPattern {
+                id: 0,
+                events: vec![edge],
+            });
+        }
+        return;
+    }
     parents.push(edge);
     results.push(SubPattern {
         id: 0,
@@ -69,7 +242,7 @@ fn has_shared_node(edge: &PatternEvent, parents: &Vec<&PatternEvent>) -> bool {
 /// Select (heuristically) valid ones from all sub-patterns.
 fn select_sub_patterns(num_edges: usize, mut sub_patterns: Vec<SubPattern>) -> Vec<SubPattern> {
     // sort in decreasing size
-    sub_patterns.sort_by(|x, y| y.events.len().cmp(&x.events.len()));
+    sub_patterns.sort_by_key(|x| std::cmp::Reverse(x.events.len()));
 
     let mut selected_sub_patterns: Vec<SubPattern> = Vec::new();
     let mut is_edge_selected: Vec<bool> = vec![false; num_edges];
@@ -118,15 +291,24 @@ mod tests {
         PatternEvent {
             id: 0,
             signature: signature.to_string(),
+            alternatives: vec![],
             event_type: PatternEventType::Default,
             subject: PatternEntity {
                 id: subject,
                 signature: "".to_string(),
+                is_wildcard: false,
             },
             object: PatternEntity {
                 id: object,
                 signature: "".to_string(),
+                is_wildcard: false,
             },
+            parent_gaps: vec![],
+            distinct_entities: vec![],
+            same_attributes: vec![],
+            simultaneous_events: vec![],
+            is_static: false,
+            is_optional: false,
         }
     }
 
@@ -201,4 +383,64 @@ mod tests {
 
         println!("{:#?}", decompose(&pattern));
     }
+
+    #[test]
+    fn test_merge_and_split() {
+        let pattern = Pattern::parse("data/universal_patterns/SP12.json").unwrap();
+        let sub_pattern = SubPattern::from_event_ids(&pattern, 0, &[0, 1, 2, 3]).unwrap();
+        let event_ids = |sub_pattern: &SubPattern| -> Vec<usize> {
+            sub_pattern.events.iter().map(|event| event.id).collect()
+        };
+
+        let (head, tail) = sub_pattern.split_at(2);
+        assert_eq!(event_ids(&head), [0, 1]);
+        assert_eq!(event_ids(&tail), [2, 3]);
+        let mut decomposition = vec![tail.clone(), head.clone()];
+        reassign_ids(&mut decomposition);
+        assert_eq!(decomposition[1].id, 1);
+        assert_eq!(validate_decomposition(&pattern, &decomposition), Ok(()));
+
+        // merged in the order relation
+        let merged = tail.merge(head, &pattern).unwrap();
+        assert_eq!(event_ids(&merged), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_validate_decomposition() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let sub_pattern =
+            |id, event_ids: &[usize]| SubPattern::from_event_ids(&pattern, id, event_ids).unwrap();
+        assert_eq!(
+            SubPattern::from_event_ids(&pattern, 0, &[4]).unwrap_err(),
+            DecompositionError::UndefinedEventId(4)
+        );
+
+        // events 0 and 1 are not ordered
+        let error = sub_pattern(0, &[1]).merge(sub_pattern(1, &[0]), &pattern);
+        assert!(matches!(
+            error,
+            Err(DecompositionError::UnorderedEvents(0, _, _))
+        ));
+
+        let decomposition = [sub_pattern(0, &[1, 3]), sub_pattern(1, &[0])];
+        assert_eq!(
+            validate_decomposition(&pattern, &decomposition),
+            Err(DecompositionError::MissingEvent(2))
+        );
+        let decomposition = [
+            sub_pattern(0, &[1, 3]),
+            sub_pattern(1, &[0]),
+            sub_pattern(2, &[2]),
+            sub_pattern(3, &[3]),
+        ];
+        assert_eq!(
+            validate_decomposition(&pattern, &decomposition),
+            Err(DecompositionError::DuplicateEvent(3))
+        );
+        let decomposition = [sub_pattern(1, &[1, 3]), sub_pattern(0, &[0, 2])];
+        assert_eq!(
+            validate_decomposition(&pattern, &decomposition),
+            Err(DecompositionError::MismatchedId(0, 1))
+        );
+    }
 }
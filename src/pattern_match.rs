@@ -1,11 +1,14 @@
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::fmt::Formatter;
 use std::fmt::{self};
 use std::hash::Hash;
 use std::rc::Rc;
 
+use crate::input_event::InputEvent;
 use crate::match_event::{MatchEvent, RawEvents};
+use crate::match_schema::MatchRecord;
 use crate::process_layers::composition_layer::match_instance::{InputEventId, PatternEventId};
 
 /// Complete Pattern Match
@@ -18,6 +21,58 @@ pub struct PatternMatch {
 
     pub event_ids: Box<[(InputEventId, PatternEventId)]>,
     pub match_event_map: Box<[Option<Rc<MatchEvent>>]>,
+
+    /// The annotations added by a [`MatchPostFilter`](crate::process_layers::MatchPostFilter),
+    /// e.g. a score, which are empty otherwise.
+    pub annotations: serde_json::Map<String, serde_json::Value>,
+
+    /// The sequence number and the window id assigned when this match is output, see
+    /// [`MatchSequencer`].
+    pub sequence: Option<MatchSequence>,
+}
+
+/// The position of an output match in the stream of matches, which identifies it across systems
+/// consuming the same output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MatchSequence {
+    /// Starts from 0, increasing by one with each match output.
+    pub number: u64,
+    /// The window of the match, `earliest_time / window size`.
+    pub window_id: u64,
+}
+
+/// Numbers the matches in the order they are output, e.g. by
+/// [`Pipeline`](crate::process_layers::Pipeline).
+#[derive(Debug, Clone)]
+pub struct MatchSequencer {
+    next_number: u64,
+    window_size: u64,
+}
+
+impl MatchSequencer {
+    /// The window ids are those of the tumbling windows of `window_size` (in milliseconds).
+    pub fn new(window_size: u64) -> Self {
+        Self {
+            next_number: 0,
+            window_size: window_size.max(1),
+        }
+    }
+
+    /// The sequence of the next match output, starting at `earliest_time`.
+    pub fn next(&mut self, earliest_time: u64) -> MatchSequence {
+        let number = self.next_number;
+        self.next_number += 1;
+        MatchSequence {
+            number,
+            window_id: earliest_time / self.window_size,
+        }
+    }
+
+    /// Assign the sequence of the next match output to `pattern_match`, replacing the one it may
+    /// have been assigned by an earlier stage.
+    pub fn assign(&mut self, pattern_match: &mut PatternMatch) {
+        pattern_match.sequence = Some(self.next(pattern_match.earliest_time));
+    }
 }
 
 impl Hash for PatternMatch {
@@ -34,21 +89,79 @@ impl PartialEq for PatternMatch {
     }
 }
 
-impl fmt::Display for PatternMatch {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let event_str = self.match_event_map.iter().flatten().map(|match_event| {
-            match &match_event.raw_events {
-                RawEvents::Single(input_event) => {
-                    input_event.event_id.to_string()
-                }
-                RawEvents::Multiple(input_events) => {
-                    format!("({})", input_events.iter().map(|e| e.event_id).join(", "))
+impl PatternMatch {
+    /// Returns the input events in this match, in the order of the matched pattern events.
+    pub fn input_events(&self) -> impl Iterator<Item = &Rc<InputEvent>> {
+        self.match_event_map
+            .iter()
+            .flatten()
+            .flat_map(|match_event| match_event.raw_events.get_events())
+    }
+
+    /// Returns the input entities (subjects and objects) of the matched events, which may repeat.
+    pub fn input_entities(&self) -> impl Iterator<Item = u64> + '_ {
+        self.match_event_map
+            .iter()
+            .flatten()
+            .flat_map(|match_event| [match_event.input_subject_id, match_event.input_object_id])
+    }
+
+    /// Returns the matched flows as `(match event, start time, end time)`, where the flow starts
+    /// at its first arc and ends at its last arc.
+    pub fn flows(&self) -> impl Iterator<Item = (&MatchEvent, u64, u64)> {
+        self.match_event_map
+            .iter()
+            .flatten()
+            .filter_map(|match_event| match match_event.raw_events {
+                RawEvents::Flow(start_time, end_time) => {
+                    Some((match_event.as_ref(), start_time, end_time))
                 }
-                RawEvents::Flow(_, _) => {
-                    format!("({} -> {})", match_event.input_subject_id, match_event.input_object_id)
+                _ => None,
+            })
+    }
+
+    /// Returns the `earliest_time` and `latest_time` (in milliseconds) and the ids of the matched
+    /// input events (`event_ids`) of this match as a JSON object, tagged by the schema
+    /// `"version"`, see [`MatchRecord`]. A match with flow events also lists its flows under
+    /// `"flows"`, e.g. `{"pattern_event_id": 0, "subject_id": 1, "object_id": 3, "start_time":
+    /// 1000, "end_time": 2500}`, an annotated match its `"annotations"`, and a numbered match its
+    /// `"sequence"` and `"window_id"`, see [`MatchSequence`].
+    pub fn to_json(&self) -> serde_json::Value {
+        MatchRecord::new(self, false).to_json()
+    }
+
+    /// Returns [`PatternMatch::to_json`] along with the input events in the order of the matched
+    /// pattern events under `"events"`, each with its timestamp and raw signatures, e.g.
+    /// `{"event_id": 3, "timestamp": 1000, "signature": "read", "subject_signature": "proc::a",
+    /// "object_signature": "file::b"}`. The events also list the `"line"` number and the `"byte"`
+    /// offset of their records in the data graph, if recorded by the Parse layer.
+    pub fn to_verbose_json(&self) -> serde_json::Value {
+        MatchRecord::new(self, true).to_json()
+    }
+
+    /// Returns the input entity bound to the pattern entity `pattern_entity_id` in this match.
+    pub fn entity_binding(&self, pattern_entity_id: u64) -> Option<u64> {
+        self.match_event_map
+            .iter()
+            .flatten()
+            .find_map(|match_event| {
+                if match_event.pattern_subject_id == pattern_entity_id {
+                    Some(match_event.input_subject_id)
+                } else if match_event.pattern_object_id == pattern_entity_id {
+                    Some(match_event.input_object_id)
+                } else {
+                    None
                 }
-            }
-        }).join(", ");
+            })
+    }
+}
+
+impl fmt::Display for PatternMatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(sequence) = &self.sequence {
+            write!(f, "#{} (window {}) ", sequence.number, sequence.window_id)?;
+        }
+        let event_str = self.match_event_map.iter().flatten().join(", ");
         let start_t = self.earliest_time as f32 / 1000.0;
         let end_t = self.latest_time as f32 / 1000.0;
         write!(f, "<{start_t:.3}, {end_t:.3}>[{event_str}]")
@@ -79,3 +192,124 @@ impl PartialOrd<Self> for EarliestFirst {
         Some(self.cmp(other))
     }
 }
+
+#[cfg(test)]
+impl PatternMatch {
+    /// A match of the input events `event_ids`, all matched to pattern event 0, lasting from
+    /// `earliest_time` to `latest_time`, without match events.
+    pub(crate) fn for_test(earliest_time: u64, latest_time: u64, event_ids: &[u64]) -> Self {
+        Self {
+            latest_time,
+            earliest_time,
+            event_ids: event_ids.iter().map(|id| (*id, 0)).collect(),
+            match_event_map: Box::new([]),
+            annotations: serde_json::Map::new(),
+            sequence: None,
+        }
+    }
+
+    /// Set the match event of pattern event 0 to a flow from the input entity `subject`, bound
+    /// to pattern entity 0, to `object`, bound to pattern entity 1.
+    pub(crate) fn with_flow(mut self, subject: u64, object: u64) -> Self {
+        self.match_event_map = Box::new([Some(Rc::new(MatchEvent {
+            match_id: 0,
+            input_subject_id: subject,
+            input_object_id: object,
+            pattern_subject_id: 0,
+            pattern_object_id: 1,
+            raw_events: RawEvents::Flow(self.earliest_time, self.latest_time),
+        }))]);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_event::RecordPosition;
+
+    #[test]
+    fn test_match_sequencer() {
+        let mut sequencer = MatchSequencer::new(1000);
+        let sequences = [2500, 999, 1000].map(|earliest_time| sequencer.next(earliest_time));
+        let sequences = sequences.map(|sequence| (sequence.number, sequence.window_id));
+        assert_eq!(sequences, [(0, 2), (1, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn test_to_verbose_json() {
+        let input_event = InputEvent::new(1500, 3, "read", 10, "proc::a", 11, "file::b")
+            .with_position(Some(RecordPosition { line: 4, byte: 96 }));
+        let input_event = Rc::new(input_event);
+        let pattern_match = PatternMatch {
+            latest_time: 1500,
+            earliest_time: 1500,
+            event_ids: Box::new([(3, 0)]),
+            match_event_map: Box::new([Some(Rc::new(MatchEvent {
+                match_id: 0,
+                input_subject_id: 10,
+                input_object_id: 11,
+                pattern_subject_id: 0,
+                pattern_object_id: 1,
+                raw_events: RawEvents::Single(input_event),
+            }))]),
+            annotations: serde_json::Map::new(),
+            sequence: None,
+        };
+
+        assert_eq!(
+            pattern_match.to_verbose_json(),
+            serde_json::json!({
+                "version": "3",
+                "earliest_time": 1500,
+                "latest_time": 1500,
+                "event_ids": [3],
+                "events": [{
+                    "event_id": 3,
+                    "timestamp": 1500,
+                    "signature": "read",
+                    "subject_signature": "proc::a",
+                    "object_signature": "file::b",
+                    "line": 4,
+                    "byte": 96
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_flow_to_json() {
+        let pattern_match = PatternMatch {
+            latest_time: 2500,
+            earliest_time: 1000,
+            event_ids: Box::new([]),
+            match_event_map: Box::new([Some(Rc::new(MatchEvent {
+                match_id: 0,
+                input_subject_id: 1,
+                input_object_id: 3,
+                pattern_subject_id: 0,
+                pattern_object_id: 1,
+                raw_events: RawEvents::Flow(1000, 2500),
+            }))]),
+            annotations: serde_json::Map::new(),
+            sequence: None,
+        };
+
+        assert_eq!(
+            pattern_match.to_json(),
+            serde_json::json!({
+                "version": "3",
+                "earliest_time": 1000,
+                "latest_time": 2500,
+                "event_ids": [],
+                "flows": [{
+                    "pattern_event_id": 0,
+                    "subject_id": 1,
+                    "object_id": 3,
+                    "start_time": 1000,
+                    "end_time": 2500
+                }]
+            })
+        );
+    }
+}
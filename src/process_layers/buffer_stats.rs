@@ -0,0 +1,321 @@
+use ahash::HashMap;
+use serde::Serialize;
+use std::fmt;
+use std::io::Write;
+
+/// Size statistics of a buffer in a layer, for tuning the window size and diagnosing state growth.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BufferStats {
+    /// e.g. `"join.buffer 3"`.
+    pub name: String,
+    /// Number of entries (partial matches) in the buffer.
+    pub len: usize,
+    /// The earliest timestamp (ms) among the entries, `None` if the buffer is empty.
+    pub oldest_time: Option<u64>,
+    /// Approximate heap and inline size of the entries, excluding the input events they share.
+    pub approx_bytes: usize,
+}
+
+impl BufferStats {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            len: 0,
+            oldest_time: None,
+            approx_bytes: 0,
+        }
+    }
+
+    /// Account an entry starting at `time` with the approximate size `bytes`.
+    pub fn add(&mut self, time: u64, bytes: usize) {
+        self.len += 1;
+        self.oldest_time = Some(self.oldest_time.map_or(time, |t| t.min(time)));
+        self.approx_bytes += bytes;
+    }
+}
+
+impl fmt::Display for BufferStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} entries, ", self.name, self.len)?;
+        if let Some(oldest_time) = self.oldest_time {
+            write!(f, "oldest at {:.3}, ", oldest_time as f64 / 1000.0)?;
+        }
+        write!(f, "~{} bytes", self.approx_bytes)
+    }
+}
+
+/// Layers that can report the sizes of their buffers.
+pub trait ReportBufferStats {
+    /// Append the statistics of the buffers of this layer and the layers it pulls from.
+    fn collect_buffer_stats(&self, stats: &mut Vec<BufferStats>);
+
+    /// Append the statistics of the partial matches grouped by the pattern prefix they have
+    /// matched, one entry per state. Layers without such states append nothing.
+    fn collect_state_stats(&self, _stats: &mut Vec<BufferStats>) {}
+
+    /// Append the number of live partial matches in each state, indexed by the state id. Layers
+    /// without such states append nothing.
+    fn collect_live_instances(&self, _counts: &mut Vec<u64>) {}
+}
+
+/// A periodic report of [`StatsReporter`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatsReport {
+    /// The stream time (ms) of the report.
+    pub time: u64,
+    /// The earliest timestamp (ms) a match can still start at, i.e. the stream time minus the
+    /// window size for sliding windows. Partial matches starting before it have expired.
+    pub watermark: u64,
+    /// The statistics of each buffer.
+    pub buffers: Vec<BufferStats>,
+    /// The states holding the most live partial matches, in descending order. Empty unless
+    /// enabled by [`StatsReporter::set_top_states`].
+    pub top_states: Vec<BufferStats>,
+    /// The number of live partial matches in each state, indexed by the state id. Empty unless
+    /// enabled by [`StatsReporter::set_live_instances`].
+    pub live_instances: Vec<u64>,
+}
+
+type StatsCallback = Box<dyn FnMut(&StatsReport)>;
+
+/// Calls a callback with the buffer statistics every `interval` (ms) of stream time, see
+/// [`JoinLayer::set_stats_reporter`](super::JoinLayer::set_stats_reporter).
+pub struct StatsReporter {
+    interval: u64,
+    next_report: Option<u64>,
+    /// See `set_top_states()`.
+    top_states: usize,
+    /// See `set_live_instances()`.
+    live_instances: bool,
+    callback: StatsCallback,
+}
+
+impl StatsReporter {
+    /// `callback` receives the report at the current stream time.
+    pub fn new(interval: u64, callback: impl FnMut(&StatsReport) + 'static) -> Self {
+        Self {
+            interval: interval.max(1),
+            next_report: None,
+            top_states: 0,
+            live_instances: false,
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Also report the `n` states with the most live partial matches, to see which pattern
+    /// prefixes are blowing up the state. Disabled by default (`n` = 0).
+    pub fn set_top_states(&mut self, n: usize) {
+        self.top_states = n;
+    }
+
+    /// Also report the number of live partial matches in every state. Disabled by default.
+    pub fn set_live_instances(&mut self, live_instances: bool) {
+        self.live_instances = live_instances;
+    }
+
+    /// Report the statistics of `layer` and the `watermark` (see [`StatsReport::watermark`]) if
+    /// an interval has passed since the last report.
+    pub fn tick(&mut self, current_time: u64, watermark: u64, layer: &impl ReportBufferStats) {
+        let next_report = *self.next_report.get_or_insert(current_time + self.interval);
+        if current_time >= next_report {
+            let mut report = StatsReport {
+                time: current_time,
+                watermark,
+                buffers: vec![],
+                top_states: vec![],
+                live_instances: vec![],
+            };
+            layer.collect_buffer_stats(&mut report.buffers);
+            if self.live_instances {
+                layer.collect_live_instances(&mut report.live_instances);
+            }
+            if self.top_states > 0 {
+                layer.collect_state_stats(&mut report.top_states);
+                report
+                    .top_states
+                    .sort_by(|a, b| b.len.cmp(&a.len).then_with(|| a.name.cmp(&b.name)));
+                report.top_states.truncate(self.top_states);
+            }
+            (self.callback)(&report);
+            self.next_report = Some(current_time + self.interval);
+        }
+    }
+}
+
+impl fmt::Debug for StatsReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StatsReporter")
+            .field("interval", &self.interval)
+            .field("next_report", &self.next_report)
+            .field("top_states", &self.top_states)
+            .field("live_instances", &self.live_instances)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Writes each [`StatsReport`] as a CSV record, to plot the state size over the course of a data
+/// graph. The columns are the stream time (ms), the watermark, the live partial matches of each
+/// state (`state <id>`), and the number of entries and the approximate bytes of each buffer, as
+/// of the first report. Enable [`StatsReporter::set_live_instances`] for the state columns.
+pub struct StateSeriesWriter<W: Write> {
+    writer: csv::Writer<W>,
+    /// The number of states and the buffer names, once the header is written.
+    columns: Option<(usize, Vec<String>)>,
+}
+
+impl<W: Write> StateSeriesWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            columns: None,
+        }
+    }
+
+    /// Append the record of `report`, and flush it so that the series can be followed while the
+    /// data graph is processed.
+    pub fn write(&mut self, report: &StatsReport) -> csv::Result<()> {
+        let (num_states, buffer_names) = match &self.columns {
+            Some(columns) => columns,
+            None => {
+                let buffer_names: Vec<String> =
+                    report.buffers.iter().map(|b| b.name.clone()).collect();
+                let mut header = vec!["time".to_string(), "watermark".to_string()];
+                header.extend((0..report.live_instances.len()).map(|id| format!("state {id}")));
+                for name in &buffer_names {
+                    header.extend([name.clone(), format!("{name} bytes")]);
+                }
+                self.writer.write_record(&header)?;
+                self.columns
+                    .insert((report.live_instances.len(), buffer_names))
+            }
+        };
+
+        let buffers: HashMap<&str, &BufferStats> = report
+            .buffers
+            .iter()
+            .map(|b| (b.name.as_str(), b))
+            .collect();
+        let mut record = vec![report.time.to_string(), report.watermark.to_string()];
+        record.extend(
+            (0..*num_states).map(|id| report.live_instances.get(id).unwrap_or(&0).to_string()),
+        );
+        for name in buffer_names {
+            let (len, approx_bytes) = buffers
+                .get(name.as_str())
+                .map_or((0, 0), |b| (b.len, b.approx_bytes));
+            record.extend([len.to_string(), approx_bytes.to_string()]);
+        }
+        self.writer.write_record(&record)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct FakeLayer;
+
+    impl ReportBufferStats for FakeLayer {
+        fn collect_buffer_stats(&self, stats: &mut Vec<BufferStats>) {
+            let mut buffer = BufferStats::new("fake");
+            buffer.add(5, 10);
+            buffer.add(3, 20);
+            stats.push(buffer);
+        }
+
+        fn collect_state_stats(&self, stats: &mut Vec<BufferStats>) {
+            for (name, len) in [("state 1", 1), ("state 2", 3), ("state 3", 2)] {
+                let mut state = BufferStats::new(name);
+                for time in 0..len {
+                    state.add(time, 10);
+                }
+                stats.push(state);
+            }
+        }
+
+        fn collect_live_instances(&self, counts: &mut Vec<u64>) {
+            counts.extend([1, 3, 2]);
+        }
+    }
+
+    #[test]
+    fn test_stats_reporter() {
+        let reports = Rc::new(RefCell::new(vec![]));
+        let mut reporter = StatsReporter::new(10, {
+            let reports = reports.clone();
+            move |report: &StatsReport| reports.borrow_mut().push(report.clone())
+        });
+
+        for time in [0, 5, 10, 12, 25] {
+            reporter.tick(time, time.saturating_sub(8), &FakeLayer);
+        }
+
+        let reports = reports.borrow();
+        assert_eq!(reports.iter().map(|r| r.time).collect::<Vec<_>>(), [10, 25]);
+        assert_eq!(
+            reports.iter().map(|r| r.watermark).collect::<Vec<_>>(),
+            [2, 17]
+        );
+        assert!(reports[0].top_states.is_empty());
+        assert_eq!(
+            reports[0].buffers,
+            [BufferStats {
+                name: "fake".to_string(),
+                len: 2,
+                oldest_time: Some(3),
+                approx_bytes: 30,
+            }]
+        );
+        assert_eq!(
+            reports[0].buffers[0].to_string(),
+            "fake: 2 entries, oldest at 0.003, ~30 bytes"
+        );
+    }
+
+    #[test]
+    fn test_top_states() {
+        let reports = Rc::new(RefCell::new(vec![]));
+        let mut reporter = StatsReporter::new(10, {
+            let reports = reports.clone();
+            move |report: &StatsReport| reports.borrow_mut().push(report.clone())
+        });
+        reporter.set_top_states(2);
+        reporter.tick(0, 0, &FakeLayer);
+        reporter.tick(10, 0, &FakeLayer);
+
+        let reports = reports.borrow();
+        let top_states: Vec<_> = reports[0]
+            .top_states
+            .iter()
+            .map(|state| (state.name.as_str(), state.len))
+            .collect();
+        assert_eq!(top_states, [("state 2", 3), ("state 3", 2)]);
+    }
+
+    #[test]
+    fn test_state_series() {
+        let series = Rc::new(RefCell::new(StateSeriesWriter::new(vec![])));
+        let mut reporter = StatsReporter::new(10, {
+            let series = series.clone();
+            move |report: &StatsReport| series.borrow_mut().write(report).unwrap()
+        });
+        reporter.set_live_instances(true);
+        for time in [0, 10, 20] {
+            reporter.tick(time, time / 2, &FakeLayer);
+        }
+
+        drop(reporter);
+        let series = Rc::into_inner(series).unwrap();
+        let csv = String::from_utf8(series.into_inner().writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "time,watermark,state 0,state 1,state 2,fake,fake bytes\n\
+             10,5,1,3,2,2,30\n\
+             20,10,1,3,2,2,30\n"
+        );
+    }
+}
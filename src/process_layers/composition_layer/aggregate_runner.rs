@@ -0,0 +1,145 @@
+use super::instance_storage::StorageRequest;
+use super::pattern_info::AggregatePattern;
+use super::state_table::StateTable;
+use super::InstanceStorage;
+use crate::input_event::InputEvent;
+use crate::match_event::{MatchEvent, RawEvents};
+use crate::process_layers::window_policy::{SlidingWindow, WindowPolicy};
+use crate::process_layers::BufferStats;
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Matches aggregate-typed pattern events, see `PatternEventType::Aggregate`.
+///
+/// An aggregate event is a sub-pattern by itself, so its matches always extend the empty
+/// instance of its match index. The recent matching events are buffered by subject instead.
+pub struct AggregateRunner {
+    window: Rc<dyn WindowPolicy>,
+    cur_time: u64,
+    /// The in-window events matching each aggregate pattern event (by match index), grouped by
+    /// their subjects and ordered by time.
+    buffers: HashMap<(usize, u64), VecDeque<Rc<InputEvent>>>,
+    /// The number of buffers left by the last purge, see `set_batch()`.
+    num_purged_buffers: usize,
+}
+
+impl AggregateRunner {
+    pub fn new(window_size: u64) -> Self {
+        Self {
+            window: Rc::new(SlidingWindow::new(window_size)),
+            cur_time: 0,
+            buffers: HashMap::new(),
+            num_purged_buffers: 0,
+        }
+    }
+
+    pub fn set_window_policy(&mut self, window: Rc<dyn WindowPolicy>) {
+        self.window = window;
+    }
+
+    /// Drop the buffered events.
+    pub fn clear(&mut self) {
+        self.buffers.clear();
+        self.num_purged_buffers = 0;
+    }
+
+    /// Advance to the batch at `time`. The buffers of the subjects without in-window events are
+    /// dropped once the number of buffers doubles, so that the purge is amortized.
+    pub fn set_batch(&mut self, time: u64) {
+        self.cur_time = time;
+        if self.buffers.len() > 2 * self.num_purged_buffers.max(64) {
+            let window_bound = self.window.window_bound(time);
+            self.buffers
+                .retain(|_, buffer| buffer.back().is_some_and(|e| e.timestamp >= window_bound));
+            self.num_purged_buffers = self.buffers.len();
+        }
+    }
+
+    /// Execute the composition logic for aggregate-typed pattern event, where `events` are the
+    /// events in the current batch matching its signature.
+    ///
+    /// Once a subject reaches the required number of events and distinct objects, the buffered
+    /// events are reported as a whole and the subject starts over.
+    pub fn run<'a, 'p>(
+        &mut self,
+        info: &AggregatePattern<'p>,
+        events: impl Iterator<Item = &'a Rc<InputEvent>>,
+        storage: &mut InstanceStorage,
+        state_table: &StateTable,
+    ) {
+        let window_bound = self.window.window_bound(self.cur_time);
+        let bound = match info.within {
+            Some(within) => window_bound.max(self.cur_time.saturating_sub(within)),
+            None => window_bound,
+        };
+
+        let mut new_instances = vec![];
+        for event in events {
+            let buffer = self
+                .buffers
+                .entry((info.match_idx, event.subject_id))
+                .or_default();
+            buffer.push_back(event.clone());
+            while buffer.front().is_some_and(|e| e.timestamp < bound) {
+                buffer.pop_front();
+            }
+            if buffer.len() < info.count as usize
+                || num_distinct_objects(buffer) < info.distinct_objects as usize
+            {
+                continue;
+            }
+
+            let new_event = MatchEvent {
+                match_id: info.pattern.id as u32,
+                input_subject_id: event.subject_id,
+                input_object_id: event.object_id,
+                pattern_subject_id: info.pattern.subject.id as u64,
+                pattern_object_id: info.pattern.object.id as u64,
+                raw_events: RawEvents::Multiple(buffer.drain(..).collect()),
+            };
+            let request = StorageRequest {
+                match_idx: info.match_idx,
+                subject_id: event.subject_id,
+                object_id: event.object_id,
+                shared_node_info: info.shared_node_info,
+            };
+            for instance in storage.query_with_windowing(&request, self.window.as_ref(), self.cur_time) {
+                if !instance.satisfy_distinct_entities(&new_event, &info.pattern.distinct_entities)
+                {
+                    continue;
+                }
+                if let Some(mut new_instance) = instance.clone_extend(
+                    new_event.clone(),
+                    info.shared_node_info,
+                    info.pattern.wildcards(),
+                    true,
+                ) {
+                    if !new_instance.bind_attributes(&info.pattern.same_attributes) {
+                        continue;
+                    }
+                    new_instance.state_id = state_table.get_next_state(instance.state_id);
+                    new_instances.push(new_instance);
+                }
+            }
+        }
+        storage.store_new_instances(new_instances.into_iter(), state_table);
+    }
+
+    /// The statistics of the buffered events, one entry per event.
+    pub fn buffer_stats(&self) -> BufferStats {
+        let mut stats = BufferStats::new("composition.aggregate_buffers");
+        for event in self.buffers.values().flatten() {
+            stats.add(event.timestamp, std::mem::size_of::<Rc<InputEvent>>());
+        }
+        stats
+    }
+}
+
+fn num_distinct_objects(events: &VecDeque<Rc<InputEvent>>) -> usize {
+    let mut objects = HashSet::new();
+    for event in events {
+        objects.insert(event.object_id);
+    }
+    objects.len()
+}
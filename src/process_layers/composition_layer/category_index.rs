@@ -0,0 +1,86 @@
+use crate::pattern::{PatternEventType, SubPattern};
+use ahash::HashSet;
+
+/// The coarse category of an event signature, which is its first `::`-separated token, e.g.
+/// `EVENT_READ` for both `EVENT_READ` and `EVENT_READ::/etc/passwd`.
+pub fn event_category(signature: &str) -> &str {
+    signature.split("::").next().unwrap_or(signature)
+}
+
+/// The event categories that any pattern event may match, so that the batches without events in
+/// these categories can be skipped as a whole. Checking a batch only takes a hash lookup per
+/// event, rather than matching the signatures of all the pattern events.
+#[derive(Debug, Clone)]
+pub struct CategoryIndex {
+    categories: HashSet<String>,
+}
+
+impl CategoryIndex {
+    /// Returns `None` if some pattern event may match events of any category: a flow event, which
+    /// may go through any event, or a regex signature whose category is not a literal, e.g.
+    /// `read.*`. A regex signature with an alternation, e.g. `fork::a|exec::b`, is never indexed,
+    /// since its alternatives may be in other categories than the first one.
+    pub fn new(decomposition: &[SubPattern], use_regex: bool) -> Option<Self> {
+        let mut categories = HashSet::default();
+        for pattern in decomposition
+            .iter()
+            .flat_map(|sub_pattern| &sub_pattern.events)
+        {
+            if matches!(pattern.event_type, PatternEventType::Flow) {
+                return None;
+            }
+            // static events match the context graph, never the input events
+            if pattern.is_static {
+                continue;
+            }
+            for signature in pattern.signatures() {
+                let category = event_category(signature);
+                if use_regex && (signature.contains('|') || regex::escape(category) != category) {
+                    return None;
+                }
+                categories.insert(category.to_string());
+            }
+        }
+        Some(Self { categories })
+    }
+
+    /// Whether an event of this signature may match any pattern event.
+    pub fn is_relevant(&self, event_signature: &str) -> bool {
+        self.categories.contains(event_category(event_signature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{decompose, Pattern};
+
+    fn signature_pattern(signatures: &[&str], use_regex: bool) -> Pattern {
+        let edges: Vec<_> = signatures
+            .iter()
+            .map(|signature| (0, 1, *signature))
+            .collect();
+        Pattern::from_graph(&["a", "b"], &edges, use_regex)
+    }
+
+    #[test]
+    fn test_category_index() {
+        let pattern = signature_pattern(&["EVENT_READ", "write::/etc/passwd"], false);
+        let index = CategoryIndex::new(&decompose(&pattern), false).unwrap();
+        assert!(index.is_relevant("EVENT_READ"));
+        assert!(index.is_relevant("write::/etc/shadow"));
+        assert!(!index.is_relevant("EVENT_WRITE"));
+        assert!(!index.is_relevant("read"));
+
+        let pattern = signature_pattern(&["fork::.*"], true);
+        let index = CategoryIndex::new(&decompose(&pattern), true).unwrap();
+        assert!(index.is_relevant("fork::bash"));
+        assert!(!index.is_relevant("execve"));
+
+        let pattern = signature_pattern(&["read|write"], true);
+        assert!(CategoryIndex::new(&decompose(&pattern), true).is_none());
+        // the second alternative is in another category
+        let pattern = signature_pattern(&["fork::a|exec::b"], true);
+        assert!(CategoryIndex::new(&decompose(&pattern), true).is_none());
+    }
+}
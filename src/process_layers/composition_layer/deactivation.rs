@@ -0,0 +1,100 @@
+use log::warn;
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// When to check the pattern for events that have matched no input event, see
+/// [`CompositionLayer::set_deactivation_policy`](super::CompositionLayer::set_deactivation_policy).
+/// The check is made once, at whichever of the limits is reached first. `None` means no limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeactivationPolicy {
+    /// Stream time (ms) since the first input event.
+    pub after_time: Option<u64>,
+    /// Number of input events.
+    pub after_events: Option<u64>,
+}
+
+/// The report of a deactivated pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deactivation {
+    /// The stream time (ms) of the check.
+    pub time: u64,
+    /// The number of input events before the check.
+    pub num_events: u64,
+    /// The ids of the pattern events whose signatures no input event has matched, sorted.
+    pub pattern_event_ids: Vec<usize>,
+}
+
+/// Tells the Join layer that the Composition layer has deactivated the pattern, so that it drops
+/// its buffered sub-pattern matches, see
+/// [`CompositionLayer::set_deactivation_signal`](super::CompositionLayer::set_deactivation_signal).
+#[derive(Debug, Clone, Default)]
+pub struct DeactivationSignal(Rc<Cell<bool>>);
+
+impl DeactivationSignal {
+    pub(crate) fn raise(&self) {
+        self.0.set(true);
+    }
+
+    /// Whether the signal is raised since the last call.
+    pub fn take(&self) -> bool {
+        self.0.replace(false)
+    }
+}
+
+/// Counts the input events and checks the signature hits of the pattern events once the policy's
+/// limit is reached.
+#[derive(Debug, Default)]
+pub struct DeactivationCheck {
+    policy: Option<DeactivationPolicy>,
+    first_time: Option<u64>,
+    num_events: u64,
+    deactivation: Option<Deactivation>,
+}
+
+impl DeactivationCheck {
+    pub fn set_policy(&mut self, policy: DeactivationPolicy) {
+        self.policy = Some(policy);
+    }
+
+    /// Record a batch of `num_events` input events at `time`. Returns `true` if the limit of the
+    /// policy is reached, after which the check is due and no longer made.
+    pub fn record(&mut self, time: u64, num_events: u64) -> bool {
+        let Some(policy) = self.policy else {
+            return false;
+        };
+        let first_time = *self.first_time.get_or_insert(time);
+        self.num_events += num_events;
+        let is_due = policy
+            .after_time
+            .is_some_and(|after_time| time.saturating_sub(first_time) >= after_time)
+            || policy
+                .after_events
+                .is_some_and(|after_events| self.num_events >= after_events);
+        if is_due {
+            self.policy = None;
+        }
+        is_due
+    }
+
+    /// Deactivate the pattern at `time` if some of its events are unmatched, i.e.
+    /// `unmatched_event_ids` is not empty. Returns `true` if deactivated.
+    pub fn check(&mut self, time: u64, unmatched_event_ids: Vec<usize>) -> bool {
+        if unmatched_event_ids.is_empty() {
+            return false;
+        }
+        warn!(
+            "Pattern deactivated at {time} after {} input events: pattern events {:?} never matched",
+            self.num_events, unmatched_event_ids
+        );
+        self.deactivation = Some(Deactivation {
+            time,
+            num_events: self.num_events,
+            pattern_event_ids: unmatched_event_ids,
+        });
+        true
+    }
+
+    pub fn deactivation(&self) -> Option<&Deactivation> {
+        self.deactivation.as_ref()
+    }
+}
@@ -0,0 +1,163 @@
+use super::match_instance::MatchInstance;
+use crate::pattern::{PatternEvent, PatternEventType, SubPattern};
+use crate::process_layers::WindowPolicy;
+
+/// Tells the partial match instances that can no longer complete their sub-pattern, so that they
+/// are dropped before they expire from the window.
+///
+/// For the instances waiting for a pattern event, the remaining events of the sub-pattern start
+/// no earlier than the current time. An instance is dead if
+/// - the critical path of the remaining events, i.e. the longest chain of minimum gaps among them,
+///   ends after the window of the instance closes, or
+/// - a remaining event can no longer start within the maximum gap from a parent already matched,
+///   or at the time of a simultaneous event already matched.
+///
+/// The estimation is skipped for the pattern events followed by flow, aggregate or static events
+/// in their sub-pattern, since those may be matched with earlier input events.
+#[derive(Debug, Default)]
+pub struct EarlyPruning {
+    /// Indexed by the match index of the pattern event the instances are waiting for.
+    deadlines: Vec<Deadline>,
+}
+
+#[derive(Debug, Default)]
+struct Deadline {
+    /// The critical-path length (ms) from the start of the pattern event to the start of the
+    /// last remaining event of its sub-pattern.
+    remaining_depth: u64,
+    /// `(parent id, max gap, child offset)`: a remaining event, starting at least `child offset`
+    /// ms after the current time, must start within `max gap` ms after the matched parent.
+    max_gaps: Vec<(usize, u64, u64)>,
+}
+
+impl EarlyPruning {
+    pub fn new(decomposition: &[SubPattern]) -> Self {
+        let mut deadlines = vec![];
+        for sub_pattern in decomposition {
+            let events = &sub_pattern.events;
+            for k in 0..events.len() {
+                deadlines.push(Self::deadline_of(&events[k..], &events[..k]));
+            }
+        }
+        Self { deadlines }
+    }
+
+    /// The deadline of the instances which have matched `matched` and wait for `remaining`.
+    fn deadline_of(remaining: &[&PatternEvent], matched: &[&PatternEvent]) -> Deadline {
+        let is_timely = |event: &PatternEvent| {
+            !event.is_static
+                && matches!(
+                    event.event_type,
+                    PatternEventType::Default | PatternEventType::Frequency(_)
+                )
+        };
+        if !remaining.iter().all(|event| is_timely(event)) {
+            return Deadline::default();
+        }
+
+        let position = |id: usize| remaining.iter().position(|event| event.id == id);
+        let mut offsets = vec![0u64; remaining.len()];
+        let mut max_gaps = vec![];
+        for (j, event) in remaining.iter().enumerate() {
+            for (parent_id, gap) in &event.parent_gaps {
+                if let Some(pos) = position(*parent_id).filter(|pos| *pos < j) {
+                    offsets[j] = offsets[j].max(offsets[pos].saturating_add(gap.min));
+                }
+            }
+            for (parent_id, gap) in &event.parent_gaps {
+                let is_matched = matched.iter().any(|parent| parent.id == *parent_id);
+                if let (true, Some(max)) = (is_matched, gap.max) {
+                    max_gaps.push((*parent_id, max, offsets[j]));
+                }
+            }
+            // a simultaneous event must start right at the end of the matched one
+            for id in &event.simultaneous_events {
+                if matched.iter().any(|other| other.id == *id) {
+                    max_gaps.push((*id, 0, offsets[j]));
+                }
+            }
+        }
+
+        Deadline {
+            remaining_depth: offsets.into_iter().max().unwrap_or(0),
+            max_gaps,
+        }
+    }
+
+    /// The earliest start time of the instances waiting for the pattern event `match_idx` that
+    /// can still complete at `time`.
+    pub fn window_bound(&self, match_idx: usize, window: &dyn WindowPolicy, time: u64) -> u64 {
+        match self.deadlines.get(match_idx) {
+            Some(deadline) => window.window_bound_after(time, deadline.remaining_depth),
+            None => window.window_bound(time),
+        }
+    }
+
+    /// Returns `true` if `instance`, waiting for the pattern event `match_idx`, misses the maximum
+    /// gap from a matched parent for sure at `time`.
+    pub fn misses_gaps(&self, match_idx: usize, instance: &MatchInstance, time: u64) -> bool {
+        let Some(deadline) = self.deadlines.get(match_idx) else {
+            return false;
+        };
+        deadline.max_gaps.iter().any(|(parent_id, max, offset)| {
+            instance
+                .match_events
+                .iter()
+                .filter(|parent| parent.match_id as usize == *parent_id)
+                .any(|parent| {
+                    let parent_end = parent.raw_events.get_interval().1;
+                    parent_end.saturating_add(*max) < time.saturating_add(*offset)
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{Pattern, TimeGap};
+    use crate::process_layers::window_policy::SlidingWindow;
+
+    #[test]
+    fn test_deadlines() {
+        // e0 -> e1 -> e2 and e0 -> e2, e1 must start 2~5ms after e0, and e2 3ms after e1
+        let mut pattern = Pattern::from_graph(
+            &["v0", "v1", "v2", "v3"],
+            &[(0, 1, "e0"), (1, 2, "e1"), (2, 3, "e2")],
+            false,
+        );
+        let gap = |min, max| TimeGap { min, max };
+        pattern.events[1].parent_gaps = vec![(0, gap(2, Some(5)))];
+        pattern.events[2].parent_gaps = vec![(1, gap(3, None)), (0, gap(0, Some(6)))];
+        let decomposition = [SubPattern::new(0, pattern.events.iter().collect())];
+        let pruning = EarlyPruning::new(&decomposition);
+
+        let depths = pruning.deadlines.iter().map(|d| d.remaining_depth);
+        assert_eq!(depths.collect::<Vec<_>>(), [5, 3, 0]);
+        assert_eq!(pruning.deadlines[1].max_gaps, [(0, 5, 0), (0, 6, 3)]);
+        assert_eq!(pruning.deadlines[2].max_gaps, [(0, 6, 0)]);
+
+        let window = SlidingWindow::new(10);
+        assert_eq!(pruning.window_bound(0, &window, 20), 15);
+        assert_eq!(pruning.window_bound(2, &window, 20), 10);
+    }
+
+    #[test]
+    fn test_skip_flow() {
+        let mut pattern =
+            Pattern::from_graph(&["v0", "v1", "v2"], &[(0, 1, "e0"), (1, 2, "e1")], false);
+        pattern.events[1].event_type = PatternEventType::Flow;
+        pattern.events[1].parent_gaps = vec![(
+            0,
+            TimeGap {
+                min: 4,
+                max: Some(5),
+            },
+        )];
+        let decomposition = [SubPattern::new(0, pattern.events.iter().collect())];
+        let pruning = EarlyPruning::new(&decomposition);
+
+        assert_eq!(pruning.deadlines[0].remaining_depth, 0);
+        assert!(pruning.deadlines[1].max_gaps.is_empty());
+    }
+}
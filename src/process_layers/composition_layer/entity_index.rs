@@ -0,0 +1,152 @@
+use super::filter::Filter;
+use super::match_instance::MatchInstance;
+use ahash::{HashMap, HashSet};
+use serde::Serialize;
+
+/// The instance buckets of [`InstanceStorage`](super::InstanceStorage), keyed as in their maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bucket {
+    Subject(usize, u64),
+    Object(usize, u64),
+    Endpoints(usize, u64, u64),
+    Freq(usize, u64, u64),
+}
+
+/// Fails for [`Filter::MatchIdxOnly`], whose instances are bound to no entity.
+impl TryFrom<Filter> for Bucket {
+    type Error = ();
+
+    fn try_from(filter: Filter) -> Result<Self, Self::Error> {
+        match filter {
+            Filter::MatchIdxOnly { .. } => Err(()),
+            Filter::Subject { match_idx, subject } => Ok(Self::Subject(match_idx, subject)),
+            Filter::Object { match_idx, object } => Ok(Self::Object(match_idx, object)),
+            Filter::Endpoints {
+                match_idx,
+                subject,
+                object,
+            } => Ok(Self::Endpoints(match_idx, subject, object)),
+        }
+    }
+}
+
+/// A live match instance bound to an input entity, see
+/// [`CompositionLayer::instances_of_entity`](super::CompositionLayer::instances_of_entity).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EntityInstance {
+    pub sub_pattern_id: u32,
+    /// The state of the instance, as in `explain-pattern`.
+    pub state_id: u32,
+    /// The pattern events matched so far, in the match order.
+    pub pattern_events: Vec<u32>,
+    /// The pattern entities bound to the input entity.
+    pub pattern_entity_ids: Vec<u64>,
+    pub start_time: u64,
+    pub event_ids: Vec<u64>,
+}
+
+/// The minimum number of entries before the index is rebuilt, see `needs_rebuild()`.
+const MIN_REBUILD_ENTRIES: usize = 1024;
+
+/// A secondary index from the input entities to the buckets holding the match instances bound to
+/// them, for querying the live instances of an entity.
+///
+/// The index is a superset: the instances are dropped from the buckets without updating it, and
+/// the stale entries are removed when queried, or all at once when the index is rebuilt.
+#[derive(Debug)]
+pub struct EntityIndex {
+    buckets: HashMap<u64, HashSet<Bucket>>,
+    num_entries: usize,
+    /// Rebuild the index once `num_entries` exceeds this.
+    rebuild_at: usize,
+}
+
+impl Default for EntityIndex {
+    fn default() -> Self {
+        Self {
+            buckets: HashMap::default(),
+            num_entries: 0,
+            rebuild_at: MIN_REBUILD_ENTRIES,
+        }
+    }
+}
+
+impl EntityIndex {
+    /// Index `instance`, which is stored in `bucket`.
+    pub fn insert(&mut self, instance: &MatchInstance, bucket: Bucket) {
+        for (entity_id, _) in &instance.match_entities {
+            if self.buckets.entry(*entity_id).or_default().insert(bucket) {
+                self.num_entries += 1;
+            }
+        }
+    }
+
+    /// The buckets which may hold the instances bound to `entity_id`.
+    pub fn buckets_of(&self, entity_id: u64) -> Vec<Bucket> {
+        self.buckets
+            .get(&entity_id)
+            .map_or_else(Vec::new, |buckets| buckets.iter().copied().collect())
+    }
+
+    /// Remove the stale `bucket` of `entity_id`, which holds no instance bound to it any more.
+    pub fn remove(&mut self, entity_id: u64, bucket: Bucket) {
+        let Some(buckets) = self.buckets.get_mut(&entity_id) else {
+            return;
+        };
+        if buckets.remove(&bucket) {
+            self.num_entries -= 1;
+        }
+        if buckets.is_empty() {
+            self.buckets.remove(&entity_id);
+        }
+    }
+
+    /// Returns `true` if the index has grown twice as large as when it was last rebuilt, which
+    /// bounds the stale entries.
+    pub fn needs_rebuild(&self) -> bool {
+        self.num_entries > self.rebuild_at
+    }
+
+    /// Clear the index before rebuilding it.
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+        self.num_entries = 0;
+    }
+
+    /// Mark the end of a rebuild.
+    pub fn finish_rebuild(&mut self) {
+        self.rebuild_at = (self.num_entries * 2).max(MIN_REBUILD_ENTRIES);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smallvec::smallvec;
+
+    #[test]
+    fn test_entity_index() {
+        let mut index = EntityIndex::default();
+        let instance = MatchInstance {
+            match_entities: smallvec![(10, 0), (20, 1)],
+            ..Default::default()
+        };
+        index.insert(&instance, Bucket::Subject(1, 10));
+        index.insert(&instance, Bucket::Subject(1, 10));
+        index.insert(&instance, Bucket::Endpoints(2, 10, 20));
+        assert_eq!(index.num_entries, 4);
+
+        let mut buckets = index.buckets_of(10);
+        buckets.sort_by_key(|bucket| format!("{bucket:?}"));
+        assert_eq!(
+            buckets,
+            [Bucket::Endpoints(2, 10, 20), Bucket::Subject(1, 10)]
+        );
+        assert!(index.buckets_of(30).is_empty());
+
+        index.remove(20, Bucket::Subject(1, 10));
+        index.remove(20, Bucket::Endpoints(2, 10, 20));
+        assert!(index.buckets_of(20).is_empty());
+        assert_eq!(index.num_entries, 2);
+    }
+}
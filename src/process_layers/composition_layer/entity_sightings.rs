@@ -0,0 +1,72 @@
+use crate::input_event::InputEvent;
+use crate::pattern::PatternEntity;
+use crate::process_layers::regex_limits::build_regex_set;
+use ahash::HashMap;
+use itertools::Itertools;
+use regex::{Error, RegexSet};
+
+/// The last time each input entity was seen with a signature matching each pattern entity, for
+/// [`EntitySignatureMode::AnyInWindow`](super::EntitySignatureMode::AnyInWindow).
+pub struct EntitySightings {
+    regexes: RegexSet,
+    /// `entity_ids[i]` is the id of the pattern entity whose signature is the `i`-th regex.
+    entity_ids: Vec<usize>,
+    /// Keyed by (input entity id, pattern entity id).
+    last_seen: HashMap<(u64, usize), u64>,
+    window_size: u64,
+    cur_window_id: u64,
+}
+
+impl EntitySightings {
+    pub fn new<'p>(
+        entities: impl IntoIterator<Item = &'p PatternEntity>,
+        window_size: u64,
+        use_regex: bool,
+    ) -> Result<Self, Error> {
+        let entities: Vec<_> = entities.into_iter().unique_by(|entity| entity.id).collect();
+        let regex_patterns: Vec<String> = entities
+            .iter()
+            .map(|entity| format!("^{}$", entity.signature_regex(!use_regex)))
+            .collect();
+        Ok(Self {
+            regexes: build_regex_set(&regex_patterns)?,
+            entity_ids: entities.iter().map(|entity| entity.id).collect(),
+            last_seen: HashMap::default(),
+            window_size: window_size.max(1),
+            cur_window_id: 0,
+        })
+    }
+
+    /// Record the signatures of the subject and the object of `event`, seen at its timestamp.
+    pub fn record(&mut self, event: &InputEvent) {
+        for (input_id, signature) in [
+            (event.subject_id, event.get_subject_signature()),
+            (event.object_id, event.get_object_signature()),
+        ] {
+            for regex_idx in self.regexes.matches(signature).iter() {
+                let time = self
+                    .last_seen
+                    .entry((input_id, self.entity_ids[regex_idx]))
+                    .or_default();
+                *time = (*time).max(event.timestamp);
+            }
+        }
+    }
+
+    /// Drop the sightings before `window_bound` once the stream enters a new window at `time`.
+    pub fn expire(&mut self, time: u64, window_bound: u64) {
+        let window_id = time / self.window_size;
+        if window_id > self.cur_window_id {
+            self.cur_window_id = window_id;
+            self.last_seen.retain(|_, seen| *seen >= window_bound);
+        }
+    }
+
+    /// Returns `true` if the input entity `input_id` has been seen with a signature matching the
+    /// pattern entity `entity_id` at or after `window_bound`.
+    pub fn seen_since(&self, input_id: u64, entity_id: usize, window_bound: u64) -> bool {
+        self.last_seen
+            .get(&(input_id, entity_id))
+            .is_some_and(|seen| *seen >= window_bound)
+    }
+}
@@ -3,6 +3,7 @@ use super::entity_encode::EntityEncode;
 /// Information for shared-node relation, with ordinary entity ids.
 #[derive(Clone, Copy, Debug)]
 pub enum Filter {
+    #[allow(dead_code)]
     MatchIdxOnly {
         match_idx: usize,
     },
@@ -42,3 +43,16 @@ pub enum FilterInfo {
         object: EntityEncode,
     },
 }
+
+impl FilterInfo {
+    /// The match index of the pattern event this filter is for, if any.
+    pub fn match_idx(&self) -> Option<usize> {
+        match *self {
+            FilterInfo::None => None,
+            FilterInfo::MatchIdxOnly { match_idx }
+            | FilterInfo::Subject { match_idx, .. }
+            | FilterInfo::Object { match_idx, .. }
+            | FilterInfo::Endpoints { match_idx, .. } => Some(match_idx),
+        }
+    }
+}
@@ -3,8 +3,12 @@ use super::instance_storage::{InstanceStorage, StorageRequest};
 use super::pattern_info::FlowPattern;
 use super::state_table::StateTable;
 use crate::input_event::InputEvent;
+use crate::invariant::OrViolated;
 use crate::match_event::{MatchEvent, RawEvents};
 use crate::pattern::{PatternEntity, PatternEventType, SubPattern};
+use crate::process_layers::regex_limits::build_regex_set;
+use crate::process_layers::window_policy::{SlidingWindow, WindowPolicy};
+use crate::process_layers::BufferStats;
 use ahash::{HashMap, HashMapExt, HashSet};
 use regex::{Error, RegexSet, SetMatches};
 use std::collections::hash_map::Entry;
@@ -26,9 +30,11 @@ pub struct FlowRunner {
 
     node_match_results: HashMap<u64, NodeMatchResult>,
 
-    /// the length of the time window
+    /// the length of the time window, for the period of clearing the outdated states
     window_size: u64,
 
+    window: Rc<dyn WindowPolicy>,
+
     /// the time of the latest batch
     cur_time: u64,
 
@@ -46,11 +52,7 @@ impl FlowRunner {
         let mut add_regex_pattern = |ent: &PatternEntity| {
             if let Entry::Vacant(e) = sig_indices.entry(ent.id) {
                 e.insert(regex_patterns.len());
-                if !use_regex {
-                    regex_patterns.push(format!("^{}$", regex::escape(&ent.signature)));
-                } else {
-                    regex_patterns.push(format!("^{}$", &ent.signature));
-                }
+                regex_patterns.push(format!("^{}$", ent.signature_regex(!use_regex)));
             }
         };
 
@@ -66,7 +68,7 @@ impl FlowRunner {
             }
         }
 
-        let node_regexes = RegexSet::new(regex_patterns)?;
+        let node_regexes = build_regex_set(&regex_patterns)?;
         let flow_tracer = FlowTracer::new(window_size);
 
         Ok((
@@ -76,6 +78,7 @@ impl FlowRunner {
                 node_regexes,
                 node_match_results: HashMap::new(),
                 window_size,
+                window: Rc::new(SlidingWindow::new(window_size)),
                 cur_time: 0,
                 cur_window_id: 0,
             },
@@ -83,11 +86,30 @@ impl FlowRunner {
         ))
     }
 
+    pub fn set_window_policy(&mut self, window: Rc<dyn WindowPolicy>) {
+        self.flow_tracer.set_window_policy(window.clone());
+        self.window = window;
+    }
+
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.flow_tracer.buffer_stats()
+    }
+
+    /// Drop the traced flows and the node matches.
+    pub fn clear(&mut self) {
+        self.flow_tracer.clear();
+        self.new_flows.clear();
+        self.node_match_results.clear();
+    }
+
     pub fn set_batch(&mut self, batch: &[Rc<InputEvent>], time: u64) {
         if self.node_regexes.is_empty() {
             return;
         }
 
+        // the node matches of this batch are updated at `time`, so they are not taken as outdated
+        // below even if the previous batch is out of the window
+        self.cur_time = time;
         for event in batch {
             self.update_node_match(event.subject_id, event.get_subject_signature());
             self.update_node_match(event.object_id, event.get_object_signature());
@@ -114,11 +136,10 @@ impl FlowRunner {
             self.new_flows = self.flow_tracer.add_batch(iter, time, is_match);
         }
 
-        self.cur_time = time;
         let window_id = time / self.window_size;
         if window_id > self.cur_window_id {
             self.cur_window_id = window_id;
-            let window_bound = time.saturating_sub(self.window_size);
+            let window_bound = self.window.window_bound(time);
             self.flow_tracer.del_outdated(window_bound);
             self.node_match_results
                 .retain(|_, r| r.update_time >= window_bound);
@@ -143,14 +164,15 @@ impl FlowRunner {
         }
     }
 
+    /// Extend the instances waiting for the flow event of `info` with the new flows between the
+    /// nodes matching its entity signatures. A flow event beginning its sub-pattern extends the
+    /// empty instance, so that a pattern may consist solely of flow events.
     pub fn run<'p>(
         &self,
         info: &FlowPattern<'p>,
         storage: &mut InstanceStorage,
         state_table: &StateTable,
     ) {
-        let window_bound = self.cur_time.saturating_sub(self.window_size);
-
         let mut new_instances = vec![];
         for (dst, new_sources) in &self.new_flows {
             if !self.is_node_match(*dst, info.dst_sig_idx) {
@@ -162,7 +184,14 @@ impl FlowRunner {
                 if !self.is_node_match(*src, info.src_sig_idx) {
                     continue;
                 }
-                
+
+                let Some(start_time) = self
+                    .flow_tracer
+                    .get_updated_time(*src, *dst)
+                    .or_violated("an updated flow without the update time of its source")
+                else {
+                    continue;
+                };
                 // a matched flow event (src --> ... --> dst)
                 let flow = MatchEvent {
                     match_id: info.pattern.id as u32,
@@ -170,7 +199,7 @@ impl FlowRunner {
                     input_object_id: *dst,
                     pattern_subject_id: info.pattern.subject.id as u64,
                     pattern_object_id: info.pattern.object.id as u64,
-                    raw_events: RawEvents::Flow(self.flow_tracer.get_updated_time(*src, *dst).unwrap(), self.cur_time)
+                    raw_events: RawEvents::Flow(start_time, self.cur_time),
                 };
 
                 let request = StorageRequest {
@@ -179,10 +208,18 @@ impl FlowRunner {
                     object_id: *dst,
                     shared_node_info: info.shared_node_info,
                 };
-                for instance in storage.query_with_windowing(&request, window_bound) {
-                    if let Some(mut new_instance) =
-                        instance.clone_extend_flow(flow.clone(), info.shared_node_info)
+                for instance in storage.query_with_windowing(&request, self.window.as_ref(), self.cur_time) {
+                    if !instance.satisfy_gaps(&flow, &info.pattern.parent_gaps)
+                        || !instance
+                            .satisfy_distinct_entities(&flow, &info.pattern.distinct_entities)
                     {
+                        continue;
+                    }
+                    if let Some(mut new_instance) = instance.clone_extend_flow(
+                        flow.clone(),
+                        info.shared_node_info,
+                        info.pattern.wildcards(),
+                    ) {
                         new_instance.state_id = state_table.get_next_state(instance.state_id);
                         new_instances.push(new_instance);
                     }
@@ -1,10 +1,15 @@
 use std::borrow::Borrow;
 
-use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use ahash::{HashMap, HashMapExt, HashSet};
 use petgraph::algo::tarjan_scc;
 use petgraph::graphmap::DiGraphMap;
 use petgraph::Direction;
 use slab::Slab;
+use std::rc::Rc;
+
+use crate::invariant::OrViolated;
+use crate::process_layers::window_policy::{SlidingWindow, WindowPolicy};
+use crate::process_layers::BufferStats;
 
 /// A set of nodes. It keeps track of the time of each nodes in the set, and supports querying the
 /// updated nodes after union with another set.
@@ -138,6 +143,7 @@ impl ReachSet {
             }
         } else {
             self.node_update_time.insert(id, update_time);
+            self.oldest_time_hint = std::cmp::min(self.oldest_time_hint, update_time);
             self.latest_time = std::cmp::max(self.latest_time, update_time);
             return true;
         }
@@ -186,21 +192,33 @@ impl ReachSet {
 ///
 /// A flow is a path on a directed graph where the timestamp of each arc on the path is newer
 /// than that of its previous arc.
+///
+/// A flow is alive while its first arc is at or after the window bound of the current time, as
+/// for the match instances. Whether a flow whose first arc is exactly a window size old is still
+/// alive is thus decided by the `WindowBoundary` of the policy, see `set_window_policy()`.
 pub struct FlowTracer {
     /// `reach_sets.get(&src)` contains the set of nodes that can reach `src`
     /// (rather than reachable from `src`). 
     reach_sets: HashMap<u64, ReachSet>,
-    window_size: u64,
+    window: Rc<dyn WindowPolicy>,
 }
 
 impl FlowTracer {
     pub fn new(window_size: u64) -> Self {
         Self {
             reach_sets: HashMap::new(),
-            window_size,
+            window: Rc::new(SlidingWindow::new(window_size)),
         }
     }
 
+    pub fn set_window_policy(&mut self, window: Rc<dyn WindowPolicy>) {
+        self.window = window;
+    }
+
+    pub fn clear(&mut self) {
+        self.reach_sets.clear();
+    }
+
     /// add an arc connecting two nodes.
     ///
     /// Parameters:
@@ -224,7 +242,7 @@ impl FlowTracer {
         let src_match = is_match(src);
         let dst_match = is_match(dst);
 
-        let time_bound = time.saturating_sub(self.window_size);
+        let time_bound = self.window.window_bound(time);
         let mut dst_set = self
             .reach_sets
             .remove(&dst)
@@ -261,7 +279,7 @@ impl FlowTracer {
         time: u64,
         is_match: impl Fn(u64) -> bool,
     ) -> HashMap<u64, HashSet<u64>> {
-        let time_bound = time.saturating_sub(self.window_size);
+        let time_bound = self.window.window_bound(time);
         let batch_graph = DiGraphMap::<u64, ()>::from_edges(batch);
 
         let num_node = batch_graph.node_count();
@@ -312,9 +330,15 @@ impl FlowTracer {
         let mut last_union = vec![-1; new_sets.capacity()];
         for scc in sccs.iter().rev() {
             for src_id in scc {
-                let src_key = id2key.get(src_id).unwrap();
+                let Some(src_key) = id2key.get(src_id).or_violated("a node without reach set")
+                else {
+                    continue;
+                };
                 for dst_id in batch_graph.neighbors_directed(*src_id, Direction::Outgoing) {
-                    let dst_key = id2key.get(&dst_id).unwrap();
+                    let Some(dst_key) = id2key.get(&dst_id).or_violated("a node without reach set")
+                    else {
+                        continue;
+                    };
 
                     // prevent unioning with itself or unioning the same pair of sets multiple
                     // times
@@ -338,7 +362,9 @@ impl FlowTracer {
         // updated seperately later on.
         for scc in &sccs {
             if let Some((first, rest)) = scc.split_first() {
-                let key = id2key.get(first).unwrap();
+                let Some(key) = id2key.get(first).or_violated("a node without reach set") else {
+                    continue;
+                };
                 let set = new_sets.remove(*key);
                 for other_id in rest {
                     let new_set = set.clone();
@@ -359,6 +385,17 @@ impl FlowTracer {
             .and_then(|s| s.get_update_time_of(src))
     }
 
+    /// The nodes in all the reach sets, i.e. the (source, destination) pairs of the live flows.
+    pub fn buffer_stats(&self) -> BufferStats {
+        let mut stats = BufferStats::new("composition.reach_sets");
+        for set in self.reach_sets.values() {
+            for time in set.node_update_time.values() {
+                stats.add(*time, std::mem::size_of::<(u64, u64)>());
+            }
+        }
+        stats
+    }
+
     /// Remove the oudated internal states that is older than `time_bound`
     pub fn del_outdated(&mut self, time_bound: u64) {
         self.reach_sets.retain(|_, set| {
@@ -371,6 +408,8 @@ impl FlowTracer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::process_layers::window_policy::WindowBoundary;
+    use ahash::HashSetExt;
     use itertools::sorted;
 
     fn set_eq<A, B, V>(a: A, b: B) -> bool
@@ -400,6 +439,58 @@ mod tests {
         assert!(set_eq(t.add_arc(3, 4, 21, is_match), [2, 3]));
     }
 
+    fn with_boundary(boundary: WindowBoundary) -> FlowTracer {
+        let mut t = FlowTracer::new(10);
+        t.set_window_policy(Rc::new(SlidingWindow { size: 10, boundary }));
+        t
+    }
+
+    #[test]
+    fn test_window_boundary() {
+        let is_match = |_| true;
+
+        // the first arc is exactly a window size before the last one
+        let mut t = with_boundary(WindowBoundary::Inclusive);
+        assert!(set_eq(t.add_arc(1, 2, 0, is_match), [1]));
+        assert!(set_eq(t.add_arc(2, 3, 10, is_match), [1, 2]));
+        assert_eq!(t.get_updated_time(1, 3), Some(0));
+
+        let mut t = with_boundary(WindowBoundary::Exclusive);
+        assert!(set_eq(t.add_arc(1, 2, 0, is_match), [1]));
+        assert!(set_eq(t.add_arc(2, 3, 10, is_match), [2]));
+        assert_eq!(t.get_updated_time(1, 3), None);
+
+        let mut t = with_boundary(WindowBoundary::Inclusive);
+        t.add_batch([(1, 2), (4, 1)], 0, is_match);
+        assert_eq!(
+            t.add_batch([(2, 3), (3, 5)], 10, is_match),
+            map([(3, set([1, 2, 4])), (5, set([1, 2, 3, 4]))])
+        );
+
+        let mut t = with_boundary(WindowBoundary::Exclusive);
+        t.add_batch([(1, 2), (4, 1)], 0, is_match);
+        assert_eq!(
+            t.add_batch([(2, 3), (3, 5)], 10, is_match),
+            map([(3, set([2])), (5, set([2, 3]))])
+        );
+    }
+
+    #[test]
+    fn test_del_outdated_at_boundary() {
+        let is_match = |_| true;
+        let mut t = with_boundary(WindowBoundary::Inclusive);
+        t.add_arc(1, 2, 0, is_match);
+        t.add_arc(2, 3, 5, is_match);
+        t.del_outdated(0);
+        assert_eq!(t.get_updated_time(1, 3), Some(0));
+        assert!(set_eq(t.add_arc(3, 4, 10, is_match), [1, 2, 3]));
+
+        // the flow from 1 is older than the bound, though the set of 3 is created later
+        t.del_outdated(3);
+        assert_eq!(t.get_updated_time(1, 3), None);
+        assert_eq!(t.get_updated_time(2, 3), Some(5));
+    }
+
     #[test]
     fn test_cycle() {
         let mut t = FlowTracer::new(10);
@@ -490,3 +581,4 @@ mod tests {
         assert!(set_eq(t.add_arc(1, 2, 3, is_match), [1, 3, 5]));
     }
 }
+
@@ -1,3 +1,4 @@
+use super::entity_sightings::EntitySightings;
 use super::instance_storage::StorageRequest;
 use super::match_instance::FreqInstance;
 use super::pattern_info::{FreqPattern, SharedNodeInfo, SinglePattern};
@@ -5,33 +6,193 @@ use super::state_table::StateTable;
 use super::{InstanceStorage, MatchInstance};
 use crate::input_event::InputEvent;
 use crate::match_event::{MatchEvent, RawEvents};
-use crate::pattern::{PatternEvent, PatternEventType, SubPattern};
+use crate::pattern::{PatternEntity, PatternEvent, PatternEventType, SubPattern};
+use crate::process_layers::matching_layer::FuzzyTolerance;
+use crate::process_layers::regex_limits::build_regex_set;
+use crate::process_layers::window_policy::{SlidingWindow, WindowPolicy};
 use regex::{Error, RegexSet, SetMatches};
+use std::cell::RefCell;
+use std::ops::Range;
 use std::rc::Rc;
 
 pub struct InstanceRunner {
-    window_size: u64,
-    /// A set of all event and entity signatures of a given pattern.
+    window: Rc<dyn WindowPolicy>,
+    /// A set of all event and entity signatures of a given pattern, with an entry for each
+    /// alternative of the event signatures.
     event_regexes: RegexSet,
+    /// The entries of `event_regexes` of each signature index, which are the alternatives of the
+    /// signature.
+    alternatives: Vec<Range<usize>>,
+    /// The `(pattern event id, alternative index)` of each entry of `event_regexes`, along with
+    /// the number of input events matching it.
+    alternative_hits: Vec<((usize, usize), u64)>,
+    /// The regex patterns of `event_regexes` as built by `new()`, where the entity signatures
+    /// must match at every event. See `set_entity_signature_mode()`.
+    strict_patterns: Vec<String>,
+    window_size: u64,
+    use_regex: bool,
+    /// The pattern entities (subject, object) of each signature index whose signatures are checked
+    /// against `entity_sightings` rather than by `event_regexes`.
+    sighted_entities: Vec<Option<(usize, usize)>>,
+    entity_sightings: Option<EntitySightings>,
+    /// The signatures of each entry of `event_regexes`, matched with `fuzzy_tolerance` instead
+    /// if it is set. See `set_fuzzy_tolerance()`.
+    fuzzy_signatures: Vec<FuzzySignature>,
+    fuzzy_tolerance: Option<FuzzyTolerance>,
     cur_time: u64,
-    cur_batch: Vec<(Rc<InputEvent>, SetMatches)>,
+    cur_batch: Vec<(Rc<InputEvent>, SignatureMatches)>,
+    /// See `set_signature_cache()`.
+    signature_cache: Option<SignatureCache>,
+    /// If `true`, events in the same batch sharing the same subject, object and signatures are
+    /// matched to default-typed pattern events as a whole. See `set_zip_events()`.
+    zip_events: bool,
+    /// See `set_event_uniqueness()`.
+    event_uniqueness: bool,
+}
+
+/// Where the signature of a pattern entity must match those of the input entity bound to it.
+/// Entity signatures may change over time, e.g. when a process is renamed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EntitySignatureMode {
+    /// At every input event the entity participates in.
+    #[default]
+    EveryEvent,
+    /// Only at the input event binding the entity first in a sub-pattern instance. The later
+    /// events of the instance only need to have the same entity id.
+    FirstBinding,
+    /// At any input event of the entity (matching the pattern or not) within the window, up to
+    /// the event binding it.
+    AnyInWindow,
+}
+
+/// The plain signatures of an entry of the regex set, see `InstanceRunner::set_fuzzy_tolerance()`.
+/// An entity signature of `None` matches any entity, as relaxed by `EntitySignatureMode`.
+#[derive(Clone)]
+struct FuzzySignature {
+    event: String,
+    subject: Option<String>,
+    object: Option<String>,
+}
+
+impl FuzzySignature {
+    fn is_match(&self, tolerance: FuzzyTolerance, event: &InputEvent) -> bool {
+        let is_entity_match = |signature: &Option<String>, input: &str| {
+            signature
+                .as_ref()
+                .is_none_or(|signature| tolerance.is_match(signature, input))
+        };
+        tolerance.is_match(&self.event, event.get_event_signature())
+            && is_entity_match(&self.subject, event.get_subject_signature())
+            && is_entity_match(&self.object, event.get_object_signature())
+    }
+}
+
+/// The entries of the regex set matched by an input event.
+#[derive(Debug, Clone)]
+enum SignatureMatches {
+    Regex(SetMatches),
+    /// The indices of the entries matched fuzzily, in the ascending order.
+    Fuzzy(Vec<usize>),
 }
 
-/// Construct a regex pattern (signature) from a pattern event. The construction is identical to 
-/// that of input events. See `InputEvent.get_signatures()`.
-fn construct_regex_pattern(pattern: &PatternEvent, escape_regex: bool) -> String {
-    let regex_pattern = format!(
-        "{}\0{}\0{}",
-        pattern.signature, pattern.subject.signature, pattern.object.signature
-    );
+impl SignatureMatches {
+    fn matched(&self, regex_idx: usize) -> bool {
+        match self {
+            SignatureMatches::Regex(matches) => matches.matched(regex_idx),
+            SignatureMatches::Fuzzy(matches) => matches.binary_search(&regex_idx).is_ok(),
+        }
+    }
+
+    fn matched_any(&self) -> bool {
+        match self {
+            SignatureMatches::Regex(matches) => matches.matched_any(),
+            SignatureMatches::Fuzzy(matches) => !matches.is_empty(),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        let (regex, fuzzy) = match self {
+            SignatureMatches::Regex(matches) => (Some(matches.iter()), None),
+            SignatureMatches::Fuzzy(matches) => (None, Some(matches.iter().copied())),
+        };
+        regex.into_iter().flatten().chain(fuzzy.into_iter().flatten())
+    }
+}
+
+/// The signature matches of the latest batch, shared by the Composition layers matching the same
+/// signatures, e.g. those of a pattern at several window sizes. The first layer given a batch
+/// matches its signatures, and the others reuse them. See
+/// [`CompositionLayer::set_signature_cache`](super::CompositionLayer::set_signature_cache).
+#[derive(Debug, Clone, Default)]
+pub struct SignatureCache(Rc<RefCell<CachedBatch>>);
+
+#[derive(Debug, Default)]
+struct CachedBatch {
+    /// The first event and the length of the batch, which identify it.
+    batch: Option<(Rc<InputEvent>, usize)>,
+    /// The events of the batch matching any signature, in the input order.
+    matches: Vec<(Rc<InputEvent>, SignatureMatches)>,
+}
 
-    if escape_regex {
-        format!("^{}$", regex::escape(&regex_pattern))
-    } else {
-        format!("^{}$", regex_pattern)
+impl SignatureCache {
+    /// The events of `batch` matching any signature, along with their matches, which are given
+    /// by `match_event` unless `batch` is the cached one.
+    fn matches(
+        &self,
+        batch: &[Rc<InputEvent>],
+        match_event: impl Fn(&InputEvent) -> Option<SignatureMatches>,
+    ) -> Vec<(Rc<InputEvent>, SignatureMatches)> {
+        let mut cached = self.0.borrow_mut();
+        let is_cached = match (&cached.batch, batch.first()) {
+            (Some((first, len)), Some(event)) => Rc::ptr_eq(first, event) && *len == batch.len(),
+            _ => false,
+        };
+        if !is_cached {
+            cached.batch = batch.first().map(|event| (event.clone(), batch.len()));
+            cached.matches = (batch.iter())
+                .filter_map(|event| Some((event.clone(), match_event(event)?)))
+                .collect();
+        }
+        cached.matches.clone()
     }
 }
 
+/// Construct the regex patterns (signatures) from a pattern event, one for each alternative of
+/// its event signature. The construction is identical to that of input events. See
+/// `InputEvent.get_signatures()`.
+pub(crate) fn construct_regex_patterns(pattern: &PatternEvent, escape_regex: bool) -> Vec<String> {
+    construct_relaxed_regex_patterns(pattern, escape_regex, (false, false))
+}
+
+/// Same as `construct_regex_patterns()`, except that the signatures of the subject and/or the
+/// object are replaced by ones matching any signature, as given by `relaxed`.
+fn construct_relaxed_regex_patterns(
+    pattern: &PatternEvent,
+    escape_regex: bool,
+    relaxed: (bool, bool),
+) -> Vec<String> {
+    let escape = |signature: &str| {
+        if escape_regex {
+            regex::escape(signature)
+        } else {
+            signature.to_string()
+        }
+    };
+    let entity_signature = |entity: &PatternEntity, relaxed: bool| {
+        if relaxed {
+            "[^\0]*".to_string()
+        } else {
+            entity.signature_regex(escape_regex)
+        }
+    };
+    let subject = entity_signature(&pattern.subject, relaxed.0);
+    let object = entity_signature(&pattern.object, relaxed.1);
+    pattern
+        .signatures()
+        .map(|signature| format!("^{}\0{}\0{}$", escape(signature), subject, object))
+        .collect()
+}
+
 impl InstanceRunner {
     pub fn new(
         decomposition: &[SubPattern],
@@ -39,34 +200,251 @@ impl InstanceRunner {
         use_regex: bool,
     ) -> Result<Self, Error> {
         let mut patterns = vec![];
+        let mut alternatives = vec![];
+        let mut alternative_hits = vec![];
+        let mut fuzzy_signatures = vec![];
         for sub_pattern in decomposition {
             for pattern in &sub_pattern.events {
                 use PatternEventType::*;
                 if matches!(pattern.event_type, Flow) {
                     continue;
                 }
-                patterns.push(construct_regex_pattern(pattern, !use_regex));
+                let start = patterns.len();
+                for (i, regex_pattern) in construct_regex_patterns(pattern, !use_regex)
+                    .into_iter()
+                    .enumerate()
+                {
+                    patterns.push(regex_pattern);
+                    alternative_hits.push(((pattern.id, i), 0));
+                }
+                fuzzy_signatures.extend(pattern.signatures().map(|signature| FuzzySignature {
+                    event: signature.to_string(),
+                    subject: Some(pattern.subject.signature.clone()),
+                    object: Some(pattern.object.signature.clone()),
+                }));
+                alternatives.push(start..patterns.len());
             }
         }
-        let event_regexes = RegexSet::new(patterns)?;
+        let event_regexes = build_regex_set(&patterns)?;
         Ok(Self {
-            window_size,
+            window: Rc::new(SlidingWindow::new(window_size)),
             event_regexes,
+            sighted_entities: vec![None; alternatives.len()],
+            alternatives,
+            alternative_hits,
+            strict_patterns: patterns,
+            window_size,
+            use_regex,
+            entity_sightings: None,
+            fuzzy_signatures,
+            fuzzy_tolerance: None,
             cur_time: 0,
             cur_batch: vec![],
+            signature_cache: None,
+            zip_events: false,
+            event_uniqueness: true,
         })
     }
 
+    /// Enable or disable event zipping.
+    ///
+    /// When enabled, the duplicated events (events with the same subject, object and signatures)
+    /// in a batch are matched to a default-typed pattern event as a single `MatchEvent` with
+    /// `RawEvents::Multiple`, instead of producing one match instance per duplicate.
+    pub fn set_zip_events(&mut self, zip_events: bool) {
+        self.zip_events = zip_events;
+    }
+
+    /// If disabled, an input event may match multiple pattern events in an instance.
+    pub fn set_event_uniqueness(&mut self, event_uniqueness: bool) {
+        self.event_uniqueness = event_uniqueness;
+    }
+
+    /// Match the signatures of the input events to those of the pattern events within
+    /// `tolerance`, treating the pattern signatures as plain strings, instead of matching the
+    /// regexes.
+    pub fn set_fuzzy_tolerance(&mut self, tolerance: FuzzyTolerance) {
+        self.fuzzy_tolerance = Some(tolerance);
+    }
+
+    pub fn set_window_policy(&mut self, window: Rc<dyn WindowPolicy>) {
+        self.window = window;
+    }
+
+    /// Take the signature matches of each batch from `signature_cache`, if another runner with
+    /// the same signatures has matched them already.
+    pub fn set_signature_cache(&mut self, signature_cache: SignatureCache) {
+        self.signature_cache = Some(signature_cache);
+    }
+
+    /// Rebuild the signatures of `patterns`, given as (signature index, pattern event,
+    /// shared-node information), so that their entity signatures are matched as in `mode`.
+    pub fn set_entity_signature_mode<'p>(
+        &mut self,
+        mode: EntitySignatureMode,
+        patterns: impl IntoIterator<Item = (usize, &'p PatternEvent, SharedNodeInfo)>,
+    ) -> Result<(), Error> {
+        let mut regex_patterns = self.strict_patterns.clone();
+        let mut sighted_entities = vec![None; self.alternatives.len()];
+        let mut entities = vec![];
+        for (signature_idx, pattern, shared_node_info) in patterns {
+            let relaxed = match mode {
+                EntitySignatureMode::EveryEvent => continue,
+                // the shared entities are bound by the preceding events of the sub-pattern
+                EntitySignatureMode::FirstBinding => match shared_node_info {
+                    SharedNodeInfo::None => continue,
+                    SharedNodeInfo::Subject => (true, false),
+                    SharedNodeInfo::Object => (false, true),
+                    SharedNodeInfo::Both => (true, true),
+                },
+                EntitySignatureMode::AnyInWindow => {
+                    sighted_entities[signature_idx] = Some((pattern.subject.id, pattern.object.id));
+                    entities.extend([&pattern.subject, &pattern.object]);
+                    (true, true)
+                }
+            };
+            let relaxed_patterns =
+                construct_relaxed_regex_patterns(pattern, !self.use_regex, relaxed);
+            for (regex_idx, regex_pattern) in self.alternatives[signature_idx]
+                .clone()
+                .zip(relaxed_patterns)
+            {
+                regex_patterns[regex_idx] = regex_pattern;
+                let fuzzy_signature = &mut self.fuzzy_signatures[regex_idx];
+                if relaxed.0 {
+                    fuzzy_signature.subject = None;
+                }
+                if relaxed.1 {
+                    fuzzy_signature.object = None;
+                }
+            }
+        }
+
+        self.event_regexes = build_regex_set(&regex_patterns)?;
+        self.entity_sightings = if entities.is_empty() {
+            None
+        } else {
+            let entity_sightings =
+                EntitySightings::new(entities, self.window_size, self.use_regex)?;
+            Some(entity_sightings)
+        };
+        self.sighted_entities = sighted_entities;
+        Ok(())
+    }
+
     /// Match the input batch of events against all pattern events (in terms of signatures). 
     pub fn set_batch(&mut self, batch: &[Rc<InputEvent>], time: u64) {
         self.cur_time = time;
         self.cur_batch.clear();
-        for event in batch {
-            let result = self.event_regexes.matches(event.get_signatures());
-            if result.matched_any() {
-                self.cur_batch.push((Rc::clone(event), result));
+        if let Some(entity_sightings) = &mut self.entity_sightings {
+            for event in batch {
+                entity_sightings.record(event);
+            }
+            entity_sightings.expire(time, self.window.window_bound(time));
+        }
+        if let Some(signature_cache) = &self.signature_cache {
+            let matches = signature_cache.matches(batch, |event| self.signature_matches(event));
+            for (event, result) in matches {
+                self.add_match(event, result);
+            }
+        } else if let [event] = batch {
+            // Live streams mostly come in batches of a single event matching no pattern event.
+            // Checking it first avoids allocating the matches of the regex set, and there is
+            // nothing to sort.
+            if self.fuzzy_tolerance.is_some() || self.event_regexes.is_match(event.get_signatures())
+            {
+                self.match_event(event);
+            }
+            return;
+        } else {
+            for event in batch {
+                self.match_event(event);
             }
         }
+
+        if self.zip_events {
+            // stable sort, so that the events in a group are still ordered by their input order
+            self.cur_batch.sort_by(|(a, _), (b, _)| {
+                (a.subject_id, a.object_id, a.get_signatures()).cmp(&(
+                    b.subject_id,
+                    b.object_id,
+                    b.get_signatures(),
+                ))
+            });
+        }
+    }
+
+    /// Add `event` to the current batch if it matches any pattern event.
+    fn match_event(&mut self, event: &Rc<InputEvent>) {
+        if let Some(result) = self.signature_matches(event) {
+            self.add_match(Rc::clone(event), result);
+        }
+    }
+
+    /// The entries of the regex set matched by `event`, or `None` if it matches none of them.
+    fn signature_matches(&self, event: &InputEvent) -> Option<SignatureMatches> {
+        let result = match self.fuzzy_tolerance {
+            Some(tolerance) => SignatureMatches::Fuzzy(
+                self.fuzzy_signatures
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, signature)| signature.is_match(tolerance, event))
+                    .map(|(regex_idx, _)| regex_idx)
+                    .collect(),
+            ),
+            None => SignatureMatches::Regex(self.event_regexes.matches(event.get_signatures())),
+        };
+        result.matched_any().then_some(result)
+    }
+
+    /// Add `event` matching `result` to the current batch.
+    fn add_match(&mut self, event: Rc<InputEvent>, result: SignatureMatches) {
+        for regex_idx in result.iter() {
+            self.alternative_hits[regex_idx].1 += 1;
+        }
+        self.cur_batch.push((event, result));
+    }
+
+    /// The events in the current batch matching the signature `signature_idx`.
+    pub fn matched_events(&self, signature_idx: usize) -> impl Iterator<Item = &Rc<InputEvent>> {
+        self.cur_batch
+            .iter()
+            .filter(move |(event, sig_match)| self.is_matched(event, sig_match, signature_idx))
+            .map(|(event, _)| event)
+    }
+
+    /// Returns `true` if any alternative of the signature `signature_idx` is in `sig_match`, and
+    /// the entities of `event` have been seen with their signatures if required.
+    fn is_matched(
+        &self,
+        event: &InputEvent,
+        sig_match: &SignatureMatches,
+        signature_idx: usize,
+    ) -> bool {
+        self.alternatives[signature_idx]
+            .clone()
+            .any(|regex_idx| sig_match.matched(regex_idx))
+            && self.is_sighted(event, signature_idx)
+    }
+
+    /// Returns `true` if the subject and the object of `event` have been seen with the signatures
+    /// of the pattern entities of `signature_idx` within the window, or they need not be. See
+    /// `EntitySignatureMode::AnyInWindow`.
+    fn is_sighted(&self, event: &InputEvent, signature_idx: usize) -> bool {
+        let (Some(entity_sightings), Some((subject, object))) =
+            (&self.entity_sightings, self.sighted_entities[signature_idx])
+        else {
+            return true;
+        };
+        let window_bound = self.window.window_bound(self.cur_time);
+        entity_sightings.seen_since(event.subject_id, subject, window_bound)
+            && entity_sightings.seen_since(event.object_id, object, window_bound)
+    }
+
+    /// The number of input events matching each alternative signature so far, keyed by
+    /// `(pattern event id, alternative index)`.
+    pub fn alternative_hits(&self) -> &[((usize, usize), u64)] {
+        &self.alternative_hits
     }
 
     /// Execute the composition logic for default-typed pattern event
@@ -76,20 +454,27 @@ impl InstanceRunner {
         storage: &mut InstanceStorage,
         state_table: &StateTable,
     ) {
-        let window_bound = self.cur_time.saturating_sub(self.window_size);
-
         let mut new_instances = vec![];
-        for (event, sig_match) in &self.cur_batch {
-            if !sig_match.matched(info.signature_idx) {
+        let groups = self
+            .cur_batch
+            .chunk_by(|(a, _), (b, _)| self.zip_events && is_duplicated(a, b));
+        for group in groups {
+            let (event, sig_match) = &group[0];
+            if !self.is_matched(event, sig_match, info.signature_idx) {
                 continue;
             }
+            let raw_events = if group.len() == 1 {
+                RawEvents::Single(event.clone())
+            } else {
+                RawEvents::Multiple(group.iter().map(|(e, _)| e.clone()).collect())
+            };
             let request = StorageRequest {
                 match_idx: info.match_idx,
                 subject_id: event.subject_id,
                 object_id: event.object_id,
                 shared_node_info: info.shared_node_info,
             };
-            for instance in storage.query_with_windowing(&request, window_bound) {
+            for instance in storage.query_with_windowing(&request, self.window.as_ref(), self.cur_time) {
 
                 let new_event = MatchEvent {
                     match_id: info.pattern.id as u32,
@@ -97,11 +482,25 @@ impl InstanceRunner {
                     input_object_id: event.object_id,
                     pattern_subject_id: info.pattern.subject.id as u64,
                     pattern_object_id: info.pattern.object.id as u64,
-                    raw_events: RawEvents::Single(event.clone())
+                    raw_events: raw_events.clone()
                 };
-                if let Some(mut new_instance) =
-                    instance.clone_extend(new_event, info.shared_node_info)
+                if !instance.satisfy_gaps(&new_event, &info.pattern.parent_gaps)
+                    || !instance
+                        .satisfy_distinct_entities(&new_event, &info.pattern.distinct_entities)
+                    || !instance
+                        .satisfy_simultaneous(&new_event, &info.pattern.simultaneous_events)
                 {
+                    continue;
+                }
+                if let Some(mut new_instance) = instance.clone_extend(
+                    new_event,
+                    info.shared_node_info,
+                    info.pattern.wildcards(),
+                    self.event_uniqueness,
+                ) {
+                    if !new_instance.bind_attributes(&info.pattern.same_attributes) {
+                        continue;
+                    }
                     new_instance.state_id = state_table.get_next_state(instance.state_id);
                     new_instances.push(new_instance);
                 }
@@ -117,10 +516,10 @@ impl InstanceRunner {
         storage: &mut InstanceStorage,
         state_table: &StateTable,
     ) {
-        let window_bound = self.cur_time.saturating_sub(self.window_size);
+        let window_bound = self.window.window_bound(self.cur_time);
 
         for (event, sig_match) in &self.cur_batch {
-            if !sig_match.matched(info.signature_idx) {
+            if !self.is_matched(event, sig_match, info.signature_idx) {
                 continue;
             }
 
@@ -132,7 +531,7 @@ impl InstanceRunner {
             };
 
             let mut new_freq_instances = vec![];
-            for instance in storage.query_with_windowing(&request, window_bound) {
+            for instance in storage.query_with_windowing(&request, self.window.as_ref(), self.cur_time) {
                 if !check_unshared_entity(instance, event, info.shared_node_info) {
                     continue;
                 }
@@ -159,11 +558,25 @@ impl InstanceRunner {
                         pattern_object_id: info.pattern.object.id as u64,
                         raw_events: RawEvents::Multiple(raw_events.into_boxed_slice())
                     };
-
-                    if let Some(mut new_instance) = instance
+                    if !instance
                         .instance
-                        .clone_extend(new_event, info.shared_node_info)
+                        .satisfy_gaps(&new_event, &info.pattern.parent_gaps)
+                        || !instance
+                            .instance
+                            .satisfy_distinct_entities(&new_event, &info.pattern.distinct_entities)
                     {
+                        continue;
+                    }
+
+                    if let Some(mut new_instance) = instance.instance.clone_extend(
+                        new_event,
+                        info.shared_node_info,
+                        info.pattern.wildcards(),
+                        self.event_uniqueness,
+                    ) {
+                        if !new_instance.bind_attributes(&info.pattern.same_attributes) {
+                            continue;
+                        }
                         new_instance.state_id =
                             state_table.get_next_state(instance.instance.state_id);
                         new_instances.push(new_instance);
@@ -176,6 +589,13 @@ impl InstanceRunner {
     }
 }
 
+/// Returns `true` if the two events have the same subject, object and signatures.
+fn is_duplicated(a: &InputEvent, b: &InputEvent) -> bool {
+    a.subject_id == b.subject_id
+        && a.object_id == b.object_id
+        && a.get_signatures() == b.get_signatures()
+}
+
 /// Returns `true` if the unshared entities are not in the [`instance`]
 fn check_unshared_entity(
     instance: &MatchInstance,
@@ -1,3 +1,5 @@
+use super::early_pruning::EarlyPruning;
+use super::entity_index::{Bucket, EntityIndex};
 use super::filter::Filter;
 use super::filter::FilterInfo;
 use super::match_instance::{FreqInstance, MatchInstance};
@@ -5,8 +7,14 @@ use super::pattern_info::SharedNodeInfo;
 use super::state::StateInfo;
 use super::state_table::StateTable;
 use crate::match_event::MatchEvent;
-use crate::universal_match_event::UniversalMatchEvent;
+use crate::process_layers::parse_layer::contains_retracted;
+use crate::process_layers::{BufferStats, EventTrace, WindowPolicy};
+use crate::trace::trace_event;
 use ahash::{HashMap, HashMapExt};
+use itertools::Itertools;
+use serde::Serialize;
+use serde_json::{json, Value};
+use smallvec::SmallVec;
 use std::borrow::Borrow;
 use std::hash::Hash;
 use std::slice::IterMut;
@@ -65,10 +73,30 @@ pub struct InstanceStorage {
     pub freq_instance: HashMap<(usize, u64, u64), Vec<FreqInstance>>,
 
     pub output_instances: Vec<(u32, MatchInstance)>,
+
+    /// The instances waiting for a static pattern event, to be extended right away by the
+    /// Composition layer with the static context graph.
+    pub static_instances: Vec<MatchInstance>,
+
+    /// Total number of match instances ever stored, for monitoring.
+    pub num_new_instances: u64,
+
+    /// Number of match instances ever stored in each state, for attributing the cost of a
+    /// pattern to its sub-patterns.
+    pub num_new_instances_by_state: Vec<u64>,
+
+    /// Drops the instances that can no longer complete on query, see `query_with_windowing()`.
+    pub pruning: EarlyPruning,
+
+    /// Records the new instances extended with the traced events.
+    pub event_trace: Option<EventTrace>,
+
+    /// See `enable_entity_index()`.
+    pub entity_index: Option<EntityIndex>,
 }
 
 impl InstanceStorage {
-    pub fn init_from_state_table(state_table: &StateTable) -> Self {
+    pub fn init_from_state_table(state_table: &StateTable, pruning: EarlyPruning) -> Self {
         let filter_infos = state_table.table.iter().map(|(_, filter_info)| filter_info);
         let simple_instances = Self::init_simple_instances(filter_infos);
         Self {
@@ -78,6 +106,38 @@ impl InstanceStorage {
             endpoints_instances: HashMap::new(),
             freq_instance: HashMap::new(),
             output_instances: Vec::new(),
+            static_instances: Vec::new(),
+            num_new_instances: 0,
+            num_new_instances_by_state: vec![0; state_table.table.len()],
+            pruning,
+            event_trace: None,
+            entity_index: None,
+        }
+    }
+
+    /// Record the extension of `new_instance` with the traced events of its last match event.
+    fn trace_new_instance(
+        event_trace: &EventTrace,
+        new_instance: &MatchInstance,
+        state: &StateInfo,
+    ) {
+        let Some(new_event) = new_instance.match_events.last() else {
+            return;
+        };
+        let detail = || {
+            json!({
+                "state_id": new_instance.state_id,
+                "pattern_event": new_event.match_id,
+                "instance_events": new_instance.event_ids,
+            })
+        };
+        let new_ids = new_event.raw_events.get_ids();
+        event_trace.record(new_ids, "composition", "extend", detail);
+        if let StateInfo::Output { subpattern_id } = state {
+            let all_ids = (new_instance.match_events.iter()).flat_map(|e| e.raw_events.get_ids());
+            event_trace.record(all_ids, "composition", "sub-pattern match", || {
+                json!({ "sub_pattern_id": subpattern_id, "instance_events": new_instance.event_ids })
+            });
         }
     }
 
@@ -99,9 +159,11 @@ impl InstanceStorage {
                     MatchInstance {
                         start_time: u64::MAX,
                         match_events: Box::new([]),
-                        match_entities: Box::new([]),
+                        match_entities: SmallVec::new(),
                         state_id: state_id as u32,
-                        event_ids: Box::new([]),
+                        event_ids: SmallVec::new(),
+                        entity_attributes: vec![],
+                        wildcard_entities: vec![],
                     },
                 );
             }
@@ -109,16 +171,24 @@ impl InstanceStorage {
         simple_instances
     }
 
+    /// Query the instances waiting for the pattern event of `request`. The instances that have
+    /// expired at `time`, or can no longer complete their sub-pattern (see [`EarlyPruning`]), are
+    /// dropped.
     pub fn query_with_windowing<'a>(
         &'a mut self,
         request: &StorageRequest,
-        window_bound: u64,
+        window: &dyn WindowPolicy,
+        time: u64,
     ) -> StorageResponseMut<'a, MatchInstance> {
         let match_idx = request.match_idx;
         let subject_id = request.subject_id;
         let object_id = request.object_id;
 
-        let is_valid = |inst: &MatchInstance| inst.start_time >= window_bound;
+        let window_bound = self.pruning.window_bound(match_idx, window, time);
+        let pruning = &self.pruning;
+        let is_valid = |inst: &MatchInstance| {
+            inst.start_time >= window_bound && !pruning.misses_gaps(match_idx, inst, time)
+        };
 
         match request.shared_node_info {
             // Get the (empty) instance for the beginning of a subpattern.
@@ -180,37 +250,63 @@ impl InstanceStorage {
         state_table: &StateTable,
     ) {
         for new_instance in new_instances {
+            self.num_new_instances += 1;
+            self.num_new_instances_by_state[new_instance.state_id as usize] += 1;
+            trace_event!(
+                TRACE,
+                state_id = new_instance.state_id,
+                start_time = new_instance.start_time,
+                entities = ?new_instance.match_entities,
+                "new instance"
+            );
             let (state_info, filter_info) = state_table.get(new_instance.state_id);
-            if let StateInfo::Output { subpattern_id } = state_info {
-                self.output_instances.push((subpattern_id, new_instance));
+            if let Some(event_trace) = &self.event_trace {
+                Self::trace_new_instance(event_trace, &new_instance, &state_info);
+            }
+            match state_info {
+                StateInfo::Output { subpattern_id } => {
+                    self.output_instances.push((subpattern_id, new_instance));
+                    continue;
+                }
+                StateInfo::Static { .. } => {
+                    self.static_instances.push(new_instance);
+                    continue;
+                }
+                _ => {}
+            }
+            let Some(filter) = Self::extract_filter(&new_instance, &filter_info) else {
                 continue;
+            };
+            if let (Some(entity_index), Ok(bucket)) = (&mut self.entity_index, filter.try_into()) {
+                entity_index.insert(&new_instance, bucket);
             }
-            match Self::extract_filter(&new_instance, &filter_info) {
-                Some(Filter::Subject { match_idx, subject }) => {
+            match filter {
+                Filter::MatchIdxOnly { .. } => continue,
+                Filter::Subject { match_idx, subject } => {
                     self.subject_instances
                         .entry((match_idx, subject))
                         .or_default()
                         .push(new_instance);
                 }
-                Some(Filter::Object { match_idx, object }) => {
+                Filter::Object { match_idx, object } => {
                     self.object_instances
                         .entry((match_idx, object))
                         .or_default()
                         .push(new_instance);
                 }
-                Some(Filter::Endpoints {
+                Filter::Endpoints {
                     match_idx,
                     subject,
                     object,
-                }) => {
+                } => {
                     self.endpoints_instances
                         .entry((match_idx, subject, object))
                         .or_default()
                         .push(new_instance);
                 }
-                _ => continue,
             }
         }
+        self.try_rebuild_entity_index();
     }
 
     pub fn store_freq_instances(
@@ -218,12 +314,307 @@ impl InstanceStorage {
         new_instances: impl Iterator<Item = ((usize, u64, u64), FreqInstance)>,
     ) {
         for (filter, instance) in new_instances {
+            self.num_new_instances += 1;
+            self.num_new_instances_by_state[instance.instance.state_id as usize] += 1;
+            if let Some(entity_index) = &mut self.entity_index {
+                let (match_idx, subject, object) = filter;
+                entity_index.insert(&instance.instance, Bucket::Freq(match_idx, subject, object));
+            }
             self.freq_instance.entry(filter).or_default().push(instance);
         }
+        self.try_rebuild_entity_index();
+    }
+
+    /// Index the stored instances by their input entities, so that `instances_of_entity()`
+    /// scans only the buckets holding the instances of the entity.
+    pub fn enable_entity_index(&mut self) {
+        if self.entity_index.is_none() {
+            self.entity_index = Some(EntityIndex::default());
+            self.rebuild_entity_index();
+        }
+    }
+
+    /// Rebuild the entity index if it has accumulated too many stale entries.
+    fn try_rebuild_entity_index(&mut self) {
+        if matches!(&self.entity_index, Some(index) if index.needs_rebuild()) {
+            self.rebuild_entity_index();
+        }
+    }
+
+    fn rebuild_entity_index(&mut self) {
+        let Some(entity_index) = &mut self.entity_index else {
+            return;
+        };
+        entity_index.clear();
+        for ((match_idx, subject), instances) in &self.subject_instances {
+            for instance in instances {
+                entity_index.insert(instance, Bucket::Subject(*match_idx, *subject));
+            }
+        }
+        for ((match_idx, object), instances) in &self.object_instances {
+            for instance in instances {
+                entity_index.insert(instance, Bucket::Object(*match_idx, *object));
+            }
+        }
+        for ((match_idx, subject, object), instances) in &self.endpoints_instances {
+            for instance in instances {
+                let bucket = Bucket::Endpoints(*match_idx, *subject, *object);
+                entity_index.insert(instance, bucket);
+            }
+        }
+        for ((match_idx, subject, object), instances) in &self.freq_instance {
+            for instance in instances {
+                let bucket = Bucket::Freq(*match_idx, *subject, *object);
+                entity_index.insert(&instance.instance, bucket);
+            }
+        }
+        entity_index.finish_rebuild();
+    }
+
+    /// The stored instances bound to the input entity `entity_id`, starting at or after
+    /// `window_bound`. The instances in `output_instances` and the unfinished frequency instances
+    /// are included. It requires `enable_entity_index()`, otherwise nothing is returned.
+    pub fn instances_of_entity(
+        &mut self,
+        entity_id: u64,
+        window_bound: u64,
+    ) -> Vec<&MatchInstance> {
+        let Some(entity_index) = &mut self.entity_index else {
+            return vec![];
+        };
+        let is_bound = |instance: &&MatchInstance| {
+            instance.start_time >= window_bound
+                && (instance.match_entities.iter()).any(|(input_id, _)| *input_id == entity_id)
+        };
+        let mut found = vec![];
+        for bucket in entity_index.buckets_of(entity_id) {
+            let instances: Vec<&MatchInstance> = match bucket {
+                Bucket::Subject(match_idx, subject) => {
+                    let instances = self.subject_instances.get(&(match_idx, subject));
+                    instances.into_iter().flatten().collect()
+                }
+                Bucket::Object(match_idx, object) => {
+                    let instances = self.object_instances.get(&(match_idx, object));
+                    instances.into_iter().flatten().collect()
+                }
+                Bucket::Endpoints(match_idx, subject, object) => {
+                    let instances = self.endpoints_instances.get(&(match_idx, subject, object));
+                    instances.into_iter().flatten().collect()
+                }
+                Bucket::Freq(match_idx, subject, object) => {
+                    let instances = self.freq_instance.get(&(match_idx, subject, object));
+                    let instances = instances.into_iter().flatten();
+                    instances.map(|freq| &freq.instance).collect()
+                }
+            };
+            let len = found.len();
+            found.extend(instances.into_iter().filter(is_bound));
+            if found.len() == len {
+                entity_index.remove(entity_id, bucket);
+            }
+        }
+        found.extend(
+            (self.output_instances.iter())
+                .map(|(_, instance)| instance)
+                .filter(is_bound),
+        );
+        found
+    }
+
+    /// Remove all the stored instances, including those not yet output.
+    pub fn clear(&mut self) {
+        self.subject_instances.clear();
+        self.object_instances.clear();
+        self.endpoints_instances.clear();
+        self.freq_instance.clear();
+        self.output_instances.clear();
+        self.static_instances.clear();
+        self.rebuild_entity_index();
+    }
+
+    /// Remove the instances containing any of the sorted `retracted_ids`. Returns the number of
+    /// removed instances.
+    pub fn purge_retracted(&mut self, retracted_ids: &[u64]) -> usize {
+        fn purge<K>(map: &mut HashMap<K, Vec<MatchInstance>>, retracted_ids: &[u64]) -> usize {
+            let mut num_purged = 0;
+            map.retain(|_, instances| {
+                let len = instances.len();
+                instances.retain(|instance| {
+                    !contains_retracted(instance.event_ids.iter().copied(), retracted_ids)
+                });
+                num_purged += len - instances.len();
+                !instances.is_empty()
+            });
+            num_purged
+        }
+
+        let mut num_purged = purge(&mut self.subject_instances, retracted_ids)
+            + purge(&mut self.object_instances, retracted_ids)
+            + purge(&mut self.endpoints_instances, retracted_ids);
+        self.freq_instance.retain(|_, instances| {
+            let len = instances.len();
+            instances.retain(|freq_instance| {
+                let event_ids = freq_instance.instance.event_ids.iter();
+                !contains_retracted(
+                    event_ids.chain(&freq_instance.cur_set).copied(),
+                    retracted_ids,
+                )
+            });
+            num_purged += len - instances.len();
+            !instances.is_empty()
+        });
+        let len = self.output_instances.len();
+        self.output_instances.retain(|(_, instance)| {
+            !contains_retracted(instance.event_ids.iter().copied(), retracted_ids)
+        });
+        num_purged + len - self.output_instances.len()
+    }
+
+    /// The statistics of each kind of stored instances. The placeholder instances in
+    /// `simple_instances` are not counted.
+    pub fn buffer_stats(&self) -> Vec<BufferStats> {
+        fn collect<'a, K: 'a>(
+            name: &str,
+            instances: impl Iterator<Item = (&'a K, &'a Vec<MatchInstance>)>,
+        ) -> BufferStats {
+            let mut stats = BufferStats::new(name);
+            for instance in instances.flat_map(|(_, v)| v) {
+                stats.add(instance.start_time, instance.approx_bytes());
+            }
+            stats
+        }
+
+        let mut freq_stats = BufferStats::new("composition.freq_instances");
+        for instance in self.freq_instance.values().flatten() {
+            freq_stats.add(instance.instance.start_time, instance.approx_bytes());
+        }
+        let mut output_stats = BufferStats::new("composition.output_instances");
+        for (_, instance) in &self.output_instances {
+            output_stats.add(instance.start_time, instance.approx_bytes());
+        }
+
+        vec![
+            collect(
+                "composition.subject_instances",
+                self.subject_instances.iter(),
+            ),
+            collect("composition.object_instances", self.object_instances.iter()),
+            collect(
+                "composition.endpoints_instances",
+                self.endpoints_instances.iter(),
+            ),
+            freq_stats,
+            output_stats,
+        ]
+    }
+
+    /// The number of stored instances in each state, indexed by the state id. The placeholder
+    /// instances, which have matched no event, are not counted.
+    pub fn live_instances(&self, state_table: &StateTable) -> Vec<u64> {
+        let mut counts = vec![0; state_table.table.len()];
+        let instances = (self.subject_instances.values().flatten())
+            .chain(self.object_instances.values().flatten())
+            .chain(self.endpoints_instances.values().flatten())
+            .chain(self.freq_instance.values().flatten().map(|freq| &freq.instance));
+        for instance in instances {
+            if !instance.match_events.is_empty() {
+                counts[instance.state_id as usize] += 1;
+            }
+        }
+        counts
     }
 
-    fn extract_filter(instance: &MatchInstance, filter_info: &FilterInfo) -> Option<Filter> {
-        let endpoints_extractor = |event: &MatchEvent| (event.input_subject_id, event.input_object_id);
+    /// The statistics of the stored instances grouped by their states, i.e. by the pattern prefix
+    /// they have matched. The placeholder instances, which have matched no event, are not counted.
+    pub fn state_stats(&self) -> Vec<BufferStats> {
+        let mut states: HashMap<u32, BufferStats> = HashMap::new();
+        let mut add = |instance: &MatchInstance, bytes: usize| {
+            if instance.match_events.is_empty() {
+                return;
+            }
+            let stats = states.entry(instance.state_id).or_insert_with(|| {
+                let mut prefix: Vec<u32> =
+                    instance.match_events.iter().map(|e| e.match_id).collect();
+                prefix.dedup();
+                BufferStats::new(format!(
+                    "composition.state {} (events {:?})",
+                    instance.state_id, prefix
+                ))
+            });
+            stats.add(instance.start_time, bytes);
+        };
+
+        let instances = (self.subject_instances.values().flatten())
+            .chain(self.object_instances.values().flatten())
+            .chain(self.endpoints_instances.values().flatten());
+        for instance in instances {
+            add(instance, instance.approx_bytes());
+        }
+        for instance in self.freq_instance.values().flatten() {
+            add(&instance.instance, instance.approx_bytes());
+        }
+
+        states.into_values().collect()
+    }
+
+    /// Serialize all the stored instances into a JSON object for offline inspection.
+    pub fn dump_state(&self) -> Value {
+        json!({
+            "simple_instances": Self::dump_instance_map(
+                self.simple_instances.iter().map(|(k, v)| (k, std::slice::from_ref(v))),
+                |match_idx| json!({ "match_idx": match_idx }),
+            ),
+            "subject_instances": Self::dump_instance_map(
+                self.subject_instances.iter().map(|(k, v)| (k, v.as_slice())),
+                |(match_idx, subject)| json!({ "match_idx": match_idx, "subject": subject }),
+            ),
+            "object_instances": Self::dump_instance_map(
+                self.object_instances.iter().map(|(k, v)| (k, v.as_slice())),
+                |(match_idx, object)| json!({ "match_idx": match_idx, "object": object }),
+            ),
+            "endpoints_instances": Self::dump_instance_map(
+                self.endpoints_instances.iter().map(|(k, v)| (k, v.as_slice())),
+                |(match_idx, subject, object)| {
+                    json!({ "match_idx": match_idx, "subject": subject, "object": object })
+                },
+            ),
+            "freq_instances": Self::dump_instance_map(
+                self.freq_instance.iter().map(|(k, v)| (k, v.as_slice())),
+                |(match_idx, subject, object)| {
+                    json!({ "match_idx": match_idx, "subject": subject, "object": object })
+                },
+            ),
+            "output_instances": self.output_instances.iter().map(|(subpattern_id, instance)| {
+                json!({ "subpattern_id": subpattern_id, "instance": instance })
+            }).collect_vec(),
+        })
+    }
+
+    /// Dump the entries of an instance map, sorted by their keys so that the output is stable.
+    fn dump_instance_map<'a, K, V>(
+        entries: impl Iterator<Item = (&'a K, &'a [V])>,
+        key_to_json: impl Fn(&K) -> Value,
+    ) -> Vec<Value>
+    where
+        K: Ord + 'a,
+        V: Serialize + 'a,
+    {
+        entries
+            .sorted_by(|a, b| a.0.cmp(b.0))
+            .map(|(key, instances)| {
+                let mut entry = key_to_json(key);
+                entry["instances"] = json!(instances);
+                entry
+            })
+            .collect()
+    }
+
+    pub(super) fn extract_filter(
+        instance: &MatchInstance,
+        filter_info: &FilterInfo,
+    ) -> Option<Filter> {
+        let endpoints_extractor =
+            |event: &MatchEvent| (event.input_subject_id, event.input_object_id);
         let filter = match filter_info {
             FilterInfo::None => return None,
             FilterInfo::MatchIdxOnly { match_idx } => Filter::MatchIdxOnly {
@@ -263,6 +654,7 @@ impl InstanceStorage {
 
 #[cfg(test)]
 mod tests {
+    use crate::match_event::RawEvents;
     use crate::process_layers::composition_layer::entity_encode::EntityEncode;
 
     use super::*;
@@ -289,4 +681,84 @@ mod tests {
         let placeholder = &simple_instances[&2];
         assert_eq!(placeholder.state_id, 3);
     }
+
+    #[test]
+    fn test_dump_state() {
+        let mut storage = InstanceStorage {
+            simple_instances: HashMap::new(),
+            subject_instances: HashMap::new(),
+            object_instances: HashMap::new(),
+            endpoints_instances: HashMap::new(),
+            freq_instance: HashMap::new(),
+            output_instances: Vec::new(),
+            static_instances: Vec::new(),
+            num_new_instances: 0,
+            num_new_instances_by_state: vec![],
+            pruning: EarlyPruning::default(),
+            event_trace: None,
+            entity_index: None,
+        };
+        let instance = |state_id| MatchInstance {
+            state_id,
+            ..Default::default()
+        };
+        storage.subject_instances.insert((1, 20), vec![instance(2)]);
+        storage
+            .subject_instances
+            .insert((1, 10), vec![instance(1), instance(3)]);
+
+        let dump = storage.dump_state();
+        let subject_instances = dump["subject_instances"].as_array().unwrap();
+        assert_eq!(subject_instances.len(), 2);
+        // entries are sorted by their keys
+        assert_eq!(subject_instances[0]["subject"], 10);
+        assert_eq!(subject_instances[0]["instances"][1]["state_id"], 3);
+        assert_eq!(subject_instances[1]["subject"], 20);
+        assert!(dump["endpoints_instances"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_state_stats() {
+        let mut storage = InstanceStorage {
+            simple_instances: HashMap::new(),
+            subject_instances: HashMap::new(),
+            object_instances: HashMap::new(),
+            endpoints_instances: HashMap::new(),
+            freq_instance: HashMap::new(),
+            output_instances: Vec::new(),
+            static_instances: Vec::new(),
+            num_new_instances: 0,
+            num_new_instances_by_state: vec![],
+            pruning: EarlyPruning::default(),
+            event_trace: None,
+            entity_index: None,
+        };
+        let instance = |state_id, start_time| MatchInstance {
+            state_id,
+            start_time,
+            match_events: Box::new([MatchEvent {
+                match_id: 0,
+                input_subject_id: 10,
+                input_object_id: 20,
+                pattern_subject_id: 0,
+                pattern_object_id: 1,
+                raw_events: RawEvents::Flow(start_time, start_time),
+            }]),
+            ..Default::default()
+        };
+        storage
+            .subject_instances
+            .insert((1, 10), vec![instance(1, 5), instance(3, 7)]);
+        storage
+            .endpoints_instances
+            .insert((2, 10, 20), vec![instance(1, 2), MatchInstance::default()]);
+
+        let mut stats = storage.state_stats();
+        stats.sort_by_key(|state| state.name.clone());
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "composition.state 1 (events [0])");
+        assert_eq!(stats[0].len, 2);
+        assert_eq!(stats[0].oldest_time, Some(2));
+        assert_eq!(stats[1].len, 1);
+    }
 }
@@ -1,11 +1,14 @@
 use super::pattern_info::SharedNodeInfo;
 use crate::input_event::InputEvent;
 use crate::match_event::MatchEvent;
+use crate::pattern::TimeGap;
 use crate::universal_match_event::UniversalMatchEvent;
 use itertools::Itertools;
+use serde::Serialize;
+use smallvec::SmallVec;
 use std::cmp::min;
-use std::collections::HashSet;
-use std::fmt::Debug;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Debug, Display};
 use std::rc::Rc;
 
 pub type InputEntityId = u64;
@@ -13,35 +16,128 @@ pub type PatternEntityId = u64;
 pub type InputEventId = u64;
 pub type PatternEventId = u32;
 
-fn dup_extend_event_ids(event_ids: &[u64], new_ids: &[u64]) -> Option<Box<[u64]>> {
-    let mut new_event_ids = Vec::with_capacity(event_ids.len() + new_ids.len());
-    new_event_ids.extend_from_slice(event_ids);
-    new_event_ids.extend_from_slice(new_ids);
+/// The entities and event ids of a [MatchInstance] are stored inline up to these lengths, which
+/// covers the instances of a single event, so that most instances allocate only their
+/// `match_events`.
+pub type MatchEntities = SmallVec<[(InputEntityId, PatternEntityId); 2]>;
+pub type EventIds = SmallVec<[InputEventId; 2]>;
+/// Sorted array of `(pattern entity id, payload column, value)`, the attribute values bound to
+/// the pattern entities by `PatternEvent::same_attributes`.
+pub type EntityAttributes = Vec<(PatternEntityId, usize, Box<str>)>;
+/// Sorted array of `(pattern entity id, input entity id)`, the bindings of the wildcard entities,
+/// see `PatternEntity::is_wildcard`.
+pub type WildcardEntities = Vec<(PatternEntityId, InputEntityId)>;
+
+/// Returns `None` if an event id duplicates, unless `event_uniqueness` is disabled.
+fn dup_extend_event_ids(
+    event_ids: &[u64],
+    new_ids: impl Iterator<Item = u64>,
+    event_uniqueness: bool,
+) -> Option<EventIds> {
+    let mut new_event_ids = EventIds::from_slice(event_ids);
+    new_event_ids.extend(new_ids);
     new_event_ids.sort_unstable();
-    for (a, b) in new_event_ids.iter().tuple_windows() {
-        if *a == *b {
-            return None;
+    if event_uniqueness {
+        for (a, b) in new_event_ids.iter().tuple_windows() {
+            if *a == *b {
+                return None;
+            }
         }
     }
 
-    Some(new_event_ids.into_boxed_slice())
+    Some(new_event_ids)
 }
 
+/// Bind the endpoints of `event` not bound by the earlier events of `instance` (see
+/// [`SharedNodeInfo`]). The wildcard endpoints, given by `wildcards` as (subject, object), are
+/// bound in `wildcard_entities` and may share an input entity with any other pattern entity.
+/// Returns `None` if the bindings conflict.
 fn dup_extend_entities_by_event(
+    instance: &MatchInstance,
+    event: &MatchEvent,
+    shared_node_info: SharedNodeInfo,
+    wildcards: (bool, bool),
+) -> Option<(MatchEntities, WildcardEntities)> {
+    use SharedNodeInfo::*;
+    if wildcards == (false, false) {
+        let match_entities =
+            dup_extend_bound_entities(&instance.match_entities, event, shared_node_info)?;
+        return Some((match_entities, instance.wildcard_entities.clone()));
+    }
+
+    // a pattern entity binds a single input entity
+    if event.pattern_subject_id == event.pattern_object_id
+        && event.input_subject_id != event.input_object_id
+    {
+        return Option::None;
+    }
+    let (new_subject, new_object) = match shared_node_info {
+        None => (true, true),
+        Subject => (false, true),
+        Object => (true, false),
+        Both => (false, false),
+    };
+    let mut match_entities = instance.match_entities.clone();
+    let mut wildcard_entities = instance.wildcard_entities.clone();
+    let endpoints = [
+        (
+            new_subject,
+            wildcards.0,
+            event.input_subject_id,
+            event.pattern_subject_id,
+        ),
+        (
+            new_object,
+            wildcards.1,
+            event.input_object_id,
+            event.pattern_object_id,
+        ),
+    ];
+    for (is_new, is_wildcard, input_id, pattern_id) in endpoints {
+        if !is_new {
+            continue;
+        }
+        if is_wildcard {
+            if let Err(idx) = wildcard_entities.binary_search(&(pattern_id, input_id)) {
+                wildcard_entities.insert(idx, (pattern_id, input_id));
+            }
+        } else {
+            match match_entities.binary_search_by_key(&input_id, |(input_id, _)| *input_id) {
+                Ok(idx) if match_entities[idx].1 != pattern_id => return Option::None,
+                Ok(_) => {}
+                Err(idx) => match_entities.insert(idx, (input_id, pattern_id)),
+            }
+        }
+    }
+    Some((match_entities, wildcard_entities))
+}
+
+/// Same as `dup_extend_entities_by_event()` for an event without wildcard endpoints.
+fn dup_extend_bound_entities(
     match_entities: &[(InputEntityId, PatternEntityId)],
     event: &MatchEvent,
     shared_node_info: SharedNodeInfo,
-) -> Option<Box<[(InputEntityId, PatternEntityId)]>> {
+) -> Option<MatchEntities> {
     use SharedNodeInfo::*;
     match shared_node_info {
         None => {
-            if event.input_subject_id < event.input_object_id {
-                Some(Box::new([
+            // distinct pattern entities match distinct input entities, and vice versa
+            let is_pattern_loop = event.pattern_subject_id == event.pattern_object_id;
+            if is_pattern_loop != (event.input_subject_id == event.input_object_id) {
+                return Option::None;
+            }
+            if is_pattern_loop {
+                Some(SmallVec::from_slice(&[(
+                    event.input_subject_id,
+                    event.pattern_subject_id,
+                )]))
+            } else if event.input_subject_id < event.input_object_id {
+                Some(SmallVec::from_slice(&[
                     (event.input_subject_id, event.pattern_subject_id),
                     (event.input_object_id, event.pattern_object_id),
                 ]))
             } else {
-                Some(Box::new([
+                Some(SmallVec::from_slice(&[
                     (event.input_object_id, event.pattern_object_id),
                     (event.input_subject_id, event.pattern_subject_id),
                 ]))
@@ -57,7 +153,7 @@ fn dup_extend_entities_by_event(
             event.input_subject_id,
             event.pattern_subject_id,
         ),
-        Both => Some(Box::from(match_entities)),
+        Both => Some(SmallVec::from_slice(match_entities)),
     }
 }
 
@@ -68,12 +164,12 @@ fn dup_extend_entities_list(
     match_entities: &[(InputEntityId, PatternEntityId)],
     entity_id: u64,
     pattern_id: u64,
-) -> Option<Box<[(InputEntityId, PatternEntityId)]>> {
+) -> Option<MatchEntities> {
     if match_entities.is_empty() {
         return None;
     }
 
-    let mut new_entities = Vec::with_capacity(match_entities.len() + 1);
+    let mut new_entities = MatchEntities::with_capacity(match_entities.len() + 1);
     let mut iter = match_entities.iter().peekable();
     for entry in iter.take_while_ref(|(ent_id, _)| *ent_id < entity_id) {
         new_entities.push(*entry);
@@ -86,12 +182,12 @@ fn dup_extend_entities_list(
     }
 
     new_entities.push((entity_id, pattern_id));
-    new_entities.extend(iter);
+    new_entities.extend(iter.copied());
 
-    Some(new_entities.into_boxed_slice())
+    Some(new_entities)
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 #[cfg_attr(test, derive(Default))]
 pub struct MatchInstance {
     pub start_time: u64,
@@ -100,9 +196,14 @@ pub struct MatchInstance {
     /// Sorted array of `(input entity id, pattern entity id)`.
     ///
     /// `match_entities.len()` == number of entities in this sub-pattern match.
-    pub match_entities: Box<[(InputEntityId, PatternEntityId)]>,
-    pub event_ids: Box<[InputEventId]>,
+    pub match_entities: MatchEntities,
+    pub event_ids: EventIds,
     pub state_id: u32,
+    /// Empty unless the pattern has `SameAttribute` constraints, see [MatchInstance::bind_attributes].
+    pub entity_attributes: EntityAttributes,
+    /// The bindings of the wildcard entities, which are not in `match_entities` since they are
+    /// exempt from the node uniqueness.
+    pub wildcard_entities: WildcardEntities,
 }
 
 impl MatchInstance {
@@ -110,24 +211,33 @@ impl MatchInstance {
         Self {
             start_time: 0,
             match_events: Box::new([]),
-            match_entities: Box::new([]),
-            event_ids: Box::new([]),
+            match_entities: SmallVec::new(),
+            event_ids: SmallVec::new(),
             state_id: 0,
+            entity_attributes: vec![],
+            wildcard_entities: vec![],
         }
     }
 
     /// clone this instance and insert the [new_event] into the new instance. [filter_info] is the
     /// filter of the original instance. This method uses this information to extract the newly
-    /// added entities.
+    /// added entities. If [event_uniqueness] is `false`, the [new_event] may reuse the input events
+    /// already in this instance. [wildcards] are given by `PatternEvent::wildcards()`.
     pub fn clone_extend(
         &self,
         new_event: MatchEvent,
         shared_node_info: SharedNodeInfo,
+        wildcards: (bool, bool),
+        event_uniqueness: bool,
     ) -> Option<Self> {
         // TODO: Perhaps we need not extend ids explicitly?
-        let event_ids = dup_extend_event_ids(&self.event_ids, &new_event.raw_events.get_ids().collect_vec())?;
-        let match_entities =
-            dup_extend_entities_by_event(&self.match_entities, &new_event, shared_node_info)?;
+        let event_ids = dup_extend_event_ids(
+            &self.event_ids,
+            new_event.raw_events.get_ids(),
+            event_uniqueness,
+        )?;
+        let (match_entities, wildcard_entities) =
+            dup_extend_entities_by_event(self, &new_event, shared_node_info, wildcards)?;
         let start_time = min(self.start_time, new_event.raw_events.get_interval().0);
 
         let mut new_match_events = Vec::with_capacity(self.match_events.len() + 1);
@@ -139,7 +249,9 @@ impl MatchInstance {
             match_events: new_match_events.into_boxed_slice(),
             match_entities,
             event_ids,
-            ..self.clone()
+            state_id: self.state_id,
+            entity_attributes: self.entity_attributes.clone(),
+            wildcard_entities,
         })
     }
 
@@ -150,10 +262,11 @@ impl MatchInstance {
         &self,
         new_event: MatchEvent,
         shared_node_info: SharedNodeInfo,
+        wildcards: (bool, bool),
     ) -> Option<Self> {
         let event_ids = self.event_ids.clone();
-        let match_entities =
-            dup_extend_entities_by_event(&self.match_entities, &new_event, shared_node_info)?;
+        let (match_entities, wildcard_entities) =
+            dup_extend_entities_by_event(self, &new_event, shared_node_info, wildcards)?;
         let start_time = min(self.start_time, new_event.raw_events.get_interval().0);
 
         let mut new_match_events = Vec::with_capacity(self.match_events.len() + 1);
@@ -165,10 +278,113 @@ impl MatchInstance {
             match_events: new_match_events.into_boxed_slice(),
             match_entities,
             event_ids,
-            ..self.clone()
+            state_id: self.state_id,
+            entity_attributes: self.entity_attributes.clone(),
+            wildcard_entities,
+        })
+    }
+
+    /// Approximate size of this instance in bytes, excluding the input events it shares.
+    pub fn approx_bytes(&self) -> usize {
+        fn heap_bytes<A: smallvec::Array>(list: &SmallVec<A>) -> usize {
+            if list.spilled() {
+                list.capacity() * size_of::<A::Item>()
+            } else {
+                0
+            }
+        }
+        size_of::<Self>()
+            + size_of_val(&*self.match_events)
+            + heap_bytes(&self.match_entities)
+            + heap_bytes(&self.event_ids)
+            + self.entity_attributes.capacity() * size_of::<(PatternEntityId, usize, Box<str>)>()
+            + self.wildcard_entities.capacity() * size_of::<(PatternEntityId, InputEntityId)>()
+    }
+
+    /// Return true if the [new_event] keeps the time gaps to its parent events in this instance.
+    pub fn satisfy_gaps(&self, new_event: &MatchEvent, parent_gaps: &[(usize, TimeGap)]) -> bool {
+        let start_time = new_event.raw_events.get_interval().0;
+        parent_gaps.iter().all(|(parent_id, gap)| {
+            self.match_events
+                .iter()
+                .filter(|parent| parent.match_id as usize == *parent_id)
+                .all(|parent| gap.allows(parent.raw_events.get_interval().1, start_time))
         })
     }
 
+    /// Return true if the [new_event] has the same timestamp as the events in
+    /// `simultaneous_events` matched in this instance.
+    pub fn satisfy_simultaneous(
+        &self,
+        new_event: &MatchEvent,
+        simultaneous_events: &[usize],
+    ) -> bool {
+        let interval = new_event.raw_events.get_interval();
+        self.match_events
+            .iter()
+            .filter(|event| simultaneous_events.contains(&(event.match_id as usize)))
+            .all(|event| event.raw_events.get_interval() == interval)
+    }
+
+    /// Return true if the [new_event] keeps the pairs in `distinct_entities` matching different
+    /// input entities. Entities not matched yet are ignored.
+    pub fn satisfy_distinct_entities(
+        &self,
+        new_event: &MatchEvent,
+        distinct_entities: &[(usize, usize)],
+    ) -> bool {
+        let input_entity = |pattern_id: usize| {
+            let pattern_id = pattern_id as u64;
+            if new_event.pattern_subject_id == pattern_id {
+                Some(new_event.input_subject_id)
+            } else if new_event.pattern_object_id == pattern_id {
+                Some(new_event.input_object_id)
+            } else {
+                let mut bindings = (self.match_entities.iter())
+                    .map(|(input_id, pattern_id)| (*pattern_id, *input_id))
+                    .chain(self.wildcard_entities.iter().copied());
+                bindings
+                    .find(|(id, _)| *id == pattern_id)
+                    .map(|(_, input_id)| input_id)
+            }
+        };
+        distinct_entities
+            .iter()
+            .all(|(a, b)| match (input_entity(*a), input_entity(*b)) {
+                (Some(a), Some(b)) => a != b,
+                _ => true,
+            })
+    }
+
+    /// Bind the payload columns in `same_attributes` of the last matched event to its entities.
+    /// Returns false if they differ from the values already bound, by the earlier events or the
+    /// other input events of the last one. Input events without the column are ignored.
+    pub fn bind_attributes(&mut self, same_attributes: &[(usize, usize)]) -> bool {
+        let Some(new_event) = self.match_events.last() else {
+            return true;
+        };
+        for (entity_id, column) in same_attributes {
+            let key = (*entity_id as u64, *column);
+            for input_event in new_event.raw_events.get_events() {
+                let Some(value) = input_event.payload.as_ref().and_then(|p| p.get(*column)) else {
+                    continue;
+                };
+                match self
+                    .entity_attributes
+                    .binary_search_by(|(entity_id, column, _)| (*entity_id, *column).cmp(&key))
+                {
+                    Ok(idx) if *self.entity_attributes[idx].2 != **value => return false,
+                    Ok(_) => {}
+                    Err(idx) => {
+                        let attribute = (key.0, key.1, value.as_str().into());
+                        self.entity_attributes.insert(idx, attribute);
+                    }
+                }
+            }
+        }
+        true
+    }
+
     /// Return true if the match_event is already in this [MatchInstance]
     pub fn contains_event(&self, input_event_id: u64) -> bool {
         self.event_ids.binary_search(&input_event_id).is_ok()
@@ -197,17 +413,64 @@ impl MatchInstance {
     }
 }
 
-impl<'p> Debug for MatchInstance {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Debug for MatchInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the input event ids of each matched pattern event, rather than the whole input events
+        let match_events: BTreeMap<_, _> = self
+            .match_events
+            .iter()
+            .map(|event| (event.match_id, event.raw_events.get_ids().collect_vec()))
+            .collect();
         f.debug_struct("MatchInstance")
             .field("start_time", &self.start_time)
-            // .field("match_events", &self.match_events)
+            .field("match_events", &match_events)
             .field("match_entities", &self.match_entities)
+            .field("wildcard_entities", &self.wildcard_entities)
+            .field("event_ids", &self.event_ids)
             .field("state_id", &self.state_id)
             .finish()
     }
 }
 
+/// Writes the time range, the input events of the matched pattern events and the bindings of
+/// the pattern entities, e.g. `<1.000, 2.500>[3, 4] {0: 17, 1: 20, 2: 21}`.
+impl Display for MatchInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let latest_time = self
+            .match_events
+            .iter()
+            .map(|event| event.raw_events.get_interval().1)
+            .max()
+            .unwrap_or(self.start_time);
+        let start_t = self.start_time as f32 / 1000.0;
+        let end_t = latest_time as f32 / 1000.0;
+        let event_str = self.match_events.iter().join(", ");
+        let entity_str = format_entity_bindings(&self.match_entities, &self.wildcard_entities);
+        write!(
+            f,
+            "<{start_t:.3}, {end_t:.3}>[{event_str}] {{{entity_str}}}"
+        )
+    }
+}
+
+/// Format the `(input entity id, pattern entity id)` pairs, along with the bindings of the
+/// wildcard entities, as `pattern: input` in the order of the pattern entities, e.g. `0: 17, 1: 20`.
+pub fn format_entity_bindings(
+    match_entities: &[(InputEntityId, PatternEntityId)],
+    wildcard_entities: &[(PatternEntityId, InputEntityId)],
+) -> String {
+    let wildcard_entities =
+        (wildcard_entities.iter()).map(|(pattern_id, input_id)| (input_id, pattern_id));
+    (match_entities
+        .iter()
+        .map(|(input_id, pattern_id)| (input_id, pattern_id)))
+    .chain(wildcard_entities)
+    .sorted_by_key(|(_, pattern_entity_id)| **pattern_entity_id)
+    .map(|(input_entity_id, pattern_entity_id)| format!("{pattern_entity_id}: {input_entity_id}"))
+    .join(", ")
+}
+
+#[derive(Serialize)]
 pub struct FreqInstance {
     pub instance: MatchInstance,
     pub start_time: u64,
@@ -228,6 +491,14 @@ impl FreqInstance {
         }
     }
 
+    /// Approximate size of this instance in bytes, excluding the input events it shares.
+    pub fn approx_bytes(&self) -> usize {
+        size_of::<Self>() - size_of::<MatchInstance>()
+            + self.instance.approx_bytes()
+            + self.cur_set.capacity() * size_of::<u64>()
+            + self.new_events.capacity() * size_of::<Rc<InputEvent>>()
+    }
+
     /// Adds an event into the frequency tracing set
     ///
     /// Returns `ture` if the event was not previously in the set; otherwise, `false` is returned.
@@ -262,11 +533,12 @@ pub enum InstanceAction<'p> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::match_event::RawEvents;
 
     #[test]
     fn test_contains_event() {
         let instance = MatchInstance {
-            event_ids: Box::new([1, 3, 7, 13, 50, 100]),
+            event_ids: smallvec::smallvec![1, 3, 7, 13, 50, 100],
             ..Default::default()
         };
 
@@ -279,7 +551,7 @@ mod tests {
     #[test]
     fn test_conflict_with_entity() {
         let instance = MatchInstance {
-            match_entities: Box::new([(100, 0), (101, 1), (103, 2)]),
+            match_entities: smallvec::smallvec![(100, 0), (101, 1), (103, 2)],
             ..Default::default()
         };
 
@@ -306,4 +578,191 @@ mod tests {
         // event_id duplicates
         assert_eq!(dup_extend_entities_list(&match_entities, 100, 3), None);
     }
+
+    #[test]
+    fn test_clone_extend() {
+        let match_event = |match_id, event_id, subject, object| MatchEvent {
+            match_id,
+            input_subject_id: subject,
+            input_object_id: object,
+            pattern_subject_id: match_id as u64,
+            pattern_object_id: match_id as u64 + 1,
+            raw_events: RawEvents::Single(Rc::new(InputEvent::new(
+                event_id, event_id, "", subject, "", object, "",
+            ))),
+        };
+
+        let instance = MatchInstance::dead_default()
+            .clone_extend(
+                match_event(0, 7, 100, 101),
+                SharedNodeInfo::None,
+                (false, false),
+                true,
+            )
+            .unwrap();
+        // a single event is stored without spilling
+        assert!(!instance.match_entities.spilled() && !instance.event_ids.spilled());
+
+        let instance = instance
+            .clone_extend(
+                match_event(1, 3, 101, 102),
+                SharedNodeInfo::Subject,
+                (false, false),
+                true,
+            )
+            .unwrap();
+        assert_eq!(*instance.event_ids, [3, 7]);
+        assert_eq!(*instance.match_entities, [(100, 0), (101, 1), (102, 2)]);
+        assert_eq!(instance.match_events.len(), 2);
+
+        // input event 7 is already matched
+        assert!(instance
+            .clone_extend(
+                match_event(2, 7, 102, 103),
+                SharedNodeInfo::Subject,
+                (false, false),
+                true
+            )
+            .is_none());
+        // unless event uniqueness is disabled
+        let instance = instance
+            .clone_extend(
+                match_event(2, 7, 102, 103),
+                SharedNodeInfo::Subject,
+                (false, false),
+                false,
+            )
+            .unwrap();
+        assert_eq!(*instance.event_ids, [3, 7, 7]);
+    }
+
+    #[test]
+    fn test_clone_extend_wildcards() {
+        let match_event = |match_id, event_id, subject, object| MatchEvent {
+            match_id,
+            input_subject_id: subject,
+            input_object_id: object,
+            pattern_subject_id: match_id as u64,
+            pattern_object_id: match_id as u64 + 1,
+            raw_events: RawEvents::Single(Rc::new(InputEvent::new(
+                event_id, event_id, "", subject, "", object, "",
+            ))),
+        };
+        // pattern entity 1 is a wildcard, 0 and 2 are not
+        let instance = MatchInstance::dead_default()
+            .clone_extend(
+                match_event(0, 1, 100, 101),
+                SharedNodeInfo::None,
+                (false, true),
+                true,
+            )
+            .unwrap();
+        assert_eq!(*instance.match_entities, [(100, 0)]);
+        assert_eq!(instance.wildcard_entities, [(1, 101)]);
+
+        // entity 2 may not bind the input entity of entity 0
+        assert!(instance
+            .clone_extend(
+                match_event(1, 2, 101, 100),
+                SharedNodeInfo::Subject,
+                (true, false),
+                true,
+            )
+            .is_none());
+        // but it may bind the input entity of the wildcard
+        let instance = instance
+            .clone_extend(
+                match_event(1, 2, 101, 101),
+                SharedNodeInfo::Subject,
+                (true, false),
+                true,
+            )
+            .unwrap();
+        assert_eq!(*instance.match_entities, [(100, 0), (101, 2)]);
+        assert_eq!(instance.wildcard_entities, [(1, 101)]);
+        assert_eq!(
+            instance.to_string(),
+            "<0.000, 0.002>[1, 2] {0: 100, 1: 101, 2: 101}"
+        );
+    }
+
+    #[test]
+    fn test_bind_attributes() {
+        let match_event = |match_id, event_id, subject, object, uid: Option<&str>| MatchEvent {
+            match_id,
+            input_subject_id: subject,
+            input_object_id: object,
+            pattern_subject_id: match_id as u64,
+            pattern_object_id: match_id as u64 + 1,
+            raw_events: RawEvents::Single(Rc::new(
+                InputEvent::new(event_id, event_id, "", subject, "", object, "")
+                    .with_payload(uid.map(|uid| Box::from([uid.to_string()]))),
+            )),
+        };
+        // the input events binding pattern entity 1 must agree on the payload column 0
+        let same_attributes = [(1, 0)];
+
+        let mut instance = MatchInstance::dead_default()
+            .clone_extend(
+                match_event(0, 1, 100, 101, Some("alice")),
+                SharedNodeInfo::None,
+                (false, false),
+                true,
+            )
+            .unwrap();
+        assert!(instance.bind_attributes(&same_attributes));
+        assert_eq!(instance.entity_attributes, [(1, 0, Box::from("alice"))]);
+
+        let extend = |event| {
+            let mut new_instance = instance
+                .clone_extend(event, SharedNodeInfo::Subject, (false, false), true)
+                .unwrap();
+            new_instance.bind_attributes(&same_attributes)
+        };
+        assert!(extend(match_event(1, 2, 101, 102, Some("alice"))));
+        assert!(!extend(match_event(1, 2, 101, 102, Some("bob"))));
+        // events without the column never conflict
+        assert!(extend(match_event(1, 2, 101, 102, None)));
+    }
+
+    #[test]
+    fn test_format_instance() {
+        let match_event = |match_id, time, event_id, subject, object| MatchEvent {
+            match_id,
+            input_subject_id: subject,
+            input_object_id: object,
+            pattern_subject_id: match_id as u64,
+            pattern_object_id: match_id as u64 + 1,
+            raw_events: RawEvents::Single(Rc::new(InputEvent::new(
+                time, event_id, "", subject, "", object, "",
+            ))),
+        };
+        let mut instance = MatchInstance::dead_default()
+            .clone_extend(
+                match_event(0, 1000, 7, 101, 100),
+                SharedNodeInfo::None,
+                (false, false),
+                true,
+            )
+            .unwrap()
+            .clone_extend(
+                match_event(1, 2500, 3, 100, 102),
+                SharedNodeInfo::Subject,
+                (false, false),
+                true,
+            )
+            .unwrap();
+        instance.start_time = 1000;
+
+        assert_eq!(
+            instance.to_string(),
+            "<1.000, 2.500>[7, 3] {0: 101, 1: 100, 2: 102}"
+        );
+        assert_eq!(
+            format!("{instance:?}"),
+            "MatchInstance { start_time: 1000, match_events: {0: [7], 1: [3]}, \
+             match_entities: [(100, 1), (101, 0), (102, 2)], wildcard_entities: [], \
+             event_ids: [3, 7], state_id: 0 }"
+        );
+    }
 }
@@ -1,4 +1,10 @@
+mod aggregate_runner;
+mod category_index;
+mod deactivation;
+mod early_pruning;
 mod entity_encode;
+mod entity_index;
+mod entity_sightings;
 mod filter;
 mod flow_runner;
 mod flow_tracer;
@@ -6,25 +12,62 @@ mod instance_runner;
 mod instance_storage;
 pub mod match_instance;
 mod pattern_info;
+mod rate_monitor;
 mod state;
 mod state_table;
+mod static_index;
 
+pub use entity_index::EntityInstance;
+pub(crate) use instance_runner::construct_regex_patterns;
+pub use instance_runner::{EntitySignatureMode, SignatureCache};
 pub use match_instance::{InputEntityId, PatternEntityId};
 
+use super::parse_layer::Retractions;
+use super::matching_layer::FuzzyTolerance;
+use super::window_policy::SlidingWindow;
+use super::{BufferStats, DumpState, EventTrace, RecentGraph, ReportBufferStats, WindowPolicy};
 use crate::input_event::InputEvent;
 use crate::pattern::{PatternEventType, SubPattern};
-use ahash::HashMap;
+use crate::trace::enter_span;
+use aggregate_runner::AggregateRunner;
+use ahash::{HashMap, HashSet};
+use category_index::CategoryIndex;
+use deactivation::DeactivationCheck;
+pub use deactivation::{Deactivation, DeactivationPolicy, DeactivationSignal};
+use early_pruning::EarlyPruning;
 use flow_runner::FlowRunner;
 use instance_runner::InstanceRunner;
 use instance_storage::InstanceStorage;
 use log::debug;
 pub use match_instance::MatchInstance;
-use pattern_info::{FlowPattern, FreqPattern, PatternInfo, SinglePattern};
+pub use rate_monitor::{RateSummary, RateThresholds};
+use pattern_info::{AggregatePattern, FlowPattern, FreqPattern, PatternInfo, SinglePattern};
+use rate_monitor::RateMonitor;
 use regex::Error as RegexError;
+use serde_json::{json, Value};
 use state::*;
 use state_table::StateTable;
+use static_index::StaticIndex;
+use std::collections::BTreeMap;
 use std::rc::Rc;
 
+/// Describe the state table of the Composition layer for `decomposition` in json, i.e. the state
+/// ids, their filters and the shared-node information of their pattern events.
+pub fn explain_state_table(decomposition: &[SubPattern]) -> Value {
+    StateTable::new(decomposition).explain()
+}
+
+/// The number of match instances created in a sub-pattern, see
+/// [`CompositionLayer::sub_pattern_instances`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubPatternInstances {
+    pub sub_pattern_id: u32,
+    /// Number of partial match instances, which have not matched the whole sub-pattern.
+    pub num_partial: u64,
+    /// Number of matches of the whole sub-pattern, sent to the Join layer.
+    pub num_complete: u64,
+}
+
 pub struct CompositionLayer<'p, P> {
     prev_layer: P,
     window_size: u64,
@@ -35,7 +78,29 @@ pub struct CompositionLayer<'p, P> {
     storage: InstanceStorage,
     runner: InstanceRunner,
     flow_runner: FlowRunner,
+    aggregate_runner: AggregateRunner,
     state_table: StateTable,
+    rate_monitor: RateMonitor,
+    /// See `set_retractions()`, along with the number of retracted ids already purged.
+    retractions: Option<(Retractions, usize)>,
+    use_regex: bool,
+    /// See `set_context_graph()`.
+    static_index: Option<StaticIndex<'p>>,
+    /// See `set_event_trace()`.
+    event_trace: Option<EventTrace>,
+    /// See `set_recent_graph()`.
+    recent_graph: Option<RecentGraph>,
+    /// See `instances_of_entity()`.
+    window: Rc<dyn WindowPolicy>,
+    /// The batches without events in the categories of this index are skipped, see
+    /// `set_skip_irrelevant_batches()`. `None` if no batch can be skipped.
+    category_index: Option<CategoryIndex>,
+    skip_irrelevant_batches: bool,
+    num_skipped_batches: u64,
+    /// See `set_deactivation_policy()`.
+    deactivation_check: DeactivationCheck,
+    /// See `set_deactivation_signal()`.
+    deactivation_signal: Option<DeactivationSignal>,
 }
 
 impl<'p, P> CompositionLayer<'p, P> {
@@ -46,10 +111,12 @@ impl<'p, P> CompositionLayer<'p, P> {
         use_regex: bool,
     ) -> Result<Self, RegexError> {
         let state_table = StateTable::new(decomposition);
-        let storage = InstanceStorage::init_from_state_table(&state_table);
+        let pruning = EarlyPruning::new(decomposition);
+        let storage = InstanceStorage::init_from_state_table(&state_table, pruning);
         let runner = InstanceRunner::new(decomposition, window_size, use_regex)?;
         let (flow_runner, sig_indices) = FlowRunner::new(decomposition, window_size, use_regex)?;
         let pattern_infos = Self::build_pattern_infos(decomposition, &sig_indices, &state_table);
+        let category_index = CategoryIndex::new(decomposition, use_regex);
 
         Ok(Self {
             prev_layer,
@@ -59,10 +126,299 @@ impl<'p, P> CompositionLayer<'p, P> {
             storage,
             runner,
             flow_runner,
+            aggregate_runner: AggregateRunner::new(window_size),
             state_table,
+            rate_monitor: RateMonitor::default(),
+            retractions: None,
+            use_regex,
+            static_index: None,
+            event_trace: None,
+            recent_graph: None,
+            window: Rc::new(SlidingWindow::new(window_size)),
+            category_index,
+            skip_irrelevant_batches: true,
+            num_skipped_batches: 0,
+            deactivation_check: DeactivationCheck::default(),
+            deactivation_signal: None,
         })
     }
 
+    /// Match the duplicated events in a batch (events with the same subject, object and
+    /// signatures) as a whole, rather than one by one. This reduces the number of match instances
+    /// on bursty duplicated events, at the cost of reporting all the duplicates in a single match.
+    ///
+    /// Frequency-typed pattern events still count each duplicate individually.
+    pub fn set_zip_events(&mut self, zip_events: bool) {
+        self.runner.set_zip_events(zip_events);
+    }
+
+    /// Whether an input event may match at most one pattern event in an instance, enabled by
+    /// default. Set it to `Pattern::event_uniqueness`.
+    pub fn set_event_uniqueness(&mut self, event_uniqueness: bool) {
+        self.runner.set_event_uniqueness(event_uniqueness);
+    }
+
+    /// Set where the entity signatures must match, see [`EntitySignatureMode`]. Flow and static
+    /// pattern events always match the entity signatures in their own ways.
+    pub fn set_entity_signature_mode(
+        &mut self,
+        mode: EntitySignatureMode,
+    ) -> Result<(), RegexError> {
+        let patterns = self.pattern_infos.iter().filter_map(|info| match info {
+            PatternInfo::Single(SinglePattern {
+                pattern,
+                shared_node_info,
+                signature_idx,
+                ..
+            })
+            | PatternInfo::Freq(FreqPattern {
+                pattern,
+                shared_node_info,
+                signature_idx,
+                ..
+            })
+            | PatternInfo::Aggregate(AggregatePattern {
+                pattern,
+                shared_node_info,
+                signature_idx,
+                ..
+            }) => Some((*signature_idx, *pattern, *shared_node_info)),
+            PatternInfo::Flow(_) | PatternInfo::Static(_) => None,
+        });
+        if mode == EntitySignatureMode::AnyInWindow {
+            // the entities are sighted at the events of any category
+            self.category_index = None;
+        }
+        self.runner.set_entity_signature_mode(mode, patterns)
+    }
+
+    /// Match the event and entity signatures of the input events to those of the pattern events
+    /// within `tolerance` instead of by the regexes, to tolerate small variations, e.g. in command
+    /// lines. The pattern signatures are treated as plain strings. Flow and static pattern events
+    /// are still matched exactly, and no batch is skipped.
+    pub fn set_fuzzy_tolerance(&mut self, tolerance: FuzzyTolerance) {
+        self.runner.set_fuzzy_tolerance(tolerance);
+        // a fuzzy signature may be in another category
+        self.category_index = None;
+    }
+
+    /// Share the signature matches of each batch through `signature_cache` with the other
+    /// Composition layers given it, so that they are matched once. All of them must match the
+    /// same sub-patterns with the same signature settings (`use_regex`, the entity signature mode
+    /// and the fuzzy tolerance), and take the same batches, e.g. at different window sizes.
+    pub fn set_signature_cache(&mut self, signature_cache: SignatureCache) {
+        self.runner.set_signature_cache(signature_cache);
+    }
+
+    /// Skip the batches in which no event is in the category of any pattern event, i.e. the
+    /// first `::`-separated token of its signature, without matching them to the pattern events.
+    /// Enabled by default. Nothing is skipped if the pattern has flow events, regex signatures
+    /// whose categories are not literals, with [`EntitySignatureMode::AnyInWindow`] or with fuzzy
+    /// signatures.
+    pub fn set_skip_irrelevant_batches(&mut self, skip_irrelevant_batches: bool) {
+        self.skip_irrelevant_batches = skip_irrelevant_batches;
+    }
+
+    /// The number of batches skipped so far, see `set_skip_irrelevant_batches()` and
+    /// `set_deactivation_policy()`.
+    pub fn num_skipped_batches(&self) -> u64 {
+        self.num_skipped_batches
+    }
+
+    /// Replace the default sliding window of `window_size` given to `new()`.
+    pub fn set_window_policy(&mut self, window: Rc<dyn WindowPolicy>) {
+        self.runner.set_window_policy(window.clone());
+        self.flow_runner.set_window_policy(window.clone());
+        self.aggregate_runner.set_window_policy(window.clone());
+        self.window = window;
+    }
+
+    /// Raise alarms (logged as warnings) when the input event rate or the match instance creation
+    /// rate in a second of stream time exceeds the thresholds.
+    pub fn set_rate_thresholds(&mut self, thresholds: RateThresholds) {
+        self.rate_monitor.set_thresholds(thresholds);
+    }
+
+    /// Deactivate the pattern if, once the limit of `policy` is reached, some of its events (other
+    /// than the flow, static and optional ones) have matched no input event, since the pattern is
+    /// unlikely to ever complete. A deactivated pattern drops its match instances and skips all
+    /// the later batches, see `deactivation()`.
+    pub fn set_deactivation_policy(&mut self, policy: DeactivationPolicy) {
+        self.deactivation_check.set_policy(policy);
+    }
+
+    /// Raise `signal` when the pattern is deactivated, so that the next layer can drop its state.
+    /// See [`JoinLayer::set_deactivation_signal`](super::JoinLayer::set_deactivation_signal).
+    pub fn set_deactivation_signal(&mut self, signal: DeactivationSignal) {
+        self.deactivation_signal = Some(signal);
+    }
+
+    /// Why and when the pattern was deactivated, or `None` if it is still active.
+    pub fn deactivation(&self) -> Option<&Deactivation> {
+        self.deactivation_check.deactivation()
+    }
+
+    /// Purge the match instances containing the input events retracted by tombstone records, see
+    /// [`ParseLayer::set_retractions`](super::ParseLayer::set_retractions).
+    pub fn set_retractions(&mut self, retractions: Retractions) {
+        self.retractions = Some((retractions, 0));
+    }
+
+    /// Add the input batches to `recent_graph`, for exporting the neighborhoods of the matches.
+    pub fn set_recent_graph(&mut self, recent_graph: RecentGraph) {
+        self.recent_graph = Some(recent_graph);
+    }
+
+    /// Trace the signature matches and the instance extensions of the events in `event_trace`.
+    pub fn set_event_trace(&mut self, event_trace: EventTrace) {
+        self.storage.event_trace = Some(event_trace.clone());
+        self.event_trace = Some(event_trace);
+    }
+
+    /// The live match instances bound to the input entity `entity_id`, i.e. the partial matches
+    /// of the sub-patterns in which the entity takes part, with their states.
+    ///
+    /// The first call indexes the instances by their input entities, and the index is maintained
+    /// from then on, at the cost of a hash-set insertion per entity of each new instance.
+    pub fn instances_of_entity(&mut self, entity_id: u64) -> Vec<EntityInstance> {
+        self.storage.enable_entity_index();
+        let window_bound = self.window.window_bound(self.cur_time);
+        let state_table = &self.state_table;
+        let instances = self.storage.instances_of_entity(entity_id, window_bound);
+        instances
+            .into_iter()
+            .filter_map(|instance| {
+                Some(EntityInstance {
+                    sub_pattern_id: state_table.sub_pattern_of(instance.state_id)?,
+                    state_id: instance.state_id,
+                    pattern_events: instance.match_events.iter().map(|e| e.match_id).collect(),
+                    pattern_entity_ids: (instance.match_entities.iter())
+                        .filter(|(input_id, _)| *input_id == entity_id)
+                        .map(|(_, pattern_id)| *pattern_id)
+                        .collect(),
+                    start_time: instance.start_time,
+                    event_ids: instance.event_ids.to_vec(),
+                })
+            })
+            .collect()
+    }
+
+    /// Match the pattern events marked `Static` against the static context graph `events`, e.g.
+    /// asset inventory edges, instead of the input stream. The static events are always
+    /// considered in-window.
+    ///
+    /// A static pattern event must share an entity with an earlier event in its sub-pattern,
+    /// otherwise it never matches.
+    pub fn set_context_graph(&mut self, events: &[Rc<InputEvent>]) -> Result<(), RegexError> {
+        self.static_index = Some(StaticIndex::new(
+            &self.pattern_infos,
+            events,
+            self.use_regex,
+        )?);
+        Ok(())
+    }
+
+    fn purge_retracted(&mut self) {
+        let Some((retractions, cursor)) = &mut self.retractions else {
+            return;
+        };
+        let retracted_ids = retractions.take_new(cursor);
+        if !retracted_ids.is_empty() {
+            let num_purged = self.storage.purge_retracted(&retracted_ids);
+            debug!(
+                "retract events {:?}: {} instances purged",
+                retracted_ids, num_purged
+            );
+        }
+    }
+
+    /// The per-second rate statistics of the input processed so far.
+    pub fn rate_summary(&self) -> RateSummary {
+        self.rate_monitor.summary()
+    }
+
+    /// The number of input events matching each alternative of the event signatures so far, as
+    /// `((pattern event id, alternative index), number of events)` sorted by the pattern event id
+    /// and the alternative index, where the alternative 0 is the `signature` of the event.
+    pub fn signature_hits(&self) -> Vec<((usize, usize), u64)> {
+        // an event in several sub-patterns has the same hits in each of them
+        let hits: BTreeMap<_, _> = self.runner.alternative_hits().iter().copied().collect();
+        hits.into_iter().collect()
+    }
+
+    /// The ids of the pattern events checked by `set_deactivation_policy()` whose signatures have
+    /// matched no input event so far, sorted.
+    fn unmatched_events(&self) -> Vec<usize> {
+        let matched: HashSet<usize> = (self.runner.alternative_hits().iter())
+            .filter(|(_, hits)| *hits > 0)
+            .map(|((event_id, _), _)| *event_id)
+            .collect();
+        let mut unmatched: Vec<usize> = (self.pattern_infos.iter())
+            .filter_map(|info| match info {
+                PatternInfo::Single(SinglePattern { pattern, .. })
+                | PatternInfo::Freq(FreqPattern { pattern, .. })
+                | PatternInfo::Aggregate(AggregatePattern { pattern, .. }) => Some(pattern),
+                PatternInfo::Flow(_) | PatternInfo::Static(_) => None,
+            })
+            .filter(|pattern| !pattern.is_optional && !matched.contains(&pattern.id))
+            .map(|pattern| pattern.id)
+            .collect();
+        unmatched.sort_unstable();
+        unmatched.dedup();
+        unmatched
+    }
+
+    /// Drop all the match instances and the buffered input of the deactivated pattern.
+    fn deactivate(&mut self) {
+        self.storage.clear();
+        self.flow_runner.clear();
+        self.aggregate_runner.clear();
+    }
+
+    /// The number of match instances created in each sub-pattern so far, for attributing the cost
+    /// of a pattern to its sub-patterns. Sorted by the sub-pattern id.
+    pub fn sub_pattern_instances(&self) -> Vec<SubPatternInstances> {
+        let mut sub_patterns: BTreeMap<u32, SubPatternInstances> = BTreeMap::new();
+        let num_instances = &self.storage.num_new_instances_by_state;
+        for (state_id, (state_info, _)) in self.state_table.iter().enumerate() {
+            let Some(sub_pattern_id) = self.state_table.sub_pattern_of(state_id as u32) else {
+                continue;
+            };
+            let entry = sub_patterns
+                .entry(sub_pattern_id)
+                .or_insert(SubPatternInstances {
+                    sub_pattern_id,
+                    num_partial: 0,
+                    num_complete: 0,
+                });
+            match state_info {
+                StateInfo::Output { .. } => entry.num_complete += num_instances[state_id],
+                _ => entry.num_partial += num_instances[state_id],
+            }
+        }
+        sub_patterns.into_values().collect()
+    }
+
+    /// The sizes of the buffers of partial match instances.
+    pub fn buffer_stats(&self) -> Vec<BufferStats> {
+        let mut stats = self.storage.buffer_stats();
+        stats.push(self.aggregate_runner.buffer_stats());
+        stats.push(self.flow_runner.buffer_stats());
+        stats
+    }
+
+    /// The number of live partial match instances in each state, indexed by the state id. See
+    /// [`InstanceStorage::live_instances`].
+    pub fn live_instances(&self) -> Vec<u64> {
+        self.storage.live_instances(&self.state_table)
+    }
+
+    /// The number of live partial match instances in each state, see
+    /// [`InstanceStorage::state_stats`].
+    pub fn state_stats(&self) -> Vec<BufferStats> {
+        self.storage.state_stats()
+    }
+
     /// build pattern_infos
     ///
     /// Arguments:
@@ -82,6 +438,13 @@ impl<'p, P> CompositionLayer<'p, P> {
                 use PatternEventType::*;
                 let shared_node_info = state_table.get_shared_node_info(match_idx);
                 let info: PatternInfo = match pattern.event_type {
+                    Default if pattern.is_static => PatternInfo::Static(SinglePattern {
+                        pattern,
+                        match_idx,
+                        shared_node_info,
+                        signature_idx,
+                    }),
+
                     Default => SinglePattern {
                         pattern,
                         match_idx,
@@ -99,6 +462,21 @@ impl<'p, P> CompositionLayer<'p, P> {
                     }
                     .into(),
 
+                    Aggregate {
+                        count,
+                        distinct_objects,
+                        within,
+                    } => AggregatePattern {
+                        pattern,
+                        match_idx,
+                        shared_node_info,
+                        signature_idx,
+                        count,
+                        distinct_objects,
+                        within,
+                    }
+                    .into(),
+
                     Flow => {
                         let src_sig_idx = *sig_indices.get(&pattern.subject.id).unwrap();
                         let dst_sig_idx = *sig_indices.get(&pattern.object.id).unwrap();
@@ -132,9 +510,82 @@ impl<'p, P> CompositionLayer<'p, P> {
         self.cur_time = time;
         self.runner.set_batch(batch, time);
         self.flow_runner.set_batch(batch, time);
+        self.aggregate_runner.set_batch(time);
+        if let Some(event_trace) = &self.event_trace {
+            self.trace_signatures(event_trace, batch);
+        }
+        if let Some(recent_graph) = &self.recent_graph {
+            recent_graph.add_batch(batch);
+        }
 
         // TODO: Consider active windowing
     }
+
+    /// Whether `batch` has no event that may match a pattern event, so that it can be skipped.
+    fn is_irrelevant(&self, batch: &[Rc<InputEvent>]) -> bool {
+        match &self.category_index {
+            Some(index) if self.skip_irrelevant_batches => !batch
+                .iter()
+                .any(|event| index.is_relevant(event.get_event_signature())),
+            _ => false,
+        }
+    }
+
+    /// Advance the time to an irrelevant `batch` without matching it.
+    fn skip_batch(&mut self, batch: &[Rc<InputEvent>]) {
+        let Some(first) = batch.first() else {
+            return;
+        };
+        self.cur_time = first.timestamp;
+        if let Some(event_trace) = &self.event_trace {
+            for event in batch {
+                event_trace.record(
+                    [event.event_id],
+                    "composition",
+                    "signature",
+                    || json!({ "time": event.timestamp, "pattern_events": [] }),
+                );
+            }
+        }
+        if let Some(recent_graph) = &self.recent_graph {
+            recent_graph.add_batch(batch);
+        }
+        self.num_skipped_batches += 1;
+    }
+
+    /// Record the pattern events whose signatures the traced events in `batch` match.
+    fn trace_signatures(&self, event_trace: &EventTrace, batch: &[Rc<InputEvent>]) {
+        for event in batch {
+            event_trace.record([event.event_id], "composition", "signature", || {
+                let pattern_events: Vec<usize> = (self.pattern_infos.iter())
+                    .filter_map(|info| match info {
+                        PatternInfo::Single(SinglePattern {
+                            pattern,
+                            signature_idx,
+                            ..
+                        })
+                        | PatternInfo::Freq(FreqPattern {
+                            pattern,
+                            signature_idx,
+                            ..
+                        })
+                        | PatternInfo::Aggregate(AggregatePattern {
+                            pattern,
+                            signature_idx,
+                            ..
+                        }) => Some((pattern.id, *signature_idx)),
+                        PatternInfo::Flow(_) | PatternInfo::Static(_) => None,
+                    })
+                    .filter(|(_, signature_idx)| {
+                        (self.runner.matched_events(*signature_idx))
+                            .any(|matched| matched.event_id == event.event_id)
+                    })
+                    .map(|(id, _)| id)
+                    .collect();
+                json!({ "time": event.timestamp, "pattern_events": pattern_events })
+            });
+        }
+    }
  
     /// Match the newly input events to all pattern events.
     pub fn advance(&mut self) {
@@ -152,11 +603,66 @@ impl<'p, P> CompositionLayer<'p, P> {
                     self.flow_runner
                         .run(info, &mut self.storage, &self.state_table)
                 }
+                PatternInfo::Aggregate(info) => self.aggregate_runner.run(
+                    info,
+                    self.runner.matched_events(info.signature_idx),
+                    &mut self.storage,
+                    &self.state_table,
+                ),
+                PatternInfo::Static(_) => continue,
             }
+            Self::match_static(
+                &mut self.storage,
+                self.static_index.as_ref(),
+                &self.state_table,
+            );
+        }
+    }
+
+    /// Extend the instances waiting for static pattern events with the static context graph,
+    /// until they reach a non-static one. They are dropped if no context graph is set.
+    fn match_static(
+        storage: &mut InstanceStorage,
+        static_index: Option<&StaticIndex>,
+        state_table: &StateTable,
+    ) {
+        while !storage.static_instances.is_empty() {
+            let instances = std::mem::take(&mut storage.static_instances);
+            let Some(static_index) = static_index else {
+                return;
+            };
+            let new_instances = instances
+                .iter()
+                .flat_map(|instance| static_index.extend(instance, state_table));
+            storage.store_new_instances(new_instances, state_table);
         }
     }
 }
 
+impl<'p, P> DumpState for CompositionLayer<'p, P> {
+    fn dump_state(&self) -> Value {
+        json!({
+            "window_size": self.window_size,
+            "cur_time": self.cur_time,
+            "instance_storage": self.storage.dump_state(),
+        })
+    }
+}
+
+impl<'p, P> ReportBufferStats for CompositionLayer<'p, P> {
+    fn collect_buffer_stats(&self, stats: &mut Vec<BufferStats>) {
+        stats.extend(self.buffer_stats());
+    }
+
+    fn collect_state_stats(&self, stats: &mut Vec<BufferStats>) {
+        stats.extend(self.state_stats());
+    }
+
+    fn collect_live_instances(&self, counts: &mut Vec<u64>) {
+        counts.extend(self.live_instances());
+    }
+}
+
 impl<'p, P> Iterator for CompositionLayer<'p, P>
 where
     P: Iterator<Item = Box<[Rc<InputEvent>]>>,
@@ -165,9 +671,39 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         while self.storage.output_instances.is_empty() {
-            let batch = self.prev_layer.next()?;
-            self.add_batch(&batch);
-            self.advance();
+            let batch = self.prev_layer.next();
+            // the previous layer may have read tombstone records
+            self.purge_retracted();
+            let Some(batch) = batch else {
+                self.rate_monitor.finish_second();
+                return None;
+            };
+            let _span = enter_span!(
+                "batch",
+                time = batch.first().map_or(0, |e| e.timestamp),
+                num_events = batch.len()
+            );
+            let num_instances = self.storage.num_new_instances;
+            if self.deactivation().is_some() || self.is_irrelevant(&batch) {
+                self.skip_batch(&batch);
+            } else {
+                self.add_batch(&batch);
+                self.advance();
+            }
+            self.rate_monitor.record(
+                self.cur_time,
+                batch.len() as u64,
+                self.storage.num_new_instances - num_instances,
+            );
+            if self.deactivation_check.record(self.cur_time, batch.len() as u64) {
+                let unmatched = self.unmatched_events();
+                if self.deactivation_check.check(self.cur_time, unmatched) {
+                    self.deactivate();
+                    if let Some(signal) = &self.deactivation_signal {
+                        signal.raise();
+                    }
+                }
+            }
         }
 
         if let Some(output) = self.storage.output_instances.last() {
@@ -187,8 +723,7 @@ mod tests {
     use super::*;
     use crate::input_event::InputEvent;
     use crate::match_event::MatchEvent;
-    use crate::pattern::{Pattern, PatternEventType};
-    use crate::universal_match_event::UniversalMatchEvent;
+    use crate::pattern::{decompose, Pattern, PatternEventType, TimeGap};
 
     /// Creates a pattern consists of 3 event and 4 entities. They form a path from v0 to v3.
     ///
@@ -272,6 +807,134 @@ mod tests {
         assert!(layer.next().is_none());
     }
 
+    #[test]
+    fn test_entity_signature_mode() {
+        let pattern = basic_pattern();
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        let num_matches = |mode, input: &[Box<[Rc<InputEvent>]>]| {
+            let mut layer =
+                CompositionLayer::new(input.iter().cloned(), &decomposition, u64::MAX, false)
+                    .unwrap();
+            layer.set_entity_signature_mode(mode).unwrap();
+            layer.count()
+        };
+        use EntitySignatureMode::*;
+
+        // v1 is renamed after it is bound by e0
+        let renamed_later = [
+            event(0, 0, 1, "e0#v0#v1"),
+            event(1, 1, 2, "e1#v1x#v2"),
+            event(2, 2, 3, "e2#v2#v3"),
+        ];
+        assert_eq!(num_matches(EveryEvent, &renamed_later), 0);
+        assert_eq!(num_matches(FirstBinding, &renamed_later), 1);
+        assert_eq!(num_matches(AnyInWindow, &renamed_later), 1);
+
+        // v1 is renamed before it is bound by e0, but seen with its signature earlier
+        let renamed_earlier = [
+            event(0, 1, 1, "noise#v1#v1"),
+            event(1, 0, 1, "e0#v0#v1x"),
+            event(2, 1, 2, "e1#v1x#v2"),
+            event(3, 2, 3, "e2#v2#v3"),
+        ];
+        assert_eq!(num_matches(EveryEvent, &renamed_earlier), 0);
+        assert_eq!(num_matches(FirstBinding, &renamed_earlier), 0);
+        assert_eq!(num_matches(AnyInWindow, &renamed_earlier), 1);
+        assert_eq!(num_matches(AnyInWindow, &renamed_earlier[1..]), 0);
+    }
+
+    #[test]
+    fn test_time_gap() {
+        let mut pattern = basic_pattern();
+        let gap = |min, max| TimeGap { min, max };
+        pattern.events[1].parent_gaps = vec![(0, gap(0, Some(1)))];
+        pattern.events[2].parent_gaps = vec![(1, gap(2, None))];
+        let window_size = u64::MAX;
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+
+        let input = [
+            event(0, 0, 1, "e0#v0#v1"),
+            event(1, 1, 2, "e1#v1#v2"),
+            event(2, 2, 3, "e2#v2#v3"), // too close to e1
+            event(3, 1, 2, "e1#v1#v2"), // too far from e0
+            event(5, 2, 3, "e2#v2#v3"),
+        ];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, window_size, false).unwrap();
+
+        verify_instance(layer.next(), 0, 0, &[0, 1, 5]);
+        assert!(layer.next().is_none());
+    }
+
+    #[test]
+    fn test_distinct_entities() {
+        let mut pattern = basic_pattern();
+        // v0 and v1 must differ, which is checked when e0 or e1 is matched
+        pattern.events[0].distinct_entities = vec![(0, 1)];
+        pattern.events[1].distinct_entities = vec![(0, 1)];
+        let window_size = u64::MAX;
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+
+        let input = [
+            event(0, 5, 5, "e0#v0#v1"), // a self-loop matching both v0 and v1
+            event(1, 5, 2, "e1#v1#v2"),
+            event(2, 2, 3, "e2#v2#v3"),
+        ];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, window_size, false).unwrap();
+        assert!(layer.next().is_none());
+    }
+
+    #[test]
+    fn test_simultaneous() {
+        let mut pattern = basic_pattern();
+        pattern.events[0].simultaneous_events = vec![2];
+        pattern.events[2].simultaneous_events = vec![0];
+        let window_size = u64::MAX;
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+
+        let batch = |time, events: &[(u64, u64, u64, &str)]| {
+            events
+                .iter()
+                .map(|(id, sub_id, obj_id, sig)| {
+                    let sigs: Vec<&str> = sig.split('#').collect();
+                    Rc::new(InputEvent::new(
+                        time, *id, sigs[0], *sub_id, sigs[1], *obj_id, sigs[2],
+                    ))
+                })
+                .collect::<Box<[_]>>()
+        };
+        let input = [
+            batch(0, &[(0, 0, 1, "e0#v0#v1")]),
+            batch(1, &[(1, 1, 2, "e1#v1#v2"), (2, 2, 3, "e2#v2#v3")]), // e2 not at the time of e0
+            batch(
+                5,
+                &[
+                    (3, 10, 11, "e0#v0#v1"),
+                    (4, 11, 12, "e1#v1#v2"),
+                    (5, 12, 13, "e2#v2#v3"),
+                ],
+            ),
+        ];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, window_size, false).unwrap();
+
+        verify_instance(layer.next(), 0, 5, &[3, 4, 5]);
+        assert!(layer.next().is_none());
+    }
+
     #[test]
     fn test_event_uniqueness() {
         let pattern = Pattern::from_graph(
@@ -317,6 +980,18 @@ mod tests {
             CompositionLayer::new(input.into_iter(), &decomposition, window_size, false).unwrap();
 
         assert!(layer.next().is_none());
+
+        // the endpoints of the first event are also distinct
+        let pattern = Pattern::from_graph(&["v1", "v1"], &[(0, 1, "e0")], false);
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        let input = [event(0, 1, 1, "e0#v1#v1")];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, window_size, false).unwrap();
+
+        assert!(layer.next().is_none());
     }
 
     #[test]
@@ -339,6 +1014,250 @@ mod tests {
         assert!(layer.next().is_none());
     }
 
+    #[test]
+    fn test_instances_of_entity() {
+        let pattern = basic_pattern();
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        let mut layer = CompositionLayer::new((), &decomposition, 10, false).unwrap();
+        layer.add_batch(&event(0, 0, 1, "e0#v0#v1"));
+        layer.advance();
+
+        // the index is built on the first query
+        let instances = layer.instances_of_entity(1);
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].pattern_entity_ids, [1]);
+        assert_eq!(instances[0].pattern_events, [0]);
+
+        layer.add_batch(&event(5, 1, 2, "e1#v1#v2"));
+        layer.advance();
+        let instances = layer.instances_of_entity(1);
+        let mut states = instances.iter().map(|i| i.state_id).collect_vec();
+        states.sort_unstable();
+        assert_eq!(states, [1, 2]);
+        let instances = layer.instances_of_entity(2);
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].event_ids, [0, 5]);
+
+        // the instances starting at 0 expire
+        layer.add_batch(&event(12, 7, 8, "e0#v0#v1"));
+        layer.advance();
+        assert!(layer.instances_of_entity(1).is_empty());
+        assert_eq!(layer.instances_of_entity(8).len(), 1);
+    }
+
+    #[test]
+    fn test_signature_alternatives() {
+        let mut pattern = basic_pattern();
+        pattern.events[0].alternatives = vec!["e0b".to_string(), "e0c".to_string()];
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        let mut layer = CompositionLayer::new((), &decomposition, 10, false).unwrap();
+        for batch in [
+            event(0, 0, 1, "e0#v0#v1"),
+            event(1, 0, 1, "e0c#v0#v1"),
+            event(2, 0, 1, "e0d#v0#v1"),
+            event(3, 0, 1, "e0c#v0#v1"),
+        ] {
+            layer.add_batch(&batch);
+            layer.advance();
+        }
+
+        let instances = layer.instances_of_entity(1);
+        let mut starts = instances.iter().map(|i| i.start_time).collect_vec();
+        starts.sort_unstable();
+        assert_eq!(starts, [0, 1, 3]);
+        assert_eq!(
+            layer.signature_hits(),
+            [
+                ((0, 0), 1),
+                ((0, 1), 0),
+                ((0, 2), 2),
+                ((1, 0), 0),
+                ((2, 0), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_signature_cache() {
+        let pattern = basic_pattern();
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        let input = [
+            event(0, 0, 1, "e0#v0#v1"),
+            event(1, 1, 2, "e1#v1#v2"),
+            event(5, 2, 3, "e2#v2#v3"),
+            event(6, 2, 3, "e2#v2#v3"),
+        ];
+        let num_outputs = |layer: &mut CompositionLayer<()>, batch: &[Rc<InputEvent>]| {
+            layer.add_batch(batch);
+            layer.advance();
+            layer.storage.output_instances.drain(..).count()
+        };
+
+        let signature_cache = SignatureCache::default();
+        let mut results = vec![];
+        for window_size in [10, 2] {
+            let mut layer = CompositionLayer::new((), &decomposition, window_size, false).unwrap();
+            let mut cached_layer =
+                CompositionLayer::new((), &decomposition, window_size, false).unwrap();
+            cached_layer.set_signature_cache(signature_cache.clone());
+            let (mut expected, mut actual) = (0, 0);
+            for batch in &input {
+                expected += num_outputs(&mut layer, batch);
+                actual += num_outputs(&mut cached_layer, batch);
+            }
+            assert_eq!(actual, expected, "window size {window_size}");
+            assert_eq!(cached_layer.signature_hits(), layer.signature_hits());
+            results.push(actual);
+        }
+        assert_eq!(results, [2, 0]);
+    }
+
+    #[test]
+    fn test_sub_pattern_instances() {
+        let pattern = basic_pattern();
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        // both e1 extend the instance of e0, and are both completed by e2
+        let input = [
+            event(1, 0, 1, "e0#v0#v1"),
+            event(2, 1, 2, "e1#v1#v2"),
+            event(3, 1, 2, "e1#v1#v2"),
+            event(4, 2, 3, "e2#v2#v3"),
+        ];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, 100, false).unwrap();
+        assert_eq!(layer.by_ref().count(), 2);
+        assert_eq!(
+            layer.sub_pattern_instances(),
+            [SubPatternInstances {
+                sub_pattern_id: 0,
+                num_partial: 3,
+                num_complete: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_skip_irrelevant_batches() {
+        let pattern = basic_pattern();
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        let input = [
+            event(0, 0, 1, "e0#v0#v1"),
+            event(1, 1, 2, "read#v1#v2"),
+            event(2, 1, 2, "e1::x#v1#v2"),
+            event(3, 1, 2, "e1#v1#v2"),
+            event(4, 2, 3, "write#v2#v3"),
+            event(5, 2, 3, "e2#v2#v3"),
+        ];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, 100, false).unwrap();
+        verify_instance(layer.next(), 0, 0, &[0, 3, 5]);
+        assert!(layer.next().is_none());
+        // `e1::x` is in the category of `e1`, even though it does not match it
+        assert_eq!(layer.num_skipped_batches(), 2);
+
+        // the second alternative matches events of another category
+        let pattern = Pattern::from_graph(&["v0", "v1"], &[(0, 1, "e0::a|e9::b")], true);
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        let input = [event(0, 0, 1, "e0::a#v0#v1"), event(1, 0, 1, "e9::b#v0#v1")];
+        let layer = CompositionLayer::new(input.into_iter(), &decomposition, 100, true).unwrap();
+        assert_eq!(layer.count(), 2);
+    }
+
+    #[test]
+    fn test_deactivation() {
+        let pattern = basic_pattern();
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        let run = |policy: DeactivationPolicy| {
+            let mut input = [
+                event(0, 0, 1, "e0#v0#v1"),
+                event(1, 1, 2, "e1#v1#v2"),
+                event(2, 2, 3, "e2#v2#v3"),
+            ]
+            .into_iter();
+            let signal = DeactivationSignal::default();
+            let mut layer =
+                CompositionLayer::new(input.by_ref(), &decomposition, 100, false).unwrap();
+            layer.set_deactivation_policy(policy);
+            layer.set_deactivation_signal(signal.clone());
+            let num_outputs = layer.by_ref().count();
+            let deactivation = layer.deactivation().cloned();
+            // the output only ends with the input
+            assert!(input.next().is_none());
+            (num_outputs, deactivation, signal.take())
+        };
+
+        // e2 has matched nothing yet at time 1
+        let policy = DeactivationPolicy {
+            after_time: Some(1),
+            after_events: None,
+        };
+        let deactivation = Deactivation {
+            time: 1,
+            num_events: 2,
+            pattern_event_ids: vec![2],
+        };
+        assert_eq!(run(policy), (0, Some(deactivation), true));
+
+        let policy = DeactivationPolicy {
+            after_time: None,
+            after_events: Some(3),
+        };
+        assert_eq!(run(policy), (1, None, false));
+    }
+
+    #[test]
+    fn test_early_pruning() {
+        let mut pattern = basic_pattern();
+        let gap = |min, max| TimeGap { min, max };
+        pattern.events[1].parent_gaps = vec![(0, gap(5, None))];
+        pattern.events[2].parent_gaps = vec![(1, gap(5, None))];
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+
+        // e2 can not arrive within the window once e1 arrives
+        let input = [event(0, 0, 1, "e0#v0#v1"), event(5, 1, 2, "e1#v1#v2")];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, 8, false).unwrap();
+        assert!(layer.next().is_none());
+        assert!(layer.state_stats().is_empty());
+
+        // e1 arrives too late after e0
+        pattern.events[1].parent_gaps = vec![(0, gap(0, Some(2)))];
+        pattern.events[2].parent_gaps = vec![];
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+        let input = [event(0, 0, 1, "e0#v0#v1"), event(3, 1, 5, "e1#v1#v2")];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, 100, false).unwrap();
+        assert!(layer.next().is_none());
+        assert!(layer.state_stats().is_empty());
+    }
+
     fn verify_event(
         match_event: &MatchEvent,
         time_pair: (u64, u64),
@@ -380,6 +1299,41 @@ mod tests {
         assert!(layer.next().is_none());
     }
 
+    #[test]
+    fn test_zip_events() {
+        let pattern = basic_pattern();
+        let window_size = u64::MAX;
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+
+        let duplicates = ["e1#v1#v2", "e1#v1#v2", "e1#v1#v2"]
+            .iter()
+            .enumerate()
+            .map(|(i, sig)| {
+                let sigs: Vec<&str> = sig.split('#').collect();
+                Rc::new(InputEvent::new(
+                    1, i as u64 + 1, sigs[0], 1, sigs[1], 2, sigs[2],
+                ))
+            })
+            .collect();
+        let input = [
+            event(0, 0, 1, "e0#v0#v1"),
+            duplicates,
+            event(4, 2, 3, "e2#v2#v3"),
+        ];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, window_size, false).unwrap();
+        layer.set_zip_events(true);
+
+        let match_events = layer.next().unwrap().1.match_events;
+        verify_event(&match_events[0], (0, 0), (0, 1), &[0]);
+        verify_event(&match_events[1], (1, 1), (1, 2), &[1, 2, 3]);
+        verify_event(&match_events[2], (4, 4), (2, 3), &[4]);
+        assert!(layer.next().is_none());
+    }
+
     #[test]
     fn test_flow() {
         let mut pattern = basic_pattern();
@@ -406,4 +1360,67 @@ mod tests {
         verify_event(&match_events[2], (4, 4), (4, 5), &[4]);
         assert!(layer.next().is_none());
     }
+
+    #[test]
+    fn test_aggregate() {
+        let mut pattern = Pattern::from_graph(&["v0", "v1"], &[(0, 1, "e0")], false);
+        pattern.events[0].event_type = PatternEventType::Aggregate {
+            count: 3,
+            distinct_objects: 2,
+            within: Some(10),
+        };
+        let window_size = u64::MAX;
+        let decomposition = decompose(&pattern);
+
+        let input = [
+            event(0, 1, 2, "e0#v0#v1"),
+            event(1, 1, 2, "e0#v0#v1"),
+            // the events above are out of 10ms
+            event(20, 1, 3, "e0#v0#v1"),
+            event(21, 1, 3, "e0#v0#v1"),
+            event(22, 5, 4, "e0#v0#v1"),
+            event(23, 1, 4, "e0#v0#v1"),
+            // the subject starts over after a match
+            event(24, 1, 5, "e0#v0#v1"),
+        ];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, window_size, false).unwrap();
+
+        let match_events = layer.next().unwrap().1.match_events;
+        verify_event(&match_events[0], (20, 23), (1, 4), &[20, 21, 23]);
+        assert!(layer.next().is_none());
+    }
+
+    #[test]
+    fn test_context_graph() {
+        let mut pattern = basic_pattern();
+        pattern.events[1].is_static = true;
+        let window_size = 10;
+        let decomposition = [SubPattern {
+            id: 0,
+            events: pattern.events.iter().collect(),
+        }];
+
+        let context_graph = [
+            event(100, 1, 2, "e1#v1#v2"),
+            event(101, 5, 6, "e1#v1#v2"),
+            event(102, 1, 7, "e1#v1#v2"),
+        ]
+        .concat();
+        // the stream event matching e1 is ignored, as e1 is static
+        let input = [
+            event(50, 0, 1, "e0#v0#v1"),
+            event(51, 1, 2, "e1#v1#v2"),
+            event(52, 2, 3, "e2#v2#v3"),
+        ];
+        let mut layer =
+            CompositionLayer::new(input.into_iter(), &decomposition, window_size, false).unwrap();
+        layer.set_context_graph(&context_graph).unwrap();
+
+        let match_events = layer.next().unwrap().1.match_events;
+        verify_event(&match_events[0], (50, 50), (0, 1), &[50]);
+        verify_event(&match_events[1], (50, 50), (1, 2), &[100]);
+        verify_event(&match_events[2], (52, 52), (2, 3), &[52]);
+        assert!(layer.next().is_none());
+    }
 }
@@ -61,10 +61,23 @@ pub struct FlowPattern<'p> {
     pub dst_sig_idx: usize,
 }
 
+pub struct AggregatePattern<'p> {
+    pub pattern: &'p PatternEvent,
+    pub match_idx: usize,
+    pub shared_node_info: SharedNodeInfo,
+    pub signature_idx: usize,
+    pub count: u32,
+    pub distinct_objects: u32,
+    pub within: Option<u64>,
+}
+
 pub enum PatternInfo<'p> {
     Single(SinglePattern<'p>),
     Freq(FreqPattern<'p>),
     Flow(FlowPattern<'p>),
+    Aggregate(AggregatePattern<'p>),
+    /// A pattern event matching the static context graph, see `StaticIndex`.
+    Static(SinglePattern<'p>),
 }
 
 impl<'p> From<SinglePattern<'p>> for PatternInfo<'p> {
@@ -84,3 +97,9 @@ impl<'p> From<FlowPattern<'p>> for PatternInfo<'p> {
         PatternInfo::Flow(value)
     }
 }
+
+impl<'p> From<AggregatePattern<'p>> for PatternInfo<'p> {
+    fn from(value: AggregatePattern<'p>) -> Self {
+        PatternInfo::Aggregate(value)
+    }
+}
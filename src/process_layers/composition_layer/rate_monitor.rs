@@ -0,0 +1,117 @@
+use log::warn;
+
+/// Thresholds of the per-second rates, in stream time. `None` means no limit.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RateThresholds {
+    /// Maximum number of input events per second.
+    pub max_event_rate: Option<u64>,
+    /// Maximum number of new (partial) match instances per second.
+    pub max_instance_rate: Option<u64>,
+}
+
+/// Statistics of the per-second rates over the whole stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RateSummary {
+    pub peak_event_rate: u64,
+    pub peak_instance_rate: u64,
+    /// Number of seconds in which the input event rate exceeded the threshold.
+    pub event_rate_alarms: u64,
+    /// Number of seconds in which the instance creation rate exceeded the threshold.
+    pub instance_rate_alarms: u64,
+}
+
+/// Collects the input event rate and the match instance creation rate in each second (of stream
+/// time), and raises an alarm when either of them exceeds its threshold.
+#[derive(Debug, Default)]
+pub struct RateMonitor {
+    thresholds: RateThresholds,
+    /// The second that is being counted.
+    cur_second: u64,
+    num_events: u64,
+    num_instances: u64,
+    summary: RateSummary,
+}
+
+impl RateMonitor {
+    pub fn set_thresholds(&mut self, thresholds: RateThresholds) {
+        self.thresholds = thresholds;
+    }
+
+    /// Record the number of input events and the number of new instances at `time` (ms).
+    pub fn record(&mut self, time: u64, num_events: u64, num_instances: u64) {
+        let second = time / 1000;
+        if second != self.cur_second {
+            self.finish_second();
+            self.cur_second = second;
+        }
+        self.num_events += num_events;
+        self.num_instances += num_instances;
+    }
+
+    /// Close the counting of the current second, and check the rates against the thresholds.
+    pub fn finish_second(&mut self) {
+        let summary = &mut self.summary;
+        summary.peak_event_rate = summary.peak_event_rate.max(self.num_events);
+        summary.peak_instance_rate = summary.peak_instance_rate.max(self.num_instances);
+
+        if self
+            .thresholds
+            .max_event_rate
+            .is_some_and(|max| self.num_events > max)
+        {
+            summary.event_rate_alarms += 1;
+            warn!(
+                "Input event rate {} events/s at time {}s exceeds the threshold",
+                self.num_events, self.cur_second
+            );
+        }
+        if self
+            .thresholds
+            .max_instance_rate
+            .is_some_and(|max| self.num_instances > max)
+        {
+            summary.instance_rate_alarms += 1;
+            warn!(
+                "Match instance creation rate {} instances/s at time {}s exceeds the threshold",
+                self.num_instances, self.cur_second
+            );
+        }
+
+        self.num_events = 0;
+        self.num_instances = 0;
+    }
+
+    pub fn summary(&self) -> RateSummary {
+        self.summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_alarm() {
+        let mut monitor = RateMonitor::default();
+        monitor.set_thresholds(RateThresholds {
+            max_event_rate: Some(2),
+            max_instance_rate: Some(5),
+        });
+
+        monitor.record(0, 1, 3);
+        monitor.record(500, 1, 3); // 2 events, 6 instances in second 0
+        monitor.record(1000, 3, 0); // 3 events in second 1
+        monitor.record(5000, 1, 1);
+        monitor.finish_second();
+
+        assert_eq!(
+            monitor.summary(),
+            RateSummary {
+                peak_event_rate: 3,
+                peak_instance_rate: 6,
+                event_rate_alarms: 1,
+                instance_rate_alarms: 1,
+            }
+        );
+    }
+}
@@ -7,8 +7,10 @@ pub enum StateInfo {
     InitFreq { next_state: u32 },
     AggFreq { next_state: u32, frequency: u32 },
     AggFlow { next_state: u32 },
+    Static { next_state: u32 },
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub enum StateData {
     Default {
@@ -47,6 +49,7 @@ impl From<StateInfo> for StateData {
                 current_set: HashSet::new(),
             },
             StateInfo::AggFlow { next_state } => StateData::Default { next_state },
+            StateInfo::Static { next_state } => StateData::Default { next_state },
         }
     }
 }
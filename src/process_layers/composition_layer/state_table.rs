@@ -5,6 +5,7 @@ use super::StateInfo;
 use crate::pattern::{PatternEventType, SubPattern};
 use ahash::{HashMap, HashMapExt};
 use log::debug;
+use serde_json::{json, Value};
 use std::slice::Iter;
 
 pub struct StateTable {
@@ -49,7 +50,12 @@ impl StateTable {
 
                 let next_state = (table.len() + 1) as u32;
                 match pattern.event_type {
-                    PatternEventType::Default | PatternEventType::Flow => {
+                    PatternEventType::Default if pattern.is_static => {
+                        table.push((StateInfo::Static { next_state }, filter_info));
+                    }
+                    PatternEventType::Default
+                    | PatternEventType::Flow
+                    | PatternEventType::Aggregate { .. } => {
                         table.push((StateInfo::Default { next_state }, filter_info));
                     }
                     PatternEventType::Frequency(frequency) => {
@@ -106,11 +112,67 @@ impl StateTable {
         &self.table[state_id as usize].1
     }
 
+    /// The id of the sub-pattern that the state `state_id` belongs to, i.e. that of the first
+    /// output state from it.
+    pub fn sub_pattern_of(&self, state_id: u32) -> Option<u32> {
+        self.table[state_id as usize..]
+            .iter()
+            .find_map(|(state_info, _)| match state_info {
+                StateInfo::Output { subpattern_id } => Some(*subpattern_id),
+                _ => None,
+            })
+    }
+
     /// Return the shared-node information of the pattern event with match index `match_idx`.
     pub fn get_shared_node_info(&self, match_idx: usize) -> SharedNodeInfo {
         self.shared_node_info[match_idx]
     }
 
+    /// Describe the states in json, along with their filters and the shared-node information of
+    /// their pattern events.
+    pub fn explain(&self) -> Value {
+        let states = self
+            .table
+            .iter()
+            .enumerate()
+            .map(|(state_id, (state_info, filter_info))| {
+                let mut state = match *state_info {
+                    StateInfo::Default { next_state } => {
+                        json!({ "type": "Default", "next_state": next_state })
+                    }
+                    StateInfo::Output { subpattern_id } => {
+                        json!({ "type": "Output", "subpattern_id": subpattern_id })
+                    }
+                    StateInfo::InitFreq { next_state } => {
+                        json!({ "type": "InitFreq", "next_state": next_state })
+                    }
+                    StateInfo::AggFreq {
+                        next_state,
+                        frequency,
+                    } => json!({
+                        "type": "AggFreq",
+                        "next_state": next_state,
+                        "frequency": frequency,
+                    }),
+                    StateInfo::AggFlow { next_state } => {
+                        json!({ "type": "AggFlow", "next_state": next_state })
+                    }
+                    StateInfo::Static { next_state } => {
+                        json!({ "type": "Static", "next_state": next_state })
+                    }
+                };
+                state["state_id"] = json!(state_id);
+                state["filter"] = explain_filter(filter_info);
+                if let Some(match_idx) = filter_info.match_idx() {
+                    let shared_node_info = self.shared_node_info[match_idx];
+                    state["shared_node_info"] = json!(format!("{:?}", shared_node_info));
+                }
+                state
+            })
+            .collect();
+        Value::Array(states)
+    }
+
     pub fn get_next_state(&self, state_id: u32) -> u32 {
         match self.table[state_id as usize].0 {
             StateInfo::Default { next_state } => next_state,
@@ -121,7 +183,38 @@ impl StateTable {
                 frequency: _,
             } => next_state,
             StateInfo::AggFlow { next_state } => next_state,
+            StateInfo::Static { next_state } => next_state,
+        }
+    }
+}
+
+fn explain_filter(filter_info: &FilterInfo) -> Value {
+    let encode = |entity: EntityEncode| format!("{:?}", entity);
+    match *filter_info {
+        FilterInfo::None => json!({ "type": "None" }),
+        FilterInfo::MatchIdxOnly { match_idx } => {
+            json!({ "type": "MatchIdxOnly", "match_idx": match_idx })
         }
+        FilterInfo::Subject { match_idx, subject } => json!({
+            "type": "Subject",
+            "match_idx": match_idx,
+            "subject": encode(subject),
+        }),
+        FilterInfo::Object { match_idx, object } => json!({
+            "type": "Object",
+            "match_idx": match_idx,
+            "object": encode(object),
+        }),
+        FilterInfo::Endpoints {
+            match_idx,
+            subject,
+            object,
+        } => json!({
+            "type": "Endpoints",
+            "match_idx": match_idx,
+            "subject": encode(subject),
+            "object": encode(object),
+        }),
     }
 }
 
@@ -0,0 +1,131 @@
+use super::filter::Filter;
+use super::instance_runner::construct_regex_patterns;
+use super::pattern_info::{PatternInfo, SharedNodeInfo};
+use super::state_table::StateTable;
+use super::{InstanceStorage, MatchInstance};
+use crate::input_event::InputEvent;
+use crate::match_event::{MatchEvent, RawEvents};
+use crate::pattern::PatternEvent;
+use crate::process_layers::regex_limits::build_regex;
+use ahash::{HashMap, HashMapExt};
+use log::warn;
+use regex::Error;
+use std::rc::Rc;
+
+/// The events of a static context graph matching a static pattern event, indexed by the input
+/// entity shared with the earlier events of its sub-pattern.
+struct StaticEvents<'p> {
+    pattern: &'p PatternEvent,
+    shared_node_info: SharedNodeInfo,
+    /// Keyed by the subject if it is shared, otherwise by the object.
+    events: HashMap<u64, Vec<Rc<InputEvent>>>,
+}
+
+/// The index of a static context graph, e.g. asset inventory edges, which is always considered
+/// in-window. The instances reaching a static pattern event are extended with the matching static
+/// events right away, instead of waiting for the input stream.
+pub struct StaticIndex<'p> {
+    /// Keyed by the match index of the static pattern events.
+    static_events: HashMap<usize, StaticEvents<'p>>,
+}
+
+impl<'p> StaticIndex<'p> {
+    pub fn new(
+        pattern_infos: &[PatternInfo<'p>],
+        events: &[Rc<InputEvent>],
+        use_regex: bool,
+    ) -> Result<Self, Error> {
+        let mut static_events = HashMap::new();
+        for info in pattern_infos {
+            let PatternInfo::Static(info) = info else {
+                continue;
+            };
+            if matches!(info.shared_node_info, SharedNodeInfo::None) {
+                warn!(
+                    "static pattern event {} shares no entity with the earlier events in its \
+                    sub-pattern, so it never matches",
+                    info.pattern.id
+                );
+            }
+
+            let regex = build_regex(&construct_regex_patterns(info.pattern, !use_regex).join("|"))?;
+            let mut indexed: HashMap<u64, Vec<Rc<InputEvent>>> = HashMap::new();
+            for event in events {
+                if regex.is_match(event.get_signatures()) {
+                    let key = match info.shared_node_info {
+                        SharedNodeInfo::Object => event.object_id,
+                        _ => event.subject_id,
+                    };
+                    indexed.entry(key).or_default().push(event.clone());
+                }
+            }
+            static_events.insert(
+                info.match_idx,
+                StaticEvents {
+                    pattern: info.pattern,
+                    shared_node_info: info.shared_node_info,
+                    events: indexed,
+                },
+            );
+        }
+        Ok(Self { static_events })
+    }
+
+    /// Extend `instance`, which waits for a static pattern event, with each matching static event.
+    /// The static events take the time of the latest event in `instance`, so that they never
+    /// expire the instance nor break the order relation.
+    pub fn extend(&self, instance: &MatchInstance, state_table: &StateTable) -> Vec<MatchInstance> {
+        let filter_info = state_table.get_filter_info(instance.state_id);
+        let (match_idx, key, object) = match InstanceStorage::extract_filter(instance, filter_info)
+        {
+            Some(Filter::Subject { match_idx, subject }) => (match_idx, subject, None),
+            Some(Filter::Object { match_idx, object }) => (match_idx, object, None),
+            Some(Filter::Endpoints {
+                match_idx,
+                subject,
+                object,
+            }) => (match_idx, subject, Some(object)),
+            Some(Filter::MatchIdxOnly { .. }) | None => return vec![],
+        };
+        let Some(static_events) = self.static_events.get(&match_idx) else {
+            return vec![];
+        };
+        let pattern = static_events.pattern;
+
+        let time = instance
+            .match_events
+            .last()
+            .map_or(0, |event| event.raw_events.get_interval().1);
+        let next_state = state_table.get_next_state(instance.state_id);
+        let mut new_instances = vec![];
+        for event in static_events.events.get(&key).into_iter().flatten() {
+            if object.is_some_and(|object| event.object_id != object) {
+                continue;
+            }
+            let mut event = InputEvent::clone(event);
+            event.timestamp = time;
+            let new_event = MatchEvent {
+                match_id: pattern.id as u32,
+                input_subject_id: event.subject_id,
+                input_object_id: event.object_id,
+                pattern_subject_id: pattern.subject.id as u64,
+                pattern_object_id: pattern.object.id as u64,
+                raw_events: RawEvents::Single(Rc::new(event)),
+            };
+            if !instance.satisfy_distinct_entities(&new_event, &pattern.distinct_entities) {
+                continue;
+            }
+            // static events are shared by all the instances, so they are exempted from the event
+            // uniqueness as flows
+            if let Some(mut new_instance) = instance.clone_extend_flow(
+                new_event,
+                static_events.shared_node_info,
+                pattern.wildcards(),
+            ) {
+                new_instance.state_id = next_state;
+                new_instances.push(new_instance);
+            }
+        }
+        new_instances
+    }
+}
@@ -0,0 +1,242 @@
+use crate::pattern::CompositeExpr;
+use crate::pattern_match::PatternMatch;
+use itertools::Itertools;
+use log::debug;
+use std::collections::VecDeque;
+use std::fmt;
+use std::iter::Peekable;
+use std::rc::Rc;
+
+/// A match of a composite pattern.
+#[derive(Debug, Clone)]
+pub struct CompositeMatch {
+    pub earliest_time: u64,
+    pub latest_time: u64,
+    /// The matches of the component patterns, along with the pattern indices.
+    pub matches: Vec<(usize, Rc<PatternMatch>)>,
+}
+
+impl CompositeMatch {
+    fn single(pattern_id: usize, pattern_match: PatternMatch) -> Self {
+        Self {
+            earliest_time: pattern_match.earliest_time,
+            latest_time: pattern_match.latest_time,
+            matches: vec![(pattern_id, Rc::new(pattern_match))],
+        }
+    }
+
+    fn merge(&self, other: &CompositeMatch) -> Self {
+        Self {
+            earliest_time: self.earliest_time.min(other.earliest_time),
+            latest_time: self.latest_time.max(other.latest_time),
+            matches: self.matches.iter().chain(&other.matches).cloned().collect(),
+        }
+    }
+}
+
+impl fmt::Display for CompositeMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start_t = self.earliest_time as f32 / 1000.0;
+        let end_t = self.latest_time as f32 / 1000.0;
+        let matches = self
+            .matches
+            .iter()
+            .map(|(pattern_id, pattern_match)| format!("{pattern_id}: {pattern_match}"))
+            .join(", ");
+        write!(f, "<{start_t:.3}, {end_t:.3}>{{{matches}}}")
+    }
+}
+
+/// A node of the composite expression, along with its matching state.
+enum Node {
+    Pattern(usize),
+    And {
+        operands: Vec<Node>,
+        within: u64,
+        /// The recent matches of each operand.
+        buffers: Vec<VecDeque<Rc<CompositeMatch>>>,
+    },
+    Or(Vec<Node>),
+}
+
+impl Node {
+    fn new(expr: &CompositeExpr, window_size: u64) -> Self {
+        match expr {
+            CompositeExpr::Pattern(pattern_id) => Node::Pattern(*pattern_id),
+            CompositeExpr::And { operands, within } => Node::And {
+                operands: operands.iter().map(|e| Node::new(e, window_size)).collect(),
+                within: within.unwrap_or(window_size),
+                buffers: vec![VecDeque::new(); operands.len()],
+            },
+            CompositeExpr::Or(operands) => {
+                Node::Or(operands.iter().map(|e| Node::new(e, window_size)).collect())
+            }
+        }
+    }
+
+    /// Feed a new match of a single pattern, and returns the new matches of this node.
+    ///
+    /// Input matches must arrive in the ascending order of `latest_time`, so that a match of an
+    /// `And` node is produced exactly once, when its last component arrives.
+    fn feed(&mut self, input: &Rc<CompositeMatch>) -> Vec<Rc<CompositeMatch>> {
+        match self {
+            Node::Pattern(pattern_id) => {
+                if input.matches[0].0 == *pattern_id {
+                    vec![input.clone()]
+                } else {
+                    vec![]
+                }
+            }
+            Node::Or(operands) => operands.iter_mut().flat_map(|op| op.feed(input)).collect(),
+            Node::And {
+                operands,
+                within,
+                buffers,
+            } => {
+                let expire_time = input.latest_time.saturating_sub(*within);
+                for buffer in buffers.iter_mut() {
+                    buffer.retain(|m| m.earliest_time >= expire_time);
+                }
+
+                let mut outputs = vec![];
+                for (i, operand) in operands.iter_mut().enumerate() {
+                    for new_match in operand.feed(input) {
+                        combine(new_match.clone(), buffers, i, 0, *within, &mut outputs);
+                        buffers[i].push_back(new_match);
+                    }
+                }
+                outputs
+            }
+        }
+    }
+}
+
+/// Extend `partial` with a match from each buffer (except the one of operand `skip`) starting from
+/// `index`, keeping the whole span within `within`.
+fn combine(
+    partial: Rc<CompositeMatch>,
+    buffers: &[VecDeque<Rc<CompositeMatch>>],
+    skip: usize,
+    index: usize,
+    within: u64,
+    outputs: &mut Vec<Rc<CompositeMatch>>,
+) {
+    if index == buffers.len() {
+        outputs.push(partial);
+        return;
+    }
+    if index == skip {
+        combine(partial, buffers, skip, index + 1, within, outputs);
+        return;
+    }
+    for candidate in &buffers[index] {
+        let merged = partial.merge(candidate);
+        if merged.latest_time - merged.earliest_time <= within {
+            combine(Rc::new(merged), buffers, skip, index + 1, within, outputs);
+        }
+    }
+}
+
+/// The layer that correlates the match streams of several patterns according to a composite
+/// expression.
+///
+/// Each input stream must be in the ascending order of `latest_time`, see
+/// [`UniquenessLayer::set_ordered_output`](super::UniquenessLayer::set_ordered_output).
+pub struct CorrelationLayer<P: Iterator> {
+    prev_layers: Vec<Peekable<P>>,
+    expression: Node,
+    outputs: VecDeque<Rc<CompositeMatch>>,
+}
+
+impl<P> CorrelationLayer<P>
+where
+    P: Iterator<Item = PatternMatch>,
+{
+    /// `prev_layers[i]` yields the matches of the pattern `i` in the expression. `window_size` is
+    /// used by the `And` expressions without their own window.
+    pub fn new(prev_layers: Vec<P>, expression: &CompositeExpr, window_size: u64) -> Self {
+        Self {
+            prev_layers: prev_layers.into_iter().map(Iterator::peekable).collect(),
+            expression: Node::new(expression, window_size),
+            outputs: VecDeque::new(),
+        }
+    }
+
+    /// Pop the earliest match among all the input streams.
+    fn next_input(&mut self) -> Option<(usize, PatternMatch)> {
+        let (pattern_id, _) = self
+            .prev_layers
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, layer)| Some((i, layer.peek()?.latest_time)))
+            .min_by_key(|(_, latest_time)| *latest_time)?;
+        Some((pattern_id, self.prev_layers[pattern_id].next()?))
+    }
+}
+
+impl<P> Iterator for CorrelationLayer<P>
+where
+    P: Iterator<Item = PatternMatch>,
+{
+    type Item = CompositeMatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.outputs.is_empty() {
+            let (pattern_id, pattern_match) = self.next_input()?;
+            debug!(
+                "correlate match of pattern {}: {}",
+                pattern_id, pattern_match
+            );
+            let input = Rc::new(CompositeMatch::single(pattern_id, pattern_match));
+            self.outputs.extend(self.expression.feed(&input));
+        }
+        let output = self.outputs.pop_front()?;
+        let mut output = Rc::try_unwrap(output).unwrap_or_else(|rc| (*rc).clone());
+        output.matches.sort_by_key(|(pattern_id, _)| *pattern_id);
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_ids(composite_match: &CompositeMatch) -> Vec<u64> {
+        composite_match
+            .matches
+            .iter()
+            .map(|(_, m)| m.event_ids[0].0)
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn test_and_or() {
+        // 0 AND (1 OR 2) within 12
+        let expression = CompositeExpr::And {
+            operands: vec![
+                CompositeExpr::Pattern(0),
+                CompositeExpr::Or(vec![CompositeExpr::Pattern(1), CompositeExpr::Pattern(2)]),
+            ],
+            within: Some(12),
+        };
+        let streams = vec![
+            vec![
+                PatternMatch::for_test(0, 2, &[100]),
+                PatternMatch::for_test(20, 25, &[101]),
+            ]
+            .into_iter(),
+            vec![PatternMatch::for_test(3, 5, &[200])].into_iter(),
+            vec![
+                PatternMatch::for_test(8, 12, &[300]),
+                PatternMatch::for_test(40, 50, &[301]),
+            ]
+            .into_iter(),
+        ];
+
+        let layer = CorrelationLayer::new(streams, &expression, 1000);
+        let results: Vec<Vec<u64>> = layer.map(|m| event_ids(&m)).collect();
+        // 101 and 301 are too far from everything else
+        assert_eq!(results, [vec![100, 200], vec![100, 300]]);
+    }
+}
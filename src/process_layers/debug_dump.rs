@@ -0,0 +1,46 @@
+use log::{info, warn};
+use serde_json::Value;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+/// Layers whose internal state can be serialized for offline inspection.
+pub trait DumpState {
+    /// Returns the internal state of this layer (and the layers it pulls from) in JSON.
+    fn dump_state(&self) -> Value;
+}
+
+/// A request to dump the state of the layers to `path` once the stream reaches `time`.
+#[derive(Debug, Clone)]
+pub struct DebugDump {
+    /// The stream time (in milliseconds) at which the dump is taken.
+    pub time: u64,
+    /// The output JSON file.
+    pub path: PathBuf,
+}
+
+impl DebugDump {
+    pub fn new(time: u64, path: impl Into<PathBuf>) -> Self {
+        Self {
+            time,
+            path: path.into(),
+        }
+    }
+
+    /// Write `state` to the output file. Failures are logged rather than aborting the matching
+    /// process, as the dump is only a debugging aid.
+    pub fn write(&self, state: &Value) {
+        let result = File::create(&self.path)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| serde_json::to_writer_pretty(BufWriter::new(file), state));
+
+        match result {
+            Ok(()) => info!("Layer states dumped to {}", self.path.display()),
+            Err(err) => warn!(
+                "Failed to dump layer states to {}: {}",
+                self.path.display(),
+                err
+            ),
+        }
+    }
+}
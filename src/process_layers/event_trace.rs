@@ -0,0 +1,89 @@
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+/// Traces the decisions of the layers on a few given input events, e.g. to tell why an event is
+/// not part of any match. Give the same `EventTrace` to the layers, see
+/// [`PipelineConfig::with_event_trace`](super::PipelineConfig::with_event_trace).
+///
+/// Whenever a traced event passes a decision point, a record is given to the sink, e.g.
+///
+/// ```json
+/// {"event_id": 456, "layer": "composition", "decision": "extend", "state_id": 3, ...}
+/// ```
+///
+/// The decisions are
+/// - `signature`: the event arrives at the Composition layer, with the ids of the pattern events
+///   whose signatures it matches (flow events excluded), possibly none,
+/// - `extend`: a match instance is extended with the event,
+/// - `sub-pattern match`: an instance containing the event completes its sub-pattern,
+/// - `join input`: the Join layer receives a sub-pattern match containing the event,
+/// - `full match`: the event is in a match of the whole pattern,
+/// - `duplicate`, `anchor limit` and `emit`: a full match containing the event is dropped as a
+///   duplicate, dropped over the anchor limit, or output by the Uniqueness layer.
+#[derive(Clone)]
+pub struct EventTrace(Rc<Inner>);
+
+type Sink = Box<dyn FnMut(&Value)>;
+
+struct Inner {
+    event_ids: HashSet<u64>,
+    sink: RefCell<Sink>,
+}
+
+impl EventTrace {
+    pub fn new(
+        event_ids: impl IntoIterator<Item = u64>,
+        sink: impl FnMut(&Value) + 'static,
+    ) -> Self {
+        Self(Rc::new(Inner {
+            event_ids: event_ids.into_iter().collect(),
+            sink: RefCell::new(Box::new(sink)),
+        }))
+    }
+
+    pub fn is_traced(&self, event_id: u64) -> bool {
+        self.0.event_ids.contains(&event_id)
+    }
+
+    /// Record the `decision` of `layer` for each traced event among `event_ids`, with the fields
+    /// of the json object `detail`, which is only built if any event is traced.
+    pub(crate) fn record(
+        &self,
+        event_ids: impl IntoIterator<Item = u64>,
+        layer: &str,
+        decision: &str,
+        detail: impl FnOnce() -> Value,
+    ) {
+        let mut traced = event_ids
+            .into_iter()
+            .filter(|id| self.is_traced(*id))
+            .peekable();
+        if traced.peek().is_none() {
+            return;
+        }
+        let detail = detail();
+        let mut sink = self.0.sink.borrow_mut();
+        for event_id in traced {
+            let mut record = json!({
+                "event_id": event_id,
+                "layer": layer,
+                "decision": decision,
+            });
+            if let (Some(record), Some(detail)) = (record.as_object_mut(), detail.as_object()) {
+                record.extend(detail.clone());
+            }
+            sink(&record);
+        }
+    }
+}
+
+impl fmt::Debug for EventTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EventTrace")
+            .field(&self.0.event_ids)
+            .finish()
+    }
+}
@@ -0,0 +1,215 @@
+use crate::pattern_match::PatternMatch;
+use crate::process_layers::parse_layer::IdleState;
+use ahash::HashMap;
+use itertools::Itertools;
+use log::debug;
+use slab::Slab;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// A group of pattern matches connected by shared input entities, where each match is within the
+/// window of the previous ones.
+#[derive(Debug, Clone)]
+pub struct Incident {
+    /// Assigned in the order the incidents are opened.
+    pub id: u64,
+    pub earliest_time: u64,
+    pub latest_time: u64,
+    /// Sorted ids of the input entities in the matches.
+    pub entities: Vec<u64>,
+    pub matches: Vec<PatternMatch>,
+}
+
+impl Incident {
+    fn merge(&mut self, other: Incident) {
+        self.id = self.id.min(other.id);
+        self.earliest_time = self.earliest_time.min(other.earliest_time);
+        self.latest_time = self.latest_time.max(other.latest_time);
+        self.entities.extend(other.entities);
+        self.matches.extend(other.matches);
+    }
+}
+
+impl fmt::Display for Incident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start_t = self.earliest_time as f32 / 1000.0;
+        let end_t = self.latest_time as f32 / 1000.0;
+        write!(
+            f,
+            "#{} <{start_t:.3}, {end_t:.3}> {} matches over entities [{}]",
+            self.id,
+            self.matches.len(),
+            self.entities.iter().join(", ")
+        )
+    }
+}
+
+/// The layer that clusters the pattern matches sharing input entities into incidents, i.e. the
+/// connected components of the bipartite graph between matches and input entities.
+///
+/// An incident is closed and output once no match arrives to extend it for a window after its
+/// `latest_time`.
+pub struct IncidentLayer<P> {
+    prev_layer: P,
+    window_size: u64,
+    open_incidents: Slab<Incident>,
+    /// Maps an input entity to the open incident containing it.
+    entity_incidents: HashMap<u64, usize>,
+    closed_incidents: VecDeque<Incident>,
+    /// The latest `latest_time` of the input matches.
+    cur_time: u64,
+    num_incidents: u64,
+    /// See `set_idle_state()`.
+    idle_state: Option<IdleState>,
+}
+
+impl<P> IncidentLayer<P> {
+    pub fn new(prev_layer: P, window_size: u64) -> Self {
+        Self {
+            prev_layer,
+            window_size,
+            open_incidents: Slab::new(),
+            entity_incidents: HashMap::default(),
+            closed_incidents: VecDeque::new(),
+            cur_time: 0,
+            num_incidents: 0,
+            idle_state: None,
+        }
+    }
+
+    /// Use the idle state given to the previous layers, see
+    /// [`UniquenessLayer::set_idle_state`](super::UniquenessLayer::set_idle_state).
+    pub fn set_idle_state(&mut self, idle_state: IdleState) {
+        self.idle_state = Some(idle_state);
+    }
+
+    /// Add `pattern_match` to the open incident sharing input entities with it, merging all such
+    /// incidents, or open a new incident.
+    fn add_match(&mut self, pattern_match: PatternMatch) {
+        let entities: Vec<u64> = pattern_match.input_entities().unique().collect();
+        let keys: Vec<usize> = entities
+            .iter()
+            .filter_map(|entity| self.entity_incidents.get(entity).copied())
+            .unique()
+            .collect();
+
+        let key = match keys
+            .iter()
+            .max_by_key(|key| self.open_incidents[**key].matches.len())
+        {
+            Some(&key) => key,
+            None => {
+                self.num_incidents += 1;
+                self.open_incidents.insert(Incident {
+                    id: self.num_incidents,
+                    earliest_time: pattern_match.earliest_time,
+                    latest_time: pattern_match.latest_time,
+                    entities: vec![],
+                    matches: vec![],
+                })
+            }
+        };
+        for other_key in keys.into_iter().filter(|k| *k != key) {
+            let other = self.open_incidents.remove(other_key);
+            debug!(
+                "merge incident {} into {}",
+                other.id, self.open_incidents[key].id
+            );
+            for entity in &other.entities {
+                self.entity_incidents.insert(*entity, key);
+            }
+            self.open_incidents[key].merge(other);
+        }
+
+        let incident = &mut self.open_incidents[key];
+        for entity in entities {
+            if self.entity_incidents.insert(entity, key) != Some(key) {
+                incident.entities.push(entity);
+            }
+        }
+        incident.earliest_time = incident.earliest_time.min(pattern_match.earliest_time);
+        incident.latest_time = incident.latest_time.max(pattern_match.latest_time);
+        incident.matches.push(pattern_match);
+    }
+
+    /// Close the incidents not extended for a window before `time`.
+    fn close_expired(&mut self, time: u64) {
+        let expired: Vec<usize> = self
+            .open_incidents
+            .iter()
+            .filter(|(_, incident)| incident.latest_time.saturating_add(self.window_size) < time)
+            .map(|(key, _)| key)
+            .collect();
+        let mut closed: Vec<Incident> = expired
+            .into_iter()
+            .map(|key| self.open_incidents.remove(key))
+            .collect();
+        closed.sort_unstable_by_key(|incident| incident.id);
+        for mut incident in closed {
+            for entity in &incident.entities {
+                self.entity_incidents.remove(entity);
+            }
+            incident.entities.sort_unstable();
+            self.closed_incidents.push_back(incident);
+        }
+    }
+}
+
+impl<P> Iterator for IncidentLayer<P>
+where
+    P: Iterator<Item = PatternMatch>,
+{
+    type Item = Incident;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.closed_incidents.is_empty() {
+            if let Some(pattern_match) = self.prev_layer.next() {
+                self.cur_time = self.cur_time.max(pattern_match.latest_time);
+                // close first, so that the open incidents are all within the window of the match
+                self.close_expired(self.cur_time);
+                self.add_match(pattern_match);
+            } else if let Some(time) = self.idle_state.as_ref().and_then(IdleState::get) {
+                self.close_expired(time);
+                break;
+            } else {
+                self.close_expired(u64::MAX);
+                break;
+            }
+        }
+        self.closed_incidents.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A match of a single flow event from `subject` to `object` at `time`.
+    fn flow_match(time: u64, subject: u64, object: u64) -> PatternMatch {
+        PatternMatch::for_test(time, time, &[time]).with_flow(subject, object)
+    }
+
+    #[test]
+    fn test_incidents() {
+        let input = vec![
+            flow_match(1, 1, 2),
+            flow_match(2, 3, 4),
+            flow_match(3, 5, 6),
+            flow_match(4, 2, 3),  // connects the first two incidents
+            flow_match(30, 6, 7), // entity 6 is shared, but beyond the window
+        ];
+        let layer = IncidentLayer::new(input.into_iter(), 10);
+
+        let incidents: Vec<_> = layer
+            .map(|incident| (incident.id, incident.entities, incident.matches.len()))
+            .collect();
+        assert_eq!(
+            incidents,
+            [
+                (1, vec![1, 2, 3, 4], 3),
+                (3, vec![5, 6], 1),
+                (4, vec![6, 7], 1)
+            ]
+        );
+    }
+}
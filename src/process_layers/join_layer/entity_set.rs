@@ -0,0 +1,59 @@
+/// A set of pattern entity ids stored as a bitset. A sub-pattern buffer keeps one to check the
+/// entity uniqueness of its joins, so that a join allocates nothing however many entities the
+/// pattern has. The set grows to hold any id inserted.
+#[derive(Debug, Default, Clone)]
+pub struct EntitySet {
+    words: Vec<u64>,
+}
+
+impl EntitySet {
+    /// An empty set holding the ids below `num_entities` without growing.
+    pub fn with_capacity(num_entities: usize) -> Self {
+        Self {
+            words: vec![0; num_entities.div_ceil(64)],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+    }
+
+    /// Adds `id` to the set. Returns `false` if it is already present.
+    pub fn insert(&mut self, id: u64) -> bool {
+        let word = (id / 64) as usize;
+        let bit = 1 << (id % 64);
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        let is_new = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        is_new
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.words
+            .get((id / 64) as usize)
+            .is_some_and(|word| word & (1 << (id % 64)) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut set = EntitySet::with_capacity(10);
+        assert!(set.insert(3));
+        assert!(!set.insert(3));
+        // beyond the capacity and a single word
+        assert!(set.insert(300));
+        assert!(set.contains(300));
+        assert!(!set.contains(299));
+        assert!(!set.contains(10_000));
+
+        set.clear();
+        assert!(!set.contains(3));
+        assert!(set.insert(300));
+    }
+}
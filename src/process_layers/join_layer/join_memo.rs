@@ -0,0 +1,83 @@
+use super::sub_pattern_match::SubPatternMatch;
+use crate::process_layers::composition_layer::match_instance::{
+    EntityAttributes, InputEntityId, PatternEntityId, WildcardEntities,
+};
+use ahash::{HashMap, HashSet, RandomState};
+
+/// All the entity bindings of a sub-pattern match.
+type EntityBindings = (
+    Box<[(InputEntityId, PatternEntityId)]>,
+    EntityAttributes,
+    WildcardEntities,
+);
+
+/// The sibling matches known to conflict with the entity bindings of the new matches of a batch,
+/// see `JoinConflict::Entities`.
+///
+/// When many new matches with the same entity bindings arrive in one batch, e.g. a process reading
+/// a file many times, each of them is joined with the whole sibling buffer. The conflicts found for
+/// the first one hold for the rest, so their joins with the conflicting sibling matches are skipped
+/// without checking the events and the entities again.
+#[derive(Debug, Default)]
+pub struct JoinMemo {
+    /// The time of the batch, as the `current_time` of `JoinLayer::join()`.
+    time: u64,
+    /// The conflicting sibling matches, keyed by the buffer of the new matches and the hash of
+    /// their entity bindings.
+    conflicts: HashMap<(usize, u64), MemoEntry>,
+    hasher: RandomState,
+    num_skipped: u64,
+}
+
+#[derive(Debug)]
+struct MemoEntry {
+    /// The bindings of the first new match with this hash, to tell the others apart from the
+    /// matches whose bindings merely collide with them.
+    bindings: EntityBindings,
+    /// The serials of the conflicting sibling matches.
+    serials: HashSet<u64>,
+}
+
+impl JoinMemo {
+    /// Start memoizing the joins of the batch at `time`, forgetting those of the earlier batches.
+    pub fn start_batch(&mut self, time: u64) {
+        if time != self.time {
+            self.time = time;
+            self.conflicts.clear();
+        }
+    }
+
+    /// The serials of the sibling matches known to conflict with `new_match` in `buffer_id`, or
+    /// `None` if the hash of its bindings collides with that of other bindings.
+    pub fn conflicts(
+        &mut self,
+        buffer_id: usize,
+        new_match: &SubPatternMatch,
+    ) -> Option<&mut HashSet<u64>> {
+        let bindings = (
+            &new_match.match_entities,
+            &new_match.entity_attributes,
+            &new_match.wildcard_entities,
+        );
+        let hash = self.hasher.hash_one(bindings);
+        let entry = self
+            .conflicts
+            .entry((buffer_id, hash))
+            .or_insert_with(|| MemoEntry {
+                bindings: (bindings.0.clone(), bindings.1.clone(), bindings.2.clone()),
+                serials: HashSet::default(),
+            });
+        let (match_entities, entity_attributes, wildcard_entities) = &entry.bindings;
+        let is_same = (match_entities, entity_attributes, wildcard_entities) == bindings;
+        is_same.then_some(&mut entry.serials)
+    }
+
+    pub fn add_skipped(&mut self, num_skipped: u64) {
+        self.num_skipped += num_skipped;
+    }
+
+    /// The number of joins skipped so far.
+    pub fn num_skipped(&self) -> u64 {
+        self.num_skipped
+    }
+}
@@ -0,0 +1,456 @@
+use crate::pattern::SubPattern;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::cmp::{max, min, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum JoinPlanError {
+    #[error("expect {expected} merges for {num_sub_patterns} sub-patterns, but got {actual}")]
+    WrongNumberOfMerges {
+        num_sub_patterns: usize,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("buffer {0} does not exist before it is merged")]
+    UndefinedBuffer(usize),
+
+    #[error("buffer {0} is merged more than once")]
+    BufferMergedTwice(usize),
+
+    #[error("the plan is for {expected} sub-patterns, but the pattern has {actual}")]
+    WrongNumberOfSubPatterns { expected: usize, actual: usize },
+
+    #[error("buffers {0} and {1} have no shared node")]
+    NoSharedNode(usize, usize),
+}
+
+/// The tree structure of the sub-pattern buffers in the Join layer.
+///
+/// Buffers `0..num_sub_patterns` store the matches of the corresponding sub-patterns, and the
+/// `i`-th pair in `merges` is merged into buffer `num_sub_patterns + i`. The last buffer is the
+/// root, which stores the complete pattern matches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinPlan {
+    pub num_sub_patterns: usize,
+    pub merges: Vec<(usize, usize)>,
+}
+
+impl JoinPlan {
+    /// Create a plan from an explicit join order, checking that it forms a binary tree over all
+    /// the sub-pattern buffers.
+    pub fn from_merges(
+        num_sub_patterns: usize,
+        merges: Vec<(usize, usize)>,
+    ) -> Result<Self, JoinPlanError> {
+        check_tree(num_sub_patterns, &merges)?;
+        Ok(Self {
+            num_sub_patterns,
+            merges,
+        })
+    }
+
+    /// Check that the plan forms a binary tree over the buffers of `sub_patterns`, and that each
+    /// merge joins buffers having shared-node relation.
+    pub fn check(&self, sub_patterns: &[SubPattern]) -> Result<(), JoinPlanError> {
+        if self.num_sub_patterns != sub_patterns.len() {
+            return Err(JoinPlanError::WrongNumberOfSubPatterns {
+                expected: self.num_sub_patterns,
+                actual: sub_patterns.len(),
+            });
+        }
+        check_tree(self.num_sub_patterns, &self.merges)?;
+
+        let mut entity_ids: Vec<HashSet<usize>> = sub_patterns
+            .iter()
+            .map(|sub_pattern| {
+                sub_pattern
+                    .events
+                    .iter()
+                    .flat_map(|e| [e.subject.id, e.object.id])
+                    .collect()
+            })
+            .collect();
+        for &(i, j) in &self.merges {
+            if !has_shared_node(&entity_ids[i], &entity_ids[j]) {
+                return Err(JoinPlanError::NoSharedNode(i, j));
+            }
+            let merged = entity_ids[i].union(&entity_ids[j]).copied().collect();
+            entity_ids.push(merged);
+        }
+        Ok(())
+    }
+
+    /// The height of the buffer tree, where a tree with a single buffer has height 1.
+    pub fn height(&self) -> u32 {
+        let mut heights = vec![1u32; self.num_sub_patterns];
+        for &(i, j) in &self.merges {
+            heights.push(max(heights[i], heights[j]) + 1);
+        }
+        heights.last().copied().unwrap_or(0)
+    }
+}
+
+/// Check that `merges` forms a binary tree over `num_sub_patterns` sub-pattern buffers.
+fn check_tree(num_sub_patterns: usize, merges: &[(usize, usize)]) -> Result<(), JoinPlanError> {
+    let expected = num_sub_patterns.saturating_sub(1);
+    if merges.len() != expected {
+        return Err(JoinPlanError::WrongNumberOfMerges {
+            num_sub_patterns,
+            expected,
+            actual: merges.len(),
+        });
+    }
+
+    let mut merged = vec![false; num_sub_patterns + merges.len()];
+    for (k, &(i, j)) in merges.iter().enumerate() {
+        for id in [i, j] {
+            if id >= num_sub_patterns + k {
+                return Err(JoinPlanError::UndefinedBuffer(id));
+            }
+            if merged[id] {
+                return Err(JoinPlanError::BufferMergedTwice(id));
+            }
+            merged[id] = true;
+        }
+    }
+    Ok(())
+}
+
+/// Build the buffer tree heuristically: repeatedly merge the pair of buffers having shared-node
+/// relation with the minimal resulting height.
+///
+/// The optional sub-patterns, which come last (see [`decompose`](crate::pattern::decompose)), are
+/// then merged one by one on top of the tree, so that each of them has the rest of the pattern as
+/// its sibling.
+pub fn build(sub_patterns: &[SubPattern]) -> JoinPlan {
+    let num_sub_patterns = sub_patterns.len();
+    let num_required = sub_patterns
+        .iter()
+        .take_while(|sub_pattern| !sub_pattern.is_optional())
+        .count();
+    let plan = build_tree(&sub_patterns[..num_required]);
+    if num_required == num_sub_patterns {
+        return plan;
+    }
+
+    // the merged buffers come after all the sub-pattern buffers
+    let buffer_id = |id: usize| {
+        if id < num_required {
+            id
+        } else {
+            id - num_required + num_sub_patterns
+        }
+    };
+    let mut merges: Vec<_> = plan
+        .merges
+        .iter()
+        .map(|&(i, j)| (buffer_id(i), buffer_id(j)))
+        .collect();
+    for optional_id in num_required..num_sub_patterns {
+        let root_id = match merges.len() {
+            0 => 0,
+            len => num_sub_patterns + len - 1,
+        };
+        merges.push((root_id, optional_id));
+    }
+
+    JoinPlan {
+        num_sub_patterns,
+        merges,
+    }
+}
+
+fn build_tree(sub_patterns: &[SubPattern]) -> JoinPlan {
+    let num_sub_patterns = sub_patterns.len();
+    let buffer_len = (2 * num_sub_patterns).saturating_sub(1);
+    let mut merges = vec![];
+
+    let mut union_find = UnionFind::new(buffer_len);
+
+    // Indicate whether a sub-pattern buffer has been processed or not.
+    let mut merged = vec![false; buffer_len];
+
+    // For `(h, i, j)` in `min_heap`, buffers `i` and `j` have shared-node relation (can be merged).
+    // If they are merged, the resulting buffer height would be `h`.
+    let mut min_heap = BinaryHeap::new();
+    let shared_node_lists = gen_shared_node_lists(sub_patterns);
+    for (i, list) in shared_node_lists.iter().enumerate() {
+        for j in list {
+            // avoid duplicates
+            if *j <= i {
+                continue;
+            }
+            min_heap.push(Reverse((2u32, i, *j)));
+        }
+    }
+
+    // Each time pop the can-be-merged buffer pair with minimal resulting height.
+    while let Some(Reverse((height, i, j))) = min_heap.pop() {
+        if merged[i] || merged[j] {
+            continue;
+        }
+
+        merged[i] = true;
+        merged[j] = true;
+
+        let new_buffer_id = num_sub_patterns + merges.len();
+        merges.push((i, j));
+        union_find.merge(i, j, new_buffer_id);
+
+        let mut visited = HashSet::new();
+        visited.insert(new_buffer_id);
+
+        // Find all buffers that has shared-node relation with the newly created buffer, for futher merger.
+        for (k, shared_node_list) in shared_node_lists.iter().enumerate() {
+            let cur_root = union_find.get_root(k);
+            if visited.contains(&cur_root) {
+                continue;
+            }
+
+            for id in shared_node_list {
+                // has shared node relation
+                if union_find.get_root(*id) == new_buffer_id {
+                    let new_height = max(height, union_find.get_height(cur_root)) + 1;
+                    min_heap.push(Reverse((new_height, new_buffer_id, cur_root)));
+
+                    visited.insert(cur_root);
+                    break;
+                }
+            }
+        }
+    }
+
+    // If the pattern is disconnected, the remaining buffers share no node and are merged in turn.
+    let mut roots = (0..num_sub_patterns)
+        .map(|k| union_find.get_root(k))
+        .unique();
+    if let Some(mut root) = roots.next() {
+        for other in roots {
+            merges.push((root, other));
+            root = num_sub_patterns + merges.len() - 1;
+        }
+    }
+
+    JoinPlan {
+        num_sub_patterns,
+        merges,
+    }
+}
+
+/// For each sub-pattern, calculate the sub-patterns that have shared-node relation with itself.
+fn gen_shared_node_lists(sub_patterns: &[SubPattern]) -> Vec<Vec<usize>> {
+    let mut shared_node_lists = vec![Vec::new(); sub_patterns.len()];
+    for (i, sub_pattern1) in sub_patterns.iter().enumerate() {
+        let entity_ids1: HashSet<usize> = sub_pattern1
+            .events
+            .iter()
+            .flat_map(|e| [e.subject.id, e.object.id])
+            .collect();
+        for (j, sub_pattern2) in sub_patterns.iter().enumerate() {
+            if j <= i {
+                continue;
+            }
+
+            let entity_ids2: HashSet<usize> = sub_pattern2
+                .events
+                .iter()
+                .flat_map(|e| [e.subject.id, e.object.id])
+                .collect();
+
+            if has_shared_node(&entity_ids1, &entity_ids2) {
+                shared_node_lists[i].push(j);
+                shared_node_lists[j].push(i);
+            }
+        }
+    }
+    shared_node_lists
+}
+
+/// Check whether two entity (node) lists have any shared element.
+fn has_shared_node(entity_ids1: &HashSet<usize>, entity_ids2: &HashSet<usize>) -> bool {
+    entity_ids1.intersection(entity_ids2).next().is_some()
+}
+
+/// Union-find tree, a.k.a disjoint set
+struct UnionFind {
+    /// Store the root (representative element) of a union-find tree (disjoint set).
+    /// For element `id`, if `roots[id] < 0`, them `id` is the root of the tree it belongs to.
+    /// Otherwise, `roots[id]` is the parent of `id` (but not necessary the root).
+    ///
+    /// A disjoint set corresponds to a sub-pattern buffer in the Join layer.
+    /// Note that for `roots[id] < 0`, the value `abs(roots[id])` corresponds to the
+    /// *height of the buffer* in the sub-pattern buffer tree structure.
+    roots: Vec<i64>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        Self {
+            roots: vec![-1; size], // height
+        }
+    }
+
+    /// Return the root element for `id`.
+    fn get_root(&mut self, id: usize) -> usize {
+        if self.roots[id] < 0 {
+            id
+        } else {
+            self.roots[id] = self.get_root(self.roots[id] as usize) as i64;
+            self.roots[id] as usize
+        }
+    }
+
+    /// Return the buffer height that `id` belongs to.
+    fn get_height(&self, id: usize) -> u32 {
+        -self.roots[id] as u32
+    }
+
+    /// Merge two union-find trees, and create a node (corresponds to a new buffer) as the new root.
+    fn merge(&mut self, id1: usize, id2: usize, new_root: usize) {
+        let root1 = self.get_root(id1);
+        let root2 = self.get_root(id2);
+        if root1 == root2 {
+            return;
+        }
+
+        // A new node (corresponds to a new buffer)
+        self.roots[new_root] = min(self.roots[root1], self.roots[root2]) - 1;
+        self.roots[root1] = new_root as i64;
+        self.roots[root2] = new_root as i64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::parser::{parse_json, PatternParsingError};
+    use crate::pattern::{decompose, Pattern};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_build() {
+        let pattern = Pattern::parse("data/universal_patterns/SP8_regex.json").unwrap();
+        let sub_patterns = decompose(&pattern);
+        let plan = build(&sub_patterns);
+
+        // a valid plan is accepted by `from_merges`
+        let plan = JoinPlan::from_merges(plan.num_sub_patterns, plan.merges).unwrap();
+        assert!(plan.height() as usize <= sub_patterns.len());
+    }
+
+    #[test]
+    fn test_build_disconnected() {
+        let pattern = Pattern::from_graph(
+            &["a", "b", "c", "d", "e"],
+            &[(0, 1, "e0"), (2, 3, "e1"), (3, 4, "e2")],
+            false,
+        );
+        let sub_patterns = decompose(&pattern);
+        let plan = build(&sub_patterns);
+        assert!(JoinPlan::from_merges(plan.num_sub_patterns, plan.merges).is_ok());
+    }
+
+    #[test]
+    fn test_build_with_optional() {
+        let content = std::fs::read_to_string("data/universal_patterns/SP8_regex.json").unwrap();
+        let mut json_obj: Value = serde_json::from_str(&content).unwrap();
+        json_obj["Events"][4]["Optional"] = json!(true);
+        json_obj["Events"][9]["Optional"] = json!(true);
+        let pattern = parse_json(&json_obj).unwrap();
+        let sub_patterns = decompose(&pattern);
+        let num_sub_patterns = sub_patterns.len();
+        assert!(!sub_patterns[num_sub_patterns - 3].is_optional());
+        assert!(sub_patterns[num_sub_patterns - 2].is_optional());
+        assert!(sub_patterns[num_sub_patterns - 1].is_optional());
+
+        let plan = build(&sub_patterns);
+        let plan = JoinPlan::from_merges(plan.num_sub_patterns, plan.merges).unwrap();
+        let root_id = 2 * num_sub_patterns - 2;
+        assert_eq!(
+            plan.merges[plan.merges.len() - 2..],
+            [
+                (root_id - 2, num_sub_patterns - 2),
+                (root_id - 1, num_sub_patterns - 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_merges() {
+        assert_eq!(
+            JoinPlan::from_merges(3, vec![(0, 2), (3, 1)]).map(|p| p.height()),
+            Ok(3)
+        );
+        assert_eq!(
+            JoinPlan::from_merges(3, vec![(0, 1)]),
+            Err(JoinPlanError::WrongNumberOfMerges {
+                num_sub_patterns: 3,
+                expected: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(
+            JoinPlan::from_merges(3, vec![(0, 4), (3, 1)]),
+            Err(JoinPlanError::UndefinedBuffer(4))
+        );
+        assert_eq!(
+            JoinPlan::from_merges(3, vec![(0, 1), (0, 2)]),
+            Err(JoinPlanError::BufferMergedTwice(0))
+        );
+    }
+
+    #[test]
+    fn test_join_order_in_pattern() {
+        let content = std::fs::read_to_string("data/universal_patterns/SP8_regex.json").unwrap();
+        let mut json_obj: Value = serde_json::from_str(&content).unwrap();
+
+        json_obj["JoinOrder"] = json!([[0, 2], [1, 3], [4, 5]]);
+        let pattern = parse_json(&json_obj).unwrap();
+        assert_eq!(
+            pattern.join_plan.map(|plan| plan.merges),
+            Some(vec![(0, 2), (1, 3), (4, 5)])
+        );
+
+        json_obj["JoinOrder"] = json!([[0, 2], [1, 3]]);
+        assert!(matches!(
+            parse_json(&json_obj),
+            Err(PatternParsingError::InvalidJoinOrder(_))
+        ));
+
+        // sub-patterns 0 and 3 share no node
+        json_obj["JoinOrder"] = json!([[0, 3], [1, 2], [4, 5]]);
+        assert!(matches!(
+            parse_json(&json_obj),
+            Err(PatternParsingError::InvalidJoinOrder(
+                JoinPlanError::NoSharedNode(0, 3)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_check() {
+        let pattern = Pattern::parse("data/universal_patterns/SP8_regex.json").unwrap();
+        let sub_patterns = decompose(&pattern);
+        let plan = build(&sub_patterns);
+        assert_eq!(plan.check(&sub_patterns), Ok(()));
+        assert_eq!(
+            plan.check(&sub_patterns[1..]),
+            Err(JoinPlanError::WrongNumberOfSubPatterns {
+                expected: 4,
+                actual: 3
+            })
+        );
+
+        let plan = JoinPlan {
+            num_sub_patterns: 4,
+            merges: vec![(0, 1), (0, 2), (4, 5)],
+        };
+        assert_eq!(
+            plan.check(&sub_patterns),
+            Err(JoinPlanError::BufferMergedTwice(0))
+        );
+    }
+}
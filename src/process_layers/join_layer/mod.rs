@@ -1,22 +1,33 @@
+mod entity_set;
+mod join_memo;
+pub mod join_plan;
+mod partial_alert;
 mod sub_pattern_buffer;
 mod sub_pattern_match;
 
 use crate::pattern::Pattern;
 use crate::pattern::SubPattern;
 use crate::pattern_match::PatternMatch;
-use log::debug;
-use std::cmp::max;
-use std::cmp::min;
-use std::cmp::Reverse;
+use crate::trace::{enter_span, trace_event};
+use itertools::Itertools;
+pub use join_plan::JoinPlan;
+use log::{debug, warn};
+pub use partial_alert::{PartialAlert, PartialAlertTrigger, PartialAlerts};
+use serde_json::{json, Value};
 use std::collections::BinaryHeap;
-use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
 use std::vec;
 pub use sub_pattern_buffer::SubPatternBuffer;
-use sub_pattern_match::EarliestFirst;
+use join_memo::JoinMemo;
+use sub_pattern_match::{EarliestFirst, JoinConflict};
 pub use sub_pattern_match::SubPatternMatch;
 
 use super::composition_layer;
-use super::composition_layer::MatchInstance;
+use super::composition_layer::{DeactivationSignal, MatchInstance};
+use super::parse_layer::{contains_retracted, IdleState, Retractions};
+use super::window_policy::{SlidingWindow, WindowPolicy};
+use super::{BufferStats, DebugDump, DumpState, EventTrace, ReportBufferStats, StatsReporter};
 
 /// The layer that joins sub-pattern matches into pattern matches.
 #[derive(Debug)]
@@ -32,7 +43,7 @@ pub struct JoinLayer<'p, P> {
     sub_pattern_buffers: Vec<SubPatternBuffer>,
 
     /// See `clear_expired()`.
-    window_size: u64,
+    window: Rc<dyn WindowPolicy>,
 
     /// Complete pattern matches.
     full_match: Vec<PatternMatch>,
@@ -41,6 +52,76 @@ pub struct JoinLayer<'p, P> {
     sibling_id_map: Vec<usize>,
     /// The parent buffer of buffer `x` is `parent_id_map[x]`.
     parent_id_map: Vec<usize>,
+
+    /// Pending request to dump the layer states, see `set_debug_dump()`.
+    debug_dump: Option<DebugDump>,
+    /// Dumps the state of the previous layer, set along with `debug_dump`.
+    dump_prev_layer: fn(&P) -> Value,
+
+    /// See `set_stats_reporter()` and `add_stats_reporter()`.
+    stats_reporters: Vec<StatsReporter>,
+
+    /// See `set_retractions()`, along with the number of retracted ids already purged.
+    retractions: Option<(Retractions, usize)>,
+
+    /// See `set_partial_alerts()`.
+    partial_alerts: Option<PartialAlerts>,
+
+    /// See `set_watermark_interval()`.
+    watermark: Option<Watermark>,
+
+    /// See `set_sub_match_callback()`.
+    sub_match_callback: Option<SubMatchCallback>,
+
+    /// See `set_event_trace()`.
+    event_trace: Option<EventTrace>,
+
+    /// See `set_join_memo()`.
+    join_memo: Option<JoinMemo>,
+    /// See `set_deactivation_signal()`.
+    deactivation_signal: Option<DeactivationSignal>,
+}
+
+struct SubMatchCallback(Box<dyn FnMut(&SubPatternMatch)>);
+
+impl fmt::Debug for SubMatchCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SubMatchCallback").finish_non_exhaustive()
+    }
+}
+
+/// Publishes the stream time to the later layers periodically, see
+/// [`JoinLayer::set_watermark_interval`].
+#[derive(Debug)]
+struct Watermark {
+    interval: u64,
+    last_time: u64,
+    idle_state: IdleState,
+    /// Whether the stream time is published and the layer is paused.
+    published: bool,
+}
+
+impl Watermark {
+    /// Returns `true` if the stream time has advanced by an interval since the last watermark.
+    fn advance(&mut self, current_time: u64) -> bool {
+        if current_time < self.last_time.saturating_add(self.interval) {
+            return false;
+        }
+        self.last_time = current_time;
+        true
+    }
+
+    fn publish(&mut self, current_time: u64) {
+        self.idle_state.set(Some(current_time));
+        self.published = true;
+    }
+
+    fn resume(&mut self) {
+        if self.published {
+            self.idle_state.set(None);
+            self.published = false;
+        }
+    }
 }
 
 impl<'p, P> JoinLayer<'p, P> {
@@ -70,13 +151,36 @@ impl<'p, P> JoinLayer<'p, P> {
         ));
     }
 
-    /// Mainly construct the tree-structure of sub-pattern buffers.
+    /// Mainly construct the tree-structure of sub-pattern buffers, following the explicit join
+    /// order of the pattern if any, or the plan built by [`join_plan::build`] otherwise. A join
+    /// order failing [`JoinPlan::check`] against `sub_patterns` is ignored with a warning.
     /// Note that all sub-pattern buffers have shared-node relation with their corresponding sibling.
     pub fn new(
         prev_layer: P,
         pattern: &'p Pattern,
         sub_patterns: &[SubPattern<'p>],
         window_size: u64,
+    ) -> Self {
+        let plan = match &pattern.join_plan {
+            Some(plan) => match plan.check(sub_patterns) {
+                Ok(()) => plan.clone(),
+                Err(err) => {
+                    warn!("Ignoring the JoinOrder of the pattern: {err}");
+                    join_plan::build(sub_patterns)
+                }
+            },
+            None => join_plan::build(sub_patterns),
+        };
+        Self::with_plan(prev_layer, pattern, sub_patterns, &plan, window_size)
+    }
+
+    /// Construct the tree-structure of sub-pattern buffers as specified by `plan`.
+    pub fn with_plan(
+        prev_layer: P,
+        pattern: &'p Pattern,
+        sub_patterns: &[SubPattern<'p>],
+        plan: &JoinPlan,
+        window_size: u64,
     ) -> Self {
         let buffer_len = 2 * sub_patterns.len() - 1;
         let mut sub_pattern_buffers = Vec::with_capacity(buffer_len);
@@ -94,34 +198,7 @@ impl<'p, P> JoinLayer<'p, P> {
             ));
         }
 
-        let mut union_find = UnionFind::new(buffer_len);
-
-        // Indicate whether a sub-pattern buffer has been processed or not.
-        let mut merged = vec![false; buffer_len];
-
-        // For `(h, i, j)` in `min_heap`, buffers `i` and `j` have shared-node relation (can be merged).
-        // If they are merged, the resulting buffer height would be `h`.
-        let mut min_heap = BinaryHeap::new();
-        let shared_node_lists = Self::gen_shared_node_lists(sub_patterns);
-        for (i, list) in shared_node_lists.iter().enumerate() {
-            for j in list {
-                // avoid duplicates
-                if *j <= i {
-                    continue;
-                }
-                min_heap.push(Reverse((2u32, i, *j)));
-            }
-        }
-
-        // Each time pop the can-be-merged buffer pair with minimal resulting height.
-        while let Some(Reverse((height, i, j))) = min_heap.pop() {
-            if merged[i] || merged[j] {
-                continue;
-            }
-
-            merged[i] = true;
-            merged[j] = true;
-
+        for &(i, j) in &plan.merges {
             let new_buffer_id = sub_pattern_buffers.len();
             Self::create_buffer_pair(i, j, new_buffer_id, pattern, &mut sub_pattern_buffers);
             sibling_id_map[i] = j;
@@ -133,75 +210,238 @@ impl<'p, P> JoinLayer<'p, P> {
                 "buffer {} and buffer {} are merged into buffer {}",
                 i, j, new_buffer_id
             );
-
-            union_find.merge(i, j, new_buffer_id);
-
-            let mut visited = HashSet::new();
-            visited.insert(new_buffer_id);
-
-            // Find all buffers that has shared-node relation with the newly created buffer, for futher merger.
-            for k in 0..sub_patterns.len() {
-                let cur_root = union_find.get_root(k);
-                if visited.contains(&cur_root) {
-                    continue;
-                }
-
-                for id in &shared_node_lists[k] {
-                    // has shared node relation
-                    if union_find.get_root(*id) == new_buffer_id {
-                        let new_height = max(height, union_find.get_height(cur_root)) + 1;
-                        min_heap.push(Reverse((new_height, new_buffer_id, cur_root)));
-
-                        visited.insert(cur_root);
-                        break;
-                    }
-                }
-            }
         }
 
         Self {
             prev_layer,
             pattern,
             sub_pattern_buffers,
-            window_size,
+            window: Rc::new(SlidingWindow::new(window_size)),
             full_match: Vec::new(),
             sibling_id_map,
             parent_id_map,
+            debug_dump: None,
+            dump_prev_layer: |_| Value::Null,
+            stats_reporters: vec![],
+            retractions: None,
+            partial_alerts: None,
+            watermark: None,
+            sub_match_callback: None,
+            event_trace: None,
+            join_memo: Some(JoinMemo::default()),
+            deactivation_signal: None,
         }
     }
 
-    /// For each sub-pattern, calculate the sub-patterns that have shared-node relation with itself.
-    fn gen_shared_node_lists(sub_patterns: &[SubPattern<'p>]) -> Vec<Vec<usize>> {
-        let mut shared_node_lists = vec![Vec::new(); sub_patterns.len()];
-        for (i, sub_pattern1) in sub_patterns.iter().enumerate() {
-            let entity_ids1: HashSet<usize> = sub_pattern1
-                .events
-                .iter()
-                .flat_map(|e| [e.subject.id, e.object.id])
-                .collect();
-            for (j, sub_pattern2) in sub_patterns.iter().enumerate() {
-                if j <= i {
-                    continue;
-                }
+    pub fn prev_layer(&self) -> &P {
+        &self.prev_layer
+    }
+
+    pub fn prev_layer_mut(&mut self) -> &mut P {
+        &mut self.prev_layer
+    }
+
+    /// Replace the default sliding window of `window_size` given to `new()`.
+    pub fn set_window_policy(&mut self, window: Rc<dyn WindowPolicy>) {
+        self.window = window;
+    }
+
+    /// Whether the input is a multi-host data graph. If so, sub-pattern matches on different
+    /// hosts are not joined, unless the pattern sets `span_hosts`.
+    pub fn set_multi_host(&mut self, multi_host: bool) {
+        for buffer in &mut self.sub_pattern_buffers {
+            buffer.relation.single_host = multi_host && !self.pattern.span_hosts;
+        }
+    }
+
+    /// Alert the partial matches meeting the trigger of `partial_alerts` as they are joined.
+    ///
+    /// Partial matches are joined from whole sub-patterns, so a pattern of a single sub-pattern
+    /// has no partial alerts.
+    pub fn set_partial_alerts(&mut self, mut partial_alerts: PartialAlerts) {
+        partial_alerts.set_buffers(&self.sub_pattern_buffers, &self.parent_id_map);
+        self.partial_alerts = Some(partial_alerts);
+    }
+
+    /// Request a dump of the states of this layer and its previous layers.
+    ///
+    /// The dump is taken right after the first sub-pattern match whose latest time reaches
+    /// `debug_dump.time` is joined, or at the end of the stream if no such match arrives.
+    pub fn set_debug_dump(&mut self, debug_dump: DebugDump)
+    where
+        P: DumpState,
+    {
+        self.debug_dump = Some(debug_dump);
+        self.dump_prev_layer = P::dump_state;
+    }
 
-                let entity_ids2: HashSet<usize> = sub_pattern2
-                    .events
-                    .iter()
-                    .flat_map(|e| [e.subject.id, e.object.id])
-                    .collect();
+    /// Every `interval` of stream time, clear the expired sub-pattern matches in all the buffers,
+    /// and pause (`next()` returns `None`) with the stream time published to `idle_state`, so
+    /// that the later layers flush the matches whose window has closed instead of holding them
+    /// until another match completes. The next call to `next()` resumes.
+    ///
+    /// The stream time is that of the sub-pattern matches, so the later layers must be given
+    /// `idle_state` (e.g. [`UniquenessLayer::set_idle_state`](super::UniquenessLayer::set_idle_state))
+    /// and be drained again while it is idle.
+    pub fn set_watermark_interval(&mut self, interval: u64, idle_state: IdleState) {
+        self.watermark = Some(Watermark {
+            interval,
+            last_time: 0,
+            idle_state,
+            published: false,
+        });
+    }
+
+    /// Periodically report the buffer statistics of this layer and its previous layers, as the
+    /// stream time advances.
+    pub fn set_stats_reporter(&mut self, stats_reporter: StatsReporter) {
+        self.stats_reporters = vec![stats_reporter];
+    }
+
+    /// Same as `set_stats_reporter()`, but keeps the previous reporters, e.g. to report at
+    /// another interval.
+    pub fn add_stats_reporter(&mut self, stats_reporter: StatsReporter) {
+        self.stats_reporters.push(stats_reporter);
+    }
 
-                if Self::has_shared_node(&entity_ids1, &entity_ids2) {
-                    shared_node_lists[i].push(j);
-                    shared_node_lists[j].push(i);
+    /// Call `callback` with each sub-pattern match from the previous layer, before it is joined.
+    pub fn set_sub_match_callback(&mut self, callback: impl FnMut(&SubPatternMatch) + 'static) {
+        self.sub_match_callback = Some(SubMatchCallback(Box::new(callback)));
+    }
+
+    /// Trace the sub-pattern matches and the full matches containing the events in `event_trace`.
+    pub fn set_event_trace(&mut self, event_trace: EventTrace) {
+        self.event_trace = Some(event_trace);
+    }
+
+    /// Whether to memoize the sibling matches whose entity bindings conflict with the new matches
+    /// of a batch, so that the new matches with the same bindings skip joining with them. Enabled
+    /// by default; when the bindings seldom repeat, it costs hashing the bindings of each new match
+    /// and keeping a copy of each distinct binding until the next batch.
+    pub fn set_join_memo(&mut self, join_memo: bool) {
+        self.join_memo = join_memo.then(JoinMemo::default);
+    }
+
+    /// Drop all the buffered sub-pattern matches once the previous layer raises `signal`, i.e.
+    /// deactivates the pattern. The signal is checked each time the previous layer returns, which
+    /// for a deactivated pattern is when its input is idle or exhausted, see
+    /// [`CompositionLayer::set_deactivation_signal`](super::CompositionLayer::set_deactivation_signal).
+    pub fn set_deactivation_signal(&mut self, signal: DeactivationSignal) {
+        self.deactivation_signal = Some(signal);
+    }
+
+    /// The number of joins skipped by `set_join_memo()`.
+    pub fn num_memoized_joins(&self) -> u64 {
+        self.join_memo.as_ref().map_or(0, JoinMemo::num_skipped)
+    }
+
+    /// Purge the sub-pattern matches containing the input events retracted by tombstone records,
+    /// see [`ParseLayer::set_retractions`](super::ParseLayer::set_retractions).
+    pub fn set_retractions(&mut self, retractions: Retractions) {
+        self.retractions = Some((retractions, 0));
+    }
+
+    fn purge_retracted(&mut self) {
+        let Some((retractions, cursor)) = &mut self.retractions else {
+            return;
+        };
+        let retracted_ids = retractions.take_new(cursor);
+        if retracted_ids.is_empty() {
+            return;
+        }
+        let is_alive = |sub_pattern_match: &EarliestFirst| {
+            let event_ids = sub_pattern_match.0.event_ids.iter().map(|(id, _)| *id);
+            !contains_retracted(event_ids, &retracted_ids)
+        };
+        for buffer in &mut self.sub_pattern_buffers {
+            buffer.buffer.retain(is_alive);
+            buffer.new_match_buffer.retain(is_alive);
+        }
+        debug!("retract events {:?} from the join buffers", retracted_ids);
+    }
+
+    /// The sizes of the sub-pattern buffers, including their new matches.
+    pub fn buffer_stats(&self) -> Vec<BufferStats> {
+        self.sub_pattern_buffers
+            .iter()
+            .map(|buffer| {
+                let mut stats = BufferStats::new(format!("join.buffer {}", buffer.id));
+                for sub_pattern_match in buffer.buffer.iter().chain(&buffer.new_match_buffer) {
+                    stats.add(
+                        sub_pattern_match.0.earliest_time,
+                        sub_pattern_match.0.approx_bytes(),
+                    );
                 }
-            }
+                stats
+            })
+            .collect()
+    }
+
+    fn try_report_stats(&mut self, current_time: u64)
+    where
+        P: ReportBufferStats,
+    {
+        if self.stats_reporters.is_empty() {
+            return;
+        }
+        let mut stats_reporters = std::mem::take(&mut self.stats_reporters);
+        let watermark = self.window.window_bound(current_time);
+        for stats_reporter in &mut stats_reporters {
+            stats_reporter.tick(current_time, watermark, self);
+        }
+        self.stats_reporters = stats_reporters;
+    }
+
+    /// Perform the requested dump if the stream has reached the requested time.
+    fn try_debug_dump(&mut self, current_time: u64) {
+        if let Some(debug_dump) = self.debug_dump.take_if(|dump| current_time >= dump.time) {
+            let prev_layer = (self.dump_prev_layer)(&self.prev_layer);
+            debug_dump.write(&self.dump_state_with(prev_layer));
         }
-        shared_node_lists
     }
 
-    /// Check whether two entity (node) lists have any shared element.
-    fn has_shared_node(entity_ids1: &HashSet<usize>, entity_ids2: &HashSet<usize>) -> bool {
-        return entity_ids1.intersection(&entity_ids2).next() != None;
+    /// The state of this layer, with `prev_layer` as the state of the previous layer.
+    fn dump_state_with(&self, prev_layer: Value) -> Value {
+        let root_id = self.get_root_buffer_id();
+        let buffers = self
+            .sub_pattern_buffers
+            .iter()
+            .map(|buffer| {
+                let (sibling_id, parent_id) = if buffer.id == root_id {
+                    (None, None)
+                } else {
+                    (
+                        Some(self.get_sibling_id(buffer.id)),
+                        Some(self.get_parent_id(buffer.id)),
+                    )
+                };
+                json!({
+                    "id": buffer.id,
+                    "sibling_id": sibling_id,
+                    "parent_id": parent_id,
+                    "buffer": buffer.buffer.iter().map(|m| &m.0).collect_vec(),
+                    "new_match_buffer": buffer.new_match_buffer.iter().map(|m| &m.0).collect_vec(),
+                })
+            })
+            .collect_vec();
+
+        json!({
+            "prev_layer": prev_layer,
+            "sub_pattern_buffers": buffers,
+            "num_pending_full_matches": self.full_match.len(),
+        })
+    }
+
+    /// Record the new full matches containing the traced events.
+    fn trace_full_matches(&self) {
+        let Some(event_trace) = &self.event_trace else {
+            return;
+        };
+        for pattern_match in &self.full_match {
+            let ids = pattern_match.event_ids.iter().map(|(id, _)| *id);
+            let detail = || json!({ "event_ids": ids.clone().collect::<Vec<_>>() });
+            event_trace.record(ids.clone(), "join", "full match", detail);
+        }
     }
 
     /// Convert `SubPatternMatch to `PatternMatch`.
@@ -225,12 +465,27 @@ impl<'p, P> JoinLayer<'p, P> {
         ));
     }
 
+    /// Advance the window policy with the events of `sub_match`, see [`WindowPolicy::advance`].
+    /// This is the only layer advancing the policy.
+    fn advance_window(&self, sub_match: &SubPatternMatch) {
+        let mut times = (sub_match.match_event_map.iter().flatten())
+            .flat_map(|event| {
+                let (start, end) = event.raw_events.get_interval();
+                [start, end]
+            })
+            .collect_vec();
+        times.sort_unstable();
+        for time in times {
+            self.window.advance(time);
+        }
+    }
+
     /// Clear sub-pattern matches whose earliest event goes beyond the window.
     fn clear_expired(&mut self, latest_time: u64, buffer_id: usize) {
         debug!("windowing...");
         while let Some(sub_pattern_match) = self.sub_pattern_buffers[buffer_id].buffer.peek() {
             debug!("earliest_time: {}", sub_pattern_match.0.earliest_time);
-            if latest_time.saturating_sub(self.window_size) > sub_pattern_match.0.earliest_time {
+            if self.window.window_bound(latest_time) > sub_pattern_match.0.earliest_time {
                 debug!(
                     "clear expired! (sub_pattern time: {}, latest_time: {}; buffer id: {}))",
                     sub_pattern_match.0.earliest_time, latest_time, buffer_id
@@ -242,6 +497,22 @@ impl<'p, P> JoinLayer<'p, P> {
         }
     }
 
+    /// Drop the sub-pattern matches in all the buffers.
+    fn clear_buffers(&mut self) {
+        for buffer in &mut self.sub_pattern_buffers {
+            buffer.buffer.clear();
+            buffer.new_match_buffer.clear();
+        }
+    }
+
+    /// Clear the expired sub-pattern matches in all the buffers but the root, which is always
+    /// emptied once joined.
+    fn clear_all_expired(&mut self, latest_time: u64) {
+        for buffer_id in 0..self.get_root_buffer_id() {
+            self.clear_expired(latest_time, buffer_id);
+        }
+    }
+
     /// Join the new matches of the current buffer (`my_id`) with existing matches in its sibling buffer (`sibling_id`).
     fn join_with_sibling(&mut self, my_id: usize, sibling_id: usize) -> BinaryHeap<EarliestFirst> {
         debug!(
@@ -255,33 +526,68 @@ impl<'p, P> JoinLayer<'p, P> {
         debug!("my new_match_buffer size: {}", buffer1.len());
         debug!("sibling buffer size: {}", buffer2.len());
 
+        let mut num_skipped = 0;
         for sub_pattern_match1 in buffer1 {
+            let mut conflicts = self
+                .join_memo
+                .as_mut()
+                .filter(|_| !buffer2.is_empty())
+                .and_then(|memo| memo.conflicts(my_id, &sub_pattern_match1.0));
             for sub_pattern_match2 in buffer2 {
+                if let Some(conflicts) = &conflicts {
+                    if conflicts.contains(&sub_pattern_match2.0.serial) {
+                        num_skipped += 1;
+                        continue;
+                    }
+                }
                 debug!("***********************************");
 
-                if let Some(merged) = SubPatternMatch::merge_matches(
+                let merged = SubPatternMatch::try_merge_matches(
                     &self.sub_pattern_buffers[my_id],
                     &sub_pattern_match1.0,
                     &sub_pattern_match2.0,
-                ) {
-                    matches_to_parent.push(EarliestFirst(merged));
-                } else {
-                    debug!(
-                        "merge {} and {} failed",
-                        sub_pattern_match1.0.id, sub_pattern_match2.0.id
-                    );
+                );
+                trace_event!(
+                    TRACE,
+                    buffer_id = my_id,
+                    sibling_id,
+                    success = merged.is_ok(),
+                    "join attempt"
+                );
+                match merged {
+                    Ok(merged) => matches_to_parent.push(EarliestFirst(merged)),
+                    Err(conflict) => {
+                        debug!(
+                            "merge {} and {} failed",
+                            sub_pattern_match1.0.id, sub_pattern_match2.0.id
+                        );
+                        if let Some(conflicts) = &mut conflicts {
+                            if conflict == JoinConflict::Entities {
+                                conflicts.insert(sub_pattern_match2.0.serial);
+                            }
+                        }
+                    }
                 }
                 debug!("***********************************");
             }
         }
+        if let Some(memo) = &mut self.join_memo {
+            memo.add_skipped(num_skipped);
+        }
 
         matches_to_parent
     }
 
     /// Continuously join matches in buffers, in a button-up fashion.
     fn join(&mut self, current_time: u64, mut buffer_id: usize) {
+        if let Some(memo) = &mut self.join_memo {
+            memo.start_batch(current_time);
+        }
         loop {
             debug!("buffer id: {}", buffer_id);
+            if let Some(partial_alerts) = &mut self.partial_alerts {
+                partial_alerts.check(&self.sub_pattern_buffers[buffer_id]);
+            }
 
             // root reached
             if buffer_id == self.get_root_buffer_id() {
@@ -290,9 +596,19 @@ impl<'p, P> JoinLayer<'p, P> {
             }
 
             // Clear only the sibling buffer, since we can clear the current buffer when needed (deferred).
-            self.clear_expired(current_time, self.get_sibling_id(buffer_id));
-
-            let joined = self.join_with_sibling(buffer_id, self.get_sibling_id(buffer_id));
+            let sibling_id = self.get_sibling_id(buffer_id);
+            self.clear_expired(current_time, sibling_id);
+
+            let mut joined = self.join_with_sibling(buffer_id, sibling_id);
+            // an optional sibling may never match, so the new matches also go up without it
+            if self.sub_pattern_buffers[sibling_id].optional {
+                joined.extend(
+                    self.sub_pattern_buffers[buffer_id]
+                        .new_match_buffer
+                        .iter()
+                        .cloned(),
+                );
+            }
             let parent_id = self.get_parent_id(buffer_id);
 
             self.sub_pattern_buffers[parent_id]
@@ -362,29 +678,100 @@ fn get_buffer_id(sub_match_id: usize) -> usize {
     sub_match_id
 }
 
+impl<'p, P: DumpState> DumpState for JoinLayer<'p, P> {
+    fn dump_state(&self) -> Value {
+        self.dump_state_with(self.prev_layer.dump_state())
+    }
+}
+
+impl<'p, P: ReportBufferStats> ReportBufferStats for JoinLayer<'p, P> {
+    fn collect_buffer_stats(&self, stats: &mut Vec<BufferStats>) {
+        self.prev_layer.collect_buffer_stats(stats);
+        stats.extend(self.buffer_stats());
+    }
+
+    fn collect_live_instances(&self, counts: &mut Vec<u64>) {
+        self.prev_layer.collect_live_instances(counts);
+    }
+
+    fn collect_state_stats(&self, stats: &mut Vec<BufferStats>) {
+        self.prev_layer.collect_state_stats(stats);
+    }
+}
+
 impl<'p, P> Iterator for JoinLayer<'p, P>
 where
-    P: Iterator<Item = (u32, composition_layer::MatchInstance)>,
+    P: Iterator<Item = (u32, composition_layer::MatchInstance)> + ReportBufferStats,
 {
     type Item = PatternMatch;
 
     fn next(&mut self) -> Option<Self::Item> {
         let num_pat_event = self.pattern.events.len();
+        if let Some(watermark) = &mut self.watermark {
+            watermark.resume();
+        }
         while self.full_match.is_empty() {
-            let (sub_pattern_id, match_instance) = self.prev_layer.next()?;
+            let next_instance = self.prev_layer.next();
+            // the previous layers may have read tombstone records
+            self.purge_retracted();
+            if self.deactivation_signal.as_ref().is_some_and(DeactivationSignal::take) {
+                self.clear_buffers();
+            }
+            let Some((sub_pattern_id, match_instance)) = next_instance else {
+                self.try_debug_dump(u64::MAX);
+                return None;
+            };
 
             if let Some(sub_match) =
                 SubPatternMatch::build(sub_pattern_id, match_instance, num_pat_event)
             {
+                // the session of a session window may have ended since the sub-pattern match
+                // started, which the previous layers may not have seen
+                self.advance_window(&sub_match);
+                if sub_match.earliest_time < self.window.window_bound(sub_match.latest_time) {
+                    continue;
+                }
+                if let Some(SubMatchCallback(callback)) = &mut self.sub_match_callback {
+                    callback(&sub_match);
+                }
+                if let Some(event_trace) = &self.event_trace {
+                    let ids = sub_match.event_ids.iter().map(|(id, _)| *id);
+                    event_trace.record(ids, "join", "join input", || {
+                        json!({
+                            "sub_pattern_id": sub_match.id,
+                            "earliest_time": sub_match.earliest_time,
+                            "latest_time": sub_match.latest_time,
+                        })
+                    });
+                }
                 // Note that `sub_match_id` should be identical as `sub_pattern_id`
                 let buffer_id = get_buffer_id(sub_match.id);
                 let current_time = sub_match.latest_time;
+                let _span = enter_span!("join", sub_pattern_id, time = current_time);
                 // put the sub-pattern match to its corresponding buffer
                 self.sub_pattern_buffers[buffer_id]
                     .new_match_buffer
                     .push(EarliestFirst(sub_match));
 
                 self.join(current_time, buffer_id);
+                self.trace_full_matches();
+                self.try_debug_dump(current_time);
+                self.try_report_stats(current_time);
+
+                let Some(watermark) = &mut self.watermark else {
+                    continue;
+                };
+                if !watermark.advance(current_time) {
+                    continue;
+                }
+                let pause = self.full_match.is_empty();
+                if pause {
+                    watermark.publish(current_time);
+                }
+                self.clear_all_expired(current_time);
+                if pause {
+                    return None;
+                }
             }
         }
 
@@ -392,55 +779,6 @@ where
     }
 }
 
-/// Union-find tree, a.k.a disjoint set
-struct UnionFind {
-    /// Store the root (representative element) of a union-find tree (disjoint set).
-    /// For element `id`, if `roots[id] < 0`, them `id` is the root of the tree it belongs to.
-    /// Otherwise, `roots[id]` is the parent of `id` (but not necessary the root).
-    ///
-    /// A disjoint set corresponds to a sub-pattern buffer in the Join layer.
-    /// Note that for `roots[id] < 0`, the value `abs(roots[id])` corresponds to the
-    /// *height of the buffer* in the sub-pattern buffer tree structure.
-    roots: Vec<i64>,
-}
-
-impl UnionFind {
-    fn new(size: usize) -> Self {
-        Self {
-            roots: vec![-1; size], // height
-        }
-    }
-
-    /// Return the root element for `id`.
-    fn get_root(&mut self, id: usize) -> usize {
-        if self.roots[id] < 0 {
-            id
-        } else {
-            self.roots[id] = self.get_root(self.roots[id] as usize) as i64;
-            self.roots[id] as usize
-        }
-    }
-
-    /// Return the buffer height that `id` belongs to.
-    fn get_height(&self, id: usize) -> u32 {
-        -self.roots[id] as u32
-    }
-
-    /// Merge two union-find trees, and create a node (corresponds to a new buffer) as the new root.
-    fn merge(&mut self, id1: usize, id2: usize, new_root: usize) {
-        let root1 = self.get_root(id1);
-        let root2 = self.get_root(id2);
-        if root1 == root2 {
-            return;
-        }
-
-        // A new node (corresponds to a new buffer)
-        self.roots[new_root] = min(self.roots[root1], self.roots[root2]) - 1;
-        self.roots[root1] = new_root as i64;
-        self.roots[root2] = new_root as i64;
-    }
-}
-
 #[cfg(test)]
 pub mod tests {
     use std::rc::Rc;
@@ -499,6 +837,35 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_invalid_join_plan_ignored() {
+        let path = "data/universal_patterns/SP8_regex.json";
+        let pattern = Pattern::parse(path).expect("Failed to parse pattern");
+        let default_plan = join_plan::build(&decompose(&pattern));
+
+        // a plan of the wrong length, and a plan joining sub-patterns without shared nodes
+        let invalid_plans = [
+            JoinPlan {
+                num_sub_patterns: 3,
+                merges: vec![(0, 1), (3, 2)],
+            },
+            JoinPlan {
+                num_sub_patterns: 4,
+                merges: vec![(0, 3), (1, 2), (4, 5)],
+            },
+        ];
+        for plan in invalid_plans {
+            let mut pattern = Pattern::parse(path).expect("Failed to parse pattern");
+            pattern.join_plan = Some(plan);
+            let sub_patterns = decompose(&pattern);
+            let join_layer = JoinLayer::new((), &pattern, &sub_patterns, 1000);
+            for (k, &(i, j)) in default_plan.merges.iter().enumerate() {
+                assert_eq!(join_layer.get_sibling_id(i), j);
+                assert_eq!(join_layer.get_parent_id(i), sub_patterns.len() + k);
+            }
+        }
+    }
+
     /*
        Note:
            The codes here are duplicate to those in benches/join_layer_benchmark.rs,
@@ -550,9 +917,11 @@ pub mod tests {
         MatchInstance {
             start_time: set_time,
             match_events: match_events.into_boxed_slice(),
-            match_entities: match_entities.into_boxed_slice(),
-            event_ids: event_ids.into_boxed_slice(),
+            match_entities: match_entities.into(),
+            event_ids: event_ids.into(),
             state_id: 0,
+            entity_attributes: vec![],
+            wildcard_entities: vec![],
         }
     }
 
@@ -581,7 +950,7 @@ pub mod tests {
         let json_obj: Value = serde_json::from_str(raw_pattern).expect("error reading json");
         let pattern = parse_json(&json_obj).expect("Failed to parse pattern");
 
-        let windows_size = 1 * 1000;
+        let windows_size = 1000;
         let sub_patterns = decompose(&pattern);
 
         debug!("sub_patterns: {:#?}", sub_patterns);
@@ -658,4 +1027,113 @@ pub mod tests {
 
         join_layer.run_isolated_join_layer(&mut match_instances);
     }
+
+    #[test]
+    fn test_join_memo() {
+        // a -> b -> c, where b is shared by the two sub-patterns
+        let pattern = Pattern::from_graph(&["a", "b", "c"], &[(0, 1, "e0"), (1, 2, "e1")], false);
+        let sub_patterns = decompose(&pattern);
+        assert_eq!(sub_patterns.len(), 2);
+        let single_event = |pattern_event: usize, time, event_id, subject, object| {
+            let sub_pattern = sub_patterns
+                .iter()
+                .find(|sub_pattern| sub_pattern.events.iter().any(|e| e.id == pattern_event))
+                .unwrap();
+            let event = &pattern.events[pattern_event];
+            let input_event = InputEvent::new(time, event_id, "", subject, "", object, "");
+            let match_event = MatchEvent {
+                match_id: event.id as u32,
+                input_subject_id: subject,
+                input_object_id: object,
+                pattern_subject_id: event.subject.id as u64,
+                pattern_object_id: event.object.id as u64,
+                raw_events: RawEvents::Single(Rc::new(input_event)),
+            };
+            let match_entities = [
+                (subject, event.subject.id as u64),
+                (object, event.object.id as u64),
+            ];
+            let instance = MatchInstance {
+                start_time: time,
+                match_events: Box::new([match_event]),
+                match_entities: match_entities.into_iter().sorted().collect(),
+                event_ids: smallvec::smallvec![event_id],
+                state_id: 0,
+                entity_attributes: vec![],
+                wildcard_entities: vec![],
+            };
+            (sub_pattern.id as u32, instance)
+        };
+
+        // 10 matches of a -> b bound to b = 200, and then a burst of 20 matches of b -> c in one
+        // batch, all but the last bound to b = 201
+        let mut match_instances = vec![];
+        for i in 0..10 {
+            match_instances.push(single_event(0, 10, i, 100 + i, 200));
+        }
+        for i in 0..20 {
+            let b = if i == 19 { 200 } else { 201 };
+            match_instances.push(single_event(1, 20, 10 + i, b, 300));
+        }
+
+        let mut num_matches = vec![];
+        for join_memo in [false, true] {
+            let mut join_layer = JoinLayer::new((), &pattern, &sub_patterns, 1000);
+            join_layer.set_join_memo(join_memo);
+            join_layer.run_isolated_join_layer(&mut match_instances.clone());
+            num_matches.push(join_layer.full_match.len());
+            if join_memo {
+                // only the first match bound to b = 201 checks the siblings
+                assert_eq!(join_layer.num_memoized_joins(), 18 * 10);
+            }
+        }
+        assert_eq!(num_matches, [10, 10]);
+    }
+
+    #[test]
+    fn test_watermark_interval() {
+        use crate::process_layers::{CompositionLayer, ParseLayer, UniquenessLayer};
+
+        // the paper data graph, followed by a copy of it 100 seconds later
+        let data = std::fs::read_to_string("testcases/paper/data.csv").unwrap();
+        let shifted = data.lines().map(|line| {
+            let fields: Vec<&str> = line.split(',').collect();
+            let shift = |field: &str| field.parse::<u64>().unwrap() + 100;
+            let (start, end, id) = (shift(fields[0]), shift(fields[1]), shift(fields[2]));
+            format!("{start},{end},{id},{}\n", fields[3..].join(","))
+        });
+        let data = data.clone() + &shifted.collect::<String>();
+
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let decomposition = decompose(&pattern);
+        let window_size = 10 * 1000;
+        let idle_state = IdleState::default();
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(data.as_bytes());
+        let composition_layer = CompositionLayer::new(
+            ParseLayer::new(csv_reader),
+            &decomposition,
+            window_size,
+            pattern.use_regex,
+        )
+        .unwrap();
+        let mut join_layer =
+            JoinLayer::new(composition_layer, &pattern, &decomposition, window_size);
+        join_layer.set_watermark_interval(1000, idle_state.clone());
+        let mut uniqueness_layer = UniquenessLayer::new(join_layer, window_size);
+        uniqueness_layer.set_idle_state(idle_state.clone());
+
+        // the first match is flushed by a watermark before the second one completes
+        let mut emitted_while_paused = vec![];
+        loop {
+            for _ in uniqueness_layer.by_ref() {
+                emitted_while_paused.push(idle_state.is_idle());
+            }
+            if !idle_state.is_idle() {
+                break;
+            }
+        }
+        assert_eq!(emitted_while_paused, [true, false]);
+    }
 }
@@ -0,0 +1,165 @@
+use super::SubPatternBuffer;
+use crate::pattern_match::PatternMatch;
+use std::fmt;
+
+/// Which partial matches are alerted, see
+/// [`JoinLayer::set_partial_alerts`](super::JoinLayer::set_partial_alerts).
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartialAlertTrigger {
+    /// Partial matches of at least this fraction of the pattern events.
+    Fraction(f64),
+    /// Partial matches containing all these (reassigned) pattern event ids.
+    Events(Vec<usize>),
+}
+
+impl PartialAlertTrigger {
+    /// Returns `true` if the partial matches of the pattern events `event_ids` are alerted.
+    fn is_met(&self, event_ids: impl Iterator<Item = usize> + Clone, num_events: usize) -> bool {
+        match self {
+            Self::Fraction(fraction) => event_ids.count() as f64 >= fraction * num_events as f64,
+            Self::Events(required) => required
+                .iter()
+                .all(|id| event_ids.clone().any(|event_id| event_id == *id)),
+        }
+    }
+}
+
+/// A match of a part of the pattern, an early warning of a pattern match that may never complete
+/// within the window.
+#[derive(Debug, Clone)]
+pub struct PartialAlert {
+    /// The unmatched pattern events are `None` in `match_event_map`.
+    pub pattern_match: PatternMatch,
+    /// The fraction of the pattern events matched, in `(0, 1)`. Lower than that of a full match.
+    pub confidence: f64,
+}
+
+type AlertCallback = Box<dyn FnMut(&PartialAlert)>;
+
+/// Calls a callback with the partial matches in the join buffers meeting a trigger.
+pub struct PartialAlerts {
+    trigger: PartialAlertTrigger,
+    callback: AlertCallback,
+    /// Whether the new matches of each join buffer are alerted, set by `JoinLayer`.
+    alerted_buffers: Vec<bool>,
+}
+
+impl PartialAlerts {
+    pub fn new(
+        trigger: PartialAlertTrigger,
+        callback: impl FnMut(&PartialAlert) + 'static,
+    ) -> Self {
+        Self {
+            trigger,
+            callback: Box::new(callback),
+            alerted_buffers: vec![],
+        }
+    }
+
+    /// Alert the new matches of the buffers whose pattern events first meet the trigger, i.e.
+    /// none of their child buffers does, so that a partial match is not alerted again as it is
+    /// joined into larger ones. The root buffer, holding the full matches, is never alerted.
+    pub(super) fn set_buffers(&mut self, buffers: &[SubPatternBuffer], parent_id_map: &[usize]) {
+        let num_events = buffers.first().map_or(0, |buffer| buffer.max_num_events);
+        let is_met: Vec<bool> = buffers
+            .iter()
+            .map(|buffer| {
+                let event_ids = buffer.edge_id_list.iter().copied();
+                self.trigger.is_met(event_ids, num_events)
+            })
+            .collect();
+
+        self.alerted_buffers = is_met.clone();
+        let root_id = buffers.len() - 1;
+        for (buffer_id, met) in is_met.into_iter().enumerate().take(root_id) {
+            if met {
+                self.alerted_buffers[parent_id_map[buffer_id]] = false;
+            }
+        }
+        self.alerted_buffers[root_id] = false;
+    }
+
+    /// Alert the new matches of `buffer` if required.
+    pub(super) fn check(&mut self, buffer: &SubPatternBuffer) {
+        if self.alerted_buffers.get(buffer.id) != Some(&true) {
+            return;
+        }
+        let confidence = buffer.edge_id_list.len() as f64 / buffer.max_num_events as f64;
+        for sub_pattern_match in &buffer.new_match_buffer {
+            let alert = PartialAlert {
+                pattern_match: sub_pattern_match.0.clone().into(),
+                confidence,
+            };
+            (self.callback)(&alert);
+        }
+    }
+}
+
+impl fmt::Debug for PartialAlerts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartialAlerts")
+            .field("trigger", &self.trigger)
+            .field("alerted_buffers", &self.alerted_buffers)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{decompose, Pattern};
+    use crate::process_layers::{CompositionLayer, JoinLayer, ParseLayer};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_trigger() {
+        let event_ids = [0, 2, 3];
+        let fraction = PartialAlertTrigger::Fraction;
+        assert!(fraction(0.5).is_met(event_ids.into_iter(), 6));
+        assert!(!fraction(0.6).is_met(event_ids.into_iter(), 6));
+
+        let events = |ids: &[usize]| PartialAlertTrigger::Events(ids.to_vec());
+        assert!(events(&[3, 0]).is_met(event_ids.into_iter(), 6));
+        assert!(!events(&[1, 3]).is_met(event_ids.into_iter(), 6));
+    }
+
+    #[test]
+    fn test_partial_alerts() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let decomposition = decompose(&pattern);
+        let window_size = 1000 * 1000;
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path("testcases/paper/data.csv")
+            .unwrap();
+        let composition_layer = CompositionLayer::new(
+            ParseLayer::new(csv_reader),
+            &decomposition,
+            window_size,
+            pattern.use_regex,
+        )
+        .unwrap();
+        let mut join_layer =
+            JoinLayer::new(composition_layer, &pattern, &decomposition, window_size);
+        let alerts = Rc::new(RefCell::new(vec![]));
+        join_layer.set_partial_alerts(PartialAlerts::new(PartialAlertTrigger::Fraction(0.5), {
+            let alerts = alerts.clone();
+            move |alert: &PartialAlert| alerts.borrow_mut().push(alert.clone())
+        }));
+
+        assert_eq!(join_layer.count(), 1);
+        let alerts = alerts.borrow();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].confidence, 0.5);
+        assert_eq!(
+            alerts[0]
+                .pattern_match
+                .match_event_map
+                .iter()
+                .flatten()
+                .count(),
+            2
+        );
+    }
+}
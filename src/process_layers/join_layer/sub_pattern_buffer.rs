@@ -1,9 +1,12 @@
+use super::entity_set::EntitySet;
 use super::sub_pattern_match::EarliestFirst;
+use crate::input_event::entity_host;
 use crate::match_event::MatchEvent;
 use crate::pattern::Pattern;
 use crate::pattern::SubPattern;
-use crate::universal_match_event::UniversalMatchEvent;
+use crate::pattern::TimeGap;
 use log::debug;
+use std::cell::RefCell;
 use std::collections::{BinaryHeap, HashSet};
 use std::rc::Rc;
 
@@ -21,10 +24,21 @@ pub struct Relation {
     /// (The "overall structure" has guaranteed nodes be shared properly, when performing "SubPatternMatch::try_merge_nodes()".)
     shared_entities: Vec<bool>,
 
-    /// `event_orders: (pattern_id1, pattern_id2)`
+    /// `event_orders: (pattern_id1, pattern_id2, gap)`
     ///
     /// `pattern_id1` (`pattern_id2`, respectively) is the id of some left  (right, respectively) buffer on `JoinLayer::sub_pattern_buffers`, where the two buffers are siblings.
-    event_orders: Vec<(usize, usize)>,
+    /// `gap` is the allowed time gap between them.
+    event_orders: Vec<(usize, usize, TimeGap)>,
+
+    /// The pairs of pattern events across the two buffers that must be matched by input events
+    /// with the same timestamp, see `PatternEvent::simultaneous_events`.
+    simultaneous_events: Vec<(usize, usize)>,
+
+    /// If `true`, the two matches must be on the same host. See `JoinLayer::set_multi_host()`.
+    pub single_host: bool,
+
+    /// If `true`, the two matches must not share an input event. See `Pattern::event_uniqueness`.
+    pub event_uniqueness: bool,
 }
 
 impl Relation {
@@ -32,33 +46,60 @@ impl Relation {
         Self {
             shared_entities: Vec::new(),
             event_orders: Vec::new(),
+            simultaneous_events: Vec::new(),
+            single_host: false,
+            event_uniqueness: true,
         }
     }
 
-    /// Check whether order relations are violated between two pattern matches.
+    /// Check whether two pattern matches, given by their `match_entities`, are on the same host
+    /// if required.
+    pub fn check_host(&self, entities1: &[(u64, u64)], entities2: &[(u64, u64)]) -> bool {
+        if !self.single_host {
+            return true;
+        }
+        match (entities1.first(), entities2.first()) {
+            (Some((id1, _)), Some((id2, _))) => entity_host(*id1) == entity_host(*id2),
+            _ => true,
+        }
+    }
+
+    /// Check whether order relations are violated between two pattern matches. The optional
+    /// events missing from the matches impose no order.
     pub fn check_order_relation(&self, match_event_map: &[Option<Rc<MatchEvent>>]) -> bool {
-        for (idx1, idx2) in &self.event_orders {
+        for (idx1, idx2, gap) in &self.event_orders {
             if let (Some(event1), Some(event2)) = (&match_event_map[*idx1], &match_event_map[*idx2])
             {
-                if !Self::satisfy_order(event1, event2) {
+                if !Self::satisfy_order(event1, event2, gap) {
                     return false;
                 }
             } else {
-                debug!("failed to get events specified in the order relation");
-                return false;
+                debug!("skip the order relation of a missing optional event");
             }
         }
 
         true
     }
 
-    fn satisfy_order(
+    /// Check whether the simultaneous events across two pattern matches have the same timestamp.
+    /// The optional events missing from the matches are ignored.
+    pub fn check_simultaneous(&self, match_event_map: &[Option<Rc<MatchEvent>>]) -> bool {
+        self.simultaneous_events.iter().all(|(idx1, idx2)| {
+            match (&match_event_map[*idx1], &match_event_map[*idx2]) {
+                (Some(event1), Some(event2)) => {
+                    event1.raw_events.get_interval() == event2.raw_events.get_interval()
+                }
+                _ => true,
+            }
+        })
+    }
 
-        event1: &MatchEvent,
-        event2: &MatchEvent,
-    ) -> bool {
-        // event1.end_time <= event2.start_time
-        event1.raw_events.get_interval().1 <= event2.raw_events.get_interval().0
+    fn satisfy_order(event1: &MatchEvent, event2: &MatchEvent, gap: &TimeGap) -> bool {
+        // event1.end_time <= event2.start_time, within the gap
+        gap.allows(
+            event1.raw_events.get_interval().1,
+            event2.raw_events.get_interval().0,
+        )
     }
 
     pub fn is_entity_shared(&self, id: usize) -> bool {
@@ -82,7 +123,7 @@ pub struct SubPatternBuffer {
     /// Ids of pattern entities (nodes) contained in this sub-pattern.
     pub (super) node_id_list: HashSet<usize>,
     /// Ids of pattern events (edges) contained in this sub-pattern.
-    edge_id_list: HashSet<usize>,
+    pub(super) edge_id_list: HashSet<usize>,
     /// A buffer that holds sub-pattern matches.
     pub(crate) buffer: BinaryHeap<EarliestFirst>,
     // pub(crate) buffer: BinaryHeap<EarliestFirst<'p>>,
@@ -93,11 +134,17 @@ pub struct SubPatternBuffer {
     pub relation: Relation,
     /// Number of entities in the overall pattern.
     pub max_num_entities: usize,
+    /// The pattern entities seen by the entity uniqueness check of a join, reused by all joins
+    /// of this buffer.
+    pub(crate) used_entities: RefCell<EntitySet>,
     /// Number of events in the overall pattern.
     pub max_num_events: usize,
+    /// Whether this buffer holds the matches of an optional sub-pattern, see
+    /// `SubPattern::is_optional()`.
+    pub optional: bool,
 }
 
-impl<'p> SubPatternBuffer {
+impl SubPatternBuffer {
     pub fn new(
         id: usize,
         sub_pattern: &SubPattern,
@@ -119,7 +166,9 @@ impl<'p> SubPatternBuffer {
             new_match_buffer: BinaryHeap::new(),
             relation: Relation::new(),
             max_num_entities,
+            used_entities: RefCell::new(EntitySet::with_capacity(max_num_entities)),
             max_num_events,
+            optional: sub_pattern.is_optional(),
         }
     }
 
@@ -131,32 +180,42 @@ impl<'p> SubPatternBuffer {
     ) -> Relation {
         let mut shared_entities = vec![false; pattern.entities.len()];
         let mut event_orders = Vec::new();
+        let mut simultaneous_events = Vec::new();
 
         // identify shared nodes
-        for i in 0..pattern.entities.len() {
+        for (i, shared) in shared_entities.iter_mut().enumerate() {
             if sub_pattern_buffer1.node_id_list.contains(&i)
                 && sub_pattern_buffer2.node_id_list.contains(&i)
             {
-                shared_entities[i] = true;
+                *shared = true;
             }
         }
 
-        // generate order-relation (new)
-        // If the dependency of (src, tgt) exists, add the dependency into the list of order relations.
-        // Note that ``src'' always precedes ``tgt''.
-        for (src, tgt) in pattern.order.get_dependencies() {
-            if (sub_pattern_buffer1.edge_id_list.contains(&src)
-                && sub_pattern_buffer2.edge_id_list.contains(&tgt))
-                || (sub_pattern_buffer2.edge_id_list.contains(&src)
-                    && sub_pattern_buffer1.edge_id_list.contains(&tgt))
-            {
-                event_orders.push((src, tgt));
+        // generate order-relation
+        // Every pair (src, tgt) across the two buffers such that ``src'' precedes ``tgt'', either
+        // directly or transitively, is checked, along with the gap given by `Parents` or a `Gap`
+        // constraint. The transitive pairs matter when the events in between are in neither
+        // buffer, e.g. they are optional and missing, or are joined later.
+        for &src in &sub_pattern_buffer1.edge_id_list {
+            for &tgt in &sub_pattern_buffer2.edge_id_list {
+                if pattern.events[src].simultaneous_events.contains(&tgt) {
+                    simultaneous_events.push((src, tgt));
+                }
+                if pattern.order.must_precede(src, tgt) {
+                    event_orders.push((src, tgt, pattern.events[tgt].gap_from(src)));
+                } else if pattern.order.must_precede(tgt, src) {
+                    event_orders.push((tgt, src, pattern.events[src].gap_from(tgt)));
+                }
             }
         }
+        event_orders.sort_unstable_by_key(|(src, tgt, _)| (*src, *tgt));
 
         Relation {
             shared_entities,
             event_orders,
+            simultaneous_events,
+            single_host: false,
+            event_uniqueness: pattern.event_uniqueness,
         }
     }
 
@@ -179,7 +238,106 @@ impl<'p> SubPatternBuffer {
             new_match_buffer: BinaryHeap::new(),
             relation: Relation::new(),
             max_num_entities: sub_pattern_buffer1.max_num_entities,
+            used_entities: RefCell::new(EntitySet::with_capacity(
+                sub_pattern_buffer1.max_num_entities,
+            )),
             max_num_events: sub_pattern_buffer1.max_num_events,
+            optional: sub_pattern_buffer1.optional && sub_pattern_buffer2.optional,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_event::InputEvent;
+    use crate::match_event::RawEvents;
+    use crate::pattern::decompose;
+    use crate::pattern::parser::parse_json;
+    use serde_json::json;
+
+    #[test]
+    fn test_transitive_order_relation() {
+        // event 0 precedes event 2 through event 1, and ends at most 1 sec before it starts
+        let pattern = parse_json(&json!({
+            "Version": "0.2.0",
+            "Entities": (0..6).map(|id| json!({ "ID": id, "Signature": "" })).collect::<Vec<_>>(),
+            "Events": [
+                { "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1 },
+                { "ID": 1, "Signature": "b", "SubjectID": 2, "ObjectID": 3, "Parents": [0] },
+                { "ID": 2, "Signature": "c", "SubjectID": 4, "ObjectID": 5, "Parents": [1] }
+            ],
+            "Constraints": [{ "Type": "Gap", "Events": [0, 2], "MaxGapMs": 1000 }]
+        }))
+        .unwrap();
+        let sub_patterns = decompose(&pattern);
+        let buffer = |event_id| {
+            let sub_pattern = sub_patterns
+                .iter()
+                .find(|sub_pattern| sub_pattern.events[0].id == event_id)
+                .unwrap();
+            SubPatternBuffer::new(sub_pattern.id, sub_pattern, 6, 3)
+        };
+        // the buffers of events 2 and 0 are joined before that of event 1
+        let relation = SubPatternBuffer::generate_relations(&pattern, &buffer(2), &buffer(0));
+
+        let match_event = |match_id, time| {
+            Some(Rc::new(MatchEvent {
+                match_id,
+                input_subject_id: 0,
+                input_object_id: 1,
+                pattern_subject_id: 0,
+                pattern_object_id: 1,
+                raw_events: RawEvents::Single(Rc::new(InputEvent::new(
+                    time, time, "", 0, "", 1, "",
+                ))),
+            }))
+        };
+        let check = |time0, time2| {
+            relation.check_order_relation(&[match_event(0, time0), None, match_event(2, time2)])
+        };
+        assert!(check(1000, 1500));
+        assert!(!check(1000, 500));
+        assert!(!check(1000, 3000));
+    }
+
+    #[test]
+    fn test_simultaneous_relation() {
+        // events 0 and 1 share no entity, so they are in different sub-patterns
+        let pattern = parse_json(&json!({
+            "Version": "0.2.0",
+            "Entities": (0..4).map(|id| json!({ "ID": id, "Signature": "" })).collect::<Vec<_>>(),
+            "Events": [
+                { "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1 },
+                { "ID": 1, "Signature": "b", "SubjectID": 2, "ObjectID": 3 }
+            ],
+            "Constraints": [{ "Type": "Simultaneous", "Events": [0, 1] }]
+        }))
+        .unwrap();
+        let sub_patterns = decompose(&pattern);
+        assert_eq!(sub_patterns.len(), 2);
+        let buffers = sub_patterns
+            .iter()
+            .map(|sub_pattern| SubPatternBuffer::new(sub_pattern.id, sub_pattern, 4, 2))
+            .collect::<Vec<_>>();
+        let relation = SubPatternBuffer::generate_relations(&pattern, &buffers[0], &buffers[1]);
+
+        let match_event = |match_id, time| {
+            Some(Rc::new(MatchEvent {
+                match_id,
+                input_subject_id: 0,
+                input_object_id: 1,
+                pattern_subject_id: 0,
+                pattern_object_id: 1,
+                raw_events: RawEvents::Single(Rc::new(InputEvent::new(
+                    time, time, "", 0, "", 1, "",
+                ))),
+            }))
+        };
+        let check = |time0, time1| {
+            relation.check_simultaneous(&[match_event(0, time0), match_event(1, time1)])
+        };
+        assert!(check(1000, 1000));
+        assert!(!check(1000, 1001));
+        assert!(relation.check_simultaneous(&[match_event(0, 1000), None]));
+    }
+}
@@ -1,19 +1,24 @@
 use crate::match_event::MatchEvent;
-use crate::pattern::PatternEvent;
 use crate::pattern_match::PatternMatch;
 use crate::process_layers::composition_layer;
 use crate::process_layers::composition_layer::match_instance::{
-    InputEntityId, InputEventId, PatternEntityId, PatternEventId,
+    format_entity_bindings, EntityAttributes, InputEntityId, InputEventId, PatternEntityId,
+    PatternEventId, WildcardEntities,
 };
+use crate::process_layers::join_layer::entity_set::EntitySet;
 use crate::process_layers::join_layer::SubPatternBuffer;
+use itertools::Itertools;
 use log::debug;
+use serde::Serialize;
 use std::cmp::Ordering;
 use std::cmp::{max, min};
-use std::fmt::Debug;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Display};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 
 /// Matches of sub-patterns.
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 // pub struct SubPatternMatch<'p> {
 pub struct SubPatternMatch {
     /// The timestamp of the last event (in `match_events`), which is also the latest timestamp; indicating "current time".
@@ -38,48 +43,83 @@ pub struct SubPatternMatch {
     ///
     /// `match_entities.len()` == number of entities in this sub-pattern match.
     pub match_entities: Box<[(InputEntityId, PatternEntityId)]>,
+
+    /// The attribute values bound to the pattern entities, see `MatchInstance::entity_attributes`.
+    pub entity_attributes: EntityAttributes,
+
+    /// The input entities bound to the wildcard pattern entities, see
+    /// `MatchInstance::wildcard_entities`.
+    pub wildcard_entities: WildcardEntities,
+
+    /// Unique among the sub-pattern matches built or merged in this process, and shared by the
+    /// clones of a match. See `JoinMemo`.
+    pub serial: u64,
 }
 
-impl Into<PatternMatch> for SubPatternMatch {
-    fn into(self) -> PatternMatch {
+/// The serial of the next sub-pattern match, see `SubPatternMatch::serial`.
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(0);
+
+fn next_serial() -> u64 {
+    NEXT_SERIAL.fetch_add(1, AtomicOrdering::Relaxed)
+}
+
+/// Why two sub-pattern matches cannot be joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinConflict {
+    /// The entity bindings conflict, e.g. an input entity binds two pattern entities, or the
+    /// matches are on different hosts. This only depends on the entity bindings of the two
+    /// matches, so it holds for any other match with the same bindings.
+    Entities,
+    /// The input events conflict, e.g. they violate the order relations.
+    Events,
+}
+
+impl From<SubPatternMatch> for PatternMatch {
+    fn from(value: SubPatternMatch) -> Self {
         PatternMatch {
-            latest_time: self.latest_time,
-            earliest_time: self.earliest_time,
-            event_ids: self.event_ids,
-            match_event_map: self.match_event_map
+            latest_time: value.latest_time,
+            earliest_time: value.earliest_time,
+            event_ids: value.event_ids,
+            match_event_map: value.match_event_map,
+            annotations: serde_json::Map::new(),
+            sequence: None,
         }
     }
 }
 
-pub struct DebugMatchEventMap<'t>(pub &'t [Option<MatchEvent>]);
-impl<'p, 't> Debug for DebugMatchEventMap<'t> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // f.debug_list()
-        //     .entries(
-        //         self.0
-        //             .iter()
-        //             .map(|opt| opt.as_ref().map(|val| &val.raw_events.get_ids())),
-        //     )
-        //     .finish()
-        !todo!("Fix debug display");
-        std::fmt::Result::Ok(())
+impl Debug for SubPatternMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the input event ids of each matched pattern event, rather than the whole input events
+        let match_event_map: BTreeMap<_, _> = self
+            .match_event_map
+            .iter()
+            .flatten()
+            .map(|event| (event.match_id, event.raw_events.get_ids().collect_vec()))
+            .collect();
+        f.debug_struct("SubPatternMatch")
+            .field("id", &self.id)
+            .field("earliest_time", &self.earliest_time)
+            .field("latest_time", &self.latest_time)
+            .field("event_ids", &self.event_ids)
+            .field("match_event_map", &match_event_map)
+            .field("match_entities", &self.match_entities)
+            .field("wildcard_entities", &self.wildcard_entities)
+            .finish()
     }
 }
 
-impl<'p> Debug for SubPatternMatch {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // f.debug_struct("SubPatternMatch")
-        //     .field("id", &self.id)
-        //     .field("match_entities", &self.match_entities)
-        //     .field("event_ids", &self.event_ids)
-        //     .field(
-        //         "match_event_map",
-        //         &DebugMatchEventMap(&self.match_event_map),
-        //     )
-        //     .finish()
-
-        !todo!("Fix debug display");
-        std::fmt::Result::Ok(())
+/// Writes the time range, the input events of the matched pattern events and the bindings of
+/// the pattern entities, e.g. `<1.000, 2.500>[3, 4] {0: 17, 1: 20, 2: 21}`.
+impl Display for SubPatternMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start_t = self.earliest_time as f32 / 1000.0;
+        let end_t = self.latest_time as f32 / 1000.0;
+        let event_str = self.match_event_map.iter().flatten().join(", ");
+        let entity_str = format_entity_bindings(&self.match_entities, &self.wildcard_entities);
+        write!(
+            f,
+            "<{start_t:.3}, {end_t:.3}>[{event_str}] {{{entity_str}}}"
+        )
     }
 }
 
@@ -105,6 +145,7 @@ where
     merged.into_boxed_slice()
 }
 
+/// Returns `false` if an input event is in both lists, i.e. it matches two pattern events.
 fn try_merge_event_ids(id_list1: &[(u64, u32)], id_list2: &[(u64, u32)]) -> bool {
     let mut p1 = id_list1.iter();
     let mut p2 = id_list2.iter();
@@ -112,7 +153,7 @@ fn try_merge_event_ids(id_list1: &[(u64, u32)], id_list2: &[(u64, u32)]) -> bool
     let mut next2 = p2.next();
 
     while let (Some(id1), Some(id2)) = (next1, next2) {
-        match id1.cmp(id2) {
+        match id1.0.cmp(&id2.0) {
             Ordering::Less => {
                 next1 = p1.next();
             }
@@ -128,8 +169,12 @@ fn try_merge_event_ids(id_list1: &[(u64, u32)], id_list2: &[(u64, u32)]) -> bool
     true
 }
 
-fn merge_event_ids(id_list1: &[(u64, u32)], id_list2: &[(u64, u32)]) -> Option<Box<[(u64, u32)]>> {
-    if !try_merge_event_ids(id_list1, id_list2) {
+fn merge_event_ids(
+    id_list1: &[(u64, u32)],
+    id_list2: &[(u64, u32)],
+    event_uniqueness: bool,
+) -> Option<Box<[(u64, u32)]>> {
+    if event_uniqueness && !try_merge_event_ids(id_list1, id_list2) {
         return None;
     }
 
@@ -169,8 +214,14 @@ fn merge_event_ids(id_list1: &[(u64, u32)], id_list2: &[(u64, u32)]) -> Option<B
     Some(merged.into_boxed_slice())
 }
 
-pub fn try_merge_entities(a: &[(u64, u64)], b: &[(u64, u64)], max_num_entities: usize) -> bool {
-    let mut used_entities = vec![false; max_num_entities];
+/// Returns `false` if an input entity matches two pattern entities, or a pattern entity is
+/// matched by two input entities. `used_entities` is cleared before use.
+pub fn try_merge_entities(
+    a: &[(u64, u64)],
+    b: &[(u64, u64)],
+    used_entities: &mut EntitySet,
+) -> bool {
+    used_entities.clear();
 
     let mut p1 = a.iter();
     let mut p2 = b.iter();
@@ -179,23 +230,23 @@ pub fn try_merge_entities(a: &[(u64, u64)], b: &[(u64, u64)], max_num_entities:
     let mut next2 = p2.next();
 
     while let (Some(node1), Some(node2)) = (next1, next2) {
-        if used_entities[node1.1 as usize] || used_entities[node2.1 as usize] {
+        if used_entities.contains(node1.1) || used_entities.contains(node2.1) {
             debug!("different input nodes match the same pattern");
             return false;
         }
 
         if node1.0 < node2.0 {
-            used_entities[node1.1 as usize] = true;
+            used_entities.insert(node1.1);
             next1 = p1.next();
         } else if node1.0 > node2.0 {
-            used_entities[node2.1 as usize] = true;
+            used_entities.insert(node2.1);
             next2 = p2.next();
         } else {
             if node1.1 != node2.1 {
                 debug!("an input node matches distinct patterns");
                 return false;
             }
-            used_entities[node1.1 as usize] = true;
+            used_entities.insert(node1.1);
             next1 = p1.next();
             next2 = p2.next();
         }
@@ -207,10 +258,9 @@ pub fn try_merge_entities(a: &[(u64, u64)], b: &[(u64, u64)], max_num_entities:
     }
 
     while let Some(node) = next1 {
-        if used_entities[node.1 as usize] {
+        if !used_entities.insert(node.1) {
             return false;
         }
-        used_entities[node.1 as usize] = true;
         next1 = p1.next();
     }
 
@@ -220,9 +270,9 @@ pub fn try_merge_entities(a: &[(u64, u64)], b: &[(u64, u64)], max_num_entities:
 pub fn merge_entities(
     a: &[(u64, u64)],
     b: &[(u64, u64)],
-    max_num_entities: usize,
+    used_entities: &mut EntitySet,
 ) -> Option<Box<[(u64, u64)]>> {
-    if !try_merge_entities(a, b, max_num_entities) {
+    if !try_merge_entities(a, b, used_entities) {
         return None;
     }
 
@@ -261,7 +311,72 @@ pub fn merge_entities(
     Some(merged.into_boxed_slice())
 }
 
-impl<'p> SubPatternMatch {
+/// Returns `None` if a pattern entity is bound to different values of the same attribute.
+fn merge_entity_attributes(
+    a: &[(u64, usize, Box<str>)],
+    b: &[(u64, usize, Box<str>)],
+) -> Option<EntityAttributes> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let mut p1 = a.iter().peekable();
+    let mut p2 = b.iter().peekable();
+    while let (Some(attr1), Some(attr2)) = (p1.peek(), p2.peek()) {
+        match (attr1.0, attr1.1).cmp(&(attr2.0, attr2.1)) {
+            Ordering::Less => merged.push(p1.next()?.clone()),
+            Ordering::Greater => merged.push(p2.next()?.clone()),
+            Ordering::Equal => {
+                if attr1.2 != attr2.2 {
+                    debug!("an entity has different values of attribute {}", attr1.1);
+                    return None;
+                }
+                merged.push(p1.next()?.clone());
+                p2.next();
+            }
+        }
+    }
+    merged.extend(p1.cloned());
+    merged.extend(p2.cloned());
+    Some(merged)
+}
+
+/// Returns `None` if a wildcard pattern entity is bound to different input entities. Unlike
+/// `merge_entities`, different wildcards may be bound to the same input entity.
+fn merge_wildcard_entities(
+    a: &[(PatternEntityId, InputEntityId)],
+    b: &[(PatternEntityId, InputEntityId)],
+) -> Option<WildcardEntities> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let mut p1 = a.iter().peekable();
+    let mut p2 = b.iter().peekable();
+    while let (Some(&&(pattern1, input1)), Some(&&(pattern2, input2))) = (p1.peek(), p2.peek()) {
+        match pattern1.cmp(&pattern2) {
+            Ordering::Less => merged.push(*p1.next()?),
+            Ordering::Greater => merged.push(*p2.next()?),
+            Ordering::Equal => {
+                if input1 != input2 {
+                    debug!("wildcard entity {pattern1} bound to different input entities");
+                    return None;
+                }
+                merged.push(*p1.next()?);
+                p2.next();
+            }
+        }
+    }
+    merged.extend(p1);
+    merged.extend(p2);
+    Some(merged)
+}
+
+impl SubPatternMatch {
+    /// Approximate size of this match in bytes, excluding the match events it shares.
+    pub fn approx_bytes(&self) -> usize {
+        size_of::<Self>()
+            + size_of_val(&*self.event_ids)
+            + size_of_val(&*self.match_event_map)
+            + size_of_val(&*self.match_entities)
+            + size_of_val(&*self.entity_attributes)
+            + size_of_val(&*self.wildcard_entities)
+    }
+
     pub fn build(
         sub_pattern_id: u32,
         match_instance: composition_layer::MatchInstance,
@@ -278,7 +393,7 @@ impl<'p> SubPatternMatch {
         // let match_events = match_instance.match_events.into_vec();
         let match_events = match_instance.match_events.into_vec();
 
-        let match_entities = match_instance.match_entities.clone();
+        let match_entities = Box::from(&*match_instance.match_entities);
 
         let mut event_ids: Vec<(u64, u32)> = match_events
             .iter()
@@ -299,6 +414,9 @@ impl<'p> SubPatternMatch {
             event_ids: event_ids.into_boxed_slice(),
             match_entities,
             match_event_map: match_event_map.into_boxed_slice(),
+            entity_attributes: match_instance.entity_attributes,
+            wildcard_entities: match_instance.wildcard_entities,
+            serial: next_serial(),
         })
     }
 
@@ -307,10 +425,31 @@ impl<'p> SubPatternMatch {
         sub_pattern_match1: &Self,
         sub_pattern_match2: &Self,
     ) -> Option<Self> {
+        Self::try_merge_matches(sub_pattern_buffer, sub_pattern_match1, sub_pattern_match2).ok()
+    }
+
+    /// Same as `merge_matches()`, but returns why the matches cannot be joined.
+    pub fn try_merge_matches(
+        sub_pattern_buffer: &SubPatternBuffer,
+        sub_pattern_match1: &Self,
+        sub_pattern_match2: &Self,
+    ) -> Result<Self, JoinConflict> {
+        if !sub_pattern_buffer.relation.check_host(
+            &sub_pattern_match1.match_entities,
+            &sub_pattern_match2.match_entities,
+        ) {
+            debug!("matches on different hosts");
+            return Err(JoinConflict::Entities);
+        }
+
         debug!("event uniqueness checking...");
 
-        let event_ids =
-            merge_event_ids(&sub_pattern_match1.event_ids, &sub_pattern_match2.event_ids)?;
+        let event_ids = merge_event_ids(
+            &sub_pattern_match1.event_ids,
+            &sub_pattern_match2.event_ids,
+            sub_pattern_buffer.relation.event_uniqueness,
+        )
+        .ok_or(JoinConflict::Events)?;
         let match_event_map = merge_match_event_map(
             &sub_pattern_match1.match_event_map,
             &sub_pattern_match2.match_event_map,
@@ -323,19 +462,41 @@ impl<'p> SubPatternMatch {
             .relation
             .check_order_relation(&match_event_map)
         {
-            return None;
+            return Err(JoinConflict::Events);
+        }
+        if !sub_pattern_buffer
+            .relation
+            .check_simultaneous(&match_event_map)
+        {
+            debug!("simultaneous events at different times");
+            return Err(JoinConflict::Events);
         }
 
         debug!("shared node and node uniqueness checking");
 
-        // handle "shared node" and "node uniqueness"
+        // handle "shared node" and "node uniqueness"; the latter also enforces the distinct
+        // entity constraints (`PatternEvent::distinct_entities`) across the two matches, while
+        // those within a match are checked by the Composition layer
         let match_entities = merge_entities(
             &sub_pattern_match1.match_entities,
             &sub_pattern_match2.match_entities,
-            sub_pattern_buffer.max_num_entities,
-        )?;
-
-        Some(SubPatternMatch {
+            &mut sub_pattern_buffer.used_entities.borrow_mut(),
+        )
+        .ok_or(JoinConflict::Entities)?;
+
+        // check the attributes bound to the shared entities, see `PatternEvent::same_attributes`
+        let entity_attributes = merge_entity_attributes(
+            &sub_pattern_match1.entity_attributes,
+            &sub_pattern_match2.entity_attributes,
+        )
+        .ok_or(JoinConflict::Entities)?;
+        let wildcard_entities = merge_wildcard_entities(
+            &sub_pattern_match1.wildcard_entities,
+            &sub_pattern_match2.wildcard_entities,
+        )
+        .ok_or(JoinConflict::Entities)?;
+
+        Ok(SubPatternMatch {
             // 'id' is meaningless here
             id: 0,
             latest_time: max(
@@ -349,6 +510,9 @@ impl<'p> SubPatternMatch {
             match_entities,
             event_ids,
             match_event_map,
+            entity_attributes,
+            wildcard_entities,
+            serial: next_serial(),
         })
     }
 }
@@ -381,16 +545,67 @@ impl PartialOrd<Self> for EarliestFirst {
 
 #[cfg(test)]
 mod tests {
+    use crate::input_event::InputEvent;
+    use crate::match_event::RawEvents;
     use crate::pattern::SubPattern;
+    use crate::process_layers::composition_layer::MatchInstance;
 
     use super::*;
 
+    #[test]
+    fn test_format_sub_pattern_match() {
+        let input_event =
+            |time, event_id| Rc::new(InputEvent::new(time, event_id, "", 100, "", 101, ""));
+        let match_events = [
+            MatchEvent {
+                match_id: 1,
+                input_subject_id: 100,
+                input_object_id: 101,
+                pattern_subject_id: 0,
+                pattern_object_id: 1,
+                raw_events: RawEvents::Single(input_event(1000, 7)),
+            },
+            MatchEvent {
+                match_id: 2,
+                input_subject_id: 100,
+                input_object_id: 101,
+                pattern_subject_id: 0,
+                pattern_object_id: 1,
+                raw_events: RawEvents::Multiple(Box::new([
+                    input_event(2000, 3),
+                    input_event(2500, 4),
+                ])),
+            },
+        ];
+        let instance = MatchInstance {
+            start_time: 1000,
+            match_events: Box::new(match_events),
+            match_entities: smallvec::smallvec![(100, 0), (101, 1)],
+            event_ids: smallvec::smallvec![3, 4, 7],
+            state_id: 0,
+            entity_attributes: vec![],
+            wildcard_entities: vec![],
+        };
+        let sub_match = SubPatternMatch::build(1, instance, 3).unwrap();
+
+        assert_eq!(
+            sub_match.to_string(),
+            "<1.000, 2.500>[7, (3, 4)] {0: 100, 1: 101}"
+        );
+        assert_eq!(
+            format!("{sub_match:?}"),
+            "SubPatternMatch { id: 1, earliest_time: 1000, latest_time: 2500, \
+             event_ids: [(3, 2), (4, 2), (7, 1)], match_event_map: {1: [7], 2: [3, 4]}, \
+             match_entities: [(100, 0), (101, 1)], wildcard_entities: [] }"
+        );
+    }
+
     #[test]
     fn test_merge_event_id_basecase() {
         let id_list1 = [(1, 0), (3, 0), (5, 0)];
         let id_list2 = [(2, 0), (4, 0)];
         assert_eq!(
-            *merge_event_ids(&id_list1, &id_list2).unwrap(),
+            *merge_event_ids(&id_list1, &id_list2, true).unwrap(),
             [(1, 0), (2, 0), (3, 0), (4, 0), (5, 0)]
         );
     }
@@ -399,15 +614,61 @@ mod tests {
     fn test_merge_event_id_dup_id() {
         let id_list1 = [(1, 0), (3, 0), (5, 0)];
         let id_list2 = [(3, 0), (4, 0)];
-        assert_eq!(merge_event_ids(&id_list1, &id_list2), None);
+        assert_eq!(merge_event_ids(&id_list1, &id_list2, true), None);
+    }
+
+    #[test]
+    fn test_merge_event_id_without_uniqueness() {
+        // input event 3 matches pattern events 0 and 1
+        let id_list1 = [(1, 0), (3, 0)];
+        let id_list2 = [(3, 1), (4, 1)];
+        assert_eq!(merge_event_ids(&id_list1, &id_list2, true), None);
+        assert_eq!(
+            *merge_event_ids(&id_list1, &id_list2, false).unwrap(),
+            [(1, 0), (3, 0), (3, 1), (4, 1)]
+        );
     }
 
     #[test]
     fn test_merge_event_id_edgecases() {
-        assert_eq!(*merge_event_ids(&[(1, 0)], &[(2, 0)]).unwrap(), [(1, 0), (2, 0)]);
-        assert_eq!(*merge_event_ids(&[(2, 0)], &[(1, 0)]).unwrap(), [(1, 0), (2, 0)]);
-        assert_eq!(*merge_event_ids(&[(1, 0)], &[]).unwrap(), [(1, 0)]);
-        assert!(merge_event_ids(&[], &[]).unwrap().is_empty(),);
+        assert_eq!(*merge_event_ids(&[(1, 0)], &[(2, 0)], true).unwrap(), [(1, 0), (2, 0)]);
+        assert_eq!(*merge_event_ids(&[(2, 0)], &[(1, 0)], true).unwrap(), [(1, 0), (2, 0)]);
+        assert_eq!(*merge_event_ids(&[(1, 0)], &[], true).unwrap(), [(1, 0)]);
+        assert!(merge_event_ids(&[], &[], true).unwrap().is_empty(),);
+    }
+
+    #[test]
+    fn test_merge_entity_attributes() {
+        let a = [(0, 1, Box::from("alice")), (2, 0, Box::from("x"))];
+        let b = [(1, 0, Box::from("y")), (2, 0, Box::from("x"))];
+        assert_eq!(
+            merge_entity_attributes(&a, &b).unwrap(),
+            [
+                (0, 1, Box::from("alice")),
+                (1, 0, Box::from("y")),
+                (2, 0, Box::from("x"))
+            ]
+        );
+
+        // entity 2 has different values of attribute 0
+        let c = [(2, 0, Box::from("z"))];
+        assert_eq!(merge_entity_attributes(&a, &c), None);
+        assert_eq!(merge_entity_attributes(&a, &[]).unwrap(), a);
+    }
+
+    #[test]
+    fn test_merge_wildcard_entities() {
+        // wildcards 1 and 2 are bound to the same input entity
+        let a = [(1, 17), (3, 20)];
+        let b = [(2, 17), (3, 20)];
+        assert_eq!(
+            merge_wildcard_entities(&a, &b).unwrap(),
+            [(1, 17), (2, 17), (3, 20)]
+        );
+
+        // wildcard 3 is bound to different input entities
+        assert_eq!(merge_wildcard_entities(&a, &[(3, 21)]), None);
+        assert_eq!(merge_wildcard_entities(&[], &b).unwrap(), b);
     }
 
     #[test]
@@ -426,7 +687,7 @@ mod tests {
 
         let ans = None;
 
-        let merged = merge_entities(&a, &b, sub_pattern_buffer.max_num_entities);
+        let merged = merge_entities(&a, &b, &mut sub_pattern_buffer.used_entities.borrow_mut());
         assert_eq!(merged, ans);
     }
 
@@ -445,7 +706,7 @@ mod tests {
         let b = vec![(0, 17), (25, 20)];
         let ans = None;
 
-        let merged = merge_entities(&a, &b, sub_pattern_buffer.max_num_entities);
+        let merged = merge_entities(&a, &b, &mut sub_pattern_buffer.used_entities.borrow_mut());
         assert_eq!(merged, ans);
     }
 
@@ -464,7 +725,7 @@ mod tests {
         let b = vec![(0, 17), (25, 27)];
         let ans = [(0, 17), (2, 19), (7, 20), (11, 9), (25, 27)];
 
-        let merged = merge_entities(&a, &b, sub_pattern_buffer.max_num_entities);
+        let merged = merge_entities(&a, &b, &mut sub_pattern_buffer.used_entities.borrow_mut());
 
         assert_ne!(merged, None);
         assert!(merged.unwrap().iter().eq(&ans));
@@ -485,9 +746,25 @@ mod tests {
         let a = vec![(0, 17), (25, 27)];
         let ans = [(0, 17), (2, 19), (7, 20), (11, 9), (25, 27)];
 
-        let merged = merge_entities(&a, &b, sub_pattern_buffer.max_num_entities);
+        let merged = merge_entities(&a, &b, &mut sub_pattern_buffer.used_entities.borrow_mut());
 
         assert_ne!(merged, None);
         assert!(merged.unwrap().iter().eq(&ans));
     }
+
+    #[test]
+    /// pattern entity ids beyond a single word and the number of pattern entities
+    fn test_merge_many_entities() {
+        let mut used_entities = EntitySet::with_capacity(3);
+
+        let a = vec![(1, 70), (5, 300)];
+        let b = vec![(2, 64), (5, 300), (8, 1000)];
+        let ans = [(1, 70), (2, 64), (5, 300), (8, 1000)];
+        let merged = merge_entities(&a, &b, &mut used_entities).unwrap();
+        assert_eq!(*merged, ans);
+
+        // pattern entity 300 matched by two input entities
+        let c = vec![(9, 300)];
+        assert_eq!(merge_entities(&a, &c, &mut used_entities), None);
+    }
 }
@@ -1,7 +1,9 @@
-use super::matcher::{construct_regex, Matcher};
+use super::matcher::Matcher;
 use super::PartialMatchEvent;
 use crate::input_event::InputEvent;
 use crate::pattern::PatternEvent;
+use crate::process_layers::composition_layer::construct_regex_patterns;
+use crate::process_layers::regex_limits::build_regex;
 use regex::Error as RegexError;
 use regex::Regex;
 use std::rc::Rc;
@@ -13,12 +15,9 @@ pub struct DefaultMatcher<'p> {
 
 impl<'p> DefaultMatcher<'p> {
     pub fn new(pattern: &'p PatternEvent, use_regex: bool) -> Result<Self, RegexError> {
-        let regex_pattern = format!(
-            "{}\0{}\0{}",
-            pattern.signature, pattern.subject.signature, pattern.object.signature
-        );
+        let regex_pattern = construct_regex_patterns(pattern, !use_regex).join("|");
         Ok(Self {
-            regex_matcher: construct_regex(&regex_pattern, !use_regex)?,
+            regex_matcher: build_regex(&regex_pattern)?,
             matched: pattern,
         })
     }
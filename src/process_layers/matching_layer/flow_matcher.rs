@@ -157,14 +157,23 @@ mod tests {
             id: 0,
             event_type: PatternEventType::Flow,
             signature: "".to_string(),
+            alternatives: vec![],
             subject: PatternEntity {
                 id: 0,
                 signature: "".to_string(),
+                is_wildcard: false,
             },
             object: PatternEntity {
                 id: 1,
                 signature: "".to_string(),
+                is_wildcard: false,
             },
+            parent_gaps: vec![],
+            distinct_entities: vec![],
+            same_attributes: vec![],
+            simultaneous_events: vec![],
+            is_static: false,
+            is_optional: false,
         };
 
         let input1 = Rc::new(InputEvent::new(1, 0, "", 0, "u", 1, "x"));
@@ -183,14 +192,23 @@ mod tests {
             id: 0,
             event_type: PatternEventType::Flow,
             signature: "".to_string(),
+            alternatives: vec![],
             subject: PatternEntity {
                 id: 0,
                 signature: "".to_string(),
+                is_wildcard: false,
             },
             object: PatternEntity {
                 id: 1,
                 signature: "".to_string(),
+                is_wildcard: false,
             },
+            parent_gaps: vec![],
+            distinct_entities: vec![],
+            same_attributes: vec![],
+            simultaneous_events: vec![],
+            is_static: false,
+            is_optional: false,
         };
 
         let input1 = Rc::new(InputEvent::new(1, 0, "", 0, "u", 1, "v"));
@@ -0,0 +1,145 @@
+use super::matcher::Matcher;
+use super::PartialMatchEvent;
+use crate::input_event::InputEvent;
+use crate::pattern::PatternEvent;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// How much an input signature may differ from the pattern signature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FuzzyTolerance {
+    /// The Levenshtein distance (in characters) is at most the given value.
+    EditDistance(usize),
+    /// The Jaccard similarity of the tokens is at least the given ratio (0.0 ~ 1.0). Tokens are
+    /// the alphanumeric segments of a signature, e.g. `Process::name::vim -u NONE` has tokens
+    /// `Process`, `name`, `vim`, `u` and `NONE`.
+    TokenOverlap(f64),
+}
+
+impl FuzzyTolerance {
+    /// Return true if `input` is close enough to `pattern`.
+    pub fn is_match(&self, pattern: &str, input: &str) -> bool {
+        match *self {
+            FuzzyTolerance::EditDistance(max_distance) => {
+                edit_distance(pattern, input, max_distance).is_some()
+            }
+            FuzzyTolerance::TokenOverlap(min_ratio) => token_overlap(pattern, input) >= min_ratio,
+        }
+    }
+}
+
+/// Matches input events whose event, subject and object signatures are each within the tolerance
+/// of the pattern's, instead of requiring a (regex) exact match. Pattern signatures are treated
+/// as plain strings.
+pub struct FuzzyMatcher<'p> {
+    matched: &'p PatternEvent,
+    tolerance: FuzzyTolerance,
+}
+
+impl<'p> FuzzyMatcher<'p> {
+    pub fn new(pattern: &'p PatternEvent, tolerance: FuzzyTolerance) -> Self {
+        Self {
+            matched: pattern,
+            tolerance,
+        }
+    }
+
+    pub fn is_match(&self, input: &InputEvent) -> bool {
+        self.matched.signatures().any(|signature| {
+            self.tolerance
+                .is_match(signature, input.get_event_signature())
+        }) && self.tolerance.is_match(
+            &self.matched.subject.signature,
+            input.get_subject_signature(),
+        ) && self
+            .tolerance
+            .is_match(&self.matched.object.signature, input.get_object_signature())
+    }
+}
+
+impl<'p> Matcher<'p> for FuzzyMatcher<'p> {
+    fn get_match(&mut self, input: &Rc<InputEvent>) -> Option<(PartialMatchEvent<'p>, bool)> {
+        if self.is_match(input) {
+            Some((
+                PartialMatchEvent {
+                    matched: self.matched,
+                    match_ord: 0,
+                    subject_id: input.subject_id,
+                    start_time: input.timestamp,
+                    input_event: Rc::clone(input),
+                },
+                false,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the Levenshtein distance between `a` and `b` if it is at most `max_distance`.
+fn edit_distance(a: &str, b: &str, max_distance: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max_distance {
+        return None;
+    }
+
+    // dp over the rows of a, `prev[j]` is the distance between a[..i] and b[..j]
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        cur[0] = i;
+        for j in 1..=b.len() {
+            let substitution = prev[j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            cur[j] = substitution.min(prev[j] + 1).min(cur[j - 1] + 1);
+        }
+        // the distance never decreases in later rows
+        if cur.iter().min().is_some_and(|&d| d > max_distance) {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    let distance = prev[b.len()];
+    (distance <= max_distance).then_some(distance)
+}
+
+/// Jaccard similarity of the alphanumeric tokens of `a` and `b`.
+fn token_overlap(a: &str, b: &str) -> f64 {
+    let tokenize = |s: &'_ str| -> HashSet<String> {
+        s.split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let a = tokenize(a);
+    let b = tokenize(b);
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(edit_distance("kitten", "sitting", 2), None);
+        assert_eq!(edit_distance("", "abc", 3), Some(3));
+        assert_eq!(edit_distance("same", "same", 0), Some(0));
+        assert_eq!(edit_distance("a", "abcd", 2), None);
+    }
+
+    #[test]
+    fn test_token_overlap() {
+        let pattern = "Process::cmd::curl -s http://evil.com";
+        let input = "Process::cmd::curl -sL http://evil.com";
+        // tokens: {Process, cmd, curl, s, http, evil, com} vs {..., sL, ...}
+        assert_eq!(token_overlap(pattern, input), 6.0 / 8.0);
+        assert!(FuzzyTolerance::TokenOverlap(0.7).is_match(pattern, input));
+        assert!(!FuzzyTolerance::TokenOverlap(0.8).is_match(pattern, input));
+    }
+}
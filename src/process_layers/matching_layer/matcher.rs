@@ -1,5 +1,6 @@
 use super::PartialMatchEvent;
 use crate::input_event::InputEvent;
+use crate::process_layers::regex_limits::build_regex;
 use regex::Error as RegexError;
 use regex::Regex;
 use std::rc::Rc;
@@ -12,12 +13,13 @@ pub trait Matcher<'p> {
     fn get_match(&mut self, input: &Rc<InputEvent>) -> Option<(PartialMatchEvent<'p>, bool)>;
 }
 
-/// Helper function to construct regex object matching the whole input.
+/// Helper function to construct regex object matching the whole input, within the limits of
+/// [`regex_limits`](crate::process_layers::regex_limits).
 pub fn construct_regex(pattern: &str, escape_regex: bool) -> Result<Regex, RegexError> {
     let match_syntax = if escape_regex {
         format!("^{}$", regex::escape(pattern))
     } else {
         format!("^{}$", pattern)
     };
-    Regex::new(&match_syntax)
+    build_regex(&match_syntax)
 }
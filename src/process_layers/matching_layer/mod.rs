@@ -1,13 +1,16 @@
 mod default_matcher;
 mod flow_matcher;
+mod fuzzy_matcher;
 mod matcher;
 mod partial_match_event;
 
 use self::default_matcher::DefaultMatcher;
 use self::flow_matcher::FlowMatcher;
+use self::fuzzy_matcher::FuzzyMatcher;
+pub use self::fuzzy_matcher::FuzzyTolerance;
 use self::matcher::Matcher;
 use crate::input_event::InputEvent;
-use crate::pattern::{Pattern, PatternEventType, SubPattern};
+use crate::pattern::{Pattern, PatternEvent, PatternEventType, SubPattern};
 pub use partial_match_event::PartialMatchEvent;
 use regex::Error as RegexError;
 use std::rc::Rc;
@@ -32,6 +35,47 @@ impl<'p, P> MatchingLayer<'p, P> {
         pattern: &'p Pattern,
         decomposition: &[SubPattern<'p>],
         window_size: u64,
+    ) -> Result<Self, RegexError> {
+        Self::with_matchers(
+            prev_layer,
+            pattern,
+            decomposition,
+            window_size,
+            |pattern_event| {
+                Ok(Box::new(DefaultMatcher::new(
+                    pattern_event,
+                    pattern.use_regex,
+                )?))
+            },
+        )
+    }
+
+    /// Create a matching layer where non-flow pattern events are matched fuzzily with
+    /// [`FuzzyMatcher`], tolerating small differences between the signatures.
+    pub fn new_fuzzy(
+        prev_layer: P,
+        pattern: &'p Pattern,
+        decomposition: &[SubPattern<'p>],
+        window_size: u64,
+        tolerance: FuzzyTolerance,
+    ) -> Result<Self, RegexError> {
+        Self::with_matchers(
+            prev_layer,
+            pattern,
+            decomposition,
+            window_size,
+            |pattern_event| Ok(Box::new(FuzzyMatcher::new(pattern_event, tolerance))),
+        )
+    }
+
+    /// Flow pattern events are always matched by [`FlowMatcher`], other pattern events are
+    /// matched by the matcher created with `new_matcher`.
+    fn with_matchers(
+        prev_layer: P,
+        pattern: &'p Pattern,
+        decomposition: &[SubPattern<'p>],
+        window_size: u64,
+        new_matcher: impl Fn(&'p PatternEvent) -> Result<Box<dyn Matcher<'p> + 'p>, RegexError>,
     ) -> Result<Self, RegexError> {
         let mut matchers = vec![];
         for sub_pattern in decomposition {
@@ -42,7 +86,7 @@ impl<'p, P> MatchingLayer<'p, P> {
                         pattern.use_regex,
                         window_size,
                     )?),
-                    _ => Box::new(DefaultMatcher::new(pattern_event, pattern.use_regex)?),
+                    _ => new_matcher(pattern_event)?,
                 };
                 matchers.push(matcher);
             }
@@ -156,6 +200,38 @@ mod tests {
         assert!(layer.next().is_none());
     }
 
+    #[test]
+    fn test_fuzzy() {
+        let pattern = Pattern::from_graph(&["u", "v"], &[(0, 1, "edge12")], true);
+
+        let sub_pattern = SubPattern {
+            id: 0,
+            events: vec![&pattern.events[0]],
+        };
+        let decomposition = [sub_pattern];
+
+        let time_batch = vec![
+            simple_input_edge(1, "edge12"),
+            simple_input_edge(2, "edge1"),
+            simple_input_edge(3, "edge[0-9]+"),
+            simple_input_edge(4, "Edge123"),
+        ];
+
+        let mut layer = MatchingLayer::new_fuzzy(
+            [time_batch].into_iter(),
+            &pattern,
+            &decomposition,
+            u64::MAX,
+            FuzzyTolerance::EditDistance(2),
+        )
+        .unwrap();
+
+        assert_eq!(layer.next().unwrap().input_event.event_id, 1);
+        assert_eq!(layer.next().unwrap().input_event.event_id, 2);
+        assert_eq!(layer.next().unwrap().input_event.event_id, 4);
+        assert!(layer.next().is_none());
+    }
+
     #[test]
     fn test_reorder() {
         let pattern = Pattern::from_graph(&["u", "v"], &[(0, 1, "edge1"), (0, 1, "edge2")], false);
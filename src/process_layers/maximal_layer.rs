@@ -0,0 +1,172 @@
+use crate::pattern_match::PatternMatch;
+use crate::process_layers::parse_layer::IdleState;
+use crate::process_layers::window_policy::{SlidingWindow, WindowPolicy};
+use log::debug;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// The layer that suppresses pattern matches whose matched input events are a strict subset of
+/// those of another match, so that only maximal matches are output. This typically happens on
+/// frequency and flow pattern events, where a match with more input events also satisfies the
+/// pattern.
+///
+/// It takes the output of [`UniquenessLayer`](super::UniquenessLayer). A match is held until the
+/// window bound of the stream time passes its `latest_time`, by which time all the matches it may
+/// contain or be contained in have left the Uniqueness layer. Thus, the output is delayed by
+/// about one more window.
+pub struct MaximalLayer<P> {
+    prev_layer: P,
+    maximal_filter: MaximalFilter,
+}
+
+impl<P> MaximalLayer<P> {
+    pub fn new(prev_layer: P, window_size: u64) -> Self {
+        Self {
+            prev_layer,
+            maximal_filter: MaximalFilter::new(Rc::new(SlidingWindow::new(window_size))),
+        }
+    }
+
+    /// Use the window policy of the previous layers, see
+    /// [`UniquenessLayer::set_window_policy`](super::UniquenessLayer::set_window_policy).
+    pub fn set_window_policy(&mut self, window: Rc<dyn WindowPolicy>) {
+        self.maximal_filter.window = window;
+    }
+
+    /// Use the idle state given to the previous layers, see
+    /// [`UniquenessLayer::set_idle_state`](super::UniquenessLayer::set_idle_state).
+    pub fn set_idle_state(&mut self, idle_state: IdleState) {
+        self.maximal_filter.idle_state = Some(idle_state);
+    }
+
+    pub fn prev_layer(&self) -> &P {
+        &self.prev_layer
+    }
+}
+
+/// The matches held by a [`MaximalLayer`], which [`Pipeline`](super::Pipeline) also keeps for the
+/// patterns with optional events.
+pub(crate) struct MaximalFilter {
+    window: Rc<dyn WindowPolicy>,
+    /// Matches that may still be contained in later ones, in their input order, along with the
+    /// sorted ids of their input events.
+    held_matches: VecDeque<(PatternMatch, Box<[u64]>)>,
+    /// Maximal matches ready for the next layer.
+    maximal_matches: VecDeque<PatternMatch>,
+    /// See `MaximalLayer::set_idle_state()`.
+    idle_state: Option<IdleState>,
+}
+
+impl MaximalFilter {
+    pub(crate) fn new(window: Rc<dyn WindowPolicy>) -> Self {
+        Self {
+            window,
+            held_matches: VecDeque::new(),
+            maximal_matches: VecDeque::new(),
+            idle_state: None,
+        }
+    }
+
+    pub(crate) fn set_idle_state(&mut self, idle_state: IdleState) {
+        self.idle_state = Some(idle_state);
+    }
+
+    /// The next maximal match among those taken from `prev_layer`.
+    pub(crate) fn next_from(
+        &mut self,
+        prev_layer: &mut impl Iterator<Item = PatternMatch>,
+    ) -> Option<PatternMatch> {
+        while self.maximal_matches.is_empty() {
+            if let Some(pattern_match) = prev_layer.next() {
+                self.release_expired(self.window.window_bound(pattern_match.latest_time));
+                self.add_match(pattern_match);
+            } else if let Some(time) = self.idle_state.as_ref().and_then(IdleState::get) {
+                self.release_expired(self.window.window_bound(time));
+                break;
+            } else {
+                self.release_expired(u64::MAX);
+                break;
+            }
+        }
+        self.maximal_matches.pop_front()
+    }
+
+    /// Add `pattern_match` to the held matches, unless it is contained in one of them. The held
+    /// matches contained in `pattern_match` are dropped.
+    fn add_match(&mut self, pattern_match: PatternMatch) {
+        let input_ids: Box<[u64]> = pattern_match.event_ids.iter().map(|(id, _)| *id).collect();
+        if let Some((superset, _)) = self
+            .held_matches
+            .iter()
+            .find(|(_, ids)| is_strict_subset(&input_ids, ids))
+        {
+            debug!(
+                "drop pattern match {} contained in {}",
+                pattern_match, superset
+            );
+            return;
+        }
+        self.held_matches.retain(|(held, ids)| {
+            let contained = is_strict_subset(ids, &input_ids);
+            if contained {
+                debug!("drop pattern match {} contained in {}", held, pattern_match);
+            }
+            !contained
+        });
+        self.held_matches.push_back((pattern_match, input_ids));
+    }
+
+    /// Release the held matches whose `latest_time` is before `window_bound`.
+    fn release_expired(&mut self, window_bound: u64) {
+        let (expired, held): (VecDeque<_>, _) = self
+            .held_matches
+            .drain(..)
+            .partition(|(held, _)| held.latest_time < window_bound);
+        self.held_matches = held;
+        self.maximal_matches
+            .extend(expired.into_iter().map(|(pattern_match, _)| pattern_match));
+    }
+}
+
+/// Returns `true` if the sorted ids `a` is a strict subset of the sorted ids `b`.
+fn is_strict_subset(a: &[u64], b: &[u64]) -> bool {
+    if a.len() >= b.len() {
+        return false;
+    }
+    let mut b = b.iter();
+    a.iter().all(|id| b.any(|other| other == id))
+}
+
+impl<P> Iterator for MaximalLayer<P>
+where
+    P: Iterator<Item = PatternMatch>,
+{
+    type Item = PatternMatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.maximal_filter.next_from(&mut self.prev_layer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maximal_matches() {
+        let input = vec![
+            PatternMatch::for_test(1, 3, &[1, 2]),
+            PatternMatch::for_test(1, 4, &[1, 2, 3]),
+            PatternMatch::for_test(2, 4, &[2, 3]),
+            PatternMatch::for_test(2, 4, &[2, 4]),
+            PatternMatch::for_test(3, 5, &[3, 5]),
+            PatternMatch::for_test(30, 30, &[1]), // far beyond the window
+        ];
+        let layer = MaximalLayer::new(input.into_iter(), 10);
+
+        let ids: Vec<Vec<u64>> = layer
+            .map(|m| m.event_ids.iter().map(|(id, _)| *id).collect())
+            .collect();
+        assert_eq!(ids, [vec![1, 2, 3], vec![2, 4], vec![3, 5], vec![1]]);
+    }
+}
@@ -1,11 +1,41 @@
+pub mod buffer_stats;
 pub mod composition_layer;
+pub mod correlation_layer;
+pub mod debug_dump;
+pub mod event_trace;
+pub mod incident_layer;
 pub mod join_layer;
 pub mod matching_layer;
+pub mod maximal_layer;
 pub mod parse_layer;
+pub mod pipeline;
+pub mod post_filter;
+#[cfg(test)]
+mod proptests;
+pub mod recent_graph;
+pub mod regex_limits;
+pub mod signature_set;
 pub mod uniqueness_layer;
+pub mod window_policy;
 
+pub use buffer_stats::{
+    BufferStats, ReportBufferStats, StateSeriesWriter, StatsReport, StatsReporter,
+};
 pub use composition_layer::CompositionLayer;
+pub use correlation_layer::CorrelationLayer;
+pub use debug_dump::{DebugDump, DumpState};
+pub use event_trace::EventTrace;
+pub use incident_layer::{Incident, IncidentLayer};
 pub use join_layer::JoinLayer;
 pub use matching_layer::MatchingLayer;
+pub use maximal_layer::MaximalLayer;
 pub use parse_layer::ParseLayer;
+pub use pipeline::{FlushPolicy, Pipeline, PipelineConfig};
+pub use post_filter::{
+    HistoricalCutoff, KeepAll, MatchPostFilter, NeighborhoodExport, NeighborhoodFormat,
+    ScoreThreshold,
+};
+pub use recent_graph::RecentGraph;
+pub use signature_set::SignatureSet;
 pub use uniqueness_layer::UniquenessLayer;
+pub use window_policy::{WindowBoundary, WindowKind, WindowPolicy, WindowSize};
@@ -0,0 +1,119 @@
+use ahash::HashMap;
+use serde::de::Error as _;
+use serde::Deserialize;
+
+/// The correction of the clock of an input source, mapping its timestamp `t` to
+/// `reference + (t - reference) * skew + offset`, e.g. for a host logging in its local time zone,
+/// or a host whose clock drifts since it was last synchronized at `reference`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClockCorrection {
+    /// Seconds added to the timestamps, e.g. `-28800` for a host logging in UTC+8.
+    #[serde(default)]
+    pub offset: f64,
+    /// The rate of the reference clock over the clock of the source, e.g. `1.0001` for a clock
+    /// running 100 ppm slow.
+    #[serde(default = "default_skew")]
+    pub skew: f64,
+    /// The timestamp (sec) at which the clock of the source was right, apart from `offset`.
+    #[serde(default)]
+    pub reference: f64,
+}
+
+fn default_skew() -> f64 {
+    1.0
+}
+
+impl ClockCorrection {
+    /// Correct `time` (in milliseconds), saturating at 0.
+    pub fn apply(&self, time: u64) -> u64 {
+        let reference = self.reference * 1000.0;
+        let corrected = reference + (time as f64 - reference) * self.skew + self.offset * 1000.0;
+        corrected.round().max(0.0) as u64
+    }
+}
+
+/// The clock corrections of the input sources, keyed by the hosts in the host column of the data
+/// graph, parsed from an input manifest, e.g.
+///
+/// ```toml
+/// [sources.web]
+/// offset = -28800
+///
+/// [sources.db]
+/// skew = 1.0001
+/// reference = 1700000000
+/// ```
+///
+/// The timestamps of the other hosts are not corrected. See
+/// [`ParseLayer::set_clock_corrections`](super::ParseLayer::set_clock_corrections).
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ClockCorrections {
+    #[serde(default)]
+    sources: HashMap<String, ClockCorrection>,
+}
+
+impl ClockCorrections {
+    /// Parse the input manifest in toml format.
+    pub fn parse(manifest: &str) -> Result<Self, toml::de::Error> {
+        let corrections: Self = toml::from_str(manifest)?;
+        for (host, correction) in &corrections.sources {
+            let valid = correction.offset.is_finite()
+                && correction.reference.is_finite()
+                && correction.skew.is_finite()
+                && correction.skew > 0.0;
+            if !valid {
+                return Err(toml::de::Error::custom(format!(
+                    "invalid clock correction of source {host}"
+                )));
+            }
+        }
+        Ok(corrections)
+    }
+
+    pub fn insert(&mut self, host: &str, correction: ClockCorrection) {
+        self.sources.insert(host.to_string(), correction);
+    }
+
+    pub fn get(&self, host: &str) -> Option<&ClockCorrection> {
+        self.sources.get(host)
+    }
+
+    /// The earliest corrected time of any source at the uncorrected `time`. Since the corrections
+    /// are monotonic, the events read after one at `time` are not corrected to earlier than this.
+    pub fn earliest(&self, time: u64) -> u64 {
+        self.sources
+            .values()
+            .map(|correction| correction.apply(time))
+            .fold(time, u64::min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let corrections = ClockCorrections::parse(
+            r#"
+            [sources.web]
+            offset = -1.5
+
+            [sources.db]
+            skew = 1.5
+            reference = 100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(corrections.get("web").unwrap().apply(2000), 500);
+        assert_eq!(corrections.get("web").unwrap().apply(1000), 0);
+        assert_eq!(corrections.get("db").unwrap().apply(110_000), 115_000);
+        assert_eq!(corrections.get("app"), None);
+        assert_eq!(corrections.earliest(110_000), 108_500);
+
+        assert!(ClockCorrections::parse("[sources.web]\nskew = 0").is_err());
+        assert!(ClockCorrections::parse("[sources.web]\ndrift = 1").is_err());
+    }
+}
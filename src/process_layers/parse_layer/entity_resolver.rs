@@ -0,0 +1,81 @@
+use ahash::HashMap;
+
+/// Maps the raw entity ids in the data graph to canonical ids, so that different ids of the same
+/// real-world entity (e.g. a renamed file, or a reused pid) are treated as the same entity by all
+/// the later layers.
+pub trait EntityResolver {
+    /// Returns the canonical ids of the subject and the object of an input event.
+    ///
+    /// It is invoked once for each input event, in the order the events appear in the data graph.
+    fn resolve(
+        &mut self,
+        event_signature: &str,
+        subject: (u64, &str),
+        object: (u64, &str),
+    ) -> (u64, u64);
+}
+
+/// Uses the raw entity ids as is.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Passthrough;
+
+impl EntityResolver for Passthrough {
+    fn resolve(&mut self, _: &str, subject: (u64, &str), object: (u64, &str)) -> (u64, u64) {
+        (subject.0, object.0)
+    }
+}
+
+/// Tracks rename events, mapping the renamed entity to the canonical id of the original entity.
+///
+/// An event whose signature equals `rename_signature` is treated as a rename from its subject
+/// (the original entity) to its object (the renamed entity). Renames can be chained.
+#[derive(Debug, Default, Clone)]
+pub struct RenameTracker {
+    rename_signature: String,
+    aliases: HashMap<u64, u64>,
+}
+
+impl RenameTracker {
+    pub fn new(rename_signature: impl Into<String>) -> Self {
+        Self {
+            rename_signature: rename_signature.into(),
+            aliases: HashMap::default(),
+        }
+    }
+
+    fn canonical_id(&self, id: u64) -> u64 {
+        self.aliases.get(&id).copied().unwrap_or(id)
+    }
+}
+
+impl EntityResolver for RenameTracker {
+    fn resolve(
+        &mut self,
+        event_signature: &str,
+        subject: (u64, &str),
+        object: (u64, &str),
+    ) -> (u64, u64) {
+        let subject_id = self.canonical_id(subject.0);
+        if event_signature == self.rename_signature && subject.0 != object.0 {
+            // aliases always point to canonical ids, so chained renames are resolved in O(1)
+            self.aliases.insert(object.0, subject_id);
+        }
+        (subject_id, self.canonical_id(object.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_tracker() {
+        let mut resolver = RenameTracker::new("rename");
+        assert_eq!(resolver.resolve("write", (1, "p"), (2, "a")), (1, 2));
+        assert_eq!(resolver.resolve("rename", (2, "a"), (3, "b")), (2, 2));
+        assert_eq!(resolver.resolve("read", (1, "p"), (3, "b")), (1, 2));
+        // chained rename
+        assert_eq!(resolver.resolve("rename", (3, "b"), (4, "c")), (2, 2));
+        assert_eq!(resolver.resolve("read", (4, "c"), (1, "p")), (2, 1));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::input_event::{entity_host, entity_key};
+use ahash::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The entity ids of the reincarnated entities are allocated downward from this one, below the
+/// host bits of [`entity_key`].
+const LAST_VERSIONED_ID: u64 = (1 << 48) - 1;
+
+#[derive(Debug, Default)]
+struct Versions {
+    /// The id of the current incarnation of each reincarnated entity, keyed by its id in the
+    /// data graph.
+    current: HashMap<u64, u64>,
+    /// The entities terminated but not referred to again yet.
+    terminated: HashSet<u64>,
+    num_versioned: u64,
+}
+
+/// Tracks the lifetime of entities ended by terminate records (e.g. process exit, file delete),
+/// after which the data graph may reuse their ids for different real entities. Each later
+/// incarnation of an id gets a new entity id, so that the partial matches bound to an earlier
+/// incarnation never bind to the events of the later one. See
+/// [`ParseLayer::set_entity_versions`](super::ParseLayer::set_entity_versions).
+///
+/// The new ids are allocated downward from `2^48 - 1`, keeping the host of the entity, so the
+/// ids in the data graph must stay below them.
+#[derive(Debug, Clone, Default)]
+pub struct EntityVersions(Rc<RefCell<Versions>>);
+
+impl EntityVersions {
+    /// Mark the entity `id` as terminated, so that its next reference starts a new incarnation.
+    pub fn terminate(&self, id: u64) {
+        self.0.borrow_mut().terminated.insert(id);
+    }
+
+    /// Returns the id of the current incarnation of the entity `id`.
+    pub fn resolve(&self, id: u64) -> u64 {
+        let mut versions = self.0.borrow_mut();
+        if versions.terminated.remove(&id) {
+            versions.num_versioned += 1;
+            let versioned_id = entity_key(
+                entity_host(id),
+                LAST_VERSIONED_ID + 1 - versions.num_versioned,
+            )
+            .unwrap_or(id);
+            versions.current.insert(id, versioned_id);
+        }
+        versions.current.get(&id).copied().unwrap_or(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entity_versions() {
+        let versions = EntityVersions::default();
+        assert_eq!(versions.resolve(5), 5);
+        versions.terminate(5);
+        // terminating twice before reuse is a single incarnation
+        versions.terminate(5);
+        let reused = versions.resolve(5);
+        assert_ne!(reused, 5);
+        assert_eq!(versions.resolve(5), reused);
+        assert_eq!(versions.resolve(6), 6);
+
+        versions.terminate(5);
+        let reused_again = versions.resolve(5);
+        assert_ne!(reused_again, reused);
+        assert_ne!(reused_again, 5);
+
+        let host_id = entity_key(2, 5).unwrap();
+        versions.terminate(host_id);
+        assert_eq!(entity_host(versions.resolve(host_id)), 2);
+    }
+}
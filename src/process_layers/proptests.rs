@@ -0,0 +1,244 @@
+//! Property tests running small random patterns over random event streams. The Composition and
+//! Join layers must not panic and must only emit valid matches, i.e. matching the signatures,
+//! the entity bindings, the order relation and the window. The pipeline must emit exactly the
+//! matches found by brute force. Set `PROPTEST_CASES` to run more cases.
+use crate::pattern::sub_pattern::decompose;
+use crate::pattern::Pattern;
+use crate::pattern_match::PatternMatch;
+use crate::process_layers::{CompositionLayer, JoinLayer, ParseLayer, Pipeline, PipelineConfig};
+use itertools::Itertools;
+use proptest::prelude::*;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+
+const SIGNATURES: [&str; 3] = ["a", "b", "c"];
+const WINDOW_SIZE: u64 = 3 * 1000;
+
+/// An input event: `(timestamp, event signature, subject id, object id)` in milliseconds, where
+/// the signature of entity `i` is `SIGNATURES[i % 3]`.
+type Event = (u64, usize, u64, u64);
+
+/// A pattern event: `(signature, subject id, object id, parent ids)`.
+type EventSpec = (usize, usize, usize, Vec<usize>);
+
+fn arb_pattern() -> impl Strategy<Value = (Vec<usize>, Vec<EventSpec>)> {
+    let entities = prop::collection::vec(0..SIGNATURES.len(), 2..=3);
+    let events = prop::collection::vec((0..2usize, 0..3usize, 0..3usize, any::<u8>()), 1..=4);
+    (entities, events).prop_map(|(entities, events)| {
+        let events = events
+            .into_iter()
+            .enumerate()
+            .map(|(id, (signature, subject, object, parents))| {
+                // the parents are earlier events, so that the order relation is acyclic
+                let parents = (0..id).filter(|i| parents & (1 << i) != 0).collect();
+                let n = entities.len();
+                (signature, subject % n, object % n, parents)
+            })
+            .collect();
+        (entities, events)
+    })
+}
+
+fn arb_events() -> impl Strategy<Value = Vec<Event>> {
+    let event = (0..=1500u64, 0..2usize, 1..=4u64, 1..=4u64);
+    prop::collection::vec(event, 0..=12).prop_map(|events| {
+        let mut timestamp = 0;
+        events
+            .into_iter()
+            .map(|(delta, signature, subject, object)| {
+                timestamp += delta;
+                (timestamp, signature, subject, object)
+            })
+            .collect()
+    })
+}
+
+fn to_pattern((entities, events): &(Vec<usize>, Vec<EventSpec>)) -> Pattern {
+    let pattern = json!({
+        "Version": "0.2.0",
+        "UseRegex": false,
+        "Entities": entities
+            .iter()
+            .enumerate()
+            .map(|(id, signature)| json!({ "ID": id, "Signature": SIGNATURES[*signature] }))
+            .collect::<Vec<_>>(),
+        "Events": events
+            .iter()
+            .enumerate()
+            .map(|(id, (signature, subject, object, parents))| json!({
+                "ID": id,
+                "Signature": SIGNATURES[*signature],
+                "SubjectID": subject,
+                "ObjectID": object,
+                "Parents": parents,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    Pattern::parse_str(&pattern.to_string()).unwrap()
+}
+
+fn to_csv(events: &[Event]) -> String {
+    let mut csv = String::new();
+    for (id, (timestamp, signature, subject, object)) in events.iter().enumerate() {
+        let time = format!("{}.{:03}", timestamp / 1000, timestamp % 1000);
+        let entity_sig = |id: u64| SIGNATURES[id as usize % SIGNATURES.len()];
+        csv += &format!(
+            "{time},{time},{id},{},{subject},{},{object},{}\n",
+            SIGNATURES[*signature],
+            entity_sig(*subject),
+            entity_sig(*object),
+        );
+    }
+    csv
+}
+
+fn parse_layer(csv: &str) -> ParseLayer<&[u8]> {
+    let csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(csv.as_bytes());
+    ParseLayer::new(csv_reader)
+}
+
+/// Returns `true` if the input event `event` can match the pattern event `pattern_event_id`.
+fn matches_signatures(pattern: &Pattern, pattern_event_id: usize, event: &Event) -> bool {
+    let pattern_event = &pattern.events[pattern_event_id];
+    let entity_sig = |id: u64| SIGNATURES[id as usize % SIGNATURES.len()];
+    pattern_event.signature == SIGNATURES[event.1]
+        && pattern_event.subject.signature == entity_sig(event.2)
+        && pattern_event.object.signature == entity_sig(event.3)
+}
+
+/// Returns `true` if the input events `assignment[i]` matching pattern event `i` form a valid
+/// match, ignoring the signatures.
+fn is_valid_match(pattern: &Pattern, events: &[Event], assignment: &[usize]) -> bool {
+    let input_events: HashSet<_> = assignment.iter().collect();
+    if input_events.len() != assignment.len() {
+        return false;
+    }
+
+    let mut bindings = HashMap::new();
+    for (pattern_event, input_event) in pattern.events.iter().zip(assignment) {
+        let (_, _, subject, object) = events[*input_event];
+        for (pattern_entity, input_entity) in [
+            (pattern_event.subject.id, subject),
+            (pattern_event.object.id, object),
+        ] {
+            if *bindings.entry(pattern_entity).or_insert(input_entity) != input_entity {
+                return false;
+            }
+        }
+    }
+    let bound_entities: HashSet<_> = bindings.values().collect();
+    if bound_entities.len() != bindings.len() {
+        return false;
+    }
+
+    let timestamps: Vec<u64> = assignment.iter().map(|i| events[*i].0).collect();
+    for (a, b) in (0..assignment.len()).flat_map(|a| (0..assignment.len()).map(move |b| (a, b))) {
+        if pattern.order.must_precede(a, b) && timestamps[a] > timestamps[b] {
+            return false;
+        }
+    }
+    let earliest = timestamps.iter().min().unwrap();
+    let latest = timestamps.iter().max().unwrap();
+    latest - earliest <= WINDOW_SIZE
+}
+
+/// Enumerate all the matches of `pattern` in `events`, as the sorted `(input event id, pattern
+/// event id)` pairs.
+fn brute_force(pattern: &Pattern, events: &[Event]) -> HashSet<Vec<(u64, u32)>> {
+    (0..pattern.events.len())
+        .map(|pattern_event_id| {
+            (0..events.len())
+                .filter(move |i| matches_signatures(pattern, pattern_event_id, &events[*i]))
+        })
+        .multi_cartesian_product()
+        .filter(|assignment| is_valid_match(pattern, events, assignment))
+        .map(|assignment| {
+            let pairs = assignment.into_iter().enumerate();
+            pairs.map(|(j, i)| (i as u64, j as u32)).sorted().collect()
+        })
+        .collect()
+}
+
+/// Assert that `pattern_match` is a valid match of `pattern` in `events`.
+fn check_match(pattern: &Pattern, events: &[Event], pattern_match: &PatternMatch) {
+    let mut assignment = vec![None; pattern.events.len()];
+    for (input_event_id, pattern_event_id) in pattern_match.event_ids.iter() {
+        let input_event = &events[*input_event_id as usize];
+        let pattern_event_id = *pattern_event_id as usize;
+        assert!(matches_signatures(pattern, pattern_event_id, input_event));
+        assert!(assignment[pattern_event_id].is_none());
+        assignment[pattern_event_id] = Some(*input_event_id as usize);
+    }
+    let assignment: Vec<usize> = assignment.into_iter().map(Option::unwrap).collect();
+    assert!(is_valid_match(pattern, events, &assignment));
+
+    let timestamps = assignment.iter().map(|i| events[*i].0);
+    assert_eq!(Some(pattern_match.earliest_time), timestamps.clone().min());
+    assert_eq!(Some(pattern_match.latest_time), timestamps.max());
+}
+
+fn sorted_event_ids(pattern_match: &PatternMatch) -> Vec<(u64, u32)> {
+    pattern_match.event_ids.iter().copied().sorted().collect()
+}
+
+proptest! {
+    #[test]
+    fn test_composition_layer(pattern in arb_pattern(), events in arb_events()) {
+        let pattern = to_pattern(&pattern);
+        let decomposition = decompose(&pattern);
+        let csv = to_csv(&events);
+        let composition_layer =
+            CompositionLayer::new(parse_layer(&csv), &decomposition, WINDOW_SIZE, false).unwrap();
+
+        for (sub_pattern_id, instance) in composition_layer {
+            let sub_pattern = &decomposition[sub_pattern_id as usize];
+            prop_assert_eq!(instance.match_events.len(), sub_pattern.events.len());
+            let mut assignment = vec![];
+            let pattern_events = &sub_pattern.events;
+            for (match_event, pattern_event) in instance.match_events.iter().zip(pattern_events) {
+                prop_assert_eq!(match_event.match_id as usize, pattern_event.id);
+                let input_event_id = match_event.raw_events.get_ids().exactly_one().ok().unwrap();
+                let input_event = &events[input_event_id as usize];
+                prop_assert!(matches_signatures(&pattern, pattern_event.id, input_event));
+                assignment.push(input_event_id as usize);
+            }
+            prop_assert!(assignment.iter().all_unique());
+            let timestamps = assignment.iter().map(|i| events[*i].0);
+            let (earliest, latest) = timestamps.minmax().into_option().unwrap();
+            prop_assert!(latest - earliest <= WINDOW_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_join_layer(pattern in arb_pattern(), events in arb_events()) {
+        let pattern = to_pattern(&pattern);
+        let decomposition = decompose(&pattern);
+        let csv = to_csv(&events);
+        let composition_layer =
+            CompositionLayer::new(parse_layer(&csv), &decomposition, WINDOW_SIZE, false).unwrap();
+        let join_layer = JoinLayer::new(composition_layer, &pattern, &decomposition, WINDOW_SIZE);
+
+        let expected = brute_force(&pattern, &events);
+        for pattern_match in join_layer {
+            check_match(&pattern, &events, &pattern_match);
+            prop_assert!(expected.contains(&sorted_event_ids(&pattern_match)));
+        }
+    }
+
+    #[test]
+    fn test_pipeline(pattern in arb_pattern(), events in arb_events()) {
+        let pattern = to_pattern(&pattern);
+        let csv = to_csv(&events);
+        let pipeline =
+            Pipeline::new(parse_layer(&csv), &pattern, PipelineConfig::new(WINDOW_SIZE)).unwrap();
+
+        let mut outputs = HashSet::new();
+        for pattern_match in pipeline {
+            check_match(&pattern, &events, &pattern_match);
+            prop_assert!(outputs.insert(sorted_event_ids(&pattern_match)), "duplicated match");
+        }
+        prop_assert_eq!(outputs, brute_force(&pattern, &events));
+    }
+}
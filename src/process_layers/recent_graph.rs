@@ -0,0 +1,300 @@
+use crate::input_event::InputEvent;
+use crate::pattern_match::PatternMatch;
+use ahash::{HashMap, HashSet};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+/// The recent input events, indexed by their entities, for exporting the neighborhoods of the
+/// matched entities, see [`NeighborhoodExport`](super::NeighborhoodExport). The Composition layer
+/// adds each input batch to it, see
+/// [`PipelineConfig::with_recent_graph`](super::PipelineConfig::with_recent_graph).
+///
+/// A match is output at most a window after its earliest event, and its neighborhood reaches a
+/// window before its latest event, so the graph keeps the events of the last two windows, but at
+/// most `max_events` of them.
+#[derive(Clone)]
+pub struct RecentGraph(Rc<RefCell<Inner>>);
+
+struct Inner {
+    window_size: u64,
+    max_events: usize,
+    /// The events in the input order.
+    events: VecDeque<Rc<InputEvent>>,
+    /// The events of each entity (as the subject or the object) in the input order.
+    adjacency: HashMap<u64, VecDeque<Rc<InputEvent>>>,
+}
+
+impl RecentGraph {
+    pub fn new(window_size: u64, max_events: usize) -> Self {
+        Self(Rc::new(RefCell::new(Inner {
+            window_size,
+            max_events,
+            events: VecDeque::new(),
+            adjacency: HashMap::default(),
+        })))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add the events of an input batch, and drop those older than two windows.
+    pub fn add_batch(&self, batch: &[Rc<InputEvent>]) {
+        let Some(first) = batch.first() else {
+            return;
+        };
+        let mut inner = self.0.borrow_mut();
+        for event in batch {
+            inner.events.push_back(event.clone());
+            for entity_id in endpoints(event) {
+                let events = inner.adjacency.entry(entity_id).or_default();
+                events.push_back(event.clone());
+            }
+        }
+
+        let bound = first.timestamp.saturating_sub(2 * inner.window_size);
+        while let Some(oldest) = inner.events.pop_front() {
+            if oldest.timestamp >= bound && inner.events.len() < inner.max_events {
+                inner.events.push_front(oldest);
+                break;
+            }
+            inner.remove_oldest(&oldest);
+        }
+    }
+
+    /// The events within `hops` hops from the input entities of `pattern_match`, during the
+    /// windows before and after the match.
+    pub fn neighborhood(&self, pattern_match: &PatternMatch, hops: usize) -> Neighborhood {
+        let inner = self.0.borrow();
+        let window_size = inner.window_size;
+        let time_range = pattern_match.latest_time.saturating_sub(window_size)
+            ..=pattern_match.earliest_time.saturating_add(window_size);
+        let mut neighborhood = inner.neighborhood(pattern_match.input_entities(), hops, time_range);
+        neighborhood.matched = pattern_match.event_ids.iter().map(|(id, _)| *id).collect();
+        neighborhood
+    }
+}
+
+impl fmt::Debug for RecentGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RecentGraph").field(&self.len()).finish()
+    }
+}
+
+impl Inner {
+    /// Remove the oldest event from the lists of its entities, where it is the first.
+    fn remove_oldest(&mut self, oldest: &Rc<InputEvent>) {
+        for entity_id in endpoints(oldest) {
+            let Some(events) = self.adjacency.get_mut(&entity_id) else {
+                continue;
+            };
+            if events
+                .front()
+                .is_some_and(|event| Rc::ptr_eq(event, oldest))
+            {
+                events.pop_front();
+            }
+            if events.is_empty() {
+                self.adjacency.remove(&entity_id);
+            }
+        }
+    }
+
+    /// A breadth-first search from `entity_ids` through the events in `time_range`.
+    fn neighborhood(
+        &self,
+        entity_ids: impl IntoIterator<Item = u64>,
+        hops: usize,
+        time_range: RangeInclusive<u64>,
+    ) -> Neighborhood {
+        let mut neighborhood = Neighborhood::default();
+        let mut visited = HashSet::default();
+        let mut frontier: Vec<u64> = entity_ids.into_iter().collect();
+        for entity_id in &frontier {
+            neighborhood.entities.entry(*entity_id).or_default();
+        }
+        for _ in 0..hops {
+            let mut next_frontier = vec![];
+            let events = frontier
+                .iter()
+                .filter_map(|entity_id| self.adjacency.get(entity_id))
+                .flatten()
+                .filter(|event| time_range.contains(&event.timestamp));
+            for event in events {
+                // the start and the end events of a record share the id
+                if !visited.insert((event.event_id, event.timestamp)) {
+                    continue;
+                }
+                for (entity_id, signature) in [
+                    (event.subject_id, event.get_subject_signature()),
+                    (event.object_id, event.get_object_signature()),
+                ] {
+                    if !neighborhood.entities.contains_key(&entity_id) {
+                        next_frontier.push(entity_id);
+                    }
+                    neighborhood
+                        .entities
+                        .insert(entity_id, signature.to_string());
+                }
+                neighborhood.events.push(event.clone());
+            }
+            frontier = next_frontier;
+        }
+        neighborhood.events.sort_by_key(|event| event.timestamp);
+        neighborhood
+    }
+}
+
+/// The subject and the object of `event`, once if they are the same entity.
+fn endpoints(event: &InputEvent) -> impl Iterator<Item = u64> {
+    let object = (event.object_id != event.subject_id).then_some(event.object_id);
+    std::iter::once(event.subject_id).chain(object)
+}
+
+/// A subgraph of the [`RecentGraph`] around a match.
+#[derive(Debug, Default, Clone)]
+pub struct Neighborhood {
+    /// The signature of each entity, which is empty for a matched entity without any event in the
+    /// recent graph.
+    pub entities: BTreeMap<u64, String>,
+    /// The events ordered by their timestamps.
+    pub events: Vec<Rc<InputEvent>>,
+    /// The ids of the matched input events, which are highlighted in the output.
+    pub matched: HashSet<u64>,
+}
+
+impl Neighborhood {
+    /// The subgraph as a json object, e.g.
+    ///
+    /// ```json
+    /// {"entities": [{"id": 1, "signature": "proc::a"}, {"id": 2, "signature": "file::b"}],
+    ///  "events": [{"id": 5, "timestamp": 1000, "signature": "read", "subject_id": 1,
+    ///              "object_id": 2, "matched": true}]}
+    /// ```
+    pub fn to_json(&self) -> Value {
+        let entities: Vec<Value> = (self.entities.iter())
+            .map(|(id, signature)| json!({ "id": id, "signature": signature }))
+            .collect();
+        let events: Vec<Value> = (self.events.iter())
+            .map(|event| {
+                json!({
+                    "id": event.event_id,
+                    "timestamp": event.timestamp,
+                    "signature": event.get_event_signature(),
+                    "subject_id": event.subject_id,
+                    "object_id": event.object_id,
+                    "matched": self.matched.contains(&event.event_id),
+                })
+            })
+            .collect();
+        json!({ "entities": entities, "events": events })
+    }
+
+    /// The subgraph in the DOT language of Graphviz, where the matched events are in bold red.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph neighborhood {\n");
+        for (id, signature) in &self.entities {
+            dot += &format!("  {id} [label={signature:?}];\n");
+        }
+        for event in &self.events {
+            let label = format!("{}@{}", event.get_event_signature(), event.timestamp);
+            let style = if self.matched.contains(&event.event_id) {
+                ", style=bold, color=red"
+            } else {
+                ""
+            };
+            dot += &format!(
+                "  {} -> {} [label={label:?}{style}];\n",
+                event.subject_id, event.object_id
+            );
+        }
+        dot += "}\n";
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::match_event::{MatchEvent, RawEvents};
+
+    fn event(timestamp: u64, id: u64, subject_id: u64, object_id: u64) -> Rc<InputEvent> {
+        let subject = format!("e{subject_id}");
+        let object = format!("e{object_id}");
+        Rc::new(InputEvent::new(
+            timestamp, id, "read", subject_id, &subject, object_id, &object,
+        ))
+    }
+
+    fn pattern_match(input_event: &Rc<InputEvent>) -> PatternMatch {
+        let match_event = MatchEvent {
+            match_id: 0,
+            input_subject_id: input_event.subject_id,
+            input_object_id: input_event.object_id,
+            pattern_subject_id: 0,
+            pattern_object_id: 1,
+            raw_events: RawEvents::Single(input_event.clone()),
+        };
+        let timestamp = input_event.timestamp;
+        let mut pattern_match =
+            PatternMatch::for_test(timestamp, timestamp, &[input_event.event_id]);
+        pattern_match.match_event_map = Box::new([Some(Rc::new(match_event))]);
+        pattern_match
+    }
+
+    #[test]
+    fn test_neighborhood() {
+        // a chain 1 -> 2 -> 3 -> 4, and 5 -> 2 long before
+        let graph = RecentGraph::new(100, 1000);
+        let matched = event(1000, 11, 1, 2);
+        graph.add_batch(&[event(850, 10, 5, 2)]);
+        graph.add_batch(std::slice::from_ref(&matched));
+        graph.add_batch(&[event(1010, 12, 2, 3), event(1010, 13, 3, 3)]);
+        graph.add_batch(&[event(1020, 14, 3, 4)]);
+
+        let neighborhood = graph.neighborhood(&pattern_match(&matched), 1);
+        let event_ids: Vec<u64> = neighborhood.events.iter().map(|e| e.event_id).collect();
+        assert_eq!(event_ids, [11, 12]);
+        let entities: Vec<u64> = neighborhood.entities.keys().copied().collect();
+        assert_eq!(entities, [1, 2, 3]);
+
+        let neighborhood = graph.neighborhood(&pattern_match(&matched), 2);
+        let event_ids: Vec<u64> = neighborhood.events.iter().map(|e| e.event_id).collect();
+        assert_eq!(event_ids, [11, 12, 13, 14]);
+
+        let json = neighborhood.to_json();
+        assert_eq!(json["entities"][3], json!({ "id": 4, "signature": "e4" }));
+        assert_eq!(json["events"][0]["matched"], json!(true));
+        assert_eq!(json["events"][1]["matched"], json!(false));
+        let dot = neighborhood.to_dot();
+        assert!(dot.starts_with("digraph neighborhood {\n  1 [label=\"e1\"];\n"));
+        assert!(dot.contains("  1 -> 2 [label=\"read@1000\", style=bold, color=red];\n"));
+        assert!(dot.contains("  3 -> 3 [label=\"read@1010\"];\n"));
+    }
+
+    #[test]
+    fn test_bounded() {
+        let graph = RecentGraph::new(100, 3);
+        graph.add_batch(&[event(0, 0, 1, 2), event(0, 1, 1, 1)]);
+        graph.add_batch(&[event(150, 2, 1, 2)]);
+        assert_eq!(graph.len(), 3);
+        // more than two windows after the first batch
+        graph.add_batch(&[event(201, 3, 2, 3)]);
+        assert_eq!(graph.len(), 2);
+        graph.add_batch(&[event(210, 4, 3, 4), event(210, 5, 4, 5)]);
+        assert_eq!(graph.len(), 3);
+
+        let inner = graph.0.borrow();
+        assert!(!inner.adjacency.contains_key(&1));
+        assert_eq!(inner.adjacency[&2].len(), 1);
+        assert_eq!(inner.adjacency[&4].len(), 2);
+    }
+}
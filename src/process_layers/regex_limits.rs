@@ -0,0 +1,66 @@
+//! Signatures come from user-supplied pattern files and are matched against attacker-influenced
+//! event strings, so the regexes built from them are bounded in size and nesting. The regex crate
+//! already matches in linear time, but a small signature such as `(a{100}){100}` may still compile
+//! into a huge automaton.
+use regex::{Error, Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+
+/// The maximum compiled size (in bytes) of the regex of a single signature.
+pub const SIZE_LIMIT: usize = 1 << 20;
+/// The maximum size (in bytes) of the lazy DFA cache of each regex.
+pub const DFA_SIZE_LIMIT: usize = 2 << 20;
+/// The maximum nesting depth (of groups and repetitions) of a signature.
+pub const NEST_LIMIT: u32 = 32;
+
+/// Compile `pattern` within the limits. A rejected pattern is reported along with its source.
+pub fn build_regex(pattern: &str) -> Result<Regex, Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(SIZE_LIMIT)
+        .dfa_size_limit(DFA_SIZE_LIMIT)
+        .nest_limit(NEST_LIMIT)
+        .build()
+        .map_err(|err| rejected(pattern, err))
+}
+
+/// Compile `patterns` into a set, where each of them is within the limits.
+pub fn build_regex_set(patterns: &[String]) -> Result<RegexSet, Error> {
+    // check the patterns one by one first, so that the rejected one is reported
+    for pattern in patterns {
+        build_regex(pattern)?;
+    }
+    RegexSetBuilder::new(patterns)
+        .size_limit(SIZE_LIMIT.saturating_mul(patterns.len().max(1)))
+        .dfa_size_limit(DFA_SIZE_LIMIT)
+        .nest_limit(NEST_LIMIT)
+        .build()
+}
+
+fn rejected(pattern: &str, err: Error) -> Error {
+    let reason = match err {
+        Error::CompiledTooBig(limit) => {
+            format!("the compiled regex exceeds the size limit of {limit} bytes")
+        }
+        err => err.to_string(),
+    };
+    Error::Syntax(format!(
+        "rejected signature {:?}: {}",
+        pattern.replace('\0', " "),
+        reason
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex_limits() {
+        assert!(build_regex("^Process::name::.*$").is_ok());
+        assert!(build_regex_set(&["^a.*$".to_string(), "^b$".to_string()]).is_ok());
+
+        let err = build_regex("^((a{100}){100}){100}$").unwrap_err();
+        assert!(err.to_string().contains("size limit"), "{err}");
+        let nested = format!("{}a{}", "(".repeat(40), ")".repeat(40));
+        let err = build_regex_set(&["^b$".to_string(), nested]).unwrap_err();
+        assert!(err.to_string().contains("rejected signature"), "{err}");
+    }
+}
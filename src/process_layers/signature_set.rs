@@ -0,0 +1,218 @@
+//! The event signatures of several patterns in one [`RegexSet`], so that an input event is matched
+//! against all the patterns at once.
+use crate::pattern::{Pattern, PatternEventType};
+use crate::process_layers::composition_layer::construct_regex_patterns;
+use crate::process_layers::regex_limits::{build_regex, build_regex_set};
+use ahash::HashMap;
+use itertools::Itertools;
+use regex::{Error, RegexSet};
+
+/// The (index of the pattern, pattern event id) pair a signature comes from.
+pub type SignatureOwner = (usize, usize);
+
+/// The signatures of the (non-flow, non-static) events of several patterns, compiled into a single
+/// [`RegexSet`]. Identical signatures are compiled once, and each match of the set is mapped back
+/// to the pattern events sharing the signature.
+///
+/// The patterns are isolated from each other: a pattern with a rejected signature (see
+/// [`regex_limits`](super::regex_limits)) is left out as a whole, and the other patterns are
+/// still loaded.
+#[derive(Debug, Clone)]
+pub struct SignatureSet {
+    regexes: RegexSet,
+    /// `owners[i]` are the pattern events whose signature is the `i`-th regex of the set.
+    owners: Vec<Vec<SignatureOwner>>,
+    /// The patterns left out, along with the error of their first rejected signature.
+    rejected: Vec<(usize, Error)>,
+}
+
+impl SignatureSet {
+    /// Compile the signatures of `patterns`, which are referred to by their indices in
+    /// `patterns`. Only fails if the whole set exceeds the limits.
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a Pattern>) -> Result<Self, Error> {
+        let mut regex_patterns: Vec<String> = vec![];
+        let mut owners: Vec<Vec<SignatureOwner>> = vec![];
+        let mut regex_ids: HashMap<String, usize> = HashMap::default();
+        let mut rejected = vec![];
+        for (pattern_idx, pattern) in patterns.into_iter().enumerate() {
+            let signatures: Vec<_> = pattern
+                .events
+                .iter()
+                .filter(|event| !matches!(event.event_type, PatternEventType::Flow))
+                .filter(|event| !event.is_static)
+                .flat_map(|event| {
+                    construct_regex_patterns(event, !pattern.use_regex)
+                        .into_iter()
+                        .map(|signature| (event.id, signature))
+                })
+                .collect();
+            let error = signatures
+                .iter()
+                .filter(|(_, signature)| !regex_ids.contains_key(signature))
+                .find_map(|(_, signature)| build_regex(signature).err());
+            if let Some(error) = error {
+                rejected.push((pattern_idx, error));
+                continue;
+            }
+
+            for (event_id, signature) in signatures {
+                let regex_id = *regex_ids.entry(signature).or_insert_with_key(|signature| {
+                    regex_patterns.push(signature.clone());
+                    owners.push(vec![]);
+                    owners.len() - 1
+                });
+                owners[regex_id].push((pattern_idx, event_id));
+            }
+        }
+
+        Ok(Self {
+            regexes: build_regex_set(&regex_patterns)?,
+            owners,
+            rejected,
+        })
+    }
+
+    /// The patterns left out, by their indices, along with the errors.
+    pub fn rejected(&self) -> &[(usize, Error)] {
+        &self.rejected
+    }
+
+    /// The number of distinct signatures.
+    pub fn len(&self) -> usize {
+        self.owners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
+
+    /// The pattern events whose signature is the `index`-th one.
+    pub fn owners(&self, index: usize) -> &[SignatureOwner] {
+        &self.owners[index]
+    }
+
+    /// Whether an event with these signatures matches any of the pattern events.
+    pub fn is_match(&self, event_sig: &str, subject_sig: &str, object_sig: &str) -> bool {
+        let signatures = format!("{event_sig}\0{subject_sig}\0{object_sig}");
+        self.regexes.is_match(&signatures)
+    }
+
+    /// Whether an event with these signatures matches any event of the `pattern_idx`-th pattern.
+    pub fn is_match_of(
+        &self,
+        pattern_idx: usize,
+        event_sig: &str,
+        subject_sig: &str,
+        object_sig: &str,
+    ) -> bool {
+        let signatures = format!("{event_sig}\0{subject_sig}\0{object_sig}");
+        self.regexes.matches(&signatures).into_iter().any(|index| {
+            self.owners[index]
+                .iter()
+                .any(|(owner_idx, _)| *owner_idx == pattern_idx)
+        })
+    }
+
+    /// The pattern events an event with these signatures matches, each listed once even if it
+    /// matches several alternatives of a signature.
+    pub fn matches(
+        &self,
+        event_sig: &str,
+        subject_sig: &str,
+        object_sig: &str,
+    ) -> Vec<SignatureOwner> {
+        let signatures = format!("{event_sig}\0{subject_sig}\0{object_sig}");
+        self.regexes
+            .matches(&signatures)
+            .into_iter()
+            .flat_map(|index| self.owners[index].iter().copied())
+            .unique()
+            .collect()
+    }
+
+    /// The signatures (by index) shared by more than one pattern, along with their owners, i.e.
+    /// the matching work saved by deduplication across the patterns.
+    pub fn shared_signatures(&self) -> impl Iterator<Item = (usize, &[SignatureOwner])> {
+        self.owners
+            .iter()
+            .enumerate()
+            .filter(|(_, owners)| {
+                owners
+                    .iter()
+                    .any(|(pattern_idx, _)| *pattern_idx != owners[0].0)
+            })
+            .map(|(index, owners)| (index, owners.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(signatures: &[&str]) -> Pattern {
+        let events: Vec<_> = signatures
+            .iter()
+            .enumerate()
+            .map(|(id, signature)| {
+                serde_json::json!({
+                    "ID": id, "Signature": signature, "SubjectID": 0, "ObjectID": 1
+                })
+            })
+            .collect();
+        let pattern = serde_json::json!({
+            "Version": "0.2.0",
+            "UseRegex": true,
+            "Entities": [
+                { "ID": 0, "Signature": "p" },
+                { "ID": 1, "Signature": "f" }
+            ],
+            "Events": events
+        });
+        Pattern::parse_str(&pattern.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_signature_set() {
+        let nested = format!("{}a{}", "(".repeat(40), ")".repeat(40));
+        let patterns = [
+            pattern(&["read", "write"]),
+            pattern(&["write", &nested]),
+            pattern(&["exec", "read|write"]),
+            pattern(&["write"]),
+        ];
+        let set = SignatureSet::new(&patterns).unwrap();
+
+        // the pattern with a rejected signature is left out, while the others are loaded
+        let rejected: Vec<_> = set.rejected().iter().map(|(idx, _)| *idx).collect();
+        assert_eq!(rejected, [1]);
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.matches("write", "p", "f"), [(0, 1), (3, 0), (2, 1)]);
+        assert!(set.is_match("exec", "p", "f"));
+        assert!(!set.is_match("exec", "f", "p"));
+        assert!(set.is_match_of(2, "exec", "p", "f"));
+        assert!(!set.is_match_of(0, "exec", "p", "f"));
+        assert!(!set.is_match_of(1, "write", "p", "f"));
+
+        let shared: Vec<_> = set.shared_signatures().collect();
+        assert_eq!(shared, [(1, &[(0, 1), (3, 0)][..])]);
+    }
+
+    #[test]
+    fn test_signature_alternatives() {
+        let pattern = Pattern::parse_str(
+            &serde_json::json!({
+                "Version": "0.2.0",
+                "Entities": [{ "ID": 0, "Signature": "p" }, { "ID": 1, "Signature": "f" }],
+                "Events": [
+                    { "ID": 0, "Signature": ["read", "pread64"], "SubjectID": 0, "ObjectID": 1 }
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let set = SignatureSet::new([&pattern]).unwrap();
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.matches("pread64", "p", "f"), [(0, 0)]);
+        assert!(!set.is_match("write", "p", "f"));
+    }
+}
@@ -1,47 +1,332 @@
-use crate::pattern_match::EarliestFirst;
 use crate::pattern_match::PatternMatch;
+use crate::process_layers::parse_layer::IdleState;
+use crate::process_layers::window_policy::{SlidingWindow, WindowPolicy};
+use crate::process_layers::EventTrace;
 use log::debug;
-use std::collections::{BinaryHeap, HashSet};
+use serde_json::json;
+use smallvec::SmallVec;
+use std::cmp::{Ordering, Reverse};
+use std::collections::binary_heap::PeekMut;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::rc::Rc;
 
 /// The layer that handles pattern match uniqueness.
 pub struct UniquenessLayer<P> {
     prev_layer: P,
     /// See `flush_expired()`.
-    window_size: u64,
+    window: Rc<dyn WindowPolicy>,
     /// A priority queue of pattern matches, where the earliest pattern match is at the top of the queue.
-    pattern_match_sequence: BinaryHeap<EarliestFirst>,
-    /// A pool which is used to maintain the uniqueness of pattern matches.
-    uniqueness_pool: HashSet<PatternMatch>,
+    pattern_match_sequence: BinaryHeap<PooledMatch>,
+    /// A pool which is used to maintain the uniqueness of pattern matches, where the matches are
+    /// keyed by [`MatchKey`], and those with the same key are told apart by their event ids.
+    uniqueness_pool: ahash::HashMap<MatchKey, SmallVec<[Rc<PatternMatch>; 1]>>,
     /// Unique pattern matches which are ready for the next layer.
     unique_matches: Vec<PatternMatch>,
+    /// See `set_ordered_output()`.
+    ordered_output: bool,
+    /// Unique pattern matches held until the watermark passes their `latest_time`, where the
+    /// match with the smallest `latest_time` is at the top of the queue.
+    ordered_matches: BinaryHeap<Reverse<LatestTimeOrder>>,
+    /// The window bound of the current stream time. No pattern match with `latest_time` less
+    /// than the watermark will come from the previous layer.
+    watermark: u64,
+    /// See `set_anchor_limit()`.
+    anchor_limit: Option<AnchorLimit>,
+    /// See `set_idle_state()`.
+    idle_state: Option<IdleState>,
+    /// See `set_event_trace()`.
+    event_trace: Option<EventTrace>,
+}
+
+/// Limits the number of output matches per input entity bound to the anchor pattern entity.
+struct AnchorLimit {
+    pattern_entity_id: u64,
+    max_matches: usize,
+    /// Number of matches emitted for each input entity bound to the anchor, and the
+    /// `latest_time` of the last of them.
+    counts: HashMap<u64, (usize, u64)>,
+    /// The `latest_time` and the binding of each counted match, in the order of output. See
+    /// `prune_anchor_counts()`.
+    expiry: VecDeque<(u64, u64)>,
+}
+
+/// The 128-bit polynomial rolling hash of the sorted `event_ids` of a match, computed in a single
+/// pass without allocation. It replaces hashing and cloning the whole match for deduplication,
+/// which dominates for patterns with hundreds of events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct MatchKey(u128);
+
+impl MatchKey {
+    /// An odd 128-bit multiplier, so that the hash is a bijection of the last event id.
+    const BASE: u128 = 0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c835;
+
+    fn new(pattern_match: &PatternMatch) -> Self {
+        let hash = pattern_match.event_ids.iter().fold(
+            0u128,
+            |hash, (input_event_id, pattern_event_id)| {
+                let id = (*input_event_id as u128) << 32 | *pattern_event_id as u128;
+                hash.wrapping_mul(Self::BASE).wrapping_add(id + 1)
+            },
+        );
+        Self(hash)
+    }
+}
+
+/// A match in the uniqueness pool, ordered such that the one with the smallest `earliest_time` is
+/// at the top of a `BinaryHeap`.
+struct PooledMatch {
+    key: MatchKey,
+    pattern_match: Rc<PatternMatch>,
+}
+
+impl Eq for PooledMatch {}
+
+impl PartialEq for PooledMatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern_match.earliest_time == other.pattern_match.earliest_time
+    }
+}
+
+impl Ord for PooledMatch {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let earliest_time = self.pattern_match.earliest_time;
+        earliest_time
+            .cmp(&other.pattern_match.earliest_time)
+            .reverse()
+    }
+}
+
+impl PartialOrd for PooledMatch {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Orders pattern matches by `latest_time`. Ties are broken by `earliest_time` and event ids to
+/// make the output deterministic.
+struct LatestTimeOrder(PatternMatch);
+
+impl LatestTimeOrder {
+    fn key(&self) -> (u64, u64, &[(u64, u32)]) {
+        (self.0.latest_time, self.0.earliest_time, &self.0.event_ids)
+    }
+}
+
+impl Eq for LatestTimeOrder {}
+
+impl PartialEq for LatestTimeOrder {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Ord for LatestTimeOrder {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl PartialOrd for LatestTimeOrder {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl<P> UniquenessLayer<P> {
     pub fn new(prev_layer: P, window_size: u64) -> Self {
         Self {
             prev_layer,
-            window_size,
+            window: Rc::new(SlidingWindow::new(window_size)),
             pattern_match_sequence: BinaryHeap::new(),
-            uniqueness_pool: HashSet::new(),
+            uniqueness_pool: ahash::HashMap::default(),
             unique_matches: Vec::new(),
+            ordered_output: false,
+            ordered_matches: BinaryHeap::new(),
+            watermark: 0,
+            anchor_limit: None,
+            idle_state: None,
+            event_trace: None,
         }
     }
-    /// Flush expired pattern matches.
-    fn flush_expired(&mut self, latest_time: u64) {
-        while let Some(pattern_match) = self.pattern_match_sequence.peek() {
-            if latest_time.saturating_sub(self.window_size) > pattern_match.0.earliest_time {
-                let item = self.pattern_match_sequence.pop().unwrap().0;
-                self.uniqueness_pool.remove(&item);
-                self.unique_matches.push(item);
-            } else {
+
+    pub fn prev_layer(&self) -> &P {
+        &self.prev_layer
+    }
+
+    pub fn prev_layer_mut(&mut self) -> &mut P {
+        &mut self.prev_layer
+    }
+
+    /// Replace the default sliding window of `window_size` given to `new()`.
+    pub fn set_window_policy(&mut self, window: Rc<dyn WindowPolicy>) {
+        self.window = window;
+    }
+
+    /// Emit pattern matches in the ascending order of their `latest_time`.
+    ///
+    /// In this mode, unique pattern matches are held until the watermark (the window bound of
+    /// the current stream time) passes their `latest_time`, so the output is delayed by about
+    /// one window.
+    pub fn set_ordered_output(&mut self, ordered_output: bool) {
+        self.ordered_output = ordered_output;
+    }
+
+    /// Emit at most `max_matches` matches for each distinct input entity bound to the pattern
+    /// entity `pattern_entity_id` (the anchor, e.g. the root process); later matches with the
+    /// same binding are dropped. The count of an input entity starts over once its last emitted
+    /// match is out of the window, so the limit applies to the matches within a window.
+    pub fn set_anchor_limit(&mut self, pattern_entity_id: u64, max_matches: usize) {
+        self.anchor_limit = Some(AnchorLimit {
+            pattern_entity_id,
+            max_matches,
+            counts: HashMap::new(),
+            expiry: VecDeque::new(),
+        });
+    }
+
+    /// When the previous layer runs out of input while `idle_state` is idle, only flush the
+    /// pattern matches expired at the estimated stream time, instead of all of them. See
+    /// [`ParseLayer::set_idle_probe`](super::ParseLayer::set_idle_probe).
+    pub fn set_idle_state(&mut self, idle_state: IdleState) {
+        self.idle_state = Some(idle_state);
+    }
+
+    /// Trace the matches containing the events in `event_trace` as they are dropped or output.
+    pub fn set_event_trace(&mut self, event_trace: EventTrace) {
+        self.event_trace = Some(event_trace);
+    }
+
+    /// Record `decision` on `pattern_match` for its traced events.
+    fn trace(&self, pattern_match: &PatternMatch, decision: &str) {
+        if let Some(event_trace) = &self.event_trace {
+            let ids = pattern_match.event_ids.iter().map(|(id, _)| *id);
+            let detail = || json!({ "event_ids": ids.clone().collect::<Vec<_>>() });
+            event_trace.record(ids.clone(), "uniqueness", decision, detail);
+        }
+    }
+
+    /// Returns `true` if `pattern_match` is over the anchor limit, otherwise count it.
+    fn exceeds_anchor_limit(&mut self, pattern_match: &PatternMatch) -> bool {
+        let window_bound = self.window.window_bound(pattern_match.latest_time);
+        let Some(limit) = &mut self.anchor_limit else {
+            return false;
+        };
+        let Some(binding) = pattern_match.entity_binding(limit.pattern_entity_id) else {
+            return false;
+        };
+        let (count, last_time) = limit.counts.entry(binding).or_default();
+        if *last_time < window_bound {
+            // the counted matches are out of the window of this one
+            *count = 0;
+        }
+        if *count >= limit.max_matches {
+            debug!(
+                "drop pattern match over the anchor limit: {}",
+                pattern_match
+            );
+            return true;
+        }
+        *count += 1;
+        *last_time = (*last_time).max(pattern_match.latest_time);
+        limit.expiry.push_back((*last_time, binding));
+        false
+    }
+
+    /// Remove the anchor counts whose last match is out of the window of the watermark.
+    ///
+    /// Only called when no match is ready for output, so that every match to come has its
+    /// `latest_time` at or after the watermark, and would have started the count over anyway.
+    fn prune_anchor_counts(&mut self) {
+        let window_bound = self.window.window_bound(self.watermark);
+        let Some(limit) = &mut self.anchor_limit else {
+            return;
+        };
+        while let Some(&(time, binding)) = limit.expiry.front() {
+            if time >= window_bound {
                 break;
             }
+            limit.expiry.pop_front();
+            // skip the stale entries of the bindings counted again later
+            if limit.counts.get(&binding).is_some_and(|&(_, last)| last == time) {
+                limit.counts.remove(&binding);
+            }
+        }
+    }
+
+    /// Flush the pattern matches whose `earliest_time` is before `window_bound`.
+    fn flush_expired(&mut self, window_bound: u64) {
+        loop {
+            let Some(oldest) = self.pattern_match_sequence.peek_mut() else {
+                break;
+            };
+            if window_bound <= oldest.pattern_match.earliest_time {
+                break;
+            }
+            let item = PeekMut::pop(oldest);
+            self.remove_from_pool(&item);
+            let item = Rc::try_unwrap(item.pattern_match).unwrap_or_else(|item| (*item).clone());
+            if self.ordered_output {
+                self.ordered_matches.push(Reverse(LatestTimeOrder(item)));
+            } else {
+                self.unique_matches.push(item);
+            }
         }
         debug!(
             "After flushing: {} pattern matches",
             self.uniqueness_pool.len()
         );
     }
+
+    /// Add `pattern_match` to the pool, unless it duplicates a pooled one.
+    fn insert_unique(&mut self, pattern_match: PatternMatch) {
+        self.insert_with_key(MatchKey::new(&pattern_match), pattern_match);
+    }
+
+    fn insert_with_key(&mut self, key: MatchKey, pattern_match: PatternMatch) {
+        let pooled = self.uniqueness_pool.entry(key).or_default();
+        // the full comparison is only needed on a hash collision or a duplicate
+        if pooled
+            .iter()
+            .any(|other| other.event_ids == pattern_match.event_ids)
+        {
+            self.trace(&pattern_match, "duplicate");
+            return;
+        }
+        let pattern_match = Rc::new(pattern_match);
+        pooled.push(pattern_match.clone());
+        self.pattern_match_sequence
+            .push(PooledMatch { key, pattern_match });
+    }
+
+    fn remove_from_pool(&mut self, item: &PooledMatch) {
+        if let Some(pooled) = self.uniqueness_pool.get_mut(&item.key) {
+            pooled.retain(|other| !Rc::ptr_eq(other, &item.pattern_match));
+            if pooled.is_empty() {
+                self.uniqueness_pool.remove(&item.key);
+            }
+        }
+    }
+
+    /// Returns `true` if there are pattern matches ready for the next layer.
+    fn has_output(&self) -> bool {
+        if self.ordered_output {
+            self.ordered_matches
+                .peek()
+                .is_some_and(|Reverse(item)| item.0.latest_time < self.watermark)
+        } else {
+            !self.unique_matches.is_empty()
+        }
+    }
+
+    fn pop_output(&mut self) -> Option<PatternMatch> {
+        if !self.ordered_output {
+            self.unique_matches.pop()
+        } else if self.has_output() {
+            self.ordered_matches.pop().map(|Reverse(item)| item.0)
+        } else {
+            None
+        }
+    }
 }
 
 impl<P> Iterator for UniquenessLayer<P>
@@ -50,24 +335,167 @@ where
 {
     type Item = PatternMatch;
     fn next(&mut self) -> Option<Self::Item> {
-        while self.unique_matches.is_empty() {
+        loop {
+            let output = self.next_unique()?;
+            if self.exceeds_anchor_limit(&output) {
+                self.trace(&output, "anchor limit");
+                continue;
+            }
+            self.trace(&output, "emit");
+            #[cfg(feature = "tracing")]
+            crate::trace::trace_event!(
+                DEBUG,
+                earliest_time = output.earliest_time,
+                latest_time = output.latest_time,
+                "emit match"
+            );
+            return Some(output);
+        }
+    }
+}
+
+impl<P> UniquenessLayer<P>
+where
+    P: Iterator<Item = PatternMatch>,
+{
+    fn next_unique(&mut self) -> Option<PatternMatch> {
+        while !self.has_output() {
             debug!("no instance available yet");
+            self.prune_anchor_counts();
             if let Some(pattern_match) = self.prev_layer.next() {
                 debug!("Got pattern match: {:?}", pattern_match);
-                let latest_time = pattern_match.latest_time;
-                self.flush_expired(latest_time);
-                if !self.uniqueness_pool.contains(&pattern_match) {
-                    self.uniqueness_pool.insert(pattern_match.clone());
-                    self.pattern_match_sequence
-                        .push(EarliestFirst(pattern_match));
-                }
+                let window_bound = self.window.window_bound(pattern_match.latest_time);
+                self.flush_expired(window_bound);
+                self.watermark = self.watermark.max(window_bound);
+                self.insert_unique(pattern_match);
                 debug!("size of uniqueness_pool: {}", self.uniqueness_pool.len());
+            } else if let Some(time) = self.idle_state.as_ref().and_then(IdleState::get) {
+                debug!("prev layer is idle, flush until {}", time);
+                let window_bound = self.window.window_bound(time);
+                self.flush_expired(window_bound);
+                self.watermark = self.watermark.max(window_bound);
+                break;
             } else {
                 debug!("prev layer no stuff, flush all");
                 self.flush_expired(u64::MAX);
+                self.watermark = u64::MAX;
                 break;
             }
         }
-        self.unique_matches.pop()
+        self.pop_output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A match at time `id`, where pattern entity 0 is bound to the input entity `root`.
+    fn with_root(id: u64, root: u64) -> PatternMatch {
+        PatternMatch::for_test(id, id, &[id]).with_flow(root, 100 + id)
+    }
+
+    #[test]
+    fn test_ordered_output() {
+        let input = vec![
+            PatternMatch::for_test(1, 5, &[1]),
+            PatternMatch::for_test(2, 3, &[2]),
+            PatternMatch::for_test(2, 3, &[2]), // duplicate
+            PatternMatch::for_test(4, 4, &[3]),
+            PatternMatch::for_test(15, 20, &[4]),
+            PatternMatch::for_test(14, 16, &[5]),
+        ];
+        let mut layer = UniquenessLayer::new(input.into_iter(), 10);
+        layer.set_ordered_output(true);
+
+        let latest_times: Vec<u64> = layer.map(|m| m.latest_time).collect();
+        assert_eq!(latest_times, [3, 4, 5, 16, 20]);
+    }
+
+    #[test]
+    fn test_key_collision() {
+        let mut layer = UniquenessLayer::new(std::iter::empty::<PatternMatch>(), 10);
+        assert_ne!(
+            MatchKey::new(&PatternMatch::for_test(1, 1, &[1])),
+            MatchKey::new(&PatternMatch::for_test(1, 1, &[2]))
+        );
+        // distinct matches with the same key are kept apart by their event ids
+        let key = MatchKey(0);
+        layer.insert_with_key(key, PatternMatch::for_test(1, 1, &[1]));
+        layer.insert_with_key(key, PatternMatch::for_test(2, 2, &[2]));
+        layer.insert_with_key(key, PatternMatch::for_test(3, 3, &[1])); // duplicate
+        assert_eq!(layer.uniqueness_pool[&key].len(), 2);
+
+        layer.flush_expired(u64::MAX);
+        assert!(layer.uniqueness_pool.is_empty());
+        let mut ids: Vec<u64> = layer
+            .unique_matches
+            .iter()
+            .map(|m| m.event_ids[0].0)
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [1, 2]);
+    }
+
+    #[test]
+    fn test_anchor_limit() {
+        let input = vec![
+            with_root(1, 7),
+            with_root(2, 7),
+            with_root(3, 8),
+            with_root(4, 7),
+            with_root(5, 8),
+        ];
+        let mut layer = UniquenessLayer::new(input.into_iter(), 10);
+        layer.set_ordered_output(true);
+        layer.set_anchor_limit(0, 1);
+
+        let ids: Vec<u64> = layer.map(|m| m.event_ids[0].0).collect();
+        assert_eq!(ids, [1, 3]);
+    }
+
+    #[test]
+    fn test_anchor_limit_window() {
+        let input = vec![
+            with_root(1, 7),
+            with_root(2, 7),
+            with_root(3, 8),
+            with_root(20, 7),
+            with_root(21, 7),
+            with_root(40, 9),
+        ];
+        let mut layer = UniquenessLayer::new(input.into_iter(), 10);
+        layer.set_ordered_output(true);
+        layer.set_anchor_limit(0, 1);
+
+        // root 7 is matched again once its first match is out of the window
+        let ids: Vec<u64> = layer.by_ref().map(|m| m.event_ids[0].0).collect();
+        assert_eq!(ids, [1, 3, 20, 40]);
+
+        let limit = layer.anchor_limit.as_ref().unwrap();
+        assert!(limit.counts.is_empty());
+        assert!(limit.expiry.is_empty());
+    }
+
+    #[test]
+    fn test_idle_flush() {
+        let idle_state = IdleState::default();
+        let input = vec![
+            PatternMatch::for_test(1, 5, &[1]),
+            PatternMatch::for_test(2, 3, &[2]),
+        ];
+        let mut layer = UniquenessLayer::new(input.into_iter(), 10);
+        layer.set_idle_state(idle_state.clone());
+
+        // not expired yet at time 11
+        idle_state.set(Some(11));
+        assert!(layer.next().is_none());
+
+        idle_state.set(Some(12));
+        assert_eq!(layer.next().map(|m| m.earliest_time), Some(1));
+        assert!(layer.next().is_none());
+
+        idle_state.set(None);
+        assert_eq!(layer.next().map(|m| m.earliest_time), Some(2));
     }
 }
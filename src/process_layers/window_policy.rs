@@ -0,0 +1,378 @@
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::fmt::Debug;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Decides which partial and complete matches have expired. A match is alive at `time` if its
+/// earliest event is at or after `window_bound(time)`, and expires once its earliest event is
+/// before the bound. All the layers (and the flow tracer) compare against the bound this way, so
+/// the boundary semantics is decided only by the policy, see [`WindowBoundary`].
+///
+/// A policy is shared by all the layers of a pipeline (see [`WindowKind::build`]). A stateful
+/// policy, i.e. a session window, is only advanced by the Join layer, see
+/// [`WindowPolicy::advance`]; the other layers only read the bound, which may lag behind but never
+/// expires a match the Join layer would keep.
+pub trait WindowPolicy: Debug {
+    /// The earliest timestamp of the events that can still be in the same window as an event at
+    /// `time`. It never exceeds `time`, and does not change the state of the policy.
+    fn window_bound(&self, time: u64) -> u64;
+
+    /// Advance the state of the policy with an event at `time`, called with non-decreasing time
+    /// up to an event that arrives late. Stateless policies ignore it.
+    fn advance(&self, time: u64) {
+        let _ = time;
+    }
+
+    /// A lower bound of `window_bound(time + delay)`, computed without advancing the policy, e.g.
+    /// to tell that a partial match can not complete in time. The default is `window_bound(time)`.
+    fn window_bound_after(&self, time: u64, delay: u64) -> u64 {
+        let _ = delay;
+        self.window_bound(time)
+    }
+}
+
+/// The windowing semantics of a pattern, given by `Window` in the pattern file, e.g.
+/// `{"Type": "Session", "GapMs": 60000}`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(tag = "Type")]
+pub enum WindowKind {
+    /// All events of a match are within one window size.
+    #[default]
+    Sliding,
+    /// The stream time is divided into consecutive windows of the window size, and all events of
+    /// a match are in the same window.
+    Tumbling,
+    /// A session ends when no event of the sub-pattern matches arrives for `gap` ms (the window
+    /// size by default), and all events of a match are in the same session.
+    Session {
+        #[serde(rename = "GapMs")]
+        gap: Option<u64>,
+    },
+}
+
+impl WindowKind {
+    pub fn build(self, window_size: u64, boundary: WindowBoundary) -> Rc<dyn WindowPolicy> {
+        match self {
+            WindowKind::Sliding => Rc::new(SlidingWindow {
+                size: window_size,
+                boundary,
+            }),
+            WindowKind::Tumbling => Rc::new(TumblingWindow(window_size)),
+            WindowKind::Session { gap } => {
+                Rc::new(SessionWindow::new(gap.unwrap_or(window_size), boundary))
+            }
+        }
+    }
+
+    /// Like [`WindowKind::build`], but the window size follows `window_size` while the pipeline
+    /// runs. A new size applies from the next time the bound is asked for:
+    ///
+    /// - the partial matches still alive are kept, and those starting before the new bound expire
+    ///   as usual, so shrinking the window expires them at the next batch, while growing it can
+    ///   not revive the ones already expired;
+    /// - tumbling windows are realigned to the multiples of the new size;
+    /// - a session with the default gap continues with the new gap, while a `GapMs` given by the
+    ///   pattern is never resized.
+    pub fn build_resizable(
+        self,
+        window_size: WindowSize,
+        boundary: WindowBoundary,
+    ) -> Rc<dyn WindowPolicy> {
+        let size = window_size.get();
+        let inner = match self {
+            WindowKind::Sliding => ResizableInner::Sliding(SlidingWindow { size, boundary }),
+            WindowKind::Tumbling => ResizableInner::Tumbling(TumblingWindow(size)),
+            WindowKind::Session { gap } => ResizableInner::Session(
+                SessionWindow::new(gap.unwrap_or(size), boundary),
+                gap.is_none(),
+            ),
+        };
+        Rc::new(ResizableWindow {
+            window_size,
+            inner: RefCell::new(inner),
+        })
+    }
+}
+
+/// A window size (in milliseconds) shared between the window policy of a pipeline and whoever
+/// resizes it at runtime, possibly from another thread, see [`WindowKind::build_resizable`].
+#[derive(Debug, Clone)]
+pub struct WindowSize(Arc<AtomicU64>);
+
+impl WindowSize {
+    pub fn new(window_size: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(window_size)))
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, window_size: u64) {
+        self.0.store(window_size, Ordering::Relaxed);
+    }
+}
+
+/// Whether two events exactly a window size (or a session gap) apart are in the same window,
+/// given by `Boundary` in the `Window` section of the pattern file. Tumbling windows are always
+/// half-open, i.e. `[start, start + window size)`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize)]
+pub enum WindowBoundary {
+    /// Events at `t` and `t + window size` are in the same window.
+    #[default]
+    Inclusive,
+    /// Events at `t` and `t + window size` are not in the same window.
+    Exclusive,
+}
+
+/// See [`WindowKind::Sliding`].
+#[derive(Debug)]
+pub struct SlidingWindow {
+    pub size: u64,
+    pub boundary: WindowBoundary,
+}
+
+impl SlidingWindow {
+    /// A sliding window with inclusive boundary.
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            boundary: WindowBoundary::Inclusive,
+        }
+    }
+}
+
+impl WindowPolicy for SlidingWindow {
+    fn window_bound(&self, time: u64) -> u64 {
+        match self.boundary {
+            WindowBoundary::Inclusive => time.saturating_sub(self.size),
+            // an event is always in the same window as itself, even if the window size is 0
+            WindowBoundary::Exclusive => time.saturating_add(1).saturating_sub(self.size).min(time),
+        }
+    }
+
+    fn window_bound_after(&self, time: u64, delay: u64) -> u64 {
+        self.window_bound(time.saturating_add(delay))
+    }
+}
+
+/// See [`WindowKind::Tumbling`].
+#[derive(Debug)]
+pub struct TumblingWindow(pub u64);
+
+impl WindowPolicy for TumblingWindow {
+    fn window_bound(&self, time: u64) -> u64 {
+        if self.0 == 0 {
+            time
+        } else {
+            time - time % self.0
+        }
+    }
+
+    fn window_bound_after(&self, time: u64, delay: u64) -> u64 {
+        self.window_bound(time.saturating_add(delay))
+    }
+}
+
+/// See [`WindowKind::Session`]. The sessions are made of the events of the sub-pattern matches
+/// given to [`WindowPolicy::advance`] by the Join layer.
+#[derive(Debug)]
+pub struct SessionWindow {
+    gap: u64,
+    boundary: WindowBoundary,
+    /// The start of the current session.
+    start: Cell<u64>,
+    /// The latest time seen so far.
+    last: Cell<Option<u64>>,
+}
+
+impl SessionWindow {
+    pub fn new(gap: u64, boundary: WindowBoundary) -> Self {
+        Self {
+            gap,
+            boundary,
+            start: Cell::new(0),
+            last: Cell::new(None),
+        }
+    }
+}
+
+impl SessionWindow {
+    fn is_within_gap(&self, elapsed: u64) -> bool {
+        match self.boundary {
+            WindowBoundary::Inclusive => elapsed <= self.gap,
+            WindowBoundary::Exclusive => elapsed < self.gap,
+        }
+    }
+}
+
+impl WindowPolicy for SessionWindow {
+    fn window_bound(&self, time: u64) -> u64 {
+        self.start.get().min(time)
+    }
+
+    fn advance(&self, time: u64) {
+        match self.last.get() {
+            Some(last) if time <= last => return,
+            Some(last) if self.is_within_gap(time - last) => {}
+            _ => self.start.set(time),
+        }
+        self.last.set(Some(time));
+    }
+}
+
+/// See [`WindowKind::build_resizable`].
+#[derive(Debug)]
+struct ResizableWindow {
+    window_size: WindowSize,
+    inner: RefCell<ResizableInner>,
+}
+
+#[derive(Debug)]
+enum ResizableInner {
+    Sliding(SlidingWindow),
+    Tumbling(TumblingWindow),
+    /// A session window, and whether its gap follows the window size.
+    Session(SessionWindow, bool),
+}
+
+impl ResizableWindow {
+    /// Apply the current window size, then call `f` on the inner policy.
+    fn with_policy<T>(&self, f: impl FnOnce(&dyn WindowPolicy) -> T) -> T {
+        let size = self.window_size.get();
+        let mut inner = self.inner.borrow_mut();
+        match &mut *inner {
+            ResizableInner::Sliding(window) => {
+                window.size = size;
+                f(window)
+            }
+            ResizableInner::Tumbling(window) => {
+                window.0 = size;
+                f(window)
+            }
+            ResizableInner::Session(window, follows_size) => {
+                if *follows_size {
+                    window.gap = size;
+                }
+                f(window)
+            }
+        }
+    }
+}
+
+impl WindowPolicy for ResizableWindow {
+    fn window_bound(&self, time: u64) -> u64 {
+        self.with_policy(|window| window.window_bound(time))
+    }
+
+    fn window_bound_after(&self, time: u64, delay: u64) -> u64 {
+        self.with_policy(|window| window.window_bound_after(time, delay))
+    }
+
+    fn advance(&self, time: u64) {
+        self.with_policy(|window| window.advance(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Advance `window` to `time` and return the bound, as the Join layer does.
+    fn advance_bound(window: &Rc<dyn WindowPolicy>, time: u64) -> u64 {
+        window.advance(time);
+        window.window_bound(time)
+    }
+
+    #[test]
+    fn test_window_bound() {
+        let sliding = WindowKind::Sliding.build(10, WindowBoundary::Inclusive);
+        assert_eq!(sliding.window_bound(5), 0);
+        assert_eq!(sliding.window_bound(25), 15);
+
+        let tumbling = WindowKind::Tumbling.build(10, WindowBoundary::Inclusive);
+        assert_eq!(tumbling.window_bound(5), 0);
+        assert_eq!(tumbling.window_bound(25), 20);
+
+        let session = WindowKind::Session { gap: Some(10) }.build(100, WindowBoundary::Inclusive);
+        assert_eq!(advance_bound(&session, 5), 5);
+        assert_eq!(advance_bound(&session, 12), 5);
+        assert_eq!(advance_bound(&session, 20), 5);
+        assert_eq!(advance_bound(&session, 3), 3); // late arrival
+        assert_eq!(advance_bound(&session, 31), 31);
+        // reading the bound does not advance the session
+        assert_eq!(session.window_bound(50), 31);
+        assert_eq!(advance_bound(&session, 40), 31);
+    }
+
+    #[test]
+    fn test_window_bound_after() {
+        let sliding = WindowKind::Sliding.build(10, WindowBoundary::Inclusive);
+        assert_eq!(sliding.window_bound_after(25, 5), 20);
+        assert_eq!(sliding.window_bound_after(u64::MAX, 5), u64::MAX - 10);
+
+        let tumbling = WindowKind::Tumbling.build(10, WindowBoundary::Inclusive);
+        assert_eq!(tumbling.window_bound_after(25, 5), 30);
+
+        // a session may be extended by later events, so it can not look ahead
+        let session = WindowKind::Session { gap: Some(10) }.build(100, WindowBoundary::Inclusive);
+        assert_eq!(advance_bound(&session, 5), 5);
+        assert_eq!(session.window_bound_after(12, 100), 5);
+        assert_eq!(advance_bound(&session, 12), 5);
+    }
+
+    #[test]
+    fn test_window_boundary() {
+        // events at 15 and 25 are exactly a window size apart
+        let inclusive = WindowKind::Sliding.build(10, WindowBoundary::Inclusive);
+        assert_eq!(inclusive.window_bound(25), 15);
+        let exclusive = WindowKind::Sliding.build(10, WindowBoundary::Exclusive);
+        assert_eq!(exclusive.window_bound(25), 16);
+        assert_eq!(exclusive.window_bound(5), 0);
+        assert_eq!(
+            WindowKind::Sliding
+                .build(0, WindowBoundary::Exclusive)
+                .window_bound(5),
+            5
+        );
+        assert_eq!(exclusive.window_bound(u64::MAX), u64::MAX - 10);
+        assert_eq!(exclusive.window_bound_after(u64::MAX - 1, 5), u64::MAX - 10);
+
+        let inclusive = WindowKind::Session { gap: Some(10) }.build(100, WindowBoundary::Inclusive);
+        assert_eq!(advance_bound(&inclusive, 5), 5);
+        assert_eq!(advance_bound(&inclusive, 15), 5);
+        let exclusive = WindowKind::Session { gap: Some(10) }.build(100, WindowBoundary::Exclusive);
+        assert_eq!(advance_bound(&exclusive, 5), 5);
+        assert_eq!(advance_bound(&exclusive, 15), 15);
+    }
+
+    #[test]
+    fn test_resizable_window() {
+        let window_size = WindowSize::new(10);
+        let sliding = WindowKind::Sliding.build_resizable(window_size.clone(), Default::default());
+        assert_eq!(sliding.window_bound(25), 15);
+        window_size.set(5);
+        assert_eq!(sliding.window_bound(25), 20);
+        assert_eq!(sliding.window_bound_after(25, 5), 25);
+
+        let tumbling =
+            WindowKind::Tumbling.build_resizable(window_size.clone(), Default::default());
+        assert_eq!(tumbling.window_bound(27), 25);
+        window_size.set(10);
+        assert_eq!(tumbling.window_bound(27), 20);
+
+        // the current session continues with the new gap
+        let session = WindowKind::Session { gap: None }
+            .build_resizable(window_size.clone(), Default::default());
+        assert_eq!(advance_bound(&session, 5), 5);
+        window_size.set(20);
+        assert_eq!(advance_bound(&session, 24), 5);
+        // the gap given by the pattern is never resized
+        let session = WindowKind::Session { gap: Some(10) }
+            .build_resizable(window_size.clone(), Default::default());
+        assert_eq!(advance_bound(&session, 5), 5);
+        assert_eq!(advance_bound(&session, 24), 24);
+    }
+}
@@ -0,0 +1,66 @@
+//! Reloading the configuration at runtime, e.g. on SIGHUP. A reload is only requested by the
+//! signal handler, and carried out by the thread running the engine before its next batch.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag shared between the engine and whoever requests a reload, possibly from another thread
+/// or a signal handler.
+#[derive(Debug, Clone, Default)]
+pub struct ReloadRequest {
+    requested: Arc<AtomicBool>,
+}
+
+impl ReloadRequest {
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a reload is requested since the last call, clearing the request.
+    pub fn take(&self) -> bool {
+        self.requested.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[cfg(unix)]
+pub use self::hangup::reload_on_hangup;
+
+#[cfg(unix)]
+mod hangup {
+    use super::ReloadRequest;
+    use nix::libc::c_int;
+    use nix::sys::signal::{self, SigHandler, Signal};
+    use std::sync::OnceLock;
+
+    static HANGUP: OnceLock<ReloadRequest> = OnceLock::new();
+
+    /// Returns a request set on every SIGHUP, the conventional signal to reload the configuration
+    /// of a daemon.
+    pub fn reload_on_hangup() -> nix::Result<ReloadRequest> {
+        let request = HANGUP.get_or_init(ReloadRequest::default).clone();
+        // SAFETY: the handler only touches atomics
+        unsafe { signal::signal(Signal::SIGHUP, SigHandler::Handler(handle_hangup)) }?;
+        Ok(request)
+    }
+
+    extern "C" fn handle_hangup(_: c_int) {
+        if let Some(request) = HANGUP.get() {
+            request.request();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use nix::sys::signal::{raise, Signal};
+
+    #[test]
+    fn test_reload_on_hangup() {
+        let request = reload_on_hangup().unwrap();
+        assert!(!request.take());
+
+        raise(Signal::SIGHUP).unwrap();
+        assert!(request.take());
+        assert!(!request.take());
+    }
+}
@@ -0,0 +1,236 @@
+//! A SQLite store of pattern matches, enabled by the `sqlite` feature.
+//!
+//! The matches are normalized into three tables:
+//!
+//! - `matches (id, pattern, earliest_time, latest_time, sequence, window_id)`
+//! - `match_events (match_id, pattern_event_id, input_event_id, timestamp, subject_id, object_id)`
+//! - `entity_bindings (match_id, pattern_entity_id, input_entity_id)`
+//!
+//! where the times are in milliseconds, and the pattern event and entity ids are those in the
+//! pattern file. `sequence` and `window_id` are those of a numbered match, see
+//! [`MatchSequence`](crate::pattern_match::MatchSequence), or `NULL`.
+use crate::pattern::Pattern;
+use crate::pattern_match::PatternMatch;
+use rusqlite::{params, Connection, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS matches (
+    id INTEGER PRIMARY KEY,
+    pattern TEXT NOT NULL,
+    earliest_time INTEGER NOT NULL,
+    latest_time INTEGER NOT NULL,
+    sequence INTEGER,
+    window_id INTEGER
+);
+CREATE TABLE IF NOT EXISTS match_events (
+    match_id INTEGER NOT NULL REFERENCES matches(id),
+    pattern_event_id INTEGER NOT NULL,
+    input_event_id INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    subject_id INTEGER NOT NULL,
+    object_id INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS entity_bindings (
+    match_id INTEGER NOT NULL REFERENCES matches(id),
+    pattern_entity_id INTEGER NOT NULL,
+    input_entity_id INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS matches_by_pattern ON matches(pattern, earliest_time);
+CREATE INDEX IF NOT EXISTS bindings_by_entity ON entity_bindings(input_entity_id);
+";
+
+/// A match read back from a [`ResultStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredMatch {
+    pub id: i64,
+    pub pattern: String,
+    pub earliest_time: u64,
+    pub latest_time: u64,
+    pub sequence: Option<u64>,
+    pub window_id: Option<u64>,
+}
+
+/// Writes pattern matches into a SQLite database, and answers common questions about them.
+pub struct ResultStore {
+    conn: Connection,
+}
+
+impl ResultStore {
+    /// Open or create the database at `path`, creating the tables if they do not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        // the databases created before the matches were numbered
+        let has_sequence: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('matches') WHERE name = 'sequence'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_sequence {
+            conn.execute_batch(
+                "ALTER TABLE matches ADD COLUMN sequence INTEGER;
+                 ALTER TABLE matches ADD COLUMN window_id INTEGER;",
+            )?;
+        }
+        Ok(Self { conn })
+    }
+
+    /// Insert `pattern_match` of `pattern`, which is stored under the name `pattern_name`.
+    /// Returns the id of the stored match.
+    pub fn insert(
+        &mut self,
+        pattern_name: &str,
+        pattern: &Pattern,
+        pattern_match: &PatternMatch,
+    ) -> Result<i64> {
+        let tx = self.conn.transaction()?;
+        let sequence = pattern_match.sequence;
+        tx.execute(
+            "INSERT INTO matches (pattern, earliest_time, latest_time, sequence, window_id)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                pattern_name,
+                pattern_match.earliest_time,
+                pattern_match.latest_time,
+                sequence.map(|sequence| sequence.number),
+                sequence.map(|sequence| sequence.window_id)
+            ],
+        )?;
+        let match_id = tx.last_insert_rowid();
+
+        let mut bindings = BTreeMap::new();
+        for (event_id, match_event) in pattern_match.match_event_map.iter().enumerate() {
+            let Some(match_event) = match_event else {
+                continue;
+            };
+            let pattern_event_id = pattern.original_event_ids[event_id];
+            for input_event in match_event.raw_events.get_events() {
+                tx.execute(
+                    "INSERT INTO match_events VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        match_id,
+                        pattern_event_id,
+                        input_event.event_id,
+                        input_event.timestamp,
+                        input_event.subject_id,
+                        input_event.object_id
+                    ],
+                )?;
+            }
+            bindings.insert(match_event.pattern_subject_id, match_event.input_subject_id);
+            bindings.insert(match_event.pattern_object_id, match_event.input_object_id);
+        }
+        for (pattern_entity_id, input_entity_id) in bindings {
+            tx.execute(
+                "INSERT INTO entity_bindings VALUES (?1, ?2, ?3)",
+                params![
+                    match_id,
+                    pattern.original_entity_ids[pattern_entity_id as usize],
+                    input_entity_id
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(match_id)
+    }
+
+    /// The matches binding the input entity `entity_id`, in the order of their earliest time.
+    pub fn matches_with_entity(&self, entity_id: u64) -> Result<Vec<StoredMatch>> {
+        self.query(
+            "SELECT DISTINCT m.id, m.pattern, m.earliest_time, m.latest_time, m.sequence,
+                m.window_id
+             FROM matches m JOIN entity_bindings b ON b.match_id = m.id
+             WHERE b.input_entity_id = ?1
+             ORDER BY m.earliest_time, m.id",
+            params![entity_id],
+        )
+    }
+
+    /// The matches of `pattern_name` lying within `[start_time, end_time]` (in milliseconds), in
+    /// the order of their earliest time.
+    pub fn matches_of_pattern(
+        &self,
+        pattern_name: &str,
+        start_time: u64,
+        end_time: u64,
+    ) -> Result<Vec<StoredMatch>> {
+        self.query(
+            "SELECT id, pattern, earliest_time, latest_time, sequence, window_id FROM matches
+             WHERE pattern = ?1 AND earliest_time >= ?2 AND latest_time <= ?3
+             ORDER BY earliest_time, id",
+            params![pattern_name, start_time, end_time],
+        )
+    }
+
+    /// The ids of the input events in the match `match_id`.
+    pub fn event_ids(&self, match_id: i64) -> Result<Vec<u64>> {
+        let mut statement = self.conn.prepare(
+            "SELECT input_event_id FROM match_events WHERE match_id = ?1 ORDER BY rowid",
+        )?;
+        let rows = statement.query_map(params![match_id], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    fn query(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<StoredMatch>> {
+        let mut statement = self.conn.prepare(sql)?;
+        let rows = statement.query_map(params, |row| {
+            Ok(StoredMatch {
+                id: row.get(0)?,
+                pattern: row.get(1)?,
+                earliest_time: row.get(2)?,
+                latest_time: row.get(3)?,
+                sequence: row.get(4)?,
+                window_id: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_result_store() {
+        let pattern = Pattern::parse("testcases/paper/pattern.json").unwrap();
+        let engine = Engine::new(pattern, 1000 * 1000);
+        let summary = engine.run_file("testcases/paper/data.csv", |_| {}).unwrap();
+        let mut store = ResultStore::open(":memory:").unwrap();
+        for pattern_match in &summary.pattern_matches {
+            store
+                .insert("paper", engine.pattern(), pattern_match)
+                .unwrap();
+        }
+
+        let pattern_match = &summary.pattern_matches[0];
+        let stored = store.matches_with_entity(4).unwrap();
+        assert_eq!(
+            stored,
+            [StoredMatch {
+                id: 1,
+                pattern: "paper".to_string(),
+                earliest_time: pattern_match.earliest_time,
+                latest_time: pattern_match.latest_time,
+                sequence: Some(0),
+                window_id: Some(0),
+            }]
+        );
+        assert!(store.matches_with_entity(1).unwrap().is_empty());
+        let mut event_ids = store.event_ids(1).unwrap();
+        event_ids.sort_unstable();
+        let expected: Vec<u64> = pattern_match.event_ids.iter().map(|(id, _)| *id).collect();
+        assert_eq!(event_ids, expected);
+
+        let in_range = |start, end| store.matches_of_pattern("paper", start, end).unwrap();
+        assert_eq!(in_range(0, 20000).len(), 1);
+        assert!(in_range(8000, 20000).is_empty());
+        assert!(store
+            .matches_of_pattern("other", 0, 20000)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,492 @@
+//! Synthesize provenance streams with embedded pattern instances, for benchmarking and for
+//! checking that the embedded instances are all matched.
+
+use crate::input_event::InputEvent;
+use crate::pattern::{Pattern, PatternEvent, PatternEventType};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use regex::Regex;
+use regex_syntax::hir::{Class, Hir, HirKind};
+use std::io::Write;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SyntheticDataError {
+    #[error("cannot synthesize an input for signature {0:?}")]
+    Signature(String),
+
+    #[error("static event {0} comes from the context graph and cannot be synthesized")]
+    StaticEvent(usize),
+
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// How the background noise events are spread over the stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimeDistribution {
+    /// Uniformly random timestamps.
+    #[default]
+    Uniform,
+    /// A Poisson process, i.e. exponentially distributed gaps between events.
+    Poisson,
+}
+
+/// The parameters of [`generate`].
+#[derive(Debug, Clone)]
+pub struct SyntheticConfig {
+    /// The number of instances embedded for each pattern.
+    pub num_instances: usize,
+    /// The number of background events.
+    pub num_noise_events: usize,
+    /// The number of entities among which the background events occur.
+    pub num_entities: u64,
+    /// The time span (ms) of the stream.
+    pub duration: u64,
+    /// The maximum time span (ms) of an embedded instance, unless its time gaps require more.
+    pub instance_span: u64,
+    pub time_distribution: TimeDistribution,
+    /// The event signatures of the background events.
+    pub noise_signatures: Vec<String>,
+    pub seed: u64,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        Self {
+            num_instances: 10,
+            num_noise_events: 10_000,
+            num_entities: 1000,
+            duration: 3_600_000,
+            instance_span: 10_000,
+            time_distribution: TimeDistribution::Uniform,
+            noise_signatures: ["read", "write", "open", "close", "execve", "connect"]
+                .map(str::to_string)
+                .to_vec(),
+            seed: 0,
+        }
+    }
+}
+
+/// A synthesized stream, sorted by time, with event ids numbered from 0 in that order.
+#[derive(Debug)]
+pub struct SyntheticData {
+    pub events: Vec<InputEvent>,
+    /// The embedded instances as `(pattern index, input event ids)`.
+    pub instances: Vec<(usize, Vec<u64>)>,
+}
+
+impl SyntheticData {
+    /// Write the events in the csv format of the data graph.
+    pub fn write_csv(&self, out: impl Write) -> Result<(), SyntheticDataError> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(out);
+        for event in &self.events {
+            let time = format!("{}.{:03}", event.timestamp / 1000, event.timestamp % 1000);
+            writer.write_record([
+                time.as_str(),
+                time.as_str(),
+                &event.event_id.to_string(),
+                event.get_event_signature(),
+                &event.subject_id.to_string(),
+                event.get_subject_signature(),
+                &event.object_id.to_string(),
+                event.get_object_signature(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// An event before the ids are assigned, along with the instance it belongs to.
+struct DraftEvent {
+    event: InputEvent,
+    instance: Option<usize>,
+}
+
+/// Synthesize a stream with `config.num_instances` instances of each of `patterns` among the
+/// background noise. The same config always gives the same stream.
+///
+/// Each instance binds its pattern entities to fresh input entities, and places its events after
+/// their parents within the gaps given by the pattern. The signatures are sampled from the
+/// regexes of the pattern. Frequency events are repeated, aggregate events spread over distinct
+/// objects, and flow events become a path of two events.
+pub fn generate(
+    patterns: &[Pattern],
+    config: &SyntheticConfig,
+) -> Result<SyntheticData, SyntheticDataError> {
+    let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+    let mut drafts = noise_events(config, &mut rng);
+
+    let mut next_entity = config.num_entities;
+    let mut instance_patterns = vec![];
+    for (pattern_idx, pattern) in patterns.iter().enumerate() {
+        let signatures = PatternSignatures::sample(pattern)?;
+        for _ in 0..config.num_instances {
+            let instance = instance_patterns.len();
+            instance_patterns.push(pattern_idx);
+            let start = rng.gen_range(0..=config.duration.saturating_sub(config.instance_span));
+            let events = embed_instance(
+                pattern,
+                &signatures,
+                start,
+                config.instance_span,
+                &mut next_entity,
+                &mut rng,
+            );
+            drafts.extend(events.into_iter().map(|event| DraftEvent {
+                event,
+                instance: Some(instance),
+            }));
+        }
+    }
+
+    drafts.sort_by_key(|draft| draft.event.timestamp);
+    let mut instances: Vec<(usize, Vec<u64>)> = instance_patterns
+        .into_iter()
+        .map(|idx| (idx, vec![]))
+        .collect();
+    let events = drafts
+        .into_iter()
+        .enumerate()
+        .map(|(event_id, mut draft)| {
+            draft.event.event_id = event_id as u64;
+            if let Some(instance) = draft.instance {
+                instances[instance].1.push(event_id as u64);
+            }
+            draft.event
+        })
+        .collect();
+    Ok(SyntheticData { events, instances })
+}
+
+fn noise_events(config: &SyntheticConfig, rng: &mut ChaCha8Rng) -> Vec<DraftEvent> {
+    let num_events = config.num_noise_events;
+    let mean_gap = config.duration as f64 / num_events.max(1) as f64;
+    let mut time = 0.0;
+    let mut drafts = Vec::with_capacity(num_events);
+    for _ in 0..num_events {
+        let timestamp = match config.time_distribution {
+            TimeDistribution::Uniform => rng.gen_range(0..=config.duration),
+            TimeDistribution::Poisson => {
+                time -= (1.0 - rng.gen::<f64>()).ln() * mean_gap;
+                time as u64
+            }
+        };
+        let subject = rng.gen_range(0..config.num_entities.max(1));
+        let object = rng.gen_range(0..config.num_entities.max(1));
+        let signature = config
+            .noise_signatures
+            .choose(rng)
+            .map_or("", String::as_str);
+        let event = InputEvent::new(
+            timestamp,
+            0,
+            signature,
+            subject,
+            &noise_entity_signature(subject),
+            object,
+            &noise_entity_signature(object),
+        );
+        drafts.push(DraftEvent {
+            event,
+            instance: None,
+        });
+    }
+    drafts
+}
+
+/// Alternates processes and files, e.g. `Process::pid::4` and `File::path::/tmp/5`.
+fn noise_entity_signature(entity_id: u64) -> String {
+    if entity_id.is_multiple_of(2) {
+        format!("Process::pid::{entity_id}")
+    } else {
+        format!("File::path::/tmp/{entity_id}")
+    }
+}
+
+/// The sampled signatures of the events and entities of a pattern, indexed by their ids.
+struct PatternSignatures {
+    events: Vec<String>,
+    entities: Vec<String>,
+}
+
+impl PatternSignatures {
+    fn sample(pattern: &Pattern) -> Result<Self, SyntheticDataError> {
+        let sample = |signature: &str| {
+            if pattern.use_regex {
+                sample_regex(signature)
+            } else {
+                Ok(signature.to_string())
+            }
+        };
+        let mut events = vec![];
+        for event in &pattern.events {
+            if event.is_static {
+                return Err(SyntheticDataError::StaticEvent(event.id));
+            }
+            events.push(match event.event_type {
+                PatternEventType::Flow => "flow".to_string(),
+                _ => sample(&event.signature)?,
+            });
+        }
+        let entities = pattern
+            .entities
+            .iter()
+            .map(|entity| sample(&entity.signature))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { events, entities })
+    }
+}
+
+/// The input events of an instance of `pattern` starting at `start`.
+fn embed_instance(
+    pattern: &Pattern,
+    signatures: &PatternSignatures,
+    start: u64,
+    span: u64,
+    next_entity: &mut u64,
+    rng: &mut ChaCha8Rng,
+) -> Vec<InputEvent> {
+    let entity_ids: Vec<u64> = (0..pattern.entities.len() as u64)
+        .map(|i| *next_entity + i)
+        .collect();
+    *next_entity += entity_ids.len() as u64;
+    let max_step = (span / pattern.events.len().max(1) as u64).max(1);
+
+    // ordered by the number of events that must precede them, which is a topological order
+    let mut order: Vec<&PatternEvent> = pattern.events.iter().collect();
+    order.sort_by_key(|event| {
+        (pattern.events.iter())
+            .filter(|other| pattern.order.must_precede(other.id, event.id))
+            .count()
+    });
+
+    // the (start, end) time of each pattern event
+    let mut times: Vec<Option<(u64, u64)>> = vec![None; pattern.events.len()];
+    let mut input_events = vec![];
+    for event in order {
+        let predecessors = (pattern.events.iter())
+            .filter(|other| pattern.order.must_precede(other.id, event.id))
+            .filter_map(|other| Some((times[other.id]?.1, event.gap_from(other.id))));
+        let mut earliest = start;
+        let mut latest = u64::MAX;
+        for (end, gap) in predecessors {
+            earliest = earliest.max(end + gap.min.max(1));
+            if let Some(max) = gap.max {
+                latest = latest.min(end + max);
+            }
+        }
+        let time = (event.simultaneous_events.iter())
+            .find_map(|other| times[*other].map(|(time, _)| time))
+            .unwrap_or_else(|| (earliest + rng.gen_range(0..max_step)).min(latest.max(earliest)));
+
+        let subject = (
+            entity_ids[event.subject.id],
+            &*signatures.entities[event.subject.id],
+        );
+        let object = (
+            entity_ids[event.object.id],
+            &*signatures.entities[event.object.id],
+        );
+        let signature = &signatures.events[event.id];
+        let new_event = |time, subject: (u64, &str), object: (u64, &str)| {
+            InputEvent::new(time, 0, signature, subject.0, subject.1, object.0, object.1)
+        };
+        let end = match event.event_type {
+            PatternEventType::Default => {
+                input_events.push(new_event(time, subject, object));
+                time
+            }
+            PatternEventType::Frequency(frequency) => {
+                for i in 0..frequency as u64 {
+                    input_events.push(new_event(time + i, subject, object));
+                }
+                time + frequency as u64 - 1
+            }
+            PatternEventType::Aggregate {
+                count,
+                distinct_objects,
+                ..
+            } => {
+                // the last event goes to the pattern object, the others to fresh objects
+                let num_fresh = distinct_objects as u64 - 1;
+                let fresh_objects = *next_entity;
+                *next_entity += num_fresh;
+                for i in 0..count as u64 {
+                    let object = match (count as u64 - 1 - i) % distinct_objects as u64 {
+                        0 => object,
+                        k => (fresh_objects + k - 1, object.1),
+                    };
+                    input_events.push(new_event(time + i, subject, object));
+                }
+                time + count as u64 - 1
+            }
+            PatternEventType::Flow => {
+                let middle = (*next_entity, "");
+                *next_entity += 1;
+                input_events.push(new_event(time, subject, middle));
+                input_events.push(new_event(time + 1, middle, object));
+                time + 1
+            }
+        };
+        times[event.id] = Some((time, end));
+    }
+    input_events
+}
+
+/// A string fully matching the regex `signature`, e.g. `Process::name::a` for
+/// `Process::name::.*sh` gives `Process::name::sh`.
+pub fn sample_regex(signature: &str) -> Result<String, SyntheticDataError> {
+    let error = || SyntheticDataError::Signature(signature.to_string());
+    let hir = regex_syntax::parse(signature).map_err(|_| error())?;
+    let mut sample = String::new();
+    sample_hir(&hir, &mut sample);
+    let regex = Regex::new(&format!("^(?:{signature})$")).map_err(|_| error())?;
+    if regex.is_match(&sample) {
+        Ok(sample)
+    } else {
+        Err(error())
+    }
+}
+
+/// Append to `sample` the shortest string matching `hir` along the first alternatives, preferring
+/// alphanumeric characters in classes.
+fn sample_hir(hir: &Hir, sample: &mut String) {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => {}
+        HirKind::Literal(literal) => sample.push_str(&String::from_utf8_lossy(&literal.0)),
+        HirKind::Class(Class::Unicode(class)) => {
+            let contains = |c: char| {
+                class
+                    .ranges()
+                    .iter()
+                    .any(|r| r.start() <= c && c <= r.end())
+            };
+            let preferred = ('a'..='z')
+                .chain('0'..='9')
+                .chain('A'..='Z')
+                .find(|c| contains(*c));
+            let first = class.ranges().first().map(|r| r.start());
+            sample.extend(preferred.or(first));
+        }
+        HirKind::Class(Class::Bytes(class)) => {
+            let contains = |b: u8| {
+                class
+                    .ranges()
+                    .iter()
+                    .any(|r| r.start() <= b && b <= r.end())
+            };
+            let preferred = (b'a'..=b'z').chain(b'0'..=b'9').find(|b| contains(*b));
+            let first = class.ranges().first().map(|r| r.start());
+            sample.extend(preferred.or(first).map(char::from));
+        }
+        HirKind::Repetition(repetition) => {
+            for _ in 0..repetition.min {
+                sample_hir(&repetition.sub, sample);
+            }
+        }
+        HirKind::Capture(capture) => sample_hir(&capture.sub, sample),
+        HirKind::Concat(hirs) => hirs.iter().for_each(|hir| sample_hir(hir, sample)),
+        HirKind::Alternation(hirs) => sample_hir(&hirs[0], sample),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::pattern::parser::parse_json;
+    use serde_json::json;
+
+    #[test]
+    fn test_sample_regex() {
+        assert_eq!(
+            sample_regex("Process::name::.*sh").unwrap(),
+            "Process::name::sh"
+        );
+        assert_eq!(sample_regex("(read|write)[0-9]+").unwrap(), "read0");
+        assert_eq!(sample_regex("[^/]*/etc/passwd").unwrap(), "/etc/passwd");
+        assert_eq!(sample_regex("a[^a-z]").unwrap(), "a0");
+        assert!(sample_regex("(").is_err());
+    }
+
+    fn exfiltration_pattern() -> Pattern {
+        parse_json(&json!({
+            "Version": "0.2.0",
+            "UseRegex": true,
+            "Entities": [
+                { "ID": 0, "Signature": "Process::name::.*sh" },
+                { "ID": 1, "Signature": "File::path::/etc/(passwd|shadow)" },
+                { "ID": 2, "Signature": "Socket::ip::[0-9]+" }
+            ],
+            "Events": [
+                { "ID": 0, "Signature": "open", "SubjectID": 0, "ObjectID": 1 },
+                {
+                    "ID": 1, "Signature": "read", "SubjectID": 0, "ObjectID": 1,
+                    "Parents": [0], "ParentsMinGapMs": 500, "Type": "Frequency", "Frequency": 3
+                },
+                { "ID": 2, "Signature": "sendto", "SubjectID": 0, "ObjectID": 2, "Parents": [1] }
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_generate() {
+        let config = SyntheticConfig {
+            num_instances: 5,
+            num_noise_events: 200,
+            num_entities: 20,
+            duration: 100_000,
+            time_distribution: TimeDistribution::Poisson,
+            ..Default::default()
+        };
+        let data = generate(&[exfiltration_pattern()], &config).unwrap();
+        assert_eq!(data.events.len(), 200 + 5 * 5);
+        assert!(data.events.is_sorted_by_key(|event| event.timestamp));
+        assert_eq!(data.instances.len(), 5);
+
+        // the same seed gives the same stream
+        let mut csv = vec![];
+        data.write_csv(&mut csv).unwrap();
+        let mut again = vec![];
+        let data_again = generate(&[exfiltration_pattern()], &config).unwrap();
+        data_again.write_csv(&mut again).unwrap();
+        assert_eq!(csv, again);
+
+        let mut pattern = exfiltration_pattern();
+        pattern.optimize();
+        let summary = Engine::new(pattern, 20_000)
+            .run_reader(csv.as_slice(), |_| {})
+            .unwrap();
+        for (_, event_ids) in &data.instances {
+            let is_matched = summary.pattern_matches.iter().any(|pattern_match| {
+                let mut ids: Vec<u64> = pattern_match.event_ids.iter().map(|(id, _)| *id).collect();
+                ids.sort_unstable();
+                ids == *event_ids
+            });
+            assert!(is_matched, "instance {:?} is not matched", event_ids);
+        }
+    }
+
+    #[test]
+    fn test_static_event() {
+        let pattern = parse_json(&json!({
+            "Version": "0.2.0",
+            "Entities": [{ "ID": 0, "Signature": "" }, { "ID": 1, "Signature": "" }],
+            "Events": [{ "ID": 0, "Signature": "a", "SubjectID": 0, "ObjectID": 1, "Static": true }]
+        }))
+        .unwrap();
+        assert!(matches!(
+            generate(&[pattern], &SyntheticConfig::default()),
+            Err(SyntheticDataError::StaticEvent(0))
+        ));
+    }
+}
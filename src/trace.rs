@@ -0,0 +1,29 @@
+//! Structured logging with [`tracing`](https://docs.rs/tracing) spans and events. The macros
+//! compile to nothing unless the `tracing` feature is enabled.
+
+/// A placeholder of the span guard when the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+pub struct NoSpan;
+
+/// Enter a debug-level span, e.g. `enter_span!("batch", time = 5000)`. The span is exited when
+/// the returned guard is dropped.
+macro_rules! enter_span {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        let guard = tracing::debug_span!($($arg)+).entered();
+        #[cfg(not(feature = "tracing"))]
+        let guard = $crate::trace::NoSpan;
+        guard
+    }};
+}
+
+/// Emit an event at the given level, e.g. `trace_event!(TRACE, state_id = 3, "new instance")`.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)+);
+    };
+}
+
+pub(crate) use enter_span;
+pub(crate) use trace_event;
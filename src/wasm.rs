@@ -0,0 +1,94 @@
+//! The wasm-bindgen API, enabled by the `wasm` feature.
+//!
+//! ```js
+//! const ipmes = new Ipmes(patternJson, 1800);
+//! ipmes.pushEvents("5,5,1,recvmsg,1,Socket::ip::8.8.8.8,2,Process::name::bash");
+//! ipmes.finish();
+//! for (const match of JSON.parse(ipmes.pollMatches())) { ... }
+//! ```
+use crate::engine::{Engine, Session};
+use crate::pattern::Pattern;
+use crate::pattern_match::PatternMatch;
+use wasm_bindgen::prelude::*;
+
+/// An incremental matching session on a single pattern.
+#[wasm_bindgen]
+pub struct Ipmes {
+    /// Borrows `*engine`, so it is dropped first in `Ipmes::drop`.
+    session: Option<Session<'static>>,
+    engine: *mut Engine,
+}
+
+impl Ipmes {
+    fn session(&mut self) -> &mut Session<'static> {
+        self.session
+            .as_mut()
+            .expect("the session lives until the engine is freed")
+    }
+}
+
+impl Drop for Ipmes {
+    fn drop(&mut self) {
+        self.session = None;
+        // SAFETY: `engine` is created by `Box::into_raw` in `Ipmes::new`, and nothing borrows it
+        // after the session is dropped.
+        drop(unsafe { Box::from_raw(self.engine) });
+    }
+}
+
+#[wasm_bindgen]
+impl Ipmes {
+    /// Create a session matching the pattern in `pattern_json` (the content of a pattern file),
+    /// with the window size in seconds.
+    #[wasm_bindgen(constructor)]
+    pub fn new(pattern_json: &str, window_size: u32) -> Result<Ipmes, JsError> {
+        let pattern = Pattern::parse_str(pattern_json)?;
+        let window_size = u64::from(window_size)
+            .checked_mul(1000)
+            .ok_or_else(|| JsError::new("window size overflows"))?;
+        let engine = Box::into_raw(Box::new(Engine::new(pattern, window_size)));
+        // SAFETY: `engine` is only freed after the session, see `Ipmes::drop`.
+        match unsafe { (*engine).session() } {
+            Ok(session) => Ok(Self {
+                session: Some(session),
+                engine,
+            }),
+            Err(err) => {
+                drop(unsafe { Box::from_raw(engine) });
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Push input events in csv format, see `Session::push_events`.
+    #[wasm_bindgen(js_name = pushEvents)]
+    pub fn push_events(&mut self, csv: &str) {
+        self.session().push_events(csv);
+    }
+
+    /// Mark the end of the input.
+    pub fn finish(&mut self) {
+        self.session().finish();
+    }
+
+    /// Returns the matches found so far as a JSON array. Each match has its `earliest_time` and
+    /// `latest_time` in milliseconds, and the ids of the matched input events in `event_ids`,
+    /// tagged by the schema `version`, see [`MatchRecord`](crate::match_schema::MatchRecord).
+    #[wasm_bindgen(js_name = pollMatches)]
+    pub fn poll_matches(&mut self) -> String {
+        let pattern_matches: Vec<_> = self
+            .session()
+            .poll_matches()
+            .iter()
+            .map(PatternMatch::to_json)
+            .collect();
+        serde_json::Value::Array(pattern_matches).to_string()
+    }
+
+    #[wasm_bindgen(js_name = numEvents)]
+    pub fn num_events(&self) -> u64 {
+        self.session
+            .as_ref()
+            .map_or(0, |session| session.num_events())
+    }
+}
@@ -0,0 +1,246 @@
+//! A watchdog for stuck pipelines. If no time batch completes for a while although the engine is
+//! not waiting for input, e.g. a pathological join is blowing up, the watchdog logs the latest
+//! buffer statistics and the largest buffers at the warn level.
+use crate::process_layers::StatsReport;
+use log::warn;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The number of the largest buffers in a dump.
+const MAX_DUMPED_BUFFERS: usize = 5;
+
+#[derive(Debug)]
+struct WatchdogState {
+    /// When the last batch is pulled from the input, or the watchdog is created.
+    last_batch: Instant,
+    /// Whether the engine is blocked on reading the input, in which case it is not stuck.
+    waiting_input: bool,
+    num_batches: u64,
+    /// When the current stall was last dumped.
+    last_dump: Option<Instant>,
+    stats: Option<StatsReport>,
+}
+
+/// A handle to the watchdog state, shared between the thread running the engine and the
+/// watchdog thread. The engine reports its progress by pulling the input through [`Watched`],
+/// and its buffers by [`Watchdog::set_stats`].
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    state: Arc<Mutex<WatchdogState>>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(WatchdogState {
+                last_batch: Instant::now(),
+                waiting_input: false,
+                num_batches: 0,
+                last_dump: None,
+                stats: None,
+            })),
+        }
+    }
+}
+
+impl Watchdog {
+    /// Update the buffer statistics to dump, see
+    /// [`StatsReporter`](crate::process_layers::StatsReporter).
+    pub fn set_stats(&self, report: &StatsReport) {
+        self.state.lock().unwrap().stats = Some(report.clone());
+    }
+
+    /// Check the watchdog every second in a background thread, and dump the diagnostics once no
+    /// batch has completed for `timeout`, then again every `timeout` until the next batch. The
+    /// thread exits when all the other handles are dropped.
+    pub fn spawn(&self, timeout: Duration) -> JoinHandle<()> {
+        let state = Arc::downgrade(&self.state);
+        let period = timeout.min(Duration::from_secs(1));
+        thread::spawn(move || loop {
+            thread::sleep(period);
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            let watchdog = Watchdog { state };
+            if let Some(dump) = watchdog.check(Instant::now(), timeout) {
+                warn!("{dump}");
+            }
+        })
+    }
+
+    /// Returns the diagnostics if the engine is stuck at `now`, unless they have been dumped in
+    /// the last `timeout`.
+    fn check(&self, now: Instant, timeout: Duration) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let stalled_for = now.saturating_duration_since(state.last_batch);
+        if state.waiting_input || stalled_for < timeout {
+            return None;
+        }
+        if state
+            .last_dump
+            .is_some_and(|last_dump| now.saturating_duration_since(last_dump) < timeout)
+        {
+            return None;
+        }
+        state.last_dump = Some(now);
+        Some(format_dump(&state, stalled_for))
+    }
+
+    fn start_reading(&self) {
+        self.state.lock().unwrap().waiting_input = true;
+    }
+
+    fn finish_reading(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.waiting_input = false;
+        state.last_batch = Instant::now();
+        state.num_batches += 1;
+        state.last_dump = None;
+    }
+}
+
+fn format_dump(state: &WatchdogState, stalled_for: Duration) -> String {
+    let mut out = format!(
+        "No batch completed for {:.1} secs after {} batches, the pipeline may be stuck",
+        stalled_for.as_secs_f64(),
+        state.num_batches
+    );
+    let Some(stats) = &state.stats else {
+        out += "; no buffer statistics reported yet";
+        return out;
+    };
+
+    let _ = write!(out, "\nBuffer stats at {:.3}:", stats.time as f64 / 1000.0);
+    // entries and bytes per layer, i.e. the prefix of the buffer names
+    let mut layers: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for buffer in &stats.buffers {
+        let layer = buffer.name.split('.').next().unwrap_or_default();
+        let entry = layers.entry(layer).or_default();
+        entry.0 += buffer.len;
+        entry.1 += buffer.approx_bytes;
+    }
+    for (layer, (len, bytes)) in layers {
+        let _ = write!(out, "\n    {layer}: {len} entries, ~{bytes} bytes");
+    }
+
+    let mut buffers: Vec<_> = stats.buffers.iter().collect();
+    buffers.sort_by_key(|buffer| Reverse(buffer.approx_bytes));
+    out += "\nLargest buffers:";
+    for buffer in buffers.into_iter().take(MAX_DUMPED_BUFFERS) {
+        let _ = write!(out, "\n    {buffer}");
+    }
+    if !stats.top_states.is_empty() {
+        out += "\nTop states by live instances:";
+        for state in &stats.top_states {
+            let _ = write!(out, "\n    {state}");
+        }
+    }
+    out
+}
+
+/// Pulls the input batches through, telling the watchdog, if any, when the engine is blocked on
+/// the input and when a batch is pulled, i.e. the previous batch has completed.
+pub struct Watched<I> {
+    source: I,
+    watchdog: Option<Watchdog>,
+}
+
+impl<I> Watched<I> {
+    pub fn new(source: I, watchdog: Option<Watchdog>) -> Self {
+        Self { source, watchdog }
+    }
+}
+
+impl<I: Iterator> Iterator for Watched<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(watchdog) = &self.watchdog else {
+            return self.source.next();
+        };
+        watchdog.start_reading();
+        let batch = self.source.next();
+        watchdog.finish_reading();
+        batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_layers::BufferStats;
+
+    #[test]
+    fn test_check() {
+        let watchdog = Watchdog::default();
+        let timeout = Duration::from_secs(10);
+        let mut source = Watched::new([1, 2].into_iter(), Some(watchdog.clone()));
+        source.next();
+        let start = watchdog.state.lock().unwrap().last_batch;
+
+        assert_eq!(
+            watchdog.check(start + Duration::from_secs(5), timeout),
+            None
+        );
+        let dump = watchdog
+            .check(start + Duration::from_secs(10), timeout)
+            .unwrap();
+        assert!(dump.contains("after 1 batches"));
+        assert!(dump.contains("no buffer statistics"));
+        // dumped once per timeout
+        assert_eq!(
+            watchdog.check(start + Duration::from_secs(15), timeout),
+            None
+        );
+        assert!(watchdog
+            .check(start + Duration::from_secs(20), timeout)
+            .is_some());
+
+        // not stuck while waiting for the input
+        watchdog.start_reading();
+        assert_eq!(
+            watchdog.check(start + Duration::from_secs(60), timeout),
+            None
+        );
+    }
+
+    #[test]
+    fn test_format_dump() {
+        let watchdog = Watchdog::default();
+        let buffer = |name: &str, len, approx_bytes| BufferStats {
+            name: name.to_string(),
+            len,
+            oldest_time: Some(1000),
+            approx_bytes,
+        };
+        watchdog.set_stats(&StatsReport {
+            time: 2500,
+            watermark: 0,
+            buffers: vec![
+                buffer("composition.subject_instances", 2, 100),
+                buffer("join.buffer 0", 3, 300),
+                buffer("join.buffer 1", 1, 50),
+            ],
+            top_states: vec![],
+            live_instances: vec![],
+        });
+
+        let state = watchdog.state.lock().unwrap();
+        let dump = format_dump(&state, Duration::from_secs(12));
+        assert_eq!(
+            dump,
+            "No batch completed for 12.0 secs after 0 batches, the pipeline may be stuck\n\
+             Buffer stats at 2.500:\n    \
+             composition: 2 entries, ~100 bytes\n    \
+             join: 4 entries, ~350 bytes\n\
+             Largest buffers:\n    \
+             join.buffer 0: 3 entries, oldest at 1.000, ~300 bytes\n    \
+             composition.subject_instances: 2 entries, oldest at 1.000, ~100 bytes\n    \
+             join.buffer 1: 1 entries, oldest at 1.000, ~50 bytes"
+        );
+    }
+}